@@ -0,0 +1,231 @@
+//! Account: a sequence of one-time keys derived from a common xpub,
+//! and the receivers that are expected to be paid.
+
+use curve25519_dalek::scalar::Scalar;
+use keytree::Xpub;
+use rand::{CryptoRng, RngCore};
+use zkvm::{ContractID, Entry, Output, VerifiedTx};
+
+use crate::receiver::{commit_sequence, ClearValue, Receiver, ReceiverWitness};
+
+/// Account generates receivers with unique one-time keys
+/// and matches incoming outputs to the receivers that are waiting for a payment.
+/// Only the xpub is needed, so the account can be kept on an online server
+/// while the xprv stays offline.
+pub struct Account {
+    xpub: Xpub,
+    sequence: u64,
+    pending: Vec<ReceiverWitness>,
+}
+
+/// Output paid to one of the account's receivers.
+#[derive(Clone, Debug)]
+pub struct ReceivedUtxo {
+    /// The received output.
+    pub output: Output,
+    /// The receiver that was paid, with the sequence number of its key
+    /// and the openings of the value commitments.
+    pub receiver_witness: ReceiverWitness,
+}
+
+impl Account {
+    /// Creates an account with the given root xpub.
+    pub fn new(xpub: Xpub) -> Self {
+        Account {
+            xpub,
+            sequence: 0,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Creates an account that continues generating receivers from the given sequence number,
+    /// e.g. when the account's state is kept by the client between the sessions.
+    pub fn with_sequence(xpub: Xpub, sequence: u64) -> Self {
+        Account {
+            xpub,
+            sequence,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Returns the root xpub of the account.
+    pub fn xpub(&self) -> &Xpub {
+        &self.xpub
+    }
+
+    /// Returns the sequence number of the next receiver.
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// Returns the receivers that are waiting for a payment.
+    pub fn pending_receivers(&self) -> &[ReceiverWitness] {
+        &self.pending
+    }
+
+    /// Creates a receiver for the given value with a new one-time key and fresh blinding factors.
+    /// The receiver is tracked by the account until it is paid or expires.
+    pub fn generate_receiver<R: RngCore + CryptoRng>(
+        &mut self,
+        value: ClearValue,
+        expiration: u64,
+        rng: &mut R,
+    ) -> Receiver {
+        let sequence = self.sequence;
+        self.sequence += 1;
+
+        let receiver = Receiver {
+            opaque_predicate: self.xpub.derive_key(|t| commit_sequence(t, sequence)),
+            value,
+            qty_blinding: Scalar::random(rng),
+            flv_blinding: Scalar::random(rng),
+            expiration,
+        };
+        self.pending.push(ReceiverWitness {
+            sequence,
+            receiver: receiver.clone(),
+        });
+        receiver
+    }
+
+    /// Matches the output against the pending receivers.
+    /// If the output pays one of them, the receiver is no longer pending.
+    pub fn match_output(&mut self, output: &Output) -> Option<ReceivedUtxo> {
+        let index = self
+            .pending
+            .iter()
+            .position(|w| w.receiver.matches(output.contract()))?;
+        Some(ReceivedUtxo {
+            output: output.clone(),
+            receiver_witness: self.pending.remove(index),
+        })
+    }
+
+    /// Matches all outputs created by the transaction against the pending receivers.
+    pub fn receive(&mut self, tx: &VerifiedTx) -> Vec<ReceivedUtxo> {
+        tx.log
+            .iter()
+            .filter_map(|entry| match entry {
+                Entry::Output(output) => self.match_output(output),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Stops tracking the receivers that expired before `now`
+    /// (in milliseconds since the Unix epoch) and returns them.
+    pub fn expire(&mut self, now: u64) -> Vec<ReceiverWitness> {
+        let (expired, pending): (Vec<_>, Vec<_>) = self
+            .pending
+            .drain(..)
+            .partition(|w| w.receiver.expiration < now);
+        self.pending = pending;
+        expired
+    }
+}
+
+impl ReceivedUtxo {
+    /// Returns the ID of the received output.
+    pub fn contract_id(&self) -> ContractID {
+        self.output.id()
+    }
+
+    /// Returns the received value.
+    pub fn value(&self) -> ClearValue {
+        self.receiver_witness.receiver.value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use curve25519_dalek::constants::RISTRETTO_BASEPOINT_POINT;
+    use keytree::Xprv;
+    use zkvm::{Anchor, Commitment, Contract, PortableItem, Predicate, Value};
+
+    fn payment(receiver: &Receiver, anchor: Anchor) -> Output {
+        // The recipient sees the output with closed commitments.
+        let value = receiver.value();
+        Output::new(Contract {
+            anchor,
+            payload: vec![PortableItem::Value(Value {
+                qty: Commitment::Closed(value.qty.to_point()),
+                flv: Commitment::Closed(value.flv.to_point()),
+            })],
+            predicate: receiver.predicate(),
+        })
+    }
+
+    #[test]
+    fn receive_payment() {
+        let mut rng = rand::thread_rng();
+        let xprv = Xprv::random(&mut rng);
+        let mut account = Account::new(xprv.to_xpub());
+        let anchor = Anchor::nonce([0u8; 32], &Predicate::Opaque(Default::default()), 0);
+
+        let value = ClearValue {
+            qty: 100,
+            flv: Scalar::from(1u64),
+        };
+        let r1 = account.generate_receiver(value, 1000, &mut rng);
+        let r2 = account.generate_receiver(value, 1000, &mut rng);
+        assert_ne!(r1.opaque_predicate, r2.opaque_predicate);
+        assert_eq!(account.pending_receivers().len(), 2);
+
+        let utxo = account.match_output(&payment(&r2, anchor)).unwrap();
+        assert_eq!(utxo.receiver_witness.sequence, 1);
+        assert_eq!(utxo.value(), value);
+        assert_eq!(account.pending_receivers().len(), 1);
+
+        // The recipient can derive the key that protects the output.
+        let privkey = utxo.receiver_witness.signing_key(&xprv);
+        assert_eq!(
+            (privkey * RISTRETTO_BASEPOINT_POINT).compress(),
+            r2.opaque_predicate
+        );
+
+        // The same receiver is not matched twice.
+        assert!(account
+            .match_output(&payment(&r2, anchor.ratchet()))
+            .is_none());
+    }
+
+    #[test]
+    fn reject_wrong_value() {
+        let mut rng = rand::thread_rng();
+        let mut account = Account::new(Xprv::random(&mut rng).to_xpub());
+        let anchor = Anchor::nonce([0u8; 32], &Predicate::Opaque(Default::default()), 0);
+
+        let receiver = account.generate_receiver(
+            ClearValue {
+                qty: 100,
+                flv: Scalar::from(1u64),
+            },
+            1000,
+            &mut rng,
+        );
+        let mut underpaid = receiver.clone();
+        underpaid.value.qty = 99;
+        assert!(account.match_output(&payment(&underpaid, anchor)).is_none());
+        assert_eq!(account.pending_receivers().len(), 1);
+    }
+
+    #[test]
+    fn expire_receivers() {
+        let mut rng = rand::thread_rng();
+        let mut account = Account::new(Xprv::random(&mut rng).to_xpub());
+        let value = ClearValue {
+            qty: 1,
+            flv: Scalar::zero(),
+        };
+        account.generate_receiver(value, 1000, &mut rng);
+        account.generate_receiver(value, 2000, &mut rng);
+
+        assert!(account.expire(1000).is_empty());
+        let expired = account.expire(1001);
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].sequence, 0);
+        assert_eq!(account.pending_receivers()[0].sequence, 1);
+        assert_eq!(account.sequence(), 2);
+    }
+}
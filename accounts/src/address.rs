@@ -0,0 +1,314 @@
+//! Addresses: human-readable encoding of the predicates and receivers with a checksum.
+//!
+//! An address is a [bech32m](https://github.com/bitcoin/bips/blob/master/bip-0350.mediawiki) string
+//! with the human-readable part that names the network, followed by the version and the payload:
+//!
+//! * version 0: the predicate (32-byte compressed point),
+//! * version 1: the receiver (144 bytes, see `Address::receiver`).
+//!
+//! The receiver addresses are longer than the 90 characters allowed by BIP173,
+//! so the length limit is not enforced.
+
+use core::fmt;
+use core::str::FromStr;
+use curve25519_dalek::ristretto::CompressedRistretto;
+use curve25519_dalek::scalar::Scalar;
+use std::iter;
+
+use crate::errors::AccountError;
+use crate::receiver::{ClearValue, Receiver};
+
+/// Address of a payment destination on the network named by the human-readable part.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Address {
+    /// Human-readable part that names the network, e.g. `zk`.
+    pub hrp: String,
+    /// Payment destination.
+    pub payload: AddressPayload,
+}
+
+/// Payment destination encoded in the address.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AddressPayload {
+    /// Predicate that must protect the payment output.
+    Predicate(CompressedRistretto),
+    /// Receiver that also specifies the requested value and the blinding factors.
+    Receiver(Receiver),
+}
+
+const SEPARATOR: char = '1';
+const CHARSET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+const BECH32M_CONST: u32 = 0x2bc8_30a3;
+const CHECKSUM_LEN: usize = 6;
+const RECEIVER_LEN: usize = 32 + 8 + 32 + 32 + 32 + 8;
+
+impl Address {
+    /// Creates an address of the predicate.
+    pub fn predicate(hrp: &str, predicate: CompressedRistretto) -> Self {
+        Address {
+            hrp: hrp.to_string(),
+            payload: AddressPayload::Predicate(predicate),
+        }
+    }
+
+    /// Creates an address of the receiver. The payload is the predicate,
+    /// the LE64 quantity, the flavor, the quantity and flavor blinding factors,
+    /// and the LE64 expiration timestamp.
+    pub fn receiver(hrp: &str, receiver: Receiver) -> Self {
+        Address {
+            hrp: hrp.to_string(),
+            payload: AddressPayload::Receiver(receiver),
+        }
+    }
+
+    /// Encodes the address as a lowercase bech32m string.
+    pub fn encode(&self) -> String {
+        let (version, payload) = match &self.payload {
+            AddressPayload::Predicate(p) => (0u8, p.as_bytes().to_vec()),
+            AddressPayload::Receiver(r) => (1u8, encode_receiver(r)),
+        };
+        let mut data = vec![version];
+        data.extend(convert_bits(&payload, 8, 5, true).expect("padding is allowed"));
+        bech32m_encode(&self.hrp, &data)
+    }
+
+    /// Parses the address and verifies its checksum.
+    /// Fails with `InvalidAddressChecksum` if the address was mistyped,
+    /// and with `InvalidAddress` if it is malformed or has an unknown version.
+    pub fn parse(s: &str) -> Result<Self, AccountError> {
+        let (hrp, data) = bech32m_decode(s)?;
+        let (version, data) = data.split_first().ok_or(AccountError::InvalidAddress)?;
+        let payload = convert_bits(data, 5, 8, false).ok_or(AccountError::InvalidAddress)?;
+        let payload = match (*version, payload.len()) {
+            (0, 32) => AddressPayload::Predicate(CompressedRistretto::from_slice(&payload)),
+            (1, RECEIVER_LEN) => AddressPayload::Receiver(decode_receiver(&payload)?),
+            _ => return Err(AccountError::InvalidAddress),
+        };
+        Ok(Address { hrp, payload })
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.encode())
+    }
+}
+
+impl FromStr for Address {
+    type Err = AccountError;
+
+    fn from_str(s: &str) -> Result<Self, AccountError> {
+        Address::parse(s)
+    }
+}
+
+fn encode_receiver(receiver: &Receiver) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(RECEIVER_LEN);
+    buf.extend_from_slice(receiver.opaque_predicate.as_bytes());
+    buf.extend_from_slice(&receiver.value.qty.to_le_bytes());
+    buf.extend_from_slice(receiver.value.flv.as_bytes());
+    buf.extend_from_slice(receiver.qty_blinding.as_bytes());
+    buf.extend_from_slice(receiver.flv_blinding.as_bytes());
+    buf.extend_from_slice(&receiver.expiration.to_le_bytes());
+    buf
+}
+
+fn decode_receiver(buf: &[u8]) -> Result<Receiver, AccountError> {
+    let u64_at = |i: usize| {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&buf[i..i + 8]);
+        u64::from_le_bytes(bytes)
+    };
+    let scalar_at = |i: usize| {
+        let mut bytes = [0u8; 32];
+        bytes.copy_from_slice(&buf[i..i + 32]);
+        Scalar::from_canonical_bytes(bytes).ok_or(AccountError::InvalidAddress)
+    };
+    Ok(Receiver {
+        opaque_predicate: CompressedRistretto::from_slice(&buf[0..32]),
+        value: ClearValue {
+            qty: u64_at(32),
+            flv: scalar_at(40)?,
+        },
+        qty_blinding: scalar_at(72)?,
+        flv_blinding: scalar_at(104)?,
+        expiration: u64_at(136),
+    })
+}
+
+/// Encodes the 5-bit values with the human-readable part and the bech32m checksum.
+fn bech32m_encode(hrp: &str, data: &[u8]) -> String {
+    let mut values = hrp_expand(hrp.as_bytes());
+    values.extend_from_slice(data);
+    values.extend_from_slice(&[0u8; CHECKSUM_LEN]);
+    let checksum = polymod(&values) ^ BECH32M_CONST;
+
+    let mut s = String::with_capacity(hrp.len() + 1 + data.len() + CHECKSUM_LEN);
+    s.push_str(hrp);
+    s.push(SEPARATOR);
+    for v in data
+        .iter()
+        .cloned()
+        .chain((0..CHECKSUM_LEN).map(|i| ((checksum >> (5 * (CHECKSUM_LEN - 1 - i))) & 31) as u8))
+    {
+        s.push(CHARSET[v as usize] as char);
+    }
+    s
+}
+
+/// Decodes the human-readable part and the 5-bit values without the checksum.
+fn bech32m_decode(s: &str) -> Result<(String, Vec<u8>), AccountError> {
+    // Either all lowercase or all uppercase.
+    if s.chars().any(|c| c.is_ascii_lowercase()) && s.chars().any(|c| c.is_ascii_uppercase()) {
+        return Err(AccountError::InvalidAddress);
+    }
+    let s = s.to_ascii_lowercase();
+    let pos = s.rfind(SEPARATOR).ok_or(AccountError::InvalidAddress)?;
+    let (hrp, data) = (&s[..pos], &s[pos + 1..]);
+    if hrp.is_empty()
+        || hrp.len() > 83
+        || hrp.bytes().any(|c| c < 33 || c > 126)
+        || data.len() < CHECKSUM_LEN
+    {
+        return Err(AccountError::InvalidAddress);
+    }
+    let data = data
+        .bytes()
+        .map(|c| {
+            CHARSET
+                .iter()
+                .position(|x| *x == c)
+                .map(|v| v as u8)
+                .ok_or(AccountError::InvalidAddress)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut values = hrp_expand(hrp.as_bytes());
+    values.extend_from_slice(&data);
+    if polymod(&values) != BECH32M_CONST {
+        return Err(AccountError::InvalidAddressChecksum);
+    }
+    Ok((hrp.to_string(), data[..data.len() - CHECKSUM_LEN].to_vec()))
+}
+
+fn hrp_expand(hrp: &[u8]) -> Vec<u8> {
+    hrp.iter()
+        .map(|c| c >> 5)
+        .chain(iter::once(0))
+        .chain(hrp.iter().map(|c| c & 31))
+        .collect()
+}
+
+fn polymod(values: &[u8]) -> u32 {
+    const GEN: [u32; 5] = [
+        0x3b6a_57b2,
+        0x2650_8e6d,
+        0x1ea1_19fa,
+        0x3d42_33dd,
+        0x2a14_62b3,
+    ];
+    let mut chk = 1u32;
+    for v in values {
+        let top = chk >> 25;
+        chk = ((chk & 0x1ff_ffff) << 5) ^ u32::from(*v);
+        for (i, g) in GEN.iter().enumerate() {
+            if (top >> i) & 1 == 1 {
+                chk ^= g;
+            }
+        }
+    }
+    chk
+}
+
+/// Regroups the bits of the values from `from`-bit to `to`-bit groups.
+/// Without padding, fails if the leftover bits are not zero or make up a whole group.
+fn convert_bits(data: &[u8], from: u32, to: u32, pad: bool) -> Option<Vec<u8>> {
+    let mut acc = 0u32;
+    let mut bits = 0u32;
+    let mut result = Vec::with_capacity(data.len() * from as usize / to as usize + 1);
+    let max = (1u32 << to) - 1;
+    for v in data {
+        acc = (acc << from) | u32::from(*v);
+        bits += from;
+        while bits >= to {
+            bits -= to;
+            result.push(((acc >> bits) & max) as u8);
+        }
+        acc &= (1 << bits) - 1;
+    }
+    if pad {
+        if bits > 0 {
+            result.push(((acc << (to - bits)) & max) as u8);
+        }
+    } else if bits >= from || acc != 0 {
+        return None;
+    }
+    Some(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn receiver() -> Receiver {
+        Receiver {
+            opaque_predicate: CompressedRistretto([7u8; 32]),
+            value: ClearValue {
+                qty: 100,
+                flv: Scalar::from(3u64),
+            },
+            qty_blinding: Scalar::from(4u64),
+            flv_blinding: Scalar::from(5u64),
+            expiration: 1_000_000,
+        }
+    }
+
+    #[test]
+    fn bech32m_checksum() {
+        // Test vectors from BIP350.
+        assert_eq!(bech32m_encode("a", &[]), "a1lqfn3a");
+        for s in [
+            "a1lqfn3a",
+            "abcdef1l7aum6echk45nj3s0wdvt2fg8x9yrzpqzd3ryx",
+            "split1checkupstagehandshakeupstreamerranterredcaperredlc445v",
+            "?1v759aa",
+        ]
+        .iter()
+        {
+            assert!(bech32m_decode(s).is_ok(), "{}", s);
+        }
+        // Bech32 (BIP173) checksum is not accepted.
+        assert_eq!(
+            bech32m_decode("a12uel5l").map(|_| ()),
+            Err(AccountError::InvalidAddressChecksum)
+        );
+    }
+
+    #[test]
+    fn addresses() {
+        let address = Address::predicate("zk", CompressedRistretto([1u8; 32]));
+        let encoded = address.encode();
+        assert!(encoded.starts_with("zk1q"));
+        assert_eq!(Address::parse(&encoded), Ok(address.clone()));
+        assert_eq!(Address::parse(&encoded.to_uppercase()), Ok(address));
+
+        let address = Address::receiver("tzk", receiver());
+        let encoded = address.to_string();
+        assert_eq!(encoded.parse::<Address>(), Ok(address));
+
+        // A mistyped character is detected.
+        let mut typo = encoded.clone().into_bytes();
+        typo[10] = if typo[10] == b'q' { b'p' } else { b'q' };
+        assert_eq!(
+            Address::parse(&String::from_utf8(typo).unwrap()),
+            Err(AccountError::InvalidAddressChecksum)
+        );
+
+        // Mixed case and unknown versions are rejected.
+        let mut mixed = encoded.clone();
+        mixed.replace_range(..1, "T");
+        assert_eq!(Address::parse(&mixed), Err(AccountError::InvalidAddress));
+        let unknown = bech32m_encode("zk", &[2, 0, 0]);
+        assert_eq!(Address::parse(&unknown), Err(AccountError::InvalidAddress));
+    }
+}
@@ -0,0 +1,234 @@
+//! Asset metadata: human-readable description of a flavor committed in its issuance metadata.
+//!
+//! The metadata is encoded as the version byte `0x01`, the length-prefixed ticker,
+//! the precision, the issuer predicate and the documentation hash.
+//! Issuers pass the encoding as the `metadata` argument of the `issue` instruction,
+//! so the flavor is bound to the metadata and anyone can check a claimed metadata blob
+//! against the flavor without trusting the party that supplied it.
+
+use curve25519_dalek::ristretto::CompressedRistretto;
+use curve25519_dalek::scalar::Scalar;
+use std::collections::HashMap;
+use zkvm::{Data, Predicate, Value};
+
+use crate::errors::AccountError;
+use crate::payment_uri::Flavor;
+
+const VERSION: u8 = 1;
+
+/// Maximum length of the ticker.
+const MAX_TICKER_LEN: usize = 16;
+
+/// Maximum precision, so that one unit of the asset fits into a u64 quantity.
+const MAX_PRECISION: u8 = 19;
+
+/// Metadata of an asset issued with the `issue` instruction.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AssetMetadata {
+    /// Short name of the asset: 1 to 16 uppercase ASCII letters and digits.
+    pub ticker: String,
+    /// Number of fractional decimal digits in the displayed amounts.
+    pub precision: u8,
+    /// Predicate of the issuer that signs the issuance.
+    pub issuer: CompressedRistretto,
+    /// Hash of the documentation of the asset (e.g. SHA-256 of the prospectus).
+    pub documentation: [u8; 32],
+}
+
+impl AssetMetadata {
+    /// Creates the metadata, checking the ticker and the precision.
+    pub fn new(
+        ticker: &str,
+        precision: u8,
+        issuer: &Predicate,
+        documentation: [u8; 32],
+    ) -> Result<Self, AccountError> {
+        let metadata = AssetMetadata {
+            ticker: ticker.to_string(),
+            precision,
+            issuer: issuer.to_point(),
+            documentation,
+        };
+        metadata.check()?;
+        Ok(metadata)
+    }
+
+    /// Encodes the metadata.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(1 + 1 + self.ticker.len() + 1 + 32 + 32);
+        buf.push(VERSION);
+        buf.push(self.ticker.len() as u8);
+        buf.extend_from_slice(self.ticker.as_bytes());
+        buf.push(self.precision);
+        buf.extend_from_slice(self.issuer.as_bytes());
+        buf.extend_from_slice(&self.documentation);
+        buf
+    }
+
+    /// Decodes the metadata, checking the ticker and the precision.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, AccountError> {
+        let ticker_len = *bytes.get(1).ok_or(AccountError::InvalidAssetMetadata)? as usize;
+        if bytes[0] != VERSION || bytes.len() != 2 + ticker_len + 1 + 32 + 32 {
+            return Err(AccountError::InvalidAssetMetadata);
+        }
+        let (ticker, rest) = bytes[2..].split_at(ticker_len);
+        let mut documentation = [0u8; 32];
+        documentation.copy_from_slice(&rest[33..]);
+        let metadata = AssetMetadata {
+            ticker: String::from_utf8(ticker.to_vec())
+                .map_err(|_| AccountError::InvalidAssetMetadata)?,
+            precision: rest[0],
+            issuer: CompressedRistretto::from_slice(&rest[1..33]),
+            documentation,
+        };
+        metadata.check()?;
+        Ok(metadata)
+    }
+
+    /// Returns the metadata argument of the `issue` instruction.
+    pub fn to_data(&self) -> Data {
+        Data::Opaque(self.to_bytes())
+    }
+
+    /// Computes the flavor issued by the issuer with this metadata.
+    pub fn flavor(&self) -> Scalar {
+        Value::issue_flavor(&Predicate::Opaque(self.issuer), self.to_data())
+    }
+
+    /// Decodes the metadata blob claimed for the flavor and verifies that it matches the flavor.
+    pub fn verify(bytes: &[u8], flavor: &Scalar) -> Result<Self, AccountError> {
+        let metadata = AssetMetadata::from_bytes(bytes)?;
+        if metadata.flavor() != *flavor {
+            return Err(AccountError::AssetMismatch);
+        }
+        Ok(metadata)
+    }
+
+    fn check(&self) -> Result<(), AccountError> {
+        if self.ticker.is_empty()
+            || self.ticker.len() > MAX_TICKER_LEN
+            || !self
+                .ticker
+                .bytes()
+                .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit())
+            || self.precision > MAX_PRECISION
+        {
+            return Err(AccountError::InvalidAssetMetadata);
+        }
+        Ok(())
+    }
+}
+
+/// Registry of the known assets, indexed by their flavors.
+#[derive(Clone, Debug, Default)]
+pub struct AssetRegistry {
+    assets: HashMap<[u8; 32], AssetMetadata>,
+}
+
+impl AssetRegistry {
+    /// Creates an empty registry.
+    pub fn new() -> Self {
+        AssetRegistry::default()
+    }
+
+    /// Adds the asset and returns its flavor.
+    pub fn add(&mut self, metadata: AssetMetadata) -> Scalar {
+        let flavor = metadata.flavor();
+        self.assets.insert(flavor.to_bytes(), metadata);
+        flavor
+    }
+
+    /// Verifies the metadata blob claimed for the flavor and adds the asset.
+    pub fn add_claimed(&mut self, bytes: &[u8], flavor: &Scalar) -> Result<(), AccountError> {
+        let metadata = AssetMetadata::verify(bytes, flavor)?;
+        self.assets.insert(flavor.to_bytes(), metadata);
+        Ok(())
+    }
+
+    /// Returns the metadata of the flavor.
+    pub fn get(&self, flavor: &Scalar) -> Option<&AssetMetadata> {
+        self.assets.get(flavor.as_bytes())
+    }
+
+    /// Returns the flavors with their precisions, e.g. for parsing the payment URIs.
+    pub fn flavors(&self) -> Vec<Flavor> {
+        self.assets
+            .iter()
+            .filter_map(|(flv, metadata)| {
+                Scalar::from_canonical_bytes(*flv).map(|flv| Flavor {
+                    flv,
+                    precision: metadata.precision,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zkvm::VerificationKey;
+
+    fn metadata() -> AssetMetadata {
+        let issuer = Predicate::Key(VerificationKey::from_secret(&Scalar::from(1u64)));
+        AssetMetadata::new("USD", 2, &issuer, [9u8; 32]).unwrap()
+    }
+
+    #[test]
+    fn asset_metadata() {
+        let metadata = metadata();
+        let bytes = metadata.to_bytes();
+        assert_eq!(AssetMetadata::from_bytes(&bytes), Ok(metadata.clone()));
+
+        // Flavor of the `issue` instruction.
+        let issuer = Predicate::Key(VerificationKey::from_secret(&Scalar::from(1u64)));
+        let flavor = Value::issue_flavor(&issuer, Data::Opaque(bytes.clone()));
+        assert_eq!(metadata.flavor(), flavor);
+        assert_eq!(AssetMetadata::verify(&bytes, &flavor), Ok(metadata));
+
+        // Metadata of another asset.
+        let other = AssetMetadata::new("EUR", 2, &issuer, [9u8; 32]).unwrap();
+        assert_eq!(
+            AssetMetadata::verify(&other.to_bytes(), &flavor),
+            Err(AccountError::AssetMismatch)
+        );
+
+        // Invalid tickers, precision and encodings.
+        for (ticker, precision) in
+            [("", 2), ("usd", 2), ("ABCDEFGHIJKLMNOPQ", 2), ("USD", 20)].iter()
+        {
+            assert_eq!(
+                AssetMetadata::new(ticker, *precision, &issuer, [0u8; 32]),
+                Err(AccountError::InvalidAssetMetadata)
+            );
+        }
+        assert_eq!(
+            AssetMetadata::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(AccountError::InvalidAssetMetadata)
+        );
+        assert_eq!(
+            AssetMetadata::from_bytes(&[]),
+            Err(AccountError::InvalidAssetMetadata)
+        );
+    }
+
+    #[test]
+    fn registry() {
+        let metadata = metadata();
+        let flavor = metadata.flavor();
+        let mut registry = AssetRegistry::new();
+        assert_eq!(
+            registry.add_claimed(&metadata.to_bytes(), &Scalar::from(1u64)),
+            Err(AccountError::AssetMismatch)
+        );
+        assert_eq!(registry.add_claimed(&metadata.to_bytes(), &flavor), Ok(()));
+        assert_eq!(registry.get(&flavor), Some(&metadata));
+        assert_eq!(
+            registry.flavors(),
+            vec![Flavor {
+                flv: flavor,
+                precision: 2
+            }]
+        );
+    }
+}
@@ -0,0 +1,63 @@
+//! Errors related to building and signing transactions from the accounts.
+
+/// Represents an error in building a transaction.
+#[derive(Fail, Clone, Debug, Eq, PartialEq)]
+pub enum AccountError {
+    /// This error occurs when the transaction has no payments.
+    #[fail(display = "Transaction has no payments.")]
+    NoPayments,
+
+    /// This error occurs when the transaction has more payments than `TxBuilder::MAX_PAYMENTS`.
+    #[fail(display = "Too many payments.")]
+    TooManyPayments,
+
+    /// This error occurs when the spendable utxos do not cover the payments and the fee.
+    #[fail(display = "Insufficient funds.")]
+    InsufficientFunds,
+
+    /// This error occurs when a receiver expires before the transaction's `maxtime`.
+    #[fail(display = "Receiver expires before the transaction.")]
+    ExpiredReceiver,
+
+    /// This error occurs when a utxo does not hold exactly one value in its payload.
+    #[fail(display = "Utxo must hold exactly one value.")]
+    UnsupportedUtxo,
+
+    /// This error occurs when the xprv is requested from a locked keystore.
+    #[fail(display = "Keystore is locked.")]
+    KeystoreLocked,
+
+    /// This error occurs when the keystore cannot be decrypted with the passphrase.
+    #[fail(display = "Invalid passphrase.")]
+    InvalidPassphrase,
+
+    /// This error occurs when the encoding or the KDF parameters of the keystore are invalid.
+    #[fail(display = "Invalid keystore.")]
+    InvalidKeystore,
+
+    /// This error occurs when the address is malformed or has an unknown version.
+    #[fail(display = "Invalid address.")]
+    InvalidAddress,
+
+    /// This error occurs when the checksum of the address does not match, e.g. because of a typo.
+    #[fail(display = "Invalid address checksum.")]
+    InvalidAddressChecksum,
+
+    /// This error occurs when the payment URI is malformed, requests an unknown flavor
+    /// or contradicts the receiver address.
+    #[fail(display = "Invalid payment URI.")]
+    InvalidPaymentUri,
+
+    /// This error occurs when the amount has more fractional digits than the precision of the flavor
+    /// or does not fit into the quantity.
+    #[fail(display = "Invalid amount.")]
+    InvalidAmount,
+
+    /// This error occurs when the asset metadata is malformed or has an invalid ticker or precision.
+    #[fail(display = "Invalid asset metadata.")]
+    InvalidAssetMetadata,
+
+    /// This error occurs when the asset metadata does not match the flavor.
+    #[fail(display = "Asset metadata does not match the flavor.")]
+    AssetMismatch,
+}
@@ -0,0 +1,401 @@
+//! Keystore: the xprv of the account encrypted with a passphrase for storing at rest.
+//!
+//! The encryption key is derived from the passphrase with Argon2id and a random salt,
+//! and the xprv is encrypted with XChaCha20-Poly1305. The KDF parameters, the salt
+//! and the nonce are stored in the clear and authenticated as the associated data.
+//!
+//! An unlocked keystore does not keep the xprv in the clear: the xprv is encrypted
+//! with a random in-memory wrapping key and is decrypted only when requested with `xprv`.
+
+use argon2::{Config, ThreadMode, Variant, Version};
+use chacha20poly1305::aead::generic_array::GenericArray;
+use chacha20poly1305::aead::{Aead, NewAead, Payload};
+use chacha20poly1305::XChaCha20Poly1305;
+use keytree::Xprv;
+use rand::{CryptoRng, RngCore};
+use zeroize::Zeroize;
+
+use crate::errors::AccountError;
+
+/// Parameters of the Argon2id key derivation.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct KdfParams {
+    /// Amount of memory in KiB.
+    pub mem_cost: u32,
+    /// Number of passes over the memory.
+    pub time_cost: u32,
+    /// Number of parallel lanes.
+    pub lanes: u32,
+}
+
+/// Xprv encrypted with a passphrase.
+pub struct Keystore {
+    params: KdfParams,
+    salt: [u8; SALT_LEN],
+    nonce: [u8; NONCE_LEN],
+    ciphertext: Vec<u8>,
+    // the xprv wrapped with an in-memory key while the keystore is unlocked
+    unlocked: Option<WrappedKey>,
+}
+
+/// Xprv encrypted with a random key that never leaves the memory.
+struct WrappedKey {
+    key: [u8; 32],
+    nonce: [u8; NONCE_LEN],
+    ciphertext: Vec<u8>,
+}
+
+const VERSION: u8 = 1;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+// Version, KDF parameters, salt and nonce.
+const HEADER_LEN: usize = 1 + 12 + SALT_LEN + NONCE_LEN;
+// Xprv and the authentication tag.
+const CIPHERTEXT_LEN: usize = 64 + 16;
+// Maximum KDF parameters accepted from a keystore, so that a crafted keystore
+// cannot make the key derivation exhaust the memory or run indefinitely.
+const MAX_MEM_COST: u32 = 4 * 1024 * 1024;
+const MAX_TIME_COST: u32 = 64;
+const MAX_LANES: u32 = 16;
+
+impl Default for KdfParams {
+    /// Parameters for interactive use: 64 MiB of memory and 3 passes.
+    fn default() -> Self {
+        KdfParams {
+            mem_cost: 64 * 1024,
+            time_cost: 3,
+            lanes: 1,
+        }
+    }
+}
+
+impl KdfParams {
+    /// Fails with `InvalidKeystore` if the parameters exceed the maximum memory (4 GiB),
+    /// number of passes (64) or number of lanes (16).
+    fn check(&self) -> Result<(), AccountError> {
+        if self.mem_cost > MAX_MEM_COST || self.time_cost > MAX_TIME_COST || self.lanes > MAX_LANES
+        {
+            return Err(AccountError::InvalidKeystore);
+        }
+        Ok(())
+    }
+}
+
+impl Keystore {
+    /// Encrypts the xprv with the passphrase. The keystore is created locked.
+    /// Fails with `InvalidKeystore` if the KDF parameters exceed the maximums or are not supported by Argon2.
+    pub fn create<R: RngCore + CryptoRng>(
+        xprv: &Xprv,
+        passphrase: &[u8],
+        params: KdfParams,
+        rng: &mut R,
+    ) -> Result<Self, AccountError> {
+        let mut keystore = Keystore {
+            params,
+            salt: [0u8; SALT_LEN],
+            nonce: [0u8; NONCE_LEN],
+            ciphertext: Vec::new(),
+            unlocked: None,
+        };
+        keystore.encrypt(xprv, passphrase, rng)?;
+        Ok(keystore)
+    }
+
+    /// Decodes the encrypted keystore. The keystore is locked.
+    /// Fails with `InvalidKeystore` if the encoding is invalid or the KDF parameters exceed the maximums.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, AccountError> {
+        if bytes.len() != HEADER_LEN + CIPHERTEXT_LEN || bytes[0] != VERSION {
+            return Err(AccountError::InvalidKeystore);
+        }
+        let read_u32 = |i: usize| {
+            let mut buf = [0u8; 4];
+            buf.copy_from_slice(&bytes[1 + 4 * i..5 + 4 * i]);
+            u32::from_le_bytes(buf)
+        };
+        let mut salt = [0u8; SALT_LEN];
+        salt.copy_from_slice(&bytes[13..13 + SALT_LEN]);
+        let mut nonce = [0u8; NONCE_LEN];
+        nonce.copy_from_slice(&bytes[13 + SALT_LEN..HEADER_LEN]);
+        let params = KdfParams {
+            mem_cost: read_u32(0),
+            time_cost: read_u32(1),
+            lanes: read_u32(2),
+        };
+        params.check()?;
+        Ok(Keystore {
+            params,
+            salt,
+            nonce,
+            ciphertext: bytes[HEADER_LEN..].to_vec(),
+            unlocked: None,
+        })
+    }
+
+    /// Encodes the encrypted keystore: the version byte, the KDF parameters as LE32 integers,
+    /// the salt, the nonce and the encrypted xprv with the authentication tag.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = self.header();
+        buf.extend_from_slice(&self.ciphertext);
+        buf
+    }
+
+    /// Returns the parameters of the key derivation.
+    pub fn params(&self) -> KdfParams {
+        self.params
+    }
+
+    /// Returns true if the keystore is locked.
+    pub fn is_locked(&self) -> bool {
+        self.unlocked.is_none()
+    }
+
+    /// Decrypts the xprv with the passphrase and keeps it wrapped in memory until the keystore is locked.
+    /// Fails with `InvalidPassphrase` if the passphrase is wrong or the keystore is corrupted.
+    pub fn unlock<R: RngCore + CryptoRng>(
+        &mut self,
+        passphrase: &[u8],
+        rng: &mut R,
+    ) -> Result<(), AccountError> {
+        let mut key = derive_key(passphrase, &self.salt, self.params)?;
+        let plaintext = open(&key, &self.nonce, &self.ciphertext, &self.header());
+        key.zeroize();
+        let mut plaintext = plaintext.ok_or(AccountError::InvalidPassphrase)?;
+
+        let mut wrapped = WrappedKey {
+            key: [0u8; 32],
+            nonce: [0u8; NONCE_LEN],
+            ciphertext: Vec::new(),
+        };
+        rng.fill_bytes(&mut wrapped.key);
+        rng.fill_bytes(&mut wrapped.nonce);
+        wrapped.ciphertext = seal(&wrapped.key, &wrapped.nonce, &plaintext, &[]);
+        plaintext.zeroize();
+        self.unlocked = Some(wrapped);
+        Ok(())
+    }
+
+    /// Forgets the unlocked xprv.
+    pub fn lock(&mut self) {
+        self.unlocked = None;
+    }
+
+    /// Returns the xprv of the unlocked keystore.
+    /// Fails with `KeystoreLocked` if the keystore is locked.
+    pub fn xprv(&self) -> Result<Xprv, AccountError> {
+        let wrapped = self.unlocked.as_ref().ok_or(AccountError::KeystoreLocked)?;
+        let mut plaintext = open(&wrapped.key, &wrapped.nonce, &wrapped.ciphertext, &[])
+            .ok_or(AccountError::InvalidKeystore)?;
+        let xprv = Xprv::from_bytes(&plaintext).ok_or(AccountError::InvalidKeystore);
+        plaintext.zeroize();
+        xprv
+    }
+
+    /// Encrypts the xprv of the unlocked keystore with a new passphrase and KDF parameters.
+    /// The keystore remains unlocked.
+    /// Fails with `KeystoreLocked` if the keystore is locked.
+    pub fn change_passphrase<R: RngCore + CryptoRng>(
+        &mut self,
+        passphrase: &[u8],
+        params: KdfParams,
+        rng: &mut R,
+    ) -> Result<(), AccountError> {
+        let xprv = self.xprv()?;
+        let old_params = self.params;
+        self.params = params;
+        self.encrypt(&xprv, passphrase, rng).map_err(|e| {
+            self.params = old_params;
+            e
+        })
+    }
+
+    /// Encrypts the xprv with a new salt and nonce.
+    fn encrypt<R: RngCore + CryptoRng>(
+        &mut self,
+        xprv: &Xprv,
+        passphrase: &[u8],
+        rng: &mut R,
+    ) -> Result<(), AccountError> {
+        let mut salt = [0u8; SALT_LEN];
+        rng.fill_bytes(&mut salt);
+        let mut key = derive_key(passphrase, &salt, self.params)?;
+        self.salt = salt;
+        rng.fill_bytes(&mut self.nonce);
+
+        let mut plaintext = xprv.to_bytes();
+        self.ciphertext = seal(&key, &self.nonce, &plaintext, &self.header());
+        plaintext.zeroize();
+        key.zeroize();
+        Ok(())
+    }
+
+    /// Encodes the data authenticated together with the encrypted xprv.
+    fn header(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(HEADER_LEN + CIPHERTEXT_LEN);
+        buf.push(VERSION);
+        buf.extend_from_slice(&self.params.mem_cost.to_le_bytes());
+        buf.extend_from_slice(&self.params.time_cost.to_le_bytes());
+        buf.extend_from_slice(&self.params.lanes.to_le_bytes());
+        buf.extend_from_slice(&self.salt);
+        buf.extend_from_slice(&self.nonce);
+        buf
+    }
+}
+
+impl Drop for WrappedKey {
+    fn drop(&mut self) {
+        self.key.zeroize();
+    }
+}
+
+/// Derives the encryption key from the passphrase with Argon2id.
+fn derive_key(passphrase: &[u8], salt: &[u8], params: KdfParams) -> Result<Vec<u8>, AccountError> {
+    params.check()?;
+    let config = Config {
+        variant: Variant::Argon2id,
+        version: Version::Version13,
+        mem_cost: params.mem_cost,
+        time_cost: params.time_cost,
+        lanes: params.lanes,
+        thread_mode: ThreadMode::Sequential,
+        secret: &[],
+        ad: &[],
+        hash_length: 32,
+    };
+    argon2::hash_raw(passphrase, salt, &config).map_err(|_| AccountError::InvalidKeystore)
+}
+
+fn seal(key: &[u8], nonce: &[u8], plaintext: &[u8], aad: &[u8]) -> Vec<u8> {
+    XChaCha20Poly1305::new(GenericArray::clone_from_slice(key))
+        .encrypt(
+            GenericArray::from_slice(nonce),
+            Payload {
+                msg: plaintext,
+                aad,
+            },
+        )
+        .expect("encryption of the xprv does not fail")
+}
+
+fn open(key: &[u8], nonce: &[u8], ciphertext: &[u8], aad: &[u8]) -> Option<Vec<u8>> {
+    XChaCha20Poly1305::new(GenericArray::clone_from_slice(key))
+        .decrypt(
+            GenericArray::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad,
+            },
+        )
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Cheap parameters to keep the tests fast.
+    const PARAMS: KdfParams = KdfParams {
+        mem_cost: 64,
+        time_cost: 1,
+        lanes: 1,
+    };
+
+    #[test]
+    fn lock_and_unlock() {
+        let mut rng = rand::thread_rng();
+        let xprv = Xprv::random(&mut rng);
+        let keystore = Keystore::create(&xprv, b"correct horse", PARAMS, &mut rng).unwrap();
+        assert!(keystore.is_locked());
+        assert_eq!(
+            keystore.xprv().map(|_| ()),
+            Err(AccountError::KeystoreLocked)
+        );
+
+        let mut keystore = Keystore::from_bytes(&keystore.to_bytes()).unwrap();
+        assert_eq!(keystore.params(), PARAMS);
+        assert_eq!(
+            keystore.unlock(b"battery staple", &mut rng),
+            Err(AccountError::InvalidPassphrase)
+        );
+        keystore.unlock(b"correct horse", &mut rng).unwrap();
+        assert_eq!(keystore.xprv().unwrap().to_bytes()[..], xprv.to_bytes()[..]);
+
+        keystore.lock();
+        assert_eq!(
+            keystore.xprv().map(|_| ()),
+            Err(AccountError::KeystoreLocked)
+        );
+
+        // KDF parameters are authenticated.
+        let mut bytes = keystore.to_bytes();
+        bytes[5] += 1;
+        let mut tampered = Keystore::from_bytes(&bytes).unwrap();
+        assert_eq!(
+            tampered.unlock(b"correct horse", &mut rng),
+            Err(AccountError::InvalidPassphrase)
+        );
+        assert_eq!(
+            Keystore::from_bytes(&bytes[1..]).map(|_| ()),
+            Err(AccountError::InvalidKeystore)
+        );
+    }
+
+    #[test]
+    fn excessive_kdf_params() {
+        let mut rng = rand::thread_rng();
+        let xprv = Xprv::random(&mut rng);
+        let bytes = Keystore::create(&xprv, b"correct horse", PARAMS, &mut rng)
+            .unwrap()
+            .to_bytes();
+
+        // Memory, passes and lanes are encoded at the offsets 1, 5 and 9.
+        for (offset, max) in [(1, MAX_MEM_COST), (5, MAX_TIME_COST), (9, MAX_LANES)].iter() {
+            let mut tampered = bytes.clone();
+            tampered[*offset..*offset + 4].copy_from_slice(&max.to_le_bytes());
+            assert!(Keystore::from_bytes(&tampered).is_ok());
+            tampered[*offset..*offset + 4].copy_from_slice(&(max + 1).to_le_bytes());
+            assert_eq!(
+                Keystore::from_bytes(&tampered).map(|_| ()),
+                Err(AccountError::InvalidKeystore)
+            );
+        }
+
+        let params = KdfParams {
+            lanes: MAX_LANES + 1,
+            ..PARAMS
+        };
+        assert_eq!(
+            Keystore::create(&xprv, b"correct horse", params, &mut rng).map(|_| ()),
+            Err(AccountError::InvalidKeystore)
+        );
+    }
+
+    #[test]
+    fn change_passphrase() {
+        let mut rng = rand::thread_rng();
+        let xprv = Xprv::random(&mut rng);
+        let mut keystore = Keystore::create(&xprv, b"old", PARAMS, &mut rng).unwrap();
+        assert_eq!(
+            keystore.change_passphrase(b"new", PARAMS, &mut rng),
+            Err(AccountError::KeystoreLocked)
+        );
+
+        keystore.unlock(b"old", &mut rng).unwrap();
+        let params = KdfParams {
+            time_cost: 2,
+            ..PARAMS
+        };
+        keystore
+            .change_passphrase(b"new", params, &mut rng)
+            .unwrap();
+        assert!(!keystore.is_locked());
+
+        let mut keystore = Keystore::from_bytes(&keystore.to_bytes()).unwrap();
+        assert_eq!(keystore.params(), params);
+        assert_eq!(
+            keystore.unlock(b"old", &mut rng),
+            Err(AccountError::InvalidPassphrase)
+        );
+        keystore.unlock(b"new", &mut rng).unwrap();
+        assert_eq!(keystore.xprv().unwrap().to_bytes()[..], xprv.to_bytes()[..]);
+    }
+}
@@ -0,0 +1,27 @@
+#![deny(missing_docs)]
+//! Accounts and payment receivers for ZkVM wallets.
+
+#[macro_use]
+extern crate failure;
+
+mod account;
+mod address;
+mod assets;
+mod errors;
+mod keystore;
+mod payment_uri;
+mod receiver;
+mod txbuilder;
+mod viewkey;
+mod wallet;
+
+pub use self::account::{Account, ReceivedUtxo};
+pub use self::address::{Address, AddressPayload};
+pub use self::assets::{AssetMetadata, AssetRegistry};
+pub use self::errors::AccountError;
+pub use self::keystore::{KdfParams, Keystore};
+pub use self::payment_uri::{Flavor, PaymentUri};
+pub use self::receiver::{ClearValue, Receiver, ReceiverWitness};
+pub use self::txbuilder::{TxBuilder, UnsignedTx};
+pub use self::viewkey::{ValueWitness, ViewKey, ViewPubkey};
+pub use self::wallet::{ScannedUtxo, Wallet};
@@ -0,0 +1,396 @@
+//! Payment URIs: requests for payment to an address that can be shared as links or QR codes.
+//!
+//! `zkvm:<address>?amount=<decimal>&flavor=<hex>&memo=<text>&exp=<timestamp>`
+//!
+//! All parameters are optional. The amount is a decimal number with at most as many
+//! fractional digits as the precision of the flavor. The flavor is the hex-encoded scalar,
+//! omitted for the flavor of the receiver address or the zero flavor of a predicate address.
+//! The memo is percent-encoded UTF-8 text, and the expiration is a timestamp in milliseconds
+//! since the Unix epoch. Unknown parameters are ignored, unless they start with `req-`.
+
+use curve25519_dalek::scalar::Scalar;
+
+use crate::address::{Address, AddressPayload};
+use crate::errors::AccountError;
+
+const SCHEME: &str = "zkvm";
+
+/// Maximum precision of a flavor, so that one unit (`10^precision`) fits into a u64 quantity.
+const MAX_PRECISION: u8 = 19;
+
+/// Flavor with its declared precision: the number of decimal digits of the quantity
+/// after the decimal point in the displayed amounts.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Flavor {
+    /// Flavor scalar.
+    pub flv: Scalar,
+    /// Number of fractional decimal digits.
+    pub precision: u8,
+}
+
+/// Request for a payment to an address.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PaymentUri {
+    /// Address to be paid.
+    pub address: Address,
+    /// Requested quantity in the smallest units of the flavor.
+    pub amount: Option<u64>,
+    /// Requested flavor.
+    pub flavor: Flavor,
+    /// Message to the payer.
+    pub memo: Option<String>,
+    /// Timestamp after which the request should not be paid (in milliseconds since the Unix epoch).
+    pub expiration: Option<u64>,
+}
+
+impl PaymentUri {
+    /// Creates a request for a payment of the flavor to the address, without other parameters.
+    pub fn new(address: Address, flavor: Flavor) -> Self {
+        PaymentUri {
+            address,
+            amount: None,
+            flavor,
+            memo: None,
+            expiration: None,
+        }
+    }
+
+    /// Encodes the request as a URI.
+    /// Fails if the precision of the flavor is too large or the parameters
+    /// do not match the value and expiration of the receiver address.
+    pub fn to_uri(&self) -> Result<String, AccountError> {
+        self.check()?;
+        let mut params = Vec::new();
+        if let Some(amount) = self.amount {
+            params.push(format!(
+                "amount={}",
+                format_amount(amount, self.flavor.precision)
+            ));
+        }
+        if self.flavor.flv != default_flavor(&self.address) {
+            params.push(format!(
+                "flavor={}",
+                hex::encode(self.flavor.flv.as_bytes())
+            ));
+        }
+        if let Some(memo) = &self.memo {
+            params.push(format!("memo={}", percent_encode(memo)));
+        }
+        if let Some(expiration) = self.expiration {
+            params.push(format!("exp={}", expiration));
+        }
+
+        let mut uri = format!("{}:{}", SCHEME, self.address);
+        if !params.is_empty() {
+            uri.push('?');
+            uri.push_str(&params.join("&"));
+        }
+        Ok(uri)
+    }
+
+    /// Parses the URI, looking up the precision of the requested flavor in the list of known flavors.
+    /// Fails with `InvalidAmount` if the amount has more fractional digits than the precision of the flavor
+    /// or does not fit into a u64 quantity, and with `InvalidPaymentUri` if the URI is malformed,
+    /// the flavor is unknown or the parameters do not match the receiver address.
+    pub fn parse(uri: &str, flavors: &[Flavor]) -> Result<Self, AccountError> {
+        let colon = uri.find(':').ok_or(AccountError::InvalidPaymentUri)?;
+        if !uri[..colon].eq_ignore_ascii_case(SCHEME) {
+            return Err(AccountError::InvalidPaymentUri);
+        }
+        let rest = &uri[colon + 1..];
+        let (address, query) = match rest.find('?') {
+            Some(i) => (&rest[..i], Some(&rest[i + 1..])),
+            None => (rest, None),
+        };
+        let address = Address::parse(address)?;
+
+        let mut amount = None;
+        let mut flv = None;
+        let mut memo = None;
+        let mut expiration = None;
+        for param in query
+            .into_iter()
+            .flat_map(|q| q.split('&'))
+            .filter(|p| !p.is_empty())
+        {
+            let eq = param.find('=').ok_or(AccountError::InvalidPaymentUri)?;
+            let (key, value) = (&param[..eq], &param[eq + 1..]);
+            let duplicate = match key {
+                "amount" => amount.replace(value).is_some(),
+                "flavor" => flv.replace(parse_flavor(value)?).is_some(),
+                "memo" => memo.replace(percent_decode(value)?).is_some(),
+                "exp" => expiration
+                    .replace(
+                        value
+                            .parse::<u64>()
+                            .map_err(|_| AccountError::InvalidPaymentUri)?,
+                    )
+                    .is_some(),
+                _ if key.starts_with("req-") => return Err(AccountError::InvalidPaymentUri),
+                _ => false,
+            };
+            if duplicate {
+                return Err(AccountError::InvalidPaymentUri);
+            }
+        }
+
+        let flv = flv.unwrap_or_else(|| default_flavor(&address));
+        let flavor = *flavors
+            .iter()
+            .find(|f| f.flv == flv)
+            .ok_or(AccountError::InvalidPaymentUri)?;
+        if flavor.precision > MAX_PRECISION {
+            return Err(AccountError::InvalidAmount);
+        }
+        let amount = match amount {
+            Some(a) => Some(parse_amount(a, flavor.precision)?),
+            None => None,
+        };
+        let request = PaymentUri {
+            address,
+            amount,
+            flavor,
+            memo,
+            expiration,
+        };
+        request.check()?;
+        Ok(request)
+    }
+
+    /// Checks the precision of the flavor and that the parameters agree with the receiver address.
+    fn check(&self) -> Result<(), AccountError> {
+        if self.flavor.precision > MAX_PRECISION {
+            return Err(AccountError::InvalidAmount);
+        }
+        if let AddressPayload::Receiver(receiver) = &self.address.payload {
+            if self.flavor.flv != receiver.value.flv
+                || self.amount.map_or(false, |a| a != receiver.value.qty)
+                || self.expiration.map_or(false, |e| e != receiver.expiration)
+            {
+                return Err(AccountError::InvalidPaymentUri);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Flavor of the receiver address, or the zero flavor for a predicate address.
+fn default_flavor(address: &Address) -> Scalar {
+    match &address.payload {
+        AddressPayload::Receiver(receiver) => receiver.value.flv,
+        AddressPayload::Predicate(_) => Scalar::zero(),
+    }
+}
+
+fn parse_flavor(s: &str) -> Result<Scalar, AccountError> {
+    let bytes = hex::decode(s).map_err(|_| AccountError::InvalidPaymentUri)?;
+    if bytes.len() != 32 {
+        return Err(AccountError::InvalidPaymentUri);
+    }
+    let mut buf = [0u8; 32];
+    buf.copy_from_slice(&bytes);
+    Scalar::from_canonical_bytes(buf).ok_or(AccountError::InvalidPaymentUri)
+}
+
+/// Formats the quantity as a decimal number without the trailing zeros in the fractional part.
+fn format_amount(qty: u64, precision: u8) -> String {
+    let unit = 10u64.pow(u32::from(precision));
+    let (int, frac) = (qty / unit, qty % unit);
+    if frac == 0 {
+        return int.to_string();
+    }
+    let frac = format!("{:0width$}", frac, width = precision as usize);
+    format!("{}.{}", int, frac.trim_end_matches('0'))
+}
+
+/// Parses the decimal number into the quantity in the smallest units.
+fn parse_amount(s: &str, precision: u8) -> Result<u64, AccountError> {
+    let (int, frac) = match s.find('.') {
+        Some(i) => (&s[..i], &s[i + 1..]),
+        None => (s, "0"),
+    };
+    let is_digits = |s: &str| !s.is_empty() && s.bytes().all(|c| c.is_ascii_digit());
+    if !is_digits(int) || !is_digits(frac) {
+        return Err(AccountError::InvalidAmount);
+    }
+    let frac = frac.trim_end_matches('0');
+    if frac.len() > precision as usize {
+        return Err(AccountError::InvalidAmount);
+    }
+    int.bytes()
+        .chain(frac.bytes())
+        .chain((frac.len()..precision as usize).map(|_| b'0'))
+        .try_fold(0u64, |qty, c| {
+            qty.checked_mul(10)?.checked_add(u64::from(c - b'0'))
+        })
+        .ok_or(AccountError::InvalidAmount)
+}
+
+/// Percent-encodes all characters except the unreserved ones (RFC 3986).
+fn percent_encode(s: &str) -> String {
+    let mut encoded = String::with_capacity(s.len());
+    for c in s.bytes() {
+        match c {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(c as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", c)),
+        }
+    }
+    encoded
+}
+
+fn percent_decode(s: &str) -> Result<String, AccountError> {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = s.get(i + 1..i + 3).ok_or(AccountError::InvalidPaymentUri)?;
+            let byte = u8::from_str_radix(hex, 16).map_err(|_| AccountError::InvalidPaymentUri)?;
+            decoded.push(byte);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).map_err(|_| AccountError::InvalidPaymentUri)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::receiver::{ClearValue, Receiver};
+    use curve25519_dalek::ristretto::CompressedRistretto;
+
+    fn flavors() -> Vec<Flavor> {
+        vec![
+            Flavor {
+                flv: Scalar::zero(),
+                precision: 8,
+            },
+            Flavor {
+                flv: Scalar::from(7u64),
+                precision: 2,
+            },
+        ]
+    }
+
+    #[test]
+    fn amounts() {
+        assert_eq!(format_amount(150_000_000, 8), "1.5");
+        assert_eq!(format_amount(1, 8), "0.00000001");
+        assert_eq!(format_amount(42, 0), "42");
+        assert_eq!(parse_amount("1.5", 8), Ok(150_000_000));
+        assert_eq!(parse_amount("0.00000001", 8), Ok(1));
+        assert_eq!(parse_amount("12.50", 1), Ok(125));
+        assert_eq!(
+            parse_amount("18446744073709551615", 0),
+            Ok(u64::max_value())
+        );
+
+        // Too many fractional digits, overflow and malformed numbers.
+        assert_eq!(parse_amount("0.001", 2), Err(AccountError::InvalidAmount));
+        assert_eq!(
+            parse_amount("18446744073709551616", 0),
+            Err(AccountError::InvalidAmount)
+        );
+        assert_eq!(
+            parse_amount("184467440737.1", 8),
+            Err(AccountError::InvalidAmount)
+        );
+        for s in ["", ".5", "1.", "-1", "1e3", "1.2.3"].iter() {
+            assert_eq!(
+                parse_amount(s, 8),
+                Err(AccountError::InvalidAmount),
+                "{}",
+                s
+            );
+        }
+    }
+
+    #[test]
+    fn payment_uris() {
+        let address = Address::predicate("zk", CompressedRistretto([1u8; 32]));
+        let mut request = PaymentUri::new(address.clone(), flavors()[1]);
+        request.amount = Some(1250);
+        request.memo = Some("Invoice #12: café".to_string());
+        request.expiration = Some(1_000_000);
+        let uri = request.to_uri().unwrap();
+        assert_eq!(
+            uri,
+            format!(
+                "zkvm:{}?amount=12.5&flavor={}&memo=Invoice%20%2312%3A%20caf%C3%A9&exp=1000000",
+                address,
+                hex::encode(Scalar::from(7u64).as_bytes())
+            )
+        );
+        assert_eq!(PaymentUri::parse(&uri, &flavors()), Ok(request));
+
+        // Zero flavor is implied, unknown parameters are ignored.
+        let uri = format!(
+            "ZKVM:{}?amount=0.1&label=shop",
+            address.to_string().to_uppercase()
+        );
+        let request = PaymentUri::parse(&uri, &flavors()).unwrap();
+        assert_eq!(request.flavor, flavors()[0]);
+        assert_eq!(request.amount, Some(10_000_000));
+        assert_eq!(
+            PaymentUri::new(address.clone(), flavors()[0]).to_uri(),
+            Ok(format!("zkvm:{}", address))
+        );
+
+        // Invalid requests.
+        let unknown = format!("flavor={}", hex::encode(Scalar::from(8u64).as_bytes()));
+        for (query, err) in [
+            (unknown.as_str(), AccountError::InvalidPaymentUri),
+            ("flavor=07", AccountError::InvalidPaymentUri),
+            ("amount=1&amount=2", AccountError::InvalidPaymentUri),
+            ("req-signature=1", AccountError::InvalidPaymentUri),
+            ("memo=%E9", AccountError::InvalidPaymentUri),
+            ("amount=0.000000001", AccountError::InvalidAmount),
+        ]
+        .iter()
+        {
+            assert_eq!(
+                PaymentUri::parse(&format!("zkvm:{}?{}", address, query), &flavors()),
+                Err(err.clone()),
+                "{}",
+                query
+            );
+        }
+        assert_eq!(
+            PaymentUri::parse(&format!("bitcoin:{}", address), &flavors()),
+            Err(AccountError::InvalidPaymentUri)
+        );
+    }
+
+    #[test]
+    fn receiver_uris() {
+        let receiver = Receiver {
+            opaque_predicate: CompressedRistretto([7u8; 32]),
+            value: ClearValue {
+                qty: 250,
+                flv: Scalar::from(7u64),
+            },
+            qty_blinding: Scalar::from(4u64),
+            flv_blinding: Scalar::from(5u64),
+            expiration: 1_000_000,
+        };
+        let address = Address::receiver("zk", receiver);
+        let mut request = PaymentUri::new(address.clone(), flavors()[1]);
+        request.amount = Some(250);
+        let uri = request.to_uri().unwrap();
+        assert_eq!(uri, format!("zkvm:{}?amount=2.5", address));
+        assert_eq!(PaymentUri::parse(&uri, &flavors()), Ok(request.clone()));
+
+        // Parameters must match the receiver.
+        request.amount = Some(300);
+        assert_eq!(request.to_uri(), Err(AccountError::InvalidPaymentUri));
+        assert_eq!(
+            PaymentUri::parse(&format!("zkvm:{}?exp=5", address), &flavors()),
+            Err(AccountError::InvalidPaymentUri)
+        );
+    }
+}
@@ -0,0 +1,85 @@
+//! Receivers: one-time addresses that request a payment of a given value.
+
+use curve25519_dalek::ristretto::CompressedRistretto;
+use curve25519_dalek::scalar::Scalar;
+use keytree::Xprv;
+use merlin::Transcript;
+use zkvm::{Commitment, Contract, PortableItem, Predicate, Value};
+
+/// Value with a cleartext quantity and flavor.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ClearValue {
+    /// Quantity of the value.
+    pub qty: u64,
+    /// Flavor of the value.
+    pub flv: Scalar,
+}
+
+/// Receiver is a one-time address given to the payer.
+/// It specifies the predicate that must protect the payment, the requested value
+/// and the blinding factors for the value commitments, so the recipient can recognize
+/// the payment output and open its commitments.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Receiver {
+    /// One-time key that must protect the payment output.
+    pub opaque_predicate: CompressedRistretto,
+    /// Requested value.
+    pub value: ClearValue,
+    /// Blinding factor for the quantity commitment.
+    pub qty_blinding: Scalar,
+    /// Blinding factor for the flavor commitment.
+    pub flv_blinding: Scalar,
+    /// Timestamp after which the receiver should not be paid (in milliseconds since the Unix epoch).
+    pub expiration: u64,
+}
+
+/// Receiver with the secret data kept by the recipient.
+#[derive(Clone, Debug)]
+pub struct ReceiverWitness {
+    /// Sequence number of the receiver's key within the account.
+    pub sequence: u64,
+    /// The receiver given to the payer.
+    pub receiver: Receiver,
+}
+
+impl Receiver {
+    /// Returns the predicate for the payment output.
+    pub fn predicate(&self) -> Predicate {
+        Predicate::Opaque(self.opaque_predicate)
+    }
+
+    /// Returns the value with the commitments expected in the payment output.
+    pub fn value(&self) -> Value {
+        Value {
+            qty: Commitment::blinded_with_factor(self.value.qty, self.qty_blinding),
+            flv: Commitment::blinded_with_factor(self.value.flv, self.flv_blinding),
+        }
+    }
+
+    /// Returns true if the contract is protected by the receiver's predicate
+    /// and holds a value with the requested quantity and flavor commitments.
+    pub fn matches(&self, contract: &Contract) -> bool {
+        if contract.predicate.to_point() != self.opaque_predicate {
+            return false;
+        }
+        contract.payload.iter().any(|item| match item {
+            PortableItem::Value(v) => {
+                v.qty.verify_opening(self.value.qty, self.qty_blinding)
+                    && v.flv.verify_opening(self.value.flv, self.flv_blinding)
+            }
+            PortableItem::Data(_) => false,
+        })
+    }
+}
+
+impl ReceiverWitness {
+    /// Derives the key for the given sequence number from the account's xprv.
+    pub fn signing_key(&self, xprv: &Xprv) -> Scalar {
+        xprv.derive_key(|t| commit_sequence(t, self.sequence))
+    }
+}
+
+/// Selects the account's key for a given sequence number.
+pub(crate) fn commit_sequence(t: &mut Transcript, sequence: u64) {
+    t.commit_u64(b"sequence", sequence)
+}
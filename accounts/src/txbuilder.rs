@@ -0,0 +1,418 @@
+//! Transaction builder: pays the receivers from the account's utxos.
+//!
+//! The builder selects the utxos covering the payments and the fee, creates the change
+//! with new receivers of the account and merges and splits the values with a single `cloak`.
+//! The outputs of the `cloak` are padded to a power of two with zero values, which are retired,
+//! so the size of the circuit depends only on the rounded number of payments.
+
+use bulletproofs::BulletproofGens;
+use curve25519_dalek::scalar::Scalar;
+use keytree::Xprv;
+use rand::{CryptoRng, RngCore};
+use zkvm::{
+    Contract, Data, Output, PortableItem, Predicate, Program, Prover, Signature, Tx, TxHeader,
+    TxID, TxLog, VMError, VerificationKey,
+};
+
+use crate::account::{Account, ReceivedUtxo};
+use crate::errors::AccountError;
+use crate::receiver::{ClearValue, Receiver, ReceiverWitness};
+
+/// Builder of a transaction paying the receivers from the account's utxos.
+/// The fee is paid in the asset with the zero flavor.
+#[derive(Clone, Debug)]
+pub struct TxBuilder {
+    header: TxHeader,
+    utxos: Vec<ReceivedUtxo>,
+    payments: Vec<Receiver>,
+    fee: u64,
+}
+
+/// Transaction that is ready to be signed with the account's xprv.
+#[derive(Clone, Debug)]
+pub struct UnsignedTx {
+    /// Header of the transaction.
+    pub header: TxHeader,
+    /// Program spending the inputs and creating the payment and change outputs.
+    pub program: Program,
+    /// Utxos spent by the transaction, in the order of their signatures.
+    pub inputs: Vec<ReceivedUtxo>,
+    /// Receivers of the change, which are pending in the account until the transaction is confirmed.
+    pub change: Vec<ReceiverWitness>,
+}
+
+impl TxBuilder {
+    /// Maximum number of payments in a transaction.
+    pub const MAX_PAYMENTS: usize = 64;
+
+    /// Creates a builder of a transaction with the given header.
+    pub fn new(header: TxHeader) -> Self {
+        TxBuilder {
+            header,
+            utxos: Vec::new(),
+            payments: Vec::new(),
+            fee: 0,
+        }
+    }
+
+    /// Adds the utxos that can be spent by the transaction.
+    pub fn spendable<I>(mut self, utxos: I) -> Self
+    where
+        I: IntoIterator<Item = ReceivedUtxo>,
+    {
+        self.utxos.extend(utxos);
+        self
+    }
+
+    /// Adds a payment to the receiver.
+    pub fn pay(mut self, receiver: Receiver) -> Self {
+        self.payments.push(receiver);
+        self
+    }
+
+    /// Adds payments to the receivers, which may request values of different flavors.
+    pub fn pay_all<I>(mut self, receivers: I) -> Self
+    where
+        I: IntoIterator<Item = Receiver>,
+    {
+        self.payments.extend(receivers);
+        self
+    }
+
+    /// Sets the fee.
+    pub fn fee(mut self, fee: u64) -> Self {
+        self.fee = fee;
+        self
+    }
+
+    /// Selects the utxos to spend, largest first, and creates the change outputs
+    /// with new receivers of the account. The change receivers expire at the transaction's `maxtime`.
+    pub fn build<R: RngCore + CryptoRng>(
+        self,
+        account: &mut Account,
+        rng: &mut R,
+    ) -> Result<UnsignedTx, AccountError> {
+        if self.payments.is_empty() {
+            return Err(AccountError::NoPayments);
+        }
+        if self.payments.len() > Self::MAX_PAYMENTS {
+            return Err(AccountError::TooManyPayments);
+        }
+        if self
+            .payments
+            .iter()
+            .any(|receiver| receiver.expiration < self.header.maxtime)
+        {
+            return Err(AccountError::ExpiredReceiver);
+        }
+
+        // Quantities to pay per flavor, including the fee.
+        let mut needed: Vec<ClearValue> = Vec::new();
+        let fee = ClearValue {
+            qty: self.fee,
+            flv: Scalar::zero(),
+        };
+        for value in Some(fee)
+            .into_iter()
+            .chain(self.payments.iter().map(|r| r.value))
+        {
+            match needed.iter_mut().find(|v| v.flv == value.flv) {
+                Some(v) => {
+                    v.qty = v
+                        .qty
+                        .checked_add(value.qty)
+                        .ok_or(AccountError::InsufficientFunds)?
+                }
+                None => needed.push(value),
+            }
+        }
+
+        let mut utxos = self.utxos;
+        utxos.sort_by(|a, b| b.value().qty.cmp(&a.value().qty));
+        let mut inputs = Vec::new();
+        let mut change = Vec::new();
+        for value in needed.into_iter() {
+            let mut remaining = value.qty;
+            let (candidates, rest): (Vec<_>, Vec<_>) = utxos
+                .into_iter()
+                .partition(|utxo| utxo.value().flv == value.flv);
+            utxos = rest;
+            for utxo in candidates.into_iter() {
+                if remaining == 0 {
+                    break;
+                }
+                let qty = utxo.value().qty;
+                if qty > remaining {
+                    change.push(ClearValue {
+                        qty: qty - remaining,
+                        flv: value.flv,
+                    });
+                    remaining = 0;
+                } else {
+                    remaining -= qty;
+                }
+                inputs.push(utxo);
+            }
+            if remaining > 0 {
+                return Err(AccountError::InsufficientFunds);
+            }
+        }
+
+        let spent = inputs
+            .iter()
+            .map(spendable_output)
+            .collect::<Result<Vec<_>, _>>()?;
+        let maxtime = self.header.maxtime;
+        let change = change
+            .into_iter()
+            .map(|value| ReceiverWitness {
+                sequence: account.sequence(),
+                receiver: account.generate_receiver(value, maxtime, rng),
+            })
+            .collect::<Vec<_>>();
+
+        let outputs = self
+            .payments
+            .iter()
+            .chain(change.iter().map(|w| &w.receiver))
+            .collect::<Vec<_>>();
+        let padding = outputs.len().next_power_of_two() - outputs.len();
+        let fee = self.fee;
+        let program = Program::build(|p| {
+            for output in spent.iter() {
+                p.push(output.clone()).input().sign_tx(); // stack: values
+            }
+            let mut m = spent.len();
+            if fee > 0 {
+                p.push(Data::Opaque(fee.to_le_bytes().to_vec())).fee(); // stack: values, fee
+                m += 1;
+            }
+            for receiver in outputs.iter() {
+                let value = receiver.value();
+                p.push(value.qty).push(value.flv);
+            }
+            for _ in 0..padding {
+                p.push(0u64).push(Scalar::zero());
+            }
+            p.cloak(m, outputs.len() + padding); // stack: payments, change, zero values
+            for _ in 0..padding {
+                p.retire();
+            }
+            for receiver in outputs.iter().rev() {
+                p.push(receiver.predicate()).output(1);
+            }
+            p
+        });
+
+        Ok(UnsignedTx {
+            header: self.header,
+            program,
+            inputs,
+            change,
+        })
+    }
+}
+
+impl UnsignedTx {
+    /// Derives the keys signing the transaction, in the order of the inputs.
+    pub fn signing_keys(&self, xprv: &Xprv) -> Vec<Scalar> {
+        self.inputs
+            .iter()
+            .map(|utxo| utxo.receiver_witness.signing_key(xprv))
+            .collect()
+    }
+
+    /// Proves and signs the transaction with the keys derived from the account's xprv.
+    pub fn sign(
+        self,
+        xprv: &Xprv,
+        bp_gens: &BulletproofGens,
+    ) -> Result<(Tx, TxID, TxLog), VMError> {
+        let keys = self.signing_keys(xprv);
+        Prover::build_tx(self.program, self.header, bp_gens, |t, _| {
+            Signature::sign_aggregated(t, &keys)
+        })
+    }
+}
+
+/// Returns the output with the opened value commitments and the one-time key as a signing key,
+/// so the prover can spend it with `input` and `signtx`.
+fn spendable_output(utxo: &ReceivedUtxo) -> Result<Output, AccountError> {
+    let (contract, _) = utxo.output.clone().into_contract();
+    match contract.payload.as_slice() {
+        [PortableItem::Value(_)] => {}
+        _ => return Err(AccountError::UnsupportedUtxo),
+    }
+    let receiver = &utxo.receiver_witness.receiver;
+    Ok(Output::new(Contract {
+        anchor: contract.anchor,
+        payload: vec![PortableItem::Value(receiver.value())],
+        predicate: Predicate::Key(VerificationKey::from(receiver.opaque_predicate)),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zkvm::{Anchor, Verifier};
+
+    fn fund(account: &mut Account, value: ClearValue, anchor: u8) -> ReceivedUtxo {
+        let receiver = account.generate_receiver(value, u64::max_value(), &mut rand::thread_rng());
+        let output = Output::new(Contract {
+            anchor: Anchor::nonce([anchor; 32], &Predicate::Opaque(Default::default()), 0),
+            payload: vec![PortableItem::Value(receiver.value())],
+            predicate: receiver.predicate(),
+        });
+        account.match_output(&output).unwrap()
+    }
+
+    #[test]
+    fn pay_with_change() {
+        let mut rng = rand::thread_rng();
+        let alice_xprv = Xprv::random(&mut rng);
+        let mut alice = Account::new(alice_xprv.to_xpub());
+        let mut bob = Account::new(Xprv::random(&mut rng).to_xpub());
+        let usd = Scalar::from(1u64);
+        let utxos = vec![
+            fund(&mut alice, ClearValue { qty: 7, flv: usd }, 0),
+            fund(&mut alice, ClearValue { qty: 10, flv: usd }, 1),
+            fund(&mut alice, ClearValue { qty: 1, flv: usd }, 2),
+            fund(
+                &mut alice,
+                ClearValue {
+                    qty: 5,
+                    flv: Scalar::zero(),
+                },
+                3,
+            ),
+        ];
+        let header = TxHeader {
+            version: 0,
+            mintime: 0,
+            maxtime: 1000,
+            maxcost: u64::max_value(),
+        };
+        let payment = bob.generate_receiver(ClearValue { qty: 12, flv: usd }, 1000, &mut rng);
+
+        let unsigned_tx = TxBuilder::new(header)
+            .spendable(utxos.clone())
+            .pay(payment.clone())
+            .fee(2)
+            .build(&mut alice, &mut rng)
+            .unwrap();
+        // Largest utxos are selected first.
+        let spent = unsigned_tx
+            .inputs
+            .iter()
+            .map(|utxo| utxo.value().qty)
+            .collect::<Vec<_>>();
+        assert_eq!(spent, vec![5, 10, 7]);
+        let change = unsigned_tx
+            .change
+            .iter()
+            .map(|w| w.receiver.value)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            change,
+            vec![
+                ClearValue {
+                    qty: 3,
+                    flv: Scalar::zero()
+                },
+                ClearValue { qty: 5, flv: usd },
+            ]
+        );
+
+        let bp_gens = BulletproofGens::new(512, 1);
+        let (tx, _, _) = unsigned_tx.sign(&alice_xprv, &bp_gens).unwrap();
+        let verified_tx = Verifier::verify_tx(tx, &bp_gens).unwrap();
+        assert_eq!(verified_tx.feerate.fee(), 2);
+        assert_eq!(bob.receive(&verified_tx).len(), 1);
+        assert_eq!(alice.receive(&verified_tx).len(), 2);
+
+        let build = |builder: TxBuilder| {
+            builder
+                .build(
+                    &mut Account::new(alice_xprv.to_xpub()),
+                    &mut rand::thread_rng(),
+                )
+                .map(|_| ())
+        };
+        assert_eq!(
+            build(TxBuilder::new(header).spendable(utxos.clone())),
+            Err(AccountError::NoPayments)
+        );
+        assert_eq!(
+            build(
+                TxBuilder::new(header)
+                    .spendable(utxos.clone())
+                    .pay(payment.clone())
+                    .fee(6)
+            ),
+            Err(AccountError::InsufficientFunds)
+        );
+        let mut expiring = payment;
+        expiring.expiration = 999;
+        assert_eq!(
+            build(TxBuilder::new(header).spendable(utxos).pay(expiring)),
+            Err(AccountError::ExpiredReceiver)
+        );
+    }
+
+    #[test]
+    fn batch_payments() {
+        let mut rng = rand::thread_rng();
+        let alice_xprv = Xprv::random(&mut rng);
+        let mut alice = Account::new(alice_xprv.to_xpub());
+        let mut bob = Account::new(Xprv::random(&mut rng).to_xpub());
+        let (usd, eur) = (Scalar::from(1u64), Scalar::from(2u64));
+        let utxos = vec![
+            fund(&mut alice, ClearValue { qty: 100, flv: usd }, 0),
+            fund(&mut alice, ClearValue { qty: 50, flv: eur }, 1),
+        ];
+        let header = TxHeader {
+            version: 0,
+            mintime: 0,
+            maxtime: 1000,
+            maxcost: u64::max_value(),
+        };
+        let payments = [(10, usd), (20, eur), (30, usd), (5, eur), (40, usd)]
+            .iter()
+            .map(|(qty, flv)| {
+                bob.generate_receiver(
+                    ClearValue {
+                        qty: *qty,
+                        flv: *flv,
+                    },
+                    1000,
+                    &mut rng,
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let unsigned_tx = TxBuilder::new(header)
+            .spendable(utxos.clone())
+            .pay_all(payments.clone())
+            .build(&mut alice, &mut rng)
+            .unwrap();
+        assert_eq!(unsigned_tx.inputs.len(), 2);
+        assert_eq!(unsigned_tx.change.len(), 2);
+
+        // 5 payments and 2 change outputs are padded to 8 outputs of the cloak.
+        let bp_gens = BulletproofGens::new(1024, 1);
+        let (tx, _, _) = unsigned_tx.sign(&alice_xprv, &bp_gens).unwrap();
+        let verified_tx = Verifier::verify_tx(tx, &bp_gens).unwrap();
+        assert_eq!(bob.receive(&verified_tx).len(), 5);
+        assert_eq!(alice.receive(&verified_tx).len(), 2);
+
+        let too_many = (0..TxBuilder::MAX_PAYMENTS + 1).map(|_| payments[0].clone());
+        assert_eq!(
+            TxBuilder::new(header)
+                .spendable(utxos)
+                .pay_all(too_many)
+                .build(&mut alice, &mut rng)
+                .map(|_| ()),
+            Err(AccountError::TooManyPayments)
+        );
+    }
+}
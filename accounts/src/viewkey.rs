@@ -0,0 +1,189 @@
+//! View keys: detecting and decrypting the outputs addressed to a wallet
+//! without the ability to spend them.
+//!
+//! The payer commits to the value with blinding factors derived from a Diffie-Hellman secret
+//! shared with the holder of the view key, and places a _note_ with the encrypted quantity and flavor
+//! in the output's payload right after the value.
+
+use curve25519_dalek::constants::RISTRETTO_BASEPOINT_POINT;
+use curve25519_dalek::ristretto::{CompressedRistretto, RistrettoPoint};
+use curve25519_dalek::scalar::Scalar;
+use merlin::Transcript;
+use rand::{CryptoRng, RngCore};
+use zkvm::{Commitment, CommitmentWitness, Data, TranscriptProtocol, Value};
+
+use crate::receiver::ClearValue;
+
+/// Length of the note: ephemeral key, encrypted quantity and encrypted flavor.
+const NOTE_LEN: usize = 32 + 8 + 32;
+
+/// Secret key that detects and decrypts the outputs addressed to it.
+/// The view key does not allow spending the outputs.
+#[derive(Clone)]
+pub struct ViewKey(Scalar);
+
+/// Public view key given to the payers.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ViewPubkey(pub CompressedRistretto);
+
+/// Openings of the value commitments recovered with the view key.
+#[derive(Clone, Debug)]
+pub struct ValueWitness {
+    /// Opening of the quantity commitment.
+    pub qty: CommitmentWitness,
+    /// Opening of the flavor commitment.
+    pub flv: CommitmentWitness,
+}
+
+impl ViewKey {
+    /// Creates a view key from a secret scalar.
+    pub fn new(secret: Scalar) -> Self {
+        ViewKey(secret)
+    }
+
+    /// Creates a random view key.
+    pub fn random<R: RngCore + CryptoRng>(rng: &mut R) -> Self {
+        ViewKey(Scalar::random(rng))
+    }
+
+    /// Returns the public view key.
+    pub fn to_pubkey(&self) -> ViewPubkey {
+        ViewPubkey((self.0 * RISTRETTO_BASEPOINT_POINT).compress())
+    }
+
+    /// Decrypts the note and checks that the value commitments open to the decrypted quantity and flavor.
+    /// Returns `None` if the note is not addressed to this view key.
+    pub fn decrypt(&self, value: &Value, note: &[u8]) -> Option<ValueWitness> {
+        if note.len() != NOTE_LEN {
+            return None;
+        }
+        let ephemeral = CompressedRistretto::from_slice(&note[..32]);
+        let shared = self.0 * ephemeral.decompress()?;
+        let (witness, keystream) = NoteSecrets::derive(&ephemeral, &shared);
+
+        let mut plaintext = [0u8; 40];
+        for (i, b) in plaintext.iter_mut().enumerate() {
+            *b = note[32 + i] ^ keystream[i];
+        }
+        let mut qty = [0u8; 8];
+        qty.copy_from_slice(&plaintext[..8]);
+        let mut flv = [0u8; 32];
+        flv.copy_from_slice(&plaintext[8..]);
+        let value_witness = witness.open(ClearValue {
+            qty: u64::from_le_bytes(qty),
+            flv: Scalar::from_canonical_bytes(flv)?,
+        });
+
+        let openings = [
+            (
+                value.qty.clone(),
+                value_witness.qty.value(),
+                value_witness.qty.blinding(),
+            ),
+            (
+                value.flv.clone(),
+                value_witness.flv.value(),
+                value_witness.flv.blinding(),
+            ),
+        ];
+        if Commitment::verify_openings(&openings) {
+            Some(value_witness)
+        } else {
+            None
+        }
+    }
+}
+
+impl ViewPubkey {
+    /// Commits to the value for the holder of the view key.
+    /// Returns the value and the note to place in the payload right after it,
+    /// or `None` if the view key is not a valid point.
+    pub fn encrypt<R: RngCore + CryptoRng>(
+        &self,
+        value: ClearValue,
+        rng: &mut R,
+    ) -> Option<(Value, Data)> {
+        let r = Scalar::random(rng);
+        let ephemeral = (r * RISTRETTO_BASEPOINT_POINT).compress();
+        let shared = r * self.0.decompress()?;
+        let (witness, keystream) = NoteSecrets::derive(&ephemeral, &shared);
+
+        let mut note = Vec::with_capacity(NOTE_LEN);
+        note.extend_from_slice(ephemeral.as_bytes());
+        note.extend_from_slice(&value.qty.to_le_bytes());
+        note.extend_from_slice(value.flv.as_bytes());
+        for (b, k) in note[32..].iter_mut().zip(keystream.iter()) {
+            *b ^= k;
+        }
+
+        let value_witness = witness.open(value);
+        Some((
+            Value {
+                qty: value_witness.qty.into(),
+                flv: value_witness.flv.into(),
+            },
+            Data::Opaque(note),
+        ))
+    }
+}
+
+/// Secrets derived from the shared Diffie-Hellman key.
+struct NoteSecrets {
+    qty_blinding: Scalar,
+    flv_blinding: Scalar,
+}
+
+impl NoteSecrets {
+    fn derive(ephemeral: &CompressedRistretto, shared: &RistrettoPoint) -> (Self, [u8; 40]) {
+        let mut t = Transcript::new(b"ZkVM.viewkey");
+        t.commit_point(b"R", ephemeral);
+        t.commit_point(b"S", &shared.compress());
+        let secrets = NoteSecrets {
+            qty_blinding: t.challenge_scalar(b"qty_blinding"),
+            flv_blinding: t.challenge_scalar(b"flv_blinding"),
+        };
+        let mut keystream = [0u8; 40];
+        t.challenge_bytes(b"keystream", &mut keystream);
+        (secrets, keystream)
+    }
+
+    fn open(&self, value: ClearValue) -> ValueWitness {
+        ValueWitness {
+            qty: CommitmentWitness::new(value.qty, self.qty_blinding),
+            flv: CommitmentWitness::new(value.flv, self.flv_blinding),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zkvm::ScalarWitness;
+
+    #[test]
+    fn encrypt_and_decrypt() {
+        let mut rng = rand::thread_rng();
+        let view_key = ViewKey::random(&mut rng);
+        let value = ClearValue {
+            qty: 42,
+            flv: Scalar::from(7u64),
+        };
+        let (value_commitments, note) = view_key.to_pubkey().encrypt(value, &mut rng).unwrap();
+        let note = match note {
+            Data::Opaque(note) => note,
+            _ => unreachable!(),
+        };
+
+        let witness = view_key.decrypt(&value_commitments, &note).unwrap();
+        assert_eq!(witness.qty.value(), ScalarWitness::from(value.qty));
+        assert_eq!(witness.flv.value(), ScalarWitness::from(value.flv));
+
+        // Another view key cannot decrypt the note.
+        let other_key = ViewKey::random(&mut rng);
+        assert!(other_key.decrypt(&value_commitments, &note).is_none());
+
+        // The note must match the value commitments.
+        let (other_commitments, _) = view_key.to_pubkey().encrypt(value, &mut rng).unwrap();
+        assert!(view_key.decrypt(&other_commitments, &note).is_none());
+    }
+}
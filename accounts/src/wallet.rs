@@ -0,0 +1,213 @@
+//! Watch-only wallet that discovers its outputs with a view key.
+
+use blockchain::{Batch, BlockchainError, Storage, VerifiedBlock};
+use zkvm::{ContractID, Data, Entry, Output, PortableItem};
+
+use crate::viewkey::{ValueWitness, ViewKey};
+
+/// Watch-only wallet: tracks the outputs discovered with a view key
+/// and forgets them when they are spent.
+#[derive(Default)]
+pub struct Wallet {
+    utxos: Vec<ScannedUtxo>,
+}
+
+/// Output discovered with a view key, with the openings of its value commitments.
+#[derive(Clone, Debug)]
+pub struct ScannedUtxo {
+    /// The discovered output.
+    pub output: Output,
+    /// Openings of the value commitments, one per value addressed to the view key.
+    pub values: Vec<ValueWitness>,
+}
+
+impl Wallet {
+    /// Creates an empty wallet.
+    pub fn new() -> Self {
+        Wallet { utxos: Vec::new() }
+    }
+
+    /// Returns the unspent outputs discovered so far.
+    pub fn utxos(&self) -> &[ScannedUtxo] {
+        &self.utxos
+    }
+
+    /// Scans the block for the outputs addressed to the view key and returns them.
+    /// Discovered outputs are added to the wallet, and the outputs spent in the block are removed.
+    pub fn scan_block(&mut self, view_key: &ViewKey, block: &VerifiedBlock) -> Vec<ScannedUtxo> {
+        let mut discovered = Vec::new();
+        for entry in block.txs.iter().flat_map(|tx| tx.log.iter()) {
+            match entry {
+                Entry::Input(contract_id) => {
+                    self.utxos.retain(|utxo| utxo.contract_id() != *contract_id);
+                }
+                Entry::Output(output) => {
+                    if let Some(utxo) = ScannedUtxo::scan(view_key, output) {
+                        self.utxos.push(utxo.clone());
+                        discovered.push(utxo);
+                    }
+                }
+                _ => {}
+            }
+        }
+        discovered
+    }
+
+    /// Writes the unspent outputs to the storage, replacing the previously saved ones.
+    /// Only the outputs are stored: the openings of their values are recovered with the view key.
+    pub fn save<S: Storage>(&self, storage: &mut S) -> Result<(), BlockchainError> {
+        let mut batch = Batch::new();
+        for (key, _) in storage.scan_prefix(UTXO_PREFIX)? {
+            batch.delete(key);
+        }
+        for utxo in self.utxos.iter() {
+            let mut key = UTXO_PREFIX.to_vec();
+            key.extend_from_slice(utxo.contract_id().as_bytes());
+            let mut value = Vec::with_capacity(utxo.output.serialized_length());
+            utxo.output.encode(&mut value);
+            batch.put(key, value);
+        }
+        storage.write(batch)
+    }
+
+    /// Loads the unspent outputs saved to the storage
+    /// and recovers the openings of their values with the view key.
+    pub fn load<S: Storage>(storage: &S, view_key: &ViewKey) -> Result<Self, BlockchainError> {
+        let utxos = storage
+            .scan_prefix(UTXO_PREFIX)?
+            .into_iter()
+            .map(|(_, value)| {
+                Output::from_bytes(&value)
+                    .ok()
+                    .and_then(|output| ScannedUtxo::scan(view_key, &output))
+                    .ok_or(BlockchainError::FormatError)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Wallet { utxos })
+    }
+}
+
+const UTXO_PREFIX: &[u8] = b"wallet/utxo/";
+
+impl ScannedUtxo {
+    /// Returns the ID of the output.
+    pub fn contract_id(&self) -> ContractID {
+        self.output.id()
+    }
+
+    /// Decrypts the notes that follow the values in the output's payload.
+    /// Returns `None` if none of the values is addressed to the view key.
+    fn scan(view_key: &ViewKey, output: &Output) -> Option<Self> {
+        let values = output
+            .contract()
+            .payload
+            .windows(2)
+            .filter_map(|pair| match (&pair[0], &pair[1]) {
+                (PortableItem::Value(value), PortableItem::Data(Data::Opaque(note))) => {
+                    view_key.decrypt(value, note)
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        if values.is_empty() {
+            return None;
+        }
+        Some(ScannedUtxo {
+            output: output.clone(),
+            values,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::receiver::ClearValue;
+    use blockchain::{BlockHeader, MemoryStorage};
+    use curve25519_dalek::scalar::Scalar;
+    use zkvm::{
+        Anchor, Contract, FeeRate, Predicate, ScalarWitness, TxHeader, TxID, VerifiedTx, WTxID,
+    };
+
+    fn tx(log: Vec<Entry>) -> VerifiedTx {
+        VerifiedTx {
+            header: TxHeader {
+                version: 0,
+                mintime: 0,
+                maxtime: 0,
+                maxcost: 0,
+            },
+            id: TxID([0u8; 32]),
+            wtxid: WTxID([0u8; 32]),
+            log,
+            feerate: FeeRate::zero(),
+            proof_metrics: Default::default(),
+        }
+    }
+
+    fn make_block(height: u64, txs: Vec<VerifiedTx>) -> VerifiedBlock {
+        let mut header = BlockHeader::make_initial(height, [0u8; 32]);
+        header.height = height;
+        VerifiedBlock { header, txs }
+    }
+
+    #[test]
+    fn scan_and_spend() {
+        let mut rng = rand::thread_rng();
+        let view_key = ViewKey::random(&mut rng);
+        let value = ClearValue {
+            qty: 10,
+            flv: Scalar::zero(),
+        };
+        let predicate = Predicate::Opaque(Default::default());
+        let anchor = Anchor::nonce([0u8; 32], &predicate, 0);
+
+        let (ours, note) = view_key.to_pubkey().encrypt(value, &mut rng).unwrap();
+        let (theirs, other_note) = ViewKey::random(&mut rng)
+            .to_pubkey()
+            .encrypt(value, &mut rng)
+            .unwrap();
+        let output = Output::new(Contract {
+            anchor,
+            payload: vec![PortableItem::Value(ours), PortableItem::Data(note)],
+            predicate: predicate.clone(),
+        });
+        let other_output = Output::new(Contract {
+            anchor: anchor.ratchet(),
+            payload: vec![PortableItem::Value(theirs), PortableItem::Data(other_note)],
+            predicate,
+        });
+
+        let mut wallet = Wallet::new();
+        let block = make_block(
+            1,
+            vec![tx(vec![
+                Entry::Output(output.clone()),
+                Entry::Output(other_output),
+            ])],
+        );
+        let discovered = wallet.scan_block(&view_key, &block);
+        assert_eq!(discovered.len(), 1);
+        assert_eq!(discovered[0].contract_id(), output.id());
+        assert_eq!(
+            discovered[0].values[0].qty.value(),
+            ScalarWitness::from(value.qty)
+        );
+        assert_eq!(wallet.utxos().len(), 1);
+
+        // The saved wallet is restored with the view key.
+        let mut storage = MemoryStorage::default();
+        wallet.save(&mut storage).unwrap();
+        let restored = Wallet::load(&storage, &view_key).unwrap();
+        assert_eq!(restored.utxos().len(), 1);
+        assert_eq!(restored.utxos()[0].contract_id(), output.id());
+        assert!(Wallet::load(&storage, &ViewKey::random(&mut rng)).is_err());
+
+        let block = make_block(2, vec![tx(vec![Entry::Input(output.id())])]);
+        assert!(wallet.scan_block(&view_key, &block).is_empty());
+        assert!(wallet.utxos().is_empty());
+
+        wallet.save(&mut storage).unwrap();
+        assert!(storage.scan_prefix(UTXO_PREFIX).unwrap().is_empty());
+    }
+}
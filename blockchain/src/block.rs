@@ -0,0 +1,799 @@
+//! Blocks of transactions and block headers.
+
+use bulletproofs::BulletproofGens;
+use merlin::Transcript;
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+use std::io;
+use zkvm::{DeferredTx, Reader, Tx, TxTree, VMError, VerifiedTx, Verifier, Writer};
+
+use crate::cache::VerificationCache;
+use crate::errors::BlockchainError;
+use crate::params::ChainParams;
+
+/// Identifier of a block: a hash of its header.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct BlockID(pub [u8; 32]);
+
+/// Header of a block that commits to its transactions and to the resulting state.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BlockHeader {
+    /// Version of the block, never decreases along the chain.
+    pub version: u64,
+
+    /// Height of the block in the chain. Initial block has height 1.
+    pub height: u64,
+
+    /// ID of the preceding block. Initial block has an all-zero ID here.
+    pub prev_id: BlockID,
+
+    /// Timestamp of the block in milliseconds since the Unix epoch.
+    /// Each block has a timestamp strictly later than the block before it.
+    pub timestamp_ms: u64,
+
+    /// Merkle root of the IDs of the transactions in the block.
+    pub txroot: [u8; 32],
+
+    /// Root of the utreexo forest of unspent outputs after applying the block.
+    pub utxoroot: [u8; 32],
+
+    /// Extension data, empty in version 1.
+    pub ext: Vec<u8>,
+}
+
+/// Block of transactions received from the network.
+#[derive(Clone)]
+pub struct Block {
+    /// Header of the block.
+    pub header: BlockHeader,
+
+    /// Transactions in the order of their inclusion in the block.
+    pub txs: Vec<Tx>,
+}
+
+/// Block of transactions verified by the node.
+pub struct VerifiedBlock {
+    /// Header of the block.
+    pub header: BlockHeader,
+
+    /// Verified transactions in the order of their inclusion in the block.
+    pub txs: Vec<VerifiedTx>,
+}
+
+impl BlockHeader {
+    /// Creates the header of the initial block with no transactions.
+    pub fn make_initial(timestamp_ms: u64, utxoroot: [u8; 32]) -> Self {
+        BlockHeader {
+            version: 1,
+            height: 1,
+            prev_id: BlockID([0u8; 32]),
+            timestamp_ms,
+            txroot: TxTree::build(&[]).root(),
+            utxoroot,
+            ext: Vec::new(),
+        }
+    }
+
+    /// Computes the ID of the block.
+    pub fn id(&self) -> BlockID {
+        let mut t = Transcript::new(b"ZkVM.blockheader");
+        t.commit_u64(b"version", self.version);
+        t.commit_u64(b"height", self.height);
+        t.commit_bytes(b"previd", &self.prev_id.0);
+        t.commit_u64(b"timestamp_ms", self.timestamp_ms);
+        t.commit_bytes(b"txroot", &self.txroot);
+        t.commit_bytes(b"utxoroot", &self.utxoroot);
+        t.commit_bytes(b"ext", &self.ext);
+        let mut id = BlockID([0u8; 32]);
+        t.challenge_bytes(b"id", &mut id.0);
+        id
+    }
+
+    /// Serializes the header into a byte array:
+    /// LE64 version, LE64 height, 32-byte previous block ID, LE64 timestamp,
+    /// 32-byte txroot, 32-byte utxoroot and LE32-prefixed extension data.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut w = Writer::new(Vec::with_capacity(self.serialized_size()));
+        self.write(&mut w).expect("writing to a vector never fails");
+        w.into_inner()
+    }
+
+    /// Returns the size of the serialized header in bytes.
+    pub fn serialized_size(&self) -> usize {
+        8 * 3 + 32 * 3 + 4 + self.ext.len()
+    }
+
+    /// Decodes the header from a byte array.
+    pub fn from_bytes(data: &[u8]) -> Result<Self, BlockchainError> {
+        let mut r = Reader::new(data);
+        let header = Self::read(&mut r).map_err(|_| BlockchainError::FormatError)?;
+        if !r.into_inner().is_empty() {
+            return Err(BlockchainError::FormatError);
+        }
+        Ok(header)
+    }
+
+    pub(crate) fn write<W: io::Write>(&self, w: &mut Writer<W>) -> io::Result<()> {
+        w.write_u64(self.version)?;
+        w.write_u64(self.height)?;
+        w.write_bytes(&self.prev_id.0)?;
+        w.write_u64(self.timestamp_ms)?;
+        w.write_bytes(&self.txroot)?;
+        w.write_bytes(&self.utxoroot)?;
+        w.write_size(self.ext.len())?;
+        w.write_bytes(&self.ext)
+    }
+
+    pub(crate) fn read<R: io::Read>(r: &mut Reader<R>) -> Result<Self, VMError> {
+        Ok(BlockHeader {
+            version: r.read_u64()?,
+            height: r.read_u64()?,
+            prev_id: BlockID(r.read_u8x32()?),
+            timestamp_ms: r.read_u64()?,
+            txroot: r.read_u8x32()?,
+            utxoroot: r.read_u8x32()?,
+            ext: {
+                let n = r.read_size()?;
+                r.read_bytes(n)?
+            },
+        })
+    }
+
+    /// Checks that the header follows the header of the previous block
+    /// with the default consensus parameters (see `validate_with_params`).
+    pub fn validate(&self, prev: &BlockHeader) -> Result<(), BlockchainError> {
+        self.validate_with_params(prev, &ChainParams::default())
+    }
+
+    /// Checks that the header follows the header of the previous block
+    /// and its version is allowed by the version rules of the network.
+    pub fn validate_with_params(
+        &self,
+        prev: &BlockHeader,
+        params: &ChainParams,
+    ) -> Result<(), BlockchainError> {
+        let rules = &params.version_rules;
+        if self.version < prev.version {
+            return Err(BlockchainError::VersionReversion);
+        }
+        if self.version < rules.min_block_version {
+            return Err(BlockchainError::BadBlockVersion);
+        }
+        if self.version < rules.state_hash_version && !self.ext.is_empty() {
+            return Err(BlockchainError::IllegalExtension);
+        }
+        if self.height != prev.height + 1 {
+            return Err(BlockchainError::BadHeight);
+        }
+        if self.prev_id != prev.id() {
+            return Err(BlockchainError::MismatchedPrevID);
+        }
+        if self.timestamp_ms <= prev.timestamp_ms {
+            return Err(BlockchainError::BadBlockTimestamp);
+        }
+        Ok(())
+    }
+}
+
+impl Block {
+    /// Serializes the block into a byte array: the header followed by
+    /// LE32 number of transactions and LE32-prefixed serialized transactions.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut w = Writer::new(Vec::new());
+        self.write(&mut w).expect("writing to a vector never fails");
+        w.into_inner()
+    }
+
+    /// Returns the size of the serialized block in bytes.
+    pub fn serialized_size(&self) -> usize {
+        self.txs
+            .iter()
+            .fold(self.header.serialized_size() + 4, |size, tx| {
+                size + 4 + tx.serialized_size()
+            })
+    }
+
+    /// Decodes the block from a byte array.
+    pub fn from_bytes(data: &[u8]) -> Result<Self, BlockchainError> {
+        let mut r = Reader::new(data);
+        let block = Self::read(&mut r).map_err(|_| BlockchainError::FormatError)?;
+        if !r.into_inner().is_empty() {
+            return Err(BlockchainError::FormatError);
+        }
+        Ok(block)
+    }
+
+    fn write<W: io::Write>(&self, w: &mut Writer<W>) -> io::Result<()> {
+        self.header.write(w)?;
+        w.write_size(self.txs.len())?;
+        for tx in self.txs.iter() {
+            let bytes = tx.to_bytes();
+            w.write_size(bytes.len())?;
+            w.write_bytes(&bytes)?;
+        }
+        Ok(())
+    }
+
+    fn read<R: io::Read>(r: &mut Reader<R>) -> Result<Self, VMError> {
+        let header = BlockHeader::read(r)?;
+        let mut txs = Vec::new();
+        for _ in 0..r.read_size()? {
+            let len = r.read_size()?;
+            txs.push(Tx::from_bytes(&r.read_bytes(len)?)?);
+        }
+        Ok(Block { header, txs })
+    }
+
+    /// Verifies the block against the header of the previous block
+    /// with the default consensus parameters (see `verify_with_params`).
+    pub fn verify(
+        self,
+        prev: &BlockHeader,
+        bp_gens: &BulletproofGens,
+    ) -> Result<VerifiedBlock, BlockchainError> {
+        self.verify_with_params(prev, &ChainParams::default(), bp_gens)
+    }
+
+    /// Verifies the block against the header of the previous block:
+    /// checks the header, the sizes of the block and the transactions,
+    /// the time bounds and versions of the transactions against the consensus parameters,
+    /// verifies the transactions and checks that they match the header's `txroot`.
+    /// The signatures and the other point operations of all the transactions
+    /// are verified in one batch.
+    ///
+    /// The `utxoroot` is checked when the block is applied to the blockchain state.
+    pub fn verify_with_params(
+        self,
+        prev: &BlockHeader,
+        params: &ChainParams,
+        bp_gens: &BulletproofGens,
+    ) -> Result<VerifiedBlock, BlockchainError> {
+        self.check_txs(prev, params)?;
+        let vm_params = params.vm_params();
+        let txs = self
+            .txs
+            .into_iter()
+            .map(|tx| Verifier::verify_tx_with_params(tx, &vm_params, bp_gens, |_| false))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(BlockchainError::TxValidation)?;
+        Self::verify_batch(self.header, txs)
+    }
+
+    /// Verifies the block like `verify`, but skips the R1CS proofs and the signatures
+    /// of the transactions whose witness IDs are in the cache,
+    /// e.g. the transactions verified when they entered the mempool.
+    pub fn verify_with_cache(
+        self,
+        prev: &BlockHeader,
+        bp_gens: &BulletproofGens,
+        cache: &VerificationCache,
+    ) -> Result<VerifiedBlock, BlockchainError> {
+        self.verify_with_params_and_cache(prev, &ChainParams::default(), bp_gens, cache)
+    }
+
+    /// Verifies the block like `verify_with_params`, skipping the R1CS proofs and the signatures
+    /// of the transactions whose witness IDs are in the cache (see `verify_with_cache`).
+    pub fn verify_with_params_and_cache(
+        self,
+        prev: &BlockHeader,
+        params: &ChainParams,
+        bp_gens: &BulletproofGens,
+        cache: &VerificationCache,
+    ) -> Result<VerifiedBlock, BlockchainError> {
+        self.check_txs(prev, params)?;
+        let vm_params = params.vm_params();
+        let txs = self
+            .txs
+            .into_iter()
+            .map(|tx| {
+                Verifier::verify_tx_with_params(tx, &vm_params, bp_gens, |wtxid| {
+                    cache.contains(wtxid)
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(BlockchainError::TxValidation)?;
+        Self::verify_batch(self.header, txs)
+    }
+
+    /// Verifies the block like `verify`, running the VMs and checking the R1CS proofs
+    /// of the transactions on a pool of `num_threads` threads (the number of CPUs if zero).
+    /// The point operations of all the transactions are verified in a single
+    /// multi-scalar multiplication, which is split across the threads of the pool
+    /// if `zkvm/parallel` is enabled.
+    #[cfg(feature = "parallel")]
+    pub fn verify_parallel(
+        self,
+        prev: &BlockHeader,
+        bp_gens: &BulletproofGens,
+        num_threads: usize,
+    ) -> Result<VerifiedBlock, BlockchainError> {
+        self.verify_parallel_with_params(prev, &ChainParams::default(), bp_gens, num_threads)
+    }
+
+    /// Verifies the block like `verify_with_params` on a pool of `num_threads` threads
+    /// (see `verify_parallel`).
+    #[cfg(feature = "parallel")]
+    pub fn verify_parallel_with_params(
+        self,
+        prev: &BlockHeader,
+        params: &ChainParams,
+        bp_gens: &BulletproofGens,
+        num_threads: usize,
+    ) -> Result<VerifiedBlock, BlockchainError> {
+        self.check_txs(prev, params)?;
+        let vm_params = params.vm_params();
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(num_threads)
+            .build()
+            .map_err(|e| BlockchainError::ThreadPoolError(e.to_string()))?;
+        let header = self.header;
+        let txs = self.txs;
+        pool.install(|| {
+            let txs = txs
+                .into_par_iter()
+                .map(|tx| Verifier::verify_tx_with_params(tx, &vm_params, bp_gens, |_| false))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(BlockchainError::TxValidation)?;
+            Self::verify_batch(header, txs)
+        })
+    }
+
+    /// Checks the header, the sizes of the block and the transactions,
+    /// the time bounds and versions of the transactions.
+    fn check_txs(&self, prev: &BlockHeader, params: &ChainParams) -> Result<(), BlockchainError> {
+        self.header.validate_with_params(prev, params)?;
+        if self.serialized_size() > params.max_block_size {
+            return Err(BlockchainError::BlockTooLarge);
+        }
+
+        let rules = &params.version_rules;
+        for tx in self.txs.iter() {
+            if tx.serialized_size() > params.max_tx_size {
+                return Err(BlockchainError::TxTooLarge);
+            }
+            if tx.header.mintime > self.header.timestamp_ms
+                || tx.header.maxtime < self.header.timestamp_ms
+            {
+                return Err(BlockchainError::BadTxTimestamp);
+            }
+            if self.header.version < rules.state_hash_version
+                && tx.header.version != rules.tx_version
+            {
+                return Err(BlockchainError::BadTxVersion);
+            }
+        }
+        Ok(())
+    }
+
+    /// Verifies the deferred point operations of the transactions in one batch
+    /// and checks the transactions against the header's `txroot`.
+    fn verify_batch(
+        header: BlockHeader,
+        txs: Vec<DeferredTx>,
+    ) -> Result<VerifiedBlock, BlockchainError> {
+        let txs = DeferredTx::verify_batch(txs).map_err(BlockchainError::TxValidation)?;
+
+        let txids = txs.iter().map(|tx| tx.id).collect::<Vec<_>>();
+        if TxTree::build(&txids).root() != header.txroot {
+            return Err(BlockchainError::InvalidTxRoot);
+        }
+
+        Ok(VerifiedBlock { header, txs })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use curve25519_dalek::scalar::Scalar;
+    use zkvm::{
+        Data, Predicate, Program, Prover, Signature, TxHeader, TxIntent, Value, VerificationKey,
+    };
+
+    fn next(prev: &BlockHeader) -> BlockHeader {
+        BlockHeader {
+            version: prev.version,
+            height: prev.height + 1,
+            prev_id: prev.id(),
+            timestamp_ms: prev.timestamp_ms + 1,
+            txroot: TxTree::build(&[]).root(),
+            utxoroot: prev.utxoroot,
+            ext: Vec::new(),
+        }
+    }
+
+    /// Makes a transaction that only creates a nonce.
+    /// Different `maxtime` values produce different transactions.
+    fn nonce_tx(maxtime: u64, bp_gens: &BulletproofGens) -> Tx {
+        let key = Scalar::from(1u64);
+        let program = Program::build(|p| {
+            p.push(Predicate::Key(VerificationKey::from_secret(&key)))
+                .push(Data::Opaque(vec![0u8; 32]))
+                .nonce()
+                .sign_tx()
+        });
+        let header = TxHeader {
+            version: 1,
+            mintime: 0,
+            maxtime,
+            maxcost: u64::max_value(),
+        };
+        let (tx, _, _) = Prover::build_tx(program, header, bp_gens, |t, _| {
+            Signature::sign_aggregated(t, &[key])
+        })
+        .unwrap();
+        tx
+    }
+
+    /// Makes a transaction that issues a value, so its proof has the range proof constraints.
+    fn issue_tx(qty: u64, bp_gens: &BulletproofGens) -> Tx {
+        let key = Scalar::from(1u64);
+        let predicate = Predicate::Key(VerificationKey::from_secret(&key));
+        let header = TxHeader {
+            version: 1,
+            mintime: 0,
+            maxtime: 2000,
+            maxcost: u64::max_value(),
+        };
+        let flavor = Value::issue_flavor(&predicate, Data::Opaque(Vec::new()));
+        let program = TxIntent::new(header, [qty as u8; 32])
+            .nonce(predicate.clone(), [0u8; 32])
+            .issue(qty, predicate.clone(), Vec::new())
+            .output(qty, flavor, predicate)
+            .compile()
+            .unwrap();
+        let (tx, _, _) = Prover::build_tx(program, header, bp_gens, |t, vks| {
+            Signature::sign_aggregated(t, &vec![key; vks.len()])
+        })
+        .unwrap();
+        tx
+    }
+
+    /// Makes the block with the transactions following the previous block.
+    fn block_with_txs(prev: &BlockHeader, txs: Vec<Tx>, bp_gens: &BulletproofGens) -> Block {
+        let txids = txs
+            .iter()
+            .map(|tx| Verifier::verify_tx(tx.clone(), bp_gens).unwrap().id)
+            .collect::<Vec<_>>();
+        let mut header = next(prev);
+        header.txroot = TxTree::build(&txids).root();
+        Block { header, txs }
+    }
+
+    /// Flips a bit of the signature of the transaction, keeping its ID.
+    fn tamper_signature(tx: &mut Tx) {
+        let mut bytes = tx.signature.to_bytes();
+        bytes[32] ^= 1;
+        tx.signature = Signature::from_bytes(bytes).unwrap();
+    }
+
+    #[test]
+    fn header_id() {
+        let initial = BlockHeader::make_initial(1000, [0u8; 32]);
+        assert_eq!(initial.id(), initial.clone().id());
+
+        let mut other = initial.clone();
+        other.utxoroot = [1u8; 32];
+        assert_ne!(initial.id(), other.id());
+
+        let mut other = initial.clone();
+        other.ext = vec![0];
+        assert_ne!(initial.id(), other.id());
+    }
+
+    #[test]
+    fn header_encoding() {
+        let mut header = next(&BlockHeader::make_initial(1000, [7u8; 32]));
+        header.version = 2;
+        header.ext = vec![1, 2, 3];
+        let bytes = header.to_bytes();
+        assert_eq!(bytes.len(), 8 * 3 + 32 * 3 + 4 + 3);
+        assert_eq!(BlockHeader::from_bytes(&bytes), Ok(header));
+
+        assert_eq!(
+            BlockHeader::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(BlockchainError::FormatError)
+        );
+        let mut longer = bytes.clone();
+        longer.push(0);
+        assert_eq!(
+            BlockHeader::from_bytes(&longer),
+            Err(BlockchainError::FormatError)
+        );
+    }
+
+    #[test]
+    fn header_validation() {
+        let initial = BlockHeader::make_initial(1000, [0u8; 32]);
+        let header = next(&initial);
+        assert_eq!(header.validate(&initial), Ok(()));
+
+        let mut h = header.clone();
+        h.version = 0;
+        assert_eq!(h.validate(&initial), Err(BlockchainError::VersionReversion));
+
+        let mut h = header.clone();
+        h.ext = vec![1];
+        assert_eq!(h.validate(&initial), Err(BlockchainError::IllegalExtension));
+        h.version = 2;
+        assert_eq!(h.validate(&initial), Ok(()));
+
+        let mut h = header.clone();
+        h.height = 3;
+        assert_eq!(h.validate(&initial), Err(BlockchainError::BadHeight));
+
+        let mut h = header.clone();
+        h.prev_id = BlockID([0u8; 32]);
+        assert_eq!(h.validate(&initial), Err(BlockchainError::MismatchedPrevID));
+
+        let mut h = header.clone();
+        h.timestamp_ms = initial.timestamp_ms;
+        assert_eq!(
+            h.validate(&initial),
+            Err(BlockchainError::BadBlockTimestamp)
+        );
+
+        // Headers chain only in order.
+        let third = next(&header);
+        assert_eq!(third.validate(&header), Ok(()));
+        assert_eq!(third.validate(&initial), Err(BlockchainError::BadHeight));
+
+        // Headers of the test vectors form a valid chain.
+        let vectors = crate::testutil::block_headers();
+        assert_eq!(vectors[1].validate(&vectors[0]), Ok(()));
+    }
+
+    #[test]
+    fn header_validation_with_params() {
+        let initial = BlockHeader::make_initial(1000, [0u8; 32]);
+        let mut header = next(&initial);
+        header.version = 2;
+        header.ext = vec![1];
+        assert_eq!(
+            header.validate_with_params(&initial, &ChainParams::default()),
+            Ok(())
+        );
+
+        // Network that allows the extensions only from version 3.
+        let mut params = ChainParams::default();
+        params.version_rules.state_hash_version = 3;
+        assert_eq!(
+            header.validate_with_params(&initial, &params),
+            Err(BlockchainError::IllegalExtension)
+        );
+
+        // Network that starts with the blocks of version 3.
+        let mut params = ChainParams::default();
+        params.version_rules.min_block_version = 3;
+        assert_eq!(
+            header.validate_with_params(&initial, &params),
+            Err(BlockchainError::BadBlockVersion)
+        );
+    }
+
+    #[test]
+    fn verify_empty_block() {
+        let bp_gens = BulletproofGens::new(256, 1);
+        let initial = BlockHeader::make_initial(1000, [0u8; 32]);
+        let header = next(&initial);
+
+        let block = Block {
+            header: header.clone(),
+            txs: Vec::new(),
+        };
+        let verified = block.verify(&initial, &bp_gens).unwrap();
+        assert_eq!(verified.header, header);
+        assert!(verified.txs.is_empty());
+
+        let mut bad_header = header.clone();
+        bad_header.txroot = [0u8; 32];
+        let block = Block {
+            header: bad_header,
+            txs: Vec::new(),
+        };
+        assert_eq!(
+            block.verify(&initial, &bp_gens).map(|_| ()),
+            Err(BlockchainError::InvalidTxRoot)
+        );
+    }
+
+    #[test]
+    fn verify_with_params() {
+        let bp_gens = BulletproofGens::new(256, 1);
+        let initial = BlockHeader::make_initial(1000, [0u8; 32]);
+        let block = Block {
+            header: next(&initial),
+            txs: Vec::new(),
+        };
+        let size = block.serialized_size();
+        assert_eq!(size, block.to_bytes().len());
+
+        let mut params = ChainParams::default();
+        params.max_block_size = size;
+        assert!(block
+            .clone()
+            .verify_with_params(&initial, &params, &bp_gens)
+            .is_ok());
+
+        params.max_block_size = size - 1;
+        assert_eq!(
+            block
+                .clone()
+                .verify_with_params(&initial, &params, &bp_gens)
+                .map(|_| ()),
+            Err(BlockchainError::BlockTooLarge)
+        );
+
+        // Network that starts with the blocks of version 2.
+        let mut params = ChainParams::default();
+        params.version_rules.min_block_version = 2;
+        assert_eq!(
+            block
+                .verify_with_params(&initial, &params, &bp_gens)
+                .map(|_| ()),
+            Err(BlockchainError::BadBlockVersion)
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "parallel")]
+    fn verify_parallel_empty_block() {
+        let bp_gens = BulletproofGens::new(256, 1);
+        let initial = BlockHeader::make_initial(1000, [0u8; 32]);
+        let header = next(&initial);
+
+        let block = Block {
+            header: header.clone(),
+            txs: Vec::new(),
+        };
+        let verified = block.verify_parallel(&initial, &bp_gens, 2).unwrap();
+        assert_eq!(verified.header, header);
+
+        let block = Block {
+            header: next(&header),
+            txs: Vec::new(),
+        };
+        assert_eq!(
+            block.verify_parallel(&initial, &bp_gens, 2).map(|_| ()),
+            Err(BlockchainError::BadHeight)
+        );
+    }
+
+    #[test]
+    fn verify_signed_txs() {
+        let bp_gens = BulletproofGens::new(256, 1);
+        let initial = BlockHeader::make_initial(1000, [0u8; 32]);
+        let txs = (0..2)
+            .map(|i| nonce_tx(2000 + i, &bp_gens))
+            .collect::<Vec<_>>();
+        let block = block_with_txs(&initial, txs, &bp_gens);
+        let verified = block.clone().verify(&initial, &bp_gens).unwrap();
+        assert_eq!(verified.txs.len(), 2);
+
+        // Signatures are verified in a batch with the other point operations.
+        let mut tampered = block;
+        tamper_signature(&mut tampered.txs[1]);
+        assert!(tampered.verify(&initial, &bp_gens).is_err());
+    }
+
+    #[test]
+    fn verify_with_cache() {
+        let bp_gens = BulletproofGens::new(256, 1);
+        // Too few generators to verify the proof of the issuance.
+        let small_gens = BulletproofGens::new(1, 1);
+        let initial = BlockHeader::make_initial(1000, [0u8; 32]);
+        let tx = issue_tx(10, &bp_gens);
+        let block = block_with_txs(&initial, vec![tx.clone()], &bp_gens);
+
+        let mut cache = VerificationCache::new(10);
+        assert!(block
+            .clone()
+            .verify_with_cache(&initial, &small_gens, &cache)
+            .is_err());
+
+        // The transaction admitted to the mempool skips the proof check.
+        let verified_tx = Verifier::verify_tx(tx, &bp_gens).unwrap();
+        cache.insert(&verified_tx);
+        let verified = block
+            .clone()
+            .verify_with_cache(&initial, &small_gens, &cache)
+            .unwrap();
+        assert_eq!(verified.txs[0].wtxid, verified_tx.wtxid);
+
+        // The tampered transaction has the same ID, but a different witness ID,
+        // so it is verified in full even with the original in the cache.
+        let mut tampered = block;
+        tamper_signature(&mut tampered.txs[0]);
+        assert!(tampered
+            .clone()
+            .verify_with_cache(&initial, &small_gens, &cache)
+            .is_err());
+        assert!(tampered
+            .verify_with_cache(&initial, &bp_gens, &cache)
+            .is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "parallel")]
+    fn verify_parallel_matches_serial() {
+        let bp_gens = BulletproofGens::new(256, 1);
+        let params = ChainParams::default();
+        let initial = BlockHeader::make_initial(1000, [0u8; 32]);
+        let txs = (0..4)
+            .map(|i| nonce_tx(2000 + i, &bp_gens))
+            .collect::<Vec<_>>();
+        let block = block_with_txs(&initial, txs, &bp_gens);
+        let ids = |block: VerifiedBlock| {
+            block
+                .txs
+                .iter()
+                .map(|tx| (tx.id, tx.wtxid))
+                .collect::<Vec<_>>()
+        };
+
+        let serial = block
+            .clone()
+            .verify_with_params(&initial, &params, &bp_gens)
+            .unwrap();
+        let parallel = block
+            .clone()
+            .verify_parallel_with_params(&initial, &params, &bp_gens, 2)
+            .unwrap();
+        assert_eq!(serial.header, parallel.header);
+        assert_eq!(ids(serial), ids(parallel));
+
+        // Both reject the block with an invalid signature.
+        let mut tampered = block.clone();
+        tamper_signature(&mut tampered.txs[2]);
+        let serial = tampered
+            .clone()
+            .verify_with_params(&initial, &params, &bp_gens)
+            .map(|_| ());
+        assert!(serial.is_err());
+        assert_eq!(
+            tampered
+                .verify_parallel_with_params(&initial, &params, &bp_gens, 2)
+                .map(|_| ()),
+            serial
+        );
+
+        // Both reject the block whose transactions do not match the `txroot`.
+        let mut reordered = block;
+        reordered.txs.swap(0, 1);
+        assert_eq!(
+            reordered
+                .clone()
+                .verify_with_params(&initial, &params, &bp_gens)
+                .map(|_| ()),
+            Err(BlockchainError::InvalidTxRoot)
+        );
+        assert_eq!(
+            reordered
+                .verify_parallel_with_params(&initial, &params, &bp_gens, 2)
+                .map(|_| ()),
+            Err(BlockchainError::InvalidTxRoot)
+        );
+    }
+
+    #[test]
+    fn block_encoding() {
+        let block = Block {
+            header: next(&BlockHeader::make_initial(1000, [0u8; 32])),
+            txs: Vec::new(),
+        };
+        let bytes = block.to_bytes();
+        assert_eq!(bytes.len(), block.header.to_bytes().len() + 4);
+        let decoded = Block::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded.header, block.header);
+        assert!(decoded.txs.is_empty());
+
+        // Number of transactions exceeds the data.
+        let mut bad = bytes.clone();
+        let n = bad.len();
+        bad[n - 4] = 1;
+        assert_eq!(
+            Block::from_bytes(&bad).map(|_| ()),
+            Err(BlockchainError::FormatError)
+        );
+    }
+}
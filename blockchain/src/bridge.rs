@@ -0,0 +1,595 @@
+//! Bridge: the full utreexo forest and up-to-date proofs of the unspent outputs.
+//!
+//! `BlockchainState` keeps only the roots of the utreexo trees, so the proofs of the spent
+//! outputs have to come with the blocks. A bridge node keeps the full trees of the forest,
+//! provides the proofs for the blocks it applies and assembles, and sends the updated proofs
+//! of the watched outputs to the stateless wallets after each block.
+
+use std::borrow::Borrow;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io;
+use std::mem;
+use std::sync::Arc;
+use zkvm::{ContractID, Entry, Reader, VMError, VerifiedTx, Writer};
+
+use crate::block::VerifiedBlock;
+use crate::errors::BlockchainError;
+use crate::spv::UtxoProof;
+use crate::state::BlockchainState;
+use crate::utreexo::{self, Forest, Path, Proof, Tree};
+
+/// Full utreexo forest of the unspent outputs at the tip.
+#[derive(Clone)]
+pub struct Bridge {
+    height: u64,
+    // full trees of the forest indexed by their heights
+    trees: Vec<Option<Arc<Node>>>,
+    // leaf hash -> position of the leaf within its tree
+    positions: HashMap<[u8; 32], u64>,
+    // outputs whose proof updates are sent to the wallets
+    watched: HashSet<ContractID>,
+    max_rollback: usize,
+    // undo data of the recent blocks, the oldest first
+    undo: VecDeque<BridgeUndo>,
+}
+
+/// Changes to the watched outputs made by a block.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BridgeUpdate {
+    /// Height of the block.
+    pub height: u64,
+    /// Updated proofs of the watched outputs that remain unspent.
+    pub proofs: Vec<(ContractID, UtxoProof)>,
+    /// Watched outputs spent by the block. They are no longer watched.
+    pub spent: Vec<ContractID>,
+}
+
+#[derive(Debug)]
+struct Node {
+    hash: [u8; 32],
+    children: Option<(Arc<Node>, Arc<Node>)>,
+}
+
+/// Changes made by a block, used to roll it back.
+#[derive(Clone)]
+struct BridgeUndo {
+    // trees before the block, sharing the unchanged subtrees with the current ones
+    trees: Vec<Option<Arc<Node>>>,
+    // leaf hashes and their previous positions, in the order of the changes
+    positions: Vec<([u8; 32], Option<u64>)>,
+}
+
+impl Bridge {
+    /// Creates a bridge for the state with no unspent outputs, e.g. the initial state of a network,
+    /// keeping the undo data for as many blocks as the state.
+    /// Bridges of the later states are restored from their checkpoints.
+    pub fn new(state: &BlockchainState) -> Result<Self, BlockchainError> {
+        if state.tip().utxoroot != Forest::new().root() {
+            return Err(BlockchainError::MismatchedBridge);
+        }
+        Ok(Bridge {
+            height: state.tip().height,
+            trees: Vec::new(),
+            positions: HashMap::new(),
+            watched: HashSet::new(),
+            max_rollback: state.max_rollback(),
+            undo: VecDeque::new(),
+        })
+    }
+
+    /// Returns the height of the last applied block.
+    pub fn height(&self) -> u64 {
+        self.height
+    }
+
+    /// Returns the number of the unspent outputs.
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    /// Returns true if there are no unspent outputs.
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    /// Returns the root hashes of the trees, from the largest tree to the smallest one.
+    pub fn roots(&self) -> Vec<[u8; 32]> {
+        self.trees
+            .iter()
+            .rev()
+            .filter_map(|tree| tree.as_ref().map(|tree| tree.hash))
+            .collect()
+    }
+
+    /// Returns a hash committing to the roots of all the trees,
+    /// equal to the `utxoroot` of the last applied block.
+    pub fn root(&self) -> [u8; 32] {
+        utreexo::roots_hash(&self.roots())
+    }
+
+    /// Returns the proof that the output is unspent at the tip, to be provided to the light clients.
+    /// Returns `None` if the output is not in the utxo set.
+    pub fn utxo_proof(&self, contract_id: &ContractID) -> Option<UtxoProof> {
+        self.path(&utreexo::leaf_hash(contract_id))
+            .map(|path| UtxoProof {
+                roots: self.roots(),
+                path,
+            })
+    }
+
+    /// Returns the utreexo proofs of the outputs spent by the transactions, in the order of their inputs,
+    /// to apply the transactions to the state at the same tip (see `BlockchainState::apply_verified_block`).
+    /// Outputs that are not in the utxo set, e.g. the ones created by the preceding transactions,
+    /// get transient proofs.
+    pub fn utreexo_proofs<T: Borrow<VerifiedTx>>(&self, txs: &[T]) -> Vec<Proof> {
+        txs.iter()
+            .flat_map(|tx| tx.borrow().log.iter())
+            .filter_map(|entry| match entry {
+                Entry::Input(contract_id) => Some(
+                    self.path(&utreexo::leaf_hash(contract_id))
+                        .map(Proof::Committed)
+                        .unwrap_or(Proof::Transient),
+                ),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Starts sending the proof updates of the output and returns its current proof.
+    /// Returns `None` if the output is not in the utxo set.
+    pub fn watch(&mut self, contract_id: ContractID) -> Option<UtxoProof> {
+        let proof = self.utxo_proof(&contract_id)?;
+        self.watched.insert(contract_id);
+        Some(proof)
+    }
+
+    /// Stops sending the proof updates of the output.
+    pub fn unwatch(&mut self, contract_id: &ContractID) {
+        self.watched.remove(contract_id);
+    }
+
+    /// Updates the trees with the block applied to the state and returns the changes
+    /// to the watched outputs. The trees are updated exactly as the state's `Forest`,
+    /// so the proofs stay valid for the state.
+    /// Fails without changing the bridge if the block does not follow the bridge's tip,
+    /// spends unknown outputs or does not match the resulting forest.
+    pub fn apply_block(&mut self, block: &VerifiedBlock) -> Result<BridgeUpdate, BlockchainError> {
+        if block.header.height != self.height + 1 {
+            return Err(BlockchainError::MismatchedBridge);
+        }
+
+        // committed leaves deleted by the block: height of the tree, position and leaf hash
+        let mut deletions: Vec<(usize, u64, [u8; 32])> = Vec::new();
+        let mut insertions = Vec::new();
+        let mut spent = Vec::new();
+        for entry in block.txs.iter().flat_map(|tx| tx.log.iter()) {
+            match entry {
+                Entry::Input(contract_id) => {
+                    let hash = utreexo::leaf_hash(contract_id);
+                    match self.locate(&hash) {
+                        Some((height, position)) => {
+                            if deletions.iter().any(|(_, _, h)| h == &hash) {
+                                return Err(BlockchainError::UtxoNotFound);
+                            }
+                            deletions.push((height, position, hash));
+                        }
+                        None => {
+                            let index = insertions
+                                .iter()
+                                .position(|h| h == &hash)
+                                .ok_or(BlockchainError::UtxoNotFound)?;
+                            insertions.remove(index);
+                        }
+                    }
+                    if self.watched.contains(contract_id) {
+                        spent.push(*contract_id);
+                    }
+                }
+                Entry::Output(output) => {
+                    insertions.push(utreexo::leaf_hash(&output.id()));
+                }
+                _ => {}
+            }
+        }
+
+        // Trees are split and merged in the same order as in `Forest::normalize`.
+        deletions.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+        let mut trees = self.trees.clone();
+        let mut subtrees = Vec::new();
+        let mut rest = &deletions[..];
+        while let Some(height) = rest.first().map(|d| d.0) {
+            let n = rest.iter().take_while(|d| d.0 == height).count();
+            let positions = rest[..n].iter().map(|d| d.1).collect::<Vec<_>>();
+            let tree = trees[height]
+                .take()
+                .expect("deleted leaves are located in the trees");
+            split(&tree, height, 0, &positions, &mut subtrees);
+            rest = &rest[n..];
+        }
+        let mut moved = Vec::new();
+        for (height, start, tree) in subtrees {
+            let offset = utreexo::add_tree(&mut trees, height, tree.clone());
+            if offset != start {
+                moved.push((tree, offset));
+            }
+        }
+        let mut added = Vec::with_capacity(insertions.len());
+        for hash in insertions {
+            let leaf = Arc::new(Node {
+                hash,
+                children: None,
+            });
+            added.push((hash, utreexo::add_tree(&mut trees, 0, leaf)));
+        }
+
+        let roots = trees
+            .iter()
+            .rev()
+            .filter_map(|tree| tree.as_ref().map(|tree| tree.hash))
+            .collect::<Vec<_>>();
+        if utreexo::roots_hash(&roots) != block.header.utxoroot {
+            return Err(BlockchainError::InvalidUtxoRoot);
+        }
+
+        let mut changes = Vec::new();
+        for (_, _, hash) in deletions.iter() {
+            self.set_position(&mut changes, *hash, None);
+        }
+        for (tree, offset) in moved {
+            let mut leaves = Vec::new();
+            tree.collect_leaves(&mut leaves);
+            for (i, hash) in leaves.into_iter().enumerate() {
+                self.set_position(&mut changes, hash, Some(offset + i as u64));
+            }
+        }
+        for (hash, position) in added {
+            self.set_position(&mut changes, hash, Some(position));
+        }
+        self.undo.push_back(BridgeUndo {
+            trees: mem::replace(&mut self.trees, trees),
+            positions: changes,
+        });
+        if self.undo.len() > self.max_rollback {
+            self.undo.pop_front();
+        }
+        self.height = block.header.height;
+
+        for contract_id in spent.iter() {
+            self.watched.remove(contract_id);
+        }
+        let mut proofs = self
+            .watched
+            .iter()
+            .filter_map(|id| self.utxo_proof(id).map(|proof| (*id, proof)))
+            .collect::<Vec<_>>();
+        proofs.sort_by(|a, b| a.0.as_bytes().cmp(b.0.as_bytes()));
+        Ok(BridgeUpdate {
+            height: self.height,
+            proofs,
+            spent,
+        })
+    }
+
+    /// Rolls back the last `n` blocks along with the state.
+    /// Outputs that became unwatched when they were spent are not watched again.
+    /// Fails without changing the bridge if there is not enough undo data.
+    pub fn rollback(&mut self, n: usize) -> Result<(), BlockchainError> {
+        if n > self.undo.len() {
+            return Err(BlockchainError::RollbackTooDeep);
+        }
+        for _ in 0..n {
+            let undo = self.undo.pop_back().expect("undo data is checked above");
+            self.trees = undo.trees;
+            for (hash, position) in undo.positions.into_iter().rev() {
+                match position {
+                    Some(position) => self.positions.insert(hash, position),
+                    None => self.positions.remove(&hash),
+                };
+            }
+            self.height -= 1;
+        }
+        Ok(())
+    }
+
+    /// Serializes the bridge into a checkpoint, without the undo data and the watched outputs:
+    /// LE64 height, LE64 `max_rollback` and LE32-prefixed list of the 32-byte utxo hashes,
+    /// from the largest tree to the smallest one and in the order of positions within each tree.
+    pub fn to_checkpoint(&self) -> Vec<u8> {
+        let mut w = Writer::new(Vec::new());
+        self.write_checkpoint(&mut w)
+            .expect("writing to a vector never fails");
+        w.into_inner()
+    }
+
+    /// Restores the bridge from a checkpoint.
+    pub fn from_checkpoint(data: &[u8]) -> Result<Self, BlockchainError> {
+        let mut r = Reader::new(data);
+        let bridge = Self::read_checkpoint(&mut r).map_err(|_| BlockchainError::FormatError)?;
+        if !r.into_inner().is_empty() {
+            return Err(BlockchainError::FormatError);
+        }
+        Ok(bridge)
+    }
+
+    fn write_checkpoint<W: io::Write>(&self, w: &mut Writer<W>) -> io::Result<()> {
+        w.write_u64(self.height)?;
+        w.write_u64(self.max_rollback as u64)?;
+        let mut leaves = Vec::with_capacity(self.positions.len());
+        for tree in self.trees.iter().rev().filter_map(|tree| tree.as_ref()) {
+            tree.collect_leaves(&mut leaves);
+        }
+        w.write_size(leaves.len())?;
+        for leaf in leaves.iter() {
+            w.write_bytes(leaf)?;
+        }
+        Ok(())
+    }
+
+    fn read_checkpoint<R: io::Read>(r: &mut Reader<R>) -> Result<Self, VMError> {
+        let height = r.read_u64()?;
+        let max_rollback = r.read_u64()? as usize;
+        let mut leaves = Vec::new();
+        for _ in 0..r.read_size()? {
+            leaves.push(r.read_u8x32()?);
+        }
+
+        let mut trees = Vec::new();
+        let mut positions = HashMap::with_capacity(leaves.len());
+        let mut offset = 0;
+        for height in (0..8 * mem::size_of::<usize>()).rev() {
+            let size = 1usize << height;
+            if leaves.len() & size != 0 {
+                let tree = &leaves[offset..offset + size];
+                for (i, hash) in tree.iter().enumerate() {
+                    positions.insert(*hash, i as u64);
+                }
+                while trees.len() <= height {
+                    trees.push(None);
+                }
+                trees[height] = Some(Node::build(tree));
+                offset += size;
+            }
+        }
+
+        Ok(Bridge {
+            height,
+            trees,
+            positions,
+            watched: HashSet::new(),
+            max_rollback,
+            undo: VecDeque::new(),
+        })
+    }
+
+    /// Returns the height of the tree containing the leaf and the leaf's position within it.
+    fn locate(&self, hash: &[u8; 32]) -> Option<(usize, u64)> {
+        let position = *self.positions.get(hash)?;
+        self.trees
+            .iter()
+            .enumerate()
+            .filter_map(|(height, tree)| tree.as_ref().map(|tree| (height, tree)))
+            .find(|(height, tree)| {
+                position >> *height == 0 && &tree.path(*height, position).0 == hash
+            })
+            .map(|(height, _)| (height, position))
+    }
+
+    fn path(&self, hash: &[u8; 32]) -> Option<Path> {
+        let (height, position) = self.locate(hash)?;
+        let tree = self.trees[height].as_ref()?;
+        Some(Path {
+            position,
+            neighbors: tree.path(height, position).1,
+        })
+    }
+
+    /// Sets the position of the leaf, remembering the previous one for the rollback.
+    fn set_position(
+        &mut self,
+        changes: &mut Vec<([u8; 32], Option<u64>)>,
+        hash: [u8; 32],
+        position: Option<u64>,
+    ) {
+        let prev = match position {
+            Some(position) => self.positions.insert(hash, position),
+            None => self.positions.remove(&hash),
+        };
+        changes.push((hash, prev));
+    }
+}
+
+impl Tree for Arc<Node> {
+    fn merge(left: Self, right: Self) -> Self {
+        Arc::new(Node {
+            hash: utreexo::node_hash(&left.hash, &right.hash),
+            children: Some((left, right)),
+        })
+    }
+}
+
+impl Node {
+    /// Builds a perfect tree out of `2^k` leaf hashes.
+    fn build(leaves: &[[u8; 32]]) -> Arc<Node> {
+        if leaves.len() == 1 {
+            return Arc::new(Node {
+                hash: leaves[0],
+                children: None,
+            });
+        }
+        let (left, right) = leaves.split_at(leaves.len() / 2);
+        Tree::merge(Node::build(left), Node::build(right))
+    }
+
+    /// Returns the hash of the leaf at the position in the tree of the given height
+    /// and its neighbors, from the leaf up.
+    fn path(&self, height: usize, position: u64) -> ([u8; 32], Vec<[u8; 32]>) {
+        let mut node = self;
+        let mut neighbors = Vec::with_capacity(height);
+        for level in (0..height).rev() {
+            let (left, right) = node
+                .children
+                .as_ref()
+                .expect("nodes above the leaves have children");
+            if (position >> level) & 1 == 0 {
+                neighbors.push(right.hash);
+                node = left;
+            } else {
+                neighbors.push(left.hash);
+                node = right;
+            }
+        }
+        neighbors.reverse();
+        (node.hash, neighbors)
+    }
+
+    fn collect_leaves(&self, leaves: &mut Vec<[u8; 32]>) {
+        match &self.children {
+            None => leaves.push(self.hash),
+            Some((left, right)) => {
+                left.collect_leaves(leaves);
+                right.collect_leaves(leaves);
+            }
+        }
+    }
+}
+
+/// Collects the largest subtrees without deleted leaves, from left to right,
+/// with their heights and the positions of their first leaves in the split tree,
+/// like `utreexo::Forest::normalize` does with the roots.
+fn split(
+    node: &Arc<Node>,
+    height: usize,
+    start: u64,
+    deleted: &[u64],
+    subtrees: &mut Vec<(usize, u64, Arc<Node>)>,
+) {
+    if deleted.is_empty() {
+        subtrees.push((height, start, node.clone()));
+        return;
+    }
+    if let Some((left, right)) = &node.children {
+        let half = 1u64 << (height - 1);
+        let n = deleted.iter().take_while(|p| **p < start + half).count();
+        split(left, height - 1, start, &deleted[..n], subtrees);
+        split(right, height - 1, start + half, &deleted[n..], subtrees);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::tests::{apply, make_block};
+    use crate::testutil::{spend, utxo};
+
+    #[test]
+    fn proof_updates() {
+        let mut state = BlockchainState::make_initial(0, 10, 10);
+        let mut bridge = Bridge::new(&state).unwrap();
+        apply(&mut state, &mut bridge, vec![spend(1, &[], &[1, 2])]).unwrap();
+
+        let proof = bridge.watch(utxo(1).id()).unwrap();
+        assert_eq!(proof.verify(&utxo(1).id(), &state.tip().utxoroot), Ok(()));
+        assert!(bridge.watch(utxo(3).id()).is_none());
+
+        let block = make_block(&state, &bridge, vec![spend(2, &[2], &[3, 4])]);
+        state
+            .apply_verified_block(&block, &bridge.utreexo_proofs(&block.txs))
+            .unwrap();
+        let update = bridge.apply_block(&block).unwrap();
+        assert_eq!(update.height, 3);
+        assert!(update.spent.is_empty());
+        assert_eq!(update.proofs.len(), 1);
+        let (id, proof) = &update.proofs[0];
+        assert_eq!(id, &utxo(1).id());
+        assert_eq!(proof.verify(id, &state.tip().utxoroot), Ok(()));
+
+        // All unspent outputs have proofs.
+        assert_eq!(bridge.len(), 3);
+        assert_eq!(
+            bridge
+                .utxo_proof(&utxo(4).id())
+                .unwrap()
+                .verify(&utxo(4).id(), &state.tip().utxoroot),
+            Ok(())
+        );
+        assert!(bridge.utxo_proof(&utxo(2).id()).is_none());
+
+        let block = make_block(&state, &bridge, vec![spend(3, &[1], &[5])]);
+        state
+            .apply_verified_block(&block, &bridge.utreexo_proofs(&block.txs))
+            .unwrap();
+        let update = bridge.apply_block(&block).unwrap();
+        assert_eq!(update.spent, vec![utxo(1).id()]);
+        assert!(update.proofs.is_empty());
+
+        // After a rollback, the outputs created by the reverted block are forgotten.
+        state.rollback(1).unwrap();
+        bridge.rollback(1).unwrap();
+        assert_eq!(bridge.height(), 3);
+        assert_eq!(bridge.root(), state.tip().utxoroot);
+        assert!(bridge.utxo_proof(&utxo(5).id()).is_none());
+        assert!(bridge.utxo_proof(&utxo(1).id()).is_some());
+        assert_eq!(bridge.rollback(3), Err(BlockchainError::RollbackTooDeep));
+    }
+
+    #[test]
+    fn follows_the_state() {
+        let mut state = BlockchainState::make_initial(0, 10, 10);
+        let mut bridge = Bridge::new(&state).unwrap();
+        apply(
+            &mut state,
+            &mut bridge,
+            vec![spend(1, &[], &[1, 2, 3, 4, 5])],
+        )
+        .unwrap();
+        apply(&mut state, &mut bridge, vec![spend(2, &[], &[6, 7, 8])]).unwrap();
+
+        // Spends outputs from the different trees, including the ones created in the same block.
+        apply(
+            &mut state,
+            &mut bridge,
+            vec![spend(3, &[2, 7], &[9, 10]), spend(4, &[9, 5], &[11])],
+        )
+        .unwrap();
+        apply(&mut state, &mut bridge, vec![spend(5, &[1, 3, 10], &[12])]).unwrap();
+        assert_eq!(bridge.root(), state.tip().utxoroot);
+        assert_eq!(bridge.len(), 5); // 4, 6, 8, 11 and 12
+        for i in &[4, 6, 8, 11, 12] {
+            let proof = bridge.utxo_proof(&utxo(*i).id()).unwrap();
+            assert_eq!(proof.verify(&utxo(*i).id(), &state.tip().utxoroot), Ok(()));
+        }
+
+        // Blocks that do not follow the bridge's tip or its forest are rejected.
+        let block = make_block(&state, &bridge, vec![spend(6, &[4], &[])]);
+        let mut other = Bridge::new(&BlockchainState::make_initial(0, 10, 10)).unwrap();
+        assert_eq!(
+            other.apply_block(&block).map(|_| ()),
+            Err(BlockchainError::MismatchedBridge)
+        );
+        let mut tampered = make_block(&state, &bridge, vec![spend(6, &[4], &[])]);
+        tampered.header.utxoroot = state.tip().utxoroot;
+        assert_eq!(
+            bridge.apply_block(&tampered).map(|_| ()),
+            Err(BlockchainError::InvalidUtxoRoot)
+        );
+
+        let restored = Bridge::from_checkpoint(&bridge.to_checkpoint()).unwrap();
+        assert_eq!(restored.root(), bridge.root());
+        assert_eq!(restored.height(), bridge.height());
+        assert_eq!(restored.to_checkpoint(), bridge.to_checkpoint());
+        for i in &[4, 6, 8, 11, 12] {
+            assert_eq!(
+                restored.utxo_proof(&utxo(*i).id()),
+                bridge.utxo_proof(&utxo(*i).id())
+            );
+        }
+
+        // The restored bridge continues the chain.
+        let mut restored = restored;
+        state
+            .apply_verified_block(&block, &restored.utreexo_proofs(&block.txs))
+            .unwrap();
+        restored.apply_block(&block).unwrap();
+        assert_eq!(restored.root(), state.tip().utxoroot);
+    }
+}
@@ -0,0 +1,109 @@
+//! Cache of the witness IDs of the verified transactions.
+//!
+//! A node verifies a transaction when it enters the mempool, and again when it receives
+//! the block that includes it. The cache remembers the witness IDs of the verified transactions,
+//! so the block validation skips their R1CS proofs and signatures (see `Block::verify_with_cache`).
+//! The mempool fills the cache with the admitted transactions (see `Mempool::verification_cache`)
+//! and `BlockchainState::apply_block_with_cache` validates the blocks against it.
+//! The witness ID commits to the program, the signature and the proof,
+//! so the transactions with the same witness ID are identical.
+//!
+//! The cache holds a limited number of IDs and forgets the oldest ones first.
+
+use std::collections::{HashSet, VecDeque};
+use zkvm::{VerifiedTx, WTxID};
+
+/// Set of the witness IDs of the verified transactions, limited in size.
+pub struct VerificationCache {
+    capacity: usize,
+    wtxids: HashSet<WTxID>,
+    // order of insertion: the oldest IDs are evicted first
+    order: VecDeque<WTxID>,
+}
+
+impl VerificationCache {
+    /// Creates an empty cache that holds up to `capacity` witness IDs.
+    pub fn new(capacity: usize) -> Self {
+        VerificationCache {
+            capacity,
+            wtxids: HashSet::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    /// Returns the number of the cached witness IDs.
+    pub fn len(&self) -> usize {
+        self.wtxids.len()
+    }
+
+    /// Returns true if the cache is empty.
+    pub fn is_empty(&self) -> bool {
+        self.wtxids.is_empty()
+    }
+
+    /// Returns true if the transaction with the witness ID was verified.
+    pub fn contains(&self, wtxid: &WTxID) -> bool {
+        self.wtxids.contains(wtxid)
+    }
+
+    /// Remembers the verified transaction, evicting the oldest ones if the cache is full.
+    pub fn insert(&mut self, tx: &VerifiedTx) {
+        if self.capacity == 0 || !self.wtxids.insert(tx.wtxid) {
+            return;
+        }
+        self.order.push_back(tx.wtxid);
+        while self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.wtxids.remove(&oldest);
+            }
+        }
+    }
+
+    /// Forgets the transactions confirmed in a block, which are not going to be verified again.
+    pub fn remove_confirmed(&mut self, txs: &[VerifiedTx]) {
+        for tx in txs.iter() {
+            self.wtxids.remove(&tx.wtxid);
+        }
+        let wtxids = &self.wtxids;
+        self.order.retain(|wtxid| wtxids.contains(wtxid));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::make_tx;
+
+    #[test]
+    fn evicts_oldest() {
+        let mut cache = VerificationCache::new(2);
+        cache.insert(&make_tx(1, vec![]));
+        cache.insert(&make_tx(2, vec![]));
+        cache.insert(&make_tx(2, vec![]));
+        assert_eq!(cache.len(), 2);
+
+        cache.insert(&make_tx(3, vec![]));
+        assert_eq!(cache.len(), 2);
+        assert!(!cache.contains(&WTxID([1; 32])));
+        assert!(cache.contains(&WTxID([2; 32])));
+        assert!(cache.contains(&WTxID([3; 32])));
+
+        let mut empty = VerificationCache::new(0);
+        empty.insert(&make_tx(1, vec![]));
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn forgets_confirmed() {
+        let mut cache = VerificationCache::new(2);
+        cache.insert(&make_tx(1, vec![]));
+        cache.insert(&make_tx(2, vec![]));
+        cache.remove_confirmed(&[make_tx(1, vec![])]);
+        assert!(!cache.contains(&WTxID([1; 32])));
+
+        // The freed slot is reused without evicting the remaining ID.
+        cache.insert(&make_tx(3, vec![]));
+        assert!(cache.contains(&WTxID([2; 32])));
+        assert!(cache.contains(&WTxID([3; 32])));
+    }
+}
@@ -0,0 +1,168 @@
+//! Blockchain state kept in a storage, so that a node resumes from its last block after a restart.
+//!
+//! Each applied block is written together with the checkpoints of the resulting state
+//! and of the bridge with the full utreexo forest of the unspent outputs in a single atomic batch,
+//! so the storage never contains a block without the matching state.
+
+use bulletproofs::BulletproofGens;
+use byteorder::{BigEndian, ByteOrder};
+
+use crate::block::{Block, VerifiedBlock};
+use crate::bridge::Bridge;
+use crate::errors::BlockchainError;
+use crate::state::BlockchainState;
+use crate::storage::{Batch, Storage};
+
+/// Blockchain state persisted in a storage together with the applied blocks.
+pub struct Chain<S: Storage> {
+    state: BlockchainState,
+    bridge: Bridge,
+    storage: S,
+}
+
+const CHECKPOINT_KEY: &[u8] = b"chain/checkpoint";
+const BRIDGE_KEY: &[u8] = b"chain/bridge";
+const BLOCK_PREFIX: &[u8] = b"chain/block/";
+
+impl<S: Storage> Chain<S> {
+    /// Opens the chain kept in the storage,
+    /// or starts it with the initial state if the storage is empty.
+    /// The state restored from the storage takes the consensus parameters of the initial state.
+    pub fn open(mut storage: S, initial: BlockchainState) -> Result<Self, BlockchainError> {
+        let (state, bridge) = match (storage.get(CHECKPOINT_KEY)?, storage.get(BRIDGE_KEY)?) {
+            (Some(checkpoint), Some(bridge)) => (
+                BlockchainState::from_checkpoint(&checkpoint)?
+                    .with_params(initial.params().clone())?,
+                Bridge::from_checkpoint(&bridge)?,
+            ),
+            (None, None) => {
+                let bridge = Bridge::new(&initial)?;
+                let mut batch = Batch::new();
+                batch.put(CHECKPOINT_KEY.to_vec(), initial.to_checkpoint());
+                batch.put(BRIDGE_KEY.to_vec(), bridge.to_checkpoint());
+                storage.write(batch)?;
+                (initial, bridge)
+            }
+            _ => return Err(BlockchainError::FormatError),
+        };
+        if bridge.height() != state.tip().height || bridge.root() != state.tip().utxoroot {
+            return Err(BlockchainError::MismatchedBridge);
+        }
+        Ok(Chain {
+            state,
+            bridge,
+            storage,
+        })
+    }
+
+    /// Returns the current state.
+    pub fn state(&self) -> &BlockchainState {
+        &self.state
+    }
+
+    /// Returns the bridge with the full utreexo forest at the tip.
+    pub fn bridge(&self) -> &Bridge {
+        &self.bridge
+    }
+
+    /// Returns the underlying storage.
+    pub fn storage(&self) -> &S {
+        &self.storage
+    }
+
+    /// Verifies the block on top of the tip, applies it to the state and the bridge and stores
+    /// the block with the new checkpoints. The state is not changed if the block is invalid
+    /// or the storage fails.
+    ///
+    /// Undo data is not stored, so the blocks applied before the chain was reopened
+    /// cannot be rolled back.
+    pub fn apply_block(
+        &mut self,
+        block: Block,
+        bp_gens: &BulletproofGens,
+    ) -> Result<VerifiedBlock, BlockchainError> {
+        let height = block.header.height;
+        let bytes = block.to_bytes();
+        let mut state = self.state.clone();
+        let mut bridge = self.bridge.clone();
+        let verified = state.apply_block(block, &bridge, bp_gens)?;
+        bridge.apply_block(&verified)?;
+
+        let mut batch = Batch::new();
+        batch.put(block_key(height), bytes);
+        batch.put(CHECKPOINT_KEY.to_vec(), state.to_checkpoint());
+        batch.put(BRIDGE_KEY.to_vec(), bridge.to_checkpoint());
+        self.storage.write(batch)?;
+
+        self.state = state;
+        self.bridge = bridge;
+        Ok(verified)
+    }
+
+    /// Returns the stored block at the given height.
+    pub fn block(&self, height: u64) -> Result<Option<Block>, BlockchainError> {
+        match self.storage.get(&block_key(height))? {
+            Some(bytes) => Block::from_bytes(&bytes).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+fn block_key(height: u64) -> Vec<u8> {
+    // Big-endian height keeps the blocks sorted by height.
+    let mut key = BLOCK_PREFIX.to_vec();
+    let mut h = [0u8; 8];
+    BigEndian::write_u64(&mut h, height);
+    key.extend_from_slice(&h);
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::BlockHeader;
+    use crate::storage::MemoryStorage;
+    use zkvm::TxTree;
+
+    fn empty_block(prev: &BlockHeader) -> Block {
+        Block {
+            header: BlockHeader {
+                version: 1,
+                height: prev.height + 1,
+                prev_id: prev.id(),
+                timestamp_ms: prev.timestamp_ms + 1000,
+                txroot: TxTree::build(&[]).root(),
+                utxoroot: prev.utxoroot,
+                ext: Vec::new(),
+            },
+            txs: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn reopen_chain() {
+        let bp_gens = BulletproofGens::new(256, 1);
+        let initial = BlockchainState::make_initial(1000, 10, 10);
+        let mut chain = Chain::open(MemoryStorage::default(), initial.clone()).unwrap();
+
+        let block = empty_block(chain.state().tip());
+        chain.apply_block(block.clone(), &bp_gens).unwrap();
+
+        // Invalid block leaves the state and the storage unchanged.
+        let mut invalid = empty_block(chain.state().tip());
+        invalid.header.utxoroot = [0u8; 32];
+        assert_eq!(
+            chain.apply_block(invalid, &bp_gens).map(|_| ()),
+            Err(BlockchainError::InvalidUtxoRoot)
+        );
+        assert_eq!(chain.block(3).unwrap().map(|b| b.to_bytes()), None);
+
+        let reopened = Chain::open(chain.storage().clone(), initial).unwrap();
+        assert_eq!(reopened.state().tip(), &block.header);
+        assert_eq!(reopened.bridge().root(), block.header.utxoroot);
+        assert_eq!(
+            reopened.block(2).unwrap().map(|b| b.to_bytes()),
+            Some(block.to_bytes())
+        );
+    }
+}
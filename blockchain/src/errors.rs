@@ -0,0 +1,136 @@
+//! Errors related to the blockchain state updates.
+
+use zkvm::VMError;
+
+/// Represents an error in validation of the blockchain state changes.
+#[derive(Fail, Clone, Debug, Eq, PartialEq)]
+pub enum BlockchainError {
+    /// This error occurs when the utreexo proof does not match the item or the forest.
+    #[fail(display = "Utreexo proof is invalid.")]
+    InvalidUtreexoProof,
+
+    /// This error occurs when the transaction is already in the mempool.
+    #[fail(display = "Transaction is already in the mempool.")]
+    DuplicateTransaction,
+
+    /// This error occurs when the transaction spends an output already spent by another transaction.
+    #[fail(display = "Transaction spends an output that is already spent.")]
+    DoubleSpend,
+
+    /// This error occurs when the mempool is full and the transaction's fee rate
+    /// is not high enough to evict other transactions.
+    #[fail(display = "Transaction fee rate is too low.")]
+    FeeTooLow,
+
+    /// This error occurs when the block's version is lower than the version of the previous block.
+    #[fail(display = "Block version is lower than the version of the previous block.")]
+    VersionReversion,
+
+    /// This error occurs when a block before the state hash version has non-empty extension data.
+    #[fail(display = "Block extension data is not allowed in this version.")]
+    IllegalExtension,
+
+    /// This error occurs when the block's version is lower than the minimum version of the network.
+    #[fail(display = "Block version is not allowed.")]
+    BadBlockVersion,
+
+    /// This error occurs when the serialized block exceeds the maximum block size.
+    #[fail(display = "Block is too large.")]
+    BlockTooLarge,
+
+    /// This error occurs when a serialized transaction exceeds the maximum transaction size.
+    #[fail(display = "Transaction is too large.")]
+    TxTooLarge,
+
+    /// This error occurs when the initial block does not match the genesis ID of the network.
+    #[fail(display = "Initial block does not match the network's genesis block.")]
+    MismatchedGenesis,
+
+    /// This error occurs when the bridge does not follow the same chain as the blockchain state.
+    #[fail(display = "Bridge does not match the blockchain state.")]
+    MismatchedBridge,
+
+    /// This error occurs when the block's height does not follow the previous block.
+    #[fail(display = "Block height does not follow the previous block.")]
+    BadHeight,
+
+    /// This error occurs when the block does not refer to the ID of the previous block.
+    #[fail(display = "Block does not refer to the previous block.")]
+    MismatchedPrevID,
+
+    /// This error occurs when the block's timestamp is not later than the previous block's.
+    #[fail(display = "Block timestamp is not later than the previous block's.")]
+    BadBlockTimestamp,
+
+    /// This error occurs when the block's timestamp is outside the time bounds of a transaction.
+    #[fail(display = "Block timestamp is outside the transaction's time bounds.")]
+    BadTxTimestamp,
+
+    /// This error occurs when the transaction's version is not allowed in the block's version.
+    #[fail(display = "Transaction version is not allowed in this block.")]
+    BadTxVersion,
+
+    /// This error occurs when the transactions do not match the block's `txroot`.
+    #[fail(display = "Transaction root does not match the transactions.")]
+    InvalidTxRoot,
+
+    /// This error occurs when a transaction in the block fails verification.
+    #[fail(display = "Transaction is invalid: {}", _0)]
+    TxValidation(VMError),
+
+    /// This error occurs when the utxo set after applying the block does not match the block's `utxoroot`.
+    #[fail(display = "Utxo root does not match the utxo set.")]
+    InvalidUtxoRoot,
+
+    /// This error occurs when a block of version 2 or a snapshot does not match the state hash.
+    #[fail(display = "State hash does not match the state.")]
+    InvalidStateHash,
+
+    /// This error occurs when the transaction spends an output that is not in the utxo set.
+    #[fail(display = "Transaction spends an output that is not in the utxo set.")]
+    UtxoNotFound,
+
+    /// This error occurs when a nonce refers to a block that is neither the initial block nor a recent one.
+    #[fail(display = "Nonce refers to an unknown block.")]
+    UnknownBlockReference,
+
+    /// This error occurs when a nonce anchor is already used by an unexpired nonce.
+    #[fail(display = "Nonce is already used.")]
+    DuplicateNonce,
+
+    /// This error occurs when a nullifier is already used by another transaction.
+    #[fail(display = "Nullifier is already used.")]
+    DuplicateNullifier,
+
+    /// This error occurs when more blocks are rolled back than the undo data is kept for.
+    #[fail(display = "Not enough undo data to roll back the blocks.")]
+    RollbackTooDeep,
+
+    /// This error occurs when the transaction's merkle proof does not match the block's `txroot`.
+    #[fail(display = "Transaction is not included in the block.")]
+    InvalidTxInclusion,
+
+    /// This error occurs when the light client has no header at the requested height.
+    #[fail(display = "Block header is unknown.")]
+    UnknownBlock,
+
+    /// This error occurs when the storage fails to read or write the data.
+    #[fail(display = "Storage error: {}", _0)]
+    StorageError(String),
+
+    /// This error occurs when the block header is not signed by the required signers.
+    #[fail(display = "Block signature is invalid.")]
+    InvalidBlockSignature,
+
+    /// This error occurs when the quorum of the signing committee is zero or larger than the committee.
+    #[fail(display = "Committee quorum is invalid.")]
+    InvalidQuorum,
+
+    /// This error occurs when the threads for the parallel verification cannot be created.
+    #[fail(display = "Thread pool error: {}", _0)]
+    ThreadPoolError(String),
+
+    /// This error occurs when data is malformed
+    #[fail(display = "Format in invalid")]
+    FormatError,
+}
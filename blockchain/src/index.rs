@@ -0,0 +1,367 @@
+//! Index of the applied blocks for the block explorers.
+//!
+//! The index maps the transaction IDs to their locations in the chain,
+//! the contract IDs to the transactions that created and spent them,
+//! the flavors to their issuance history, and the predicates (e.g. the receivers' addresses)
+//! to the outputs locked by them.
+//!
+//! The index is kept in a `Storage`, and each block is indexed or reverted
+//! with a single atomic batch of writes.
+
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+use curve25519_dalek::ristretto::CompressedRistretto;
+use zkvm::{ContractID, Entry, TxID};
+
+use crate::block::{BlockID, VerifiedBlock};
+use crate::errors::BlockchainError;
+use crate::storage::{Batch, Storage};
+
+/// Index of the applied blocks.
+pub struct Index<S: Storage> {
+    storage: S,
+}
+
+/// Location of a transaction in the chain.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TxLocation {
+    /// Height of the block containing the transaction.
+    pub height: u64,
+    /// ID of the block containing the transaction.
+    pub block_id: BlockID,
+    /// Position of the transaction in the block.
+    pub position: u32,
+}
+
+/// Transactions that created and spent an output.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OutputHistory {
+    /// Transaction that created the output.
+    pub created: TxID,
+    /// Transaction that spent the output, if it is spent.
+    pub spent: Option<TxID>,
+}
+
+/// Issuance of a flavor.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Issuance {
+    /// Transaction that issued the value.
+    pub txid: TxID,
+    /// Height of the block containing the transaction.
+    pub height: u64,
+    /// Commitment to the issued quantity.
+    pub qty: CompressedRistretto,
+}
+
+// Namespace of the index keys, so that the storage can be shared with the chain state.
+const NAMESPACE: &[u8] = b"index/";
+
+// Prefixes of the keys for each kind of the index entries.
+const TX_PREFIX: u8 = b't';
+const CREATED_PREFIX: u8 = b'c';
+const SPENT_PREFIX: u8 = b's';
+const ISSUANCE_PREFIX: u8 = b'i';
+const PREDICATE_PREFIX: u8 = b'p';
+
+impl<S: Storage> Index<S> {
+    /// Creates an index kept in the given storage.
+    pub fn new(storage: S) -> Self {
+        Index { storage }
+    }
+
+    /// Returns the underlying storage.
+    pub fn storage(&self) -> &S {
+        &self.storage
+    }
+
+    /// Adds the transactions of the applied block to the index.
+    pub fn apply_block(&mut self, block: &VerifiedBlock) -> Result<(), BlockchainError> {
+        let mut batch = Batch::new();
+        let height = block.header.height;
+        let block_id = block.header.id();
+        for (position, tx) in block.txs.iter().enumerate() {
+            let position = position as u32;
+            let mut location = Vec::with_capacity(8 + 32 + 4);
+            write_u64_le(height, &mut location);
+            location.extend_from_slice(&block_id.0);
+            write_u32_le(position, &mut location);
+            batch.put(key(TX_PREFIX, &[&tx.id.0]), location);
+
+            for (i, entry) in tx.log.iter().enumerate() {
+                // Height, position and entry index in big-endian order
+                // make the keys sorted in the order of the chain.
+                let order = chain_order(height, position, i as u32);
+                match entry {
+                    Entry::Input(contract_id) => {
+                        batch.put(
+                            key(SPENT_PREFIX, &[contract_id.as_bytes()]),
+                            tx.id.0.to_vec(),
+                        );
+                    }
+                    Entry::Output(output) => {
+                        let contract_id = output.id();
+                        let predicate = output.contract().predicate.to_point();
+                        batch.put(
+                            key(CREATED_PREFIX, &[contract_id.as_bytes()]),
+                            tx.id.0.to_vec(),
+                        );
+                        batch.put(
+                            key(PREDICATE_PREFIX, &[predicate.as_bytes(), &order]),
+                            contract_id.as_bytes().to_vec(),
+                        );
+                    }
+                    Entry::Issue(qty, flv) => {
+                        let mut value = tx.id.0.to_vec();
+                        value.extend_from_slice(qty.as_bytes());
+                        batch.put(key(ISSUANCE_PREFIX, &[flv.as_bytes(), &order]), value);
+                    }
+                    _ => {}
+                }
+            }
+        }
+        self.storage.write(batch)
+    }
+
+    /// Removes the transactions of the block from the index when the block is rolled back.
+    /// The outputs spent by the block become unspent again.
+    pub fn revert_block(&mut self, block: &VerifiedBlock) -> Result<(), BlockchainError> {
+        let mut batch = Batch::new();
+        let height = block.header.height;
+        for (position, tx) in block.txs.iter().enumerate() {
+            batch.delete(key(TX_PREFIX, &[&tx.id.0]));
+            for (i, entry) in tx.log.iter().enumerate() {
+                let order = chain_order(height, position as u32, i as u32);
+                match entry {
+                    Entry::Input(contract_id) => {
+                        batch.delete(key(SPENT_PREFIX, &[contract_id.as_bytes()]));
+                    }
+                    Entry::Output(output) => {
+                        let predicate = output.contract().predicate.to_point();
+                        batch.delete(key(CREATED_PREFIX, &[output.id().as_bytes()]));
+                        batch.delete(key(PREDICATE_PREFIX, &[predicate.as_bytes(), &order]));
+                    }
+                    Entry::Issue(_, flv) => {
+                        batch.delete(key(ISSUANCE_PREFIX, &[flv.as_bytes(), &order]));
+                    }
+                    _ => {}
+                }
+            }
+        }
+        self.storage.write(batch)
+    }
+
+    /// Returns the location of the transaction in the chain.
+    pub fn tx_location(&self, txid: &TxID) -> Result<Option<TxLocation>, BlockchainError> {
+        let value = match self.storage.get(&key(TX_PREFIX, &[&txid.0]))? {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        if value.len() != 8 + 32 + 4 {
+            return Err(BlockchainError::FormatError);
+        }
+        Ok(Some(TxLocation {
+            height: LittleEndian::read_u64(&value[0..8]),
+            block_id: BlockID(read_hash(&value[8..40])?),
+            position: LittleEndian::read_u32(&value[40..44]),
+        }))
+    }
+
+    /// Returns the transactions that created and spent the output.
+    pub fn output_history(
+        &self,
+        contract_id: &ContractID,
+    ) -> Result<Option<OutputHistory>, BlockchainError> {
+        let created = match self
+            .storage
+            .get(&key(CREATED_PREFIX, &[contract_id.as_bytes()]))?
+        {
+            Some(txid) => TxID(read_hash(&txid)?),
+            None => return Ok(None),
+        };
+        let spent = match self
+            .storage
+            .get(&key(SPENT_PREFIX, &[contract_id.as_bytes()]))?
+        {
+            Some(txid) => Some(TxID(read_hash(&txid)?)),
+            None => None,
+        };
+        Ok(Some(OutputHistory { created, spent }))
+    }
+
+    /// Returns the issuances of the flavor in the order of the chain.
+    /// Issued flavors are not blinded, so the flavor is identified by its point `flavor·B`.
+    pub fn issuances(
+        &self,
+        flavor: &CompressedRistretto,
+    ) -> Result<Vec<Issuance>, BlockchainError> {
+        self.storage
+            .scan_prefix(&key(ISSUANCE_PREFIX, &[flavor.as_bytes()]))?
+            .into_iter()
+            .map(|(key, value)| {
+                let order = NAMESPACE.len() + 1 + 32;
+                if key.len() != order + 16 || value.len() != 64 {
+                    return Err(BlockchainError::FormatError);
+                }
+                Ok(Issuance {
+                    txid: TxID(read_hash(&value[0..32])?),
+                    height: BigEndian::read_u64(&key[order..order + 8]),
+                    qty: CompressedRistretto(read_hash(&value[32..64])?),
+                })
+            })
+            .collect()
+    }
+
+    /// Returns the IDs of the outputs locked by the predicate in the order of the chain,
+    /// including the spent ones. For outputs sent to a receiver, this is the receiver's predicate.
+    pub fn outputs(
+        &self,
+        predicate: &CompressedRistretto,
+    ) -> Result<Vec<ContractID>, BlockchainError> {
+        self.storage
+            .scan_prefix(&key(PREDICATE_PREFIX, &[predicate.as_bytes()]))?
+            .into_iter()
+            .map(|(_, value)| read_hash(&value).map(ContractID::from_raw_bytes))
+            .collect()
+    }
+}
+
+fn key(prefix: u8, parts: &[&[u8]]) -> Vec<u8> {
+    let mut key = NAMESPACE.to_vec();
+    key.push(prefix);
+    for part in parts.iter() {
+        key.extend_from_slice(part);
+    }
+    key
+}
+
+fn chain_order(height: u64, position: u32, index: u32) -> [u8; 16] {
+    let mut order = [0u8; 16];
+    BigEndian::write_u64(&mut order[0..8], height);
+    BigEndian::write_u32(&mut order[8..12], position);
+    BigEndian::write_u32(&mut order[12..16], index);
+    order
+}
+
+fn write_u64_le(x: u64, buf: &mut Vec<u8>) {
+    let mut b = [0u8; 8];
+    LittleEndian::write_u64(&mut b, x);
+    buf.extend_from_slice(&b);
+}
+
+fn write_u32_le(x: u32, buf: &mut Vec<u8>) {
+    let mut b = [0u8; 4];
+    LittleEndian::write_u32(&mut b, x);
+    buf.extend_from_slice(&b);
+}
+
+fn read_hash(bytes: &[u8]) -> Result<[u8; 32], BlockchainError> {
+    if bytes.len() != 32 {
+        return Err(BlockchainError::FormatError);
+    }
+    let mut hash = [0u8; 32];
+    hash.copy_from_slice(bytes);
+    Ok(hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::BlockHeader;
+    use crate::storage::MemoryStorage;
+    use crate::testutil::make_tx;
+    use curve25519_dalek::constants::RISTRETTO_BASEPOINT_COMPRESSED;
+    use zkvm::{Anchor, Contract, Output, Predicate, VerifiedTx};
+
+    fn utxo(i: u8, predicate: &Predicate) -> Output {
+        Output::new(Contract {
+            anchor: Anchor::nonce([i; 32], predicate, 0),
+            payload: vec![],
+            predicate: predicate.clone(),
+        })
+    }
+
+    fn make_block(height: u64, txs: Vec<VerifiedTx>) -> VerifiedBlock {
+        let mut header = BlockHeader::make_initial(1000 * height, [0u8; 32]);
+        header.height = height;
+        VerifiedBlock { header, txs }
+    }
+
+    #[test]
+    fn index_blocks() {
+        let alice = Predicate::Opaque(RISTRETTO_BASEPOINT_COMPRESSED);
+        let bob = Predicate::Opaque(CompressedRistretto([1u8; 32]));
+        let flavor = CompressedRistretto([2u8; 32]);
+        let qty = CompressedRistretto([3u8; 32]);
+        let (out1, out2, out3) = (utxo(1, &alice), utxo(2, &bob), utxo(3, &alice));
+
+        let block1 = make_block(
+            2,
+            vec![
+                make_tx(
+                    1,
+                    vec![Entry::Issue(qty, flavor), Entry::Output(out1.clone())],
+                ),
+                make_tx(2, vec![Entry::Output(out2.clone())]),
+            ],
+        );
+        let block2 = make_block(
+            3,
+            vec![make_tx(
+                3,
+                vec![
+                    Entry::Input(out1.id()),
+                    Entry::Issue(qty, flavor),
+                    Entry::Output(out3.clone()),
+                ],
+            )],
+        );
+
+        let mut index = Index::new(MemoryStorage::default());
+        index.apply_block(&block1).unwrap();
+        index.apply_block(&block2).unwrap();
+
+        assert_eq!(
+            index.tx_location(&TxID([2; 32])).unwrap(),
+            Some(TxLocation {
+                height: 2,
+                block_id: block1.header.id(),
+                position: 1,
+            })
+        );
+        assert_eq!(index.tx_location(&TxID([4; 32])).unwrap(), None);
+        assert_eq!(
+            index.output_history(&out1.id()).unwrap(),
+            Some(OutputHistory {
+                created: TxID([1; 32]),
+                spent: Some(TxID([3; 32])),
+            })
+        );
+        assert_eq!(
+            index
+                .issuances(&flavor)
+                .unwrap()
+                .iter()
+                .map(|i| (i.txid, i.height))
+                .collect::<Vec<_>>(),
+            vec![(TxID([1; 32]), 2), (TxID([3; 32]), 3)]
+        );
+        assert_eq!(
+            index.outputs(&alice.to_point()).unwrap(),
+            vec![out1.id(), out3.id()]
+        );
+        assert_eq!(index.outputs(&bob.to_point()).unwrap(), vec![out2.id()]);
+
+        // Reverting the block restores the index to the state after the first block.
+        index.revert_block(&block2).unwrap();
+        assert_eq!(index.tx_location(&TxID([3; 32])).unwrap(), None);
+        assert_eq!(
+            index.output_history(&out1.id()).unwrap(),
+            Some(OutputHistory {
+                created: TxID([1; 32]),
+                spent: None,
+            })
+        );
+        assert_eq!(index.output_history(&out3.id()).unwrap(), None);
+        assert_eq!(index.issuances(&flavor).unwrap().len(), 1);
+        assert_eq!(index.outputs(&alice.to_point()).unwrap(), vec![out1.id()]);
+    }
+}
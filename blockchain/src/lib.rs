@@ -0,0 +1,44 @@
+#![deny(missing_docs)]
+//! Blockchain state machine for ZkVM transactions.
+
+#[macro_use]
+extern crate failure;
+
+mod block;
+mod bridge;
+mod cache;
+mod chain;
+mod errors;
+mod index;
+mod mempool;
+mod params;
+mod signer;
+mod spv;
+mod state;
+mod storage;
+mod template;
+mod utreexo;
+
+#[cfg(feature = "simulator")]
+pub mod simulator;
+#[cfg(any(test, feature = "testutil"))]
+pub mod testutil;
+
+pub use self::block::{Block, BlockHeader, BlockID, VerifiedBlock};
+pub use self::bridge::{Bridge, BridgeUpdate};
+pub use self::cache::VerificationCache;
+pub use self::chain::Chain;
+pub use self::errors::BlockchainError;
+pub use self::index::{Index, Issuance, OutputHistory, TxLocation};
+pub use self::mempool::Mempool;
+pub use self::params::{ChainParams, VersionRules};
+pub use self::signer::{BlockSigner, Committee};
+pub use self::spv::{SpvClient, TxInclusion, UtxoProof};
+pub use self::state::{BlockchainState, StateSnapshot};
+#[cfg(feature = "rocksdb")]
+pub use self::storage::RocksDbStorage;
+#[cfg(feature = "sled")]
+pub use self::storage::SledStorage;
+pub use self::storage::{Batch, BatchOp, MemoryStorage, Storage};
+pub use self::template::BlockTemplate;
+pub use self::utreexo::{Forest, Path, Proof};
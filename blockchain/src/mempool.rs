@@ -0,0 +1,663 @@
+//! Mempool: a pool of verified transactions waiting to be included in a block.
+//!
+//! Mempool tracks the outputs consumed and created by its transactions,
+//! so it can reject conflicting spends and keep the transactions that depend
+//! on each other consistent when some of them are removed.
+//! Transactions publishing the same nullifier conflict in the same way as double spends.
+//!
+//! A conflicting transaction replaces the transactions it conflicts with if it pays
+//! a strictly higher fee rate than each of them (replace-by-fee), and transactions
+//! are selected for a block by the fee rates of their packages of unconfirmed ancestors,
+//! so a child can pay for its parents (child-pays-for-parent).
+
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use zkvm::{ContractID, Entry, FeeRate, TxID, VerifiedTx};
+
+use crate::cache::VerificationCache;
+use crate::errors::BlockchainError;
+
+/// Number of the witness IDs of the admitted transactions remembered by the mempool.
+const VERIFICATION_CACHE_CAPACITY: usize = 100_000;
+
+/// Pool of unconfirmed transactions limited by their total size in bytes.
+pub struct Mempool {
+    max_size: u64,
+    size: u64,
+    next_seq: u64,
+    entries: HashMap<TxID, MempoolEntry>,
+    // utxo -> transaction spending it
+    spent: HashMap<ContractID, TxID>,
+    // output -> transaction creating it
+    created: HashMap<ContractID, TxID>,
+    // nullifier -> transaction publishing it
+    nullifiers: HashMap<[u8; 32], TxID>,
+    // witness IDs of the admitted transactions
+    verified: VerificationCache,
+}
+
+struct MempoolEntry {
+    tx: VerifiedTx,
+    // order of arrival: older transactions have lower numbers
+    seq: u64,
+    inputs: Vec<ContractID>,
+    outputs: Vec<ContractID>,
+    nullifiers: Vec<[u8; 32]>,
+}
+
+impl Mempool {
+    /// Creates an empty mempool that holds transactions up to a given total size in bytes.
+    pub fn new(max_size: u64) -> Self {
+        Mempool {
+            max_size,
+            size: 0,
+            next_seq: 0,
+            entries: HashMap::new(),
+            spent: HashMap::new(),
+            created: HashMap::new(),
+            nullifiers: HashMap::new(),
+            verified: VerificationCache::new(VERIFICATION_CACHE_CAPACITY),
+        }
+    }
+
+    /// Returns the number of transactions in the mempool.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns `true` if the mempool has no transactions.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns the total size of the transactions in bytes.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Returns `true` if the mempool contains a transaction with a given ID.
+    pub fn contains(&self, txid: &TxID) -> bool {
+        self.entries.contains_key(txid)
+    }
+
+    /// Returns the witness IDs of the transactions admitted to the mempool,
+    /// whose proofs and signatures are skipped when a block includes them
+    /// (see `BlockchainState::apply_block_with_cache`).
+    pub fn verification_cache(&self) -> &VerificationCache {
+        &self.verified
+    }
+
+    /// Adds a transaction to the mempool.
+    ///
+    /// Fails if the transaction is already in the mempool.
+    /// A transaction that spends an output already spent by another transaction in the mempool
+    /// or publishes a nullifier already published by another transaction in the mempool
+    /// replaces the conflicting transactions together with their descendants if it pays
+    /// a strictly higher fee rate than each conflicting transaction, and at least
+    /// the total fee of all the replaced transactions. Otherwise it is rejected.
+    /// If the mempool is full, transactions are evicted together with their descendants,
+    /// starting from the lowest fee rate of such a package (the oldest first). Transaction is rejected if it would
+    /// have to evict a transaction with the same or higher fee rate.
+    /// Returns the IDs of the replaced and evicted transactions.
+    ///
+    /// The transaction is fully verified, including its point operations,
+    /// so its witness ID is remembered in the verification cache once it is admitted.
+    pub fn append(&mut self, tx: VerifiedTx) -> Result<Vec<TxID>, BlockchainError> {
+        if self.entries.contains_key(&tx.id) {
+            return Err(BlockchainError::DuplicateTransaction);
+        }
+        let (inputs, outputs) = utxos(&tx);
+        let nullifiers = nullifiers(&tx);
+        let entry = MempoolEntry {
+            tx,
+            seq: self.next_seq,
+            inputs,
+            outputs,
+            nullifiers,
+        };
+
+        let replaced = self.select_replacements(&entry)?;
+        let evicted = self.select_evictions(&entry, &replaced)?;
+        let mut removed = Vec::new();
+        for txid in replaced.iter().chain(evicted.iter()) {
+            self.remove_with_descendants(txid, &mut removed);
+        }
+
+        for input in entry.inputs.iter() {
+            self.spent.insert(*input, entry.tx.id);
+        }
+        for output in entry.outputs.iter() {
+            self.created.insert(*output, entry.tx.id);
+        }
+        for tag in entry.nullifiers.iter() {
+            self.nullifiers.insert(*tag, entry.tx.id);
+        }
+        self.size += entry.tx.feerate.size();
+        self.next_seq += 1;
+        self.verified.insert(&entry.tx);
+        self.entries.insert(entry.tx.id, entry);
+        Ok(removed)
+    }
+
+    /// Removes the transactions included in a block, and the transactions
+    /// that conflict with them together with their descendants.
+    /// Transactions spending the outputs of the confirmed ones remain in the mempool.
+    /// Returns the IDs of the removed conflicting transactions.
+    pub fn remove_confirmed(&mut self, txs: &[VerifiedTx]) -> Vec<TxID> {
+        self.verified.remove_confirmed(txs);
+        let mut removed = Vec::new();
+        for tx in txs.iter() {
+            self.remove_entry(&tx.id);
+            let (inputs, _) = utxos(tx);
+            for input in inputs.iter() {
+                if let Some(txid) = self.spent.get(input).cloned() {
+                    self.remove_with_descendants(&txid, &mut removed);
+                }
+            }
+            for tag in nullifiers(tx).iter() {
+                if let Some(txid) = self.nullifiers.get(tag).cloned() {
+                    self.remove_with_descendants(&txid, &mut removed);
+                }
+            }
+        }
+        removed
+    }
+
+    /// Removes the transactions that expire before a given time (in milliseconds
+    /// since the Unix epoch), together with their descendants.
+    /// Returns the IDs of the removed transactions.
+    pub fn expire(&mut self, now: u64) -> Vec<TxID> {
+        let expired: Vec<TxID> = self
+            .entries
+            .values()
+            .filter(|e| e.tx.header.maxtime < now)
+            .map(|e| e.tx.id)
+            .collect();
+        let mut removed = Vec::new();
+        for txid in expired.iter() {
+            self.remove_with_descendants(txid, &mut removed);
+        }
+        removed
+    }
+
+    /// Returns the transactions ordered for inclusion in a block.
+    ///
+    /// Transactions are selected by the fee rate of their packages: the transaction
+    /// together with its ancestors that are not selected yet. Among the equal fee rates,
+    /// the package with the oldest transaction goes first. Transactions always follow
+    /// the transactions in the mempool whose outputs they spend.
+    pub fn candidates(&self) -> Vec<&VerifiedTx> {
+        let mut result = Vec::with_capacity(self.entries.len());
+        let mut included = HashSet::new();
+        while result.len() < self.entries.len() {
+            let best = self
+                .entries
+                .values()
+                .filter(|e| !included.contains(&e.tx.id))
+                .max_by_key(|e| {
+                    let package = self.package(e, &included);
+                    let oldest = package.iter().map(|e| e.seq).min();
+                    (package_feerate(&package), Reverse(oldest), Reverse(e.seq))
+                });
+            match best {
+                Some(entry) => self.include_with_ancestors(entry, &mut included, &mut result),
+                None => break,
+            }
+        }
+        result
+    }
+
+    /// Returns the transactions in the mempool whose outputs the transaction spends,
+    /// directly or through other transactions, in the order of their inclusion in a block.
+    /// The transaction itself does not have to be in the mempool.
+    pub fn ancestors(&self, tx: &VerifiedTx) -> Vec<&VerifiedTx> {
+        let (inputs, _) = utxos(tx);
+        let mut included = HashSet::new();
+        let mut result = Vec::new();
+        for txid in inputs.iter().filter_map(|input| self.created.get(input)) {
+            if let Some(entry) = self.entries.get(txid) {
+                self.include_with_ancestors(entry, &mut included, &mut result);
+            }
+        }
+        result
+    }
+
+    /// Returns the fee rate of the transaction together with all its ancestors in the mempool.
+    pub fn ancestor_feerate(&self, txid: &TxID) -> Option<FeeRate> {
+        let entry = self.entries.get(txid)?;
+        Some(package_feerate(&self.package(entry, &HashSet::new())))
+    }
+
+    /// Returns the fee rate of the transaction together with all its descendants in the mempool.
+    pub fn descendant_feerate(&self, txid: &TxID) -> Option<FeeRate> {
+        let entry = self.entries.get(txid)?;
+        Some(package_feerate(&self.descendants(entry)))
+    }
+
+    /// Returns the entry together with all its descendants.
+    fn descendants<'a>(&'a self, entry: &'a MempoolEntry) -> Vec<&'a MempoolEntry> {
+        let mut descendants = Vec::new();
+        let mut visited = HashSet::new();
+        let mut stack = vec![entry];
+        while let Some(e) = stack.pop() {
+            if visited.insert(e.tx.id) {
+                descendants.push(e);
+                stack.extend(self.children(e));
+            }
+        }
+        descendants
+    }
+
+    /// Returns the entry together with its ancestors that are not in the excluded set.
+    fn package<'a>(
+        &'a self,
+        entry: &'a MempoolEntry,
+        excluded: &HashSet<TxID>,
+    ) -> Vec<&'a MempoolEntry> {
+        let mut package = Vec::new();
+        let mut visited = HashSet::new();
+        let mut stack = vec![entry];
+        while let Some(e) = stack.pop() {
+            if !excluded.contains(&e.tx.id) && visited.insert(e.tx.id) {
+                package.push(e);
+                stack.extend(self.parents(e));
+            }
+        }
+        package
+    }
+
+    fn include_with_ancestors<'a>(
+        &'a self,
+        entry: &'a MempoolEntry,
+        included: &mut HashSet<TxID>,
+        result: &mut Vec<&'a VerifiedTx>,
+    ) {
+        if included.contains(&entry.tx.id) {
+            return;
+        }
+        for parent in self.parents(entry) {
+            self.include_with_ancestors(parent, included, result);
+        }
+        included.insert(entry.tx.id);
+        result.push(&entry.tx);
+    }
+
+    /// Selects the transactions replaced by the new entry: the transactions spending
+    /// the same outputs or publishing the same nullifiers, together with their descendants.
+    fn select_replacements(&self, entry: &MempoolEntry) -> Result<Vec<TxID>, BlockchainError> {
+        let conflicts = entry
+            .inputs
+            .iter()
+            .filter_map(|input| self.spent.get(input))
+            .map(|txid| (txid, BlockchainError::DoubleSpend))
+            .chain(
+                entry
+                    .nullifiers
+                    .iter()
+                    .filter_map(|tag| self.nullifiers.get(tag))
+                    .map(|txid| (txid, BlockchainError::DuplicateNullifier)),
+            );
+
+        let mut replaced = Vec::new();
+        let mut replaced_set = HashSet::new();
+        let mut replaced_fee = 0u64;
+        for (txid, err) in conflicts {
+            let conflict = &self.entries[txid];
+            if entry.tx.feerate <= conflict.tx.feerate {
+                return Err(err);
+            }
+            let mut stack = vec![conflict];
+            while let Some(e) = stack.pop() {
+                if replaced_set.insert(e.tx.id) {
+                    replaced.push(e.tx.id);
+                    replaced_fee = replaced_fee.saturating_add(e.tx.feerate.fee());
+                    stack.extend(self.children(e));
+                }
+            }
+        }
+
+        // Replacement cannot spend the outputs of the transactions it replaces.
+        if self
+            .parents(entry)
+            .any(|parent| replaced_set.contains(&parent.tx.id))
+        {
+            return Err(BlockchainError::DoubleSpend);
+        }
+        // Replacement pays for relaying the transactions it replaces,
+        // so they cannot be replaced repeatedly at no cost.
+        if entry.tx.feerate.fee() < replaced_fee {
+            return Err(BlockchainError::FeeTooLow);
+        }
+        Ok(replaced)
+    }
+
+    /// Selects the lowest-priority transactions that must be evicted
+    /// to make room for the new entry after the replaced transactions are removed.
+    fn select_evictions(
+        &self,
+        entry: &MempoolEntry,
+        replaced: &[TxID],
+    ) -> Result<Vec<TxID>, BlockchainError> {
+        let tx_size = entry.tx.feerate.size();
+        if tx_size > self.max_size {
+            return Err(BlockchainError::FeeTooLow);
+        }
+        let replaced: HashSet<TxID> = replaced.iter().cloned().collect();
+        let size = self.size
+            - replaced
+                .iter()
+                .map(|txid| self.entries[txid].tx.feerate.size())
+                .sum::<u64>();
+        if size + tx_size <= self.max_size {
+            return Ok(Vec::new());
+        }
+        let needed = size + tx_size - self.max_size;
+
+        // Ancestors of the new transaction cannot be evicted.
+        let mut ancestors = HashSet::new();
+        let mut stack: Vec<&MempoolEntry> = self.parents(entry).collect();
+        while let Some(e) = stack.pop() {
+            if ancestors.insert(e.tx.id) {
+                stack.extend(self.parents(e));
+            }
+        }
+
+        // Transactions are evicted with their descendants, so they are ordered
+        // by the fee rate of the transaction together with its descendants.
+        let mut candidates: Vec<(FeeRate, &MempoolEntry)> = self
+            .entries
+            .values()
+            .filter(|e| !ancestors.contains(&e.tx.id) && !replaced.contains(&e.tx.id))
+            .map(|e| (package_feerate(&self.descendants(e)), e))
+            .collect();
+        candidates.sort_by_key(|(feerate, e)| (*feerate, e.seq));
+
+        let mut evicted = Vec::new();
+        let mut evicted_set = HashSet::new();
+        let mut freed = 0u64;
+        for (_, candidate) in candidates {
+            if freed >= needed {
+                break;
+            }
+            if evicted_set.contains(&candidate.tx.id) {
+                continue;
+            }
+            // The candidate is evicted together with its descendants,
+            // none of which may pay the same or higher fee rate.
+            let mut descendants = HashSet::new();
+            let mut stack = vec![candidate];
+            while let Some(e) = stack.pop() {
+                if replaced.contains(&e.tx.id)
+                    || evicted_set.contains(&e.tx.id)
+                    || !descendants.insert(e.tx.id)
+                {
+                    continue;
+                }
+                if e.tx.feerate >= entry.tx.feerate {
+                    return Err(BlockchainError::FeeTooLow);
+                }
+                freed += e.tx.feerate.size();
+                stack.extend(self.children(e));
+            }
+            evicted.push(candidate.tx.id);
+            evicted_set.extend(descendants);
+        }
+        if freed < needed {
+            return Err(BlockchainError::FeeTooLow);
+        }
+        Ok(evicted)
+    }
+
+    fn parents<'a>(&'a self, entry: &'a MempoolEntry) -> impl Iterator<Item = &'a MempoolEntry> {
+        entry
+            .inputs
+            .iter()
+            .filter_map(move |input| self.created.get(input))
+            .filter_map(move |txid| self.entries.get(txid))
+    }
+
+    fn children<'a>(&'a self, entry: &'a MempoolEntry) -> impl Iterator<Item = &'a MempoolEntry> {
+        entry
+            .outputs
+            .iter()
+            .filter_map(move |output| self.spent.get(output))
+            .filter_map(move |txid| self.entries.get(txid))
+    }
+
+    fn remove_with_descendants(&mut self, txid: &TxID, removed: &mut Vec<TxID>) {
+        if let Some(entry) = self.remove_entry(txid) {
+            removed.push(entry.tx.id);
+            for output in entry.outputs.iter() {
+                if let Some(child) = self.spent.get(output).cloned() {
+                    self.remove_with_descendants(&child, removed);
+                }
+            }
+        }
+    }
+
+    fn remove_entry(&mut self, txid: &TxID) -> Option<MempoolEntry> {
+        let entry = self.entries.remove(txid)?;
+        for input in entry.inputs.iter() {
+            self.spent.remove(input);
+        }
+        for output in entry.outputs.iter() {
+            self.created.remove(output);
+        }
+        for tag in entry.nullifiers.iter() {
+            self.nullifiers.remove(tag);
+        }
+        self.size -= entry.tx.feerate.size();
+        Some(entry)
+    }
+}
+
+/// Returns the outputs spent and created by the transaction.
+fn utxos(tx: &VerifiedTx) -> (Vec<ContractID>, Vec<ContractID>) {
+    let mut inputs = Vec::new();
+    let mut outputs = Vec::new();
+    for entry in tx.log.iter() {
+        match entry {
+            Entry::Input(id) => inputs.push(*id),
+            Entry::Output(output) => outputs.push(output.id()),
+            _ => {}
+        }
+    }
+    (inputs, outputs)
+}
+
+/// Returns the fee rate of the transactions taken together.
+fn package_feerate(entries: &[&MempoolEntry]) -> FeeRate {
+    let fee = entries
+        .iter()
+        .fold(0u64, |fee, e| fee.saturating_add(e.tx.feerate.fee()));
+    let size = entries.iter().map(|e| e.tx.feerate.size()).sum::<u64>();
+    FeeRate::new(fee, size as usize)
+}
+
+/// Returns the nullifiers published by the transaction.
+fn nullifiers(tx: &VerifiedTx) -> Vec<[u8; 32]> {
+    tx.log
+        .iter()
+        .filter_map(|entry| match entry {
+            Entry::Nullifier(tag) => Some(*tag),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::spend;
+    use zkvm::WTxID;
+
+    /// Creates a transaction spending and creating utxos with given numbers,
+    /// paying the fee for its size and expiring after `1000 * id` ms.
+    fn make_tx(id: u8, inputs: &[u8], outputs: &[u8], fee: u64, size: usize) -> VerifiedTx {
+        let mut tx = spend(id, inputs, outputs);
+        tx.header.maxtime = 1000 * (id as u64);
+        tx.feerate = FeeRate::new(fee, size);
+        tx
+    }
+
+    fn ids(txs: Vec<&VerifiedTx>) -> Vec<u8> {
+        txs.into_iter().map(|tx| tx.id.0[0]).collect()
+    }
+
+    #[test]
+    fn rejects_conflicts() {
+        let mut pool = Mempool::new(10_000);
+        pool.append(make_tx(1, &[0], &[10], 100, 100)).unwrap();
+        assert_eq!(
+            pool.append(make_tx(1, &[1], &[], 100, 100)),
+            Err(BlockchainError::DuplicateTransaction)
+        );
+        assert_eq!(
+            pool.append(make_tx(2, &[0], &[], 100, 100)),
+            Err(BlockchainError::DoubleSpend)
+        );
+        assert_eq!(pool.len(), 1);
+        assert_eq!(pool.size(), 100);
+
+        // Only the admitted transaction is remembered as verified.
+        assert!(pool.verification_cache().contains(&WTxID([1; 32])));
+        assert!(!pool.verification_cache().contains(&WTxID([2; 32])));
+
+        let with_nullifier = |id: u8, input: u8| {
+            let mut tx = make_tx(id, &[input], &[], 100, 100);
+            tx.log.push(Entry::Nullifier([1; 32]));
+            tx
+        };
+        pool.append(with_nullifier(3, 1)).unwrap();
+        assert_eq!(
+            pool.append(with_nullifier(4, 2)),
+            Err(BlockchainError::DuplicateNullifier)
+        );
+
+        // Confirmed nullifier removes the conflicting transaction.
+        assert_eq!(
+            pool.remove_confirmed(&[with_nullifier(4, 2)]),
+            vec![TxID([3; 32])]
+        );
+        assert_eq!(pool.len(), 1);
+    }
+
+    #[test]
+    fn orders_candidates() {
+        let mut pool = Mempool::new(10_000);
+        pool.append(make_tx(1, &[0], &[10], 100, 100)).unwrap();
+        pool.append(make_tx(2, &[1], &[], 300, 100)).unwrap();
+        pool.append(make_tx(3, &[2], &[], 100, 100)).unwrap();
+        // Child with a high fee rate follows its parent.
+        pool.append(make_tx(4, &[10], &[], 500, 100)).unwrap();
+        assert_eq!(ids(pool.candidates()), vec![1, 4, 2, 3]);
+    }
+
+    #[test]
+    fn replaces_by_fee() {
+        let mut pool = Mempool::new(10_000);
+        pool.append(make_tx(1, &[0], &[10], 100, 100)).unwrap();
+        pool.append(make_tx(2, &[10], &[], 100, 100)).unwrap();
+
+        // Higher fee rate, but less than the total fee of tx 1 and its child.
+        assert_eq!(
+            pool.append(make_tx(3, &[0], &[], 150, 100)),
+            Err(BlockchainError::FeeTooLow)
+        );
+
+        let replaced = pool.append(make_tx(4, &[0], &[11], 300, 100)).unwrap();
+        assert_eq!(replaced, vec![TxID([1; 32]), TxID([2; 32])]);
+        assert_eq!(ids(pool.candidates()), vec![4]);
+        assert_eq!(pool.size(), 100);
+
+        // Replacement cannot spend the outputs of the transaction it replaces.
+        assert_eq!(
+            pool.append(make_tx(5, &[0, 11], &[], 1000, 100)),
+            Err(BlockchainError::DoubleSpend)
+        );
+        assert_eq!(pool.len(), 1);
+    }
+
+    #[test]
+    fn package_feerates() {
+        let mut pool = Mempool::new(10_000);
+        pool.append(make_tx(1, &[0], &[10], 0, 1000)).unwrap();
+        pool.append(make_tx(2, &[1], &[], 100, 100)).unwrap();
+        pool.append(make_tx(3, &[10], &[11], 500, 100)).unwrap();
+        pool.append(make_tx(4, &[11], &[], 300, 100)).unwrap();
+
+        assert_eq!(
+            pool.ancestor_feerate(&TxID([3; 32])),
+            Some(FeeRate::new(500, 1100))
+        );
+        assert_eq!(
+            pool.descendant_feerate(&TxID([1; 32])),
+            Some(FeeRate::new(800, 1200))
+        );
+        assert_eq!(pool.descendant_feerate(&TxID([5; 32])), None);
+
+        // Tx 4 alone pays more per byte than tx 2, but less together with its ancestors.
+        assert_eq!(ids(pool.candidates()), vec![2, 1, 3, 4]);
+
+        // Higher fee of the child pulls the whole package ahead.
+        pool.remove_confirmed(&[make_tx(4, &[11], &[], 300, 100)]);
+        pool.append(make_tx(5, &[11], &[], 1600, 100)).unwrap();
+        assert_eq!(ids(pool.candidates()), vec![1, 3, 5, 2]);
+    }
+
+    #[test]
+    fn evicts_lowest_feerate() {
+        let mut pool = Mempool::new(300);
+        pool.append(make_tx(1, &[0], &[10], 100, 100)).unwrap();
+        pool.append(make_tx(2, &[10], &[], 500, 100)).unwrap();
+        pool.append(make_tx(3, &[2], &[], 200, 100)).unwrap();
+
+        // Does not displace transactions with a higher fee rate.
+        assert_eq!(
+            pool.append(make_tx(4, &[3], &[], 50, 100)),
+            Err(BlockchainError::FeeTooLow)
+        );
+
+        // Tx 1 alone pays the least per byte, but its child raises the rate of the package above tx 3.
+        assert_eq!(
+            pool.append(make_tx(5, &[4], &[], 300, 100)),
+            Ok(vec![TxID([3; 32])])
+        );
+
+        // Parent cannot be evicted without its child with a higher fee rate.
+        assert_eq!(
+            pool.append(make_tx(6, &[5], &[], 600, 150)),
+            Err(BlockchainError::FeeTooLow)
+        );
+        assert_eq!(pool.len(), 3);
+
+        // Evicts the parent together with its child.
+        let evicted = pool.append(make_tx(7, &[6], &[], 900, 150)).unwrap();
+        assert_eq!(evicted.len(), 2);
+        assert!(!pool.contains(&TxID([1; 32])));
+        assert!(!pool.contains(&TxID([2; 32])));
+        assert_eq!(ids(pool.candidates()), vec![7, 5]);
+        assert_eq!(pool.size(), 250);
+    }
+
+    #[test]
+    fn removes_confirmed_and_expired() {
+        let mut pool = Mempool::new(10_000);
+        pool.append(make_tx(1, &[0], &[10], 100, 100)).unwrap();
+        pool.append(make_tx(2, &[10], &[], 100, 100)).unwrap();
+        pool.append(make_tx(3, &[1], &[11], 100, 100)).unwrap();
+        pool.append(make_tx(4, &[11], &[], 100, 100)).unwrap();
+        pool.append(make_tx(5, &[2], &[], 100, 100)).unwrap();
+
+        // Block confirms tx 1 and a tx conflicting with tx 3.
+        let removed = pool.remove_confirmed(&[
+            make_tx(1, &[0], &[10], 100, 100),
+            make_tx(6, &[1], &[], 100, 100),
+        ]);
+        assert_eq!(removed.len(), 2);
+        assert_eq!(ids(pool.candidates()), vec![2, 5]);
+
+        assert_eq!(pool.expire(4500), vec![TxID([2; 32])]);
+        assert_eq!(pool.len(), 1);
+    }
+}
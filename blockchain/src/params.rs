@@ -0,0 +1,81 @@
+//! Consensus parameters of a network.
+//!
+//! The defaults are the parameters of the main network. Testnets and private deployments
+//! can tune the limits and the version rules without changing the validation code.
+
+use curve25519_dalek::scalar::Scalar;
+use zkvm::VMParams;
+
+use crate::block::BlockID;
+
+/// Consensus parameters used to validate the blocks and the transactions.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChainParams {
+    /// ID of the initial block of the network, or `None` to accept any initial block.
+    pub genesis_id: Option<BlockID>,
+
+    /// Maximum size of the serialized block in bytes.
+    pub max_block_size: usize,
+
+    /// Maximum size of the serialized transaction in bytes.
+    pub max_tx_size: usize,
+
+    /// Maximum number of the nested programs executed by the VM (see `VMParams`).
+    pub max_program_depth: usize,
+
+    /// Versions of the blocks and the transactions.
+    pub version_rules: VersionRules,
+
+    /// Flavor of the values paid with the `fee` instruction.
+    pub fee_flavor: Scalar,
+}
+
+/// Rules for the versions of the blocks and the transactions in them.
+#[derive(Clone, Debug, PartialEq)]
+pub struct VersionRules {
+    /// Lowest allowed version of the blocks.
+    pub min_block_version: u64,
+
+    /// Version of the blocks from which the headers commit to the state hash
+    /// in the `ext` field (see `BlockchainState::state_hash`).
+    /// Blocks of the earlier versions have empty `ext`
+    /// and contain only the transactions of the version `tx_version`.
+    pub state_hash_version: u64,
+
+    /// Version of the transactions in the blocks before `state_hash_version`.
+    pub tx_version: u64,
+}
+
+impl Default for ChainParams {
+    fn default() -> Self {
+        let vm_params = VMParams::default();
+        ChainParams {
+            genesis_id: None,
+            max_block_size: usize::max_value(),
+            max_tx_size: usize::max_value(),
+            max_program_depth: vm_params.max_program_depth,
+            version_rules: VersionRules::default(),
+            fee_flavor: vm_params.fee_flavor,
+        }
+    }
+}
+
+impl Default for VersionRules {
+    fn default() -> Self {
+        VersionRules {
+            min_block_version: 1,
+            state_hash_version: 2,
+            tx_version: 1,
+        }
+    }
+}
+
+impl ChainParams {
+    /// Returns the parameters of the VM that verifies the transactions.
+    pub fn vm_params(&self) -> VMParams {
+        VMParams {
+            max_program_depth: self.max_program_depth,
+            fee_flavor: self.fee_flavor,
+        }
+    }
+}
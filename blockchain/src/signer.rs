@@ -0,0 +1,259 @@
+//! Block signing: the consensus rule that authorizes new blocks with signatures in their headers.
+//!
+//! The signature follows the state hash in the `ext` field of a version 2 header,
+//! and signs the ID of the header without the signature.
+//! `Committee` is a reference scheme for federated chains: a quorum of the committee members
+//! signs each header with a single aggregated MuSig signature.
+
+use curve25519_dalek::ristretto::CompressedRistretto;
+use curve25519_dalek::scalar::Scalar;
+use merlin::Transcript;
+use zkvm::signature::musig::{Multikey, Signature};
+use zkvm::signature::Party;
+use zkvm::VerificationKey;
+
+use crate::block::BlockHeader;
+use crate::errors::BlockchainError;
+
+/// Scheme of block signatures that decides which block headers are authorized.
+pub trait BlockSigner {
+    /// Verifies that the header carries a valid signature.
+    fn verify(&self, header: &BlockHeader) -> Result<(), BlockchainError>;
+}
+
+/// Committee of keys, a quorum of which must sign each block.
+#[derive(Clone, Debug)]
+pub struct Committee {
+    keys: Vec<VerificationKey>,
+    quorum: usize,
+}
+
+impl Committee {
+    /// Creates a committee of the keys that requires the signatures of `quorum` members.
+    /// Fails if the quorum is zero or larger than the committee.
+    pub fn new(keys: Vec<VerificationKey>, quorum: usize) -> Result<Self, BlockchainError> {
+        if quorum == 0 || quorum > keys.len() {
+            return Err(BlockchainError::InvalidQuorum);
+        }
+        Ok(Committee { keys, quorum })
+    }
+
+    /// Returns the keys of the committee members.
+    pub fn keys(&self) -> &[VerificationKey] {
+        &self.keys
+    }
+
+    /// Returns the number of members that must sign a block.
+    pub fn quorum(&self) -> usize {
+        self.quorum
+    }
+
+    /// Returns the transcript with the message signed by the committee:
+    /// the ID of the header without the signature.
+    pub fn signing_transcript(header: &BlockHeader) -> Transcript {
+        let mut unsigned = header.clone();
+        unsigned.ext.truncate(32);
+        let mut t = Transcript::new(b"ZkVM.blocksignature");
+        t.commit_bytes(b"header", &unsigned.id().0);
+        t
+    }
+
+    /// Aggregates the keys of the signing members, listed by their indices in ascending order.
+    pub fn multikey(&self, signers: &[usize]) -> Result<Multikey, BlockchainError> {
+        if signers.windows(2).any(|w| w[0] >= w[1]) || signers.iter().any(|i| *i >= self.keys.len())
+        {
+            return Err(BlockchainError::InvalidBlockSignature);
+        }
+        Multikey::new(signers.iter().map(|i| self.keys[*i]).collect())
+            .map_err(|_| BlockchainError::InvalidBlockSignature)
+    }
+
+    /// Stores the aggregated signature of the signing members in the header,
+    /// replacing the previous signature. Signers are listed as in `multikey`.
+    pub fn seal(
+        &self,
+        header: &mut BlockHeader,
+        signers: &[usize],
+        signature: &Signature,
+    ) -> Result<(), BlockchainError> {
+        if header.version < 2 || header.ext.len() < 32 {
+            return Err(BlockchainError::InvalidBlockSignature);
+        }
+        let mut mask = vec![0u8; self.mask_len()];
+        for i in signers.iter() {
+            if *i >= self.keys.len() {
+                return Err(BlockchainError::InvalidBlockSignature);
+            }
+            mask[i / 8] |= 1 << (i % 8);
+        }
+        header.ext.truncate(32);
+        header.ext.extend_from_slice(&mask);
+        header.ext.extend_from_slice(signature.R.as_bytes());
+        header.ext.extend_from_slice(signature.s.as_bytes());
+        Ok(())
+    }
+
+    /// Signs the header with the secret keys of the members, given with their indices,
+    /// running the MuSig protocol locally.
+    /// Members held by different parties run the protocol with `signing_transcript`
+    /// and `multikey`, and store the signature with `seal`.
+    pub fn sign(
+        &self,
+        header: &mut BlockHeader,
+        secrets: &[(usize, Scalar)],
+    ) -> Result<(), BlockchainError> {
+        let mut secrets = secrets.to_vec();
+        secrets.sort_by_key(|(i, _)| *i);
+        let signers = secrets.iter().map(|(i, _)| *i).collect::<Vec<_>>();
+        let multikey = self.multikey(&signers)?;
+        let pubkeys = signers.iter().map(|i| self.keys[*i]).collect::<Vec<_>>();
+        if secrets
+            .iter()
+            .zip(pubkeys.iter())
+            .any(|((_, secret), key)| VerificationKey::from_secret(secret) != *key)
+        {
+            return Err(BlockchainError::InvalidBlockSignature);
+        }
+
+        let transcript = Self::signing_transcript(header);
+        let mut transcripts = vec![transcript; secrets.len()];
+        let (parties, precommitments): (Vec<_>, Vec<_>) = secrets
+            .iter()
+            .zip(transcripts.iter_mut())
+            .map(|((_, secret), t)| Party::new(t, *secret, multikey.clone(), pubkeys.clone()))
+            .unzip();
+        let (parties, commitments): (Vec<_>, Vec<_>) = parties
+            .into_iter()
+            .map(|p| p.receive_precommitments(precommitments.clone()))
+            .unzip();
+        let (parties, shares): (Vec<_>, Vec<_>) = parties
+            .into_iter()
+            .map(|p| p.receive_commitments(commitments.clone()))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| BlockchainError::InvalidBlockSignature)?
+            .into_iter()
+            .unzip();
+        let signature = parties
+            .into_iter()
+            .next()
+            .ok_or(BlockchainError::InvalidBlockSignature)?
+            .receive_shares(shares)
+            .map_err(|_| BlockchainError::InvalidBlockSignature)?;
+
+        self.seal(header, &signers, &signature)
+    }
+
+    fn mask_len(&self) -> usize {
+        (self.keys.len() + 7) / 8
+    }
+}
+
+impl BlockSigner for Committee {
+    /// Verifies that the header is signed by at least a quorum of the committee.
+    fn verify(&self, header: &BlockHeader) -> Result<(), BlockchainError> {
+        let seal = header
+            .ext
+            .get(32..)
+            .ok_or(BlockchainError::InvalidBlockSignature)?;
+        let mask_len = self.mask_len();
+        if header.version < 2 || seal.len() != mask_len + 64 {
+            return Err(BlockchainError::InvalidBlockSignature);
+        }
+        let (mask, signature) = seal.split_at(mask_len);
+
+        let signers = (0..mask_len * 8)
+            .filter(|i| mask[i / 8] & (1 << (i % 8)) != 0)
+            .collect::<Vec<_>>();
+        if signers.len() < self.quorum {
+            return Err(BlockchainError::InvalidBlockSignature);
+        }
+        let multikey = self.multikey(&signers)?;
+
+        let mut s = [0u8; 32];
+        s.copy_from_slice(&signature[32..]);
+        let signature = Signature {
+            R: CompressedRistretto::from_slice(&signature[..32]),
+            s: Scalar::from_canonical_bytes(s).ok_or(BlockchainError::InvalidBlockSignature)?,
+        };
+        signature
+            .verify(
+                &mut Self::signing_transcript(header),
+                multikey.aggregated_key(),
+            )
+            .map_err(|_| BlockchainError::InvalidBlockSignature)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::BlockID;
+
+    fn header() -> BlockHeader {
+        BlockHeader {
+            version: 2,
+            height: 2,
+            prev_id: BlockID([1u8; 32]),
+            timestamp_ms: 1000,
+            txroot: [2u8; 32],
+            utxoroot: [3u8; 32],
+            ext: vec![4u8; 32],
+        }
+    }
+
+    #[test]
+    fn quorum_signatures() {
+        let secrets = (0..4u64)
+            .map(|i| (i as usize, Scalar::from(i + 1)))
+            .collect::<Vec<_>>();
+        let keys = secrets
+            .iter()
+            .map(|(_, secret)| VerificationKey::from_secret(secret))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            Committee::new(keys.clone(), 5).map(|_| ()),
+            Err(BlockchainError::InvalidQuorum)
+        );
+        let committee = Committee::new(keys, 3).unwrap();
+
+        let mut signed = header();
+        committee
+            .sign(&mut signed, &[secrets[3], secrets[0], secrets[1]])
+            .unwrap();
+        assert_eq!(signed.ext[..32], header().ext[..]);
+        assert_eq!(committee.verify(&signed), Ok(()));
+
+        // Signature does not cover another header.
+        let mut other = signed.clone();
+        other.timestamp_ms += 1;
+        assert_eq!(
+            committee.verify(&other),
+            Err(BlockchainError::InvalidBlockSignature)
+        );
+
+        // Claiming another set of signers invalidates the signature.
+        let mut other = signed.clone();
+        other.ext[32] |= 1 << 2;
+        assert_eq!(
+            committee.verify(&other),
+            Err(BlockchainError::InvalidBlockSignature)
+        );
+
+        // Fewer signers than the quorum.
+        let mut other = header();
+        committee
+            .sign(&mut other, &[secrets[0], secrets[2]])
+            .unwrap();
+        assert_eq!(
+            committee.verify(&other),
+            Err(BlockchainError::InvalidBlockSignature)
+        );
+
+        // Secret key of another member.
+        let mut other = header();
+        assert_eq!(
+            committee.sign(&mut other, &[(0, Scalar::from(2u64))]),
+            Err(BlockchainError::InvalidBlockSignature)
+        );
+    }
+}
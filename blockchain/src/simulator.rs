@@ -0,0 +1,524 @@
+//! In-memory chain for the integration tests of wallets and contracts (enabled by the `simulator` feature).
+//!
+//! The simulator keeps the blockchain state with its bridge, the mempool and a clock. Blocks are produced instantly
+//! from the mempool with `mine_block`, and the clock can be moved forward with `warp`
+//! to test the time bounds of the transactions and the time-locked contracts.
+//!
+//! Test accounts are funded by a faucet that issues a test asset, which is also the fee asset
+//! of the simulated network. The simulator remembers the outputs (with their secret openings)
+//! of the transactions it built, so the tests can spend them after they are confirmed.
+
+use bulletproofs::BulletproofGens;
+use curve25519_dalek::scalar::Scalar;
+use merlin::Transcript;
+use std::collections::HashMap;
+use zkvm::{
+    ContractID, Data, DeferredTx, Entry, Output, PortableItem, Predicate, Program, Prover,
+    ScalarWitness, Signature, Tx, TxHeader, TxID, TxIntent, Value, VerificationKey, VerifiedTx,
+    Verifier,
+};
+
+use crate::block::{Block, BlockHeader, VerifiedBlock};
+use crate::bridge::Bridge;
+use crate::errors::BlockchainError;
+use crate::mempool::Mempool;
+use crate::params::ChainParams;
+use crate::signer::{BlockSigner, Committee};
+use crate::state::BlockchainState;
+use crate::template::BlockTemplate;
+
+/// Metadata of the test asset issued by the faucet.
+const TEST_ASSET: &[u8] = b"simulator/test-asset";
+
+/// Account controlled by a single key, funded by the simulator's faucet.
+#[derive(Clone, Debug)]
+pub struct TestAccount {
+    /// Secret key of the account.
+    pub secret: Scalar,
+
+    /// Predicate of the account's outputs.
+    pub predicate: Predicate,
+}
+
+/// In-memory chain with instant block production.
+pub struct Simulator {
+    state: BlockchainState,
+    bridge: Bridge,
+    mempool: Mempool,
+    bp_gens: BulletproofGens,
+    now_ms: u64,
+    block_interval_ms: u64,
+    // transactions in the mempool
+    txs: HashMap<TxID, Tx>,
+    // outputs with the witness data of the transactions in the mempool
+    pending: HashMap<TxID, Vec<Output>>,
+    // confirmed unspent outputs with the witness data
+    utxos: HashMap<ContractID, Output>,
+    // applied blocks, the oldest first
+    blocks: Vec<VerifiedBlock>,
+    faucet: Scalar,
+    // number of the derived keys
+    keys_count: u64,
+    // committee signing the blocks with the secret keys of its members, by their indices
+    committee: Option<(Committee, Vec<(usize, Scalar)>)>,
+}
+
+impl Simulator {
+    /// Creates a chain whose initial block has the given timestamp.
+    /// The test asset is the fee asset of the chain.
+    pub fn new(timestamp_ms: u64) -> Self {
+        let mut params = ChainParams::default();
+        params.fee_flavor = test_flavor(&faucet_predicate());
+        Self::with_params(timestamp_ms, params)
+            .expect("default parameters accept any initial block")
+    }
+
+    /// Creates a chain with the consensus parameters.
+    /// Fails if the parameters specify another initial block.
+    pub fn with_params(timestamp_ms: u64, params: ChainParams) -> Result<Self, BlockchainError> {
+        let state = BlockchainState::make_initial(timestamp_ms, 64, 64).with_params(params)?;
+        let bridge = Bridge::new(&state)?;
+        Ok(Simulator {
+            state,
+            bridge,
+            mempool: Mempool::new(u64::max_value()),
+            bp_gens: BulletproofGens::new(4096, 1),
+            now_ms: timestamp_ms,
+            block_interval_ms: 1000,
+            txs: HashMap::new(),
+            pending: HashMap::new(),
+            utxos: HashMap::new(),
+            blocks: Vec::new(),
+            faucet: faucet_secret(),
+            keys_count: 0,
+            committee: None,
+        })
+    }
+
+    /// Returns the blockchain state at the tip.
+    pub fn state(&self) -> &BlockchainState {
+        &self.state
+    }
+
+    /// Returns the bridge with the utreexo proofs of the unspent outputs at the tip.
+    pub fn bridge(&self) -> &Bridge {
+        &self.bridge
+    }
+
+    /// Returns the header of the latest block.
+    pub fn tip(&self) -> &BlockHeader {
+        self.state.tip()
+    }
+
+    /// Returns the mempool.
+    pub fn mempool(&self) -> &Mempool {
+        &self.mempool
+    }
+
+    /// Returns the generators used to build and verify the transactions.
+    pub fn bp_gens(&self) -> &BulletproofGens {
+        &self.bp_gens
+    }
+
+    /// Returns the blocks applied after the initial one, the oldest first.
+    pub fn blocks(&self) -> &[VerifiedBlock] {
+        &self.blocks
+    }
+
+    /// Returns the current time of the simulator's clock in milliseconds.
+    pub fn now(&self) -> u64 {
+        self.now_ms
+    }
+
+    /// Sets the time by which the clock moves forward for each mined block.
+    pub fn set_block_interval(&mut self, interval_ms: u64) {
+        self.block_interval_ms = interval_ms;
+    }
+
+    /// Makes the committee sign the mined blocks with the secret keys of the given members.
+    /// The blocks are applied only if the signatures meet the committee's quorum,
+    /// so the tests of the federated chains can mine with too few signers.
+    /// Signed blocks are of the state hash version or later.
+    pub fn set_committee(&mut self, committee: Committee, secrets: &[(usize, Scalar)]) {
+        self.committee = Some((committee, secrets.to_vec()));
+    }
+
+    /// Moves the clock forward without producing blocks.
+    pub fn warp(&mut self, duration_ms: u64) {
+        self.now_ms += duration_ms;
+    }
+
+    /// Moves the clock to the given time. The clock never moves backwards,
+    /// so an earlier time is ignored.
+    pub fn warp_to(&mut self, timestamp_ms: u64) {
+        self.now_ms = self.now_ms.max(timestamp_ms);
+    }
+
+    /// Returns the flavor of the test asset issued by the faucet.
+    pub fn test_flavor(&self) -> Scalar {
+        test_flavor(&faucet_predicate())
+    }
+
+    /// Returns a header for the new transactions, valid from now on without an upper time bound.
+    pub fn tx_header(&self) -> TxHeader {
+        TxHeader {
+            version: self.state.params().version_rules.tx_version,
+            mintime: self.now_ms,
+            maxtime: u64::max_value(),
+            maxcost: u64::max_value(),
+        }
+    }
+
+    /// Generates a new key for a contract or an account. The keys are derived deterministically,
+    /// so the simulated chains are reproducible.
+    pub fn generate_key(&mut self) -> Scalar {
+        let mut t = Transcript::new(b"ZkVM.simulator");
+        t.append_u64(b"key", self.keys_count);
+        self.keys_count += 1;
+        let mut buf = [0u8; 64];
+        t.challenge_bytes(b"secret", &mut buf);
+        Scalar::from_bytes_mod_order_wide(&buf)
+    }
+
+    /// Creates an account with `qty` units of the test asset and mines the block that funds it.
+    pub fn create_account(&mut self, qty: u64) -> Result<TestAccount, BlockchainError> {
+        let secret = self.generate_key();
+        let account = TestAccount {
+            secret,
+            predicate: Predicate::Key(VerificationKey::from_secret(&secret)),
+        };
+        self.fund(&account.predicate, qty)?;
+        self.mine_block()?;
+        Ok(account)
+    }
+
+    /// Issues `qty` units of the test asset to the predicate and adds the transaction
+    /// to the mempool. The output is spendable once the transaction is mined.
+    pub fn fund(&mut self, predicate: &Predicate, qty: u64) -> Result<TxID, BlockchainError> {
+        // Each issuance is made unique with a nonce signed by a fresh key.
+        let nonce_key = self.generate_key();
+        let mut seed = [0u8; 32];
+        seed.copy_from_slice(nonce_key.as_bytes());
+        let program = TxIntent::new(self.tx_header(), seed)
+            .nonce(
+                Predicate::Key(VerificationKey::from_secret(&nonce_key)),
+                self.state.tip().id().0,
+            )
+            .issue(qty, faucet_predicate(), TEST_ASSET.to_vec())
+            .output(qty, self.test_flavor(), predicate.clone())
+            .compile()
+            .map_err(BlockchainError::TxValidation)?;
+        let faucet = self.faucet;
+        self.submit_program(program, &[faucet, nonce_key])
+    }
+
+    /// Builds the transaction with the program, signs it with the keys
+    /// and adds it to the mempool. The signing keys are matched to the verification keys
+    /// of the `signtx` instructions, so they may be listed in any order.
+    pub fn submit_program(
+        &mut self,
+        program: Program,
+        keys: &[Scalar],
+    ) -> Result<TxID, BlockchainError> {
+        let (tx, txid, txlog) = Prover::build_tx_with_params(
+            program,
+            self.tx_header(),
+            &self.state.params().vm_params(),
+            &self.bp_gens,
+            |t, verification_keys| {
+                let keys = verification_keys
+                    .iter()
+                    .filter_map(|vk| {
+                        keys.iter()
+                            .find(|k| VerificationKey::from_secret(k) == *vk)
+                            .cloned()
+                    })
+                    .collect::<Vec<_>>();
+                Signature::sign_aggregated(t, &keys)
+            },
+        )
+        .map_err(BlockchainError::TxValidation)?;
+        self.submit_tx(tx)?;
+        let outputs = txlog
+            .into_iter()
+            .filter_map(|entry| match entry {
+                Entry::Output(output) => Some(output),
+                _ => None,
+            })
+            .collect();
+        self.pending.insert(txid, outputs);
+        Ok(txid)
+    }
+
+    /// Verifies the transaction, including its signature and the other point operations,
+    /// and adds it to the mempool. An invalid transaction is rejected here rather than in `mine_block`.
+    pub fn submit_tx(&mut self, tx: Tx) -> Result<TxID, BlockchainError> {
+        let vm_params = self.state.params().vm_params();
+        let verified =
+            Verifier::verify_tx_with_params(tx.clone(), &vm_params, &self.bp_gens, |_| false)
+                .and_then(DeferredTx::verify)
+                .map_err(BlockchainError::TxValidation)?;
+        let id = verified.id;
+        let evicted = self.mempool.append(verified)?;
+        for txid in evicted.iter() {
+            self.forget(txid);
+        }
+        self.txs.insert(id, tx);
+        Ok(id)
+    }
+
+    /// Moves the clock forward by the block interval and mines a block
+    /// with the mempool transactions valid at that time, signed by the committee if it is set.
+    pub fn mine_block(&mut self) -> Result<&VerifiedBlock, BlockchainError> {
+        let timestamp_ms = (self.now_ms + self.block_interval_ms).max(self.tip().timestamp_ms + 1);
+        let template = BlockTemplate::build(
+            &self.state,
+            &self.bridge,
+            &self.mempool,
+            u64::max_value(),
+            timestamp_ms,
+        )?;
+        let txs = template
+            .txids
+            .iter()
+            .map(|txid| self.txs[txid].clone())
+            .collect();
+        let mut header = template.header;
+        if let Some((committee, secrets)) = &self.committee {
+            let rules = &self.state.params().version_rules;
+            if header.version < rules.state_hash_version {
+                header.version = rules.state_hash_version;
+                header.ext = self.state.state_hash().to_vec();
+            }
+            committee.sign(&mut header, secrets)?;
+            committee.verify(&header)?;
+        }
+        let block = Block { header, txs };
+        let verified = self.state.apply_block_with_cache(
+            block,
+            &self.bridge,
+            self.mempool.verification_cache(),
+            &self.bp_gens,
+        )?;
+        self.bridge.apply_block(&verified)?;
+        self.now_ms = timestamp_ms;
+
+        for tx in verified.txs.iter() {
+            self.confirm(tx);
+        }
+        let removed = self
+            .mempool
+            .remove_confirmed(&verified.txs)
+            .into_iter()
+            .chain(self.mempool.expire(timestamp_ms))
+            .collect::<Vec<_>>();
+        for txid in removed.iter() {
+            self.forget(txid);
+        }
+        self.blocks.push(verified);
+        Ok(self.blocks.last().expect("the block was just added"))
+    }
+
+    /// Mines `n` blocks, e.g. to move past the time locks.
+    pub fn mine_blocks(&mut self, n: usize) -> Result<(), BlockchainError> {
+        for _ in 0..n {
+            self.mine_block()?;
+        }
+        Ok(())
+    }
+
+    /// Returns the confirmed unspent outputs of the predicate known to the simulator.
+    pub fn utxos(&self, predicate: &Predicate) -> Vec<&Output> {
+        let point = predicate.to_point();
+        let mut utxos = self
+            .utxos
+            .values()
+            .filter(|output| output.contract().predicate.to_point() == point)
+            .collect::<Vec<_>>();
+        utxos.sort_by(|a, b| a.id().as_bytes().cmp(b.id().as_bytes()));
+        utxos
+    }
+
+    /// Returns the total quantity of the flavor in the confirmed unspent outputs of the predicate.
+    pub fn balance(&self, predicate: &Predicate, flavor: Scalar) -> u64 {
+        self.utxos(predicate)
+            .iter()
+            .flat_map(|output| output.contract().payload.iter())
+            .filter_map(|item| match item {
+                PortableItem::Value(value) => open_quantity(value, flavor),
+                PortableItem::Data(_) => None,
+            })
+            .sum()
+    }
+
+    /// Updates the known utxos with the confirmed transaction.
+    fn confirm(&mut self, tx: &VerifiedTx) {
+        for entry in tx.log.iter() {
+            if let Entry::Input(contract_id) = entry {
+                self.utxos.remove(contract_id);
+            }
+        }
+        if let Some(outputs) = self.pending.remove(&tx.id) {
+            for output in outputs {
+                self.utxos.insert(output.id(), output);
+            }
+        }
+        self.txs.remove(&tx.id);
+    }
+
+    /// Forgets the transaction removed from the mempool.
+    fn forget(&mut self, txid: &TxID) {
+        self.txs.remove(txid);
+        self.pending.remove(txid);
+    }
+}
+
+fn faucet_secret() -> Scalar {
+    let mut t = Transcript::new(b"ZkVM.simulator");
+    let mut buf = [0u8; 64];
+    t.challenge_bytes(b"faucet", &mut buf);
+    Scalar::from_bytes_mod_order_wide(&buf)
+}
+
+fn faucet_predicate() -> Predicate {
+    Predicate::Key(VerificationKey::from_secret(&faucet_secret()))
+}
+
+fn test_flavor(faucet: &Predicate) -> Scalar {
+    Value::issue_flavor(faucet, Data::Opaque(TEST_ASSET.to_vec()))
+}
+
+/// Returns the quantity of the value if it has the flavor and its commitments are open.
+fn open_quantity(value: &Value, flavor: Scalar) -> Option<u64> {
+    match (value.qty.assignment(), value.flv.assignment()) {
+        (Some(ScalarWitness::Integer(qty)), Some(ScalarWitness::Scalar(flv))) if flv == flavor => {
+            qty.to_u64()
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fund_and_spend() {
+        let mut sim = Simulator::new(1_000);
+        let alice = sim.create_account(100).unwrap();
+        let bob = sim.create_account(0).unwrap();
+        let flavor = sim.test_flavor();
+        assert_eq!(sim.balance(&alice.predicate, flavor), 100);
+        assert_eq!(sim.tip().height, 3);
+
+        // Alice pays 30 to Bob and 2 in fees.
+        let utxo = sim.utxos(&alice.predicate)[0].clone();
+        let program = TxIntent::new(sim.tx_header(), [1u8; 32])
+            .input(utxo)
+            .output(30, flavor, bob.predicate.clone())
+            .output(68, flavor, alice.predicate.clone())
+            .fee(2)
+            .compile()
+            .unwrap();
+        sim.submit_program(program, &[alice.secret]).unwrap();
+        assert_eq!(sim.balance(&bob.predicate, flavor), 0);
+
+        let block = sim.mine_block().unwrap();
+        assert_eq!(block.txs.len(), 1);
+        assert_eq!(block.txs[0].feerate.fee(), 2);
+        assert_eq!(sim.balance(&alice.predicate, flavor), 68);
+        assert_eq!(sim.balance(&bob.predicate, flavor), 30);
+        assert!(sim.mempool().is_empty());
+    }
+
+    #[test]
+    fn rejects_bad_signature() {
+        let mut sim = Simulator::new(1_000);
+        let alice = sim.create_account(10).unwrap();
+        let bob = sim.create_account(0).unwrap();
+
+        // Bob cannot spend Alice's output: the signature is rejected on submission,
+        // before the transaction enters the mempool.
+        let header = sim.tx_header();
+        let utxo = sim.utxos(&alice.predicate)[0].clone();
+        let program = TxIntent::new(header, [3u8; 32])
+            .input(utxo)
+            .output(10, sim.test_flavor(), bob.predicate.clone())
+            .compile()
+            .unwrap();
+        let (tx, _, _) = Prover::build_tx_with_params(
+            program,
+            header,
+            &sim.state().params().vm_params(),
+            sim.bp_gens(),
+            |t, _| Signature::sign_aggregated(t, &[bob.secret]),
+        )
+        .unwrap();
+        assert!(sim.submit_tx(tx).is_err());
+        assert!(sim.mempool().is_empty());
+        assert_eq!(sim.balance(&alice.predicate, sim.test_flavor()), 10);
+    }
+
+    #[test]
+    fn signed_blocks() {
+        let mut sim = Simulator::new(1_000);
+        let secrets = (0..3u64)
+            .map(|i| (i as usize, Scalar::from(i + 1)))
+            .collect::<Vec<_>>();
+        let keys = secrets
+            .iter()
+            .map(|(_, secret)| VerificationKey::from_secret(secret))
+            .collect::<Vec<_>>();
+        let committee = Committee::new(keys, 2).unwrap();
+
+        sim.set_committee(committee.clone(), &secrets[..1]);
+        assert_eq!(
+            sim.mine_block().map(|_| ()),
+            Err(BlockchainError::InvalidBlockSignature)
+        );
+        assert_eq!(sim.tip().height, 1);
+
+        sim.set_committee(committee.clone(), &secrets[1..]);
+        let header = sim.mine_block().unwrap().header.clone();
+        assert_eq!(committee.verify(&header), Ok(()));
+        assert_eq!(sim.tip(), &header);
+    }
+
+    #[test]
+    fn time_warp() {
+        let mut sim = Simulator::new(1_000);
+        let alice = sim.create_account(10).unwrap();
+        let start = sim.now();
+
+        // Transaction is not valid until the clock reaches its `mintime`.
+        let mut header = sim.tx_header();
+        header.mintime = start + 60_000;
+        let utxo = sim.utxos(&alice.predicate)[0].clone();
+        let program = TxIntent::new(header, [2u8; 32])
+            .input(utxo)
+            .output(10, sim.test_flavor(), alice.predicate.clone())
+            .compile()
+            .unwrap();
+        let (tx, _, _) = Prover::build_tx_with_params(
+            program,
+            header,
+            &sim.state().params().vm_params(),
+            sim.bp_gens(),
+            |t, _| Signature::sign_aggregated(t, &[alice.secret]),
+        )
+        .unwrap();
+        sim.submit_tx(tx).unwrap();
+
+        assert!(sim.mine_block().unwrap().txs.is_empty());
+        assert_eq!(sim.mempool().len(), 1);
+
+        sim.warp(60_000);
+        assert_eq!(sim.mine_block().unwrap().txs.len(), 1);
+        assert!(sim.now() > start + 60_000);
+
+        // The clock never moves backwards.
+        let now = sim.now();
+        sim.warp_to(0);
+        assert_eq!(sim.now(), now);
+    }
+}
@@ -0,0 +1,303 @@
+//! Light client (SPV) verification.
+//!
+//! The light client follows the chain of block headers without verifying the transactions.
+//! Full nodes provide it with the logs of the relevant transactions and the proofs of their
+//! inclusion in the blocks, and with the utreexo proofs that the client's outputs are unspent.
+//! The transaction ID commits to the log, so the log is checked against the block's `txroot`
+//! without executing the transaction.
+
+use zkvm::{ContractID, Entry, MerkleItem, MerkleProof, Output, TxID, TxLog, TxTree};
+
+use crate::block::{BlockHeader, VerifiedBlock};
+use crate::errors::BlockchainError;
+use crate::utreexo::{self, Path};
+
+/// Log of a transaction with a proof of its inclusion in a block.
+#[derive(Clone, Debug)]
+pub struct TxInclusion {
+    /// Log of the transaction.
+    pub log: TxLog,
+    /// Merkle proof of the transaction ID in the block's transaction tree.
+    pub proof: MerkleProof,
+}
+
+/// Proof that an item belongs to the utxo set committed to by a block's `utxoroot`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct UtxoProof {
+    /// Roots of the utreexo trees, from the largest tree to the smallest one.
+    pub roots: Vec<[u8; 32]>,
+    /// Path from the item to the root of its tree.
+    pub path: Path,
+}
+
+/// Light client that follows the chain of block headers
+/// and tracks the outputs relevant to a wallet.
+pub struct SpvClient {
+    // headers starting with the initial one
+    headers: Vec<BlockHeader>,
+    utxos: Vec<Output>,
+}
+
+impl TxInclusion {
+    /// Creates the inclusion proof for the transaction at a given index in the block.
+    pub fn new(block: &VerifiedBlock, index: usize) -> Option<Self> {
+        let log = block.txs.get(index)?.log.clone();
+        let txids = block.txs.iter().map(|tx| tx.id).collect::<Vec<_>>();
+        let proof = TxTree::build(&txids).create_proof(index).ok()?;
+        Some(TxInclusion { log, proof })
+    }
+
+    /// Verifies that the transaction is included in the block with the given header.
+    /// Returns the ID of the transaction.
+    pub fn verify(&self, header: &BlockHeader) -> Result<TxID, BlockchainError> {
+        let id = TxID::from_log(&self.log);
+        self.proof
+            .verify(&header.txroot, &id)
+            .map_err(|_| BlockchainError::InvalidTxInclusion)?;
+        Ok(id)
+    }
+}
+
+impl UtxoProof {
+    /// Verifies that the item is in the utxo set with the given `utxoroot`.
+    pub fn verify<M: MerkleItem>(
+        &self,
+        item: &M,
+        utxoroot: &[u8; 32],
+    ) -> Result<(), BlockchainError> {
+        if &utreexo::roots_hash(&self.roots) != utxoroot {
+            return Err(BlockchainError::InvalidUtxoRoot);
+        }
+        if self.path.neighbors.len() >= 64 {
+            return Err(BlockchainError::InvalidUtreexoProof);
+        }
+        let root = self.path.root(&utreexo::leaf_hash(item));
+        if !self.roots.contains(&root) {
+            return Err(BlockchainError::InvalidUtreexoProof);
+        }
+        Ok(())
+    }
+}
+
+impl SpvClient {
+    /// Creates a client that trusts the given initial block header.
+    pub fn new(initial: BlockHeader) -> Self {
+        SpvClient {
+            headers: vec![initial],
+            utxos: Vec::new(),
+        }
+    }
+
+    /// Returns the latest block header.
+    pub fn tip(&self) -> &BlockHeader {
+        self.headers
+            .last()
+            .expect("initial header is always present")
+    }
+
+    /// Returns the block header at a given height.
+    pub fn header(&self, height: u64) -> Option<&BlockHeader> {
+        height
+            .checked_sub(self.headers[0].height)
+            .and_then(|i| self.headers.get(i as usize))
+    }
+
+    /// Returns the outputs tracked by the client.
+    pub fn utxos(&self) -> &[Output] {
+        &self.utxos
+    }
+
+    /// Validates the headers following the tip and appends them to the chain.
+    /// No headers are appended if any of them is invalid.
+    pub fn apply_headers(&mut self, headers: &[BlockHeader]) -> Result<(), BlockchainError> {
+        let mut prev = self.tip();
+        for header in headers.iter() {
+            header.validate(prev)?;
+            prev = header;
+        }
+        self.headers.extend_from_slice(headers);
+        Ok(())
+    }
+
+    /// Verifies that the transaction is included in the block at a given height,
+    /// forgets the outputs it spends and starts tracking the outputs selected by `is_relevant`.
+    /// Transactions must be applied in the order of the chain.
+    /// Returns the outputs that are tracked from now on.
+    pub fn apply_tx<F>(
+        &mut self,
+        height: u64,
+        tx: &TxInclusion,
+        is_relevant: F,
+    ) -> Result<Vec<Output>, BlockchainError>
+    where
+        F: Fn(&Output) -> bool,
+    {
+        let header = self.header(height).ok_or(BlockchainError::UnknownBlock)?;
+        tx.verify(header)?;
+
+        let mut added = Vec::new();
+        for entry in tx.log.iter() {
+            match entry {
+                Entry::Input(contract_id) => {
+                    self.utxos.retain(|utxo| utxo.id() != *contract_id);
+                }
+                Entry::Output(output) => {
+                    if is_relevant(output) && !self.is_tracked(&output.id()) {
+                        self.utxos.push(output.clone());
+                        added.push(output.clone());
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(added)
+    }
+
+    /// Verifies that the output is unspent as of the latest block.
+    pub fn verify_unspent(
+        &self,
+        output: &Output,
+        proof: &UtxoProof,
+    ) -> Result<(), BlockchainError> {
+        proof.verify(&output.id(), &self.tip().utxoroot)
+    }
+
+    fn is_tracked(&self, contract_id: &ContractID) -> bool {
+        self.utxos.iter().any(|utxo| utxo.id() == *contract_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bridge::Bridge;
+    use crate::state::tests::apply;
+    use crate::state::BlockchainState;
+    use crate::testutil::{self, utxo};
+    use zkvm::VerifiedTx;
+
+    /// Creates a transaction spending and creating utxos with given numbers.
+    /// Its ID is computed from the log, as the light client does.
+    fn spend(inputs: &[u8], outputs: &[u8]) -> VerifiedTx {
+        let mut tx = testutil::spend(0, inputs, outputs);
+        tx.id = TxID::from_log(&tx.log);
+        tx
+    }
+
+    /// Makes the next block with the given transactions and utxo root.
+    fn make_block(prev: &BlockHeader, txs: Vec<VerifiedTx>, utxoroot: [u8; 32]) -> VerifiedBlock {
+        let txids = txs.iter().map(|tx| tx.id).collect::<Vec<_>>();
+        VerifiedBlock {
+            header: BlockHeader {
+                version: 1,
+                height: prev.height + 1,
+                prev_id: prev.id(),
+                timestamp_ms: prev.timestamp_ms + 1000,
+                txroot: TxTree::build(&txids).root(),
+                utxoroot,
+                ext: Vec::new(),
+            },
+            txs,
+        }
+    }
+
+    #[test]
+    fn header_chain() {
+        let initial = BlockHeader::make_initial(1000, [0u8; 32]);
+        let mut client = SpvClient::new(initial.clone());
+        let block2 = make_block(&initial, vec![], [0u8; 32]);
+        let block3 = make_block(&block2.header, vec![], [0u8; 32]);
+        client
+            .apply_headers(&[block2.header.clone(), block3.header.clone()])
+            .unwrap();
+        assert_eq!(client.tip(), &block3.header);
+        assert_eq!(client.header(2), Some(&block2.header));
+        assert_eq!(client.header(4), None);
+
+        // Headers that do not follow the tip are not appended.
+        let mut block4 = make_block(&block3.header, vec![], [0u8; 32]);
+        let block5 = make_block(&block4.header, vec![], [0u8; 32]);
+        block4.header.timestamp_ms = block3.header.timestamp_ms;
+        assert_eq!(
+            client.apply_headers(&[block4.header, block5.header]),
+            Err(BlockchainError::BadBlockTimestamp)
+        );
+        assert_eq!(client.tip(), &block3.header);
+    }
+
+    #[test]
+    fn track_outputs() {
+        let initial = BlockHeader::make_initial(1000, [0u8; 32]);
+        let mut client = SpvClient::new(initial.clone());
+        let is_relevant =
+            |output: &Output| output.id() == utxo(1).id() || output.id() == utxo(3).id();
+
+        let block2 = make_block(
+            &initial,
+            vec![spend(&[], &[0, 1]), spend(&[], &[2])],
+            [0u8; 32],
+        );
+        let block3 = make_block(&block2.header, vec![spend(&[1], &[3])], [0u8; 32]);
+        client
+            .apply_headers(&[block2.header.clone(), block3.header.clone()])
+            .unwrap();
+
+        let tx = TxInclusion::new(&block2, 0).unwrap();
+        assert_eq!(tx.verify(&block2.header), Ok(block2.txs[0].id));
+        assert_eq!(
+            tx.verify(&block3.header),
+            Err(BlockchainError::InvalidTxInclusion)
+        );
+        let added = client.apply_tx(2, &tx, is_relevant).unwrap();
+        assert_eq!(added.len(), 1);
+        assert_eq!(added[0].id(), utxo(1).id());
+        // Applying the same transaction again does not duplicate the outputs.
+        assert!(client.apply_tx(2, &tx, is_relevant).unwrap().is_empty());
+
+        // The transaction is not in the block at height 3.
+        assert_eq!(
+            client.apply_tx(3, &tx, is_relevant).map(|_| ()),
+            Err(BlockchainError::InvalidTxInclusion)
+        );
+        assert_eq!(
+            client.apply_tx(4, &tx, is_relevant).map(|_| ()),
+            Err(BlockchainError::UnknownBlock)
+        );
+
+        // Spending the output replaces it with the new one.
+        let tx = TxInclusion::new(&block3, 0).unwrap();
+        client.apply_tx(3, &tx, is_relevant).unwrap();
+        assert_eq!(client.utxos().len(), 1);
+        assert_eq!(client.utxos()[0].id(), utxo(3).id());
+        assert!(TxInclusion::new(&block3, 1).is_none());
+    }
+
+    #[test]
+    fn unspent_outputs() {
+        let mut state = BlockchainState::make_initial(1000, 10, 10);
+        let mut bridge = Bridge::new(&state).unwrap();
+        let mut client = SpvClient::new(state.tip().clone());
+        let tx = testutil::spend(1, &[], &[0, 1, 2, 3, 4]);
+        apply(&mut state, &mut bridge, vec![tx]).unwrap();
+        client.apply_headers(&[state.tip().clone()]).unwrap();
+        let proof = |i: u8| bridge.utxo_proof(&utxo(i).id()).unwrap();
+
+        for i in 0..5 {
+            assert_eq!(client.verify_unspent(&utxo(i), &proof(i)), Ok(()));
+        }
+        assert_eq!(
+            client.verify_unspent(&utxo(5), &proof(1)),
+            Err(BlockchainError::InvalidUtreexoProof)
+        );
+        assert_eq!(
+            client.verify_unspent(&utxo(1), &proof(2)),
+            Err(BlockchainError::InvalidUtreexoProof)
+        );
+        let mut wrong_roots = proof(1);
+        wrong_roots.roots.pop();
+        assert_eq!(
+            client.verify_unspent(&utxo(1), &wrong_roots),
+            Err(BlockchainError::InvalidUtxoRoot)
+        );
+    }
+}
@@ -0,0 +1,947 @@
+//! Blockchain state: the utxo accumulator, the nonces, the nullifiers and the recent block IDs.
+//!
+//! The state is updated by applying blocks one by one. For each applied block the state keeps
+//! the data needed to undo it, so the last blocks can be rolled back when the node switches
+//! to another branch of the chain.
+
+use bulletproofs::BulletproofGens;
+use merlin::Transcript;
+use std::borrow::Borrow;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io;
+use zkvm::{Anchor, Entry, Reader, VMError, VerifiedTx, Writer};
+
+use crate::block::{Block, BlockHeader, BlockID, VerifiedBlock};
+use crate::bridge::Bridge;
+use crate::cache::VerificationCache;
+use crate::errors::BlockchainError;
+use crate::params::ChainParams;
+use crate::signer::BlockSigner;
+use crate::utreexo::{Forest, Proof};
+
+/// State of the blockchain at the tip block.
+#[derive(Clone)]
+pub struct BlockchainState {
+    initial: BlockHeader,
+    tip: BlockHeader,
+    utreexo: Forest,
+    // nonce anchor -> maxtime
+    nonces: HashMap<[u8; 32], u64>,
+    // nullifiers of the one-time claims, never expire
+    nullifiers: HashSet<[u8; 32]>,
+    // recent block IDs, the oldest first
+    refids: VecDeque<BlockID>,
+    refscount: usize,
+    max_rollback: usize,
+    // undo data of the recent blocks, the oldest first
+    undo: VecDeque<BlockUndo>,
+    // consensus parameters, not included in the checkpoint
+    params: ChainParams,
+}
+
+/// State of the blockchain at a given height, serialized for the new nodes
+/// that bootstrap from it instead of applying all preceding blocks.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StateSnapshot {
+    /// Height of the tip block of the state.
+    pub height: u64,
+    /// Hash of the state, committed to by the next block of version 2.
+    pub hash: [u8; 32],
+    /// Serialized state in the checkpoint format.
+    pub data: Vec<u8>,
+}
+
+/// Changes made by a block, used to roll it back.
+#[derive(Clone)]
+struct BlockUndo {
+    prev_tip: BlockHeader,
+    // roots of the utreexo forest before the block
+    utreexo: Forest,
+    nonces_added: Vec<[u8; 32]>,
+    nonces_expired: Vec<([u8; 32], u64)>,
+    nullifiers_added: Vec<[u8; 32]>,
+    refids_pruned: Vec<BlockID>,
+}
+
+impl BlockchainState {
+    /// Creates the state of a new network with an initial block with no transactions.
+    /// `refscount` is the number of recent block IDs that nonces may refer to,
+    /// and `max_rollback` is the number of recent blocks that can be rolled back.
+    pub fn make_initial(timestamp_ms: u64, refscount: usize, max_rollback: usize) -> Self {
+        let utreexo = Forest::new();
+        let initial = BlockHeader::make_initial(timestamp_ms, utreexo.root());
+        BlockchainState {
+            initial: initial.clone(),
+            tip: initial,
+            utreexo,
+            nonces: HashMap::new(),
+            nullifiers: HashSet::new(),
+            refids: VecDeque::new(),
+            refscount,
+            max_rollback,
+            undo: VecDeque::new(),
+            params: ChainParams::default(),
+        }
+    }
+
+    /// Sets the consensus parameters of the network, which are the default ones
+    /// for a new or restored state. Fails with `BlockchainError::MismatchedGenesis`
+    /// if the parameters specify another initial block.
+    pub fn with_params(mut self, params: ChainParams) -> Result<Self, BlockchainError> {
+        if let Some(genesis_id) = params.genesis_id {
+            if genesis_id != self.initial.id() {
+                return Err(BlockchainError::MismatchedGenesis);
+            }
+        }
+        self.params = params;
+        Ok(self)
+    }
+
+    /// Returns the consensus parameters of the network.
+    pub fn params(&self) -> &ChainParams {
+        &self.params
+    }
+
+    /// Returns the header of the initial block.
+    pub fn initial_header(&self) -> &BlockHeader {
+        &self.initial
+    }
+
+    /// Returns the header of the latest applied block.
+    pub fn tip(&self) -> &BlockHeader {
+        &self.tip
+    }
+
+    /// Returns the roots of the utreexo forest of the unspent outputs.
+    pub fn utreexo(&self) -> &Forest {
+        &self.utreexo
+    }
+
+    /// Returns the number of recent blocks that can be rolled back.
+    pub fn max_rollback(&self) -> usize {
+        self.max_rollback
+    }
+
+    /// Returns the number of blocks that can be rolled back.
+    pub fn rollback_depth(&self) -> usize {
+        self.undo.len()
+    }
+
+    /// Verifies the block on top of the current tip and applies it to the state
+    /// with the utreexo proofs of the spent outputs from the bridge at the same tip.
+    /// Returns the verified block, to be applied to the bridge with `Bridge::apply_block`,
+    /// or an error if the block is invalid. The state is not changed if the block is invalid.
+    pub fn apply_block(
+        &mut self,
+        block: Block,
+        bridge: &Bridge,
+        bp_gens: &BulletproofGens,
+    ) -> Result<VerifiedBlock, BlockchainError> {
+        self.apply_block_with_cache(block, bridge, &VerificationCache::new(0), bp_gens)
+    }
+
+    /// Verifies and applies the block like `apply_block`, but skips the R1CS proofs
+    /// and the signatures of the transactions whose witness IDs are in the cache,
+    /// e.g. the transactions admitted to the mempool (see `Mempool::verification_cache`).
+    pub fn apply_block_with_cache(
+        &mut self,
+        block: Block,
+        bridge: &Bridge,
+        cache: &VerificationCache,
+        bp_gens: &BulletproofGens,
+    ) -> Result<VerifiedBlock, BlockchainError> {
+        if bridge.height() != self.tip.height || bridge.root() != self.tip.utxoroot {
+            return Err(BlockchainError::MismatchedBridge);
+        }
+        let block = block.verify_with_params_and_cache(&self.tip, &self.params, bp_gens, cache)?;
+        self.apply_verified_block(&block, &bridge.utreexo_proofs(&block.txs))?;
+        Ok(block)
+    }
+
+    /// Checks that the unconfirmed transaction applies on top of the tip after its unconfirmed
+    /// ancestors (see `Mempool::ancestors`), with the utreexo proofs from the bridge at the same tip:
+    /// its time bounds admit the next block, it spends the unspent outputs,
+    /// refers to the known blocks and uses the nonces and the nullifiers that are not used yet.
+    pub fn check_unconfirmed_tx(
+        &self,
+        tx: &VerifiedTx,
+        ancestors: &[&VerifiedTx],
+        bridge: &Bridge,
+    ) -> Result<(), BlockchainError> {
+        if bridge.height() != self.tip.height || bridge.root() != self.tip.utxoroot {
+            return Err(BlockchainError::MismatchedBridge);
+        }
+        // The earliest timestamp of the next block.
+        let timestamp_ms = self.tip.timestamp_ms + 1;
+        if tx.header.maxtime < timestamp_ms {
+            return Err(BlockchainError::BadTxTimestamp);
+        }
+        let mut txs = ancestors.to_vec();
+        txs.push(tx);
+        self.apply_txs(timestamp_ms, &txs, &bridge.utreexo_proofs(&txs))?;
+        Ok(())
+    }
+
+    /// Verifies the signature of the block header with the block signing scheme,
+    /// then verifies and applies the block as `apply_block`.
+    pub fn apply_signed_block<S: BlockSigner>(
+        &mut self,
+        block: Block,
+        signer: &S,
+        bridge: &Bridge,
+        bp_gens: &BulletproofGens,
+    ) -> Result<VerifiedBlock, BlockchainError> {
+        signer.verify(&block.header)?;
+        self.apply_block(block, bridge, bp_gens)
+    }
+
+    /// Rolls back the last `n` blocks using their undo data.
+    /// Fails without changing the state if there is not enough undo data.
+    pub fn rollback(&mut self, n: usize) -> Result<(), BlockchainError> {
+        if n > self.undo.len() {
+            return Err(BlockchainError::RollbackTooDeep);
+        }
+        for _ in 0..n {
+            let undo = self.undo.pop_back().expect("undo data is checked above");
+            self.undo_block(undo);
+        }
+        Ok(())
+    }
+
+    /// Switches to another branch: rolls back the last `n` blocks and applies the given blocks,
+    /// updating the bridge along with the state.
+    /// Neither the state nor the bridge is changed if any of the blocks is invalid.
+    pub fn reorg(
+        &mut self,
+        n: usize,
+        blocks: Vec<Block>,
+        bridge: &mut Bridge,
+        bp_gens: &BulletproofGens,
+    ) -> Result<Vec<VerifiedBlock>, BlockchainError> {
+        self.reorg_with(n, blocks, bridge, |state, block, bridge| {
+            state.apply_block(block, bridge, bp_gens)
+        })
+    }
+
+    /// Switches to another branch like `reorg`, verifying the signatures of the new blocks
+    /// with the block signing scheme as `apply_signed_block`.
+    pub fn reorg_signed<S: BlockSigner>(
+        &mut self,
+        n: usize,
+        blocks: Vec<Block>,
+        signer: &S,
+        bridge: &mut Bridge,
+        bp_gens: &BulletproofGens,
+    ) -> Result<Vec<VerifiedBlock>, BlockchainError> {
+        self.reorg_with(n, blocks, bridge, |state, block, bridge| {
+            state.apply_signed_block(block, signer, bridge, bp_gens)
+        })
+    }
+
+    fn reorg_with<F>(
+        &mut self,
+        n: usize,
+        blocks: Vec<Block>,
+        bridge: &mut Bridge,
+        mut apply: F,
+    ) -> Result<Vec<VerifiedBlock>, BlockchainError>
+    where
+        F: FnMut(&mut Self, Block, &Bridge) -> Result<VerifiedBlock, BlockchainError>,
+    {
+        let mut state = self.clone();
+        let mut new_bridge = bridge.clone();
+        state.rollback(n)?;
+        new_bridge.rollback(n)?;
+        let blocks = blocks
+            .into_iter()
+            .map(|block| {
+                let block = apply(&mut state, block, &new_bridge)?;
+                new_bridge.apply_block(&block)?;
+                Ok(block)
+            })
+            .collect::<Result<Vec<_>, BlockchainError>>()?;
+        *self = state;
+        *bridge = new_bridge;
+        Ok(blocks)
+    }
+
+    /// Serializes the state into a checkpoint from which a node can resume
+    /// without applying the preceding blocks. Undo data is not included,
+    /// so the blocks before the checkpoint cannot be rolled back.
+    ///
+    /// The checkpoint consists of the initial and the tip headers,
+    /// LE64 `refscount`, LE64 `max_rollback`, the roots of the utreexo forest
+    /// (LE32 number of trees, followed by LE64 height and 32-byte root of each tree, the largest first),
+    /// LE32-prefixed list of nonces (32-byte anchor and LE64 maxtime) sorted by anchor,
+    /// LE32-prefixed list of the recent 32-byte block IDs, the oldest first,
+    /// and LE32-prefixed list of the 32-byte nullifiers in ascending order.
+    pub fn to_checkpoint(&self) -> Vec<u8> {
+        let mut w = Writer::new(Vec::new());
+        self.write_checkpoint(&mut w)
+            .expect("writing to a vector never fails");
+        w.into_inner()
+    }
+
+    /// Computes the hash of the state, which the blocks of version 2 and later
+    /// (see `VersionRules::state_hash_version`) commit to
+    /// in the first 32 bytes of the `ext` field of the header of the next block.
+    pub fn state_hash(&self) -> [u8; 32] {
+        let mut t = Transcript::new(b"ZkVM.statehash");
+        t.commit_bytes(b"initial", &self.initial.id().0);
+        t.commit_bytes(b"tip", &self.tip.id().0);
+        t.commit_u64(b"refscount", self.refscount as u64);
+
+        let mut nonces = self.nonces.iter().collect::<Vec<_>>();
+        nonces.sort();
+        t.commit_u64(b"nonces", nonces.len() as u64);
+        for (anchor, maxtime) in nonces {
+            t.commit_bytes(b"anchor", anchor);
+            t.commit_u64(b"maxtime", *maxtime);
+        }
+
+        t.commit_u64(b"refids", self.refids.len() as u64);
+        for id in self.refids.iter() {
+            t.commit_bytes(b"refid", &id.0);
+        }
+
+        let mut nullifiers = self.nullifiers.iter().collect::<Vec<_>>();
+        nullifiers.sort();
+        t.commit_u64(b"nullifiers", nullifiers.len() as u64);
+        for tag in nullifiers {
+            t.commit_bytes(b"nullifier", tag);
+        }
+
+        let mut hash = [0u8; 32];
+        t.challenge_bytes(b"hash", &mut hash);
+        hash
+    }
+
+    /// Creates a snapshot of the state for the new nodes.
+    pub fn snapshot(&self) -> StateSnapshot {
+        StateSnapshot {
+            height: self.tip.height,
+            hash: self.state_hash(),
+            data: self.to_checkpoint(),
+        }
+    }
+
+    /// Restores the state of the network with the parameters from a snapshot
+    /// and checks it against a trusted header of the next block, which must commit
+    /// to the state hash (see `VersionRules::state_hash_version`).
+    /// The bulletproofs of the preceding blocks are not verified.
+    pub fn from_snapshot(
+        snapshot: &StateSnapshot,
+        next: &BlockHeader,
+        params: &ChainParams,
+    ) -> Result<Self, BlockchainError> {
+        let state = Self::from_checkpoint(&snapshot.data)?.with_params(params.clone())?;
+        let hash = state.state_hash();
+        if state.tip.height != snapshot.height
+            || hash != snapshot.hash
+            || next.version < params.version_rules.state_hash_version
+            || next.prev_id != state.tip.id()
+            || next.ext.get(..32) != Some(&hash[..])
+        {
+            return Err(BlockchainError::InvalidStateHash);
+        }
+        Ok(state)
+    }
+
+    /// Restores the state from a checkpoint, with the default consensus parameters.
+    pub fn from_checkpoint(data: &[u8]) -> Result<Self, BlockchainError> {
+        let mut r = Reader::new(data);
+        let state = Self::read_checkpoint(&mut r).map_err(|_| BlockchainError::FormatError)?;
+        if !r.into_inner().is_empty() || state.refids.len() > state.refscount {
+            return Err(BlockchainError::FormatError);
+        }
+        if state.utreexo.root() != state.tip.utxoroot {
+            return Err(BlockchainError::InvalidUtxoRoot);
+        }
+        Ok(state)
+    }
+
+    /// Applies the verified block on top of the current tip with the utreexo proofs
+    /// of the outputs spent by the block, listed in the order of the inputs
+    /// (see `Bridge::utreexo_proofs`). The state is not changed if the block is invalid.
+    pub fn apply_verified_block(
+        &mut self,
+        block: &VerifiedBlock,
+        utxo_proofs: &[Proof],
+    ) -> Result<(), BlockchainError> {
+        if block.header.version >= self.params.version_rules.state_hash_version
+            && block.header.ext.get(..32) != Some(&self.state_hash()[..])
+        {
+            return Err(BlockchainError::InvalidStateHash);
+        }
+        let (utreexo, nonces, nullifiers, mut undo) =
+            self.apply_txs(block.header.timestamp_ms, &block.txs, utxo_proofs)?;
+        if utreexo.root() != block.header.utxoroot {
+            return Err(BlockchainError::InvalidUtxoRoot);
+        }
+
+        self.utreexo = utreexo;
+        self.nonces = nonces;
+        self.nullifiers = nullifiers;
+        self.refids.push_back(block.header.id());
+        while self.refids.len() > self.refscount {
+            undo.refids_pruned.extend(self.refids.pop_front());
+        }
+        self.tip = block.header.clone();
+
+        self.undo.push_back(undo);
+        if self.undo.len() > self.max_rollback {
+            self.undo.pop_front();
+        }
+        Ok(())
+    }
+
+    /// Applies the transaction logs to a copy of the utxo forest, the nonces and the nullifiers,
+    /// deleting the spent outputs with the utreexo proofs listed in the order of the inputs,
+    /// and returns them with the normalized forest and the undo data.
+    pub(crate) fn apply_txs<T: Borrow<VerifiedTx>>(
+        &self,
+        timestamp_ms: u64,
+        txs: &[T],
+        utxo_proofs: &[Proof],
+    ) -> Result<(Forest, HashMap<[u8; 32], u64>, HashSet<[u8; 32]>, BlockUndo), BlockchainError>
+    {
+        let mut undo = BlockUndo {
+            prev_tip: self.tip.clone(),
+            utreexo: self.utreexo.clone(),
+            nonces_added: Vec::new(),
+            nonces_expired: Vec::new(),
+            nullifiers_added: Vec::new(),
+            refids_pruned: Vec::new(),
+        };
+
+        let mut nonces = self.nonces.clone();
+        undo.nonces_expired = nonces
+            .iter()
+            .filter(|(_, maxtime)| **maxtime < timestamp_ms)
+            .map(|(anchor, maxtime)| (*anchor, *maxtime))
+            .collect();
+        for (anchor, _) in undo.nonces_expired.iter() {
+            nonces.remove(anchor);
+        }
+
+        let mut nullifiers = self.nullifiers.clone();
+        let initial_id = self.initial.id();
+        let mut utreexo = self.utreexo.clone();
+        let mut proofs = utxo_proofs.iter();
+        for entry in txs.iter().flat_map(|tx| tx.borrow().log.iter()) {
+            match entry {
+                Entry::Nonce(blockid, maxtime, anchor) => {
+                    let blockid = BlockID(*blockid);
+                    if blockid != initial_id && !self.refids.contains(&blockid) {
+                        return Err(BlockchainError::UnknownBlockReference);
+                    }
+                    let anchor = anchor_bytes(anchor);
+                    if nonces.contains_key(&anchor) {
+                        return Err(BlockchainError::DuplicateNonce);
+                    }
+                    nonces.insert(anchor, *maxtime);
+                    undo.nonces_added.push(anchor);
+                }
+                Entry::Nullifier(tag) => {
+                    if !nullifiers.insert(*tag) {
+                        return Err(BlockchainError::DuplicateNullifier);
+                    }
+                    undo.nullifiers_added.push(*tag);
+                }
+                Entry::Input(contract_id) => {
+                    // Outputs created earlier in the same block have transient proofs.
+                    let proof = proofs.next().ok_or(BlockchainError::InvalidUtreexoProof)?;
+                    utreexo
+                        .delete_with_proof(contract_id, proof)
+                        .map_err(|_| BlockchainError::UtxoNotFound)?;
+                }
+                Entry::Output(output) => {
+                    utreexo.insert(&output.id());
+                }
+                _ => {}
+            }
+        }
+        if proofs.next().is_some() {
+            return Err(BlockchainError::InvalidUtreexoProof);
+        }
+
+        Ok((utreexo.normalize(), nonces, nullifiers, undo))
+    }
+
+    fn undo_block(&mut self, undo: BlockUndo) {
+        self.utreexo = undo.utreexo;
+
+        for anchor in undo.nonces_added.iter() {
+            self.nonces.remove(anchor);
+        }
+        self.nonces.extend(undo.nonces_expired);
+        for tag in undo.nullifiers_added.iter() {
+            self.nullifiers.remove(tag);
+        }
+
+        self.refids.pop_back();
+        for id in undo.refids_pruned.into_iter().rev() {
+            self.refids.push_front(id);
+        }
+        self.tip = undo.prev_tip;
+    }
+
+    fn write_checkpoint<W: io::Write>(&self, w: &mut Writer<W>) -> io::Result<()> {
+        self.initial.write(w)?;
+        self.tip.write(w)?;
+        w.write_u64(self.refscount as u64)?;
+        w.write_u64(self.max_rollback as u64)?;
+
+        self.utreexo.write(w)?;
+
+        let mut nonces = self.nonces.iter().collect::<Vec<_>>();
+        nonces.sort();
+        w.write_size(nonces.len())?;
+        for (anchor, maxtime) in nonces {
+            w.write_bytes(anchor)?;
+            w.write_u64(*maxtime)?;
+        }
+
+        w.write_size(self.refids.len())?;
+        for id in self.refids.iter() {
+            w.write_bytes(&id.0)?;
+        }
+
+        let mut nullifiers = self.nullifiers.iter().collect::<Vec<_>>();
+        nullifiers.sort();
+        w.write_size(nullifiers.len())?;
+        for tag in nullifiers {
+            w.write_bytes(tag)?;
+        }
+        Ok(())
+    }
+
+    fn read_checkpoint<R: io::Read>(r: &mut Reader<R>) -> Result<Self, VMError> {
+        let initial = BlockHeader::read(r)?;
+        let tip = BlockHeader::read(r)?;
+        let refscount = r.read_u64()? as usize;
+        let max_rollback = r.read_u64()? as usize;
+
+        let utreexo = Forest::read(r)?;
+
+        let mut nonces = HashMap::new();
+        for _ in 0..r.read_size()? {
+            let anchor = r.read_u8x32()?;
+            nonces.insert(anchor, r.read_u64()?);
+        }
+
+        let mut refids = VecDeque::new();
+        for _ in 0..r.read_size()? {
+            refids.push_back(BlockID(r.read_u8x32()?));
+        }
+
+        let mut nullifiers = HashSet::new();
+        for _ in 0..r.read_size()? {
+            nullifiers.insert(r.read_u8x32()?);
+        }
+
+        Ok(BlockchainState {
+            initial,
+            tip,
+            utreexo,
+            nonces,
+            nullifiers,
+            refids,
+            refscount,
+            max_rollback,
+            undo: VecDeque::new(),
+            params: ChainParams::default(),
+        })
+    }
+}
+
+fn anchor_bytes(anchor: &Anchor) -> [u8; 32] {
+    let mut bytes = [0u8; 32];
+    bytes.copy_from_slice(anchor.as_bytes());
+    bytes
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::signer::Committee;
+    use crate::testutil::{make_tx, spend};
+    use curve25519_dalek::constants::RISTRETTO_BASEPOINT_COMPRESSED;
+    use curve25519_dalek::scalar::Scalar;
+    use zkvm::{Predicate, TxTree, VerificationKey};
+
+    fn nonce(id: u8, blockid: BlockID, maxtime: u64) -> VerifiedTx {
+        let anchor = Anchor::nonce(
+            blockid.0,
+            &Predicate::Opaque(RISTRETTO_BASEPOINT_COMPRESSED),
+            maxtime,
+        );
+        make_tx(id, vec![Entry::Nonce(blockid.0, maxtime, anchor)])
+    }
+
+    fn nullifier(id: u8, tag: u8) -> VerifiedTx {
+        make_tx(id, vec![Entry::Nullifier([tag; 32])])
+    }
+
+    /// Makes the next block with the given transactions on top of the state's tip,
+    /// spending the outputs with the proofs from the bridge.
+    pub(crate) fn make_block(
+        state: &BlockchainState,
+        bridge: &Bridge,
+        txs: Vec<VerifiedTx>,
+    ) -> VerifiedBlock {
+        let tip = state.tip();
+        let timestamp_ms = tip.timestamp_ms + 1000;
+        let utxoroot = match state.apply_txs(timestamp_ms, &txs, &bridge.utreexo_proofs(&txs)) {
+            Ok((utreexo, _, _, _)) => utreexo.root(),
+            Err(_) => tip.utxoroot,
+        };
+        let txids = txs.iter().map(|tx| tx.id).collect::<Vec<_>>();
+        VerifiedBlock {
+            header: BlockHeader {
+                version: 1,
+                height: tip.height + 1,
+                prev_id: tip.id(),
+                timestamp_ms,
+                txroot: TxTree::build(&txids).root(),
+                utxoroot,
+                ext: Vec::new(),
+            },
+            txs,
+        }
+    }
+
+    /// Applies the next block with the given transactions to the state and the bridge.
+    pub(crate) fn apply(
+        state: &mut BlockchainState,
+        bridge: &mut Bridge,
+        txs: Vec<VerifiedTx>,
+    ) -> Result<(), BlockchainError> {
+        let block = make_block(state, bridge, txs);
+        state.apply_verified_block(&block, &bridge.utreexo_proofs(&block.txs))?;
+        bridge.apply_block(&block).map(|_| ())
+    }
+
+    #[test]
+    fn apply_and_rollback() {
+        let mut state = BlockchainState::make_initial(0, 10, 10);
+        let mut bridge = Bridge::new(&state).unwrap();
+        let initial_root = state.utreexo().root();
+
+        apply(&mut state, &mut bridge, vec![spend(1, &[], &[1, 2, 3])]).unwrap();
+        let header1 = state.tip().clone();
+        assert_eq!(header1.height, 2);
+        assert_eq!(state.utreexo().roots().len(), 2); // 2 + 1
+
+        // Spends committed utxos and a utxo created in the same block.
+        apply(
+            &mut state,
+            &mut bridge,
+            vec![spend(2, &[2], &[4, 5]), spend(3, &[4, 1], &[6])],
+        )
+        .unwrap();
+        assert_eq!(state.tip().height, 3);
+        assert_eq!(bridge.len(), 3); // 3, 5, 6
+        assert_eq!(state.utreexo().root(), bridge.root());
+
+        state.rollback(1).unwrap();
+        bridge.rollback(1).unwrap();
+        assert_eq!(state.tip(), &header1);
+        assert_eq!(state.utreexo().root(), header1.utxoroot);
+
+        // Rolled back utxos can be spent again.
+        apply(&mut state, &mut bridge, vec![spend(4, &[1, 2, 3], &[])]).unwrap();
+        assert!(state.utreexo().roots().is_empty());
+
+        assert_eq!(state.rollback(3), Err(BlockchainError::RollbackTooDeep));
+        state.rollback(2).unwrap();
+        bridge.rollback(2).unwrap();
+        assert_eq!(state.tip().height, 1);
+        assert_eq!(state.utreexo().root(), initial_root);
+    }
+
+    #[test]
+    fn rejects_invalid_blocks() {
+        let mut state = BlockchainState::make_initial(0, 10, 10);
+        let mut bridge = Bridge::new(&state).unwrap();
+        apply(&mut state, &mut bridge, vec![spend(1, &[], &[1])]).unwrap();
+        let tip = state.tip().clone();
+
+        assert_eq!(
+            apply(&mut state, &mut bridge, vec![spend(2, &[7], &[])]),
+            Err(BlockchainError::UtxoNotFound)
+        );
+        assert_eq!(
+            apply(
+                &mut state,
+                &mut bridge,
+                vec![spend(2, &[1], &[]), spend(3, &[1], &[])]
+            ),
+            Err(BlockchainError::UtxoNotFound)
+        );
+
+        let mut block = make_block(&state, &bridge, vec![spend(2, &[1], &[2])]);
+        let proofs = bridge.utreexo_proofs(&block.txs);
+        assert_eq!(
+            state.apply_verified_block(&block, &[]),
+            Err(BlockchainError::InvalidUtreexoProof)
+        );
+        assert_eq!(
+            state.apply_verified_block(&block, &[Proof::Transient]),
+            Err(BlockchainError::UtxoNotFound)
+        );
+        block.header.utxoroot = tip.utxoroot;
+        assert_eq!(
+            state.apply_verified_block(&block, &proofs),
+            Err(BlockchainError::InvalidUtxoRoot)
+        );
+
+        // Failed blocks do not change the state.
+        assert_eq!(state.tip(), &tip);
+        assert_eq!(state.rollback_depth(), 1);
+        apply(&mut state, &mut bridge, vec![spend(2, &[1], &[])]).unwrap();
+    }
+
+    #[test]
+    fn nonces() {
+        let mut state = BlockchainState::make_initial(0, 1, 10);
+        let mut bridge = Bridge::new(&state).unwrap();
+        let initial_id = state.initial_header().id();
+
+        apply(&mut state, &mut bridge, vec![nonce(1, initial_id, 2500)]).unwrap();
+        assert_eq!(
+            apply(&mut state, &mut bridge, vec![nonce(2, initial_id, 2500)]),
+            Err(BlockchainError::DuplicateNonce)
+        );
+        assert_eq!(
+            apply(
+                &mut state,
+                &mut bridge,
+                vec![nonce(2, BlockID([0u8; 32]), 2500)]
+            ),
+            Err(BlockchainError::UnknownBlockReference)
+        );
+
+        // Only the latest block ID can be referenced with `refscount` of 1.
+        let id2 = state.tip().id();
+        apply(&mut state, &mut bridge, vec![nonce(2, id2, 10_000)]).unwrap();
+        assert_eq!(
+            apply(&mut state, &mut bridge, vec![nonce(3, id2, 20_000)]),
+            Err(BlockchainError::UnknownBlockReference)
+        );
+
+        // The first nonce expires at the next block.
+        apply(&mut state, &mut bridge, vec![]).unwrap();
+        assert_eq!(state.nonces.len(), 1);
+
+        // Rolling back restores the expired nonce and the pruned block ID.
+        state.rollback(1).unwrap();
+        bridge.rollback(1).unwrap();
+        assert_eq!(state.nonces.len(), 2);
+        state.rollback(1).unwrap();
+        bridge.rollback(1).unwrap();
+        assert_eq!(state.nonces.len(), 1);
+        assert_eq!(state.refids, vec![id2].into_iter().collect::<VecDeque<_>>());
+        apply(&mut state, &mut bridge, vec![nonce(3, id2, 20_000)]).unwrap();
+    }
+
+    #[test]
+    fn nullifiers() {
+        let mut state = BlockchainState::make_initial(0, 10, 10);
+        let mut bridge = Bridge::new(&state).unwrap();
+
+        apply(&mut state, &mut bridge, vec![nullifier(1, 1)]).unwrap();
+        assert_eq!(
+            apply(&mut state, &mut bridge, vec![nullifier(2, 1)]),
+            Err(BlockchainError::DuplicateNullifier)
+        );
+        assert_eq!(
+            apply(
+                &mut state,
+                &mut bridge,
+                vec![nullifier(2, 2), nullifier(3, 2)]
+            ),
+            Err(BlockchainError::DuplicateNullifier)
+        );
+
+        // Nullifiers do not expire.
+        apply(&mut state, &mut bridge, vec![nullifier(2, 2)]).unwrap();
+        apply(&mut state, &mut bridge, vec![]).unwrap();
+        assert_eq!(state.nullifiers.len(), 2);
+
+        // Rolled back nullifiers can be used again.
+        state.rollback(2).unwrap();
+        bridge.rollback(2).unwrap();
+        assert_eq!(state.nullifiers.len(), 1);
+        apply(&mut state, &mut bridge, vec![nullifier(3, 2)]).unwrap();
+    }
+
+    #[test]
+    fn checkpoint_roundtrip() {
+        let mut state = BlockchainState::make_initial(0, 2, 10);
+        let mut bridge = Bridge::new(&state).unwrap();
+        apply(&mut state, &mut bridge, vec![spend(1, &[], &[1, 2, 3])]).unwrap();
+        let id = state.tip().id();
+        apply(
+            &mut state,
+            &mut bridge,
+            vec![spend(2, &[2], &[4]), nonce(3, id, 100_000), nullifier(5, 1)],
+        )
+        .unwrap();
+
+        let checkpoint = state.to_checkpoint();
+        let mut restored = BlockchainState::from_checkpoint(&checkpoint).unwrap();
+        assert_eq!(restored.tip(), state.tip());
+        assert_eq!(restored.utreexo().root(), state.utreexo().root());
+        assert_eq!(restored.nonces, state.nonces);
+        assert_eq!(restored.refids, state.refids);
+        assert_eq!(restored.nullifiers, state.nullifiers);
+        assert_eq!(restored.rollback_depth(), 0);
+        assert_eq!(restored.to_checkpoint(), checkpoint);
+
+        // The restored state continues the chain.
+        apply(&mut restored, &mut bridge, vec![spend(4, &[1, 4], &[5])]).unwrap();
+
+        assert_eq!(
+            BlockchainState::from_checkpoint(&checkpoint[..checkpoint.len() - 1]).map(|_| ()),
+            Err(BlockchainError::FormatError)
+        );
+    }
+
+    #[test]
+    fn unconfirmed_txs() {
+        let mut state = BlockchainState::make_initial(0, 2, 10);
+        let mut bridge = Bridge::new(&state).unwrap();
+        apply(&mut state, &mut bridge, vec![spend(1, &[], &[1, 2])]).unwrap();
+
+        let parent = spend(2, &[1], &[3]);
+        let child = spend(3, &[3], &[4]);
+        state.check_unconfirmed_tx(&parent, &[], &bridge).unwrap();
+        state
+            .check_unconfirmed_tx(&child, &[&parent], &bridge)
+            .unwrap();
+        assert_eq!(
+            state.check_unconfirmed_tx(&child, &[], &bridge),
+            Err(BlockchainError::UtxoNotFound)
+        );
+
+        let mut expired = spend(4, &[2], &[]);
+        expired.header.maxtime = state.tip().timestamp_ms;
+        assert_eq!(
+            state.check_unconfirmed_tx(&expired, &[], &bridge),
+            Err(BlockchainError::BadTxTimestamp)
+        );
+        assert_eq!(
+            state.check_unconfirmed_tx(&nonce(5, BlockID([9u8; 32]), 10_000), &[], &bridge),
+            Err(BlockchainError::UnknownBlockReference)
+        );
+
+        let stale = bridge.clone();
+        apply(&mut state, &mut bridge, vec![spend(6, &[2], &[])]).unwrap();
+        assert_eq!(
+            state.check_unconfirmed_tx(&parent, &[], &stale),
+            Err(BlockchainError::MismatchedBridge)
+        );
+    }
+
+    #[test]
+    fn signed_reorg() {
+        let mut state = BlockchainState::make_initial(0, 2, 10);
+        let mut bridge = Bridge::new(&state).unwrap();
+        let bp_gens = BulletproofGens::new(64, 1);
+        let secret = Scalar::from(1u64);
+        let committee = Committee::new(vec![VerificationKey::from_secret(&secret)], 1).unwrap();
+        apply(&mut state, &mut bridge, vec![]).unwrap();
+        let tip = state.tip().clone();
+
+        // Replaces the last block with a version 2 block committing to the state before it.
+        let mut other = state.clone();
+        other.rollback(1).unwrap();
+        let mut header = make_block(&other, &bridge, vec![]).header;
+        header.version = 2;
+        header.ext = other.state_hash().to_vec();
+        let unsigned = Block {
+            header: header.clone(),
+            txs: Vec::new(),
+        };
+        assert_eq!(
+            state
+                .reorg_signed(1, vec![unsigned], &committee, &mut bridge, &bp_gens)
+                .map(|_| ()),
+            Err(BlockchainError::InvalidBlockSignature)
+        );
+        assert_eq!(state.tip(), &tip);
+
+        committee.sign(&mut header, &[(0, secret)]).unwrap();
+        let signed = Block {
+            header: header.clone(),
+            txs: Vec::new(),
+        };
+        state
+            .reorg_signed(1, vec![signed], &committee, &mut bridge, &bp_gens)
+            .unwrap();
+        assert_eq!(state.tip(), &header);
+        assert_eq!(bridge.root(), header.utxoroot);
+    }
+
+    #[test]
+    fn genesis_params() {
+        let state = BlockchainState::make_initial(0, 2, 10);
+        let mut params = ChainParams::default();
+        params.genesis_id = Some(state.initial_header().id());
+        let state = state.with_params(params.clone()).unwrap();
+        assert_eq!(state.params(), &params);
+
+        let other = BlockchainState::make_initial(1, 2, 10);
+        assert_eq!(
+            other.with_params(params).map(|_| ()),
+            Err(BlockchainError::MismatchedGenesis)
+        );
+    }
+
+    #[test]
+    fn snapshot() {
+        let mut state = BlockchainState::make_initial(0, 2, 10);
+        let mut bridge = Bridge::new(&state).unwrap();
+        apply(
+            &mut state,
+            &mut bridge,
+            vec![spend(1, &[], &[1, 2]), nullifier(2, 1)],
+        )
+        .unwrap();
+        let snapshot = state.snapshot();
+
+        // Version 2 block commits to the state it is applied to.
+        let mut block = make_block(&state, &bridge, vec![spend(3, &[1], &[3])]);
+        let proofs = bridge.utreexo_proofs(&block.txs);
+        block.header.version = 2;
+        assert_eq!(
+            state.clone().apply_verified_block(&block, &proofs),
+            Err(BlockchainError::InvalidStateHash)
+        );
+        block.header.ext = snapshot.hash.to_vec();
+        state.apply_verified_block(&block, &proofs).unwrap();
+
+        // New node loads the snapshot and continues the chain.
+        let mut restored =
+            BlockchainState::from_snapshot(&snapshot, &block.header, &ChainParams::default())
+                .unwrap();
+        restored.apply_verified_block(&block, &proofs).unwrap();
+        assert_eq!(restored.tip(), state.tip());
+        assert_eq!(restored.state_hash(), state.state_hash());
+
+        let mut other = block.header.clone();
+        other.ext = state.state_hash().to_vec();
+        assert_eq!(
+            BlockchainState::from_snapshot(&snapshot, &other, &ChainParams::default()).map(|_| ()),
+            Err(BlockchainError::InvalidStateHash)
+        );
+        let mut tampered = snapshot.clone();
+        tampered.hash = [0u8; 32];
+        assert_eq!(
+            BlockchainState::from_snapshot(&tampered, &block.header, &ChainParams::default())
+                .map(|_| ()),
+            Err(BlockchainError::InvalidStateHash)
+        );
+    }
+}
@@ -0,0 +1,243 @@
+//! Key-value storage for the chain state, the index and the wallets.
+//!
+//! `Storage` provides lookups, scans of the keys with a common prefix,
+//! and batches of writes that are applied atomically.
+//! `MemoryStorage` keeps the entries in memory. With the `sled` and `rocksdb` features,
+//! `SledStorage` and `RocksDbStorage` keep them in the embedded databases, so the node
+//! and the wallets survive restarts.
+
+use std::collections::BTreeMap;
+#[cfg(any(feature = "sled", feature = "rocksdb"))]
+use std::path::Path;
+
+use crate::errors::BlockchainError;
+
+/// Key-value storage with atomic batches of writes.
+pub trait Storage {
+    /// Returns the value stored under the key.
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, BlockchainError>;
+
+    /// Returns the entries with the keys starting with the prefix, ordered by the keys.
+    fn scan_prefix(&self, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>, BlockchainError>;
+
+    /// Applies all writes of the batch, or none of them if the storage fails.
+    fn write(&mut self, batch: Batch) -> Result<(), BlockchainError>;
+
+    /// Stores the value under the key, replacing the previous value.
+    fn put(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<(), BlockchainError> {
+        let mut batch = Batch::new();
+        batch.put(key, value);
+        self.write(batch)
+    }
+
+    /// Removes the value stored under the key, if any.
+    fn delete(&mut self, key: Vec<u8>) -> Result<(), BlockchainError> {
+        let mut batch = Batch::new();
+        batch.delete(key);
+        self.write(batch)
+    }
+}
+
+/// Write operation in a batch.
+#[derive(Clone, Debug, PartialEq)]
+pub enum BatchOp {
+    /// Stores the value under the key.
+    Put(Vec<u8>, Vec<u8>),
+    /// Removes the value stored under the key.
+    Delete(Vec<u8>),
+}
+
+/// List of writes applied atomically, in order.
+#[derive(Clone, Debug, Default)]
+pub struct Batch {
+    ops: Vec<BatchOp>,
+}
+
+/// Storage that keeps the entries in memory.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryStorage {
+    entries: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+/// Storage backed by a [sled](https://github.com/spacejam/sled) database.
+#[cfg(feature = "sled")]
+pub struct SledStorage {
+    db: sled::Db,
+}
+
+/// Storage backed by a [RocksDB](https://rocksdb.org) database.
+#[cfg(feature = "rocksdb")]
+pub struct RocksDbStorage {
+    db: rocksdb::DB,
+}
+
+impl Batch {
+    /// Creates an empty batch.
+    pub fn new() -> Self {
+        Batch { ops: Vec::new() }
+    }
+
+    /// Adds a write of the value under the key.
+    pub fn put(&mut self, key: Vec<u8>, value: Vec<u8>) {
+        self.ops.push(BatchOp::Put(key, value));
+    }
+
+    /// Adds a removal of the value under the key.
+    pub fn delete(&mut self, key: Vec<u8>) {
+        self.ops.push(BatchOp::Delete(key));
+    }
+
+    /// Returns true if the batch has no writes.
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    /// Returns the writes in the order they are applied.
+    pub fn into_ops(self) -> Vec<BatchOp> {
+        self.ops
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, BlockchainError> {
+        Ok(self.entries.get(key).cloned())
+    }
+
+    fn scan_prefix(&self, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>, BlockchainError> {
+        Ok(self
+            .entries
+            .range(prefix.to_vec()..)
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect())
+    }
+
+    fn write(&mut self, batch: Batch) -> Result<(), BlockchainError> {
+        for op in batch.ops {
+            match op {
+                BatchOp::Put(key, value) => {
+                    self.entries.insert(key, value);
+                }
+                BatchOp::Delete(key) => {
+                    self.entries.remove(&key);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "sled")]
+impl SledStorage {
+    /// Opens or creates the database at the path.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, BlockchainError> {
+        let db = sled::Db::open(path).map_err(storage_error)?;
+        Ok(SledStorage { db })
+    }
+}
+
+#[cfg(feature = "sled")]
+impl Storage for SledStorage {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, BlockchainError> {
+        let value = self.db.get(key).map_err(storage_error)?;
+        Ok(value.map(|value| value.as_ref().to_vec()))
+    }
+
+    fn scan_prefix(&self, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>, BlockchainError> {
+        self.db
+            .scan_prefix(prefix)
+            .map(|entry| {
+                entry
+                    .map(|(key, value)| (key.as_ref().to_vec(), value.as_ref().to_vec()))
+                    .map_err(storage_error)
+            })
+            .collect()
+    }
+
+    fn write(&mut self, batch: Batch) -> Result<(), BlockchainError> {
+        let mut sled_batch = sled::Batch::default();
+        for op in batch.ops {
+            match op {
+                BatchOp::Put(key, value) => sled_batch.insert(key, value),
+                BatchOp::Delete(key) => sled_batch.remove(key),
+            }
+        }
+        self.db.apply_batch(sled_batch).map_err(storage_error)?;
+        self.db.flush().map_err(storage_error)?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "rocksdb")]
+impl RocksDbStorage {
+    /// Opens or creates the database at the path.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, BlockchainError> {
+        let db = rocksdb::DB::open_default(path).map_err(storage_error)?;
+        Ok(RocksDbStorage { db })
+    }
+}
+
+#[cfg(feature = "rocksdb")]
+impl Storage for RocksDbStorage {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, BlockchainError> {
+        let value = self.db.get(key).map_err(storage_error)?;
+        Ok(value.map(|value| value.to_vec()))
+    }
+
+    fn scan_prefix(&self, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>, BlockchainError> {
+        let mode = rocksdb::IteratorMode::From(prefix, rocksdb::Direction::Forward);
+        Ok(self
+            .db
+            .iterator(mode)
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .map(|(key, value)| (key.to_vec(), value.to_vec()))
+            .collect())
+    }
+
+    fn write(&mut self, batch: Batch) -> Result<(), BlockchainError> {
+        let mut rocks_batch = rocksdb::WriteBatch::default();
+        for op in batch.ops {
+            match op {
+                BatchOp::Put(key, value) => rocks_batch.put(&key, &value),
+                BatchOp::Delete(key) => rocks_batch.delete(&key),
+            }
+            .map_err(storage_error)?;
+        }
+        self.db.write(rocks_batch).map_err(storage_error)
+    }
+}
+
+#[cfg(any(feature = "sled", feature = "rocksdb"))]
+fn storage_error<E: std::fmt::Display>(e: E) -> BlockchainError {
+    BlockchainError::StorageError(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memory_storage() {
+        let mut storage = MemoryStorage::default();
+        let mut batch = Batch::new();
+        batch.put(b"a/1".to_vec(), b"one".to_vec());
+        batch.put(b"a/2".to_vec(), b"two".to_vec());
+        batch.put(b"b/1".to_vec(), b"three".to_vec());
+        batch.delete(b"a/2".to_vec());
+        storage.write(batch).unwrap();
+
+        assert_eq!(storage.get(b"a/1").unwrap(), Some(b"one".to_vec()));
+        assert_eq!(storage.get(b"a/2").unwrap(), None);
+
+        storage.put(b"a/3".to_vec(), b"four".to_vec()).unwrap();
+        storage.delete(b"b/1".to_vec()).unwrap();
+        assert_eq!(
+            storage.scan_prefix(b"a/").unwrap(),
+            vec![
+                (b"a/1".to_vec(), b"one".to_vec()),
+                (b"a/3".to_vec(), b"four".to_vec()),
+            ]
+        );
+        assert!(storage.scan_prefix(b"b/").unwrap().is_empty());
+    }
+}
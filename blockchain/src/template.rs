@@ -0,0 +1,203 @@
+//! Block template: the next block assembled from the mempool, ready to be sealed.
+//!
+//! Transactions are taken in the order of `Mempool::candidates`, i.e. by the fee rates
+//! of their packages, and the ones that cannot be included are skipped together
+//! with their descendants. The header commits to the utxo set produced by applying
+//! the selected transactions to the state.
+
+use std::collections::HashSet;
+use zkvm::{ContractID, Entry, TxID, TxTree, VerifiedTx};
+
+use crate::block::BlockHeader;
+use crate::bridge::Bridge;
+use crate::errors::BlockchainError;
+use crate::mempool::Mempool;
+use crate::state::BlockchainState;
+
+/// Header and transactions of the next block selected from the mempool.
+#[derive(Clone, Debug)]
+pub struct BlockTemplate {
+    /// Header of the block that commits to the selected transactions and the resulting utxo set.
+    pub header: BlockHeader,
+
+    /// IDs of the selected transactions in the order of their inclusion in the block.
+    pub txids: Vec<TxID>,
+
+    /// Total fee paid by the selected transactions.
+    pub fee: u64,
+
+    /// Size of the serialized block in bytes.
+    pub size: u64,
+}
+
+impl BlockTemplate {
+    /// Builds the block on top of the state's tip with the given timestamp
+    /// from the mempool transactions, up to `max_size` bytes of the serialized block
+    /// and the maximum block size of the state's consensus parameters.
+    /// The spent outputs are checked with the utreexo proofs from the bridge at the same tip.
+    ///
+    /// Transactions whose time bounds, version or size do not match the block,
+    /// that do not apply to the state (e.g. spend the outputs that are already spent)
+    /// or do not fit into the block are skipped together with their descendants.
+    /// Blocks of the state hash version commit to the state hash in `ext`.
+    pub fn build(
+        state: &BlockchainState,
+        bridge: &Bridge,
+        mempool: &Mempool,
+        max_size: u64,
+        timestamp_ms: u64,
+    ) -> Result<Self, BlockchainError> {
+        let tip = state.tip();
+        let params = state.params();
+        let rules = &params.version_rules;
+        let max_size = max_size.min(params.max_block_size as u64);
+        if timestamp_ms <= tip.timestamp_ms {
+            return Err(BlockchainError::BadBlockTimestamp);
+        }
+        let mut header = BlockHeader {
+            version: tip.version,
+            height: tip.height + 1,
+            prev_id: tip.id(),
+            timestamp_ms,
+            txroot: [0u8; 32],
+            utxoroot: tip.utxoroot,
+            ext: if tip.version >= rules.state_hash_version {
+                state.state_hash().to_vec()
+            } else {
+                Vec::new()
+            },
+        };
+
+        let mut skipped = HashSet::new();
+        let mut selected = Vec::new();
+        for tx in mempool.candidates() {
+            let valid = tx.header.mintime <= timestamp_ms
+                && tx.header.maxtime >= timestamp_ms
+                && tx.feerate.size() <= params.max_tx_size as u64
+                && (header.version >= rules.state_hash_version
+                    || tx.header.version == rules.tx_version);
+            if valid && !spends_any(tx, &skipped) {
+                selected.push(tx);
+            } else {
+                skip(tx, &mut skipped);
+            }
+        }
+
+        // Mempool transactions normally apply to the tip, so they are checked together first.
+        // Otherwise each transaction is checked on top of the ones selected before it.
+        if state
+            .apply_txs(timestamp_ms, &selected, &bridge.utreexo_proofs(&selected))
+            .is_err()
+        {
+            let mut applied = Vec::with_capacity(selected.len());
+            for tx in selected {
+                if spends_any(tx, &skipped) {
+                    skip(tx, &mut skipped);
+                    continue;
+                }
+                applied.push(tx);
+                if state
+                    .apply_txs(timestamp_ms, &applied, &bridge.utreexo_proofs(&applied))
+                    .is_err()
+                {
+                    applied.pop();
+                    skip(tx, &mut skipped);
+                }
+            }
+            selected = applied;
+        }
+
+        // Header, the number of transactions and the size prefix of each transaction.
+        let mut size = header.to_bytes().len() as u64 + 4;
+        let mut included = Vec::with_capacity(selected.len());
+        for tx in selected {
+            let tx_size = 4 + tx.feerate.size();
+            if size + tx_size <= max_size && !spends_any(tx, &skipped) {
+                size += tx_size;
+                included.push(tx);
+            } else {
+                skip(tx, &mut skipped);
+            }
+        }
+
+        let (utreexo, _, _, _) =
+            state.apply_txs(timestamp_ms, &included, &bridge.utreexo_proofs(&included))?;
+        let txids = included.iter().map(|tx| tx.id).collect::<Vec<_>>();
+        header.txroot = TxTree::build(&txids).root();
+        header.utxoroot = utreexo.root();
+        Ok(BlockTemplate {
+            header,
+            txids,
+            fee: included.iter().map(|tx| tx.feerate.fee()).sum(),
+            size,
+        })
+    }
+}
+
+/// Returns true if the transaction spends any of the outputs.
+fn spends_any(tx: &VerifiedTx, outputs: &HashSet<ContractID>) -> bool {
+    tx.log.iter().any(|entry| match entry {
+        Entry::Input(contract_id) => outputs.contains(contract_id),
+        _ => false,
+    })
+}
+
+/// Remembers the outputs of the skipped transaction, so its descendants are skipped too.
+fn skip(tx: &VerifiedTx, outputs: &mut HashSet<ContractID>) {
+    for entry in tx.log.iter() {
+        if let Entry::Output(output) = entry {
+            outputs.insert(output.id());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::VerifiedBlock;
+    use crate::state::tests::apply;
+    use crate::testutil::spend;
+    use zkvm::FeeRate;
+
+    fn with_fee(mut tx: VerifiedTx, fee: u64) -> VerifiedTx {
+        tx.feerate = FeeRate::new(fee, 100);
+        tx
+    }
+
+    #[test]
+    fn selects_transactions() {
+        let mut state = BlockchainState::make_initial(0, 10, 10);
+        let mut bridge = Bridge::new(&state).unwrap();
+        apply(&mut state, &mut bridge, vec![spend(1, &[], &[1, 2])]).unwrap();
+
+        let mut mempool = Mempool::new(10_000);
+        mempool.append(with_fee(spend(2, &[1], &[3]), 100)).unwrap();
+        // Spends an unknown output, and its child.
+        mempool.append(with_fee(spend(3, &[9], &[4]), 500)).unwrap();
+        mempool.append(with_fee(spend(4, &[4], &[5]), 500)).unwrap();
+        // Does not fit into the block.
+        mempool.append(with_fee(spend(5, &[2], &[6]), 50)).unwrap();
+
+        let timestamp_ms = state.tip().timestamp_ms + 1000;
+        assert_eq!(
+            BlockTemplate::build(&state, &bridge, &mempool, 1000, state.tip().timestamp_ms)
+                .map(|_| ()),
+            Err(BlockchainError::BadBlockTimestamp)
+        );
+
+        // Header, the number of transactions and one transaction with its size prefix.
+        let max_size = 124 + 4 + 104;
+        let template =
+            BlockTemplate::build(&state, &bridge, &mempool, max_size, timestamp_ms).unwrap();
+        assert_eq!(template.txids, vec![TxID([2; 32])]);
+        assert_eq!(template.fee, 100);
+        assert_eq!(template.size, max_size);
+
+        let block = VerifiedBlock {
+            header: template.header,
+            txs: vec![spend(2, &[1], &[3])],
+        };
+        let proofs = bridge.utreexo_proofs(&block.txs);
+        assert_eq!(state.apply_verified_block(&block, &proofs), Ok(()));
+    }
+}
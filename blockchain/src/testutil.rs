@@ -0,0 +1,84 @@
+//! Test vectors of the block headers (enabled by the `testutil` feature)
+//! and the verified transactions spending and creating the numbered outputs,
+//! shared by the tests of the state, the mempool and the other components.
+//! See `zkvm::testutil` for the vectors of the transactions.
+
+use curve25519_dalek::constants::RISTRETTO_BASEPOINT_COMPRESSED;
+#[cfg(feature = "testutil")]
+use zkvm::testutil::TestVector;
+use zkvm::{
+    Anchor, Contract, Entry, FeeRate, Output, Predicate, TxHeader, TxID, TxTree, VerifiedTx, WTxID,
+};
+
+use crate::block::{BlockHeader, BlockID};
+
+/// Returns the headers of the initial block and the block that follows it.
+pub fn block_headers() -> Vec<BlockHeader> {
+    let initial = BlockHeader::make_initial(1_000, [1u8; 32]);
+    let next = BlockHeader {
+        version: 1,
+        height: 2,
+        prev_id: initial.id(),
+        timestamp_ms: 2_000,
+        txroot: TxTree::build(&[]).root(),
+        utxoroot: [2u8; 32],
+        ext: Vec::new(),
+    };
+    vec![initial, next]
+}
+
+/// Returns the output with an opaque predicate, identified by the number.
+pub fn utxo(i: u8) -> Output {
+    let predicate = Predicate::Opaque(RISTRETTO_BASEPOINT_COMPRESSED);
+    Output::new(Contract {
+        anchor: Anchor::nonce([i; 32], &predicate, 0),
+        payload: vec![],
+        predicate,
+    })
+}
+
+/// Creates a transaction with the given log, identified by the number.
+/// The transaction is valid at any time and pays no fee.
+pub fn make_tx(id: u8, log: Vec<Entry>) -> VerifiedTx {
+    VerifiedTx {
+        header: TxHeader {
+            version: 1,
+            mintime: 0,
+            maxtime: u64::max_value(),
+            maxcost: 0,
+        },
+        id: TxID([id; 32]),
+        wtxid: WTxID([id; 32]),
+        log,
+        feerate: FeeRate::zero(),
+        proof_metrics: Default::default(),
+    }
+}
+
+/// Creates a transaction spending and creating the outputs with the given numbers (see `utxo`).
+pub fn spend(id: u8, inputs: &[u8], outputs: &[u8]) -> VerifiedTx {
+    let mut log = Vec::new();
+    log.extend(inputs.iter().map(|i| Entry::Input(utxo(*i).id())));
+    log.extend(outputs.iter().map(|i| Entry::Output(utxo(*i))));
+    make_tx(id, log)
+}
+
+/// Encodings and IDs of the block headers returned by `block_headers`.
+#[cfg(feature = "testutil")]
+pub fn vectors() -> Vec<TestVector> {
+    block_headers()
+        .into_iter()
+        .flat_map(|header| {
+            let BlockID(id) = header.id();
+            let name = format!("blockheader/{}", header.height);
+            vec![
+                TestVector::new(format!("{}/encoding", name), vec![], header.to_bytes()),
+                TestVector::new(
+                    format!("{}/id", name),
+                    vec![("header", header.to_bytes())],
+                    id.to_vec(),
+                ),
+            ]
+        })
+        .collect()
+}
@@ -0,0 +1,540 @@
+//! Utreexo: a dynamic hash-based accumulator for the set of unspent outputs.
+//!
+//! The forest consists of perfect binary Merkle trees of distinct heights, one for each bit
+//! of the number of items. `Forest` keeps only the roots of the trees: items are deleted
+//! with the proofs of their inclusion, and the neighbors in these proofs are the hashes
+//! needed to update the roots. When the forest is normalized, each tree with deleted items
+//! is split into its largest intact subtrees, which are merged back into the forest
+//! together with the inserted items. The full trees, needed to create the proofs,
+//! are kept by the `Bridge`.
+
+use byteorder::{ByteOrder, LittleEndian};
+use merlin::Transcript;
+use std::io;
+use zkvm::{MerkleItem, Reader, VMError, Writer};
+
+use crate::errors::BlockchainError;
+
+/// Utreexo forest represented by the roots of its trees.
+#[derive(Clone, Debug)]
+pub struct Forest {
+    // roots of the trees indexed by their heights
+    roots: Vec<Option<[u8; 32]>>,
+    insertions: Vec<[u8; 32]>,
+    // committed items deleted since the last normalization
+    deletions: Vec<Deletion>,
+}
+
+/// Proof of inclusion of an item in the forest.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Proof {
+    /// Item was inserted into the forest after the last normalization
+    /// and is not yet committed to any tree.
+    Transient,
+    /// Item is committed to one of the trees at a given path.
+    Committed(Path),
+}
+
+/// Merkle path from an item to the root of its tree.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Path {
+    /// Position of the item within its tree.
+    pub position: u64,
+    /// Hashes of the neighbors, from the leaf up to the root.
+    /// The number of neighbors is the height of the tree.
+    pub neighbors: Vec<[u8; 32]>,
+}
+
+/// Tree of the forest that is merged with the trees of the same height.
+pub(crate) trait Tree: Sized {
+    /// Merges two trees of the same height into a tree one level higher.
+    fn merge(left: Self, right: Self) -> Self;
+}
+
+#[derive(Clone, Debug)]
+struct Deletion {
+    height: usize,
+    position: u64,
+    neighbors: Vec<[u8; 32]>,
+}
+
+impl Forest {
+    /// Creates an empty forest.
+    pub fn new() -> Self {
+        Forest {
+            roots: Vec::new(),
+            insertions: Vec::new(),
+            deletions: Vec::new(),
+        }
+    }
+
+    /// Returns the root hashes of the trees, from the largest tree to the smallest one.
+    pub fn roots(&self) -> Vec<[u8; 32]> {
+        self.roots.iter().rev().filter_map(|root| *root).collect()
+    }
+
+    /// Returns a hash committing to the roots of all the trees.
+    /// Pending insertions and deletions are reflected only after normalization.
+    pub fn root(&self) -> [u8; 32] {
+        roots_hash(&self.roots())
+    }
+
+    /// Adds a new item to the forest. The item is committed to a tree
+    /// upon the next normalization, until then it has a transient proof.
+    pub fn insert<M: MerkleItem>(&mut self, item: &M) {
+        self.insertions.push(leaf_hash(item));
+    }
+
+    /// Deletes the item from the forest, checking the proof of its inclusion.
+    /// The roots do not change until normalization, so the proofs of the other items
+    /// remain valid until then.
+    pub fn delete_with_proof<M: MerkleItem>(
+        &mut self,
+        item: &M,
+        proof: &Proof,
+    ) -> Result<(), BlockchainError> {
+        let hash = leaf_hash(item);
+        match proof {
+            Proof::Transient => {
+                let index = self
+                    .insertions
+                    .iter()
+                    .position(|h| h == &hash)
+                    .ok_or(BlockchainError::InvalidUtreexoProof)?;
+                self.insertions.remove(index);
+                Ok(())
+            }
+            Proof::Committed(path) => {
+                let height = path.neighbors.len();
+                match self.roots.get(height) {
+                    Some(Some(root))
+                        if path.position >> height == 0 && &path.root(&hash) == root => {}
+                    _ => return Err(BlockchainError::InvalidUtreexoProof),
+                }
+                if self
+                    .deletions
+                    .iter()
+                    .any(|d| d.height == height && d.position == path.position)
+                {
+                    return Err(BlockchainError::InvalidUtreexoProof);
+                }
+                self.deletions.push(Deletion {
+                    height,
+                    position: path.position,
+                    neighbors: path.neighbors.clone(),
+                });
+                Ok(())
+            }
+        }
+    }
+
+    /// Splits the trees with the deleted items into their largest intact subtrees,
+    /// from the largest tree to the smallest one and from left to right within each tree,
+    /// and adds the subtrees and then the inserted items to the remaining trees.
+    /// Returns the normalized forest.
+    pub fn normalize(self) -> Forest {
+        let mut roots = self.roots;
+        let mut deletions = self.deletions;
+        deletions.sort_by(|a, b| b.height.cmp(&a.height).then(a.position.cmp(&b.position)));
+
+        // All affected trees are removed before the subtrees are added back.
+        let mut subtrees = Vec::new();
+        let mut rest = &deletions[..];
+        while let Some(height) = rest.first().map(|d| d.height) {
+            let n = rest.iter().take_while(|d| d.height == height).count();
+            roots[height] = None;
+            split(height, 0, &rest[..n], &mut subtrees);
+            rest = &rest[n..];
+        }
+        for (height, hash) in subtrees {
+            add_tree(&mut roots, height, hash);
+        }
+        for hash in self.insertions {
+            add_tree(&mut roots, 0, hash);
+        }
+
+        Forest {
+            roots,
+            insertions: Vec::new(),
+            deletions: Vec::new(),
+        }
+    }
+
+    /// Serializes the roots of the normalized forest: LE32 number of trees,
+    /// followed by LE64 height and 32-byte root of each tree, from the largest tree to the smallest one.
+    pub(crate) fn write<W: io::Write>(&self, w: &mut Writer<W>) -> io::Result<()> {
+        let trees = self.trees();
+        w.write_size(trees.len())?;
+        for (height, root) in trees {
+            w.write_u64(height as u64)?;
+            w.write_bytes(&root)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn read<R: io::Read>(r: &mut Reader<R>) -> Result<Self, VMError> {
+        let mut roots = Vec::new();
+        let mut prev = 64;
+        for _ in 0..r.read_size()? {
+            let height = r.read_u64()?;
+            if height >= prev {
+                return Err(VMError::FormatError);
+            }
+            prev = height;
+            let height = height as usize;
+            while roots.len() <= height {
+                roots.push(None);
+            }
+            roots[height] = Some(r.read_u8x32()?);
+        }
+        Ok(Forest {
+            roots,
+            insertions: Vec::new(),
+            deletions: Vec::new(),
+        })
+    }
+
+    /// Returns the heights and the roots of the trees, from the largest tree to the smallest one.
+    fn trees(&self) -> Vec<(usize, [u8; 32])> {
+        self.roots
+            .iter()
+            .enumerate()
+            .rev()
+            .filter_map(|(height, root)| root.map(|root| (height, root)))
+            .collect()
+    }
+}
+
+impl Path {
+    /// Computes the root of the tree containing the leaf, using the bits of the position
+    /// to order the leaf's ancestors and their neighbors.
+    pub(crate) fn root(&self, leaf: &[u8; 32]) -> [u8; 32] {
+        self.neighbors
+            .iter()
+            .enumerate()
+            .fold(*leaf, |hash, (i, neighbor)| {
+                if (self.position >> i) & 1 == 0 {
+                    node_hash(&hash, neighbor)
+                } else {
+                    node_hash(neighbor, &hash)
+                }
+            })
+    }
+}
+
+impl Proof {
+    /// Serializes the proof into a byte array:
+    /// byte `0` for a transient proof, or byte `1` followed by LE64 position,
+    /// LE32 number of neighbors and 32-byte neighbor hashes.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            Proof::Transient => vec![0],
+            Proof::Committed(path) => {
+                let mut buf = Vec::with_capacity(1 + 8 + 4 + 32 * path.neighbors.len());
+                buf.push(1);
+                let mut n = [0u8; 8];
+                LittleEndian::write_u64(&mut n, path.position);
+                buf.extend_from_slice(&n);
+                LittleEndian::write_u32(&mut n[..4], path.neighbors.len() as u32);
+                buf.extend_from_slice(&n[..4]);
+                for h in path.neighbors.iter() {
+                    buf.extend_from_slice(h);
+                }
+                buf
+            }
+        }
+    }
+
+    /// Decodes the proof from a byte array.
+    pub fn from_bytes(data: &[u8]) -> Result<Self, BlockchainError> {
+        match data.split_first() {
+            Some((&0, rest)) if rest.is_empty() => Ok(Proof::Transient),
+            Some((&1, rest)) if rest.len() >= 12 => {
+                let position = LittleEndian::read_u64(&rest[0..8]);
+                let n = LittleEndian::read_u32(&rest[8..12]) as usize;
+                let hashes = &rest[12..];
+                if hashes.len() / 32 != n || hashes.len() % 32 != 0 {
+                    return Err(BlockchainError::FormatError);
+                }
+                let neighbors = hashes
+                    .chunks(32)
+                    .map(|chunk| {
+                        let mut h = [0u8; 32];
+                        h.copy_from_slice(chunk);
+                        h
+                    })
+                    .collect();
+                Ok(Proof::Committed(Path {
+                    position,
+                    neighbors,
+                }))
+            }
+            _ => Err(BlockchainError::FormatError),
+        }
+    }
+}
+
+impl Tree for [u8; 32] {
+    fn merge(left: Self, right: Self) -> Self {
+        node_hash(&left, &right)
+    }
+}
+
+/// Adds the tree of the given height to the trees indexed by their heights,
+/// merging it to the right of the existing tree of the same height, if any.
+/// Returns the position of the tree's first leaf in the resulting tree.
+pub(crate) fn add_tree<T: Tree>(trees: &mut Vec<Option<T>>, height: usize, tree: T) -> u64 {
+    let mut height = height;
+    let mut tree = tree;
+    let mut offset = 0;
+    loop {
+        while trees.len() <= height {
+            trees.push(None);
+        }
+        match trees[height].take() {
+            Some(left) => {
+                tree = T::merge(left, tree);
+                offset += 1u64 << height;
+                height += 1;
+            }
+            None => {
+                trees[height] = Some(tree);
+                return offset;
+            }
+        }
+    }
+}
+
+/// Collects the largest intact subtrees of the subtree at the given height and position,
+/// from left to right, given the deletions within it sorted by position.
+/// Hashes of the intact subtrees are the neighbors of the deleted items.
+fn split(height: usize, start: u64, deletions: &[Deletion], subtrees: &mut Vec<(usize, [u8; 32])>) {
+    if height == 0 {
+        return;
+    }
+    let half = 1u64 << (height - 1);
+    let n = deletions
+        .iter()
+        .take_while(|d| d.position < start + half)
+        .count();
+    let (left, right) = deletions.split_at(n);
+    if left.is_empty() {
+        subtrees.push((height - 1, right[0].neighbors[height - 1]));
+    } else {
+        split(height - 1, start, left, subtrees);
+    }
+    if right.is_empty() {
+        subtrees.push((height - 1, left[0].neighbors[height - 1]));
+    } else {
+        split(height - 1, start + half, right, subtrees);
+    }
+}
+
+pub(crate) fn leaf_hash<M: MerkleItem>(item: &M) -> [u8; 32] {
+    let mut t = Transcript::new(b"ZkVM.utreexo");
+    item.commit(&mut t);
+    let mut result = [0u8; 32];
+    t.challenge_bytes(b"merkle.leaf", &mut result);
+    result
+}
+
+pub(crate) fn roots_hash(roots: &[[u8; 32]]) -> [u8; 32] {
+    let mut t = Transcript::new(b"ZkVM.utreexo");
+    t.commit_bytes(b"n", &(roots.len() as u64).to_le_bytes());
+    for root in roots.iter() {
+        t.commit_bytes(b"root", root);
+    }
+    let mut result = [0u8; 32];
+    t.challenge_bytes(b"utreexo.roots", &mut result);
+    result
+}
+
+pub(crate) fn node_hash(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let mut t = Transcript::new(b"ZkVM.utreexo");
+    t.commit_bytes(b"L", left);
+    t.commit_bytes(b"R", right);
+    let mut result = [0u8; 32];
+    t.challenge_bytes(b"merkle.node", &mut result);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Item(u64);
+
+    impl MerkleItem for Item {
+        fn commit(&self, t: &mut Transcript) {
+            t.commit_bytes(b"item", &self.0.to_le_bytes());
+        }
+    }
+
+    /// Returns the neighbors of the leaf at a given position in the tree built of the leaves.
+    fn neighbors(leaves: &[[u8; 32]], position: usize) -> Vec<[u8; 32]> {
+        if leaves.len() == 1 {
+            return Vec::new();
+        }
+        let (left, right) = leaves.split_at(leaves.len() / 2);
+        let (mut result, neighbor) = if position < left.len() {
+            (neighbors(left, position), right)
+        } else {
+            (neighbors(right, position - left.len()), left)
+        };
+        result.push(tree_root(neighbor));
+        result
+    }
+
+    fn tree_root(leaves: &[[u8; 32]]) -> [u8; 32] {
+        if leaves.len() == 1 {
+            return leaves[0];
+        }
+        let (left, right) = leaves.split_at(leaves.len() / 2);
+        node_hash(&tree_root(left), &tree_root(right))
+    }
+
+    /// Builds a normalized forest with items `0..n` and returns it with the items' proofs.
+    /// Items inserted into an empty forest fill the trees from the largest one.
+    fn forest_with_items(n: u64) -> (Forest, Vec<Proof>) {
+        let mut forest = Forest::new();
+        for i in 0..n {
+            forest.insert(&Item(i));
+        }
+        let forest = forest.normalize();
+
+        let leaves = (0..n).map(|i| leaf_hash(&Item(i))).collect::<Vec<_>>();
+        let mut proofs = Vec::new();
+        let mut offset = 0;
+        for height in (0..64).rev() {
+            let size = 1usize << height;
+            if n as usize & size != 0 {
+                let tree = &leaves[offset..offset + size];
+                proofs.extend((0..size).map(|i| {
+                    Proof::Committed(Path {
+                        position: i as u64,
+                        neighbors: neighbors(tree, i),
+                    })
+                }));
+                offset += size;
+            }
+        }
+        (forest, proofs)
+    }
+
+    #[test]
+    fn trees_follow_item_count() {
+        let (forest, proofs) = forest_with_items(13);
+        assert_eq!(forest.roots().len(), 3); // 8 + 4 + 1
+        match &proofs[12] {
+            Proof::Committed(path) => assert_eq!(path.neighbors.len(), 0),
+            _ => panic!("Item must be committed after normalization"),
+        }
+    }
+
+    #[test]
+    fn delete_committed_items() {
+        let (mut forest, proofs) = forest_with_items(7);
+        let root = forest.root();
+
+        assert!(forest.delete_with_proof(&Item(3), &proofs[3]).is_ok());
+        // Deletions do not change the roots until normalization.
+        assert_eq!(forest.root(), root);
+        assert!(forest.delete_with_proof(&Item(5), &proofs[5]).is_ok());
+
+        // Double spends and mismatching proofs are rejected.
+        assert!(forest.delete_with_proof(&Item(3), &proofs[3]).is_err());
+        assert!(forest.delete_with_proof(&Item(4), &proofs[2]).is_err());
+        assert!(forest
+            .delete_with_proof(&Item(4), &Proof::Transient)
+            .is_err());
+
+        let forest = forest.normalize();
+        assert_eq!(forest.roots().len(), 2); // 4 + 1
+        assert_ne!(forest.root(), root);
+    }
+
+    #[test]
+    fn normalize_splits_trees() {
+        let (mut forest, proofs) = forest_with_items(4);
+        forest.delete_with_proof(&Item(1), &proofs[1]).unwrap();
+        let forest = forest.normalize();
+
+        // The tree of 4 items is split into the subtree of items 2 and 3, and item 0.
+        let leaves = (0..4).map(|i| leaf_hash(&Item(i))).collect::<Vec<_>>();
+        assert_eq!(
+            forest.roots(),
+            vec![node_hash(&leaves[2], &leaves[3]), leaves[0]]
+        );
+
+        // Inserted item is merged with the single item into a tree of 2 items.
+        let mut forest = forest;
+        forest.insert(&Item(4));
+        let forest = forest.normalize();
+        assert_eq!(
+            forest.roots(),
+            vec![node_hash(
+                &node_hash(&leaves[2], &leaves[3]),
+                &node_hash(&leaves[0], &leaf_hash(&Item(4)))
+            )]
+        );
+    }
+
+    #[test]
+    fn proofs_of_intact_trees_remain_valid() {
+        let (mut forest, proofs) = forest_with_items(6);
+        forest.delete_with_proof(&Item(5), &proofs[5]).unwrap();
+        let mut forest = forest.normalize();
+
+        // The tree of items 0..4 is not changed, while the tree of items 4 and 5 is split.
+        assert!(forest.delete_with_proof(&Item(4), &proofs[4]).is_err());
+        assert!(forest.delete_with_proof(&Item(1), &proofs[1]).is_ok());
+        let proof = Proof::Committed(Path {
+            position: 0,
+            neighbors: Vec::new(),
+        });
+        assert!(forest.delete_with_proof(&Item(4), &proof).is_ok());
+        let forest = forest.normalize();
+        assert_eq!(forest.roots().len(), 2); // 2 + 1
+    }
+
+    #[test]
+    fn delete_transient_items() {
+        let (mut forest, _) = forest_with_items(2);
+        forest.insert(&Item(10));
+        assert!(forest
+            .delete_with_proof(&Item(10), &Proof::Transient)
+            .is_ok());
+        assert!(forest
+            .delete_with_proof(&Item(10), &Proof::Transient)
+            .is_err());
+        let forest = forest.normalize();
+        assert_eq!(forest.roots().len(), 1);
+    }
+
+    #[test]
+    fn roots_encoding() {
+        let (forest, _) = forest_with_items(13);
+        let mut w = Writer::new(Vec::new());
+        forest.write(&mut w).unwrap();
+        let bytes = w.into_inner();
+        assert_eq!(bytes.len(), 4 + 3 * (8 + 32));
+        let decoded = Forest::read(&mut Reader::new(&bytes[..])).unwrap();
+        assert_eq!(decoded.roots(), forest.roots());
+
+        // Heights must decrease.
+        let mut bad = bytes.clone();
+        bad[4] = 0;
+        assert!(Forest::read(&mut Reader::new(&bad[..])).is_err());
+    }
+
+    #[test]
+    fn proof_encoding() {
+        let (_, proofs) = forest_with_items(5);
+        for proof in proofs.iter().chain(Some(&Proof::Transient)) {
+            assert_eq!(&Proof::from_bytes(&proof.to_bytes()).unwrap(), proof);
+        }
+        let mut bytes = proofs[0].to_bytes();
+        bytes.push(0);
+        assert!(Proof::from_bytes(&bytes).is_err());
+    }
+}
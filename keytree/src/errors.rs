@@ -0,0 +1,26 @@
+//! Errors related to parsing of derivation paths, encoded keys and mnemonics.
+
+use core::fmt;
+
+/// Represents an error in parsing a derivation path, an encoded key or a mnemonic.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ParseError {
+    /// Derivation path is not of the form `m/1/2'/3`.
+    InvalidPath,
+    /// Encoded key has an unexpected prefix or length, or is not a valid key.
+    InvalidKey,
+    /// Mnemonic has an invalid number of words, an unknown word or a wrong checksum.
+    InvalidMnemonic,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::InvalidPath => write!(f, "Invalid derivation path"),
+            ParseError::InvalidKey => write!(f, "Invalid encoded key"),
+            ParseError::InvalidMnemonic => write!(f, "Invalid mnemonic"),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
@@ -2,16 +2,28 @@
 //! Implementation of the key tree protocol, a key blinding scheme for deriving hierarchies of public keys.
 
 use crate::transcript::TranscriptProtocol;
+use core::fmt;
+use core::str::FromStr;
 use curve25519_dalek::constants;
 use curve25519_dalek::ristretto::CompressedRistretto;
 use curve25519_dalek::ristretto::RistrettoPoint;
 use curve25519_dalek::scalar::Scalar;
 use merlin::Transcript;
 use rand::{CryptoRng, RngCore};
+use zeroize::Zeroize;
 
+mod errors;
+mod mnemonic;
+mod path;
 mod transcript;
 
+pub use self::errors::ParseError;
+pub use self::mnemonic::Mnemonic;
+pub use self::path::{ChildIndex, DerivationPath};
+
 /// Xprv represents an extended private key.
+/// The secret scalar and the derivation key are zeroized when dropped.
+#[derive(Clone)]
 pub struct Xprv {
     scalar: Scalar,
     dk: [u8; 32],
@@ -19,6 +31,7 @@ pub struct Xprv {
 }
 
 /// Xpub represents an extended public key.
+#[derive(Clone)]
 pub struct Xpub {
     point: RistrettoPoint,
     dk: [u8; 32],
@@ -41,6 +54,24 @@ impl Xprv {
         }
     }
 
+    /// Returns the root Xprv derived from a seed, such as the seed of a mnemonic
+    /// (see `Mnemonic::to_seed`).
+    pub fn from_seed(seed: &[u8]) -> Self {
+        let mut t = Transcript::new(b"Keytree.seed");
+        t.commit_bytes(b"seed", seed);
+        let scalar = t.challenge_scalar(b"scalar");
+        let mut dk = [0u8; 32];
+        t.challenge_bytes(b"dk", &mut dk);
+
+        let precompressed_pubkey = (scalar * &constants::RISTRETTO_BASEPOINT_POINT).compress();
+
+        Xprv {
+            scalar,
+            dk,
+            precompressed_pubkey,
+        }
+    }
+
     /// Returns a new Xpub, generated from the provided Xprv.
     pub fn to_xpub(&self) -> Xpub {
         let point = self.scalar * &constants::RISTRETTO_BASEPOINT_POINT;
@@ -73,12 +104,75 @@ impl Xprv {
         let child_point = xpub.point + (f * &constants::RISTRETTO_BASEPOINT_POINT);
 
         Xprv {
-            scalar: self.scalar,
+            scalar: self.scalar + f,
             dk: child_dk,
             precompressed_pubkey: child_point.compress(),
         }
     }
 
+    /// Returns a hardened child xprv. Unlike an intermediate key, a hardened key
+    /// cannot be derived from the parent Xpub, and the parent Xprv cannot be recovered
+    /// from the parent Xpub and the child Xprv.
+    pub fn derive_hardened_key(&self, customize: impl FnOnce(&mut Transcript)) -> Xprv {
+        let mut t = Transcript::new(b"Keytree.derivation");
+        t.commit_bytes(b"pt", self.precompressed_pubkey.as_bytes());
+        t.commit_bytes(b"dk", &self.dk);
+        t.commit_bytes(b"sk", self.scalar.as_bytes());
+
+        // change the derivation path for this key
+        customize(&mut t);
+
+        // squeeze a challenge scalar
+        let f = t.challenge_scalar(b"f.hardened");
+
+        // squeeze a new derivation key
+        let mut child_dk = [0u8; 32];
+        t.challenge_bytes(b"dk", &mut child_dk);
+
+        let child_scalar = self.scalar + f;
+
+        Xprv {
+            scalar: child_scalar,
+            dk: child_dk,
+            precompressed_pubkey: (child_scalar * &constants::RISTRETTO_BASEPOINT_POINT).compress(),
+        }
+    }
+
+    /// Returns a leaf private key. Users must provide customize, in order to separate
+    /// sibling keys from one another through unique derivation paths.
+    pub fn derive_key(&self, customize: impl FnOnce(&mut Transcript)) -> Scalar {
+        let mut t = Transcript::new(b"Keytree.derivation");
+        t.commit_bytes(b"pt", self.precompressed_pubkey.as_bytes());
+        t.commit_bytes(b"dk", &self.dk);
+
+        // change the derivation path for this key
+        customize(&mut t);
+
+        // squeeze a challenge scalar
+        let f = t.challenge_scalar(b"f.leaf");
+
+        self.scalar + f
+    }
+
+    /// Returns a child xprv at the given index.
+    pub fn derive_child(&self, index: ChildIndex) -> Xprv {
+        match index {
+            ChildIndex::Normal(i) => {
+                self.derive_intermediate_key(|t| t.commit_u64(b"index", i as u64))
+            }
+            ChildIndex::Hardened(i) => {
+                self.derive_hardened_key(|t| t.commit_u64(b"index", i as u64))
+            }
+        }
+    }
+
+    /// Returns a descendant xprv at the given derivation path.
+    pub fn derive_path(&self, path: &DerivationPath) -> Xprv {
+        path.indices()
+            .iter()
+            .fold(self.clone(), |key, index| key.derive_child(*index))
+    }
+
     /// Serializes this Xprv to a sequence of bytes.
     pub fn to_bytes(&self) -> [u8; 64] {
         let mut buf = [0u8; 64];
@@ -112,6 +206,19 @@ impl Xprv {
     }
 }
 
+impl Zeroize for Xprv {
+    fn zeroize(&mut self) {
+        self.scalar.zeroize();
+        self.dk.zeroize();
+    }
+}
+
+impl Drop for Xprv {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
 impl Xpub {
     /// Returns a intermediate child pubkey. Users must provide customize, in order to separate
     /// sibling keys from one another through unique derivation paths.
@@ -139,6 +246,41 @@ impl Xpub {
         }
     }
 
+    /// Returns a leaf public key. Users must provide customize, in order to separate
+    /// sibling keys from one another through unique derivation paths.
+    pub fn derive_key(&self, customize: impl FnOnce(&mut Transcript)) -> CompressedRistretto {
+        let mut t = Transcript::new(b"Keytree.derivation");
+        t.commit_bytes(b"pt", self.precompressed_pubkey.as_bytes());
+        t.commit_bytes(b"dk", &self.dk);
+
+        // change the derivation path for this key
+        customize(&mut t);
+
+        // squeeze a challenge scalar
+        let f = t.challenge_scalar(b"f.leaf");
+
+        (self.point + (f * &constants::RISTRETTO_BASEPOINT_POINT)).compress()
+    }
+
+    /// Returns a child xpub at the given index,
+    /// or `None` if the index is hardened.
+    pub fn derive_child(&self, index: ChildIndex) -> Option<Xpub> {
+        match index {
+            ChildIndex::Normal(i) => {
+                Some(self.derive_intermediate_key(|t| t.commit_u64(b"index", i as u64)))
+            }
+            ChildIndex::Hardened(_) => None,
+        }
+    }
+
+    /// Returns a descendant xpub at the given derivation path,
+    /// or `None` if the path contains hardened indices.
+    pub fn derive_path(&self, path: &DerivationPath) -> Option<Xpub> {
+        path.indices()
+            .iter()
+            .try_fold(self.clone(), |key, index| key.derive_child(*index))
+    }
+
     /// Serializes this Xpub to a sequence of bytes.
     pub fn to_bytes(&self) -> [u8; 64] {
         let mut buf = [0u8; 64];
@@ -171,13 +313,59 @@ impl Xpub {
     }
 }
 
+impl fmt::Display for Xprv {
+    /// Encodes the Xprv as a string `xprv` followed by 128 hex digits of its bytes.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "xprv{}", hex::encode(&self.to_bytes()[..]))
+    }
+}
+
+impl FromStr for Xprv {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, ParseError> {
+        decode_key(s, "xprv")
+            .and_then(|bytes| Xprv::from_bytes(&bytes).ok_or(ParseError::InvalidKey))
+    }
+}
+
+impl fmt::Display for Xpub {
+    /// Encodes the Xpub as a string `xpub` followed by 128 hex digits of its bytes.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "xpub{}", hex::encode(&self.to_bytes()[..]))
+    }
+}
+
+impl FromStr for Xpub {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, ParseError> {
+        decode_key(s, "xpub")
+            .and_then(|bytes| Xpub::from_bytes(&bytes).ok_or(ParseError::InvalidKey))
+    }
+}
+
+/// Strips the prefix and decodes the hex-encoded key bytes.
+fn decode_key(s: &str, prefix: &str) -> Result<Vec<u8>, ParseError> {
+    if !s.starts_with(prefix) {
+        return Err(ParseError::InvalidKey);
+    }
+    hex::decode(&s[prefix.len()..]).map_err(|_| ParseError::InvalidKey)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use hex;
     use rand::SeedableRng;
     use rand_chacha::ChaChaRng;
 
+    #[test]
+    fn zeroize_xprv() {
+        let mut xprv = Xprv::random(ChaChaRng::from_seed([0u8; 32]));
+        xprv.zeroize();
+        assert_eq!(xprv.to_bytes()[..], [0u8; 64][..]);
+    }
+
     #[test]
     fn random_xprv_test() {
         let seed = [0u8; 32];
@@ -205,7 +393,7 @@ mod tests {
 
         assert_eq!(
             hex::encode(xprv.scalar.as_bytes()),
-            "4a53c3fbbc59970ee5f85af813875dffc13a904a2e53ae7e65fa0dea6e62c901"
+            "55d65740c47cff19c35c2787dbc0e207e901fbb311caa4d583da8efdc7088b03"
         );
         assert_eq!(
             to_hex_32(xprv.dk),
@@ -215,6 +403,8 @@ mod tests {
             to_hex_32(xprv.precompressed_pubkey.to_bytes()),
             "7414c0c5238c2277318ba3e51fc6fb8e836a2d9b4c04508f93cd5a455422221b"
         );
+        // checks that the derived scalar matches the derived pubkey
+        assert_eq!(xprv.to_xpub().point.compress(), xprv.precompressed_pubkey);
     }
 
     #[test]
@@ -313,6 +503,110 @@ mod tests {
         );
     }
 
+    #[test]
+    fn leaf_derivation_test() {
+        let seed = [0u8; 32];
+        let mut rng = ChaChaRng::from_seed(seed);
+        let xprv = Xprv::random(&mut rng);
+        let xpub = xprv.to_xpub();
+
+        let privkey = xprv.derive_key(|t| t.commit_u64(b"invoice", 5));
+        let pubkey = xpub.derive_key(|t| t.commit_u64(b"invoice", 5));
+        assert_eq!(
+            (privkey * &constants::RISTRETTO_BASEPOINT_POINT).compress(),
+            pubkey
+        );
+
+        // leaf keys are domain-separated from the intermediate keys
+        let intermediate = xpub.derive_intermediate_key(|t| t.commit_u64(b"invoice", 5));
+        assert_ne!(intermediate.precompressed_pubkey, pubkey);
+    }
+
+    #[test]
+    fn path_derivation_test() {
+        let seed = [0u8; 32];
+        let mut rng = ChaChaRng::from_seed(seed);
+        let xprv = Xprv::random(&mut rng);
+        let xpub = xprv.to_xpub();
+
+        let path: DerivationPath = "m/1/2/3".parse().unwrap();
+        let child_xprv = xprv.derive_path(&path);
+        let child_xpub = xpub.derive_path(&path).unwrap();
+        assert_eq!(
+            child_xprv.to_xpub().to_bytes()[..],
+            child_xpub.to_bytes()[..]
+        );
+
+        // the same as deriving the children one by one
+        let step_xpub = xpub
+            .derive_child(ChildIndex::Normal(1))
+            .and_then(|k| k.derive_child(ChildIndex::Normal(2)))
+            .and_then(|k| k.derive_child(ChildIndex::Normal(3)))
+            .unwrap();
+        assert_eq!(step_xpub.to_bytes()[..], child_xpub.to_bytes()[..]);
+
+        // the root path returns the key itself
+        let root = xprv.derive_path(&"m".parse().unwrap());
+        assert_eq!(root.to_bytes()[..], xprv.to_bytes()[..]);
+    }
+
+    #[test]
+    fn hardened_derivation_test() {
+        let seed = [0u8; 32];
+        let mut rng = ChaChaRng::from_seed(seed);
+        let xprv = Xprv::random(&mut rng);
+        let xpub = xprv.to_xpub();
+
+        let path: DerivationPath = "m/1/2'/3".parse().unwrap();
+        assert!(xpub.derive_path(&path).is_none());
+        assert!(xpub.derive_child(ChildIndex::Hardened(2)).is_none());
+
+        // hardened and normal children with the same index are unrelated
+        let hardened = xprv.derive_child(ChildIndex::Hardened(2));
+        let normal = xprv.derive_child(ChildIndex::Normal(2));
+        assert_ne!(hardened.to_bytes()[..], normal.to_bytes()[..]);
+        assert_eq!(
+            hardened.to_xpub().point.compress(),
+            hardened.precompressed_pubkey
+        );
+
+        // normal descendants of a hardened key can be derived from its xpub
+        let parent_xpub = xprv.derive_path(&"m/1/2'".parse().unwrap()).to_xpub();
+        let child_xpub = parent_xpub.derive_child(ChildIndex::Normal(3)).unwrap();
+        let child_xprv = xprv.derive_path(&path);
+        assert_eq!(
+            child_xprv.to_xpub().to_bytes()[..],
+            child_xpub.to_bytes()[..]
+        );
+    }
+
+    #[test]
+    fn encode_keys_test() {
+        let seed = [0u8; 32];
+        let mut rng = ChaChaRng::from_seed(seed);
+        let xprv = Xprv::random(&mut rng);
+        let xpub = xprv.to_xpub();
+
+        assert_eq!(
+            xprv.to_string(),
+            "xprv4a53c3fbbc59970ee5f85af813875dffc13a904a2e53ae7e65fa0dea6e62c9019f07e7be5551387a98ba977c732d080dcb0f29a048e3656912c6533e32ee7aed"
+        );
+        assert_eq!(
+            xpub.to_string(),
+            "xpub9c66a339c8344f922fc3206cb5dae814a594c0177dd3235c254d9c409a65b8089f07e7be5551387a98ba977c732d080dcb0f29a048e3656912c6533e32ee7aed"
+        );
+
+        let decoded_xprv: Xprv = xprv.to_string().parse().unwrap();
+        assert_eq!(decoded_xprv.to_bytes()[..], xprv.to_bytes()[..]);
+        let decoded_xpub: Xpub = xpub.to_string().parse().unwrap();
+        assert_eq!(decoded_xpub.to_bytes()[..], xpub.to_bytes()[..]);
+
+        // keys are not accepted with the wrong prefix or length
+        assert!(xpub.to_string().parse::<Xprv>().is_err());
+        assert!(xprv.to_string().parse::<Xpub>().is_err());
+        assert!("xprv00".parse::<Xprv>().is_err());
+    }
+
     fn to_hex_32(input: [u8; 32]) -> String {
         return hex::encode(&input[..]);
     }
@@ -320,5 +614,4 @@ mod tests {
     fn to_hex_64(input: [u8; 64]) -> String {
         return hex::encode(&input[..]);
     }
-
 }
@@ -0,0 +1,230 @@
+//! Mnemonic phrases compatible with BIP39 for backing up the root key.
+
+use core::fmt;
+use core::str::FromStr;
+use hmac::Hmac;
+use rand::{CryptoRng, RngCore};
+use sha2::{Digest, Sha256, Sha512};
+use subtle::ConstantTimeEq;
+use unicode_normalization::UnicodeNormalization;
+use zeroize::Zeroize;
+
+use crate::errors::ParseError;
+use crate::Xprv;
+
+/// BIP39 English word list, sorted alphabetically.
+const WORDLIST: &str = include_str!("english.txt");
+
+/// Number of PBKDF2 iterations in the seed derivation.
+const SEED_ITERATIONS: usize = 2048;
+
+/// Mnemonic phrase of 12 to 24 words encoding 128 to 256 bits of entropy.
+/// The entropy is zeroized when dropped.
+#[derive(Clone)]
+pub struct Mnemonic {
+    entropy: Vec<u8>,
+}
+
+impl Mnemonic {
+    /// Generates a random mnemonic with the given number of words: 12, 15, 18, 21 or 24.
+    pub fn random<R: RngCore + CryptoRng>(
+        word_count: usize,
+        rng: &mut R,
+    ) -> Result<Self, ParseError> {
+        if word_count % 3 != 0 || word_count < 12 || word_count > 24 {
+            return Err(ParseError::InvalidMnemonic);
+        }
+        let mut entropy = vec![0u8; word_count / 3 * 4];
+        rng.fill_bytes(&mut entropy);
+        Ok(Mnemonic { entropy })
+    }
+
+    /// Creates a mnemonic from 16, 20, 24, 28 or 32 bytes of entropy.
+    pub fn from_entropy(entropy: &[u8]) -> Result<Self, ParseError> {
+        if entropy.len() % 4 != 0 || entropy.len() < 16 || entropy.len() > 32 {
+            return Err(ParseError::InvalidMnemonic);
+        }
+        Ok(Mnemonic {
+            entropy: entropy.to_vec(),
+        })
+    }
+
+    /// Returns the entropy encoded by the mnemonic.
+    pub fn entropy(&self) -> &[u8] {
+        &self.entropy
+    }
+
+    /// Returns the words of the mnemonic.
+    pub fn words(&self) -> Vec<&'static str> {
+        let wordlist = wordlist();
+        let mut bits = self.entropy.clone();
+        bits.push(checksum(&self.entropy));
+        let words = (0..self.entropy.len() * 3 / 4)
+            .map(|i| {
+                let index = (i * 11..(i + 1) * 11)
+                    .fold(0usize, |acc, b| (acc << 1) | bit(&bits, b) as usize);
+                wordlist[index]
+            })
+            .collect();
+        bits.zeroize();
+        words
+    }
+
+    /// Derives the 64-byte BIP39 seed from the mnemonic and the passphrase
+    /// (an empty string if there is no passphrase).
+    pub fn to_seed(&self, passphrase: &str) -> [u8; 64] {
+        // Words of the English list are already in NFKD.
+        let mut phrase = self.to_string().into_bytes();
+        let mut salt = format!("mnemonic{}", passphrase)
+            .nfkd()
+            .collect::<String>()
+            .into_bytes();
+        let mut seed = [0u8; 64];
+        pbkdf2::pbkdf2::<Hmac<Sha512>>(&phrase, &salt, SEED_ITERATIONS, &mut seed);
+        phrase.zeroize();
+        salt.zeroize();
+        seed
+    }
+
+    /// Derives the root key from the seed of the mnemonic and the passphrase.
+    pub fn to_xprv(&self, passphrase: &str) -> Xprv {
+        let mut seed = self.to_seed(passphrase);
+        let xprv = Xprv::from_seed(&seed);
+        seed.zeroize();
+        xprv
+    }
+}
+
+impl fmt::Display for Mnemonic {
+    /// Writes the words separated by single spaces.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.words().join(" "))
+    }
+}
+
+impl FromStr for Mnemonic {
+    type Err = ParseError;
+
+    /// Parses the words separated by whitespace and verifies the checksum.
+    fn from_str(s: &str) -> Result<Self, ParseError> {
+        let wordlist = wordlist();
+        let indices = s
+            .split_whitespace()
+            .map(|word| {
+                wordlist
+                    .binary_search_by(|w| (*w).cmp(word))
+                    .map_err(|_| ParseError::InvalidMnemonic)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if indices.len() % 3 != 0 || indices.len() < 12 || indices.len() > 24 {
+            return Err(ParseError::InvalidMnemonic);
+        }
+
+        // Entropy followed by the checksum in the high bits of the last byte.
+        let entropy_len = indices.len() / 3 * 4;
+        let mut bits = vec![0u8; entropy_len + 1];
+        for (i, index) in indices.iter().enumerate() {
+            for b in 0..11 {
+                if (index >> (10 - b)) & 1 == 1 {
+                    let pos = i * 11 + b;
+                    bits[pos / 8] |= 0x80 >> (pos % 8);
+                }
+            }
+        }
+        // The checksum is derived from the secret entropy, so it is compared in constant time.
+        let valid = checksum(&bits[..entropy_len]).ct_eq(&bits[entropy_len]);
+        bits.truncate(entropy_len);
+        let mnemonic = Mnemonic { entropy: bits };
+        if bool::from(valid) {
+            Ok(mnemonic)
+        } else {
+            Err(ParseError::InvalidMnemonic)
+        }
+    }
+}
+
+impl Drop for Mnemonic {
+    fn drop(&mut self) {
+        self.entropy.zeroize();
+    }
+}
+
+fn wordlist() -> Vec<&'static str> {
+    WORDLIST.lines().collect()
+}
+
+/// Returns the first `entropy.len()/4` bits of SHA-256 of the entropy, in the high bits of the byte.
+fn checksum(entropy: &[u8]) -> u8 {
+    let mask = (0xff00u16 >> (entropy.len() / 4)) as u8;
+    Sha256::digest(entropy)[0] & mask
+}
+
+/// Returns the bit at the position, counting from the highest bit of the first byte.
+fn bit(bytes: &[u8], pos: usize) -> u8 {
+    (bytes[pos / 8] >> (7 - pos % 8)) & 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bip39_vectors() {
+        let vectors = [
+            (
+                "00000000000000000000000000000000",
+                "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about",
+                "c55257c360c07c72029aebc1b53c05ed0362ada38ead3e3e9efa3708e53495531f09a6987599d18264c1e1c92f2cf141630c7a3c4ab7c81b2f001698e7463b04",
+            ),
+            (
+                "7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f",
+                "legal winner thank year wave sausage worth useful legal winner thank yellow",
+                "2e8905819b8723fe2c1d161860e5ee1830318dbf49a83bd451cfb8440c28bd6fa457fe1296106559a3c80937a1c1069be3a3a5bd381ee6260e8d9739fce1f607",
+            ),
+            (
+                "ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff",
+                "zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo vote",
+                "dd48c104698c30cfe2b6142103248622fb7bb0ff692eebb00089b32d22484e1613912f0a5b694407be899ffd31ed3992c456cdf60f5d4564b8ba3f05a69890ad",
+            ),
+        ];
+        for (entropy, phrase, seed) in vectors.iter() {
+            let mnemonic = Mnemonic::from_entropy(&hex::decode(entropy).unwrap()).unwrap();
+            assert_eq!(mnemonic.to_string(), *phrase);
+            assert_eq!(hex::encode(&mnemonic.to_seed("TREZOR")[..]), *seed);
+
+            let parsed = Mnemonic::from_str(phrase).unwrap();
+            assert_eq!(parsed.entropy(), mnemonic.entropy());
+        }
+    }
+
+    #[test]
+    fn invalid_mnemonics() {
+        // Wrong checksum
+        assert!(Mnemonic::from_str(&["abandon"; 12].join(" ")).is_err());
+        // Unknown word
+        assert!(Mnemonic::from_str(
+            "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandonment"
+        )
+        .is_err());
+        // Wrong word count
+        assert!(Mnemonic::from_str(&["abandon"; 11].join(" ")).is_err());
+        assert!(Mnemonic::from_str(&["zoo"; 13].join(" ")).is_err());
+        assert!(Mnemonic::from_entropy(&[0u8; 15]).is_err());
+        assert!(Mnemonic::random(13, &mut rand::thread_rng()).is_err());
+    }
+
+    #[test]
+    fn root_key() {
+        let mnemonic = Mnemonic::random(24, &mut rand::thread_rng()).unwrap();
+        assert_eq!(mnemonic.words().len(), 24);
+        let restored = Mnemonic::from_str(&mnemonic.to_string()).unwrap();
+        assert_eq!(
+            restored.to_xprv("").to_bytes()[..],
+            mnemonic.to_xprv("").to_bytes()[..]
+        );
+        assert_ne!(
+            restored.to_xprv("passphrase").to_bytes()[..],
+            mnemonic.to_xprv("").to_bytes()[..]
+        );
+    }
+}
@@ -0,0 +1,162 @@
+//! Derivation paths: sequences of child indices written as `m/1/2'/3`.
+
+use core::fmt;
+use core::str::FromStr;
+
+use crate::errors::ParseError;
+
+/// Index of a child key at one level of the hierarchy.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ChildIndex {
+    /// Child key that can be derived from both Xprv and Xpub.
+    Normal(u32),
+    /// Child key that can be derived only from Xprv.
+    Hardened(u32),
+}
+
+/// Derivation path from a root key to one of its descendants.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DerivationPath(Vec<ChildIndex>);
+
+impl ChildIndex {
+    /// Returns the numeric value of the index.
+    pub fn index(&self) -> u32 {
+        match self {
+            ChildIndex::Normal(i) => *i,
+            ChildIndex::Hardened(i) => *i,
+        }
+    }
+
+    /// Returns true if the index is hardened.
+    pub fn is_hardened(&self) -> bool {
+        match self {
+            ChildIndex::Normal(_) => false,
+            ChildIndex::Hardened(_) => true,
+        }
+    }
+}
+
+impl DerivationPath {
+    /// Creates a path from a list of child indices, starting at the root.
+    pub fn new(indices: Vec<ChildIndex>) -> Self {
+        DerivationPath(indices)
+    }
+
+    /// Returns the child indices of the path.
+    pub fn indices(&self) -> &[ChildIndex] {
+        &self.0
+    }
+
+    /// Returns true if the path contains hardened indices
+    /// and therefore cannot be followed from an Xpub.
+    pub fn is_hardened(&self) -> bool {
+        self.0.iter().any(|i| i.is_hardened())
+    }
+
+    /// Returns a path extended with one more child index.
+    pub fn child(&self, index: ChildIndex) -> Self {
+        let mut indices = self.0.clone();
+        indices.push(index);
+        DerivationPath(indices)
+    }
+}
+
+impl fmt::Display for ChildIndex {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ChildIndex::Normal(i) => write!(f, "{}", i),
+            ChildIndex::Hardened(i) => write!(f, "{}'", i),
+        }
+    }
+}
+
+impl FromStr for ChildIndex {
+    type Err = ParseError;
+
+    /// Parses an index such as `7`, or a hardened index such as `7'` or `7h`.
+    fn from_str(s: &str) -> Result<Self, ParseError> {
+        let (digits, hardened) = if s.ends_with('\'') || s.ends_with('h') {
+            (&s[..s.len() - 1], true)
+        } else {
+            (s, false)
+        };
+        // `u32::from_str` also accepts a leading `+`, which is not a valid path segment.
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(ParseError::InvalidPath);
+        }
+        let i = digits.parse::<u32>().map_err(|_| ParseError::InvalidPath)?;
+        if hardened {
+            Ok(ChildIndex::Hardened(i))
+        } else {
+            Ok(ChildIndex::Normal(i))
+        }
+    }
+}
+
+impl fmt::Display for DerivationPath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "m")?;
+        for index in self.0.iter() {
+            write!(f, "/{}", index)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for DerivationPath {
+    type Err = ParseError;
+
+    /// Parses a path such as `m/1/2'/3`. The path `m` denotes the root key itself.
+    fn from_str(s: &str) -> Result<Self, ParseError> {
+        let mut segments = s.split('/');
+        if segments.next() != Some("m") {
+            return Err(ParseError::InvalidPath);
+        }
+        segments
+            .map(ChildIndex::from_str)
+            .collect::<Result<Vec<_>, _>>()
+            .map(DerivationPath)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_path() {
+        let path: DerivationPath = "m/1/2'/3h".parse().unwrap();
+        assert_eq!(
+            path.indices(),
+            &[
+                ChildIndex::Normal(1),
+                ChildIndex::Hardened(2),
+                ChildIndex::Hardened(3)
+            ]
+        );
+        assert!(path.is_hardened());
+        assert_eq!(path.to_string(), "m/1/2'/3'");
+
+        let root: DerivationPath = "m".parse().unwrap();
+        assert_eq!(root, DerivationPath::default());
+        assert_eq!(root.child(ChildIndex::Normal(0)).to_string(), "m/0");
+    }
+
+    #[test]
+    fn reject_invalid_paths() {
+        for s in &[
+            "",
+            "1/2",
+            "M/1",
+            "m/",
+            "m//1",
+            "m/x",
+            "m/1''",
+            "m/+1",
+            "m/-1",
+            "m/4294967296",
+        ] {
+            assert_eq!(s.parse::<DerivationPath>(), Err(ParseError::InvalidPath));
+        }
+    }
+}
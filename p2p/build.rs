@@ -0,0 +1,9 @@
+fn main() {
+    // The gRPC server and messages are generated from the schema only with the `grpc` feature.
+    #[cfg(feature = "grpc")]
+    tower_grpc_build::Config::new()
+        .enable_server(true)
+        .enable_client(false)
+        .build(&["proto/api.proto"], &["proto"])
+        .unwrap_or_else(|e| panic!("protobuf compilation failed: {}", e));
+}
@@ -0,0 +1,180 @@
+//! API: submission of transactions and queries of the chain state for wallets and explorers.
+//!
+//! `Api` is independent of the transport: it takes and returns plain data types
+//! with the hashes and serialized blocks encoded in hex. `RpcServer` exposes it over JSON-RPC.
+
+use blockchain::{Block, BlockHeader, BlockID};
+use futures::Stream;
+use serde::{Deserialize, Serialize};
+use zkvm::{ContractID, Tx};
+
+use crate::errors::ApiError;
+use crate::network::Network;
+
+/// Node API backed by the network's node.
+#[derive(Clone)]
+pub struct Api {
+    network: Network,
+}
+
+/// Block header with the hashes encoded in hex.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HeaderInfo {
+    /// ID of the block.
+    pub id: String,
+    /// Version of the block.
+    pub version: u64,
+    /// Height of the block.
+    pub height: u64,
+    /// ID of the preceding block.
+    pub prev_id: String,
+    /// Timestamp of the block in milliseconds since the Unix epoch.
+    pub timestamp_ms: u64,
+    /// Merkle root of the transaction IDs.
+    pub txroot: String,
+    /// Root of the utreexo forest after applying the block.
+    pub utxoroot: String,
+    /// Extension data.
+    pub ext: String,
+}
+
+/// Block with its header and its serialized bytes encoded in hex.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BlockInfo {
+    /// Header of the block.
+    pub header: HeaderInfo,
+    /// Number of transactions in the block.
+    pub tx_count: usize,
+    /// Serialized block, as in the `Block` message.
+    pub block: String,
+}
+
+/// Transaction in the mempool.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MempoolTxInfo {
+    /// ID of the transaction.
+    pub id: String,
+    /// Total fee paid by the transaction.
+    pub fee: u64,
+    /// Size of the transaction in bytes.
+    pub size: u64,
+}
+
+/// Contents of the mempool.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MempoolInfo {
+    /// Total size of the transactions in bytes.
+    pub size: u64,
+    /// Transactions in the order of their priority for inclusion in a block.
+    pub txs: Vec<MempoolTxInfo>,
+}
+
+/// Proof that an output is unspent, relative to the utreexo roots of the tip.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct UtxoProofInfo {
+    /// Height of the block for which the proof is valid.
+    pub height: u64,
+    /// Roots of the utreexo trees, from the largest tree to the smallest one.
+    pub roots: Vec<String>,
+    /// Position of the output among the leaves of its tree.
+    pub position: u64,
+    /// Hashes of the neighbors, from the leaf up to the root.
+    pub neighbors: Vec<String>,
+}
+
+impl Api {
+    /// Creates the API for the network's node.
+    pub fn new(network: Network) -> Self {
+        Api { network }
+    }
+
+    /// Verifies the transaction, adds it to the mempool and announces it to the peers.
+    /// Returns the ID of the transaction encoded in hex.
+    pub fn submit_tx(&self, tx: Tx) -> Result<String, ApiError> {
+        let id = self.network.submit_tx(tx).map_err(ApiError::Rejected)?;
+        Ok(hex::encode(&id.0))
+    }
+
+    /// Returns the block at the given height.
+    pub fn get_block(&self, height: u64) -> Result<BlockInfo, ApiError> {
+        let node = self.network.node();
+        let node = node.lock().unwrap();
+        node.block(height)
+            .map(BlockInfo::from)
+            .ok_or(ApiError::NotFound)
+    }
+
+    /// Returns the block with the given ID.
+    pub fn get_block_by_id(&self, id: &BlockID) -> Result<BlockInfo, ApiError> {
+        let node = self.network.node();
+        let node = node.lock().unwrap();
+        node.block_by_id(id)
+            .map(BlockInfo::from)
+            .ok_or(ApiError::NotFound)
+    }
+
+    /// Returns the transactions in the mempool.
+    pub fn get_mempool(&self) -> MempoolInfo {
+        let node = self.network.node();
+        let node = node.lock().unwrap();
+        let mempool = node.mempool();
+        MempoolInfo {
+            size: mempool.size(),
+            txs: mempool
+                .candidates()
+                .into_iter()
+                .map(|tx| MempoolTxInfo {
+                    id: hex::encode(&tx.id.0),
+                    fee: tx.feerate.fee(),
+                    size: tx.feerate.size(),
+                })
+                .collect(),
+        }
+    }
+
+    /// Returns the proof that the output is unspent at the tip.
+    pub fn get_utxo_proof(&self, contract_id: &ContractID) -> Result<UtxoProofInfo, ApiError> {
+        let node = self.network.node();
+        let node = node.lock().unwrap();
+        let bridge = node.bridge();
+        let proof = bridge.utxo_proof(contract_id).ok_or(ApiError::NotFound)?;
+        Ok(UtxoProofInfo {
+            height: bridge.height(),
+            roots: proof.roots.iter().map(hex::encode).collect(),
+            position: proof.path.position,
+            neighbors: proof.path.neighbors.iter().map(hex::encode).collect(),
+        })
+    }
+
+    /// Returns a stream of the headers of the new blocks applied by the node.
+    pub fn subscribe_blocks(&self) -> impl Stream<Item = HeaderInfo, Error = ()> {
+        self.network
+            .subscribe_blocks()
+            .map(|header| HeaderInfo::from(&header))
+    }
+}
+
+impl<'a> From<&'a BlockHeader> for HeaderInfo {
+    fn from(header: &BlockHeader) -> Self {
+        HeaderInfo {
+            id: hex::encode(&header.id().0),
+            version: header.version,
+            height: header.height,
+            prev_id: hex::encode(&header.prev_id.0),
+            timestamp_ms: header.timestamp_ms,
+            txroot: hex::encode(&header.txroot),
+            utxoroot: hex::encode(&header.utxoroot),
+            ext: hex::encode(&header.ext),
+        }
+    }
+}
+
+impl<'a> From<&'a Block> for BlockInfo {
+    fn from(block: &Block) -> Self {
+        BlockInfo {
+            header: HeaderInfo::from(&block.header),
+            tx_count: block.txs.len(),
+            block: hex::encode(block.to_bytes()),
+        }
+    }
+}
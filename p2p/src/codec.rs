@@ -0,0 +1,91 @@
+//! Framing of the messages in a byte stream: each message is prefixed with its LE32 length.
+
+use byteorder::{ByteOrder, LittleEndian};
+use bytes::{BufMut, BytesMut};
+use tokio::codec::{Decoder, Encoder};
+
+use crate::errors::P2PError;
+use crate::messages::Message;
+
+/// Maximum size of an encoded message in bytes.
+pub const MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
+/// Codec that splits the stream into messages.
+#[derive(Clone, Debug, Default)]
+pub struct MessageCodec;
+
+impl Decoder for MessageCodec {
+    type Item = Message;
+    type Error = P2PError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Message>, P2PError> {
+        if src.len() < 4 {
+            return Ok(None);
+        }
+        let len = LittleEndian::read_u32(&src[..4]) as usize;
+        if len > MAX_MESSAGE_SIZE {
+            return Err(P2PError::MessageTooLarge);
+        }
+        if src.len() < 4 + len {
+            src.reserve(4 + len - src.len());
+            return Ok(None);
+        }
+        let frame = src.split_to(4 + len);
+        Message::from_bytes(&frame[4..]).map(Some)
+    }
+}
+
+impl Encoder for MessageCodec {
+    type Item = Message;
+    type Error = P2PError;
+
+    fn encode(&mut self, msg: Message, dst: &mut BytesMut) -> Result<(), P2PError> {
+        let payload = msg.to_bytes();
+        if payload.len() > MAX_MESSAGE_SIZE {
+            return Err(P2PError::MessageTooLarge);
+        }
+        dst.reserve(4 + payload.len());
+        dst.put_u32_le(payload.len() as u32);
+        dst.put_slice(&payload);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn framing() {
+        let mut codec = MessageCodec;
+        let mut buf = BytesMut::new();
+        codec.encode(Message::GetBlocks(3), &mut buf).unwrap();
+        codec.encode(Message::GetMempool, &mut buf).unwrap();
+        assert_eq!(buf.len(), 4 + 9 + 4 + 1);
+
+        // Incomplete frame is not decoded until the rest of it arrives.
+        let mut partial = BytesMut::from(&buf[..6]);
+        assert!(codec.decode(&mut partial).unwrap().is_none());
+        assert_eq!(partial.len(), 6);
+
+        match codec.decode(&mut buf).unwrap() {
+            Some(Message::GetBlocks(3)) => {}
+            _ => panic!("Must decode the first message"),
+        }
+        match codec.decode(&mut buf).unwrap() {
+            Some(Message::GetMempool) => {}
+            _ => panic!("Must decode the second message"),
+        }
+        assert!(codec.decode(&mut buf).unwrap().is_none());
+    }
+
+    #[test]
+    fn rejects_large_frames() {
+        let mut buf = BytesMut::new();
+        buf.put_u32_le(MAX_MESSAGE_SIZE as u32 + 1);
+        assert_eq!(
+            MessageCodec.decode(&mut buf).map(|_| ()),
+            Err(P2PError::MessageTooLarge)
+        );
+    }
+}
@@ -0,0 +1,251 @@
+//! Compact blocks: blocks relayed with short transaction IDs, similar to BIP152.
+//!
+//! Peers usually have most transactions of a new block in their mempools,
+//! so instead of the full transactions with their proofs the block is sent as a header
+//! and 6-byte short IDs of the transactions. The receiver fills in the transactions
+//! from its mempool and requests only the missing ones.
+//!
+//! Short IDs are keyed with the block ID and a nonce chosen by the sender,
+//! so that colliding transactions cannot be crafted in advance for all peers.
+
+use blockchain::{Block, BlockHeader, BlockID};
+use merlin::Transcript;
+use std::collections::HashMap;
+use zkvm::{Tx, TxID};
+
+use crate::errors::P2PError;
+
+/// Length of the short transaction ID in bytes.
+pub const SHORT_ID_LEN: usize = 6;
+
+/// Short transaction ID used in compact blocks.
+pub type ShortID = [u8; SHORT_ID_LEN];
+
+/// Block with the transactions replaced by their short IDs.
+#[derive(Clone)]
+pub struct CompactBlock {
+    /// Header of the block.
+    pub header: BlockHeader,
+
+    /// Nonce used to compute the short IDs.
+    pub nonce: u64,
+
+    /// Short IDs of the transactions that are not prefilled, in the order of the block.
+    pub short_ids: Vec<ShortID>,
+
+    /// Transactions sent in full with their indices in the block, in increasing order.
+    pub prefilled: Vec<(usize, Tx)>,
+}
+
+/// Block reconstructed from a compact block, with some transactions possibly missing.
+pub struct PartialBlock {
+    header: BlockHeader,
+    txs: Vec<Option<Tx>>,
+}
+
+impl CompactBlock {
+    /// Creates a compact block from the block and the IDs of its transactions.
+    /// Transactions with indices in `prefill` are sent in full:
+    /// these are the ones the peers are unlikely to have.
+    pub fn new(block: &Block, txids: &[TxID], prefill: &[usize], nonce: u64) -> Self {
+        let hasher = short_id_hasher(&block.header.id(), nonce);
+        let mut short_ids = Vec::with_capacity(block.txs.len());
+        let mut prefilled = Vec::new();
+        for (i, (tx, txid)) in block.txs.iter().zip(txids.iter()).enumerate() {
+            if prefill.contains(&i) {
+                prefilled.push((i, tx.clone()));
+            } else {
+                short_ids.push(short_id(&hasher, txid));
+            }
+        }
+        CompactBlock {
+            header: block.header.clone(),
+            nonce,
+            short_ids,
+            prefilled,
+        }
+    }
+
+    /// Returns the short ID of a transaction in this compact block.
+    pub fn short_id(&self, txid: &TxID) -> ShortID {
+        short_id(&short_id_hasher(&self.header.id(), self.nonce), txid)
+    }
+
+    /// Fills in the prefilled transactions and the transactions from `known`
+    /// matching the short IDs. Known transactions with colliding short IDs are
+    /// not used, so the corresponding transactions remain missing.
+    pub fn reconstruct(self, known: &HashMap<TxID, Tx>) -> Result<PartialBlock, P2PError> {
+        let n = self.short_ids.len() + self.prefilled.len();
+        let mut txs: Vec<Option<Tx>> = vec![None; n];
+        let mut next_index = 0;
+        for (index, tx) in self.prefilled.into_iter() {
+            if index < next_index || index >= n {
+                return Err(P2PError::FormatError);
+            }
+            txs[index] = Some(tx);
+            next_index = index + 1;
+        }
+
+        let hasher = short_id_hasher(&self.header.id(), self.nonce);
+        let mut candidates: HashMap<ShortID, Option<&Tx>> = HashMap::with_capacity(known.len());
+        for (txid, tx) in known.iter() {
+            candidates
+                .entry(short_id(&hasher, txid))
+                .and_modify(|candidate| *candidate = None)
+                .or_insert(Some(tx));
+        }
+        let slots = txs.iter_mut().filter(|slot| slot.is_none());
+        for (slot, short_id) in slots.zip(self.short_ids.iter()) {
+            *slot = candidates
+                .get(short_id)
+                .and_then(|candidate| candidate.cloned());
+        }
+
+        Ok(PartialBlock {
+            header: self.header,
+            txs,
+        })
+    }
+}
+
+impl PartialBlock {
+    /// Returns the header of the block.
+    pub fn header(&self) -> &BlockHeader {
+        &self.header
+    }
+
+    /// Returns the indices of the missing transactions.
+    pub fn missing(&self) -> Vec<usize> {
+        self.txs
+            .iter()
+            .enumerate()
+            .filter(|(_, tx)| tx.is_none())
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// Fills in the missing transactions listed in the order of `missing()`.
+    pub fn fill(&mut self, txs: Vec<Tx>) -> Result<(), P2PError> {
+        let missing = self.missing();
+        if txs.len() != missing.len() {
+            return Err(P2PError::FormatError);
+        }
+        for (i, tx) in missing.into_iter().zip(txs.into_iter()) {
+            self.txs[i] = Some(tx);
+        }
+        Ok(())
+    }
+
+    /// Returns the block if all its transactions are present.
+    pub fn into_block(self) -> Option<Block> {
+        let header = self.header;
+        self.txs
+            .into_iter()
+            .collect::<Option<Vec<_>>>()
+            .map(|txs| Block { header, txs })
+    }
+}
+
+fn short_id_hasher(block_id: &BlockID, nonce: u64) -> Transcript {
+    let mut t = Transcript::new(b"ZkVM.shortid");
+    t.commit_bytes(b"blockid", &block_id.0);
+    t.commit_u64(b"nonce", nonce);
+    t
+}
+
+fn short_id(hasher: &Transcript, txid: &TxID) -> ShortID {
+    let mut t = hasher.clone();
+    t.commit_bytes(b"txid", &txid.0);
+    let mut id = [0u8; SHORT_ID_LEN];
+    t.challenge_bytes(b"id", &mut id);
+    id
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use bulletproofs::{BulletproofGens, PedersenGens};
+    use curve25519_dalek::scalar::Scalar;
+    use zkvm::{Data, Predicate, Program, Prover, Signature, TxHeader, TxTree};
+
+    /// Makes a transaction that only creates a nonce referencing the given block.
+    /// Different `maxtime` values produce different nonces.
+    pub(crate) fn make_tx(blockid: BlockID, maxtime: u64) -> (TxID, Tx) {
+        let key = Scalar::from(1u64);
+        let pred = Predicate::Key((key * PedersenGens::default().B).compress().into());
+        let program = Program::build(|p| {
+            p.push(pred)
+                .push(Data::Opaque(blockid.0.to_vec()))
+                .nonce()
+                .sign_tx()
+        });
+        let header = TxHeader {
+            version: 1,
+            mintime: 0,
+            maxtime,
+            maxcost: u64::max_value(),
+        };
+        let bp_gens = BulletproofGens::new(256, 1);
+        let (tx, txid, _) = Prover::build_tx(program, header, &bp_gens, |t, _| {
+            Signature::sign_aggregated(t, &[key])
+        })
+        .unwrap();
+        (txid, tx)
+    }
+
+    fn make_block(txs: &[(TxID, Tx)]) -> Block {
+        let txids = txs.iter().map(|(id, _)| *id).collect::<Vec<_>>();
+        let mut header = BlockHeader::make_initial(1000, [0u8; 32]);
+        header.txroot = TxTree::build(&txids).root();
+        Block {
+            header,
+            txs: txs.iter().map(|(_, tx)| tx.clone()).collect(),
+        }
+    }
+
+    #[test]
+    fn reconstruction() {
+        let txs = (0..4)
+            .map(|i| make_tx(BlockID([0u8; 32]), 1000 + i))
+            .collect::<Vec<_>>();
+        let txids = txs.iter().map(|(id, _)| *id).collect::<Vec<_>>();
+        let block = make_block(&txs);
+
+        let compact = CompactBlock::new(&block, &txids, &[2], 7);
+        assert_eq!(compact.short_ids.len(), 3);
+        assert_eq!(compact.prefilled[0].0, 2);
+        assert_eq!(compact.short_ids[1], compact.short_id(&txids[1]));
+        assert_ne!(
+            compact.short_id(&txids[1]),
+            CompactBlock::new(&block, &txids, &[], 8).short_id(&txids[1])
+        );
+
+        // The receiver knows the first two transactions.
+        let known = txs[..2].iter().cloned().collect::<HashMap<_, _>>();
+        let mut partial = compact.clone().reconstruct(&known).unwrap();
+        assert_eq!(partial.missing(), vec![3]);
+        assert_eq!(partial.fill(vec![]), Err(P2PError::FormatError));
+        partial.fill(vec![txs[3].1.clone()]).unwrap();
+        let reconstructed = partial.into_block().unwrap();
+        assert_eq!(reconstructed.to_bytes(), block.to_bytes());
+
+        // Nothing is known: all but the prefilled transactions are missing.
+        let partial = compact.clone().reconstruct(&HashMap::new()).unwrap();
+        assert_eq!(partial.missing(), vec![0, 1, 3]);
+        assert!(partial.into_block().is_none());
+
+        // Prefilled transactions must be in range and in increasing order.
+        let mut invalid = compact.clone();
+        invalid.prefilled[0].0 = 4;
+        assert_eq!(
+            invalid.reconstruct(&known).map(|_| ()),
+            Err(P2PError::FormatError)
+        );
+        let mut invalid = CompactBlock::new(&block, &txids, &[1, 2], 7);
+        invalid.prefilled.swap(0, 1);
+        assert_eq!(
+            invalid.reconstruct(&known).map(|_| ()),
+            Err(P2PError::FormatError)
+        );
+    }
+}
@@ -0,0 +1,92 @@
+//! Errors related to the peer-to-peer protocol.
+
+use blockchain::BlockchainError;
+use std::io;
+
+/// Represents an error in communication with a peer.
+#[derive(Fail, Clone, Debug, Eq, PartialEq)]
+pub enum P2PError {
+    /// This error occurs when reading from or writing to the connection fails.
+    #[fail(display = "I/O error: {:?}.", _0)]
+    IoError(io::ErrorKind),
+
+    /// This error occurs when a message is malformed.
+    #[fail(display = "Message is malformed.")]
+    FormatError,
+
+    /// This error occurs when a message exceeds the maximum size.
+    #[fail(display = "Message is too large.")]
+    MessageTooLarge,
+
+    /// This error occurs when the peer uses another version of the protocol.
+    #[fail(display = "Peer uses an incompatible protocol version.")]
+    IncompatibleVersion,
+
+    /// This error occurs when the peer's chain starts with another initial block.
+    #[fail(display = "Peer belongs to another network.")]
+    WrongNetwork,
+
+    /// This error occurs when the peer sends a message that is not expected at this point,
+    /// e.g. anything but `Hello` during the handshake.
+    #[fail(display = "Unexpected message.")]
+    UnexpectedMessage,
+
+    /// This error occurs when the connection to the peer is closed.
+    #[fail(display = "Peer is disconnected.")]
+    Disconnected,
+
+    /// This error occurs when the block or transaction received from the peer is invalid.
+    #[fail(display = "Rejected: {}", _0)]
+    Rejected(BlockchainError),
+}
+
+impl From<io::Error> for P2PError {
+    fn from(e: io::Error) -> P2PError {
+        P2PError::IoError(e.kind())
+    }
+}
+
+/// Represents an error in handling an API request.
+#[cfg(feature = "api")]
+#[derive(Fail, Clone, Debug, Eq, PartialEq)]
+pub enum ApiError {
+    /// This error occurs when the request is not valid JSON.
+    #[fail(display = "Parse error.")]
+    ParseError,
+
+    /// This error occurs when the request is not a valid JSON-RPC request.
+    #[fail(display = "Invalid request.")]
+    InvalidRequest,
+
+    /// This error occurs when the requested method does not exist.
+    #[fail(display = "Method not found: {}.", _0)]
+    MethodNotFound(String),
+
+    /// This error occurs when the parameters of the request are missing or malformed.
+    #[fail(display = "Invalid parameters: {}.", _0)]
+    InvalidParams(String),
+
+    /// This error occurs when the requested block or unspent output is not known to the node.
+    #[fail(display = "Not found.")]
+    NotFound,
+
+    /// This error occurs when the node rejects the submitted transaction.
+    #[fail(display = "{}", _0)]
+    Rejected(P2PError),
+}
+
+#[cfg(feature = "api")]
+impl ApiError {
+    /// Returns the JSON-RPC error code: the standard codes for the malformed requests,
+    /// and the codes from the range reserved for the server errors otherwise.
+    pub fn code(&self) -> i64 {
+        match self {
+            ApiError::ParseError => -32700,
+            ApiError::InvalidRequest => -32600,
+            ApiError::MethodNotFound(_) => -32601,
+            ApiError::InvalidParams(_) => -32602,
+            ApiError::NotFound => -32001,
+            ApiError::Rejected(_) => -32002,
+        }
+    }
+}
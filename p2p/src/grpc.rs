@@ -0,0 +1,293 @@
+//! gRPC server: exposes the `Api` as the `NodeApi` service of `proto/api.proto` over HTTP/2.
+//!
+//! The service wraps the `Api` methods like `RpcServer`, but the hashes, transactions and blocks
+//! are raw bytes instead of hex, and the errors are reported as gRPC statuses.
+
+use blockchain::BlockID;
+use futures::{future, Future, Stream};
+use std::net::SocketAddr;
+use tokio::net::TcpListener;
+use tower_grpc::{Code, Request, Response, Status};
+use tower_hyper::server::{Http, Server};
+use zkvm::{ContractID, Tx};
+
+use crate::api::{self, Api};
+use crate::errors::{ApiError, P2PError};
+
+/// Messages and the service generated from `proto/api.proto`.
+#[allow(missing_docs)]
+pub mod proto {
+    include!(concat!(env!("OUT_DIR"), "/slingshot.p2p.rs"));
+}
+
+/// gRPC server for the node API.
+#[derive(Clone)]
+pub struct GrpcServer {
+    api: Api,
+}
+
+type GrpcResult<T> = future::FutureResult<Response<T>, Status>;
+
+impl GrpcServer {
+    /// Creates a server for the API.
+    pub fn new(api: Api) -> Self {
+        GrpcServer { api }
+    }
+
+    /// Binds to the address and returns a future that accepts the client connections.
+    /// The connections are spawned on the default tokio executor.
+    pub fn listen(
+        &self,
+        addr: &SocketAddr,
+    ) -> Result<impl Future<Item = (), Error = ()>, P2PError> {
+        let listener = TcpListener::bind(addr)?;
+        let mut server = Server::new(proto::server::NodeApiServer::new(self.clone()));
+        let http = Http::new().http2_only(true).clone();
+        Ok(listener.incoming().map_err(|_| ()).for_each(move |socket| {
+            tokio::spawn(server.serve_with(socket, http.clone()).map_err(|_| ()));
+            Ok(())
+        }))
+    }
+}
+
+impl proto::server::NodeApi for GrpcServer {
+    type SubmitTxFuture = GrpcResult<proto::SubmitTxResponse>;
+    type GetBlockFuture = GrpcResult<proto::BlockInfo>;
+    type GetMempoolFuture = GrpcResult<proto::MempoolInfo>;
+    type GetUtxoProofFuture = GrpcResult<proto::UtxoProofInfo>;
+    type SubscribeBlocksStream = Box<dyn Stream<Item = proto::HeaderInfo, Error = Status> + Send>;
+    type SubscribeBlocksFuture = GrpcResult<Self::SubscribeBlocksStream>;
+
+    fn submit_tx(&mut self, request: Request<proto::SubmitTxRequest>) -> Self::SubmitTxFuture {
+        let result = Tx::from_bytes(&request.get_ref().tx)
+            .map_err(|_| Status::new(Code::InvalidArgument, "malformed tx"))
+            .and_then(|tx| self.api.submit_tx(tx).map_err(status))
+            .map(|id| proto::SubmitTxResponse { id: from_hex(&id) });
+        respond(result)
+    }
+
+    fn get_block(&mut self, request: Request<proto::GetBlockRequest>) -> Self::GetBlockFuture {
+        use self::proto::get_block_request::Block;
+        let result = match &request.get_ref().block {
+            Some(Block::Height(height)) => self.api.get_block(*height).map_err(status),
+            Some(Block::Id(id)) => {
+                hash(id).and_then(|id| self.api.get_block_by_id(&BlockID(id)).map_err(status))
+            }
+            None => Err(Status::new(
+                Code::InvalidArgument,
+                "expected either height or id",
+            )),
+        };
+        respond(result.map(proto::BlockInfo::from))
+    }
+
+    fn get_mempool(&mut self, _: Request<proto::GetMempoolRequest>) -> Self::GetMempoolFuture {
+        respond(Ok(proto::MempoolInfo::from(self.api.get_mempool())))
+    }
+
+    fn get_utxo_proof(
+        &mut self,
+        request: Request<proto::GetUtxoProofRequest>,
+    ) -> Self::GetUtxoProofFuture {
+        let result = hash(&request.get_ref().contract_id).and_then(|id| {
+            self.api
+                .get_utxo_proof(&ContractID::from_raw_bytes(id))
+                .map_err(status)
+        });
+        respond(result.map(proto::UtxoProofInfo::from))
+    }
+
+    fn subscribe_blocks(
+        &mut self,
+        _: Request<proto::SubscribeBlocksRequest>,
+    ) -> Self::SubscribeBlocksFuture {
+        let headers = self
+            .api
+            .subscribe_blocks()
+            .map(proto::HeaderInfo::from)
+            .map_err(|_| Status::new(Code::Unavailable, "node is stopped"));
+        respond(Ok(Box::new(headers) as Self::SubscribeBlocksStream))
+    }
+}
+
+fn respond<T>(result: Result<T, Status>) -> GrpcResult<T> {
+    future::result(result.map(Response::new))
+}
+
+/// Maps the API errors to the gRPC statuses listed in `proto/api.proto`.
+fn status(e: ApiError) -> Status {
+    let code = match e {
+        ApiError::NotFound => Code::NotFound,
+        ApiError::Rejected(_) => Code::FailedPrecondition,
+        _ => Code::InvalidArgument,
+    };
+    Status::new(code, e.to_string())
+}
+
+fn hash(bytes: &[u8]) -> Result<[u8; 32], Status> {
+    if bytes.len() != 32 {
+        return Err(Status::new(
+            Code::InvalidArgument,
+            "expected a 32-byte hash",
+        ));
+    }
+    let mut hash = [0u8; 32];
+    hash.copy_from_slice(bytes);
+    Ok(hash)
+}
+
+/// Decodes the hashes and the blocks that `Api` encodes in hex.
+fn from_hex(s: &str) -> Vec<u8> {
+    hex::decode(s).expect("API encodes the bytes in hex")
+}
+
+impl From<api::HeaderInfo> for proto::HeaderInfo {
+    fn from(header: api::HeaderInfo) -> Self {
+        proto::HeaderInfo {
+            id: from_hex(&header.id),
+            version: header.version,
+            height: header.height,
+            prev_id: from_hex(&header.prev_id),
+            timestamp_ms: header.timestamp_ms,
+            txroot: from_hex(&header.txroot),
+            utxoroot: from_hex(&header.utxoroot),
+            ext: from_hex(&header.ext),
+        }
+    }
+}
+
+impl From<api::BlockInfo> for proto::BlockInfo {
+    fn from(block: api::BlockInfo) -> Self {
+        proto::BlockInfo {
+            header: Some(proto::HeaderInfo::from(block.header)),
+            tx_count: block.tx_count as u64,
+            block: from_hex(&block.block),
+        }
+    }
+}
+
+impl From<api::MempoolInfo> for proto::MempoolInfo {
+    fn from(mempool: api::MempoolInfo) -> Self {
+        proto::MempoolInfo {
+            size: mempool.size,
+            txs: mempool
+                .txs
+                .into_iter()
+                .map(|tx| proto::MempoolTxInfo {
+                    id: from_hex(&tx.id),
+                    fee: tx.fee,
+                    size: tx.size,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<api::UtxoProofInfo> for proto::UtxoProofInfo {
+    fn from(proof: api::UtxoProofInfo) -> Self {
+        proto::UtxoProofInfo {
+            height: proof.height,
+            roots: proof.roots.iter().map(|root| from_hex(root)).collect(),
+            position: proof.position,
+            neighbors: proof.neighbors.iter().map(|n| from_hex(n)).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::proto::server::NodeApi;
+    use super::*;
+    use crate::compact::tests::make_tx;
+    use crate::network::Network;
+    use crate::node::tests::{empty_block, make_node};
+
+    fn make_server() -> (Network, GrpcServer) {
+        let network = Network::new(make_node());
+        let server = GrpcServer::new(Api::new(network.clone()));
+        (network, server)
+    }
+
+    fn get_block(
+        server: &mut GrpcServer,
+        block: proto::get_block_request::Block,
+    ) -> Result<proto::BlockInfo, Code> {
+        server
+            .get_block(Request::new(proto::GetBlockRequest { block: Some(block) }))
+            .wait()
+            .map(Response::into_inner)
+            .map_err(|status| status.code())
+    }
+
+    #[test]
+    fn blocks() {
+        use super::proto::get_block_request::Block;
+        let (network, mut server) = make_server();
+        let headers = server
+            .subscribe_blocks(Request::new(proto::SubscribeBlocksRequest {}))
+            .wait()
+            .unwrap()
+            .into_inner();
+
+        let block = empty_block(network.node().lock().unwrap().state().tip());
+        let id = block.header.id().0.to_vec();
+        network.submit_block(block.clone()).unwrap();
+
+        let by_height = get_block(&mut server, Block::Height(2)).unwrap();
+        let by_id = get_block(&mut server, Block::Id(id.clone())).unwrap();
+        assert_eq!(by_height, by_id);
+        assert_eq!(by_height.header.unwrap().id, id);
+        assert_eq!(by_height.block, block.to_bytes());
+
+        assert_eq!(
+            get_block(&mut server, Block::Height(3)),
+            Err(Code::NotFound)
+        );
+        assert_eq!(
+            get_block(&mut server, Block::Id(vec![0u8; 3])),
+            Err(Code::InvalidArgument)
+        );
+
+        let header = headers.wait().next().unwrap().unwrap();
+        assert_eq!(header.id, id);
+        assert_eq!(header.height, 2);
+    }
+
+    #[test]
+    fn transactions() {
+        let (network, mut server) = make_server();
+        let initial_id = network.node().lock().unwrap().state().initial_header().id();
+        let (txid, tx) = make_tx(initial_id, 1_000_000);
+
+        let submitted = server
+            .submit_tx(Request::new(proto::SubmitTxRequest { tx: tx.to_bytes() }))
+            .wait()
+            .unwrap()
+            .into_inner();
+        assert_eq!(submitted.id, txid.0.to_vec());
+
+        let mempool = server
+            .get_mempool(Request::new(proto::GetMempoolRequest {}))
+            .wait()
+            .unwrap()
+            .into_inner();
+        assert_eq!(mempool.txs[0].id, submitted.id);
+
+        let malformed = server
+            .submit_tx(Request::new(proto::SubmitTxRequest { tx: vec![0u8] }))
+            .wait();
+        assert_eq!(
+            malformed.map(|_| ()).map_err(|status| status.code()),
+            Err(Code::InvalidArgument)
+        );
+        let proof = server
+            .get_utxo_proof(Request::new(proto::GetUtxoProofRequest {
+                contract_id: vec![0u8; 32],
+            }))
+            .wait();
+        assert_eq!(
+            proof.map(|_| ()).map_err(|status| status.code()),
+            Err(Code::NotFound)
+        );
+    }
+}
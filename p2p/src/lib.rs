@@ -0,0 +1,34 @@
+#![deny(missing_docs)]
+//! Peer-to-peer relay protocol for ZkVM blocks and transactions.
+
+#[macro_use]
+extern crate failure;
+
+#[cfg(feature = "api")]
+mod api;
+mod codec;
+mod compact;
+mod errors;
+#[cfg(feature = "grpc")]
+mod grpc;
+mod messages;
+mod network;
+mod node;
+#[cfg(feature = "api")]
+mod rpc;
+
+pub use self::codec::{MessageCodec, MAX_MESSAGE_SIZE};
+pub use self::compact::{CompactBlock, PartialBlock, ShortID, SHORT_ID_LEN};
+pub use self::errors::P2PError;
+pub use self::messages::{Hello, Inventory, Message, MAX_INVENTORY, PROTOCOL_VERSION};
+pub use self::network::Network;
+pub use self::node::{Node, Response};
+
+#[cfg(feature = "api")]
+pub use self::api::{Api, BlockInfo, HeaderInfo, MempoolInfo, MempoolTxInfo, UtxoProofInfo};
+#[cfg(feature = "api")]
+pub use self::errors::ApiError;
+#[cfg(feature = "grpc")]
+pub use self::grpc::{proto, GrpcServer};
+#[cfg(feature = "api")]
+pub use self::rpc::RpcServer;
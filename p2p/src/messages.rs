@@ -0,0 +1,373 @@
+//! Messages exchanged by the peers.
+//!
+//! Each message is encoded as a one-byte type followed by the message fields.
+//! Integers are encoded as LE64, lists and byte strings are prefixed with LE32 length.
+
+use blockchain::{Block, BlockHeader, BlockID};
+use std::io;
+use zkvm::{Reader, Tx, TxID, VMError, Writer};
+
+use crate::compact::{CompactBlock, ShortID, SHORT_ID_LEN};
+use crate::errors::P2PError;
+
+/// Version of the protocol. Peers with different versions do not connect.
+pub const PROTOCOL_VERSION: u64 = 2;
+
+/// Maximum number of items in the `Inv` and `GetData` messages.
+pub const MAX_INVENTORY: usize = 500;
+
+/// Message exchanged by the peers.
+#[derive(Clone)]
+pub enum Message {
+    /// First message sent by both peers upon connection.
+    Hello(Hello),
+    /// Announces the blocks and transactions available at the peer.
+    Inv(Vec<Inventory>),
+    /// Requests the blocks and transactions previously announced with `Inv`.
+    GetData(Vec<Inventory>),
+    /// Block sent in response to `GetData` request.
+    Block(Block),
+    /// Transaction sent in response to `GetData` request.
+    Tx(Tx),
+    /// Requests the IDs of the blocks starting with a given height, announced with `Inv`.
+    GetBlocks(u64),
+    /// Requests the IDs of the transactions in the peer's mempool, announced with `Inv`.
+    GetMempool,
+    /// Block with short transaction IDs sent in response to `GetData` request.
+    CompactBlock(CompactBlock),
+    /// Requests the transactions with given indices from a block received as `CompactBlock`.
+    GetBlockTxs(BlockID, Vec<usize>),
+    /// Transactions sent in response to `GetBlockTxs` request.
+    BlockTxs(BlockID, Vec<Tx>),
+}
+
+/// Handshake message that describes the peer's protocol and chain.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Hello {
+    /// Version of the protocol, must be equal to `PROTOCOL_VERSION`.
+    pub version: u64,
+    /// ID of the initial block of the chain.
+    pub genesis: BlockID,
+    /// Height of the peer's latest block.
+    pub height: u64,
+}
+
+/// Reference to a block or a transaction.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Inventory {
+    /// Block with a given ID.
+    Block(BlockID),
+    /// Transaction with a given ID.
+    Tx(TxID),
+    /// Block with a given ID requested as `CompactBlock`.
+    CompactBlock(BlockID),
+}
+
+impl Message {
+    /// Serializes the message into a byte array.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut w = Writer::new(Vec::new());
+        self.write(&mut w).expect("writing to a vector never fails");
+        w.into_inner()
+    }
+
+    /// Decodes the message from a byte array.
+    pub fn from_bytes(data: &[u8]) -> Result<Self, P2PError> {
+        let mut r = Reader::new(data);
+        let msg = Self::read(&mut r).map_err(|_| P2PError::FormatError)?;
+        if !r.into_inner().is_empty() {
+            return Err(P2PError::FormatError);
+        }
+        Ok(msg)
+    }
+
+    fn write<W: io::Write>(&self, w: &mut Writer<W>) -> io::Result<()> {
+        match self {
+            Message::Hello(hello) => {
+                w.write_u8(0)?;
+                w.write_u64(hello.version)?;
+                w.write_bytes(&hello.genesis.0)?;
+                w.write_u64(hello.height)
+            }
+            Message::Inv(items) => {
+                w.write_u8(1)?;
+                write_inventory(items, w)
+            }
+            Message::GetData(items) => {
+                w.write_u8(2)?;
+                write_inventory(items, w)
+            }
+            Message::Block(block) => {
+                w.write_u8(3)?;
+                let bytes = block.to_bytes();
+                w.write_size(bytes.len())?;
+                w.write_bytes(&bytes)
+            }
+            Message::Tx(tx) => {
+                w.write_u8(4)?;
+                write_tx(tx, w)
+            }
+            Message::GetBlocks(height) => {
+                w.write_u8(5)?;
+                w.write_u64(*height)
+            }
+            Message::GetMempool => w.write_u8(6),
+            Message::CompactBlock(compact) => {
+                w.write_u8(7)?;
+                let header = compact.header.to_bytes();
+                w.write_size(header.len())?;
+                w.write_bytes(&header)?;
+                w.write_u64(compact.nonce)?;
+                w.write_size(compact.short_ids.len())?;
+                for short_id in compact.short_ids.iter() {
+                    w.write_bytes(short_id)?;
+                }
+                w.write_size(compact.prefilled.len())?;
+                for (index, tx) in compact.prefilled.iter() {
+                    w.write_size(*index)?;
+                    write_tx(tx, w)?;
+                }
+                Ok(())
+            }
+            Message::GetBlockTxs(id, indices) => {
+                w.write_u8(8)?;
+                w.write_bytes(&id.0)?;
+                w.write_size(indices.len())?;
+                for index in indices.iter() {
+                    w.write_size(*index)?;
+                }
+                Ok(())
+            }
+            Message::BlockTxs(id, txs) => {
+                w.write_u8(9)?;
+                w.write_bytes(&id.0)?;
+                w.write_size(txs.len())?;
+                for tx in txs.iter() {
+                    write_tx(tx, w)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn read<R: io::Read>(r: &mut Reader<R>) -> Result<Self, VMError> {
+        match r.read_u8()? {
+            0 => Ok(Message::Hello(Hello {
+                version: r.read_u64()?,
+                genesis: BlockID(r.read_u8x32()?),
+                height: r.read_u64()?,
+            })),
+            1 => Ok(Message::Inv(read_inventory(r)?)),
+            2 => Ok(Message::GetData(read_inventory(r)?)),
+            3 => {
+                let len = r.read_size()?;
+                Block::from_bytes(&r.read_bytes(len)?)
+                    .map(Message::Block)
+                    .map_err(|_| VMError::FormatError)
+            }
+            4 => Ok(Message::Tx(read_tx(r)?)),
+            5 => Ok(Message::GetBlocks(r.read_u64()?)),
+            6 => Ok(Message::GetMempool),
+            7 => {
+                let header_len = r.read_size()?;
+                let header = BlockHeader::from_bytes(&r.read_bytes(header_len)?)
+                    .map_err(|_| VMError::FormatError)?;
+                let nonce = r.read_u64()?;
+                let mut short_ids = Vec::new();
+                for _ in 0..r.read_size()? {
+                    let mut short_id: ShortID = [0u8; SHORT_ID_LEN];
+                    short_id.copy_from_slice(&r.read_bytes(SHORT_ID_LEN)?);
+                    short_ids.push(short_id);
+                }
+                let mut prefilled = Vec::new();
+                for _ in 0..r.read_size()? {
+                    let index = r.read_size()?;
+                    prefilled.push((index, read_tx(r)?));
+                }
+                Ok(Message::CompactBlock(CompactBlock {
+                    header,
+                    nonce,
+                    short_ids,
+                    prefilled,
+                }))
+            }
+            8 => {
+                let id = BlockID(r.read_u8x32()?);
+                let mut indices = Vec::new();
+                for _ in 0..r.read_size()? {
+                    indices.push(r.read_size()?);
+                }
+                Ok(Message::GetBlockTxs(id, indices))
+            }
+            9 => {
+                let id = BlockID(r.read_u8x32()?);
+                let mut txs = Vec::new();
+                for _ in 0..r.read_size()? {
+                    txs.push(read_tx(r)?);
+                }
+                Ok(Message::BlockTxs(id, txs))
+            }
+            _ => Err(VMError::FormatError),
+        }
+    }
+}
+
+fn write_tx<W: io::Write>(tx: &Tx, w: &mut Writer<W>) -> io::Result<()> {
+    let bytes = tx.to_bytes();
+    w.write_size(bytes.len())?;
+    w.write_bytes(&bytes)
+}
+
+fn read_tx<R: io::Read>(r: &mut Reader<R>) -> Result<Tx, VMError> {
+    let len = r.read_size()?;
+    Tx::from_bytes(&r.read_bytes(len)?)
+}
+
+fn write_inventory<W: io::Write>(items: &[Inventory], w: &mut Writer<W>) -> io::Result<()> {
+    w.write_size(items.len())?;
+    for item in items.iter() {
+        match item {
+            Inventory::Block(id) => {
+                w.write_u8(0)?;
+                w.write_bytes(&id.0)?;
+            }
+            Inventory::Tx(id) => {
+                w.write_u8(1)?;
+                w.write_bytes(&id.0)?;
+            }
+            Inventory::CompactBlock(id) => {
+                w.write_u8(2)?;
+                w.write_bytes(&id.0)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn read_inventory<R: io::Read>(r: &mut Reader<R>) -> Result<Vec<Inventory>, VMError> {
+    let n = r.read_size()?;
+    if n > MAX_INVENTORY {
+        return Err(VMError::FormatError);
+    }
+    let mut items = Vec::with_capacity(n);
+    for _ in 0..n {
+        let item = match r.read_u8()? {
+            0 => Inventory::Block(BlockID(r.read_u8x32()?)),
+            1 => Inventory::Tx(TxID(r.read_u8x32()?)),
+            2 => Inventory::CompactBlock(BlockID(r.read_u8x32()?)),
+            _ => return Err(VMError::FormatError),
+        };
+        items.push(item);
+    }
+    Ok(items)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blockchain::BlockHeader;
+
+    fn roundtrip(msg: Message) -> Message {
+        let bytes = msg.to_bytes();
+        let decoded = Message::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded.to_bytes(), bytes);
+        decoded
+    }
+
+    #[test]
+    fn message_encoding() {
+        let hello = Hello {
+            version: PROTOCOL_VERSION,
+            genesis: BlockID([1u8; 32]),
+            height: 10,
+        };
+        match roundtrip(Message::Hello(hello.clone())) {
+            Message::Hello(h) => assert_eq!(h, hello),
+            _ => panic!("Must be a hello message"),
+        }
+
+        let items = vec![
+            Inventory::Block(BlockID([2u8; 32])),
+            Inventory::Tx(TxID([3u8; 32])),
+            Inventory::CompactBlock(BlockID([4u8; 32])),
+        ];
+        match roundtrip(Message::Inv(items.clone())) {
+            Message::Inv(i) => assert_eq!(i, items),
+            _ => panic!("Must be an inv message"),
+        }
+        match roundtrip(Message::GetData(items.clone())) {
+            Message::GetData(i) => assert_eq!(i, items),
+            _ => panic!("Must be a getdata message"),
+        }
+
+        let header = BlockHeader::make_initial(1000, [0u8; 32]);
+        let block = Block {
+            header: header.clone(),
+            txs: Vec::new(),
+        };
+        match roundtrip(Message::Block(block)) {
+            Message::Block(b) => assert_eq!(b.header, header),
+            _ => panic!("Must be a block message"),
+        }
+
+        let compact = CompactBlock {
+            header: header.clone(),
+            nonce: 5,
+            short_ids: vec![[1u8; SHORT_ID_LEN], [2u8; SHORT_ID_LEN]],
+            prefilled: Vec::new(),
+        };
+        match roundtrip(Message::CompactBlock(compact)) {
+            Message::CompactBlock(c) => {
+                assert_eq!(c.header, header);
+                assert_eq!(c.nonce, 5);
+                assert_eq!(c.short_ids, vec![[1u8; SHORT_ID_LEN], [2u8; SHORT_ID_LEN]]);
+            }
+            _ => panic!("Must be a compact block message"),
+        }
+        match roundtrip(Message::GetBlockTxs(header.id(), vec![0, 3])) {
+            Message::GetBlockTxs(id, indices) => {
+                assert_eq!(id, header.id());
+                assert_eq!(indices, vec![0, 3]);
+            }
+            _ => panic!("Must be a getblocktxs message"),
+        }
+        match roundtrip(Message::BlockTxs(header.id(), Vec::new())) {
+            Message::BlockTxs(id, txs) => {
+                assert_eq!(id, header.id());
+                assert!(txs.is_empty());
+            }
+            _ => panic!("Must be a blocktxs message"),
+        }
+
+        assert_eq!(
+            Message::GetBlocks(7).to_bytes(),
+            vec![5, 7, 0, 0, 0, 0, 0, 0, 0]
+        );
+        assert_eq!(Message::GetMempool.to_bytes(), vec![6]);
+    }
+
+    #[test]
+    fn malformed_messages() {
+        assert_eq!(
+            Message::from_bytes(&[10]).map(|_| ()),
+            Err(P2PError::FormatError)
+        );
+        assert_eq!(
+            Message::from_bytes(&[6, 0]).map(|_| ()),
+            Err(P2PError::FormatError)
+        );
+        // Unknown inventory type.
+        let mut bytes = Message::Inv(vec![Inventory::Tx(TxID([0u8; 32]))]).to_bytes();
+        bytes[5] = 3;
+        assert_eq!(
+            Message::from_bytes(&bytes).map(|_| ()),
+            Err(P2PError::FormatError)
+        );
+        // Too many items.
+        let items = vec![Inventory::Tx(TxID([0u8; 32])); MAX_INVENTORY + 1];
+        assert_eq!(
+            Message::from_bytes(&Message::GetData(items).to_bytes()).map(|_| ()),
+            Err(P2PError::FormatError)
+        );
+    }
+}
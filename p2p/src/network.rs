@@ -0,0 +1,202 @@
+//! Network: connections to the peers driven by the tokio runtime.
+//!
+//! Each connection starts with the exchange of `Hello` messages. Then the messages
+//! from the peer are passed to the shared `Node`: its replies are sent back to the peer,
+//! and the announcements of new blocks and transactions are relayed to all other peers.
+
+use blockchain::{Block, BlockHeader};
+use futures::sync::mpsc;
+use futures::{Future, Sink, Stream};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::codec::Decoder;
+use tokio::net::{TcpListener, TcpStream};
+use zkvm::{Tx, TxID};
+
+use crate::codec::MessageCodec;
+use crate::errors::P2PError;
+use crate::messages::{Inventory, Message};
+use crate::node::Node;
+
+/// Set of connected peers sharing a node.
+#[derive(Clone)]
+pub struct Network {
+    node: Arc<Mutex<Node>>,
+    peers: Arc<Mutex<Peers>>,
+    subscribers: Arc<Mutex<Vec<mpsc::UnboundedSender<BlockHeader>>>>,
+}
+
+#[derive(Default)]
+struct Peers {
+    next_id: usize,
+    senders: HashMap<usize, mpsc::UnboundedSender<Message>>,
+}
+
+impl Network {
+    /// Creates a network with no peers around the node.
+    pub fn new(node: Node) -> Self {
+        Network {
+            node: Arc::new(Mutex::new(node)),
+            peers: Arc::new(Mutex::new(Peers::default())),
+            subscribers: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Returns the node shared by the connections.
+    pub fn node(&self) -> Arc<Mutex<Node>> {
+        self.node.clone()
+    }
+
+    /// Returns the number of connected peers.
+    pub fn peers_count(&self) -> usize {
+        self.peers.lock().unwrap().senders.len()
+    }
+
+    /// Returns a stream of the headers of the new blocks applied by the node,
+    /// whether received from the peers or submitted locally.
+    pub fn subscribe_blocks(&self) -> mpsc::UnboundedReceiver<BlockHeader> {
+        let (sender, receiver) = mpsc::unbounded();
+        self.subscribers.lock().unwrap().push(sender);
+        receiver
+    }
+
+    /// Binds to the address and returns a future that accepts the incoming connections.
+    /// The connections are spawned on the default tokio executor.
+    pub fn listen(
+        &self,
+        addr: &SocketAddr,
+    ) -> Result<impl Future<Item = (), Error = ()>, P2PError> {
+        let listener = TcpListener::bind(addr)?;
+        let network = self.clone();
+        Ok(listener.incoming().map_err(|_| ()).for_each(move |socket| {
+            tokio::spawn(network.clone().run_peer(socket).map_err(|_| ()));
+            Ok(())
+        }))
+    }
+
+    /// Returns a future that connects to the peer and communicates with it until disconnection.
+    pub fn connect(&self, addr: &SocketAddr) -> impl Future<Item = (), Error = P2PError> {
+        let network = self.clone();
+        TcpStream::connect(addr)
+            .map_err(P2PError::from)
+            .and_then(move |socket| network.run_peer(socket))
+    }
+
+    /// Adds a transaction created by this node to the mempool and announces it to the peers.
+    /// Returns the ID of the transaction.
+    pub fn submit_tx(&self, tx: Tx) -> Result<TxID, P2PError> {
+        let (id, relay) = self.node.lock().unwrap().add_tx(tx)?;
+        self.relay(None, relay);
+        Ok(id)
+    }
+
+    /// Applies a block created by this node and announces it to the peers.
+    pub fn submit_block(&self, block: Block) -> Result<(), P2PError> {
+        let relay = self.node.lock().unwrap().submit_block(block)?;
+        self.relay(None, relay);
+        Ok(())
+    }
+
+    /// Performs the handshake and then serves the peer until the connection is closed.
+    fn run_peer(self, socket: TcpStream) -> impl Future<Item = (), Error = P2PError> {
+        let hello = self.node.lock().unwrap().hello();
+        MessageCodec
+            .framed(socket)
+            .send(Message::Hello(hello))
+            .and_then(|framed| framed.into_future().map_err(|(e, _)| e))
+            .and_then(move |(msg, framed)| {
+                let requests = match msg {
+                    Some(Message::Hello(hello)) => self.node.lock().unwrap().accept_peer(&hello)?,
+                    Some(_) => return Err(P2PError::UnexpectedMessage),
+                    None => return Err(P2PError::Disconnected),
+                };
+                Ok((self, framed, requests))
+            })
+            .and_then(|(network, framed, requests)| {
+                let (sink, stream) = framed.split();
+                let (sender, receiver) = mpsc::unbounded();
+                for msg in requests {
+                    let _ = sender.unbounded_send(msg);
+                }
+                let id = network.add_peer(sender.clone());
+
+                let handler = network.clone();
+                let reader = stream.for_each(move |msg| {
+                    let response = match handler.node.lock().unwrap().handle(msg) {
+                        Ok(response) => response,
+                        // Invalid blocks and transactions are ignored,
+                        // since the peer could have received them before they became invalid.
+                        Err(P2PError::Rejected(_)) => return Ok(()),
+                        Err(e) => return Err(e),
+                    };
+                    for msg in response.reply {
+                        let _ = sender.unbounded_send(msg);
+                    }
+                    handler.relay(Some(id), response.relay);
+                    Ok(())
+                });
+                let writer = receiver
+                    .map_err(|()| P2PError::Disconnected)
+                    .forward(sink)
+                    .map(|_| ());
+
+                reader
+                    .select(writer)
+                    .map(|_| ())
+                    .map_err(|(e, _)| e)
+                    .then(move |result| {
+                        network.peers.lock().unwrap().senders.remove(&id);
+                        result
+                    })
+            })
+    }
+
+    fn add_peer(&self, sender: mpsc::UnboundedSender<Message>) -> usize {
+        let mut peers = self.peers.lock().unwrap();
+        let id = peers.next_id;
+        peers.next_id += 1;
+        peers.senders.insert(id, sender);
+        id
+    }
+
+    /// Sends the messages to all peers except the given one.
+    fn relay(&self, except: Option<usize>, msgs: Vec<Message>) {
+        if msgs.is_empty() {
+            return;
+        }
+        self.notify_subscribers(&msgs);
+        let peers = self.peers.lock().unwrap();
+        for (id, sender) in peers.senders.iter() {
+            if Some(*id) == except {
+                continue;
+            }
+            for msg in msgs.iter() {
+                let _ = sender.unbounded_send(msg.clone());
+            }
+        }
+    }
+
+    /// Sends the headers of the announced blocks to the subscribers.
+    /// Subscribers that dropped their streams are removed.
+    fn notify_subscribers(&self, msgs: &[Message]) {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        if subscribers.is_empty() {
+            return;
+        }
+        let node = self.node.lock().unwrap();
+        for msg in msgs.iter() {
+            if let Message::Inv(items) = msg {
+                for item in items.iter() {
+                    if let Inventory::Block(id) = item {
+                        if let Some(block) = node.block_by_id(id) {
+                            subscribers.retain(|sender| {
+                                sender.unbounded_send(block.header.clone()).is_ok()
+                            });
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,758 @@
+//! Node: the blockchain state, the bridge and the mempool updated with the messages from the peers.
+//!
+//! The node does not perform any I/O: it handles one message at a time
+//! and returns the messages to send back to the peer and to relay to the other peers.
+
+use blockchain::{
+    Block, BlockHeader, BlockID, BlockSigner, BlockTemplate, BlockchainError, BlockchainState,
+    Bridge, Mempool,
+};
+use bulletproofs::BulletproofGens;
+use std::collections::{BTreeMap, HashMap};
+use zkvm::{DeferredTx, Tx, TxID, Verifier};
+
+use crate::compact::{CompactBlock, PartialBlock};
+use crate::errors::P2PError;
+use crate::messages::{Hello, Inventory, Message, MAX_INVENTORY, PROTOCOL_VERSION};
+
+/// Maximum number of compact blocks waiting for the missing transactions.
+/// Past it, the oldest one is dropped, so the peers announcing fake blocks
+/// cannot grow the node's memory.
+const MAX_PENDING_BLOCKS: usize = 16;
+
+/// Node that keeps the blockchain state, the bridge, the mempool and the blocks and transactions
+/// to serve to the peers.
+pub struct Node {
+    state: BlockchainState,
+    bridge: Bridge,
+    mempool: Mempool,
+    bp_gens: BulletproofGens,
+    // scheme of the block signatures, if the chain authorizes blocks with signatures
+    signer: Option<Box<dyn BlockSigner + Send>>,
+    // transactions in the mempool, kept for relaying them to the peers
+    txs: HashMap<TxID, Tx>,
+    // blocks applied by the node, by height
+    blocks: BTreeMap<u64, StoredBlock>,
+    block_heights: HashMap<BlockID, u64>,
+    // last block of the latest full batch of announced blocks:
+    // once it is applied, the next batch is requested
+    sync_marker: Option<BlockID>,
+    // compact blocks waiting for the missing transactions, with the sequence numbers
+    // of their insertion: the oldest ones are dropped first
+    pending: HashMap<BlockID, (u64, PartialBlock)>,
+    pending_seq: u64,
+    // nonce for the short IDs of the compact blocks sent by this node
+    nonce: u64,
+}
+
+struct StoredBlock {
+    block: Block,
+    txids: Vec<TxID>,
+    // transactions that were not in the mempool when the block was applied:
+    // the peers are unlikely to have them, so they are prefilled in the compact block
+    prefill: Vec<usize>,
+}
+
+/// Messages produced by the node in response to a message from a peer.
+#[derive(Default)]
+pub struct Response {
+    /// Messages to send back to the peer.
+    pub reply: Vec<Message>,
+    /// Messages to relay to all other peers.
+    pub relay: Vec<Message>,
+}
+
+impl Node {
+    /// Creates a node with a given blockchain state, the bridge following the same chain
+    /// and the mempool.
+    pub fn new(
+        state: BlockchainState,
+        bridge: Bridge,
+        mempool: Mempool,
+        bp_gens: BulletproofGens,
+    ) -> Self {
+        Node {
+            state,
+            bridge,
+            mempool,
+            bp_gens,
+            signer: None,
+            txs: HashMap::new(),
+            blocks: BTreeMap::new(),
+            block_heights: HashMap::new(),
+            sync_marker: None,
+            pending: HashMap::new(),
+            pending_seq: 0,
+            nonce: rand::random(),
+        }
+    }
+
+    /// Sets the block signing scheme of the chain: the blocks submitted to the node
+    /// or received from the peers are applied only if their headers are signed with it.
+    pub fn with_signer<S: BlockSigner + Send + 'static>(mut self, signer: S) -> Self {
+        self.signer = Some(Box::new(signer));
+        self
+    }
+
+    /// Returns the blockchain state.
+    pub fn state(&self) -> &BlockchainState {
+        &self.state
+    }
+
+    /// Returns the bridge with the utreexo proofs of the unspent outputs.
+    pub fn bridge(&self) -> &Bridge {
+        &self.bridge
+    }
+
+    /// Returns the mempool.
+    pub fn mempool(&self) -> &Mempool {
+        &self.mempool
+    }
+
+    /// Returns the block applied by the node at the given height.
+    /// The initial block and the blocks applied before the node was created are not stored.
+    pub fn block(&self, height: u64) -> Option<&Block> {
+        self.blocks.get(&height).map(|stored| &stored.block)
+    }
+
+    /// Returns the block applied by the node with the given ID.
+    pub fn block_by_id(&self, id: &BlockID) -> Option<&Block> {
+        self.stored_block(id).map(|stored| &stored.block)
+    }
+
+    /// Returns the transaction from the mempool.
+    pub fn tx(&self, id: &TxID) -> Option<&Tx> {
+        self.txs.get(id)
+    }
+
+    /// Returns the handshake message describing this node.
+    pub fn hello(&self) -> Hello {
+        Hello {
+            version: PROTOCOL_VERSION,
+            genesis: self.state.initial_header().id(),
+            height: self.state.tip().height,
+        }
+    }
+
+    /// Checks the peer's handshake message and returns the requests
+    /// to synchronize the blocks and the mempool with the peer.
+    pub fn accept_peer(&self, hello: &Hello) -> Result<Vec<Message>, P2PError> {
+        if hello.version != PROTOCOL_VERSION {
+            return Err(P2PError::IncompatibleVersion);
+        }
+        if hello.genesis != self.state.initial_header().id() {
+            return Err(P2PError::WrongNetwork);
+        }
+        let mut requests = Vec::new();
+        if hello.height > self.state.tip().height {
+            requests.push(Message::GetBlocks(self.state.tip().height + 1));
+        }
+        requests.push(Message::GetMempool);
+        Ok(requests)
+    }
+
+    /// Verifies the transaction and adds it to the mempool.
+    /// Returns the messages announcing the transaction to the peers.
+    pub fn submit_tx(&mut self, tx: Tx) -> Result<Vec<Message>, P2PError> {
+        self.add_tx(tx).map(|(_, msgs)| msgs)
+    }
+
+    /// Verifies the transaction with the consensus parameters of the chain,
+    /// checks that it applies to the tip after its ancestors in the mempool and adds it to the mempool.
+    /// Returns the transaction ID and the messages announcing the transaction,
+    /// which are empty if the transaction is already in the mempool.
+    pub(crate) fn add_tx(&mut self, tx: Tx) -> Result<(TxID, Vec<Message>), P2PError> {
+        let params = self.state.params();
+        let verified = tx
+            .precheck(params.max_tx_size)
+            .and_then(|_| {
+                Verifier::verify_tx_with_params(
+                    tx.clone(),
+                    &params.vm_params(),
+                    &self.bp_gens,
+                    |_| false,
+                )
+            })
+            .and_then(DeferredTx::verify)
+            .map_err(|e| P2PError::Rejected(BlockchainError::TxValidation(e)))?;
+        let id = verified.id;
+        if self.mempool.contains(&id) {
+            return Ok((id, Vec::new()));
+        }
+        // Only the transactions that can be included in the next block are admitted,
+        // so the unspendable ones do not evict the others.
+        self.state
+            .check_unconfirmed_tx(&verified, &self.mempool.ancestors(&verified), &self.bridge)
+            .map_err(P2PError::Rejected)?;
+        let evicted = self.mempool.append(verified).map_err(P2PError::Rejected)?;
+        for txid in evicted.iter() {
+            self.txs.remove(txid);
+        }
+        self.txs.insert(id, tx);
+        Ok((id, vec![Message::Inv(vec![Inventory::Tx(id)])]))
+    }
+
+    /// Assembles the next block from the mempool transactions with the given timestamp,
+    /// up to `max_size` bytes. The block is applied once it is sealed and passed to `submit_block`.
+    pub fn make_block(&self, max_size: u64, timestamp_ms: u64) -> Result<Block, P2PError> {
+        let template = BlockTemplate::build(
+            &self.state,
+            &self.bridge,
+            &self.mempool,
+            max_size,
+            timestamp_ms,
+        )
+        .map_err(P2PError::Rejected)?;
+        let txs = template
+            .txids
+            .iter()
+            .map(|txid| self.txs[txid].clone())
+            .collect();
+        Ok(Block {
+            header: template.header,
+            txs,
+        })
+    }
+
+    /// Verifies the block, including its signature if the node has a block signing scheme,
+    /// and applies it on top of the current tip.
+    /// Returns the messages announcing the block to the peers.
+    pub fn submit_block(&mut self, block: Block) -> Result<Vec<Message>, P2PError> {
+        let id = block.header.id();
+        let height = block.header.height;
+        if let Some(signer) = &self.signer {
+            signer.verify(&block.header).map_err(P2PError::Rejected)?;
+        }
+        let verified = self
+            .state
+            .apply_block_with_cache(
+                block.clone(),
+                &self.bridge,
+                self.mempool.verification_cache(),
+                &self.bp_gens,
+            )
+            .map_err(P2PError::Rejected)?;
+        self.bridge
+            .apply_block(&verified)
+            .map_err(P2PError::Rejected)?;
+
+        let txids = verified.txs.iter().map(|tx| tx.id).collect::<Vec<_>>();
+        let prefill = txids
+            .iter()
+            .enumerate()
+            .filter(|(_, txid)| !self.mempool.contains(txid))
+            .map(|(i, _)| i)
+            .collect();
+
+        let removed = self
+            .mempool
+            .remove_confirmed(&verified.txs)
+            .into_iter()
+            .chain(self.mempool.expire(verified.header.timestamp_ms));
+        for txid in removed {
+            self.txs.remove(&txid);
+        }
+        self.blocks.insert(
+            height,
+            StoredBlock {
+                block,
+                txids,
+                prefill,
+            },
+        );
+        self.block_heights.insert(id, height);
+        self.pending
+            .retain(|_, (_, partial)| partial.header().height > height);
+        Ok(vec![Message::Inv(vec![Inventory::Block(id)])])
+    }
+
+    /// Handles a message from a peer received after the handshake.
+    pub fn handle(&mut self, msg: Message) -> Result<Response, P2PError> {
+        let mut response = Response::default();
+        match msg {
+            Message::Hello(_) => return Err(P2PError::UnexpectedMessage),
+            Message::Inv(items) => {
+                let wanted = items
+                    .iter()
+                    .filter(|item| !self.has(item))
+                    .map(|item| match item {
+                        // A single block is a new block likely consisting of the transactions
+                        // already in the mempool, so it is requested as a compact block.
+                        Inventory::Block(id) if items.len() == 1 => Inventory::CompactBlock(*id),
+                        item => *item,
+                    })
+                    .collect::<Vec<_>>();
+                if items.len() == MAX_INVENTORY {
+                    self.sync_marker = items.iter().rev().find_map(|item| match item {
+                        Inventory::Block(id) => Some(*id),
+                        _ => None,
+                    });
+                }
+                if !wanted.is_empty() {
+                    response.reply.push(Message::GetData(wanted));
+                }
+            }
+            Message::GetData(items) => {
+                for item in items.iter() {
+                    match item {
+                        Inventory::Block(id) => {
+                            if let Some(stored) = self.stored_block(id) {
+                                response.reply.push(Message::Block(stored.block.clone()));
+                            }
+                        }
+                        Inventory::Tx(id) => {
+                            if let Some(tx) = self.txs.get(id) {
+                                response.reply.push(Message::Tx(tx.clone()));
+                            }
+                        }
+                        Inventory::CompactBlock(id) => {
+                            if let Some(stored) = self.stored_block(id) {
+                                response.reply.push(Message::CompactBlock(CompactBlock::new(
+                                    &stored.block,
+                                    &stored.txids,
+                                    &stored.prefill,
+                                    self.nonce,
+                                )));
+                            }
+                        }
+                    }
+                }
+            }
+            Message::Block(block) => {
+                let id = block.header.id();
+                if self.extends_tip(&id, &block.header, &mut response) {
+                    self.apply_next(id, block, &mut response)?;
+                }
+            }
+            Message::Tx(tx) => {
+                response.relay = self.submit_tx(tx)?;
+            }
+            Message::GetBlocks(height) => {
+                let ids = self
+                    .blocks
+                    .range(height..)
+                    .take(MAX_INVENTORY)
+                    .map(|(_, stored)| Inventory::Block(stored.block.header.id()))
+                    .collect::<Vec<_>>();
+                if !ids.is_empty() {
+                    response.reply.push(Message::Inv(ids));
+                }
+            }
+            Message::GetMempool => {
+                let ids = self
+                    .mempool
+                    .candidates()
+                    .into_iter()
+                    .take(MAX_INVENTORY)
+                    .map(|tx| Inventory::Tx(tx.id))
+                    .collect::<Vec<_>>();
+                if !ids.is_empty() {
+                    response.reply.push(Message::Inv(ids));
+                }
+            }
+            Message::CompactBlock(compact) => {
+                let id = compact.header.id();
+                if self.extends_tip(&id, &compact.header, &mut response) {
+                    let partial = compact.reconstruct(&self.txs)?;
+                    self.complete_block(id, partial, &mut response)?;
+                }
+            }
+            Message::GetBlockTxs(id, indices) => {
+                if let Some(stored) = self.stored_block(&id) {
+                    let txs = indices
+                        .iter()
+                        .map(|i| stored.block.txs.get(*i).cloned())
+                        .collect::<Option<Vec<_>>>()
+                        .ok_or(P2PError::FormatError)?;
+                    response.reply.push(Message::BlockTxs(id, txs));
+                }
+            }
+            Message::BlockTxs(id, txs) => {
+                // Transactions for the blocks that are not pending are ignored.
+                if let Some((_, mut partial)) = self.pending.remove(&id) {
+                    partial.fill(txs)?;
+                    if self.extends_tip(&id, partial.header(), &mut response) {
+                        self.complete_block(id, partial, &mut response)?;
+                    }
+                }
+            }
+        }
+        Ok(response)
+    }
+
+    fn stored_block(&self, id: &BlockID) -> Option<&StoredBlock> {
+        self.block_heights.get(id).and_then(|h| self.blocks.get(h))
+    }
+
+    /// Checks if the received block is new and extends the tip.
+    /// If the block is ahead of the tip, requests the missing blocks.
+    fn extends_tip(&self, id: &BlockID, header: &BlockHeader, response: &mut Response) -> bool {
+        if self.block_heights.contains_key(id) {
+            return false;
+        }
+        let tip = self.state.tip();
+        if header.prev_id != tip.id() {
+            // The node does not choose among competing branches,
+            // but catches up if it is behind the peer.
+            if header.height > tip.height + 1 {
+                response.reply.push(Message::GetBlocks(tip.height + 1));
+            }
+            return false;
+        }
+        true
+    }
+
+    /// Applies the block extending the tip and requests the next batch of blocks
+    /// if the node is synchronizing with the peer.
+    fn apply_next(
+        &mut self,
+        id: BlockID,
+        block: Block,
+        response: &mut Response,
+    ) -> Result<(), P2PError> {
+        response.relay = self.submit_block(block)?;
+        if self.sync_marker == Some(id) {
+            self.sync_marker = None;
+            response
+                .reply
+                .push(Message::GetBlocks(self.state.tip().height + 1));
+        }
+        Ok(())
+    }
+
+    /// Applies the reconstructed block, or requests the missing transactions.
+    fn complete_block(
+        &mut self,
+        id: BlockID,
+        partial: PartialBlock,
+        response: &mut Response,
+    ) -> Result<(), P2PError> {
+        let missing = partial.missing();
+        if !missing.is_empty() {
+            if self.pending.len() >= MAX_PENDING_BLOCKS {
+                let oldest = self
+                    .pending
+                    .iter()
+                    .min_by_key(|(_, (seq, _))| *seq)
+                    .map(|(id, _)| *id);
+                if let Some(oldest) = oldest {
+                    self.pending.remove(&oldest);
+                }
+            }
+            self.pending_seq += 1;
+            self.pending.insert(id, (self.pending_seq, partial));
+            response.reply.push(Message::GetBlockTxs(id, missing));
+            return Ok(());
+        }
+        let block = partial.into_block().expect("all transactions are present");
+        match self.apply_next(id, block, response) {
+            // Short IDs may collide and match a wrong transaction from the mempool,
+            // so the block that failed validation is requested in full.
+            Err(P2PError::Rejected(_)) => {
+                response
+                    .reply
+                    .push(Message::GetData(vec![Inventory::Block(id)]));
+                Ok(())
+            }
+            result => result,
+        }
+    }
+
+    fn has(&self, item: &Inventory) -> bool {
+        match item {
+            Inventory::Block(id) | Inventory::CompactBlock(id) => {
+                self.block_heights.contains_key(id)
+            }
+            Inventory::Tx(id) => self.mempool.contains(id),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::compact::tests::make_tx;
+    use blockchain::Committee;
+    use curve25519_dalek::scalar::Scalar;
+    use zkvm::{TxTree, VerificationKey};
+
+    pub(crate) fn make_node() -> Node {
+        let state = BlockchainState::make_initial(1000, 10, 10);
+        let bridge = Bridge::new(&state).unwrap();
+        Node::new(
+            state,
+            bridge,
+            Mempool::new(100_000),
+            BulletproofGens::new(256, 1),
+        )
+    }
+
+    /// Makes an empty block on top of the given header.
+    pub(crate) fn empty_block(prev: &BlockHeader) -> Block {
+        Block {
+            header: BlockHeader {
+                version: 1,
+                height: prev.height + 1,
+                prev_id: prev.id(),
+                timestamp_ms: prev.timestamp_ms + 1000,
+                txroot: TxTree::build(&[]).root(),
+                utxoroot: prev.utxoroot,
+                ext: Vec::new(),
+            },
+            txs: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn handshake() {
+        let node = make_node();
+        let mut hello = node.hello();
+        assert_eq!(hello.height, 1);
+        assert_eq!(node.accept_peer(&hello).unwrap().len(), 1);
+
+        hello.height = 5;
+        match node.accept_peer(&hello).unwrap().as_slice() {
+            [Message::GetBlocks(2), Message::GetMempool] => {}
+            _ => panic!("Must request the blocks and the mempool"),
+        }
+
+        hello.version = PROTOCOL_VERSION + 1;
+        assert_eq!(
+            node.accept_peer(&hello).map(|_| ()),
+            Err(P2PError::IncompatibleVersion)
+        );
+
+        let state = BlockchainState::make_initial(2000, 10, 10);
+        let bridge = Bridge::new(&state).unwrap();
+        let other = Node::new(
+            state,
+            bridge,
+            Mempool::new(100_000),
+            BulletproofGens::new(256, 1),
+        );
+        assert_eq!(
+            node.accept_peer(&other.hello()).map(|_| ()),
+            Err(P2PError::WrongNetwork)
+        );
+    }
+
+    #[test]
+    fn block_relay() {
+        let mut alice = make_node();
+        let mut bob = make_node();
+
+        let block1 = empty_block(alice.state().tip());
+        let block2 = empty_block(&block1.header);
+        let id1 = block1.header.id();
+        alice.submit_block(block1).unwrap();
+        alice.submit_block(block2).unwrap();
+
+        // Bob catches up with Alice after the handshake.
+        let mut requests = bob.accept_peer(&alice.hello()).unwrap();
+        let mut rounds = 0;
+        while !requests.is_empty() {
+            rounds += 1;
+            let mut next = Vec::new();
+            for msg in requests {
+                for reply in alice.handle(msg).unwrap().reply {
+                    next.extend(bob.handle(reply).unwrap().reply);
+                }
+            }
+            requests = next;
+        }
+        assert_eq!(rounds, 2);
+        assert_eq!(bob.state().tip(), alice.state().tip());
+
+        // Known blocks are not requested again.
+        let response = bob
+            .handle(Message::Inv(vec![Inventory::Block(id1)]))
+            .unwrap();
+        assert!(response.reply.is_empty());
+
+        // Block extending the tip is relayed to the other peers.
+        let block3 = empty_block(bob.state().tip());
+        let response = alice.handle(Message::Block(block3.clone())).unwrap();
+        match response.relay.as_slice() {
+            [Message::Inv(items)] => assert_eq!(items, &vec![Inventory::Block(block3.header.id())]),
+            _ => panic!("Must announce the block"),
+        }
+
+        // Block that does not follow the tip makes the node request the missing blocks.
+        let block4 = empty_block(&block3.header);
+        let block5 = empty_block(&block4.header);
+        match bob.handle(Message::Block(block5)).unwrap().reply.as_slice() {
+            [Message::GetBlocks(4)] => {}
+            _ => panic!("Must request the missing blocks"),
+        }
+
+        // Invalid block is rejected.
+        let mut invalid = empty_block(alice.state().tip());
+        invalid.header.utxoroot = [0u8; 32];
+        assert_eq!(
+            alice.handle(Message::Block(invalid)).map(|_| ()),
+            Err(P2PError::Rejected(BlockchainError::InvalidUtxoRoot))
+        );
+        assert_eq!(
+            alice.handle(Message::Hello(alice.hello())).map(|_| ()),
+            Err(P2PError::UnexpectedMessage)
+        );
+    }
+
+    #[test]
+    fn signed_blocks() {
+        let secret = Scalar::from(1u64);
+        let committee = Committee::new(vec![VerificationKey::from_secret(&secret)], 1).unwrap();
+        let mut node = make_node().with_signer(committee.clone());
+
+        let unsigned = empty_block(node.state().tip());
+        assert_eq!(
+            node.handle(Message::Block(unsigned)).map(|_| ()),
+            Err(P2PError::Rejected(BlockchainError::InvalidBlockSignature))
+        );
+
+        // Version 2 block commits to the state hash and carries the committee's signature.
+        let mut block = empty_block(node.state().tip());
+        block.header.version = 2;
+        block.header.ext = node.state().state_hash().to_vec();
+        committee.sign(&mut block.header, &[(0, secret)]).unwrap();
+        node.submit_block(block.clone()).unwrap();
+        assert_eq!(node.state().tip(), &block.header);
+    }
+
+    #[test]
+    fn make_block() {
+        let mut node = make_node();
+        let initial_id = node.state().initial_header().id();
+        let (txid1, tx1) = make_tx(initial_id, 1_000_000);
+        let (_, tx2) = make_tx(initial_id, 1500);
+        node.submit_tx(tx1).unwrap();
+        node.submit_tx(tx2).unwrap();
+
+        // The second transaction expires before the block.
+        let block = node.make_block(100_000, 2000).unwrap();
+        assert_eq!(block.txs.len(), 1);
+        node.submit_block(block).unwrap();
+        assert!(!node.mempool().contains(&txid1));
+        assert!(node.mempool().is_empty());
+    }
+
+    #[test]
+    fn rejects_oversized_tx() {
+        let initial_id = make_node().state().initial_header().id();
+        let (_, tx) = make_tx(initial_id, 1_000_000);
+
+        // Relay policy follows the consensus limit of the chain.
+        let mut params = blockchain::ChainParams::default();
+        params.max_tx_size = tx.serialized_size() - 1;
+        let state = BlockchainState::make_initial(1000, 10, 10)
+            .with_params(params)
+            .unwrap();
+        let bridge = Bridge::new(&state).unwrap();
+        let mut node = Node::new(
+            state,
+            bridge,
+            Mempool::new(100_000),
+            BulletproofGens::new(256, 1),
+        );
+        assert!(node.submit_tx(tx).is_err());
+        assert!(node.mempool().is_empty());
+    }
+
+    #[test]
+    fn rejects_inapplicable_tx() {
+        let mut node = make_node();
+        let initial_id = node.state().initial_header().id();
+
+        // Nonce refers to an unknown block.
+        let (_, tx) = make_tx(BlockID([9u8; 32]), 1_000_000);
+        assert_eq!(
+            node.submit_tx(tx).map(|_| ()),
+            Err(P2PError::Rejected(BlockchainError::UnknownBlockReference))
+        );
+
+        // Transaction expires before the next block.
+        let (_, tx) = make_tx(initial_id, 1000);
+        assert_eq!(
+            node.submit_tx(tx).map(|_| ()),
+            Err(P2PError::Rejected(BlockchainError::BadTxTimestamp))
+        );
+        assert!(node.mempool().is_empty());
+    }
+
+    #[test]
+    fn compact_block_relay() {
+        let mut alice = make_node();
+        let mut bob = make_node();
+        let initial_id = alice.state().initial_header().id();
+        let (txid1, tx1) = make_tx(initial_id, 1_000_000);
+        let (txid2, tx2) = make_tx(initial_id, 2_000_000);
+
+        // Both nodes have the first transaction, but only Alice has the second one.
+        alice.submit_tx(tx1.clone()).unwrap();
+        alice.submit_tx(tx2.clone()).unwrap();
+        bob.submit_tx(tx1.clone()).unwrap();
+
+        let mut block = empty_block(alice.state().tip());
+        block.header.txroot = TxTree::build(&[txid1, txid2]).root();
+        block.txs = vec![tx1, tx2];
+        let id = block.header.id();
+        let announcement = alice.submit_block(block).unwrap();
+
+        let mut requests = bob.handle(announcement[0].clone()).unwrap().reply;
+        match requests.as_slice() {
+            [Message::GetData(items)] => assert_eq!(items, &vec![Inventory::CompactBlock(id)]),
+            _ => panic!("Must request the compact block"),
+        }
+        let mut relay = Vec::new();
+        while !requests.is_empty() {
+            let mut next = Vec::new();
+            for msg in requests {
+                for reply in alice.handle(msg).unwrap().reply {
+                    if let Message::GetBlockTxs(_, indices) = &reply {
+                        assert_eq!(indices, &vec![1]);
+                    }
+                    let response = bob.handle(reply).unwrap();
+                    next.extend(response.reply);
+                    relay.extend(response.relay);
+                }
+            }
+            requests = next;
+        }
+        assert_eq!(bob.state().tip(), alice.state().tip());
+        assert!(!bob.mempool().contains(&txid1));
+        match relay.as_slice() {
+            [Message::Inv(items)] => assert_eq!(items, &vec![Inventory::Block(id)]),
+            _ => panic!("Must announce the block"),
+        }
+    }
+
+    #[test]
+    fn pending_blocks_limit() {
+        let mut node = make_node();
+        let initial_id = node.state().initial_header().id();
+        let (txid, tx) = make_tx(initial_id, 1_000_000);
+
+        // Compact blocks with a transaction unknown to the node wait for it.
+        let mut ids = Vec::new();
+        for i in 0..MAX_PENDING_BLOCKS as u64 + 1 {
+            let mut block = empty_block(node.state().tip());
+            block.header.timestamp_ms += i;
+            block.header.txroot = TxTree::build(&[txid]).root();
+            block.txs = vec![tx.clone()];
+            ids.push(block.header.id());
+            let compact = CompactBlock::new(&block, &[txid], &[], 0);
+            let reply = node.handle(Message::CompactBlock(compact)).unwrap().reply;
+            match reply.as_slice() {
+                [Message::GetBlockTxs(_, indices)] => assert_eq!(indices, &vec![0]),
+                _ => panic!("Must request the missing transaction"),
+            }
+        }
+        assert_eq!(node.pending.len(), MAX_PENDING_BLOCKS);
+        assert!(!node.pending.contains_key(&ids[0]));
+        assert!(ids[1..].iter().all(|id| node.pending.contains_key(id)));
+
+        // Pending blocks are dropped once the tip reaches their height.
+        node.submit_block(empty_block(node.state().tip())).unwrap();
+        assert!(node.pending.is_empty());
+    }
+}
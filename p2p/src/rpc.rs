@@ -0,0 +1,316 @@
+//! JSON-RPC server: exposes the `Api` over TCP connections as JSON-RPC 2.0,
+//! one JSON message per line.
+//!
+//! Subscriptions to new blocks are delivered as notifications on the same connection
+//! and last until the client disconnects.
+
+use blockchain::BlockID;
+use futures::sync::mpsc;
+use futures::{Future, Sink, Stream};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::net::SocketAddr;
+use tokio::codec::{Decoder, LinesCodec};
+use tokio::net::{TcpListener, TcpStream};
+use zkvm::{ContractID, Tx};
+
+use crate::api::Api;
+use crate::codec::MAX_MESSAGE_SIZE;
+use crate::errors::{ApiError, P2PError};
+
+/// Maximum length of a request line: a message of the p2p protocol encoded in hex
+/// together with the rest of the request. Longer lines close the connection.
+const MAX_LINE_LENGTH: usize = 2 * MAX_MESSAGE_SIZE + 1024;
+
+/// JSON-RPC server for the node API.
+#[derive(Clone)]
+pub struct RpcServer {
+    api: Api,
+}
+
+#[derive(Deserialize)]
+struct Request {
+    jsonrpc: String,
+    #[serde(default)]
+    id: Value,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Deserialize)]
+struct TxParams {
+    tx: String,
+}
+
+#[derive(Deserialize)]
+struct BlockParams {
+    height: Option<u64>,
+    id: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct UtxoProofParams {
+    contract_id: String,
+}
+
+impl RpcServer {
+    /// Creates a server for the API.
+    pub fn new(api: Api) -> Self {
+        RpcServer { api }
+    }
+
+    /// Binds to the address and returns a future that accepts the client connections.
+    /// The connections are spawned on the default tokio executor.
+    pub fn listen(
+        &self,
+        addr: &SocketAddr,
+    ) -> Result<impl Future<Item = (), Error = ()>, P2PError> {
+        let listener = TcpListener::bind(addr)?;
+        let server = self.clone();
+        Ok(listener.incoming().map_err(|_| ()).for_each(move |socket| {
+            tokio::spawn(server.clone().run_client(socket));
+            Ok(())
+        }))
+    }
+
+    /// Calls the method that returns a single result:
+    /// `submit_tx`, `get_block`, `get_mempool` or `get_utxo_proof`.
+    pub fn call(&self, method: &str, params: Value) -> Result<Value, ApiError> {
+        match method {
+            "submit_tx" => {
+                let params: TxParams = parse_params(params)?;
+                let tx = decode_hex(&params.tx).and_then(|bytes| {
+                    Tx::from_bytes(&bytes).map_err(|_| invalid("malformed tx"))
+                })?;
+                to_json(self.api.submit_tx(tx)?)
+            }
+            "get_block" => match parse_params(params)? {
+                BlockParams {
+                    height: Some(height),
+                    id: None,
+                } => to_json(self.api.get_block(height)?),
+                BlockParams {
+                    height: None,
+                    id: Some(id),
+                } => to_json(self.api.get_block_by_id(&BlockID(decode_hash(&id)?))?),
+                _ => Err(invalid("expected either height or id")),
+            },
+            "get_mempool" => to_json(self.api.get_mempool()),
+            "get_utxo_proof" => {
+                let params: UtxoProofParams = parse_params(params)?;
+                let contract_id = ContractID::from_raw_bytes(decode_hash(&params.contract_id)?);
+                to_json(self.api.get_utxo_proof(&contract_id)?)
+            }
+            _ => Err(ApiError::MethodNotFound(method.to_string())),
+        }
+    }
+
+    /// Serves the client's requests until the connection is closed.
+    fn run_client(self, socket: TcpStream) -> impl Future<Item = (), Error = ()> {
+        let (sink, stream) = LinesCodec::new_with_max_length(MAX_LINE_LENGTH)
+            .framed(socket)
+            .split();
+        let (sender, receiver) = mpsc::unbounded();
+        let mut next_subscription = 0u64;
+
+        let reader = stream.map_err(|_| ()).for_each(move |line| {
+            let response = self.respond(&line, &sender, &mut next_subscription);
+            sender.unbounded_send(response).map_err(|_| ())
+        });
+        let writer = receiver.forward(sink.sink_map_err(|_| ())).map(|_| ());
+
+        reader.select(writer).map(|_| ()).map_err(|_| ())
+    }
+
+    /// Handles the request line and returns the response line.
+    fn respond(
+        &self,
+        line: &str,
+        notifications: &mpsc::UnboundedSender<String>,
+        next_subscription: &mut u64,
+    ) -> String {
+        let request = serde_json::from_str::<Value>(line)
+            .map_err(|_| ApiError::ParseError)
+            .and_then(|value| {
+                serde_json::from_value::<Request>(value).map_err(|_| ApiError::InvalidRequest)
+            });
+        let (id, result) = match request {
+            Ok(ref request) if request.jsonrpc != "2.0" => {
+                (request.id.clone(), Err(ApiError::InvalidRequest))
+            }
+            Ok(request) => {
+                let result = if request.method == "subscribe_blocks" {
+                    let subscription = *next_subscription;
+                    *next_subscription += 1;
+                    self.subscribe_blocks(subscription, notifications.clone());
+                    Ok(json!(subscription))
+                } else {
+                    self.call(&request.method, request.params)
+                };
+                (request.id, result)
+            }
+            Err(e) => (Value::Null, Err(e)),
+        };
+        let response = match result {
+            Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+            Err(e) => json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": {"code": e.code(), "message": e.to_string()},
+            }),
+        };
+        response.to_string()
+    }
+
+    /// Forwards the headers of the new blocks to the client as `new_block` notifications.
+    fn subscribe_blocks(&self, subscription: u64, notifications: mpsc::UnboundedSender<String>) {
+        let forward = self
+            .api
+            .subscribe_blocks()
+            .map(move |header| {
+                json!({
+                    "jsonrpc": "2.0",
+                    "method": "new_block",
+                    "params": {"subscription": subscription, "result": header},
+                })
+                .to_string()
+            })
+            .forward(notifications.sink_map_err(|_| ()))
+            .map(|_| ());
+        tokio::spawn(forward);
+    }
+}
+
+fn parse_params<T: DeserializeOwned>(params: Value) -> Result<T, ApiError> {
+    serde_json::from_value(params).map_err(|e| ApiError::InvalidParams(e.to_string()))
+}
+
+fn to_json<T: serde::Serialize>(result: T) -> Result<Value, ApiError> {
+    Ok(serde_json::to_value(result).expect("API types are always serializable"))
+}
+
+fn invalid(reason: &str) -> ApiError {
+    ApiError::InvalidParams(reason.to_string())
+}
+
+fn decode_hex(s: &str) -> Result<Vec<u8>, ApiError> {
+    hex::decode(s).map_err(|_| invalid("malformed hex"))
+}
+
+fn decode_hash(s: &str) -> Result<[u8; 32], ApiError> {
+    let bytes = decode_hex(s)?;
+    if bytes.len() != 32 {
+        return Err(invalid("expected a 32-byte hash"));
+    }
+    let mut hash = [0u8; 32];
+    hash.copy_from_slice(&bytes);
+    Ok(hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compact::tests::make_tx;
+    use crate::network::Network;
+    use crate::node::tests::{empty_block, make_node};
+
+    fn make_server() -> (Network, RpcServer) {
+        let network = Network::new(make_node());
+        let server = RpcServer::new(Api::new(network.clone()));
+        (network, server)
+    }
+
+    #[test]
+    fn blocks() {
+        let (network, server) = make_server();
+        let headers = server.api.subscribe_blocks();
+
+        let block = empty_block(network.node().lock().unwrap().state().tip());
+        let id = hex::encode(&block.header.id().0);
+        network.submit_block(block.clone()).unwrap();
+
+        let by_height = server.call("get_block", json!({"height": 2})).unwrap();
+        let by_id = server.call("get_block", json!({ "id": id })).unwrap();
+        assert_eq!(by_height, by_id);
+        assert_eq!(by_height["header"]["id"], json!(id));
+        assert_eq!(by_height["block"], json!(hex::encode(block.to_bytes())));
+
+        assert_eq!(
+            server.call("get_block", json!({"height": 3})),
+            Err(ApiError::NotFound)
+        );
+        assert_eq!(
+            server.call("get_block", json!({"height": 2, "id": id})),
+            Err(invalid("expected either height or id"))
+        );
+
+        let header = headers.wait().next().unwrap().unwrap();
+        assert_eq!(header.id, id);
+        assert_eq!(header.height, 2);
+    }
+
+    #[test]
+    fn transactions() {
+        let (network, server) = make_server();
+        let initial_id = network.node().lock().unwrap().state().initial_header().id();
+        let (txid, tx) = make_tx(initial_id, 1_000_000);
+
+        let submitted = server
+            .call("submit_tx", json!({"tx": hex::encode(tx.to_bytes())}))
+            .unwrap();
+        assert_eq!(submitted, json!(hex::encode(&txid.0)));
+
+        let mempool = server.call("get_mempool", Value::Null).unwrap();
+        assert_eq!(mempool["txs"][0]["id"], submitted);
+
+        assert_eq!(
+            server.call("submit_tx", json!({"tx": "00"})),
+            Err(invalid("malformed tx"))
+        );
+        assert_eq!(
+            server.call("get_utxo_proof", json!({"contract_id": "00"})),
+            Err(invalid("expected a 32-byte hash"))
+        );
+        assert_eq!(
+            server.call(
+                "get_utxo_proof",
+                json!({ "contract_id": hex::encode([0u8; 32]) })
+            ),
+            Err(ApiError::NotFound)
+        );
+        assert_eq!(
+            server.call("get_utxos", Value::Null),
+            Err(ApiError::MethodNotFound("get_utxos".to_string()))
+        );
+    }
+
+    #[test]
+    fn requests() {
+        let (_, server) = make_server();
+        let (sender, _receiver) = mpsc::unbounded();
+        let mut next_subscription = 0;
+        let mut respond = |line: &str| -> Value {
+            serde_json::from_str(&server.respond(line, &sender, &mut next_subscription)).unwrap()
+        };
+
+        let response = respond(r#"{"jsonrpc": "2.0", "id": 1, "method": "get_mempool"}"#);
+        assert_eq!(response["id"], json!(1));
+        assert_eq!(response["result"]["txs"], json!([]));
+
+        let response = respond(r#"{"jsonrpc": "2.0", "id": 2, "method": "get_block"}"#);
+        assert_eq!(response["error"]["code"], json!(-32602));
+
+        let response = respond(r#"{"jsonrpc": "1.0", "id": 3, "method": "get_mempool"}"#);
+        assert_eq!(response["error"]["code"], json!(-32600));
+
+        let response = respond("not json");
+        assert_eq!(response["id"], Value::Null);
+        assert_eq!(response["error"]["code"], json!(-32700));
+
+        let response = respond(r#"{"jsonrpc": "2.0", "id": 4}"#);
+        assert_eq!(response["id"], Value::Null);
+        assert_eq!(response["error"]["code"], json!(-32600));
+    }
+}
@@ -1,5 +1,6 @@
-/// Represents a usize with value in the range [0,64]
-#[derive(Copy, Clone, Debug)]
+/// Represents a usize with value in the range [0,252]:
+/// up to 64 bits with `new`, and up to 252 bits with `new_wide`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct BitRange(usize);
 
 impl BitRange {
@@ -13,10 +14,33 @@ impl BitRange {
         }
     }
 
+    /// Returns Some(BitRange) if `n` is ≤ 252.
+    /// Otherwise returns None.
+    ///
+    /// 252 bits is the widest range whose values are all
+    /// smaller than the order of the scalar field.
+    pub fn new_wide(n: usize) -> Option<Self> {
+        if n > 252 {
+            None
+        } else {
+            Some(BitRange(n))
+        }
+    }
+
     /// Returns 64-bit range
     pub fn max() -> Self {
         BitRange(64)
     }
+
+    /// Returns 252-bit range
+    pub fn max_wide() -> Self {
+        BitRange(252)
+    }
+
+    /// Returns 64-bit range used for the quantities of the values.
+    pub fn u64() -> Self {
+        BitRange(64)
+    }
 }
 
 impl Into<usize> for BitRange {
@@ -1,8 +1,12 @@
-use crate::{mix::k_mix, range_proof};
+use crate::{
+    mix::{merge, split},
+    range_proof,
+};
 use bit_range::BitRange;
 use bulletproofs::r1cs::{ConstraintSystem, R1CSError};
+use curve25519_dalek::scalar::Scalar;
 use shuffle::{padded_shuffle, value_shuffle};
-use value::AllocatedValue;
+use value::{AllocatedValue, Value};
 
 /// Enforces that the outputs are a valid rearrangement of the inputs, following the
 /// soundness and secrecy requirements in the [Cloak specification](../spec.md).
@@ -11,11 +15,35 @@ pub fn cloak<CS: ConstraintSystem>(
     inputs: Vec<AllocatedValue>,
     outputs: Vec<AllocatedValue>,
 ) -> Result<(), R1CSError> {
+    cloak_with_fees(cs, inputs, outputs, &[])
+}
+
+/// Enforces that the outputs together with the public `fees` are a valid rearrangement
+/// of the inputs. That is, for each fee the inputs may exceed the outputs
+/// by the fee quantity of the fee flavor.
+///
+/// Fees are known to both the prover and the verifier, so they are not range-proven:
+/// negative fee quantities are rejected with a `GadgetError`.
+pub fn cloak_with_fees<CS: ConstraintSystem>(
+    cs: &mut CS,
+    inputs: Vec<AllocatedValue>,
+    outputs: Vec<AllocatedValue>,
+    fees: &[Value],
+) -> Result<(), R1CSError> {
+    check_bit_widths(&inputs)?;
+    check_bit_widths(&outputs)?;
+
+    // Fees are appended to the outputs so that they take part in the split and the shuffles.
+    let mut all_outputs = outputs.clone();
+    for fee in fees.iter() {
+        all_outputs.push(allocate_fee(cs, fee)?);
+    }
+
     // Merge
     let (merge_in, merge_out) = merge(cs, inputs.clone())?;
 
     // Split
-    let (split_in, split_out) = split(cs, outputs.clone())?;
+    let (split_in, split_out) = split(cs, all_outputs.clone())?;
 
     // Shuffle 1
     // Check that `merge_in` is a valid reordering of `inputs`
@@ -28,45 +56,83 @@ pub fn cloak<CS: ConstraintSystem>(
     padded_shuffle(cs, merge_out, split_in)?;
 
     // Shuffle 3
-    // Check that `split_out` is a valid reordering of `outputs`
-    // when `outputs` are grouped by flavor.
-    value_shuffle(cs, split_out, outputs.clone())?;
+    // Check that `split_out` is a valid reordering of `outputs` and fees
+    // when they are grouped by flavor.
+    value_shuffle(cs, split_out, all_outputs)?;
 
     // Range Proof
-    // Check that each of the quantities in `outputs` lies in [0, 2^64).
+    // Check that each of the quantities in `outputs` lies in [0, 2^bits).
     for output in outputs {
         range_proof(
             cs,
             output.q.into(),
-            output.assignment.map(|v| v.q),
-            BitRange::max(),
+            output.assignment.map(|v| v.q.into()),
+            output.bits,
         )?;
     }
 
     Ok(())
 }
 
-/// Enforces that the outputs are either a merge of the inputs: `D = A + B && C = 0`,
-/// or the outputs are equal to the inputs `C = A && D = B`. See spec for more details.
-/// Works for `k` inputs and `k` outputs.
-fn merge<CS: ConstraintSystem>(
+/// Allocates a fee value and constrains it to the public quantity and flavor.
+fn allocate_fee<CS: ConstraintSystem>(
     cs: &mut CS,
-    inputs: Vec<AllocatedValue>,
-) -> Result<(Vec<AllocatedValue>, Vec<AllocatedValue>), R1CSError> {
-    k_mix(cs, inputs)
+    fee: &Value,
+) -> Result<AllocatedValue, R1CSError> {
+    let q = fee.q.to_u64().ok_or_else(|| R1CSError::GadgetError {
+        description: "fee quantity must be non-negative".to_string(),
+    })?;
+    let (q_var, f_var, _) = cs.allocate_multiplier(Some((Scalar::from(q), fee.f)))?;
+    cs.constrain(q_var - Scalar::from(q));
+    cs.constrain(f_var - fee.f);
+    Ok(AllocatedValue {
+        q: q_var,
+        f: f_var,
+        assignment: Some(*fee),
+        bits: BitRange::u64(),
+    })
 }
 
-/// Enforces that the outputs are either a split of the inputs :`A = C + D && B = 0`,
-/// or the outputs are equal to the inputs `C = A && D = B`. See spec for more details.
-/// Works for `k` inputs and `k` outputs.
-///
-/// Note: the `split` gadget is the same thing as a `merge` gadget, but "backwards".
-/// This means that if you reverse all of the commitment vectors, and switch the
-/// inputs and outputs of a `merge` gadget, then you have a `split` gadget.
-fn split<CS: ConstraintSystem>(
-    cs: &mut CS,
-    mut inputs: Vec<AllocatedValue>,
-) -> Result<(Vec<AllocatedValue>, Vec<AllocatedValue>), R1CSError> {
-    inputs.reverse();
-    k_mix(cs, inputs).map(|(outs, ins)| (ins, outs))
+/// Checks that the sum of the values cannot overflow the scalar field:
+/// for `k` values of at most `n` bits, `n + ceil(log2(k))` must not exceed 252 bits.
+fn check_bit_widths(values: &[AllocatedValue]) -> Result<(), R1CSError> {
+    let max_bits = values
+        .iter()
+        .map(|v| -> usize { v.bits.into() })
+        .max()
+        .unwrap_or(0);
+    let sum_bits = max_bits + values.len().next_power_of_two().trailing_zeros() as usize;
+    if BitRange::new_wide(sum_bits).is_none() {
+        return Err(R1CSError::GadgetError {
+            description: "sum of the values may exceed 252 bits".to_string(),
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bulletproofs::r1cs::Variable;
+
+    fn values(k: usize, bits: usize) -> Vec<AllocatedValue> {
+        (0..k)
+            .map(|_| AllocatedValue {
+                q: Variable::One(),
+                f: Variable::One(),
+                assignment: None,
+                bits: BitRange::new_wide(bits).unwrap(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn bit_widths() {
+        assert!(check_bit_widths(&[]).is_ok());
+        assert!(check_bit_widths(&values(1, 252)).is_ok());
+        assert!(check_bit_widths(&values(2, 252)).is_err());
+        assert!(check_bit_widths(&values(4, 250)).is_ok());
+        assert!(check_bit_widths(&values(5, 250)).is_err());
+        assert!(check_bit_widths(&values(1000, 64)).is_ok());
+    }
 }
@@ -0,0 +1,4 @@
+//! Gadgets of the Cloak protocol that are usable on their own in other circuits.
+
+pub use mix::{by_flavor_and_quantity, k_mix, k_mix_by, merge, merge_by, mix, split, split_by};
+pub use shuffle::scalar_shuffle;
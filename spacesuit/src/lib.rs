@@ -11,6 +11,7 @@ extern crate curve25519_dalek;
 extern crate merlin;
 extern crate rand;
 extern crate subtle;
+extern crate zeroize;
 
 mod bit_range;
 mod cloak;
@@ -20,9 +21,11 @@ mod shuffle;
 mod signed_integer;
 mod value;
 
+pub mod gadgets;
+
 pub use bit_range::BitRange;
-pub use cloak::cloak;
-pub use range_proof::range_proof;
+pub use cloak::{cloak, cloak_with_fees};
+pub use range_proof::{bit_decomposition, range_proof};
 pub use signed_integer::SignedInteger;
 pub use value::{AllocatedValue, CommittedValue, Value};
 
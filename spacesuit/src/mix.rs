@@ -4,6 +4,7 @@ use crate::signed_integer::SignedInteger;
 use crate::value::{AllocatedValue, Value};
 use bulletproofs::r1cs::{ConstraintSystem, R1CSError, RandomizedConstraintSystem};
 use curve25519_dalek::scalar::Scalar;
+use std::cmp::Ordering;
 use std::iter;
 use subtle::{ConditionallySelectable, ConstantTimeEq};
 
@@ -44,13 +45,105 @@ pub fn mix<CS: ConstraintSystem>(
 pub fn k_mix<CS: ConstraintSystem>(
     cs: &mut CS,
     inputs: Vec<AllocatedValue>,
+) -> Result<(Vec<AllocatedValue>, Vec<AllocatedValue>), R1CSError> {
+    k_mix_with_order(cs, inputs, None)
+}
+
+/// Same as `k_mix`, but the prover orders the input values by sorting them with the comparison `order`
+/// (e.g. `by_flavor_and_quantity`) instead of the default grouping by flavor.
+/// The sorting is stable and not constant-time.
+///
+/// The order must place the values of the same flavor next to each other:
+/// otherwise, a `GadgetError` is returned instead of an unprovable assignment.
+pub fn k_mix_by<CS, F>(
+    cs: &mut CS,
+    inputs: Vec<AllocatedValue>,
+    mut order: F,
+) -> Result<(Vec<AllocatedValue>, Vec<AllocatedValue>), R1CSError>
+where
+    CS: ConstraintSystem,
+    F: FnMut(&Value, &Value) -> Ordering,
+{
+    k_mix_with_order(cs, inputs, Some(&mut order))
+}
+
+/// Enforces that the outputs are either a merge of the inputs: `D = A + B && C = 0`,
+/// or the outputs are equal to the inputs `C = A && D = B`. See spec for more details.
+/// Works for `k` inputs and `k` outputs.
+///
+/// Returns the inputs reordered by flavor and the merged outputs.
+pub fn merge<CS: ConstraintSystem>(
+    cs: &mut CS,
+    inputs: Vec<AllocatedValue>,
+) -> Result<(Vec<AllocatedValue>, Vec<AllocatedValue>), R1CSError> {
+    k_mix(cs, inputs)
+}
+
+/// Same as `merge`, but orders the inputs with the comparison `order` as in `k_mix_by`.
+pub fn merge_by<CS, F>(
+    cs: &mut CS,
+    inputs: Vec<AllocatedValue>,
+    order: F,
+) -> Result<(Vec<AllocatedValue>, Vec<AllocatedValue>), R1CSError>
+where
+    CS: ConstraintSystem,
+    F: FnMut(&Value, &Value) -> Ordering,
+{
+    k_mix_by(cs, inputs, order)
+}
+
+/// Enforces that the outputs are either a split of the inputs :`A = C + D && B = 0`,
+/// or the outputs are equal to the inputs `C = A && D = B`. See spec for more details.
+/// Works for `k` inputs and `k` outputs.
+///
+/// Returns the split inputs and the outputs reordered by flavor.
+///
+/// Note: the `split` gadget is the same thing as a `merge` gadget, but "backwards".
+/// This means that if you reverse all of the commitment vectors, and switch the
+/// inputs and outputs of a `merge` gadget, then you have a `split` gadget.
+pub fn split<CS: ConstraintSystem>(
+    cs: &mut CS,
+    mut inputs: Vec<AllocatedValue>,
+) -> Result<(Vec<AllocatedValue>, Vec<AllocatedValue>), R1CSError> {
+    inputs.reverse();
+    k_mix(cs, inputs).map(|(outs, ins)| (ins, outs))
+}
+
+/// Same as `split`, but orders the reversed outputs with the comparison `order` as in `k_mix_by`.
+pub fn split_by<CS, F>(
+    cs: &mut CS,
+    mut inputs: Vec<AllocatedValue>,
+    order: F,
+) -> Result<(Vec<AllocatedValue>, Vec<AllocatedValue>), R1CSError>
+where
+    CS: ConstraintSystem,
+    F: FnMut(&Value, &Value) -> Ordering,
+{
+    inputs.reverse();
+    k_mix_by(cs, inputs, order).map(|(outs, ins)| (ins, outs))
+}
+
+/// Orders the values by flavor (as little-endian bytes), then by quantity.
+/// Can be used as the order of `k_mix_by`, `merge_by` and `split_by`.
+pub fn by_flavor_and_quantity(a: &Value, b: &Value) -> Ordering {
+    a.f.as_bytes()
+        .iter()
+        .rev()
+        .cmp(b.f.as_bytes().iter().rev())
+        .then(a.q.cmp(&b.q))
+}
+
+fn k_mix_with_order<CS: ConstraintSystem>(
+    cs: &mut CS,
+    inputs: Vec<AllocatedValue>,
+    order: Option<&mut dyn FnMut(&Value, &Value) -> Ordering>,
 ) -> Result<(Vec<AllocatedValue>, Vec<AllocatedValue>), R1CSError> {
     // If there is only one input and output, simply reuse the input wires as output wires.
     if inputs.len() == 1 {
         return Ok((inputs.clone(), inputs));
     }
 
-    let (mix_in, mix_mid, mix_out) = make_intermediate_values(&inputs, cs)?;
+    let (mix_in, mix_mid, mix_out) = make_intermediate_values(&inputs, cs, order)?;
     call_mix_gadget(cs, &mix_in, &mix_mid, &mix_out)?;
     Ok((mix_in, mix_out))
 }
@@ -93,6 +186,7 @@ fn call_mix_gadget<CS: ConstraintSystem>(
 
 // Takes:
 // * a vector of `AllocatedValue`s that represents the input (or output) values for a cloak gadget
+// * an optional order of the values (by default, the values are grouped by flavor)
 //
 // Returns:
 // * a vector of `AllocatedValue`s for the input values of a k-mix gadget
@@ -101,6 +195,7 @@ fn call_mix_gadget<CS: ConstraintSystem>(
 fn make_intermediate_values<CS: ConstraintSystem>(
     inputs: &Vec<AllocatedValue>,
     cs: &mut CS,
+    order: Option<&mut dyn FnMut(&Value, &Value) -> Ordering>,
 ) -> Result<
     (
         Vec<AllocatedValue>,
@@ -112,7 +207,10 @@ fn make_intermediate_values<CS: ConstraintSystem>(
     let collected_inputs: Option<Vec<_>> = inputs.iter().map(|input| input.assignment).collect();
     match collected_inputs {
         Some(input_values) => {
-            let (mix_in, mix_in_values) = order_by_flavor(&input_values, cs)?;
+            let (mix_in, mix_in_values) = match order {
+                Some(order) => sort_by_order(&input_values, order, cs)?,
+                None => order_by_flavor(&input_values, cs)?,
+            };
             let (mix_mid, mix_out) = combine_by_flavor(&mix_in_values, cs)?;
             Ok((mix_in, mix_mid, mix_out))
         }
@@ -168,6 +266,40 @@ fn order_by_flavor<CS: ConstraintSystem>(
     Ok((allocated_outputs, outputs))
 }
 
+// Takes:
+// * a vector of `AllocatedValue`s
+// * an order of the values
+//
+// Returns:
+// * a vector of `AllocatedValue`s that is a reordering of the inputs sorted by the order,
+//   or an error if the values of the same flavor are not adjacent
+// * a vector of `Value`s that were used to create the output `AllocatedValue`s
+fn sort_by_order<CS: ConstraintSystem>(
+    inputs: &Vec<Value>,
+    order: &mut dyn FnMut(&Value, &Value) -> Ordering,
+    cs: &mut CS,
+) -> Result<(Vec<AllocatedValue>, Vec<Value>), R1CSError> {
+    let mut outputs = inputs.clone();
+    outputs.sort_by(|a, b| order(a, b));
+
+    // Once the flavor changes, it must not appear again.
+    for i in 1..outputs.len() {
+        if outputs[i].f != outputs[i - 1].f && outputs[i..].iter().any(|v| v.f == outputs[i - 1].f)
+        {
+            return Err(R1CSError::GadgetError {
+                description: "values of the same flavor are not adjacent in the order".to_string(),
+            });
+        }
+    }
+
+    let allocated_outputs = outputs
+        .iter()
+        .map(|value| value.allocate(cs))
+        .collect::<Result<Vec<AllocatedValue>, _>>()?;
+
+    Ok((allocated_outputs, outputs))
+}
+
 // Takes:
 // * a vector of `Value`s that are grouped according to flavor
 //
@@ -624,4 +756,65 @@ mod tests {
 
         (mid, output)
     }
+
+    #[test]
+    fn sort_by_order_test() {
+        let pc_gens = PedersenGens::default();
+        let bp_gens = BulletproofGens::new(128, 1);
+        let mut transcript = Transcript::new(b"SortByOrderTest");
+        let mut prover_cs = Prover::new(&bp_gens, &pc_gens, &mut transcript);
+
+        // Sorted by flavor (yuan = 888 > peso = 666 > zero), then by quantity
+        assert_eq!(
+            sort_by_order(
+                &vec![yuan(2), peso(3), zero(), yuan(1), peso(1)],
+                &mut by_flavor_and_quantity,
+                &mut prover_cs
+            )
+            .unwrap()
+            .1,
+            vec![zero(), peso(1), peso(3), yuan(1), yuan(2)]
+        );
+        // Custom tie-break: larger quantities first
+        assert_eq!(
+            sort_by_order(
+                &vec![yuan(2), peso(3), yuan(5), peso(4)],
+                &mut |a: &Value, b: &Value| by_flavor_and_quantity(b, a),
+                &mut prover_cs
+            )
+            .unwrap()
+            .1,
+            vec![yuan(5), yuan(2), peso(4), peso(3)]
+        );
+        // Order that does not group the flavors
+        assert!(sort_by_order(
+            &vec![yuan(2), peso(3), yuan(5), peso(4)],
+            &mut |a: &Value, b: &Value| a.q.cmp(&b.q),
+            &mut prover_cs
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn k_mix_by_test() {
+        let inputs = vec![yuan(2), peso(3), yuan(5), peso(4)];
+        let merged =
+            |order: &mut dyn FnMut(&Value, &Value) -> Ordering| -> Result<Vec<Value>, R1CSError> {
+                let pc_gens = PedersenGens::default();
+                let bp_gens = BulletproofGens::new(128, 1);
+                let mut transcript = Transcript::new(b"KMixByTest");
+                let mut prover = Prover::new(&bp_gens, &pc_gens, &mut transcript);
+                let (_, vars) = inputs.commit(&mut prover, &mut rand::thread_rng());
+                let (_, outputs) = k_mix_by(&mut prover, vars, order)?;
+                Ok(outputs
+                    .iter()
+                    .map(|v| v.assignment.unwrap())
+                    .collect::<Vec<_>>())
+            };
+        assert_eq!(
+            merged(&mut by_flavor_and_quantity).unwrap(),
+            vec![zero(), peso(7), zero(), yuan(7)]
+        );
+        assert!(merged(&mut |a: &Value, b: &Value| a.q.cmp(&b.q)).is_err());
+    }
 }
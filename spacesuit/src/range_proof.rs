@@ -2,25 +2,43 @@ use bit_range::BitRange;
 use bulletproofs::r1cs::{ConstraintSystem, LinearCombination, R1CSError};
 use curve25519_dalek::scalar::Scalar;
 
-use crate::signed_integer::SignedInteger;
-
 /// Enforces that the quantity of v is in the range [0, 2^n).
 pub fn range_proof<CS: ConstraintSystem>(
     cs: &mut CS,
-    mut v: LinearCombination,
-    v_assignment: Option<SignedInteger>,
+    v: LinearCombination,
+    v_assignment: Option<Scalar>,
     n: BitRange,
 ) -> Result<(), R1CSError> {
+    let (residual, _) = bit_decomposition(cs, v, v_assignment, n)?;
+
+    // Enforce that v = Sum(b_i * 2^i, i = 0..n-1)
+    cs.constrain(residual);
+
+    Ok(())
+}
+
+/// Allocates `n` boolean variables `b_i` for the bits of `v` and returns
+/// the linear combination `v - Sum(b_i * 2^i, i = 0..n-1)` without constraining it,
+/// together with its assignment.
+///
+/// The returned combination can be zero only if `v` is in the range [0, 2^n),
+/// which allows composing the range check with other constraints.
+pub fn bit_decomposition<CS: ConstraintSystem>(
+    cs: &mut CS,
+    mut v: LinearCombination,
+    v_assignment: Option<Scalar>,
+    n: BitRange,
+) -> Result<(LinearCombination, Option<Scalar>), R1CSError> {
+    let bytes = v_assignment.map(|v| v.to_bytes());
+    let mut residual = v_assignment;
     let mut exp_2 = Scalar::one();
     let n_usize: usize = n.into();
     for i in 0..n_usize {
+        let bit = bytes.map(|bytes| (bytes[i / 8] >> (i % 8)) & 1);
+
         // Create low-level variables and add them to constraints
-        let (a, b, o) = cs.allocate_multiplier(v_assignment.and_then(|q| {
-            q.to_u64().map(|q| {
-                let bit: u64 = (q >> i) & 1;
-                ((1 - bit).into(), bit.into())
-            })
-        }))?;
+        let (a, b, o) =
+            cs.allocate_multiplier(bit.map(|bit| (Scalar::from(1 - bit), Scalar::from(bit))))?;
 
         // Enforce a * b = 0, so one of (a,b) is zero
         cs.constrain(o.into());
@@ -29,17 +47,17 @@ pub fn range_proof<CS: ConstraintSystem>(
         cs.constrain(a + (b - 1u64));
 
         // Add `-b_i*2^i` to the linear combination
-        // in order to form the following constraint by the end of the loop:
-        // v = Sum(b_i * 2^i, i = 0..n-1)
+        // in order to form the following expression by the end of the loop:
+        // v - Sum(b_i * 2^i, i = 0..n-1)
         v = v - b * exp_2;
+        if bit == Some(1) {
+            residual = residual.map(|r| r - exp_2);
+        }
 
         exp_2 = exp_2 + exp_2;
     }
 
-    // Enforce that v = Sum(b_i * 2^i, i = 0..n-1)
-    cs.constrain(v);
-
-    Ok(())
+    Ok((v, residual))
 }
 
 #[cfg(test)]
@@ -67,12 +85,32 @@ mod tests {
         }
     }
 
-    fn range_proof_helper(v_val: SignedInteger, n: usize) -> Result<(), R1CSError> {
+    #[test]
+    fn wide_range_proof_gadget() {
+        for n in [64, 128, 252].iter() {
+            // 2^n - 1 is the largest value in range
+            let max = pow2(*n) - Scalar::one();
+            assert!(range_proof_helper(max, *n).is_ok());
+            assert!(range_proof_helper(pow2(*n - 1) + Scalar::from(12345u64), *n).is_ok());
+            assert!(range_proof_helper(max + Scalar::one(), *n).is_err());
+            assert!(range_proof_helper(-Scalar::one(), *n).is_err());
+        }
+        assert!(BitRange::new(65).is_none());
+        assert!(BitRange::new_wide(253).is_none());
+    }
+
+    fn pow2(n: usize) -> Scalar {
+        let mut bytes = [0u8; 32];
+        bytes[n / 8] = 1 << (n % 8);
+        Scalar::from_bits(bytes)
+    }
+
+    fn range_proof_helper(v_val: Scalar, n: usize) -> Result<(), R1CSError> {
         // Common
         let pc_gens = PedersenGens::default();
-        let bp_gens = BulletproofGens::new(128, 1);
-        let bit_width = BitRange::new(n).ok_or(R1CSError::GadgetError {
-            description: "Invalid Bitrange; Bitrange must be between 0 and 64".to_string(),
+        let bp_gens = BulletproofGens::new(256, 1);
+        let bit_width = BitRange::new_wide(n).ok_or(R1CSError::GadgetError {
+            description: "Invalid Bitrange; Bitrange must be between 0 and 252".to_string(),
         })?;
 
         // Prover's scope
@@ -83,7 +121,7 @@ mod tests {
 
             let mut prover = Prover::new(&bp_gens, &pc_gens, &mut prover_transcript);
 
-            let (com, var) = prover.commit(v_val.into(), Scalar::random(&mut rng));
+            let (com, var) = prover.commit(v_val, Scalar::random(&mut rng));
             assert!(range_proof(&mut prover, var.into(), Some(v_val), bit_width).is_ok());
 
             let proof = prover.prove()?;
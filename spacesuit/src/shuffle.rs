@@ -3,6 +3,10 @@ use core::cmp::{max, min};
 use value::{AllocatedValue, Value};
 
 /// Enforces that the output variables `y` are a valid reordering of the inputs variables `x`.
+///
+/// The gadget proves that `∏(x_i - z) == ∏(y_i - z)` for a random challenge `z`,
+/// using `2*(k-1)` multipliers for `k` variables in the randomized phase of the constraint system.
+/// Fails if the lists have different lengths. Empty lists are trivially a reordering of each other.
 pub fn scalar_shuffle<CS: ConstraintSystem>(
     cs: &mut CS,
     x: Vec<Variable>,
@@ -15,6 +19,9 @@ pub fn scalar_shuffle<CS: ConstraintSystem>(
     }
 
     let k = x.len();
+    if k == 0 {
+        return Ok(());
+    }
     if k == 1 {
         cs.constrain(y[0] - x[0]);
         return Ok(());
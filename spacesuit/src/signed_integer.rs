@@ -3,9 +3,10 @@ use core::ops::Neg;
 use curve25519_dalek::scalar::Scalar;
 use std::ops::{Add, Mul};
 use subtle::{Choice, ConditionallySelectable};
+use zeroize::Zeroize;
 
 /// Represents a signed integer with absolute value in the 64-bit range.
-#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
 pub struct SignedInteger(i128);
 
 impl SignedInteger {
@@ -25,6 +26,12 @@ impl SignedInteger {
     }
 }
 
+impl Zeroize for SignedInteger {
+    fn zeroize(&mut self) {
+        self.0.zeroize();
+    }
+}
+
 impl From<u64> for SignedInteger {
     fn from(u: u64) -> SignedInteger {
         SignedInteger(u as i128)
@@ -3,6 +3,7 @@ use curve25519_dalek::ristretto::CompressedRistretto;
 use curve25519_dalek::scalar::Scalar;
 use rand::{CryptoRng, Rng};
 
+use crate::bit_range::BitRange;
 use crate::signed_integer::SignedInteger;
 
 /// A pair of a secret _quantity_ (64-bit integer)
@@ -33,6 +34,9 @@ pub struct AllocatedValue {
     pub f: Variable,
     /// Secret assignment to the above variables
     pub assignment: Option<Value>,
+    /// Bit width of the quantity enforced by the range proof
+    /// when the value is an output of the cloak gadget
+    pub bits: BitRange,
 }
 
 impl Value {
@@ -53,6 +57,7 @@ impl Value {
             q: q_var,
             f: f_var,
             assignment: Some(*self),
+            bits: BitRange::u64(),
         })
     }
 }
@@ -68,6 +73,7 @@ impl AllocatedValue {
             q,
             f,
             assignment: None,
+            bits: BitRange::u64(),
         })
     }
 
@@ -104,6 +110,7 @@ impl ProverCommittable for Value {
             q: q_var,
             f: f_var,
             assignment: Some(*self),
+            bits: BitRange::u64(),
         };
         (commitments, vars)
     }
@@ -135,6 +142,7 @@ impl VerifierCommittable for CommittedValue {
             q: verifier.commit(self.q),
             f: verifier.commit(self.f),
             assignment: None,
+            bits: BitRange::u64(),
         }
     }
 }
@@ -0,0 +1,63 @@
+extern crate bulletproofs;
+extern crate curve25519_dalek;
+extern crate merlin;
+extern crate rand;
+extern crate spacesuit;
+
+use bulletproofs::r1cs::{Prover, R1CSError, Verifier};
+use bulletproofs::{BulletproofGens, PedersenGens};
+use curve25519_dalek::scalar::Scalar;
+use merlin::Transcript;
+
+use spacesuit::gadgets::scalar_shuffle;
+
+fn shuffle_helper(left: Vec<u64>, right: Vec<u64>) -> Result<(), R1CSError> {
+    let pc_gens = PedersenGens::default();
+    let bp_gens = BulletproofGens::new(16, 1);
+    let mut rng = rand::thread_rng();
+
+    let (proof, left_com, right_com) = {
+        let mut prover_transcript = Transcript::new(b"ShuffleTest");
+        let mut prover = Prover::new(&bp_gens, &pc_gens, &mut prover_transcript);
+
+        let (left_com, left_vars): (Vec<_>, Vec<_>) = left
+            .iter()
+            .map(|x| prover.commit(Scalar::from(*x), Scalar::random(&mut rng)))
+            .unzip();
+        let (right_com, right_vars): (Vec<_>, Vec<_>) = right
+            .iter()
+            .map(|y| prover.commit(Scalar::from(*y), Scalar::random(&mut rng)))
+            .unzip();
+
+        scalar_shuffle(&mut prover, left_vars, right_vars)?;
+        (prover.prove()?, left_com, right_com)
+    };
+
+    let mut verifier_transcript = Transcript::new(b"ShuffleTest");
+    let mut verifier = Verifier::new(&bp_gens, &pc_gens, &mut verifier_transcript);
+
+    let left_vars = left_com.iter().map(|c| verifier.commit(*c)).collect();
+    let right_vars = right_com.iter().map(|c| verifier.commit(*c)).collect();
+
+    scalar_shuffle(&mut verifier, left_vars, right_vars)?;
+    verifier.verify(&proof)
+}
+
+#[test]
+fn scalar_shuffle_gadget() {
+    assert!(shuffle_helper(vec![3], vec![3]).is_ok());
+    assert!(shuffle_helper(vec![3], vec![4]).is_err());
+    assert!(shuffle_helper(vec![3, 5], vec![5, 3]).is_ok());
+    assert!(shuffle_helper(vec![3, 5, 7, 9], vec![7, 3, 9, 5]).is_ok());
+    assert!(shuffle_helper(vec![3, 3, 7, 9], vec![9, 3, 7, 3]).is_ok());
+
+    // Not a permutation
+    assert!(shuffle_helper(vec![3, 5, 7, 9], vec![7, 3, 9, 8]).is_err());
+    assert!(shuffle_helper(vec![3, 3, 7, 9], vec![3, 7, 7, 9]).is_err());
+
+    // Lengths do not match
+    match shuffle_helper(vec![3, 5], vec![3, 5, 7]) {
+        Err(R1CSError::GadgetError { .. }) => {}
+        _ => panic!("expected a gadget error"),
+    }
+}
@@ -8,19 +8,30 @@ use bulletproofs::{BulletproofGens, PedersenGens};
 use merlin::Transcript;
 use rand::{CryptoRng, Rng};
 
-use spacesuit::{cloak, CommittedValue, ProverCommittable, Value, VerifierCommittable};
+use spacesuit::{
+    cloak_with_fees, CommittedValue, ProverCommittable, SignedInteger, Value, VerifierCommittable,
+};
 
 fn spacesuit_helper(
     bp_gens: &BulletproofGens,
     inputs: Vec<Value>,
     outputs: Vec<Value>,
+) -> Result<(), R1CSError> {
+    spacesuit_fee_helper(bp_gens, inputs, outputs, vec![])
+}
+
+fn spacesuit_fee_helper(
+    bp_gens: &BulletproofGens,
+    inputs: Vec<Value>,
+    outputs: Vec<Value>,
+    fees: Vec<Value>,
 ) -> Result<(), R1CSError> {
     let pc_gens = PedersenGens::default();
     let mut rng = rand::thread_rng();
 
-    let (proof, in_com, out_com) = prove(&bp_gens, &pc_gens, &inputs, &outputs, &mut rng)?;
+    let (proof, in_com, out_com) = prove(&bp_gens, &pc_gens, &inputs, &outputs, &fees, &mut rng)?;
 
-    verify(&bp_gens, &pc_gens, &proof, &in_com, &out_com)
+    verify(&bp_gens, &pc_gens, &proof, &in_com, &out_com, &fees)
 }
 
 fn prove<R: Rng + CryptoRng>(
@@ -28,6 +39,7 @@ fn prove<R: Rng + CryptoRng>(
     pc_gens: &PedersenGens,
     inputs: &Vec<Value>,
     outputs: &Vec<Value>,
+    fees: &[Value],
     rng: &mut R,
 ) -> Result<(R1CSProof, Vec<CommittedValue>, Vec<CommittedValue>), R1CSError>
 where
@@ -39,7 +51,7 @@ where
     let (in_com, in_vars) = inputs.commit(&mut prover, rng);
     let (out_com, out_vars) = outputs.commit(&mut prover, rng);
 
-    cloak(&mut prover, in_vars, out_vars)?;
+    cloak_with_fees(&mut prover, in_vars, out_vars, fees)?;
     let proof = prover.prove()?;
 
     Ok((proof, in_com, out_com))
@@ -51,6 +63,7 @@ fn verify(
     proof: &R1CSProof,
     in_com: &Vec<CommittedValue>,
     out_com: &Vec<CommittedValue>,
+    fees: &[Value],
 ) -> Result<(), R1CSError> {
     // Verifier makes a `ConstraintSystem` instance representing a merge gadget
     let mut verifier_transcript = Transcript::new(b"TransactionTest");
@@ -59,7 +72,7 @@ fn verify(
     let in_vars = in_com.commit(&mut verifier);
     let out_vars = out_com.commit(&mut verifier);
 
-    assert!(cloak(&mut verifier, in_vars, out_vars,).is_ok());
+    assert!(cloak_with_fees(&mut verifier, in_vars, out_vars, fees).is_ok());
 
     Ok(verifier.verify(&proof)?)
 }
@@ -336,3 +349,40 @@ fn spacesuit_4_4() {
     )
     .is_ok());
 }
+
+#[test]
+fn spacesuit_fees() {
+    let bp_gens = BulletproofGens::new(1000, 1);
+    // Fee of the same flavor as the outputs
+    assert!(spacesuit_fee_helper(&bp_gens, vec![yuan(10)], vec![yuan(7)], vec![yuan(3)]).is_ok());
+    assert!(spacesuit_fee_helper(&bp_gens, vec![yuan(10)], vec![yuan(8)], vec![yuan(3)]).is_err());
+    assert!(spacesuit_fee_helper(&bp_gens, vec![yuan(10)], vec![yuan(6)], vec![yuan(3)]).is_err());
+
+    // Per-flavor fees
+    assert!(spacesuit_fee_helper(
+        &bp_gens,
+        vec![yuan(10), peso(5), peso(5)],
+        vec![peso(8), yuan(9)],
+        vec![yuan(1), peso(2)]
+    )
+    .is_ok());
+    assert!(spacesuit_fee_helper(
+        &bp_gens,
+        vec![yuan(10), peso(5), peso(5)],
+        vec![peso(8), yuan(9)],
+        vec![peso(1), yuan(2)]
+    )
+    .is_err());
+
+    // Entire input spent on the fee
+    assert!(spacesuit_fee_helper(&bp_gens, vec![euro(4)], vec![], vec![euro(4)]).is_ok());
+
+    // Negative fees are rejected
+    let negative_fee = Value {
+        q: -SignedInteger::from(1u64),
+        f: 888u64.into(),
+    };
+    assert!(
+        spacesuit_fee_helper(&bp_gens, vec![yuan(1)], vec![yuan(2)], vec![negative_fee]).is_err()
+    );
+}
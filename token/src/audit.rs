@@ -0,0 +1,251 @@
+use bulletproofs::PedersenGens;
+use curve25519_dalek::ristretto::{CompressedRistretto, RistrettoPoint};
+use curve25519_dalek::scalar::Scalar;
+use curve25519_dalek::traits::Identity;
+use zkvm::{Entry, TxLog};
+
+use crate::errors::TokenError;
+use crate::issuance::Issuance;
+use crate::retirement::Retirement;
+use crate::token::Token;
+
+/// Proof of the supply of a token: the issued quantity minus the retired quantity.
+///
+/// The auditor collects all issuances of the token from the transaction logs:
+/// their flavor commitments are never blinded. The retirements are identified by
+/// their quantity commitments and shown to be of the token by opening their flavor commitments.
+/// The sum of the issued minus the retired quantity commitments is opened as a whole,
+/// so the quantities of the individual issuances are not disclosed.
+#[derive(Clone, Debug)]
+pub struct SupplyProof {
+    /// Issued minus retired quantity.
+    pub supply: u64,
+    /// Sum of the blinding factors of the issued minus the retired quantities.
+    pub qty_blinding: Scalar,
+    /// Quantity commitments of the retirements and the blinding factors of their flavor commitments.
+    pub retirements: Vec<(CompressedRistretto, Scalar)>,
+}
+
+/// Issuer's record of the issuances and retirements of a token, used to prove its supply.
+#[derive(Clone, Debug)]
+pub struct SupplyAudit {
+    flavor: Scalar,
+    issued: u64,
+    retired: u64,
+    qty_blinding: Scalar,
+    retirements: Vec<(CompressedRistretto, Scalar)>,
+}
+
+impl SupplyAudit {
+    /// Creates an empty record for the token.
+    pub fn new(token: &Token) -> Self {
+        SupplyAudit {
+            flavor: token.flavor(),
+            issued: 0,
+            retired: 0,
+            qty_blinding: Scalar::zero(),
+            retirements: Vec::new(),
+        }
+    }
+
+    /// Records a published issuance of the token.
+    pub fn add_issuance(&mut self, issuance: &Issuance) -> Result<(), TokenError> {
+        if issuance.flavor() != self.flavor {
+            return Err(TokenError::FlavorMismatch);
+        }
+        self.issued = self
+            .issued
+            .checked_add(issuance.total_qty())
+            .ok_or(TokenError::InvalidSupplyProof)?;
+        self.qty_blinding += issuance.qty_blinding();
+        Ok(())
+    }
+
+    /// Records a published retirement of the token.
+    pub fn add_retirement(&mut self, retirement: &Retirement) -> Result<(), TokenError> {
+        if retirement.token().flavor() != self.flavor {
+            return Err(TokenError::FlavorMismatch);
+        }
+        self.retired = self
+            .retired
+            .checked_add(retirement.qty())
+            .ok_or(TokenError::InvalidSupplyProof)?;
+        self.qty_blinding -= retirement.qty_blinding();
+        let qty_point = PedersenGens::default()
+            .commit(Scalar::from(retirement.qty()), retirement.qty_blinding())
+            .compress();
+        self.retirements
+            .push((qty_point, retirement.flv_blinding()));
+        Ok(())
+    }
+
+    /// Creates the proof of the supply.
+    pub fn prove(&self) -> Result<SupplyProof, TokenError> {
+        let supply = self
+            .issued
+            .checked_sub(self.retired)
+            .ok_or(TokenError::InvalidSupplyProof)?;
+        Ok(SupplyProof {
+            supply,
+            qty_blinding: self.qty_blinding,
+            retirements: self.retirements.clone(),
+        })
+    }
+}
+
+impl SupplyProof {
+    /// Verifies the supply of the token issued and retired in the given transaction logs.
+    pub fn verify(&self, token: &Token, logs: &[TxLog]) -> Result<(), TokenError> {
+        let gens = PedersenGens::default();
+        let flavor = token.flavor();
+        let flv_point = gens.commit(flavor, Scalar::zero()).compress();
+
+        let mut net = RistrettoPoint::identity();
+        let mut found = vec![false; self.retirements.len()];
+        for entry in logs.iter().flat_map(|log| log.iter()) {
+            match entry {
+                Entry::Issue(qty, flv) if flv == &flv_point => {
+                    net += qty.decompress().ok_or(TokenError::InvalidSupplyProof)?;
+                }
+                Entry::Retire(qty, flv) => {
+                    let index = self
+                        .retirements
+                        .iter()
+                        .zip(found.iter())
+                        .position(|((point, _), found)| point == qty && !found);
+                    if let Some(i) = index {
+                        let flv_blinding = self.retirements[i].1;
+                        if flv != &gens.commit(flavor, flv_blinding).compress() {
+                            return Err(TokenError::InvalidSupplyProof);
+                        }
+                        net -= qty.decompress().ok_or(TokenError::InvalidSupplyProof)?;
+                        found[i] = true;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if found.iter().any(|found| !found) {
+            return Err(TokenError::InvalidSupplyProof);
+        }
+        if net != gens.commit(Scalar::from(self.supply), self.qty_blinding) {
+            return Err(TokenError::InvalidSupplyProof);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bulletproofs::BulletproofGens;
+    use zkvm::{Data, Predicate, Program, Prover, Signature, TxHeader, VerificationKey, Verifier};
+
+    fn build(program: Program, keys: &[Scalar]) -> TxLog {
+        let header = TxHeader {
+            version: 0u64,
+            mintime: 0u64,
+            maxtime: 0u64,
+            maxcost: u64::max_value(),
+        };
+        let bp_gens = BulletproofGens::new(256, 1);
+        let (tx, _, txlog) = Prover::build_tx(program, header, &bp_gens, |t, vks| {
+            let signtx_keys = vks
+                .iter()
+                .map(|vk| {
+                    *keys
+                        .iter()
+                        .find(|key| VerificationKey::from_secret(key) == *vk)
+                        .unwrap()
+                })
+                .collect::<Vec<_>>();
+            Signature::sign_aggregated(t, &signtx_keys)
+        })
+        .unwrap();
+        assert!(Verifier::verify_tx(tx, &bp_gens).is_ok());
+        txlog
+    }
+
+    #[test]
+    fn supply_audit() {
+        let issuer_key = Scalar::from(1u64);
+        let nonce_key = Scalar::from(2u64);
+        let holder_key = Scalar::from(3u64);
+        let holder = Predicate::Key(VerificationKey::from_secret(&holder_key));
+
+        let issuance = Issuance::new(issuer_key, b"USD".to_vec())
+            .output(10, holder.clone())
+            .output(5, holder.clone());
+        let token = issuance.token().clone();
+        let issue_log = build(
+            Program::build(|p| {
+                p.push(Predicate::Key(VerificationKey::from_secret(&nonce_key)))
+                    .push(Data::Opaque([0xffu8; 32].to_vec()))
+                    .nonce()
+                    .sign_tx();
+                issuance.append_to(p).unwrap()
+            }),
+            &[issuer_key, nonce_key],
+        );
+        let outputs = issue_log
+            .iter()
+            .filter_map(|entry| match entry {
+                Entry::Output(output) => Some(output.clone()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(outputs.len(), 2);
+
+        // Holder retires 3 out of 10 and keeps the change.
+        let retirement = Retirement::new(token.clone(), 3)
+            .input(outputs[0].clone(), 10)
+            .change(holder.clone());
+        let retire_log = build(retirement.program().unwrap(), &[holder_key]);
+        assert_eq!(
+            Retirement::new(token.clone(), 11)
+                .input(outputs[0].clone(), 10)
+                .program()
+                .map(|_| ()),
+            Err(TokenError::InsufficientQuantity)
+        );
+        assert_eq!(
+            Retirement::new(token.clone(), 3)
+                .input(outputs[0].clone(), 10)
+                .program()
+                .map(|_| ()),
+            Err(TokenError::MissingChange)
+        );
+
+        let mut audit = SupplyAudit::new(&token);
+        audit.add_issuance(&issuance).unwrap();
+        audit.add_retirement(&retirement).unwrap();
+        let proof = audit.prove().unwrap();
+        assert_eq!(proof.supply, 12);
+
+        let logs = vec![issue_log.clone(), retire_log.clone()];
+        assert_eq!(proof.verify(&token, &logs), Ok(()));
+
+        // Wrong supply is rejected.
+        let mut wrong = proof.clone();
+        wrong.supply = 11;
+        assert_eq!(
+            wrong.verify(&token, &logs),
+            Err(TokenError::InvalidSupplyProof)
+        );
+
+        // Retirement missing from the logs is rejected.
+        assert_eq!(
+            proof.verify(&token, &[issue_log]),
+            Err(TokenError::InvalidSupplyProof)
+        );
+
+        // Other tokens are not counted.
+        let other = Issuance::new(issuer_key, b"EUR".to_vec());
+        assert_eq!(
+            proof.verify(other.token(), &logs),
+            Err(TokenError::InvalidSupplyProof)
+        );
+        assert_eq!(audit.add_issuance(&other), Err(TokenError::FlavorMismatch));
+    }
+}
@@ -0,0 +1,31 @@
+//! Errors related to the token operations.
+
+/// Represents an error in building a token transaction.
+#[derive(Fail, Clone, Debug, Eq, PartialEq)]
+pub enum TokenError {
+    /// This error occurs when the transaction requires a signature
+    /// with a key that was not provided.
+    #[fail(display = "Signing key is missing.")]
+    MissingSigningKey,
+
+    /// This error occurs when an issuance has no outputs.
+    #[fail(display = "Issuance has no outputs.")]
+    EmptyIssuance,
+
+    /// This error occurs when the spent outputs hold less than the retired quantity.
+    #[fail(display = "Inputs hold less than the retired quantity.")]
+    InsufficientQuantity,
+
+    /// This error occurs when the spent outputs hold more than the retired quantity,
+    /// but no predicate for the change is provided.
+    #[fail(display = "Change predicate is missing.")]
+    MissingChange,
+
+    /// This error occurs when an issuance or a retirement is of a different token.
+    #[fail(display = "Token flavor does not match.")]
+    FlavorMismatch,
+
+    /// This error occurs when the supply proof does not match the transaction logs.
+    #[fail(display = "Supply proof is invalid.")]
+    InvalidSupplyProof,
+}
@@ -0,0 +1,178 @@
+use curve25519_dalek::scalar::Scalar;
+use merlin::Transcript;
+use zkvm::{Predicate, Program, Signature, VerificationKey};
+
+use crate::errors::TokenError;
+use crate::token::Token;
+
+/// Builder of the standard issuance of a token: issues the given quantities
+/// of the token and locks each of them with a destination predicate.
+///
+/// The token's flavor is derived from the issuance predicate and the metadata,
+/// and the issuance is authorized with the issuer's key via `signtx`.
+/// The blinding factors of the issued quantities are kept for the supply audits.
+#[derive(Clone, Debug)]
+pub struct Issuance {
+    token: Token,
+    issuer_key: Scalar,
+    // quantity, its blinding factor and the destination
+    outputs: Vec<(u64, Scalar, Predicate)>,
+}
+
+impl Issuance {
+    /// Creates an issuance of the token defined by the issuer's key and the metadata.
+    pub fn new(issuer_key: Scalar, metadata: Vec<u8>) -> Self {
+        let predicate = Predicate::Key(VerificationKey::from_secret(&issuer_key));
+        Issuance {
+            token: Token::new(predicate, metadata),
+            issuer_key,
+            outputs: Vec::new(),
+        }
+    }
+
+    /// Adds an output with a given quantity of the token locked by the destination predicate.
+    pub fn output(mut self, qty: u64, destination: Predicate) -> Self {
+        let blinding = Scalar::random(&mut rand::thread_rng());
+        self.outputs.push((qty, blinding, destination));
+        self
+    }
+
+    /// Returns the issued token.
+    pub fn token(&self) -> &Token {
+        &self.token
+    }
+
+    /// Returns the flavor of the issued token.
+    pub fn flavor(&self) -> Scalar {
+        self.token.flavor()
+    }
+
+    /// Returns the total quantity issued.
+    pub fn total_qty(&self) -> u64 {
+        self.outputs.iter().map(|(qty, _, _)| qty).sum()
+    }
+
+    /// Returns the sum of the blinding factors of the issued quantities.
+    pub fn qty_blinding(&self) -> Scalar {
+        self.outputs
+            .iter()
+            .fold(Scalar::zero(), |sum, (_, blinding, _)| sum + blinding)
+    }
+
+    /// Builds the program fragment issuing the outputs.
+    /// The fragment leaves the stack clean and requires the issuer's signature
+    /// over the transaction for each output.
+    pub fn program(&self) -> Result<Program, TokenError> {
+        let mut program = Program::new();
+        self.append_to(&mut program)?;
+        Ok(program)
+    }
+
+    /// Adds the instructions issuing the outputs to a program.
+    pub fn append_to<'a>(&self, program: &'a mut Program) -> Result<&'a mut Program, TokenError> {
+        if self.outputs.is_empty() {
+            return Err(TokenError::EmptyIssuance);
+        }
+        for (qty, blinding, destination) in self.outputs.iter() {
+            self.token
+                .issue_with_factor(program, *qty, *blinding)
+                .push(destination.clone())
+                .output(1);
+        }
+        Ok(program)
+    }
+
+    /// Signs the transaction with the issuer's key and the other keys required by the transaction,
+    /// e.g. the keys of the nonce and of the spent inputs.
+    /// Returns an error if a key for any of the verification keys is missing.
+    pub fn sign_tx(
+        &self,
+        transcript: &mut Transcript,
+        verification_keys: &[VerificationKey],
+        other_keys: &[Scalar],
+    ) -> Result<Signature, TokenError> {
+        let keys = verification_keys
+            .iter()
+            .map(|vk| {
+                Some(&self.issuer_key)
+                    .into_iter()
+                    .chain(other_keys.iter())
+                    .find(|key| VerificationKey::from_secret(key) == *vk)
+                    .cloned()
+                    .ok_or(TokenError::MissingSigningKey)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Signature::sign_aggregated(transcript, &keys))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bulletproofs::BulletproofGens;
+    use zkvm::{Data, Entry, Prover, TxHeader, Verifier};
+
+    #[test]
+    fn issue_to_many() {
+        let issuer_key = Scalar::from(1u64);
+        let nonce_key = Scalar::from(2u64);
+        let alice = Predicate::Key(VerificationKey::from_secret(&Scalar::from(3u64)));
+        let bob = Predicate::Key(VerificationKey::from_secret(&Scalar::from(4u64)));
+
+        let issuance = Issuance::new(issuer_key, b"USD".to_vec())
+            .output(10, alice)
+            .output(5, bob);
+        assert_eq!(issuance.total_qty(), 15);
+        assert_eq!(
+            issuance.flavor(),
+            Token::new(
+                Predicate::Key(VerificationKey::from_secret(&issuer_key)),
+                b"USD".to_vec()
+            )
+            .flavor()
+        );
+        assert_eq!(
+            Issuance::new(issuer_key, b"USD".to_vec())
+                .program()
+                .map(|_| ()),
+            Err(TokenError::EmptyIssuance)
+        );
+
+        let program = Program::build(|p| {
+            p.push(Predicate::Key(VerificationKey::from_secret(&nonce_key)))
+                .push(Data::Opaque([0xffu8; 32].to_vec()))
+                .nonce()
+                .sign_tx();
+            issuance.append_to(p).unwrap()
+        });
+        let header = TxHeader {
+            version: 0u64,
+            mintime: 0u64,
+            maxtime: 0u64,
+            maxcost: u64::max_value(),
+        };
+        let bp_gens = BulletproofGens::new(256, 1);
+
+        // Nonce key is required.
+        let mut missing = None;
+        let _ = Prover::build_tx(program.clone(), header, &bp_gens, |t, vks| {
+            missing = issuance.sign_tx(t, vks, &[]).err();
+            Signature::sign_single(t, issuer_key)
+        });
+        assert_eq!(missing, Some(TokenError::MissingSigningKey));
+
+        let (tx, _, txlog) = Prover::build_tx(program, header, &bp_gens, |t, vks| {
+            issuance.sign_tx(t, vks, &[nonce_key]).unwrap()
+        })
+        .unwrap();
+        let issued = txlog
+            .iter()
+            .filter(|entry| match entry {
+                Entry::Issue(_, _) => true,
+                _ => false,
+            })
+            .count();
+        assert_eq!(issued, 2);
+        assert!(Verifier::verify_tx(tx, &bp_gens).is_ok());
+    }
+}
@@ -1,6 +1,17 @@
 #![deny(missing_docs)]
 //! Token API for ZkVM
 
+#[macro_use]
+extern crate failure;
+
+mod audit;
+mod errors;
+mod issuance;
+mod retirement;
 mod token;
 
+pub use self::audit::{SupplyAudit, SupplyProof};
+pub use self::errors::TokenError;
+pub use self::issuance::Issuance;
+pub use self::retirement::Retirement;
 pub use self::token::Token;
@@ -0,0 +1,116 @@
+use curve25519_dalek::scalar::Scalar;
+use zkvm::{Commitment, Output, Predicate, Program};
+
+use crate::errors::TokenError;
+use crate::token::Token;
+
+/// Builder of the retirement of a quantity of a token from the holder's outputs.
+///
+/// The inputs are merged and split with `cloak` into the retired value
+/// and the change locked by the change predicate. The retired value is
+/// committed with known blinding factors, so it can be accounted for in the supply audits.
+#[derive(Clone, Debug)]
+pub struct Retirement {
+    token: Token,
+    qty: u64,
+    qty_blinding: Scalar,
+    flv_blinding: Scalar,
+    // spent outputs with a single value of the token and their quantities
+    inputs: Vec<(Output, u64)>,
+    change: Option<Predicate>,
+}
+
+impl Retirement {
+    /// Creates a retirement of a given quantity of the token.
+    pub fn new(token: Token, qty: u64) -> Self {
+        let mut rng = rand::thread_rng();
+        Retirement {
+            token,
+            qty,
+            qty_blinding: Scalar::random(&mut rng),
+            flv_blinding: Scalar::random(&mut rng),
+            inputs: Vec::new(),
+            change: None,
+        }
+    }
+
+    /// Adds an output to spend. The output must hold a single value of the token
+    /// with a given quantity, and its commitments must be open to the prover.
+    pub fn input(mut self, output: Output, qty: u64) -> Self {
+        self.inputs.push((output, qty));
+        self
+    }
+
+    /// Sets the predicate for the change output.
+    pub fn change(mut self, predicate: Predicate) -> Self {
+        self.change = Some(predicate);
+        self
+    }
+
+    /// Returns the retired token.
+    pub fn token(&self) -> &Token {
+        &self.token
+    }
+
+    /// Returns the retired quantity.
+    pub fn qty(&self) -> u64 {
+        self.qty
+    }
+
+    /// Returns the blinding factor of the retired quantity.
+    pub fn qty_blinding(&self) -> Scalar {
+        self.qty_blinding
+    }
+
+    /// Returns the blinding factor of the retired flavor.
+    pub fn flv_blinding(&self) -> Scalar {
+        self.flv_blinding
+    }
+
+    /// Builds the program fragment retiring the value.
+    /// The fragment leaves the stack clean and requires the signatures
+    /// of the spent outputs' predicates over the transaction.
+    pub fn program(&self) -> Result<Program, TokenError> {
+        let mut program = Program::new();
+        self.append_to(&mut program)?;
+        Ok(program)
+    }
+
+    /// Adds the instructions retiring the value to a program.
+    pub fn append_to<'a>(&self, program: &'a mut Program) -> Result<&'a mut Program, TokenError> {
+        let total = self
+            .inputs
+            .iter()
+            .try_fold(0u64, |total, (_, qty)| total.checked_add(*qty))
+            .ok_or(TokenError::InsufficientQuantity)?;
+        if self.inputs.is_empty() || total < self.qty {
+            return Err(TokenError::InsufficientQuantity);
+        }
+        let change = total - self.qty;
+        if change > 0 && self.change.is_none() {
+            return Err(TokenError::MissingChange);
+        }
+
+        let flavor = self.token.flavor();
+        for (output, _) in self.inputs.iter() {
+            program.push(output.clone()).input().sign_tx(); // stack: values
+        }
+        program
+            .push(Commitment::blinded_with_factor(self.qty, self.qty_blinding))
+            .push(Commitment::blinded_with_factor(flavor, self.flv_blinding));
+        match &self.change {
+            Some(predicate) if change > 0 => {
+                program
+                    .push(Commitment::blinded(change))
+                    .push(Commitment::blinded(flavor))
+                    .cloak(self.inputs.len(), 2) // stack: retired, change
+                    .push(predicate.clone())
+                    .output(1); // stack: retired
+            }
+            _ => {
+                program.cloak(self.inputs.len(), 1); // stack: retired
+            }
+        }
+        Ok(program.retire())
+    }
+}
@@ -29,8 +29,19 @@ impl Token {
     /// Adds instructions to a program to issues a given quantity
     /// of this Token.
     pub fn issue<'a>(&self, program: &'a mut Program, qty: u64) -> &'a mut Program {
+        self.issue_with_factor(program, qty, Scalar::random(&mut rand::thread_rng()))
+    }
+
+    /// Adds instructions to a program to issue a given quantity
+    /// of this Token, committed with a given blinding factor.
+    pub fn issue_with_factor<'a>(
+        &self,
+        program: &'a mut Program,
+        qty: u64,
+        blinding: Scalar,
+    ) -> &'a mut Program {
         program
-            .push(Commitment::blinded(qty)) // stack: qty
+            .push(Commitment::blinded_with_factor(qty, blinding)) // stack: qty
             .var() // stack: qty-var
             .push(Commitment::unblinded(self.flavor())) // stack: qty-var, flv
             .var() // stack: qty-var, flv-var
@@ -138,6 +149,7 @@ mod tests {
             version: 0u64,
             mintime: 0u64,
             maxtime: 0u64,
+            maxcost: u64::max_value(),
         };
         // TBD: figure out better + more robust signing mechanism
         let gens = PedersenGens::default();
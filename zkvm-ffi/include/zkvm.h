@@ -0,0 +1,60 @@
+/* C interface to the ZkVM core operations (see src/lib.rs). */
+
+#ifndef ZKVM_H
+#define ZKVM_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef enum {
+    ZKVM_OK = 0,
+    ZKVM_NULL_POINTER = 1,
+    ZKVM_INVALID_ENCODING = 2,
+    ZKVM_INVALID_TRANSACTION = 3,
+    ZKVM_INVALID_SIGNATURE = 4,
+    ZKVM_INVALID_OPENING = 5,
+    ZKVM_PANIC = 6,
+} zkvm_status;
+
+typedef struct {
+    uint8_t id[32];
+    uint8_t wtxid[32];
+    uint64_t version;
+    uint64_t mintime;
+    uint64_t maxtime;
+    uint64_t fee;
+    uint64_t size;
+    uint32_t inputs;
+    uint32_t outputs;
+} zkvm_verified_tx;
+
+zkvm_status zkvm_verify_tx(const uint8_t *tx, size_t tx_len, zkvm_verified_tx *out);
+
+zkvm_status zkvm_commit(const uint8_t value[32], const uint8_t blinding[32], uint8_t out[32]);
+
+zkvm_status zkvm_commit_u64(uint64_t qty, const uint8_t blinding[32], uint8_t out[32]);
+
+zkvm_status zkvm_verify_opening(const uint8_t commitment[32],
+                                const uint8_t value[32],
+                                const uint8_t blinding[32]);
+
+zkvm_status zkvm_verification_key(const uint8_t privkey[32], uint8_t out[32]);
+
+zkvm_status zkvm_sign(const uint8_t privkey[32], const uint8_t *msg, size_t msg_len, uint8_t out[64]);
+
+zkvm_status zkvm_verify_signature(const uint8_t pubkey[32],
+                                  const uint8_t *msg,
+                                  size_t msg_len,
+                                  const uint8_t sig[64]);
+
+const char *zkvm_status_message(int status);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* ZKVM_H */
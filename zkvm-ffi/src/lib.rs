@@ -0,0 +1,420 @@
+#![deny(missing_docs)]
+//! C ABI for the core ZkVM operations: verifying transactions,
+//! making Pedersen commitments and Schnorr signatures.
+//!
+//! All functions return a `ZkvmStatus` code and write their results into
+//! the buffers provided by the caller: 32 bytes for scalars, points and IDs,
+//! and 64 bytes for signatures. The functions do not retain the pointers
+//! and do not return memory that the caller has to free.
+//! Panics are caught at the boundary and reported as `ZkvmStatus::Panic`.
+//!
+//! The C declarations are in `include/zkvm.h`.
+
+#[macro_use]
+extern crate lazy_static;
+
+use curve25519_dalek::ristretto::CompressedRistretto;
+use curve25519_dalek::scalar::Scalar;
+use merlin::Transcript;
+use std::os::raw::{c_char, c_int};
+use std::panic;
+use std::slice;
+use std::sync::Mutex;
+use zkvm::{
+    Commitment, Entry, GensCache, Signature, Tx, VMError, VerificationKey, VerifiedTx, Verifier,
+};
+
+lazy_static! {
+    // Generators are grown to the largest transaction verified so far.
+    static ref GENS: Mutex<GensCache> = Mutex::new(GensCache::default());
+}
+
+/// Result of a call.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ZkvmStatus {
+    /// The operation succeeded.
+    Ok = 0,
+    /// A required pointer is null.
+    NullPointer = 1,
+    /// A scalar, point, signature or transaction is not encoded correctly.
+    InvalidEncoding = 2,
+    /// The transaction is invalid.
+    InvalidTransaction = 3,
+    /// The signature is invalid.
+    InvalidSignature = 4,
+    /// The commitment does not open to the value with the blinding factor.
+    InvalidOpening = 5,
+    /// The operation panicked.
+    Panic = 6,
+}
+
+/// Summary of a verified transaction.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ZkvmVerifiedTx {
+    /// Transaction ID.
+    pub id: [u8; 32],
+    /// Witness transaction ID, which also commits to the program, the signature and the proof.
+    pub wtxid: [u8; 32],
+    /// Version of the transaction.
+    pub version: u64,
+    /// Minimum time of the block that can include the transaction (in milliseconds).
+    pub mintime: u64,
+    /// Maximum time of the block that can include the transaction (in milliseconds).
+    pub maxtime: u64,
+    /// Total fee paid by the transaction.
+    pub fee: u64,
+    /// Size of the transaction in bytes.
+    pub size: u64,
+    /// Number of the spent outputs.
+    pub inputs: u32,
+    /// Number of the created outputs.
+    pub outputs: u32,
+}
+
+/// Verifies the serialized transaction of `tx_len` bytes and writes its summary to `out`.
+/// Returns `InvalidEncoding` if the transaction cannot be decoded
+/// and `InvalidTransaction` if it fails verification.
+#[no_mangle]
+pub unsafe extern "C" fn zkvm_verify_tx(
+    tx: *const u8,
+    tx_len: usize,
+    out: *mut ZkvmVerifiedTx,
+) -> ZkvmStatus {
+    guard(|| {
+        let bytes = bytes_arg(tx, tx_len)?;
+        let out = out_arg(out)?;
+        let tx = Tx::from_bytes(bytes).map_err(|_| ZkvmStatus::InvalidEncoding)?;
+        let vtx = verify(tx).map_err(|_| ZkvmStatus::InvalidTransaction)?;
+        let (mut inputs, mut outputs) = (0, 0);
+        for entry in vtx.log.iter() {
+            match entry {
+                Entry::Input(_) => inputs += 1,
+                Entry::Output(_) => outputs += 1,
+                _ => {}
+            }
+        }
+        *out = ZkvmVerifiedTx {
+            id: vtx.id.0,
+            wtxid: vtx.wtxid.0,
+            version: vtx.header.version,
+            mintime: vtx.header.mintime,
+            maxtime: vtx.header.maxtime,
+            fee: vtx.feerate.fee(),
+            size: vtx.feerate.size(),
+            inputs,
+            outputs,
+        };
+        Ok(())
+    })
+}
+
+/// Writes the Pedersen commitment to the scalar `value` with the `blinding` factor to `out`.
+#[no_mangle]
+pub unsafe extern "C" fn zkvm_commit(
+    value: *const [u8; 32],
+    blinding: *const [u8; 32],
+    out: *mut [u8; 32],
+) -> ZkvmStatus {
+    guard(|| {
+        let value = scalar_arg(value)?;
+        let blinding = scalar_arg(blinding)?;
+        *out_arg(out)? = Commitment::blinded_with_factor(value, blinding)
+            .to_point()
+            .to_bytes();
+        Ok(())
+    })
+}
+
+/// Writes the Pedersen commitment to the quantity `qty` with the `blinding` factor to `out`.
+#[no_mangle]
+pub unsafe extern "C" fn zkvm_commit_u64(
+    qty: u64,
+    blinding: *const [u8; 32],
+    out: *mut [u8; 32],
+) -> ZkvmStatus {
+    guard(|| {
+        let blinding = scalar_arg(blinding)?;
+        *out_arg(out)? = Commitment::blinded_with_factor(qty, blinding)
+            .to_point()
+            .to_bytes();
+        Ok(())
+    })
+}
+
+/// Checks that the `commitment` opens to the scalar `value` with the `blinding` factor.
+/// Returns `InvalidOpening` if it does not.
+#[no_mangle]
+pub unsafe extern "C" fn zkvm_verify_opening(
+    commitment: *const [u8; 32],
+    value: *const [u8; 32],
+    blinding: *const [u8; 32],
+) -> ZkvmStatus {
+    guard(|| {
+        let point = CompressedRistretto(*array_arg(commitment)?);
+        let value = scalar_arg(value)?;
+        let blinding = scalar_arg(blinding)?;
+        if Commitment::Closed(point).verify_opening(value, blinding) {
+            Ok(())
+        } else {
+            Err(ZkvmStatus::InvalidOpening)
+        }
+    })
+}
+
+/// Writes the verification key of the secret key `privkey` to `out`.
+#[no_mangle]
+pub unsafe extern "C" fn zkvm_verification_key(
+    privkey: *const [u8; 32],
+    out: *mut [u8; 32],
+) -> ZkvmStatus {
+    guard(|| {
+        let privkey = scalar_arg(privkey)?;
+        *out_arg(out)? = VerificationKey::from_secret(&privkey).0.to_bytes();
+        Ok(())
+    })
+}
+
+/// Signs the message of `msg_len` bytes with the secret key `privkey`
+/// and writes the signature to `out`.
+#[no_mangle]
+pub unsafe extern "C" fn zkvm_sign(
+    privkey: *const [u8; 32],
+    msg: *const u8,
+    msg_len: usize,
+    out: *mut [u8; 64],
+) -> ZkvmStatus {
+    guard(|| {
+        let privkey = scalar_arg(privkey)?;
+        let msg = bytes_arg(msg, msg_len)?;
+        let out = out_arg(out)?;
+        *out = Signature::sign_single(&mut message_transcript(msg), privkey).to_bytes();
+        Ok(())
+    })
+}
+
+/// Verifies the signature `sig` of the message of `msg_len` bytes with the verification key `pubkey`.
+/// Returns `InvalidSignature` if the signature is invalid.
+#[no_mangle]
+pub unsafe extern "C" fn zkvm_verify_signature(
+    pubkey: *const [u8; 32],
+    msg: *const u8,
+    msg_len: usize,
+    sig: *const [u8; 64],
+) -> ZkvmStatus {
+    guard(|| {
+        let pubkey = VerificationKey::from(CompressedRistretto(*array_arg(pubkey)?));
+        let msg = bytes_arg(msg, msg_len)?;
+        let sig = sig.as_ref().ok_or(ZkvmStatus::NullPointer)?;
+        let sig = Signature::from_bytes(*sig).map_err(|_| ZkvmStatus::InvalidEncoding)?;
+        sig.verify_single(&mut message_transcript(msg), pubkey)
+            .verify()
+            .map_err(|_| ZkvmStatus::InvalidSignature)
+    })
+}
+
+/// Returns a static NUL-terminated description of the status code.
+#[no_mangle]
+pub extern "C" fn zkvm_status_message(status: c_int) -> *const c_char {
+    let message: &'static [u8] = match status {
+        0 => b"Ok\0",
+        1 => b"Null pointer\0",
+        2 => b"Invalid encoding\0",
+        3 => b"Invalid transaction\0",
+        4 => b"Invalid signature\0",
+        5 => b"Invalid commitment opening\0",
+        6 => b"Panic\0",
+        _ => b"Unknown status\0",
+    };
+    message.as_ptr() as *const c_char
+}
+
+/// Transcript for the messages signed with `zkvm_sign`.
+fn message_transcript(msg: &[u8]) -> Transcript {
+    let mut t = Transcript::new(b"ZkVM.ffi.message");
+    t.commit_bytes(b"message", msg);
+    t
+}
+
+/// Verifies the transaction, growing the shared generators to the required capacity.
+fn verify(tx: Tx) -> Result<VerifiedTx, VMError> {
+    let mut gens = GENS.lock().unwrap_or_else(|e| e.into_inner());
+    let result = Verifier::verify_tx(tx.clone(), gens.bp_gens());
+    let required = match result {
+        Err(ref e) => match e.root() {
+            VMError::InsufficientGenerators { required, .. } => Some(*required),
+            _ => None,
+        },
+        Ok(_) => None,
+    };
+    match required {
+        Some(capacity) => Verifier::verify_tx(tx, gens.reserve(capacity)),
+        None => result,
+    }
+}
+
+/// Runs the body of a function, converting its result and panics to the status code.
+fn guard<F>(f: F) -> ZkvmStatus
+where
+    F: FnOnce() -> Result<(), ZkvmStatus>,
+{
+    match panic::catch_unwind(panic::AssertUnwindSafe(f)) {
+        Ok(Ok(())) => ZkvmStatus::Ok,
+        Ok(Err(status)) => status,
+        Err(_) => ZkvmStatus::Panic,
+    }
+}
+
+unsafe fn bytes_arg<'a>(ptr: *const u8, len: usize) -> Result<&'a [u8], ZkvmStatus> {
+    if len == 0 {
+        return Ok(&[]);
+    }
+    if ptr.is_null() {
+        return Err(ZkvmStatus::NullPointer);
+    }
+    Ok(slice::from_raw_parts(ptr, len))
+}
+
+unsafe fn array_arg<'a>(ptr: *const [u8; 32]) -> Result<&'a [u8; 32], ZkvmStatus> {
+    ptr.as_ref().ok_or(ZkvmStatus::NullPointer)
+}
+
+unsafe fn scalar_arg(ptr: *const [u8; 32]) -> Result<Scalar, ZkvmStatus> {
+    Scalar::from_canonical_bytes(*array_arg(ptr)?).ok_or(ZkvmStatus::InvalidEncoding)
+}
+
+unsafe fn out_arg<'a, T>(ptr: *mut T) -> Result<&'a mut T, ZkvmStatus> {
+    ptr.as_mut().ok_or(ZkvmStatus::NullPointer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bulletproofs::BulletproofGens;
+    use std::ffi::CStr;
+    use std::ptr;
+    use zkvm::{Data, Predicate, Program, Prover, TxHeader};
+
+    #[test]
+    fn verify_tx() {
+        let privkey = Scalar::from(7u64);
+        let pred = Predicate::Key(VerificationKey::from_secret(&privkey));
+        let program = Program::build(|p| {
+            p.push(pred)
+                .push(Data::Opaque(vec![0u8; 32]))
+                .nonce()
+                .sign_tx()
+        });
+        let header = TxHeader {
+            version: 1,
+            mintime: 0,
+            maxtime: 1000,
+            maxcost: u64::max_value(),
+        };
+        let (tx, id, _) =
+            Prover::build_tx(program, header, &BulletproofGens::new(64, 1), |t, _| {
+                Signature::sign_single(t, privkey)
+            })
+            .unwrap();
+        let bytes = tx.to_bytes();
+
+        let mut out = ZkvmVerifiedTx {
+            id: [0u8; 32],
+            wtxid: [0u8; 32],
+            version: 0,
+            mintime: 0,
+            maxtime: 0,
+            fee: 0,
+            size: 0,
+            inputs: 0,
+            outputs: 0,
+        };
+        unsafe {
+            assert_eq!(
+                zkvm_verify_tx(bytes.as_ptr(), bytes.len(), &mut out),
+                ZkvmStatus::Ok
+            );
+            assert_eq!(out.id, id.0);
+            assert_ne!(out.wtxid, id.0);
+            assert_eq!((out.version, out.maxtime), (1, 1000));
+            assert_eq!(out.size, bytes.len() as u64);
+
+            assert_eq!(
+                zkvm_verify_tx(bytes.as_ptr(), bytes.len() - 1, &mut out),
+                ZkvmStatus::InvalidEncoding
+            );
+            let mut tampered = bytes.clone();
+            let last = tampered.len() - 1;
+            tampered[last] ^= 1;
+            assert_ne!(
+                zkvm_verify_tx(tampered.as_ptr(), tampered.len(), &mut out),
+                ZkvmStatus::Ok
+            );
+            assert_eq!(
+                zkvm_verify_tx(bytes.as_ptr(), bytes.len(), ptr::null_mut()),
+                ZkvmStatus::NullPointer
+            );
+        }
+    }
+
+    #[test]
+    fn commitments() {
+        let value = Scalar::from(10u64).to_bytes();
+        let blinding = Scalar::from(3u64).to_bytes();
+        let mut commitment = [0u8; 32];
+        let mut qty_commitment = [0u8; 32];
+        unsafe {
+            assert_eq!(
+                zkvm_commit(&value, &blinding, &mut commitment),
+                ZkvmStatus::Ok
+            );
+            assert_eq!(
+                zkvm_commit_u64(10, &blinding, &mut qty_commitment),
+                ZkvmStatus::Ok
+            );
+            assert_eq!(commitment, qty_commitment);
+            assert_eq!(
+                zkvm_verify_opening(&commitment, &value, &blinding),
+                ZkvmStatus::Ok
+            );
+            assert_eq!(
+                zkvm_verify_opening(&commitment, &blinding, &value),
+                ZkvmStatus::InvalidOpening
+            );
+            assert_eq!(
+                zkvm_commit(&[0xff; 32], &blinding, &mut commitment),
+                ZkvmStatus::InvalidEncoding
+            );
+        }
+    }
+
+    #[test]
+    fn signatures() {
+        let privkey = Scalar::from(5u64).to_bytes();
+        let msg = b"hello";
+        let mut pubkey = [0u8; 32];
+        let mut sig = [0u8; 64];
+        unsafe {
+            assert_eq!(zkvm_verification_key(&privkey, &mut pubkey), ZkvmStatus::Ok);
+            assert_eq!(
+                zkvm_sign(&privkey, msg.as_ptr(), msg.len(), &mut sig),
+                ZkvmStatus::Ok
+            );
+            assert_eq!(
+                zkvm_verify_signature(&pubkey, msg.as_ptr(), msg.len(), &sig),
+                ZkvmStatus::Ok
+            );
+            assert_eq!(
+                zkvm_verify_signature(&pubkey, msg.as_ptr(), msg.len() - 1, &sig),
+                ZkvmStatus::InvalidSignature
+            );
+            assert_eq!(
+                zkvm_verify_signature(&pubkey, ptr::null(), 0, &sig),
+                ZkvmStatus::InvalidSignature
+            );
+        }
+        let message = unsafe { CStr::from_ptr(zkvm_status_message(4)) };
+        assert_eq!(message.to_str(), Ok("Invalid signature"));
+    }
+}
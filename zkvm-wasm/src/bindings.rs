@@ -0,0 +1,74 @@
+//! Functions exported to JavaScript.
+//!
+//! The handles are passed as plain JavaScript objects with the JSON structure
+//! of the corresponding Rust types, and the errors are thrown as strings.
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use wasm_bindgen::prelude::*;
+
+use crate::errors::WasmError;
+use crate::handles::{CommitmentHandle, ReceiverHandle};
+use crate::wallet;
+
+/// Creates a random xprv and returns it as a hex string.
+#[wasm_bindgen(js_name = generateXprv)]
+pub fn generate_xprv() -> String {
+    wallet::generate_xprv()
+}
+
+/// Returns the xpub of the hex-encoded xprv as a hex string.
+#[wasm_bindgen(js_name = toXpub)]
+pub fn to_xpub(xprv: &str) -> Result<String, JsValue> {
+    Ok(wallet::to_xpub(xprv)?)
+}
+
+/// Creates a receiver from a `ReceiverRequest` and returns a `ReceiverWitnessHandle`.
+#[wasm_bindgen(js_name = generateReceiver)]
+pub fn generate_receiver(request: &JsValue) -> Result<JsValue, JsValue> {
+    with_json(request, |request| wallet::generate_receiver(&request))
+}
+
+/// Returns the quantity and flavor `CommitmentHandle`s expected in the payment
+/// to a `ReceiverHandle`.
+#[wasm_bindgen(js_name = receiverCommitments)]
+pub fn receiver_commitments(receiver: &JsValue) -> Result<JsValue, JsValue> {
+    with_json(receiver, |receiver: ReceiverHandle| receiver.commitments())
+}
+
+/// Checks the opening of a `CommitmentHandle`.
+#[wasm_bindgen(js_name = verifyCommitment)]
+pub fn verify_commitment(commitment: &JsValue) -> Result<bool, JsValue> {
+    let commitment: CommitmentHandle = commitment.into_serde().map_err(WasmError::from)?;
+    Ok(commitment.verify()?)
+}
+
+/// Builds, proves and signs the transaction for a `TxRequest` and returns a `SignedTxHandle`.
+#[wasm_bindgen(js_name = buildTx)]
+pub fn build_tx(request: &JsValue) -> Result<JsValue, JsValue> {
+    with_json(request, |request| wallet::build_tx(&request))
+}
+
+/// Verifies the hex-encoded transaction and returns a `VerifiedTxHandle`.
+#[wasm_bindgen(js_name = verifyTx)]
+pub fn verify_tx(tx: &JsValue) -> Result<JsValue, JsValue> {
+    with_json(tx, |tx| wallet::verify_tx(&tx))
+}
+
+/// Returns the `UtxoHandle`s paying the receivers of a `ReceiveRequest`.
+#[wasm_bindgen]
+pub fn receive(request: &JsValue) -> Result<JsValue, JsValue> {
+    with_json(request, |request| wallet::receive(&request))
+}
+
+/// Decodes the argument, performs the operation and encodes its result.
+fn with_json<T, U, F>(arg: &JsValue, f: F) -> Result<JsValue, JsValue>
+where
+    T: DeserializeOwned,
+    U: Serialize,
+    F: FnOnce(T) -> Result<U, WasmError>,
+{
+    let arg = arg.into_serde().map_err(WasmError::from)?;
+    let result = f(arg)?;
+    Ok(JsValue::from_serde(&result).map_err(WasmError::from)?)
+}
@@ -0,0 +1,50 @@
+//! Errors returned to JavaScript by the bindings.
+
+use accounts::AccountError;
+use wasm_bindgen::JsValue;
+use zkvm::VMError;
+
+/// Represents an error in the bindings.
+#[derive(Fail, Clone, Debug, Eq, PartialEq)]
+pub enum WasmError {
+    /// This error occurs when a key or a scalar is not a hex string of the expected length.
+    #[fail(display = "Invalid hex encoding of {}.", _0)]
+    InvalidHex(&'static str),
+
+    /// This error occurs when a JSON handle does not match the expected structure.
+    #[fail(display = "Invalid JSON: {}", _0)]
+    InvalidJson(String),
+
+    /// This error occurs when the VM fails to build or verify the transaction.
+    #[fail(display = "Transaction is invalid: {}", _0)]
+    Transaction(VMError),
+
+    /// This error occurs when the transaction cannot be built from the account's utxos.
+    #[fail(display = "Transaction cannot be built: {}", _0)]
+    Account(AccountError),
+}
+
+impl From<VMError> for WasmError {
+    fn from(e: VMError) -> Self {
+        WasmError::Transaction(e)
+    }
+}
+
+impl From<AccountError> for WasmError {
+    fn from(e: AccountError) -> Self {
+        WasmError::Account(e)
+    }
+}
+
+impl From<serde_json::Error> for WasmError {
+    fn from(e: serde_json::Error) -> Self {
+        WasmError::InvalidJson(e.to_string())
+    }
+}
+
+impl From<WasmError> for JsValue {
+    /// Converts the error to a JavaScript string, so it can be thrown as an exception.
+    fn from(e: WasmError) -> Self {
+        JsValue::from_str(&e.to_string())
+    }
+}
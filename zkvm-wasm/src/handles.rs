@@ -0,0 +1,212 @@
+//! JSON-serializable handles exchanged with JavaScript.
+//!
+//! Keys, scalars and points are encoded as hex strings, while quantities,
+//! sequence numbers and times are JSON numbers. Outputs and transactions
+//! use the `serde` encoding of the `zkvm` crate.
+
+use accounts::{ClearValue, ReceivedUtxo, Receiver, ReceiverWitness};
+use curve25519_dalek::ristretto::CompressedRistretto;
+use curve25519_dalek::scalar::Scalar;
+use keytree::{Xprv, Xpub};
+use serde::{Deserialize, Serialize};
+use zkvm::{Commitment, Output};
+
+use crate::errors::WasmError;
+
+/// Pedersen commitment with its opening.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CommitmentHandle {
+    /// Compressed commitment point.
+    pub point: String,
+    /// Committed scalar.
+    pub value: String,
+    /// Blinding factor of the commitment.
+    pub blinding: String,
+}
+
+/// Value with a cleartext quantity and flavor.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ValueHandle {
+    /// Quantity of the value.
+    pub qty: u64,
+    /// Flavor of the value.
+    pub flv: String,
+}
+
+/// Receiver given to the payer (see `accounts::Receiver`).
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ReceiverHandle {
+    /// One-time key that must protect the payment output.
+    pub predicate: String,
+    /// Requested value.
+    pub value: ValueHandle,
+    /// Blinding factor for the quantity commitment.
+    pub qty_blinding: String,
+    /// Blinding factor for the flavor commitment.
+    pub flv_blinding: String,
+    /// Timestamp after which the receiver should not be paid (in milliseconds since the Unix epoch).
+    pub expiration: u64,
+}
+
+/// Receiver with the sequence number of its key, kept by the recipient.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ReceiverWitnessHandle {
+    /// Sequence number of the receiver's key within the account.
+    pub sequence: u64,
+    /// The receiver given to the payer.
+    pub receiver: ReceiverHandle,
+}
+
+/// Output paid to one of the account's receivers.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct UtxoHandle {
+    /// The received output.
+    pub output: Output,
+    /// The receiver that was paid.
+    pub receiver_witness: ReceiverWitnessHandle,
+}
+
+impl CommitmentHandle {
+    /// Creates a commitment to the value with the blinding factor.
+    pub fn new(value: Scalar, blinding: Scalar) -> Self {
+        CommitmentHandle {
+            point: hex::encode(
+                Commitment::blinded_with_factor(value, blinding)
+                    .to_point()
+                    .as_bytes(),
+            ),
+            value: hex::encode(value.as_bytes()),
+            blinding: hex::encode(blinding.as_bytes()),
+        }
+    }
+
+    /// Returns true if the point is a commitment to the value with the blinding factor.
+    pub fn verify(&self) -> Result<bool, WasmError> {
+        let point = point_from_hex(&self.point, "commitment")?;
+        let value = scalar_from_hex(&self.value, "committed value")?;
+        let blinding = scalar_from_hex(&self.blinding, "blinding factor")?;
+        Ok(Commitment::Closed(point).verify_opening(value, blinding))
+    }
+}
+
+impl ValueHandle {
+    /// Decodes the value.
+    pub fn to_clear_value(&self) -> Result<ClearValue, WasmError> {
+        Ok(ClearValue {
+            qty: self.qty,
+            flv: scalar_from_hex(&self.flv, "flavor")?,
+        })
+    }
+}
+
+impl From<&ClearValue> for ValueHandle {
+    fn from(value: &ClearValue) -> Self {
+        ValueHandle {
+            qty: value.qty,
+            flv: hex::encode(value.flv.as_bytes()),
+        }
+    }
+}
+
+impl ReceiverHandle {
+    /// Decodes the receiver.
+    pub fn to_receiver(&self) -> Result<Receiver, WasmError> {
+        Ok(Receiver {
+            opaque_predicate: point_from_hex(&self.predicate, "predicate")?,
+            value: self.value.to_clear_value()?,
+            qty_blinding: scalar_from_hex(&self.qty_blinding, "blinding factor")?,
+            flv_blinding: scalar_from_hex(&self.flv_blinding, "blinding factor")?,
+            expiration: self.expiration,
+        })
+    }
+
+    /// Returns the commitments to the quantity and the flavor
+    /// expected in the payment output.
+    pub fn commitments(&self) -> Result<(CommitmentHandle, CommitmentHandle), WasmError> {
+        let receiver = self.to_receiver()?;
+        Ok((
+            CommitmentHandle::new(Scalar::from(receiver.value.qty), receiver.qty_blinding),
+            CommitmentHandle::new(receiver.value.flv, receiver.flv_blinding),
+        ))
+    }
+}
+
+impl From<&Receiver> for ReceiverHandle {
+    fn from(receiver: &Receiver) -> Self {
+        ReceiverHandle {
+            predicate: hex::encode(receiver.opaque_predicate.as_bytes()),
+            value: ValueHandle::from(&receiver.value),
+            qty_blinding: hex::encode(receiver.qty_blinding.as_bytes()),
+            flv_blinding: hex::encode(receiver.flv_blinding.as_bytes()),
+            expiration: receiver.expiration,
+        }
+    }
+}
+
+impl ReceiverWitnessHandle {
+    /// Decodes the receiver witness.
+    pub fn to_receiver_witness(&self) -> Result<ReceiverWitness, WasmError> {
+        Ok(ReceiverWitness {
+            sequence: self.sequence,
+            receiver: self.receiver.to_receiver()?,
+        })
+    }
+}
+
+impl From<&ReceiverWitness> for ReceiverWitnessHandle {
+    fn from(witness: &ReceiverWitness) -> Self {
+        ReceiverWitnessHandle {
+            sequence: witness.sequence,
+            receiver: ReceiverHandle::from(&witness.receiver),
+        }
+    }
+}
+
+impl UtxoHandle {
+    /// Decodes the utxo.
+    pub fn to_utxo(&self) -> Result<ReceivedUtxo, WasmError> {
+        Ok(ReceivedUtxo {
+            output: self.output.clone(),
+            receiver_witness: self.receiver_witness.to_receiver_witness()?,
+        })
+    }
+}
+
+impl From<&ReceivedUtxo> for UtxoHandle {
+    fn from(utxo: &ReceivedUtxo) -> Self {
+        UtxoHandle {
+            output: utxo.output.clone(),
+            receiver_witness: ReceiverWitnessHandle::from(&utxo.receiver_witness),
+        }
+    }
+}
+
+/// Decodes the xprv from a hex string.
+pub(crate) fn xprv_from_hex(s: &str) -> Result<Xprv, WasmError> {
+    let bytes = hex::decode(s).map_err(|_| WasmError::InvalidHex("xprv"))?;
+    Xprv::from_bytes(&bytes).ok_or(WasmError::InvalidHex("xprv"))
+}
+
+/// Decodes the xpub from a hex string.
+pub(crate) fn xpub_from_hex(s: &str) -> Result<Xpub, WasmError> {
+    let bytes = hex::decode(s).map_err(|_| WasmError::InvalidHex("xpub"))?;
+    Xpub::from_bytes(&bytes).ok_or(WasmError::InvalidHex("xpub"))
+}
+
+fn scalar_from_hex(s: &str, what: &'static str) -> Result<Scalar, WasmError> {
+    Scalar::from_canonical_bytes(bytes32_from_hex(s, what)?).ok_or(WasmError::InvalidHex(what))
+}
+
+fn point_from_hex(s: &str, what: &'static str) -> Result<CompressedRistretto, WasmError> {
+    Ok(CompressedRistretto(bytes32_from_hex(s, what)?))
+}
+
+fn bytes32_from_hex(s: &str, what: &'static str) -> Result<[u8; 32], WasmError> {
+    let bytes = hex::decode(s).map_err(|_| WasmError::InvalidHex(what))?;
+    if bytes.len() != 32 {
+        return Err(WasmError::InvalidHex(what));
+    }
+    let mut buf = [0u8; 32];
+    buf.copy_from_slice(&bytes);
+    Ok(buf)
+}
@@ -0,0 +1,23 @@
+#![deny(missing_docs)]
+//! WebAssembly bindings for building, signing, proving and verifying ZkVM transactions.
+//!
+//! The wallet operations exchange JSON-serializable handles for keys, commitments,
+//! receivers and utxos, so a JavaScript client can keep the account's state
+//! and pass it to the stateless functions exported with `wasm-bindgen`.
+
+#[macro_use]
+extern crate failure;
+
+mod bindings;
+mod errors;
+mod handles;
+mod wallet;
+
+pub use self::errors::WasmError;
+pub use self::handles::{
+    CommitmentHandle, ReceiverHandle, ReceiverWitnessHandle, UtxoHandle, ValueHandle,
+};
+pub use self::wallet::{
+    build_tx, generate_receiver, generate_xprv, receive, to_xpub, verify_tx, ReceiveRequest,
+    ReceiverRequest, SignedTxHandle, TxRequest, VerifiedTxHandle,
+};
@@ -0,0 +1,298 @@
+//! Wallet operations on the handles: receivers, building and signing
+//! transactions from the account's utxos, and verifying transactions.
+//!
+//! The operations are stateless: the client keeps the account's sequence number,
+//! its pending receivers and utxos, and passes them with each request.
+
+use accounts::{Account, TxBuilder};
+use keytree::Xprv;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use zkvm::{
+    ContractID, Entry, GensCache, Output, Prover, Signature, Tx, TxHeader, TxID, VMError, Verifier,
+};
+
+use crate::errors::WasmError;
+use crate::handles::{
+    xprv_from_hex, xpub_from_hex, ReceiverHandle, ReceiverWitnessHandle, UtxoHandle, ValueHandle,
+};
+
+thread_local! {
+    // Generators are grown to the largest transaction built or verified so far.
+    static GENS: RefCell<GensCache> = RefCell::new(GensCache::default());
+}
+
+/// Request to create a receiver for the account's key with the given sequence number.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ReceiverRequest {
+    /// Root xpub of the account.
+    pub xpub: String,
+    /// Sequence number of the receiver's key.
+    pub sequence: u64,
+    /// Requested value.
+    pub value: ValueHandle,
+    /// Timestamp after which the receiver should not be paid (in milliseconds since the Unix epoch).
+    pub expiration: u64,
+}
+
+/// Request to build and sign a transaction paying the receivers from the account's utxos.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TxRequest {
+    /// Header of the transaction.
+    pub header: TxHeader,
+    /// Root xprv of the account.
+    pub xprv: String,
+    /// Sequence number of the account's next receiver, used for the change.
+    pub sequence: u64,
+    /// Utxos that can be spent by the transaction.
+    pub utxos: Vec<UtxoHandle>,
+    /// Receivers to pay.
+    pub payments: Vec<ReceiverHandle>,
+    /// Fee paid in the asset with the zero flavor.
+    pub fee: u64,
+}
+
+/// Signed transaction with the receivers of the change.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SignedTxHandle {
+    /// Transaction with the signature and the proof.
+    pub tx: Tx,
+    /// ID of the transaction.
+    pub id: TxID,
+    /// Receivers of the change, pending until the transaction is confirmed.
+    pub change: Vec<ReceiverWitnessHandle>,
+    /// Sequence number of the account's next receiver.
+    pub sequence: u64,
+}
+
+/// Effects of a verified transaction.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct VerifiedTxHandle {
+    /// ID of the transaction.
+    pub id: TxID,
+    /// Fee paid by the transaction.
+    pub fee: u64,
+    /// IDs of the spent outputs.
+    pub inputs: Vec<ContractID>,
+    /// Created outputs.
+    pub outputs: Vec<Output>,
+}
+
+/// Request to find the outputs paying the pending receivers.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ReceiveRequest {
+    /// Outputs of a verified transaction.
+    pub outputs: Vec<Output>,
+    /// Receivers that are waiting for a payment.
+    pub receivers: Vec<ReceiverWitnessHandle>,
+}
+
+/// Creates a random xprv and returns it as a hex string.
+pub fn generate_xprv() -> String {
+    hex::encode(&Xprv::random(rand::thread_rng()).to_bytes()[..])
+}
+
+/// Returns the xpub of the hex-encoded xprv as a hex string.
+pub fn to_xpub(xprv: &str) -> Result<String, WasmError> {
+    let xpub = xprv_from_hex(xprv)?.to_xpub();
+    Ok(hex::encode(&xpub.to_bytes()[..]))
+}
+
+/// Creates a receiver with fresh blinding factors.
+pub fn generate_receiver(request: &ReceiverRequest) -> Result<ReceiverWitnessHandle, WasmError> {
+    let xpub = xpub_from_hex(&request.xpub)?;
+    let mut account = Account::with_sequence(xpub, request.sequence);
+    let receiver = account.generate_receiver(
+        request.value.to_clear_value()?,
+        request.expiration,
+        &mut rand::thread_rng(),
+    );
+    Ok(ReceiverWitnessHandle {
+        sequence: request.sequence,
+        receiver: ReceiverHandle::from(&receiver),
+    })
+}
+
+/// Builds, proves and signs the transaction paying the receivers (see `accounts::TxBuilder`).
+pub fn build_tx(request: &TxRequest) -> Result<SignedTxHandle, WasmError> {
+    let xprv = xprv_from_hex(&request.xprv)?;
+    let mut account = Account::with_sequence(xprv.to_xpub(), request.sequence);
+    let utxos = request
+        .utxos
+        .iter()
+        .map(UtxoHandle::to_utxo)
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut builder = TxBuilder::new(request.header)
+        .spendable(utxos)
+        .fee(request.fee);
+    for payment in request.payments.iter() {
+        builder = builder.pay(payment.to_receiver()?);
+    }
+    let unsigned_tx = builder.build(&mut account, &mut rand::thread_rng())?;
+
+    let keys = unsigned_tx.signing_keys(&xprv);
+    let change = unsigned_tx
+        .change
+        .iter()
+        .map(ReceiverWitnessHandle::from)
+        .collect();
+    let (tx, id, _) = GENS.with(|gens| {
+        Prover::build_tx_padded(
+            unsigned_tx.program,
+            unsigned_tx.header,
+            &mut gens.borrow_mut(),
+            |t, _| Signature::sign_aggregated(t, &keys),
+        )
+    })?;
+    Ok(SignedTxHandle {
+        tx,
+        id,
+        change,
+        sequence: account.sequence(),
+    })
+}
+
+/// Verifies the transaction and returns its effects.
+pub fn verify_tx(tx: &Tx) -> Result<VerifiedTxHandle, WasmError> {
+    let vtx = GENS.with(|gens| {
+        let mut gens = gens.borrow_mut();
+        let result = Verifier::verify_tx(tx.clone(), gens.bp_gens());
+        // Retry with the generators of the capacity required by the transaction.
+        let required = match result {
+            Err(ref e) => match e.root() {
+                VMError::InsufficientGenerators { required, .. } => Some(*required),
+                _ => None,
+            },
+            Ok(_) => None,
+        };
+        match required {
+            Some(capacity) => Verifier::verify_tx(tx.clone(), gens.reserve(capacity)),
+            None => result,
+        }
+    })?;
+
+    let mut inputs = Vec::new();
+    let mut outputs = Vec::new();
+    for entry in vtx.log.into_iter() {
+        match entry {
+            Entry::Input(id) => inputs.push(id),
+            Entry::Output(output) => outputs.push(output),
+            _ => {}
+        }
+    }
+    Ok(VerifiedTxHandle {
+        id: vtx.id,
+        fee: vtx.feerate.fee(),
+        inputs,
+        outputs,
+    })
+}
+
+/// Returns the outputs that pay the pending receivers, as the account's new utxos.
+pub fn receive(request: &ReceiveRequest) -> Result<Vec<UtxoHandle>, WasmError> {
+    let mut utxos = Vec::new();
+    for witness in request.receivers.iter() {
+        let receiver = witness.receiver.to_receiver()?;
+        if let Some(output) = request
+            .outputs
+            .iter()
+            .find(|output| receiver.matches(output.contract()))
+        {
+            utxos.push(UtxoHandle {
+                output: output.clone(),
+                receiver_witness: witness.clone(),
+            });
+        }
+    }
+    Ok(utxos)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handles::CommitmentHandle;
+    use curve25519_dalek::scalar::Scalar;
+    use zkvm::{Anchor, Contract, PortableItem, Predicate};
+
+    fn request_receiver(xpub: &str, sequence: u64, qty: u64) -> ReceiverWitnessHandle {
+        let request = ReceiverRequest {
+            xpub: xpub.to_string(),
+            sequence,
+            value: ValueHandle {
+                qty,
+                flv: hex::encode(Scalar::zero().as_bytes()),
+            },
+            expiration: u64::max_value(),
+        };
+        generate_receiver(&request).unwrap()
+    }
+
+    #[test]
+    fn pay_and_receive() {
+        let alice_xprv = generate_xprv();
+        let alice_xpub = to_xpub(&alice_xprv).unwrap();
+        let bob_xpub = to_xpub(&generate_xprv()).unwrap();
+
+        // Fund Alice with an output paying her receiver.
+        let funding = request_receiver(&alice_xpub, 0, 10);
+        let receiver = funding.receiver.to_receiver().unwrap();
+        let output = Output::new(Contract {
+            anchor: Anchor::nonce([0; 32], &Predicate::Opaque(Default::default()), 0),
+            payload: vec![PortableItem::Value(receiver.value())],
+            predicate: receiver.predicate(),
+        });
+        let utxos = receive(&ReceiveRequest {
+            outputs: vec![output],
+            receivers: vec![funding],
+        })
+        .unwrap();
+        assert_eq!(utxos.len(), 1);
+
+        let payment = request_receiver(&bob_xpub, 0, 7);
+        let (qty, _) = payment.receiver.commitments().unwrap();
+        assert!(qty.verify().unwrap());
+
+        // Handles survive the JSON round trip.
+        let request = TxRequest {
+            header: TxHeader {
+                version: 0,
+                mintime: 0,
+                maxtime: 1000,
+                maxcost: u64::max_value(),
+            },
+            xprv: alice_xprv,
+            sequence: 1,
+            utxos,
+            payments: vec![payment.receiver.clone()],
+            fee: 1,
+        };
+        let request: TxRequest =
+            serde_json::from_str(&serde_json::to_string(&request).unwrap()).unwrap();
+        let signed = build_tx(&request).unwrap();
+        assert_eq!(signed.change.len(), 1);
+        assert_eq!(signed.sequence, 2);
+
+        let tx: Tx = serde_json::from_str(&serde_json::to_string(&signed.tx).unwrap()).unwrap();
+        let verified = verify_tx(&tx).unwrap();
+        assert_eq!(verified.id, signed.id);
+        assert_eq!(verified.fee, 1);
+        assert_eq!(verified.inputs.len(), 1);
+
+        let received = receive(&ReceiveRequest {
+            outputs: verified.outputs.clone(),
+            receivers: vec![payment],
+        })
+        .unwrap();
+        assert_eq!(received.len(), 1);
+        let change = receive(&ReceiveRequest {
+            outputs: verified.outputs,
+            receivers: signed.change,
+        })
+        .unwrap();
+        assert_eq!(change[0].receiver_witness.receiver.value.qty, 2);
+
+        let mut wrong = CommitmentHandle::new(Scalar::from(7u64), Scalar::one());
+        wrong.value = hex::encode(Scalar::from(8u64).as_bytes());
+        assert_eq!(wrong.verify(), Ok(false));
+    }
+}
@@ -0,0 +1,12 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+
+use zkvm::Program;
+
+// A program that decodes successfully must have a single encoding.
+fuzz_target!(|data: &[u8]| {
+    if let Ok(instructions) = Program::disassemble(data) {
+        assert_eq!(Program::from_instructions(instructions).to_bytes(), data);
+    }
+});
@@ -0,0 +1,16 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+
+use zkvm::{Tx, TxRef};
+
+// A transaction that decodes successfully must have a single encoding.
+fuzz_target!(|data: &[u8]| {
+    if let Ok(tx) = Tx::from_bytes(data) {
+        assert_eq!(tx.to_bytes(), data);
+        assert_eq!(tx.serialized_size(), data.len());
+    }
+    if let Ok(tx) = TxRef::from_bytes(data) {
+        assert_eq!(tx.to_tx().to_bytes(), data);
+    }
+});
@@ -0,0 +1,303 @@
+//! Textual assembly format for ZkVM programs.
+//!
+//! Instructions are written in the notation of the specification, separated by whitespace:
+//! `push:3:0x010203 input signtx output:1`. The length of the pushed data may be omitted
+//! (`push:0x010203`), and a bare hex literal `0x010203` is a shorthand for `push`.
+//! Everything after `#` until the end of the line is a comment.
+//!
+//! A label `name:` starts a named block of instructions that is not executed in place,
+//! but can be pushed as a data string with `@name` (e.g. a program for `call` or `delegate`).
+//! Instructions before the first label form the main program.
+
+use spacesuit::BitRange;
+use std::collections::HashMap;
+
+use crate::errors::VMError;
+use crate::ops::Instruction;
+use crate::program::Program;
+use crate::types::Data;
+
+/// A token with the line number for error reporting.
+struct Token<'a> {
+    line: usize,
+    text: &'a str,
+}
+
+/// Assembles the program from the text.
+pub(crate) fn assemble(src: &str) -> Result<Program, VMError> {
+    let mut main = Vec::new();
+    let mut blocks: HashMap<&str, Vec<Token>> = HashMap::new();
+    let mut current: Option<&str> = None;
+
+    for (i, line) in src.lines().enumerate() {
+        let code = match line.find('#') {
+            Some(pos) => &line[..pos],
+            None => line,
+        };
+        for text in code.split_whitespace() {
+            let token = Token { line: i + 1, text };
+            if text.ends_with(':') {
+                let name = &text[..text.len() - 1];
+                if !is_label(name) || blocks.contains_key(name) {
+                    return Err(token.error());
+                }
+                blocks.insert(name, Vec::new());
+                current = Some(name);
+            } else {
+                match current {
+                    Some(name) => blocks.get_mut(name).unwrap().push(token),
+                    None => main.push(token),
+                }
+            }
+        }
+    }
+
+    let mut assembler = Assembler {
+        blocks: &blocks,
+        bytecode: HashMap::new(),
+        resolving: Vec::new(),
+    };
+    let instructions = main
+        .iter()
+        .map(|t| assembler.instruction(t))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Program::from_instructions(instructions))
+}
+
+/// Resolves the references to the labeled blocks.
+struct Assembler<'s, 'a> {
+    blocks: &'s HashMap<&'a str, Vec<Token<'a>>>,
+    // Bytecode of the blocks that are already assembled.
+    bytecode: HashMap<&'a str, Vec<u8>>,
+    // Labels of the blocks being assembled, to detect cyclic references.
+    resolving: Vec<&'a str>,
+}
+
+impl<'s, 'a> Assembler<'s, 'a> {
+    fn instruction(&mut self, token: &Token<'a>) -> Result<Instruction, VMError> {
+        let text = token.text;
+        if text.starts_with("0x") {
+            return Ok(Instruction::Push(Data::Opaque(token.hex(text)?)));
+        }
+        if text.starts_with('@') {
+            return Ok(Instruction::Push(Data::Opaque(
+                self.block(token, &text[1..])?,
+            )));
+        }
+
+        let mut parts = text.split(':');
+        let mnemonic = parts.next().unwrap_or("");
+        let args = parts.collect::<Vec<_>>();
+        let instr = match (mnemonic, args.as_slice()) {
+            ("push", [data]) if data.starts_with('@') => {
+                Instruction::Push(Data::Opaque(self.block(token, &text["push:@".len()..])?))
+            }
+            ("push", [data]) => Instruction::Push(Data::Opaque(token.hex(data)?)),
+            ("push", [n, data]) => {
+                let bytes = token.hex(data)?;
+                if token.int::<usize>(n)? != bytes.len() {
+                    return Err(token.error());
+                }
+                Instruction::Push(Data::Opaque(bytes))
+            }
+            ("drop", []) => Instruction::Drop,
+            ("dup", [k]) => Instruction::Dup(token.int(k)?),
+            ("roll", [k]) => Instruction::Roll(token.int(k)?),
+            ("const", []) => Instruction::Const,
+            ("var", []) => Instruction::Var,
+            ("alloc", []) => Instruction::Alloc(None),
+            ("mintime", []) => Instruction::Mintime,
+            ("maxtime", []) => Instruction::Maxtime,
+            ("expr", []) => Instruction::Expr,
+            ("neg", []) => Instruction::Neg,
+            ("add", []) => Instruction::Add,
+            ("mul", []) => Instruction::Mul,
+            ("eq", []) => Instruction::Eq,
+            ("range", [n]) => Instruction::Range(
+                BitRange::new_wide(token.int::<usize>(n)?).ok_or_else(|| token.error())?,
+            ),
+            ("sha256", [n]) => Instruction::Sha256(token.int(n)?),
+            ("sha512", [n]) => Instruction::Sha512(token.int(n)?),
+            ("poseidon", [n]) => Instruction::Poseidon(token.int(n)?),
+            ("and", []) => Instruction::And,
+            ("or", []) => Instruction::Or,
+            ("not", []) => Instruction::Not,
+            ("verify", []) => Instruction::Verify,
+            ("unblind", []) => Instruction::Unblind,
+            ("issue", []) => Instruction::Issue,
+            ("borrow", []) => Instruction::Borrow,
+            ("retire", []) => Instruction::Retire,
+            ("fee", []) => Instruction::Fee,
+            ("cloak", [m, n]) => Instruction::Cloak(token.int(m)?, token.int(n)?),
+            ("import", []) => Instruction::Import,
+            ("export", []) => Instruction::Export,
+            ("input", []) => Instruction::Input,
+            ("output", [k]) => Instruction::Output(token.int(k)?),
+            ("contract", [k]) => Instruction::Contract(token.int(k)?),
+            ("nonce", []) => Instruction::Nonce,
+            ("log", []) => Instruction::Log,
+            ("nullify", []) => Instruction::Nullify,
+            ("carry", [k]) => Instruction::Carry(token.int(k)?),
+            ("signtx", []) => Instruction::Signtx,
+            ("call", []) => Instruction::Call,
+            ("select", [n, k]) => Instruction::Select(token.int(n)?, token.int(k)?),
+            ("delegate", []) => Instruction::Delegate,
+            ("ext", [x]) => Instruction::Ext(token.int(x)?),
+            ("inspect", [label]) => Instruction::Inspect(label.to_string()),
+            _ => return Err(token.error()),
+        };
+        Ok(instr)
+    }
+
+    /// Returns the bytecode of the labeled block, assembling it if needed.
+    fn block(&mut self, token: &Token<'a>, name: &'a str) -> Result<Vec<u8>, VMError> {
+        let blocks = self.blocks;
+        let tokens = blocks.get(name).ok_or_else(|| token.error())?;
+        if let Some(bytecode) = self.bytecode.get(name) {
+            return Ok(bytecode.clone());
+        }
+        if self.resolving.contains(&name) {
+            return Err(token.error());
+        }
+        self.resolving.push(name);
+        let instructions = tokens
+            .iter()
+            .map(|t| self.instruction(t))
+            .collect::<Result<Vec<_>, _>>()?;
+        self.resolving.pop();
+
+        let bytecode = Program::from_instructions(instructions).to_bytes();
+        self.bytecode.insert(name, bytecode.clone());
+        Ok(bytecode)
+    }
+}
+
+impl<'a> Token<'a> {
+    fn error(&self) -> VMError {
+        VMError::AssemblyError {
+            line: self.line,
+            token: self.text.to_string(),
+        }
+    }
+
+    /// Parses a decimal or `0x`-prefixed hexadecimal integer.
+    fn int<T: FromStrRadix>(&self, s: &str) -> Result<T, VMError> {
+        let value = if s.starts_with("0x") {
+            T::from_str_radix(&s[2..], 16)
+        } else {
+            T::from_str_radix(s, 10)
+        };
+        value.ok_or_else(|| self.error())
+    }
+
+    /// Parses a `0x`-prefixed hex string.
+    fn hex(&self, s: &str) -> Result<Vec<u8>, VMError> {
+        if !s.starts_with("0x") || s.len() % 2 != 0 {
+            return Err(self.error());
+        }
+        (2..s.len())
+            .step_by(2)
+            .map(|i| <u8 as FromStrRadix>::from_str_radix(s.get(i..i + 2)?, 16))
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| self.error())
+    }
+}
+
+fn is_label(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {}
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Integer types that can be parsed from the immediate data.
+trait FromStrRadix: Sized {
+    fn from_str_radix(s: &str, radix: u32) -> Option<Self>;
+}
+
+macro_rules! impl_from_str_radix {
+    ($t:ty) => {
+        impl FromStrRadix for $t {
+            fn from_str_radix(s: &str, radix: u32) -> Option<Self> {
+                // `from_str_radix` also accepts a leading `+`.
+                if s.is_empty() || !s.chars().all(|c| c.is_digit(radix)) {
+                    return None;
+                }
+                <$t>::from_str_radix(s, radix).ok()
+            }
+        }
+    };
+}
+
+impl_from_str_radix!(u8);
+impl_from_str_radix!(usize);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytecode(src: &str) -> Vec<u8> {
+        assemble(src).unwrap().to_bytes()
+    }
+
+    #[test]
+    fn roundtrip() {
+        let src =
+            "push:3:0x010203 input signtx dup:2 range:64 cloak:2:1 select:3:1 output:1 ext:0xff";
+        let program = assemble(src).unwrap();
+        assert_eq!(program.to_string(), src);
+        assert_eq!(Program::disassemble(&program.to_bytes()).unwrap().len(), 9);
+    }
+
+    #[test]
+    fn push_shorthands_and_comments() {
+        let expected = bytecode("push:2:0xabcd drop");
+        assert_eq!(bytecode("push:0xabcd drop"), expected);
+        assert_eq!(bytecode("0xabcd # comment\n  drop # another"), expected);
+    }
+
+    #[test]
+    fn labels() {
+        let src = "
+            @sub call   # forward reference
+            sub:
+                0x01 @inner drop drop
+            inner:
+                0x02
+        ";
+        let inner = bytecode("0x02");
+        let mut sub = bytecode("0x01");
+        Instruction::Push(Data::Opaque(inner)).encode(&mut sub);
+        sub.extend_from_slice(&bytecode("drop drop"));
+
+        let mut expected = Vec::new();
+        Instruction::Push(Data::Opaque(sub)).encode(&mut expected);
+        Instruction::Call.encode(&mut expected);
+        assert_eq!(bytecode(src), expected);
+    }
+
+    #[test]
+    fn errors() {
+        let error = |line: usize, token: &str| {
+            Err(VMError::AssemblyError {
+                line,
+                token: token.to_string(),
+            })
+        };
+        assert_eq!(assemble("input\nfoo").map(|_| ()), error(2, "foo"));
+        assert_eq!(assemble("dup").map(|_| ()), error(1, "dup"));
+        assert_eq!(assemble("drop:1").map(|_| ()), error(1, "drop:1"));
+        assert_eq!(
+            assemble("push:3:0x0102").map(|_| ()),
+            error(1, "push:3:0x0102")
+        );
+        assert_eq!(assemble("0x123").map(|_| ()), error(1, "0x123"));
+        assert_eq!(assemble("range:65").map(|_| ()), error(1, "range:65"));
+        assert_eq!(assemble("dup:+1").map(|_| ()), error(1, "dup:+1"));
+        assert_eq!(assemble("@missing").map(|_| ()), error(1, "@missing"));
+        assert_eq!(assemble("@a\na: @b\nb: @a").map(|_| ()), error(3, "@a"));
+        assert_eq!(assemble("a: drop\na: drop").map(|_| ()), error(2, "a:"));
+    }
+}
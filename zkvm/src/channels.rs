@@ -0,0 +1,527 @@
+//! Two-party payment channels.
+//!
+//! Implements the [payment channel example](../docs/zkvm-spec.md#payment-channel-example)
+//! from the specification. The channel contract is locked with the aggregated key of both parties
+//! and holds a payload `exptime seq value`, where `exptime` and `seq` are zero in the funding contract.
+//!
+//! Each update of the balances is a new `ChannelState` with a higher sequence number.
+//! Both parties sign the state's _close program_, which can be used by either party
+//! via `delegate` to initiate a force-close or to dispute a stale one:
+//! it checks that the sequence number of the spent contract is lower than its own,
+//! and locks the value in a _holding contract_ until `tx.maxtime + contest_period`.
+//! The holding contract can be spent by a close program of a newer state at any time,
+//! or by its _settle program_ after the contest period, which pays out the balances.
+//! Stale states are not punished: they are overridden by newer ones, so the parties
+//! do not need to exchange revocation secrets, but must watch the blockchain during the contest period.
+//!
+//! The keys of the parties must not be reused across channels,
+//! as the signed programs are valid for any contract locked with the same aggregated key.
+
+use curve25519_dalek::scalar::Scalar;
+use merlin::Transcript;
+use rand::{CryptoRng, RngCore};
+use spacesuit::BitRange;
+
+use crate::constraints::Commitment;
+use crate::contract::Output;
+use crate::errors::VMError;
+use crate::predicate::Predicate;
+use crate::program::Program;
+use crate::signature::musig::{Multikey, NonceCommitment};
+use crate::signature::{SecretNonce, Signature, VerificationKey};
+use crate::transcript::{labels, TranscriptProtocol};
+use crate::types::Data;
+
+/// Payment channel between two parties.
+#[derive(Clone)]
+pub struct Channel {
+    keys: Vec<VerificationKey>,
+    multikey: Multikey,
+    flavor: Scalar,
+    contest_period: u64,
+}
+
+/// Payment of a part of the channel's balance to a predicate.
+#[derive(Clone, Debug)]
+pub struct Payout {
+    /// Quantity of the payment.
+    pub qty: u64,
+    /// Predicate of the output receiving the payment.
+    pub predicate: Predicate,
+}
+
+/// Distribution of the channel's balance agreed by the parties.
+#[derive(Clone, Debug)]
+pub struct ChannelState {
+    /// Sequence number of the state, starting with 1. Newer states have higher numbers.
+    pub sequence: u64,
+    /// Payments made when the channel is closed with this state.
+    pub payouts: Vec<Payout>,
+}
+
+/// State with the signature of its close program by both parties.
+#[derive(Clone, Debug)]
+pub struct ChannelUpdate {
+    /// The signed state.
+    pub state: ChannelState,
+    /// Signature of the state's close program with the channel key.
+    pub signature: Signature,
+}
+
+impl Channel {
+    /// Creates a channel between the parties with the given keys, in an order agreed by both,
+    /// for values of the given flavor. Force-closes are contested for `contest_period` milliseconds.
+    pub fn new(
+        keys: Vec<VerificationKey>,
+        flavor: Scalar,
+        contest_period: u64,
+    ) -> Result<Self, VMError> {
+        if keys.len() != 2 {
+            return Err(VMError::BadArguments);
+        }
+        let multikey = Multikey::new(keys.clone())?;
+        Ok(Channel {
+            keys,
+            multikey,
+            flavor,
+            contest_period,
+        })
+    }
+
+    /// Returns the aggregated key of the parties, which locks the channel contract.
+    pub fn key(&self) -> VerificationKey {
+        self.multikey.aggregated_key()
+    }
+
+    /// Returns the predicate of the funding contract.
+    pub fn predicate(&self) -> Predicate {
+        Predicate::Key(self.key())
+    }
+
+    /// Adds instructions that lock the value on top of the stack in the funding contract.
+    pub fn open<'p>(&self, p: &'p mut Program) -> &'p mut Program {
+        p.push(0u64) // stack: value, exptime
+            .roll(1)
+            .push(0u64) // stack: exptime, value, seq
+            .roll(1) // stack: exptime, seq, value
+            .push(self.predicate())
+            .output(3)
+    }
+
+    /// Returns the program that pays out the balances after the contest period,
+    /// spending the holding contract of the state.
+    pub fn settle_program(&self, state: &ChannelState) -> Result<Program, VMError> {
+        let mut program = Program::new();
+        program
+            .roll(1) // stack: exptime, value, seq
+            .drop()
+            .roll(1) // stack: value, exptime
+            .r#const()
+            .neg()
+            .mintime()
+            .add() // stack: value, mintime-exptime
+            .range(BitRange::u64())
+            .drop();
+        self.pay_out(&mut program, &state.payouts)?;
+        Ok(program)
+    }
+
+    /// Returns the predicate of the holding contract of the state, which can be spent
+    /// by a close program of a newer state or by the settle program of this state.
+    pub fn holding_predicate(&self, state: &ChannelState) -> Result<Predicate, VMError> {
+        Predicate::disjunction(vec![
+            self.predicate(),
+            Predicate::unblinded_program(self.settle_program(state)?),
+        ])
+    }
+
+    /// Returns the program to be signed by both parties for the state.
+    /// It expects the expiration time of the contest period under the spent contract,
+    /// checks that the contract has a lower sequence number than the state
+    /// and locks the value in the holding contract of the state.
+    pub fn close_program(&self, state: &ChannelState) -> Result<Program, VMError> {
+        if state.sequence == 0 {
+            return Err(VMError::BadArguments);
+        }
+        let holding_predicate = self.holding_predicate(state)?;
+        let seq_range = BitRange::new(24).expect("24 bits is a valid range");
+        Ok(Program::build(|p| {
+            // stack: new-exptime, exptime, seq, value
+            p.roll(1)
+                .r#const()
+                .neg()
+                .push(state.sequence - 1)
+                .r#const()
+                .add() // stack: new-exptime, exptime, value, new-seq-1-seq
+                .range(seq_range)
+                .drop()
+                .roll(1) // stack: new-exptime, value, exptime
+                .drop()
+                .dup(1)
+                .r#const()
+                .maxtime()
+                .push(self.contest_period)
+                .r#const()
+                .add()
+                .eq()
+                .verify() // stack: new-exptime, value
+                .push(state.sequence)
+                .roll(1) // stack: new-exptime, new-seq, value
+                .push(holding_predicate)
+                .output(3)
+        }))
+    }
+
+    /// Returns the program to be signed by both parties to close the channel
+    /// mutually, spending the funding contract without the contest period.
+    pub fn mutual_close_program(&self, payouts: &[Payout]) -> Result<Program, VMError> {
+        let mut program = Program::new();
+        program
+            .roll(1) // stack: exptime, value, seq
+            .drop()
+            .roll(1) // stack: value, exptime
+            .drop();
+        self.pay_out(&mut program, payouts)?;
+        Ok(program)
+    }
+
+    /// Creates a nonce for signing the program with the party's key.
+    /// The parties exchange the nonce commitments (after the precommitments,
+    /// see `NonceCommitment::precommit`) in the order of the channel keys.
+    pub fn nonce<R: RngCore + CryptoRng>(
+        &self,
+        program: &Program,
+        privkey: &Scalar,
+        rng: &mut R,
+    ) -> SecretNonce {
+        SecretNonce::new(&Self::transcript(program), privkey, rng)
+    }
+
+    /// Creates the party's share of the signature of the program with the channel key,
+    /// given the nonce commitments of both parties.
+    pub fn sign_share(
+        &self,
+        program: &Program,
+        commitments: &[NonceCommitment],
+        privkey: Scalar,
+        nonce: SecretNonce,
+    ) -> Result<Scalar, VMError> {
+        if !self.keys.contains(&VerificationKey::from_secret(&privkey))
+            || commitments.len() != self.keys.len()
+        {
+            return Err(VMError::BadArguments);
+        }
+        Ok(Signature::sign_multikey_share(
+            &mut Self::transcript(program),
+            &self.multikey,
+            commitments,
+            privkey,
+            nonce,
+        ))
+    }
+
+    /// Verifies the shares of both parties and combines them into the signature of the program.
+    pub fn aggregate(
+        &self,
+        program: &Program,
+        commitments: &[NonceCommitment],
+        shares: &[Scalar],
+    ) -> Result<Signature, VMError> {
+        Signature::aggregate_multikey_shares(
+            &mut Self::transcript(program),
+            &self.multikey,
+            &self.keys,
+            commitments,
+            shares,
+        )
+    }
+
+    /// Adds instructions that spend the channel contract with the update's close program,
+    /// locking the value in its holding contract until `maxtime + contest_period`.
+    /// `previous` is the state of the spent holding contract, or `None` for the funding contract.
+    /// The transaction must have the given `maxtime`.
+    pub fn force_close(
+        &self,
+        p: &mut Program,
+        contract: Output,
+        previous: Option<&ChannelState>,
+        update: &ChannelUpdate,
+        maxtime: u64,
+    ) -> Result<(), VMError> {
+        let exptime = maxtime
+            .checked_add(self.contest_period)
+            .ok_or(VMError::BadArguments)?;
+        p.push(exptime).push(contract).input();
+        if let Some(previous) = previous {
+            p.choose_predicate(self.holding_predicate(previous)?, |t| t.select(0))?;
+        }
+        p.push(self.close_program(&update.state)?)
+            .push(Data::Opaque(update.signature.to_bytes().to_vec()))
+            .delegate();
+        Ok(())
+    }
+
+    /// Adds instructions that pay out the balances from the holding contract of the state.
+    /// The transaction's `mintime` must not be earlier than the end of the contest period.
+    pub fn settle(
+        &self,
+        p: &mut Program,
+        contract: Output,
+        state: &ChannelState,
+    ) -> Result<(), VMError> {
+        p.push(contract).input();
+        p.choose_predicate(self.holding_predicate(state)?, |t| t.select(1)?.call())?;
+        Ok(())
+    }
+
+    /// Adds instructions that spend the funding contract with the mutual close program
+    /// for the payouts, signed by both parties.
+    pub fn mutual_close(
+        &self,
+        p: &mut Program,
+        contract: Output,
+        payouts: &[Payout],
+        signature: Signature,
+    ) -> Result<(), VMError> {
+        p.push(contract)
+            .input()
+            .push(self.mutual_close_program(payouts)?)
+            .push(Data::Opaque(signature.to_bytes().to_vec()))
+            .delegate();
+        Ok(())
+    }
+
+    /// Adds instructions that split the value on top of the stack into the payouts.
+    fn pay_out(&self, p: &mut Program, payouts: &[Payout]) -> Result<(), VMError> {
+        if payouts.is_empty() {
+            return Err(VMError::BadArguments);
+        }
+        for payout in payouts.iter() {
+            p.push(Commitment::unblinded(payout.qty))
+                .push(Commitment::unblinded(self.flavor));
+        }
+        p.cloak(1, payouts.len());
+        for payout in payouts.iter().rev() {
+            p.push(payout.predicate.clone()).output(1);
+        }
+        Ok(())
+    }
+
+    fn transcript(program: &Program) -> Transcript {
+        let mut t = Transcript::new(labels::DELEGATE);
+        t.commit_bytes(b"prog", &program.to_bytes());
+        t
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::contract::{Anchor, Contract, PortableItem};
+    use crate::prover::Prover;
+    use crate::txlog::Entry;
+    use crate::types::Value;
+    use crate::verifier::Verifier;
+    use crate::vm::TxHeader;
+    use bulletproofs::BulletproofGens;
+
+    /// Proves and verifies the transaction, returning its first output.
+    fn build_and_verify(
+        program: Program,
+        mintime: u64,
+        maxtime: u64,
+        keys: &[Scalar],
+    ) -> Result<Output, VMError> {
+        let header = TxHeader {
+            version: 0,
+            mintime,
+            maxtime,
+            maxcost: u64::max_value(),
+        };
+        let bp_gens = BulletproofGens::new(256, 1);
+        let (tx, _, log) = Prover::build_tx(program, header, &bp_gens, |t, _| {
+            Signature::sign_aggregated(t, keys)
+        })?;
+        Verifier::verify_tx(tx, &bp_gens)?;
+        Ok(log
+            .into_iter()
+            .filter_map(|entry| match entry {
+                Entry::Output(output) => Some(output),
+                _ => None,
+            })
+            .next()
+            .expect("transaction must have outputs"))
+    }
+
+    /// Signs the program by both parties.
+    fn sign(channel: &Channel, program: &Program, privkeys: &[Scalar]) -> Signature {
+        let mut rng = rand::thread_rng();
+        let nonces = privkeys
+            .iter()
+            .map(|x| channel.nonce(program, x, &mut rng))
+            .collect::<Vec<_>>();
+        let commitments = nonces.iter().map(|n| n.commitment()).collect::<Vec<_>>();
+        let shares = privkeys
+            .iter()
+            .zip(nonces.into_iter())
+            .map(|(x, nonce)| {
+                channel
+                    .sign_share(program, &commitments, *x, nonce)
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+        channel.aggregate(program, &commitments, &shares).unwrap()
+    }
+
+    #[test]
+    fn dispute_stale_close() {
+        let privkeys = vec![Scalar::from(1u64), Scalar::from(2u64)];
+        let pubkeys = privkeys
+            .iter()
+            .map(VerificationKey::from_secret)
+            .collect::<Vec<_>>();
+        let flavor = Scalar::from(7u64);
+        let channel = Channel::new(pubkeys.clone(), flavor, 100).unwrap();
+        let state = |sequence, alice_qty| ChannelState {
+            sequence,
+            payouts: vec![
+                Payout {
+                    qty: alice_qty,
+                    predicate: Predicate::Key(pubkeys[0]),
+                },
+                Payout {
+                    qty: 10 - alice_qty,
+                    predicate: Predicate::Key(pubkeys[1]),
+                },
+            ],
+        };
+
+        // Alice funds the channel.
+        let funds = Output::new(Contract {
+            anchor: Anchor::from_raw_bytes([0u8; 32]),
+            payload: vec![PortableItem::Value(Value {
+                qty: Commitment::blinded(10u64),
+                flv: Commitment::blinded(flavor),
+            })],
+            predicate: Predicate::Key(pubkeys[0]),
+        });
+        let funding = build_and_verify(
+            Program::build(|p| channel.open(p.push(funds).input().sign_tx())),
+            0,
+            0,
+            &privkeys[..1],
+        )
+        .unwrap();
+
+        let updates = vec![state(1, 6), state(2, 3)]
+            .into_iter()
+            .map(|state| {
+                let program = channel.close_program(&state).unwrap();
+                ChannelUpdate {
+                    signature: sign(&channel, &program, &privkeys),
+                    state,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        // Bob force-closes with the stale state.
+        let mut p = Program::new();
+        channel
+            .force_close(&mut p, funding, None, &updates[0], 1000)
+            .unwrap();
+        let stale = build_and_verify(p, 0, 1000, &[]).unwrap();
+
+        // Alice overrides it with the latest state.
+        let mut p = Program::new();
+        channel
+            .force_close(
+                &mut p,
+                stale.clone(),
+                Some(&updates[0].state),
+                &updates[1],
+                1050,
+            )
+            .unwrap();
+        let latest = build_and_verify(p, 0, 1050, &[]).unwrap();
+
+        // The stale state cannot override the same or a newer one.
+        for (contract, previous) in vec![(stale.clone(), 0), (latest.clone(), 1)] {
+            let mut p = Program::new();
+            channel
+                .force_close(
+                    &mut p,
+                    contract,
+                    Some(&updates[previous].state),
+                    &updates[0],
+                    1050,
+                )
+                .unwrap();
+            assert!(build_and_verify(p, 0, 1050, &[]).is_err());
+        }
+
+        // The balances are paid out after the contest period.
+        let settle = |mintime| {
+            let mut p = Program::new();
+            channel
+                .settle(&mut p, latest.clone(), &updates[1].state)
+                .unwrap();
+            build_and_verify(p, mintime, u64::max_value(), &[])
+        };
+        assert!(settle(1149).is_err());
+        // The payouts are output in reverse order.
+        let payout = settle(1150).unwrap();
+        assert_eq!(
+            payout.contract().predicate.to_point(),
+            Predicate::Key(pubkeys[1]).to_point()
+        );
+    }
+
+    #[test]
+    fn mutual_close() {
+        let privkeys = vec![Scalar::from(1u64), Scalar::from(2u64)];
+        let pubkeys = privkeys
+            .iter()
+            .map(VerificationKey::from_secret)
+            .collect::<Vec<_>>();
+        let flavor = Scalar::from(7u64);
+        let channel = Channel::new(pubkeys.clone(), flavor, 100).unwrap();
+        let funding = Output::new(Contract {
+            anchor: Anchor::from_raw_bytes([0u8; 32]),
+            payload: vec![
+                PortableItem::Data(Data::from(0u64)),
+                PortableItem::Data(Data::from(0u64)),
+                PortableItem::Value(Value {
+                    qty: Commitment::blinded(10u64),
+                    flv: Commitment::blinded(flavor),
+                }),
+            ],
+            predicate: channel.predicate(),
+        });
+        let payouts = vec![Payout {
+            qty: 10,
+            predicate: Predicate::Key(pubkeys[1]),
+        }];
+
+        let program = channel.mutual_close_program(&payouts).unwrap();
+        let signature = sign(&channel, &program, &privkeys);
+        let mut p = Program::new();
+        channel
+            .mutual_close(&mut p, funding.clone(), &payouts, signature)
+            .unwrap();
+        assert!(build_and_verify(p, 0, 0, &[]).is_ok());
+
+        // A party cannot sign alone.
+        assert_eq!(
+            channel
+                .sign_share(
+                    &program,
+                    &[],
+                    privkeys[0],
+                    channel.nonce(&program, &privkeys[0], &mut rand::thread_rng())
+                )
+                .unwrap_err(),
+            VMError::BadArguments
+        );
+        assert!(Channel::new(pubkeys[..1].to_vec(), flavor, 100).is_err());
+    }
+}
@@ -0,0 +1,358 @@
+//! Estimation of the constraint system size produced by a program.
+//!
+//! The estimate mirrors the allocations performed by the VM and the Spacesuit gadgets,
+//! so that the prover can pick a sufficient `BulletproofGens` capacity up front.
+
+use bulletproofs::r1cs::R1CSProof;
+use bulletproofs::BulletproofGens;
+use core::cmp::{max, min};
+use core::ops::{Add, AddAssign};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use spacesuit::BitRange;
+
+use crate::constraints::Constraint;
+use crate::errors::VMError;
+
+use crate::gadgets::{self, Sha2};
+use crate::ops::Instruction;
+use crate::poseidon::{self, Poseidon};
+
+/// Number of multipliers and linear constraints allocated in the constraint system.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct CircuitSize {
+    /// Number of multiplication gates.
+    pub multipliers: usize,
+
+    /// Number of linear constraints.
+    pub constraints: usize,
+
+    /// Number of variables allocated with the `alloc` instruction.
+    /// Two such variables share one multiplication gate.
+    pub allocations: usize,
+}
+
+impl CircuitSize {
+    /// Returns the total number of multiplication gates,
+    /// including the gates used by the low-level allocated variables.
+    pub fn gates(&self) -> usize {
+        self.multipliers + (self.allocations + 1) / 2
+    }
+
+    /// Returns the minimum capacity of `BulletproofGens` required to prove the circuit.
+    /// The proof pads the number of gates to the next power of two with zero-valued multipliers,
+    /// so all the range proofs and gadgets of a transaction share the padded generators.
+    pub fn bp_gens_capacity(&self) -> usize {
+        self.gates().next_power_of_two()
+    }
+
+    /// Returns `VMError::InsufficientGenerators` if the generators are too small for the circuit.
+    /// Used to explain a failure to create or verify the proof.
+    pub(crate) fn insufficient_generators(&self, bp_gens: &BulletproofGens) -> Option<VMError> {
+        let required = self.bp_gens_capacity();
+        if bp_gens.gens_capacity < required {
+            Some(VMError::InsufficientGenerators {
+                required,
+                available: bp_gens.gens_capacity,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Computes the upper bound of the circuit size allocated by a single instruction.
+    ///
+    /// Instructions that execute nested programs (`call` and `delegate`) and
+    /// the predicate programs of the spent contracts are not accounted for:
+    /// their sizes must be estimated separately and added up.
+    pub(crate) fn instruction(instr: &Instruction) -> Self {
+        match instr {
+            Instruction::Alloc(_) => CircuitSize {
+                multipliers: 0,
+                constraints: 0,
+                allocations: 1,
+            },
+            // Multiplication of two linear combinations allocates a multiplier,
+            // while multiplication by a constant is free.
+            Instruction::Mul => Self::gates_with_constraints(1, 2),
+            // Constraints are flattened by `verify`, but we account for them when they are created.
+            Instruction::Or => Self::gates_with_constraints(1, 2),
+            Instruction::Not => Self::gates_with_constraints(2, 4),
+            Instruction::Verify | Instruction::Nullify => Self::gates_with_constraints(0, 1),
+            Instruction::Range(n) => Self::range_proof(*n),
+            Instruction::Sha256(n) => Self::sha2(&gadgets::SHA256, *n),
+            Instruction::Sha512(n) => Self::sha2(&gadgets::SHA512, *n),
+            // Three multipliers per S-box, except for the S-boxes applied to constants.
+            Instruction::Poseidon(n) => Self::gates_with_constraints(3, 6)
+                .times(poseidon::SBOXES * Poseidon::permutations(*n)),
+            Instruction::Issue => Self::range_proof(BitRange::u64()),
+            Instruction::Borrow => {
+                Self::range_proof(BitRange::u64())
+                    + CircuitSize {
+                        multipliers: 0,
+                        constraints: 1,
+                        allocations: 1,
+                    }
+            }
+            // Negative quantity and zero flavor share one multiplier.
+            Instruction::Fee => Self::gates_with_constraints(1, 2),
+            Instruction::Cloak(m, n) => Self::cloak(*m, *n),
+            _ => Self::default(),
+        }
+    }
+
+    /// Computes the circuit size allocated by `verify` for the constraint,
+    /// the same as of the instructions creating its `or` and `not` nodes.
+    /// Other nodes are counted by the gadgets they are flattened into without the optimization.
+    pub(crate) fn constraint(constraint: &Constraint) -> Self {
+        match constraint {
+            Constraint::Eq(_, _) => Self::default(),
+            Constraint::And(c1, c2) => Self::constraint(c1) + Self::constraint(c2),
+            Constraint::Or(c1, c2) => {
+                Self::constraint(c1) + Self::constraint(c2) + Self::gates_with_constraints(1, 2)
+            }
+            Constraint::Not(c) => Self::constraint(c) + Self::gates_with_constraints(2, 4),
+            Constraint::Xor(c1, c2) => {
+                Self::constraint(c1) + Self::constraint(c2) + Self::gates_with_constraints(3, 6)
+            }
+            Constraint::IfThenElse(cond, c1, c2) => {
+                Self::constraint(cond)
+                    + Self::constraint(c1)
+                    + Self::constraint(c2)
+                    + Self::gates_with_constraints(3, 6)
+            }
+            Constraint::Range(_, n) => Self::range_proof(*n),
+            Constraint::Lt(_, _) | Constraint::Le(_, _) => Self::range_proof(BitRange::u64()),
+        }
+    }
+
+    fn gates_with_constraints(multipliers: usize, constraints: usize) -> Self {
+        CircuitSize {
+            multipliers,
+            constraints,
+            allocations: 0,
+        }
+    }
+
+    /// Size of `spacesuit::range_proof`: a multiplier and two constraints per bit,
+    /// and a constraint for the sum of the bits.
+    fn range_proof(n: BitRange) -> Self {
+        let n: usize = n.into();
+        Self::gates_with_constraints(n, 2 * n + 1)
+    }
+
+    /// Size of `spacesuit::cloak` with `m` inputs and `n` outputs.
+    fn cloak(m: usize, n: usize) -> Self {
+        if m == 0 || n == 0 {
+            return Self::default();
+        }
+        let mut size = Self::k_mix(m) + Self::k_mix(n);
+        size += Self::value_shuffle(m);
+        size += Self::padded_shuffle(m, n);
+        size += Self::value_shuffle(n);
+        for _ in 0..n {
+            size += Self::range_proof(BitRange::u64());
+        }
+        size
+    }
+
+    /// Upper bound of the size of the SHA-2 gadget for a preimage of `n` bytes:
+    /// every byte and every intermediate word is assumed to be a variable.
+    /// Additions of words decompose the sums into bits with their carries,
+    /// and bitwise operations on variables use one multiplier and two constraints per bit
+    /// (two multipliers for the majority function).
+    fn sha2(sha2: &Sha2, n: usize) -> Self {
+        let w = sha2.word_bits;
+        let [s0, s1] = sha2.small_sigma;
+        let rounds = sha2.k.len();
+        let bitwise = |k: usize| Self::gates_with_constraints(k, 2 * k);
+        let add = |k: usize| Self::unpack_bits(w + gadgets::carry_bits(k));
+
+        // σ0 and σ1 of two words, with the shifted-out bits being constants.
+        let schedule = bitwise(2 * w - s0[2]) + bitwise(2 * w - s1[2]) + add(4);
+        // Σ1, ch, Σ0 and maj of the working variables, and the new `e` and `a`.
+        let round = bitwise(2 * w) + bitwise(w) + bitwise(2 * w) + bitwise(2 * w) + add(6) + add(7);
+        let block = schedule.times(rounds - 16) + round.times(rounds) + add(2).times(8);
+
+        Self::unpack_bits(8).times(n) + block.times(sha2.blocks(n))
+    }
+
+    /// Size of `gadgets::unpack_bits`, the same as a range proof of `n` bits.
+    fn unpack_bits(n: usize) -> Self {
+        Self::gates_with_constraints(n, 2 * n + 1)
+    }
+
+    fn times(self, k: usize) -> Self {
+        CircuitSize {
+            multipliers: self.multipliers * k,
+            constraints: self.constraints * k,
+            allocations: self.allocations * k,
+        }
+    }
+
+    /// `k` input, `k-2` intermediate and `k` output values, one multiplier each,
+    /// and `k-1` mix gadgets, each using one multiplier and three constraints.
+    fn k_mix(k: usize) -> Self {
+        if k == 1 {
+            return Self::default();
+        }
+        Self::gates_with_constraints((3 * k - 2) + (k - 1), 3 * (k - 1))
+    }
+
+    /// `k` multipliers for the values and `2*(k-1)` multipliers for the scalar shuffle,
+    /// two constraints per multiplier and one constraint for the shuffle result.
+    fn value_shuffle(k: usize) -> Self {
+        if k == 1 {
+            return Self::gates_with_constraints(0, 2);
+        }
+        let multipliers = k + 2 * (k - 1);
+        Self::gates_with_constraints(multipliers, 2 * multipliers + 1)
+    }
+
+    /// One zero value (a multiplier with two constraints) per padded slot
+    /// and a value shuffle of the padded lists.
+    fn padded_shuffle(m: usize, n: usize) -> Self {
+        let pad_count = max(m, n) - min(m, n);
+        Self::gates_with_constraints(pad_count, 2 * pad_count) + Self::value_shuffle(max(m, n))
+    }
+}
+
+impl Add for CircuitSize {
+    type Output = CircuitSize;
+
+    fn add(self, rhs: CircuitSize) -> CircuitSize {
+        CircuitSize {
+            multipliers: self.multipliers + rhs.multipliers,
+            constraints: self.constraints + rhs.constraints,
+            allocations: self.allocations + rhs.allocations,
+        }
+    }
+}
+
+impl AddAssign for CircuitSize {
+    fn add_assign(&mut self, rhs: CircuitSize) {
+        *self = *self + rhs;
+    }
+}
+
+/// Size of the R1CS proof of a transaction and the estimated cost of its verification,
+/// returned by `Prover::build_tx_with_metrics` and stored in `VerifiedTx`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct R1CSProofMetrics {
+    /// Number of multiplication gates, including the gates used by the allocated variables,
+    /// as estimated from the executed instructions (see `CircuitSize`).
+    pub multipliers: usize,
+
+    /// Number of linear constraints, as estimated from the executed instructions.
+    pub constraints: usize,
+
+    /// Number of variables committed to the constraint system (commitments to the values and scalars).
+    pub commitments: usize,
+
+    /// Size of the serialized proof in bytes.
+    pub proof_size: usize,
+
+    /// Number of scalar-point multiplications in the multiscalar multiplication
+    /// that verifies the proof: two per padded multiplier, two per round of the inner-product proof,
+    /// one per commitment, and 13 for the points of the proof and the Pedersen generators.
+    pub verification_scalar_ops: usize,
+}
+
+impl R1CSProofMetrics {
+    /// Computes the metrics of the proof of the circuit with `commitments` committed variables.
+    /// The proof consists of 11 points, 3 scalars and an inner-product proof with two points per round
+    /// and two final scalars, so the number of padded multipliers is `2^rounds`.
+    pub(crate) fn new(size: CircuitSize, commitments: usize, proof: &R1CSProof) -> Self {
+        let proof_size = proof.serialized_size();
+        let rounds = proof_size.saturating_sub(16 * 32) / 64;
+        R1CSProofMetrics {
+            multipliers: size.gates(),
+            constraints: size.constraints,
+            commitments,
+            proof_size,
+            verification_scalar_ops: 2 * (1 << rounds) + 2 * rounds + commitments + 13,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::program::Program;
+
+    #[test]
+    fn allocations_share_gates() {
+        let size =
+            Program::build(|p| p.alloc(None).alloc(None).alloc(None)).estimate_circuit_size();
+        assert_eq!(size.allocations, 3);
+        assert_eq!(size.gates(), 2);
+        assert_eq!(size.bp_gens_capacity(), 2);
+    }
+
+    #[test]
+    fn range_and_constraints() {
+        let size = Program::build(|p| {
+            p.alloc(None)
+                .range(BitRange::new(8).unwrap())
+                .alloc(None)
+                .alloc(None)
+                .eq()
+                .not()
+                .verify()
+        })
+        .estimate_circuit_size();
+        assert_eq!(size.multipliers, 8 + 2);
+        assert_eq!(size.constraints, 17 + 4 + 1);
+        assert_eq!(size.gates(), 12);
+        assert_eq!(size.bp_gens_capacity(), 16);
+    }
+
+    #[test]
+    fn cloak_size() {
+        // 1:1 cloak consists only of the two trivial shuffles, the padded shuffle and a range proof.
+        assert_eq!(
+            CircuitSize::cloak(1, 1),
+            CircuitSize {
+                multipliers: 64,
+                constraints: 2 + 2 + 2 + 129,
+                allocations: 0,
+            }
+        );
+
+        // 2:1 cloak: mix of 2 inputs (5 gates), value shuffle of 2 (4 gates),
+        // padded shuffle with 1 padding value (1 + 4 gates) and a range proof.
+        assert_eq!(CircuitSize::cloak(2, 1).multipliers, 5 + 4 + 1 + 4 + 64);
+
+        // 2:2 cloak fits into 256 generators used in the tests.
+        let size = Program::build(|p| p.cloak(2, 2)).estimate_circuit_size();
+        assert_eq!(size.gates(), 5 + 5 + 4 + 4 + 4 + 2 * 64);
+        assert_eq!(size.bp_gens_capacity(), 256);
+    }
+
+    #[test]
+    fn sha2_size() {
+        // One block: the preimage bytes, 48 schedule words, 64 rounds and the final additions.
+        let block256 = 48 * 149 + 64 * 294 + 8 * 33;
+        let size = Program::build(|p| p.sha256(32)).estimate_circuit_size();
+        assert_eq!(size.multipliers, 8 * 32 + block256);
+        assert_eq!(size.bp_gens_capacity(), 32768);
+
+        // 56 bytes do not leave room for the padding in one block.
+        let size = Program::build(|p| p.sha256(56)).estimate_circuit_size();
+        assert_eq!(size.multipliers, 8 * 56 + 2 * block256);
+
+        let size = Program::build(|p| p.sha512(64)).estimate_circuit_size();
+        assert_eq!(size.multipliers, 8 * 64 + 64 * 309 + 80 * 582 + 8 * 65);
+    }
+
+    #[test]
+    fn poseidon_size() {
+        // One permutation absorbs two inputs.
+        let size = Program::build(|p| p.poseidon(2)).estimate_circuit_size();
+        assert_eq!(size.multipliers, 243);
+        let size = Program::build(|p| p.poseidon(3)).estimate_circuit_size();
+        assert_eq!(size.multipliers, 2 * 243);
+        assert_eq!(size.bp_gens_capacity(), 512);
+    }
+}
@@ -1,21 +1,33 @@
 //! Constraint system-related types and operations:
 //! Commitments, Variables, Expressions and Constraints.
 
-use bulletproofs::{r1cs, r1cs::ConstraintSystem, PedersenGens};
+use bulletproofs::{r1cs, r1cs::ConstraintSystem};
 use curve25519_dalek::ristretto::CompressedRistretto;
 use curve25519_dalek::scalar::Scalar;
+use rand::{CryptoRng, RngCore};
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use spacesuit::BitRange;
+use std::collections::HashMap;
+use std::iter;
 use std::iter::FromIterator;
-use std::ops::{Add, Neg};
-use subtle::{ConditionallySelectable, ConstantTimeEq};
+use std::ops::{Add, Mul, Neg, Sub};
+use zeroize::Zeroize;
 
 use crate::encoding;
 use crate::errors::VMError;
+use crate::gadgets;
+use crate::gens;
+use crate::point_ops::PointOp;
 use crate::scalar_witness::ScalarWitness;
 
 /// Variable represents a high-level R1CS variable specified by its
 /// Pedersen commitment. In ZkVM variables are actually indices to a list
 /// of stored commitments to permit commitment reblinding (see `reblind` instruction).
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Variable {
     /// TBD: maybe do this as a subtype of the Expression
     pub(crate) commitment: Commitment,
@@ -23,19 +35,27 @@ pub struct Variable {
 
 /// Expression is a linear combination of high-level variables (`var`),
 /// low-level variables (`alloc`) and constants.
+/// Note: R1CS variables are bound to a particular constraint system,
+/// so a serialized linear combination is meaningful only within that constraint system.
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Expression {
     /// Represents a constant. Operations on constants produce constants.
     Constant(ScalarWitness),
 
     /// Linear combination of R1CS variables and constants.
-    LinearCombination(Vec<(r1cs::Variable, Scalar)>, Option<ScalarWitness>),
+    LinearCombination(
+        #[cfg_attr(feature = "serde", serde(with = "crate::serialization::r1cs_terms"))]
+        Vec<(r1cs::Variable, Scalar)>,
+        Option<ScalarWitness>,
+    ),
 }
 
 /// Constraint is a boolean function of expressions and other constraints.
 /// Constraints can be evaluated to true or false. The `verify` instruction
 /// enforces that the final composition evaluates to `true` in zero knowledge.
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Constraint {
     /// Equality constraint between two expressions.
     /// Created by `eq` instruction.
@@ -52,12 +72,35 @@ pub enum Constraint {
     /// Negation of a constraint: must be zero to evaluate to true.
     /// Created by 'not' instruction.
     Not(Box<Constraint>),
+
+    /// Exclusive disjunction of two constraints: exactly one must evaluate to true.
+    Xor(Box<Constraint>, Box<Constraint>),
+
+    /// Conditional constraint: if the first constraint evaluates to true,
+    /// the second one must evaluate to true, otherwise the third one must evaluate to true.
+    IfThenElse(Box<Constraint>, Box<Constraint>, Box<Constraint>),
+
+    /// Range constraint: evaluates to true if the expression is in range [0, 2^n).
+    /// Unlike the `range` instruction, it can be composed with other constraints.
+    Range(
+        Expression,
+        #[cfg_attr(feature = "serde", serde(with = "crate::serialization::bit_range"))] BitRange,
+    ),
+
+    /// Less-than constraint: evaluates to true if the first expression is strictly
+    /// less than the second one. Both expressions are expected to be in range [0, 2^64).
+    Lt(Expression, Expression),
+
+    /// Less-or-equal constraint: evaluates to true if the first expression is less
+    /// than or equal to the second one. Both expressions are expected to be in range [0, 2^64).
+    Le(Expression, Expression),
     // no witness needed as it's normally true/false and we derive it on the fly during processing.
     // this also allows us not to wrap this enum in a struct.
 }
 
 /// Commitment is a represention of an _open_ or _closed_ Pedersen commitment.
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Commitment {
     /// Hides a secret value and its blinding factor in the Ristretto point.
     Closed(CompressedRistretto),
@@ -66,30 +109,338 @@ pub enum Commitment {
     Open(Box<CommitmentWitness>),
 }
 
-/// Prover's representation of the commitment secret: witness and blinding factor
+/// Prover's representation of the commitment secret: witness and blinding factor.
+/// The secret is zeroized when dropped.
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct CommitmentWitness {
     value: ScalarWitness,
     blinding: Scalar,
 }
 
+/// Result of the constraint optimization: either a truth value known from the constants,
+/// or a constraint that has to be proven.
+enum Optimized {
+    Known(bool),
+    Constraint(Constraint),
+}
+
 impl Constraint {
     /// Generates and adds to R1CS constraints that enforce that the self evaluates to true.
     /// Implements the logic behind `verify` instruction.
+    ///
+    /// The constraint is optimized before flattening (see `optimize`),
+    /// so the prover and the verifier must produce the same constraint tree.
+    ///
+    /// Fails with `VMError::NegatedRange` if a range constraint remains negated
+    /// after the optimization: the prover chooses the bits of the range constraint,
+    /// so it can always make it false.
     pub fn verify<CS: r1cs::ConstraintSystem>(self, cs: &mut CS) -> Result<(), VMError> {
+        let constraint = match self.optimize() {
+            Optimized::Known(true) => return Ok(()),
+            // The constraint is unsatisfiable: `1 == 0`.
+            Optimized::Known(false) => {
+                cs.constrain(Scalar::one().into());
+                return Ok(());
+            }
+            Optimized::Constraint(c) => c,
+        };
+        if constraint.negates_range() {
+            return Err(VMError::NegatedRange);
+        }
         cs.specify_randomized_constraints(move |cs| {
             // Flatten the constraint into one expression
             // Note: cloning because we can't move out of captured variable in an `Fn` closure,
             // and `Box<FnOnce>` is not fully supported yet. (We can update when that happens).
             // Cf. https://github.com/dalek-cryptography/bulletproofs/issues/244
-            let (expr, _) = self.clone().flatten(cs)?;
+            let (expr, _) = constraint.clone().flatten(cs)?;
 
             // Add the resulting expression to the constraint system
             cs.constrain(expr);
 
             Ok(())
         })
-        .map_err(|e| VMError::R1CSError(e))
+        .map_err(VMError::R1CSError)
+    }
+
+    /// Simplifies the constraint tree to reduce the number of multipliers:
+    /// 1. Folds the sub-constraints over constant expressions.
+    /// 2. Removes double negations and duplicate operands of `and` and `or` chains.
+    /// 3. Replaces two or more negated operands of a chain with a single negation
+    ///    using De Morgan's laws, since each `not` costs 2 multipliers and 4 constraints.
+    /// 4. Keeps the inequalities ordered: a negated inequality becomes the reversed one
+    ///    (see `negate`), and the operands with inequalities are not wrapped in a negation,
+    ///    so the optimized constraint is as sound as the original one.
+    ///
+    /// Duplicates are detected by the terms of the expressions and not their assignments,
+    /// so the prover and the verifier optimize the constraint in the same way.
+    fn optimize(self) -> Optimized {
+        match self {
+            Constraint::Eq(expr1, expr2) => match (&expr1, &expr2) {
+                (Expression::Constant(a), Expression::Constant(b)) => {
+                    Optimized::Known(a.to_scalar() == b.to_scalar())
+                }
+                _ => Optimized::Constraint(Constraint::Eq(expr1, expr2)),
+            },
+            Constraint::Range(expr, bitrange) => match expr {
+                Expression::Constant(a) => Optimized::Known(a.in_range(bitrange)),
+                expr => Optimized::Constraint(Constraint::Range(expr, bitrange)),
+            },
+            Constraint::Lt(expr1, expr2) => match (&expr1, &expr2) {
+                (Expression::Constant(_), Expression::Constant(_)) => {
+                    let diff = expr2 - expr1 - Expression::constant(1u64);
+                    Constraint::Range(diff, BitRange::u64()).optimize()
+                }
+                _ => Optimized::Constraint(Constraint::Lt(expr1, expr2)),
+            },
+            Constraint::Le(expr1, expr2) => match (&expr1, &expr2) {
+                (Expression::Constant(_), Expression::Constant(_)) => {
+                    Constraint::Range(expr2 - expr1, BitRange::u64()).optimize()
+                }
+                _ => Optimized::Constraint(Constraint::Le(expr1, expr2)),
+            },
+            Constraint::Not(c) => match c.optimize() {
+                Optimized::Known(b) => Optimized::Known(!b),
+                Optimized::Constraint(c) => Optimized::Constraint(c.negate()),
+            },
+            Constraint::And(c1, c2) => Self::optimize_chain(vec![*c1, *c2], false),
+            Constraint::Or(c1, c2) => Self::optimize_chain(vec![*c1, *c2], true),
+            Constraint::Xor(c1, c2) => match (c1.optimize(), c2.optimize()) {
+                (Optimized::Known(a), Optimized::Known(b)) => Optimized::Known(a != b),
+                (Optimized::Known(k), Optimized::Constraint(c))
+                | (Optimized::Constraint(c), Optimized::Known(k)) => {
+                    Optimized::Constraint(if k { c.negate() } else { c })
+                }
+                (Optimized::Constraint(a), Optimized::Constraint(b)) => {
+                    if a.same_as(&b) {
+                        return Optimized::Known(false);
+                    }
+                    // The "not both" part of `xor` negates the operands (see `flatten`),
+                    // so the operands with inequalities are combined as `(a & !b) | (!a & b)`.
+                    if a.has_range() || b.has_range() {
+                        let (not_a, not_b) = (a.clone().negate(), b.clone().negate());
+                        return Self::optimize_chain(
+                            vec![
+                                Constraint::And(Box::new(a), Box::new(not_b)),
+                                Constraint::And(Box::new(not_a), Box::new(b)),
+                            ],
+                            true,
+                        );
+                    }
+                    let xor = match (a, b) {
+                        (Constraint::Not(a), Constraint::Not(b)) => Constraint::Xor(a, b),
+                        (a, b) => Constraint::Xor(Box::new(a), Box::new(b)),
+                    };
+                    Optimized::Constraint(xor)
+                }
+            },
+            Constraint::IfThenElse(cond, c1, c2) => {
+                let cond = match cond.optimize() {
+                    Optimized::Known(true) => return c1.optimize(),
+                    Optimized::Known(false) => return c2.optimize(),
+                    Optimized::Constraint(cond) => cond,
+                };
+                // The condition is also used negated (see `flatten`),
+                // so the condition with inequalities is combined as `(c & a) | (!c & b)`.
+                if cond.has_range() {
+                    let not_cond = cond.clone().negate();
+                    return Self::optimize_chain(
+                        vec![
+                            Constraint::And(Box::new(cond), c1),
+                            Constraint::And(Box::new(not_cond), c2),
+                        ],
+                        true,
+                    );
+                }
+                // Swap the branches instead of negating the condition.
+                let (cond, c1, c2) = match cond {
+                    Constraint::Not(cond) => (*cond, c2, c1),
+                    cond => (cond, c1, c2),
+                };
+                let optimized = match (c1.optimize(), c2.optimize()) {
+                    (Optimized::Known(a), Optimized::Known(b)) => {
+                        if a == b {
+                            return Optimized::Known(a);
+                        } else if a {
+                            cond
+                        } else {
+                            cond.negate()
+                        }
+                    }
+                    (Optimized::Known(true), Optimized::Constraint(c2)) => {
+                        Constraint::Or(Box::new(cond), Box::new(c2))
+                    }
+                    (Optimized::Known(false), Optimized::Constraint(c2)) => {
+                        Constraint::And(Box::new(cond.negate()), Box::new(c2))
+                    }
+                    (Optimized::Constraint(c1), Optimized::Known(true)) => {
+                        Constraint::Or(Box::new(cond.negate()), Box::new(c1))
+                    }
+                    (Optimized::Constraint(c1), Optimized::Known(false)) => {
+                        Constraint::And(Box::new(cond), Box::new(c1))
+                    }
+                    (Optimized::Constraint(c1), Optimized::Constraint(c2)) => {
+                        if c1.same_as(&c2) {
+                            c1
+                        } else {
+                            Constraint::IfThenElse(Box::new(cond), Box::new(c1), Box::new(c2))
+                        }
+                    }
+                };
+                Optimized::Constraint(optimized)
+            }
+        }
+    }
+
+    /// Optimizes a chain of conjunctions (`is_or == false`) or disjunctions (`is_or == true`).
+    fn optimize_chain(operands: Vec<Constraint>, is_or: bool) -> Optimized {
+        let mut terms: Vec<Constraint> = Vec::new();
+        for operand in operands {
+            match operand.optimize() {
+                // `false` decides the conjunction and `true` decides the disjunction.
+                Optimized::Known(b) if b == is_or => return Optimized::Known(b),
+                Optimized::Known(_) => {}
+                Optimized::Constraint(c) => {
+                    let mut flattened = Vec::new();
+                    c.split_chain(is_or, &mut flattened);
+                    for c in flattened {
+                        if !terms.iter().any(|t| t.same_as(&c)) {
+                            terms.push(c);
+                        }
+                    }
+                }
+            }
+        }
+
+        // De Morgan's laws: `!a & !b == !(a | b)` and `!a | !b == !(a & b)`.
+        // The range constraints are never moved under a negation.
+        let (negated, mut terms): (Vec<_>, Vec<_>) = terms.into_iter().partition(|c| match c {
+            Constraint::Not(c) => !c.has_range(),
+            _ => false,
+        });
+        if negated.len() > 1 {
+            let inner = negated.into_iter().map(|c| c.negate()).collect();
+            terms.push(Self::chain(inner, !is_or).negate());
+        } else {
+            terms.extend(negated);
+        }
+
+        if terms.is_empty() {
+            Optimized::Known(!is_or)
+        } else {
+            Optimized::Constraint(Self::chain(terms, is_or))
+        }
+    }
+
+    /// Collects the operands of nested conjunctions or disjunctions.
+    fn split_chain(self, is_or: bool, operands: &mut Vec<Constraint>) {
+        match (self, is_or) {
+            (Constraint::And(c1, c2), false) | (Constraint::Or(c1, c2), true) => {
+                c1.split_chain(is_or, operands);
+                c2.split_chain(is_or, operands);
+            }
+            (c, _) => operands.push(c),
+        }
+    }
+
+    /// Combines a non-empty list of operands into a chain of conjunctions or disjunctions.
+    fn chain(operands: Vec<Constraint>, is_or: bool) -> Constraint {
+        let mut operands = operands.into_iter();
+        let first = operands
+            .next()
+            .expect("chain must have at least one operand");
+        operands.fold(first, |acc, c| {
+            if is_or {
+                Constraint::Or(Box::new(acc), Box::new(c))
+            } else {
+                Constraint::And(Box::new(acc), Box::new(c))
+            }
+        })
+    }
+
+    /// Negates the constraint, removing a double negation.
+    /// The inequalities are reversed instead of negated:
+    /// `!(a < b) == b <= a` and `!(a <= b) == b < a` for the values in range [0, 2^64).
+    /// The negation of a constraint with inequalities is pushed down to them.
+    fn negate(self) -> Constraint {
+        if !self.has_range() {
+            return match self {
+                Constraint::Not(c) => *c,
+                c => Constraint::Not(Box::new(c)),
+            };
+        }
+        match self {
+            Constraint::Not(c) => *c,
+            Constraint::Lt(expr1, expr2) => Constraint::Le(expr2, expr1),
+            Constraint::Le(expr1, expr2) => Constraint::Lt(expr2, expr1),
+            Constraint::And(c1, c2) => Constraint::Or(Box::new(c1.negate()), Box::new(c2.negate())),
+            Constraint::Or(c1, c2) => Constraint::And(Box::new(c1.negate()), Box::new(c2.negate())),
+            Constraint::Xor(c1, c2) => Constraint::Xor(Box::new(c1.negate()), c2),
+            Constraint::IfThenElse(cond, c1, c2) => {
+                Constraint::IfThenElse(cond, Box::new(c1.negate()), Box::new(c2.negate()))
+            }
+            c => Constraint::Not(Box::new(c)),
+        }
+    }
+
+    /// Returns true if a range constraint or an inequality is used negated:
+    /// under `not`, in an operand of `xor` or in the condition of an if-then-else.
+    fn negates_range(&self) -> bool {
+        match self {
+            Constraint::Not(c) => c.has_range(),
+            Constraint::Xor(c1, c2) => c1.has_range() || c2.has_range(),
+            Constraint::IfThenElse(cond, c1, c2) => {
+                cond.has_range() || c1.negates_range() || c2.negates_range()
+            }
+            Constraint::And(c1, c2) | Constraint::Or(c1, c2) => {
+                c1.negates_range() || c2.negates_range()
+            }
+            Constraint::Eq(_, _)
+            | Constraint::Range(_, _)
+            | Constraint::Lt(_, _)
+            | Constraint::Le(_, _) => false,
+        }
+    }
+
+    /// Returns true if the constraint contains a range constraint or an inequality.
+    fn has_range(&self) -> bool {
+        match self {
+            Constraint::Eq(_, _) => false,
+            Constraint::Range(_, _) | Constraint::Lt(_, _) | Constraint::Le(_, _) => true,
+            Constraint::Not(c) => c.has_range(),
+            Constraint::And(c1, c2) | Constraint::Or(c1, c2) | Constraint::Xor(c1, c2) => {
+                c1.has_range() || c2.has_range()
+            }
+            Constraint::IfThenElse(cond, c1, c2) => {
+                cond.has_range() || c1.has_range() || c2.has_range()
+            }
+        }
+    }
+
+    /// Returns true if both constraints have the same structure and the same expressions,
+    /// regardless of the assignments.
+    fn same_as(&self, other: &Constraint) -> bool {
+        match (self, other) {
+            (Constraint::Eq(a1, b1), Constraint::Eq(a2, b2))
+            | (Constraint::Lt(a1, b1), Constraint::Lt(a2, b2))
+            | (Constraint::Le(a1, b1), Constraint::Le(a2, b2)) => {
+                a1.same_terms(a2) && b1.same_terms(b2)
+            }
+            (Constraint::And(a1, b1), Constraint::And(a2, b2))
+            | (Constraint::Or(a1, b1), Constraint::Or(a2, b2))
+            | (Constraint::Xor(a1, b1), Constraint::Xor(a2, b2)) => {
+                a1.same_as(a2) && b1.same_as(b2)
+            }
+            (Constraint::Not(a), Constraint::Not(b)) => a.same_as(b),
+            (Constraint::IfThenElse(c1, a1, b1), Constraint::IfThenElse(c2, a2, b2)) => {
+                c1.same_as(c2) && a1.same_as(a2) && b1.same_as(b2)
+            }
+            (Constraint::Range(a, n), Constraint::Range(b, m)) => {
+                let (n, m): (usize, usize) = ((*n).into(), (*m).into());
+                a.same_terms(b) && n == m
+            }
+            _ => false,
+        }
     }
 
     fn flatten<CS: r1cs::RandomizedConstraintSystem>(
@@ -122,43 +473,63 @@ impl Constraint {
                 // Compute the input linear combination and its secret assignment
                 let (x_lc, x_assg) = c1.flatten(cs)?;
 
-                // Compute assignments for all the wires
-                let (xy_assg, xw_assg, y_assg) = match x_assg {
-                    Some(x) => {
-                        let is_zero = x.ct_eq(&Scalar::zero());
-                        let y = Scalar::conditional_select(
-                            &Scalar::zero(),
-                            &Scalar::one(),
-                            is_zero.into(),
-                        );
-                        let w = Scalar::conditional_select(&x, &Scalar::one(), is_zero.into());
-                        let w = w.invert();
-                        (Some((x, y)), Some((x, w)), Some(y))
-                    }
-                    None => (None, None, None),
-                };
-
-                // Allocate two multipliers.
-                let (l1, r1, o1) = cs.allocate_multiplier(xy_assg)?;
-                let (l2, _r2, o2) = cs.allocate_multiplier(xw_assg)?;
-
-                // Add 4 constraints.
+                // Compute `y = (x == 0)`: two multipliers and four constraints.
+                let (y, y_assg) = gadgets::is_zero_lc(cs, x_lc, x_assg)?;
 
-                // (1) `x == l1`
-                cs.constrain(l1 - x_lc);
+                Ok((r1cs::LinearCombination::from(y), y_assg))
+            }
+            Constraint::Xor(c1, c2) => {
+                let (a, a_assg) = c1.flatten(cs)?;
+                let (b, b_assg) = c2.flatten(cs)?;
 
-                // (2) `l1 == l2` (== x)
-                cs.constrain(l1 - l2);
+                // At least one is true: `a * b == 0`.
+                let (_l, _r, o) = cs.multiply(a.clone(), b.clone());
+                let o_assg = a_assg.and_then(|a| b_assg.map(|b| a * b));
 
-                // (3) `x*y == 0` which implies that y == 0 if x != 0.
-                cs.constrain(o1.into());
+                // Not both are true: `a + z*b != 0`, computed with the `is_zero` gadget.
+                let z = cs.challenge_scalar(b"ZkVM.verify.xor-challenge");
+                let ab_assg = a_assg.and_then(|a| b_assg.map(|b| a + z * b));
+                let (y, y_assg) = gadgets::is_zero_lc(cs, a + z * b, ab_assg)?;
 
-                // (4) `x*w == 1 - y` which implies that y == 1 if x == 0.
-                cs.constrain(o2 - Scalar::one() + r1);
+                // Both statements must hold: three multipliers in total.
+                let w = cs.challenge_scalar(b"ZkVM.verify.and-challenge");
+                let assignment = o_assg.and_then(|o| y_assg.map(|y| o + w * y));
+                let (o, y) = (
+                    r1cs::LinearCombination::from(o),
+                    r1cs::LinearCombination::from(y),
+                );
+                Ok((o + w * y, assignment))
+            }
+            Constraint::IfThenElse(cond, c1, c2) => {
+                let (x, x_assg) = cond.flatten(cs)?;
+                let (a, a_assg) = c1.flatten(cs)?;
+                let (b, b_assg) = c2.flatten(cs)?;
 
-                // Note: w (r2) is left unconstrained — it is a free variable.
+                // `y` is zero if the condition is false.
+                let (y, y_assg) = gadgets::is_zero_lc(cs, x.clone(), x_assg)?;
+                let y = r1cs::LinearCombination::from(y);
 
-                Ok((r1cs::LinearCombination::from(r1), y_assg))
+                // (cond AND then) OR (NOT cond AND else): three multipliers in total.
+                let z = cs.challenge_scalar(b"ZkVM.verify.ite-challenge");
+                let l_assg = x_assg.and_then(|x| a_assg.map(|a| x + z * a));
+                let r_assg = y_assg.and_then(|y| b_assg.map(|b| y + z * b));
+                let (_l, _r, o) = cs.multiply(x + z * a, y + z * b);
+                let assignment = l_assg.and_then(|l| r_assg.map(|r| l * r));
+                Ok((r1cs::LinearCombination::from(o), assignment))
+            }
+            Constraint::Range(expr, bitrange) => {
+                // Values out of range leave a non-zero residual, so the constraint is false.
+                let assignment = expr.eval().map(|x| x.to_scalar());
+                spacesuit::bit_decomposition(cs, expr.to_r1cs_lc(), assignment, bitrange)
+            }
+            Constraint::Lt(expr1, expr2) => {
+                // a < b  <=>  b - a - 1 is in range [0, 2^64)
+                let diff = expr2 - expr1 - Expression::constant(1u64);
+                Constraint::Range(diff, BitRange::u64()).flatten(cs)
+            }
+            Constraint::Le(expr1, expr2) => {
+                // a <= b  <=>  b - a is in range [0, 2^64)
+                Constraint::Range(expr2 - expr1, BitRange::u64()).flatten(cs)
             }
         }
     }
@@ -178,6 +549,18 @@ impl Commitment {
         }
     }
 
+    /// Converts the commitments to compressed points.
+    /// Open commitments are computed with the precomputed tables of the fixed-base multiplications,
+    /// and with the `parallel` feature the commitments are converted on all the threads of the rayon pool.
+    /// Closed commitments are returned as is.
+    pub fn to_points(commitments: &[Commitment]) -> Vec<CompressedRistretto> {
+        #[cfg(feature = "parallel")]
+        let iter = commitments.par_iter();
+        #[cfg(not(feature = "parallel"))]
+        let iter = commitments.iter();
+        iter.map(|c| c.to_point()).collect()
+    }
+
     /// Encodes the commitment as a point.
     pub(crate) fn encode(&self, buf: &mut Vec<u8>) {
         encoding::write_point(&self.to_point(), buf);
@@ -192,11 +575,17 @@ impl Commitment {
     }
 
     /// Creates an open commitment with a random blinding factor.
+    #[cfg(feature = "std")]
     pub fn blinded<T: Into<ScalarWitness>>(x: T) -> Self {
-        Commitment::Open(Box::new(CommitmentWitness {
-            blinding: Scalar::random(&mut rand::thread_rng()),
-            value: x.into(),
-        }))
+        Commitment::blinded_with_rng(x, &mut rand::thread_rng())
+    }
+
+    /// Creates an open commitment with a blinding factor sampled from the provided RNG.
+    pub fn blinded_with_rng<T: Into<ScalarWitness>, R: RngCore + CryptoRng>(
+        x: T,
+        rng: &mut R,
+    ) -> Self {
+        Commitment::blinded_with_factor(x, Scalar::random(rng))
     }
 
     /// Creates an open commitment with a specified blinding factor.
@@ -224,12 +613,66 @@ impl Commitment {
             Commitment::Open(w) => Some(w.value),
         }
     }
+
+    /// Returns true if the commitment opens to a given value and blinding factor.
+    pub fn verify_opening<T: Into<ScalarWitness>>(&self, value: T, blinding: Scalar) -> bool {
+        self.opening_op(value.into(), blinding).verify().is_ok()
+    }
+
+    /// Returns true if every commitment opens to its value and blinding factor.
+    /// The entire batch is checked with a single multi-scalar multiplication.
+    pub fn verify_openings(openings: &[(Commitment, ScalarWitness, Scalar)]) -> bool {
+        let ops = openings
+            .iter()
+            .map(|(c, value, blinding)| c.opening_op(*value, *blinding))
+            .collect::<Vec<_>>();
+        PointOp::verify_batch(&ops).is_ok()
+    }
+
+    /// Point operation for the opening: `0 == value·B + blinding·B2 - C`.
+    fn opening_op(&self, value: ScalarWitness, blinding: Scalar) -> PointOp {
+        PointOp {
+            primary: Some(value.to_scalar()),
+            secondary: Some(blinding),
+            arbitrary: vec![(-Scalar::one(), self.to_point())],
+        }
+    }
 }
 
 impl CommitmentWitness {
+    /// Creates a witness for a given value and blinding factor.
+    pub fn new<T: Into<ScalarWitness>>(value: T, blinding: Scalar) -> Self {
+        CommitmentWitness {
+            value: value.into(),
+            blinding,
+        }
+    }
+
+    /// Returns the committed scalar or integer.
+    pub fn value(&self) -> ScalarWitness {
+        self.value
+    }
+
+    /// Returns the blinding factor.
+    pub fn blinding(&self) -> Scalar {
+        self.blinding
+    }
+
     fn to_point(&self) -> CompressedRistretto {
-        let gens = PedersenGens::default();
-        gens.commit(self.value.into(), self.blinding).compress()
+        gens::commit(&self.value.into(), &self.blinding).compress()
+    }
+}
+
+impl Zeroize for CommitmentWitness {
+    fn zeroize(&mut self) {
+        self.value.zeroize();
+        self.blinding.zeroize();
+    }
+}
+
+impl Drop for CommitmentWitness {
+    fn drop(&mut self) {
+        self.zeroize();
     }
 }
 
@@ -242,40 +685,18 @@ impl Expression {
     /// Multiplies two expressions by constraining them to the left/right wires
     /// of a newly allocated R1CS multiplier, and returns
     /// the output wire wrapped in Expression type.
+    /// If either side is a constant, no multiplier is allocated
+    /// and the other side is simply scaled (see `Mul<ScalarWitness>`).
     ///
     /// Note: we can't implement this as a `Mul` trait because we have to pass in a
     /// ConstraintSystem, because the `LinearCombination * LinearCombination` case
     /// requires the creation of a multiplier in the constraint system.
     pub fn multiply<CS: r1cs::ConstraintSystem>(self, rhs: Self, cs: &mut CS) -> Self {
         match (self, rhs) {
-            // Constant * Constant
-            (Expression::Constant(left), Expression::Constant(right)) => {
-                Expression::Constant(left * right)
-            }
-            // Constant * LinearCombination
-            (
-                Expression::Constant(l),
-                Expression::LinearCombination(mut right_terms, right_assignment),
-            ) => {
-                // Multiply coefficients in right_terms by l,
-                // Multiply assignment in right_assignment by l
-                for (_, n) in right_terms.iter_mut() {
-                    *n = *n * l.to_scalar();
-                }
-                Expression::LinearCombination(right_terms, right_assignment.map(|r| r * l))
-            }
-            // LinearCombination * Constant
-            (
-                Expression::LinearCombination(mut left_terms, left_assignment),
-                Expression::Constant(r),
-            ) => {
-                // Multiply coefficients in left_terms by r,
-                // Multiply assignment in left_assignment by r
-                for (_, n) in left_terms.iter_mut() {
-                    *n = *n * r.to_scalar();
-                }
-                Expression::LinearCombination(left_terms, left_assignment.map(|l| l * r))
-            }
+            // Constant * Expression
+            (Expression::Constant(l), right) => right * l,
+            // Expression * Constant
+            (left, Expression::Constant(r)) => left * r,
             // LinearCombination * LinearCombination
             // Creates a multiplication gate in r1cs
             (
@@ -295,14 +716,114 @@ impl Expression {
         }
     }
 
-    pub(crate) fn to_r1cs_lc(&self) -> r1cs::LinearCombination {
+    /// Wraps an R1CS variable allocated outside of ZkVM (e.g. by a gadget library)
+    /// into an expression, so it can be combined with the expressions of the VM.
+    /// The assignment must be the value assigned to the variable in the prover's
+    /// constraint system, and `None` in the verifier's.
+    /// The constant variable `r1cs::Variable::One()` becomes the constant 1.
+    pub fn from_r1cs_variable(var: r1cs::Variable, assignment: Option<ScalarWitness>) -> Self {
+        match var {
+            r1cs::Variable::One() => Expression::constant(1u64),
+            var => Expression::LinearCombination(vec![(var, Scalar::one())], assignment),
+        }
+    }
+
+    /// Allocates a low-level R1CS variable with the assignment (`None` in the verifier)
+    /// and returns it as an expression carrying the same assignment.
+    /// The variable is not constrained: the caller must constrain it with other expressions.
+    pub fn allocate<CS: r1cs::ConstraintSystem>(
+        cs: &mut CS,
+        assignment: Option<ScalarWitness>,
+    ) -> Result<Self, VMError> {
+        let var = cs
+            .allocate(assignment.map(|a| a.to_scalar()))
+            .map_err(VMError::R1CSError)?;
+        Ok(Expression::LinearCombination(
+            vec![(var, Scalar::one())],
+            assignment,
+        ))
+    }
+
+    /// Converts the expression to the linear combination of the R1CS variables,
+    /// to constrain it with the raw constraint system or pass it to a gadget.
+    pub fn to_r1cs_lc(&self) -> r1cs::LinearCombination {
         match self {
             Expression::Constant(a) => a.to_scalar().into(),
             Expression::LinearCombination(terms, _) => r1cs::LinearCombination::from_iter(terms),
         }
     }
 
-    fn eval(&self) -> Option<ScalarWitness> {
+    /// Computes the sum of the expressions as a single linear combination
+    /// in which each variable appears only once.
+    /// Returns a constant if all the expressions are constants.
+    pub fn sum<I>(exprs: I) -> Expression
+    where
+        I: IntoIterator<Item = Expression>,
+    {
+        let mut constant = ScalarWitness::from(0u64);
+        let mut terms = Vec::new();
+        let mut assignment = Some(ScalarWitness::from(0u64));
+        let mut is_constant = true;
+        for expr in exprs {
+            match expr {
+                Expression::Constant(c) => {
+                    constant = constant + c;
+                    assignment = assignment.map(|a| a + c);
+                }
+                Expression::LinearCombination(t, a) => {
+                    is_constant = false;
+                    terms.extend(t);
+                    assignment = assignment.and_then(|sum| a.map(|a| sum + a));
+                }
+            }
+        }
+        if is_constant {
+            return Expression::Constant(constant);
+        }
+        terms.push((r1cs::Variable::One(), constant.to_scalar()));
+        Expression::LinearCombination(merge_terms(terms), assignment)
+    }
+
+    /// Computes the weighted sum of the expressions `sum{exprs[i]·weights[i]}`
+    /// as a single linear combination in which each variable appears only once.
+    ///
+    /// Panics if the numbers of expressions and weights are different.
+    pub fn dot(exprs: &[Expression], weights: &[Scalar]) -> Expression {
+        assert_eq!(
+            exprs.len(),
+            weights.len(),
+            "Each expression must have a weight"
+        );
+        Self::sum(
+            exprs
+                .iter()
+                .zip(weights.iter())
+                .map(|(e, w)| e.clone() * *w),
+        )
+    }
+
+    /// Adds up the coefficients of the same variables and removes the terms with zero coefficients.
+    /// Sums of expressions are compacted automatically.
+    pub fn compact(self) -> Expression {
+        match self {
+            Expression::LinearCombination(terms, assignment) => {
+                Expression::LinearCombination(merge_terms(terms), assignment)
+            }
+            constant => constant,
+        }
+    }
+
+    /// Returns true if both expressions have the same terms, regardless of their assignments.
+    fn same_terms(&self, other: &Expression) -> bool {
+        match (self, other) {
+            (Expression::Constant(a), Expression::Constant(b)) => a.to_scalar() == b.to_scalar(),
+            (Expression::LinearCombination(a, _), Expression::LinearCombination(b, _)) => a == b,
+            (_, _) => false,
+        }
+    }
+
+    /// Returns the value of the expression, if it is known to the prover.
+    pub fn eval(&self) -> Option<ScalarWitness> {
         match self {
             Expression::Constant(a) => Some(*a),
             Expression::LinearCombination(_, a) => match a {
@@ -311,6 +832,28 @@ impl Expression {
             },
         }
     }
+
+    /// Returns the value of the expression, computing it from the assignments
+    /// of the R1CS variables if the expression does not carry its own assignment.
+    /// Returns `None` if any variable in the linear combination has no assignment.
+    pub fn eval_with<F>(&self, assignment: F) -> Option<ScalarWitness>
+    where
+        F: Fn(r1cs::Variable) -> Option<Scalar>,
+    {
+        match self {
+            Expression::LinearCombination(terms, None) => terms
+                .iter()
+                .try_fold(Scalar::zero(), |sum, (var, coeff)| {
+                    let value = match var {
+                        r1cs::Variable::One() => Scalar::one(),
+                        var => assignment(*var)?,
+                    };
+                    Some(sum + coeff * value)
+                })
+                .map(ScalarWitness::Scalar),
+            _ => self.eval(),
+        }
+    }
 }
 
 impl Neg for Expression {
@@ -339,28 +882,29 @@ impl Add for Expression {
             }
             (
                 Expression::Constant(l),
-                Expression::LinearCombination(mut right_terms, right_assignment),
+                Expression::LinearCombination(right_terms, right_assignment),
             ) => {
                 // prepend constant term to `term vector` in non-constant expression
-                right_terms.insert(0, (r1cs::Variable::One(), l.into()));
-                Expression::LinearCombination(right_terms, right_assignment.map(|r| l + r))
+                let terms = iter::once((r1cs::Variable::One(), l.into())).chain(right_terms);
+                Expression::LinearCombination(merge_terms(terms), right_assignment.map(|r| l + r))
             }
             (
-                Expression::LinearCombination(mut left_terms, left_assignment),
+                Expression::LinearCombination(left_terms, left_assignment),
                 Expression::Constant(r),
             ) => {
                 // append constant term to term vector in non-constant expression
-                left_terms.push((r1cs::Variable::One(), r.into()));
-                Expression::LinearCombination(left_terms, left_assignment.map(|l| l + r))
+                let terms = left_terms
+                    .into_iter()
+                    .chain(iter::once((r1cs::Variable::One(), r.into())));
+                Expression::LinearCombination(merge_terms(terms), left_assignment.map(|l| l + r))
             }
             (
-                Expression::LinearCombination(mut left_terms, left_assignment),
+                Expression::LinearCombination(left_terms, left_assignment),
                 Expression::LinearCombination(right_terms, right_assignment),
             ) => {
-                // append right terms to left terms in non-constant expression
-                left_terms.extend(right_terms);
+                // append right terms to left terms, merging the terms with the same variables
                 Expression::LinearCombination(
-                    left_terms,
+                    merge_terms(left_terms.into_iter().chain(right_terms)),
                     left_assignment.and_then(|l| right_assignment.map(|r| l + r)),
                 )
             }
@@ -368,6 +912,87 @@ impl Add for Expression {
     }
 }
 
+impl Sub for Expression {
+    type Output = Expression;
+
+    fn sub(self, rhs: Expression) -> Expression {
+        // Constant folding and assignment propagation are handled by `Neg` and `Add`.
+        self + (-rhs)
+    }
+}
+
+impl<'a> Sub<&'a Expression> for Expression {
+    type Output = Expression;
+
+    fn sub(self, rhs: &'a Expression) -> Expression {
+        self - rhs.clone()
+    }
+}
+
+impl<'a, 'b> Sub<&'b Expression> for &'a Expression {
+    type Output = Expression;
+
+    fn sub(self, rhs: &'b Expression) -> Expression {
+        self.clone() - rhs.clone()
+    }
+}
+
+impl Mul<ScalarWitness> for Expression {
+    type Output = Expression;
+
+    fn mul(self, rhs: ScalarWitness) -> Expression {
+        match self {
+            Expression::Constant(a) => Expression::Constant(a * rhs),
+            Expression::LinearCombination(mut terms, assignment) => {
+                // Multiply coefficients in terms by rhs,
+                // Multiply assignment by rhs
+                let factor = rhs.to_scalar();
+                for (_, n) in terms.iter_mut() {
+                    *n = *n * factor;
+                }
+                Expression::LinearCombination(terms, assignment.map(|a| a * rhs))
+            }
+        }
+    }
+}
+
+impl Mul<Scalar> for Expression {
+    type Output = Expression;
+
+    fn mul(self, rhs: Scalar) -> Expression {
+        self * ScalarWitness::Scalar(rhs)
+    }
+}
+
+/// Adds up the coefficients of the same variables and removes the terms with zero coefficients.
+/// The terms keep the order of the first occurrence of their variables.
+fn merge_terms<I>(terms: I) -> Vec<(r1cs::Variable, Scalar)>
+where
+    I: IntoIterator<Item = (r1cs::Variable, Scalar)>,
+{
+    let mut merged: Vec<(r1cs::Variable, Scalar)> = Vec::new();
+    // Positions of the variables in the list of merged terms.
+    let mut positions: HashMap<(u8, usize), usize> = HashMap::new();
+    for (var, coeff) in terms {
+        let key = match var {
+            r1cs::Variable::Committed(i) => (0, i),
+            r1cs::Variable::MultiplierLeft(i) => (1, i),
+            r1cs::Variable::MultiplierRight(i) => (2, i),
+            r1cs::Variable::MultiplierOutput(i) => (3, i),
+            r1cs::Variable::One() => (4, 0),
+        };
+        match positions.get(&key) {
+            Some(&i) => merged[i].1 += coeff,
+            None => {
+                positions.insert(key, merged.len());
+                merged.push((var, coeff));
+            }
+        }
+    }
+    merged.retain(|(_, coeff)| *coeff != Scalar::zero());
+    merged
+}
+
 // Upcasting witness/points into Commitment
 
 impl From<CommitmentWitness> for Commitment {
@@ -387,3 +1012,663 @@ impl Into<CompressedRistretto> for Commitment {
         self.to_point()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bulletproofs::{BulletproofGens, PedersenGens};
+    use merlin::Transcript;
+
+    /// Commits `value` in a prover and a verifier, builds a constraint for it
+    /// and checks the resulting R1CS proof.
+    fn prove_and_verify<F>(value: u64, make_constraint: F) -> Result<(), VMError>
+    where
+        F: Fn(Expression) -> Constraint,
+    {
+        prove_with_assignment(value, value, make_constraint)
+    }
+
+    /// Commits `value` like `prove_and_verify`, but the prover builds the constraint
+    /// with a different `assignment`, as a malicious prover would.
+    fn prove_with_assignment<F>(
+        value: u64,
+        assignment: u64,
+        make_constraint: F,
+    ) -> Result<(), VMError>
+    where
+        F: Fn(Expression) -> Constraint,
+    {
+        let pc_gens = PedersenGens::default();
+        let bp_gens = BulletproofGens::new(256, 1);
+
+        let (proof, commitment) = {
+            let mut transcript = Transcript::new(b"ConstraintsTest");
+            let mut prover = r1cs::Prover::new(&bp_gens, &pc_gens, &mut transcript);
+            let (com, var) = prover.commit(Scalar::from(value), Scalar::from(1u64));
+            let expr =
+                Expression::LinearCombination(vec![(var, Scalar::one())], Some(assignment.into()));
+            make_constraint(expr).verify(&mut prover)?;
+            (prover.prove().map_err(VMError::R1CSError)?, com)
+        };
+
+        let mut transcript = Transcript::new(b"ConstraintsTest");
+        let mut verifier = r1cs::Verifier::new(&bp_gens, &pc_gens, &mut transcript);
+        let var = verifier.commit(commitment);
+        let expr = Expression::LinearCombination(vec![(var, Scalar::one())], None);
+        make_constraint(expr).verify(&mut verifier)?;
+        verifier.verify(&proof).map_err(VMError::R1CSError)
+    }
+
+    #[test]
+    fn verify_opening() {
+        let blinding = Scalar::from(7u64);
+        let open = Commitment::blinded_with_factor(100u64, blinding);
+        let closed = Commitment::Closed(open.to_point());
+
+        assert!(open.verify_opening(100u64, blinding));
+        assert!(closed.verify_opening(100u64, blinding));
+        assert!(!closed.verify_opening(101u64, blinding));
+        assert!(!closed.verify_opening(100u64, Scalar::from(8u64)));
+
+        let negative = Commitment::blinded_with_factor(-ScalarWitness::from(5u64), blinding);
+        assert!(negative.verify_opening(-ScalarWitness::from(5u64), blinding));
+        assert!(!negative.verify_opening(5u64, blinding));
+    }
+
+    #[test]
+    fn blinded_with_rng() {
+        use rand::{rngs::StdRng, SeedableRng};
+
+        let c1 = Commitment::blinded_with_rng(100u64, &mut StdRng::seed_from_u64(1));
+        let c2 = Commitment::blinded_with_rng(100u64, &mut StdRng::seed_from_u64(1));
+        let c3 = Commitment::blinded_with_rng(100u64, &mut StdRng::seed_from_u64(2));
+        assert_eq!(c1.to_point(), c2.to_point());
+        assert_ne!(c1.to_point(), c3.to_point());
+    }
+
+    #[test]
+    fn verify_openings() {
+        let openings = (0..5u64)
+            .map(|i| {
+                let c = Commitment::blinded(i);
+                let (value, blinding) = c.witness().unwrap();
+                (Commitment::Closed(c.to_point()), value, blinding)
+            })
+            .collect::<Vec<_>>();
+        assert!(Commitment::verify_openings(&openings));
+        assert!(Commitment::verify_openings(&[]));
+
+        let mut invalid = openings.clone();
+        invalid[3].1 = ScalarWitness::from(100u64);
+        assert!(!Commitment::verify_openings(&invalid));
+    }
+
+    fn bits(n: usize) -> BitRange {
+        BitRange::new(n).unwrap()
+    }
+
+    #[test]
+    fn range_constraint() {
+        assert!(prove_and_verify(0, |x| Constraint::Range(x, bits(8))).is_ok());
+        assert!(prove_and_verify(255, |x| Constraint::Range(x, bits(8))).is_ok());
+        assert!(prove_and_verify(256, |x| Constraint::Range(x, bits(8))).is_err());
+        assert!(prove_and_verify(u64::max_value(), |x| Constraint::Range(x, bits(64))).is_ok());
+    }
+
+    #[test]
+    fn composed_range_constraint() {
+        // The prover could choose the bits to make any range constraint false.
+        let not_in_range = |x| Constraint::Not(Box::new(Constraint::Range(x, bits(8))));
+        assert_eq!(
+            prove_and_verify(256, not_in_range).unwrap_err(),
+            VMError::NegatedRange
+        );
+        let in_range_xor_eq = |x: Expression| {
+            Constraint::Xor(
+                Box::new(Constraint::Range(x.clone(), bits(4))),
+                Box::new(Constraint::Eq(x, Expression::constant(100u64))),
+            )
+        };
+        assert_eq!(
+            prove_and_verify(100, in_range_xor_eq).unwrap_err(),
+            VMError::NegatedRange
+        );
+
+        let in_range_or_eq = |x: Expression| {
+            Constraint::Or(
+                Box::new(Constraint::Range(x.clone(), bits(4))),
+                Box::new(Constraint::Eq(x, Expression::constant(100u64))),
+            )
+        };
+        assert!(prove_and_verify(15, in_range_or_eq).is_ok());
+        assert!(prove_and_verify(100, in_range_or_eq).is_ok());
+        assert!(prove_and_verify(16, in_range_or_eq).is_err());
+    }
+
+    #[test]
+    fn xor_constraint() {
+        let lt_16_xor_le_20 = |x: Expression| {
+            Constraint::Xor(
+                Box::new(Constraint::Lt(x.clone(), Expression::constant(16u64))),
+                Box::new(Constraint::Le(x, Expression::constant(20u64))),
+            )
+        };
+        assert!(prove_and_verify(18, lt_16_xor_le_20).is_ok());
+        assert!(prove_and_verify(3, lt_16_xor_le_20).is_err());
+        assert!(prove_and_verify(25, lt_16_xor_le_20).is_err());
+
+        let eq_100_xor_lt_16 = |x: Expression| {
+            Constraint::Xor(
+                Box::new(Constraint::Eq(x.clone(), Expression::constant(100u64))),
+                Box::new(Constraint::Lt(x, Expression::constant(16u64))),
+            )
+        };
+        assert!(prove_and_verify(100, eq_100_xor_lt_16).is_ok());
+        assert!(prove_and_verify(3, eq_100_xor_lt_16).is_ok());
+        assert!(prove_and_verify(20, eq_100_xor_lt_16).is_err());
+
+        let eq_1_xor_eq_2 = |x: Expression| {
+            Constraint::Xor(
+                Box::new(Constraint::Eq(x.clone(), Expression::constant(1u64))),
+                Box::new(Constraint::Eq(x, Expression::constant(2u64))),
+            )
+        };
+        assert!(prove_and_verify(2, eq_1_xor_eq_2).is_ok());
+        assert!(prove_and_verify(3, eq_1_xor_eq_2).is_err());
+    }
+
+    #[test]
+    fn if_then_else_constraint() {
+        let eq = |x: &Expression, value: u64| {
+            Box::new(Constraint::Eq(x.clone(), Expression::constant(value)))
+        };
+        let if_lt_10_then_5_else_100 = |x: Expression| {
+            Constraint::IfThenElse(
+                Box::new(Constraint::Lt(x.clone(), Expression::constant(10u64))),
+                eq(&x, 5),
+                eq(&x, 100),
+            )
+        };
+        assert!(prove_and_verify(5, if_lt_10_then_5_else_100).is_ok());
+        assert!(prove_and_verify(100, if_lt_10_then_5_else_100).is_ok());
+        assert!(prove_and_verify(6, if_lt_10_then_5_else_100).is_err());
+        assert!(prove_and_verify(101, if_lt_10_then_5_else_100).is_err());
+
+        // The condition with an inequality is never negated.
+        let cond = Constraint::Lt(var(0, None), var(1, None));
+        assert!(optimizes_to(
+            Constraint::IfThenElse(
+                Box::new(cond.clone()),
+                Box::new(is_zero(2)),
+                Box::new(is_zero(3))
+            ),
+            or(
+                and(cond, is_zero(2)),
+                and(Constraint::Le(var(1, None), var(0, None)), is_zero(3))
+            )
+        ));
+    }
+
+    #[test]
+    fn negate_inequalities() {
+        let lt = Constraint::Lt(var(0, None), var(1, None));
+        assert!(optimizes_to(
+            not(and(lt.clone(), is_zero(2))),
+            or(Constraint::Le(var(1, None), var(0, None)), not(is_zero(2)))
+        ));
+        assert!(optimizes_to(
+            Constraint::Xor(Box::new(lt.clone()), Box::new(is_zero(2))),
+            or(
+                and(lt, not(is_zero(2))),
+                and(Constraint::Le(var(1, None), var(0, None)), is_zero(2))
+            )
+        ));
+    }
+
+    fn var(i: usize, assignment: Option<u64>) -> Expression {
+        Expression::LinearCombination(
+            vec![(r1cs::Variable::Committed(i), Scalar::one())],
+            assignment.map(|x| x.into()),
+        )
+    }
+
+    fn is_zero(i: usize) -> Constraint {
+        Constraint::Eq(var(i, None), Expression::constant(0u64))
+    }
+
+    fn not(c: Constraint) -> Constraint {
+        Constraint::Not(Box::new(c))
+    }
+
+    fn and(a: Constraint, b: Constraint) -> Constraint {
+        Constraint::And(Box::new(a), Box::new(b))
+    }
+
+    fn or(a: Constraint, b: Constraint) -> Constraint {
+        Constraint::Or(Box::new(a), Box::new(b))
+    }
+
+    fn known(c: Constraint) -> Option<bool> {
+        match c.optimize() {
+            Optimized::Known(b) => Some(b),
+            Optimized::Constraint(_) => None,
+        }
+    }
+
+    fn optimizes_to(c: Constraint, expected: Constraint) -> bool {
+        match c.optimize() {
+            Optimized::Known(_) => false,
+            Optimized::Constraint(c) => c.same_as(&expected),
+        }
+    }
+
+    #[test]
+    fn optimize_constants() {
+        let t = Constraint::Eq(Expression::constant(1u64), Expression::constant(1u64));
+        let f = Constraint::Lt(Expression::constant(2u64), Expression::constant(1u64));
+        assert_eq!(known(t.clone()), Some(true));
+        assert_eq!(known(f.clone()), Some(false));
+        assert_eq!(known(or(f.clone(), t.clone())), Some(true));
+        assert_eq!(known(and(is_zero(0), f.clone())), Some(false));
+        assert_eq!(known(not(f.clone())), Some(true));
+        assert_eq!(
+            known(Constraint::Range(Expression::constant(256u64), bits(8))),
+            Some(false)
+        );
+        assert!(optimizes_to(and(t.clone(), is_zero(0)), is_zero(0)));
+        assert!(optimizes_to(
+            Constraint::IfThenElse(Box::new(is_zero(0)), Box::new(t), Box::new(is_zero(1))),
+            or(is_zero(0), is_zero(1))
+        ));
+    }
+
+    #[test]
+    fn optimize_negations_and_duplicates() {
+        assert!(optimizes_to(not(not(is_zero(0))), is_zero(0)));
+
+        // Duplicates are found regardless of the assignments.
+        let assigned = Constraint::Eq(var(0, Some(5)), Expression::constant(0u64));
+        assert!(optimizes_to(
+            or(is_zero(0), or(is_zero(1), assigned)),
+            or(is_zero(0), is_zero(1))
+        ));
+        assert_eq!(
+            known(Constraint::Xor(Box::new(is_zero(0)), Box::new(is_zero(0)))),
+            Some(false)
+        );
+
+        // De Morgan's laws leave a single negation.
+        assert!(optimizes_to(
+            or(not(is_zero(0)), not(is_zero(1))),
+            not(and(is_zero(0), is_zero(1)))
+        ));
+        assert!(optimizes_to(
+            and(not(is_zero(0)), and(is_zero(2), not(is_zero(1)))),
+            and(is_zero(2), not(or(is_zero(0), is_zero(1))))
+        ));
+
+        // Negated condition swaps the branches.
+        assert!(optimizes_to(
+            Constraint::IfThenElse(
+                Box::new(not(is_zero(0))),
+                Box::new(is_zero(1)),
+                Box::new(is_zero(2))
+            ),
+            Constraint::IfThenElse(
+                Box::new(is_zero(0)),
+                Box::new(is_zero(2)),
+                Box::new(is_zero(1))
+            )
+        ));
+    }
+
+    #[test]
+    fn optimize_inequalities() {
+        let lt = Constraint::Lt(var(0, None), var(1, None));
+        let le = Constraint::Le(var(0, None), var(1, None));
+        assert!(optimizes_to(
+            not(lt.clone()),
+            Constraint::Le(var(1, None), var(0, None))
+        ));
+        assert!(optimizes_to(
+            not(le.clone()),
+            Constraint::Lt(var(1, None), var(0, None))
+        ));
+
+        // Inequalities are not moved under a negation by De Morgan's laws.
+        assert!(optimizes_to(
+            or(not(lt), not(le)),
+            or(
+                Constraint::Le(var(1, None), var(0, None)),
+                Constraint::Lt(var(1, None), var(0, None))
+            )
+        ));
+        assert!(optimizes_to(
+            and(
+                not(is_zero(0)),
+                not(Constraint::Range(var(1, None), bits(8)))
+            ),
+            and(
+                not(is_zero(0)),
+                not(Constraint::Range(var(1, None), bits(8)))
+            )
+        ));
+    }
+
+    #[test]
+    fn optimized_constraint_proof() {
+        // Rewritten into `or(100 <= x, 200 < x)`.
+        let not_lt_100_or_not_le_200 = |x: Expression| {
+            or(
+                not(Constraint::Lt(x.clone(), Expression::constant(100u64))),
+                not(Constraint::Le(x, Expression::constant(200u64))),
+            )
+        };
+        assert!(prove_and_verify(150, not_lt_100_or_not_le_200).is_ok());
+        assert!(prove_and_verify(300, not_lt_100_or_not_le_200).is_ok());
+        assert!(prove_and_verify(50, not_lt_100_or_not_le_200).is_err());
+    }
+
+    #[test]
+    fn malicious_assignment() {
+        // The prover decomposes the wrong assignment into bits, which does not prove
+        // the statement about the committed value.
+        let in_range = |x| Constraint::Range(x, bits(8));
+        assert!(prove_with_assignment(300, 5, in_range).is_err());
+
+        // Negated inequality `!(x < 10)` is proven as `10 <= x`.
+        let not_lt_10 =
+            |x| Constraint::Not(Box::new(Constraint::Lt(x, Expression::constant(10u64))));
+        assert!(prove_and_verify(20, not_lt_10).is_ok());
+        assert!(prove_and_verify(5, not_lt_10).is_err());
+        assert!(prove_with_assignment(5, 20, not_lt_10).is_err());
+        assert!(prove_with_assignment(5, 300, not_lt_10).is_err());
+    }
+
+    #[test]
+    fn inequality_constraints() {
+        let lt_10 = |x| Constraint::Lt(x, Expression::constant(10u64));
+        assert!(prove_and_verify(0, lt_10).is_ok());
+        assert!(prove_and_verify(9, lt_10).is_ok());
+        assert!(prove_and_verify(10, lt_10).is_err());
+        assert!(prove_and_verify(11, lt_10).is_err());
+
+        let le_10 = |x| Constraint::Le(x, Expression::constant(10u64));
+        assert!(prove_and_verify(10, le_10).is_ok());
+        assert!(prove_and_verify(11, le_10).is_err());
+
+        let gt_10 = |x| Constraint::Lt(Expression::constant(10u64), x);
+        assert!(prove_and_verify(11, gt_10).is_ok());
+        assert!(prove_and_verify(10, gt_10).is_err());
+    }
+
+    #[test]
+    fn external_r1cs_variables() {
+        let pc_gens = PedersenGens::default();
+        let bp_gens = BulletproofGens::new(16, 1);
+
+        // Constrains `x·y == z` with a multiplier allocated by the caller,
+        // and `z == x + 2` using the expressions.
+        fn gadget<CS: r1cs::ConstraintSystem>(
+            cs: &mut CS,
+            x: u64,
+            y: u64,
+            prover: bool,
+        ) -> Result<(), VMError> {
+            let assignments = if prover {
+                Some((Scalar::from(x), Scalar::from(y)))
+            } else {
+                None
+            };
+            let (l, r, o) = cs
+                .allocate_multiplier(assignments)
+                .map_err(VMError::R1CSError)?;
+            let witness = |v: u64| {
+                if prover {
+                    Some(ScalarWitness::from(v))
+                } else {
+                    None
+                }
+            };
+            let x = Expression::from_r1cs_variable(l, witness(x));
+            let y = Expression::from_r1cs_variable(r, witness(y));
+            let z =
+                Expression::from_r1cs_variable(o, x.eval().and_then(|x| y.eval().map(|y| x * y)));
+            let two = Expression::allocate(cs, witness(2))?;
+            cs.constrain((two.clone() - Expression::constant(2u64)).to_r1cs_lc());
+            Constraint::Eq(z, x + two).verify(cs)
+        }
+
+        let prove = |x, y| {
+            let mut transcript = Transcript::new(b"ConstraintsTest");
+            let mut prover = r1cs::Prover::new(&bp_gens, &pc_gens, &mut transcript);
+            gadget(&mut prover, x, y, true)?;
+            prover.prove().map_err(VMError::R1CSError)
+        };
+        let verify = |proof: r1cs::R1CSProof| {
+            let mut transcript = Transcript::new(b"ConstraintsTest");
+            let mut verifier = r1cs::Verifier::new(&bp_gens, &pc_gens, &mut transcript);
+            gadget(&mut verifier, 0, 0, false)?;
+            verifier.verify(&proof).map_err(VMError::R1CSError)
+        };
+
+        assert!(prove(2, 2).and_then(verify).is_ok());
+        assert!(prove(3, 2).and_then(verify).is_err());
+
+        let one = Expression::from_r1cs_variable(r1cs::Variable::One(), None);
+        assert_eq!(one.eval(), Some(ScalarWitness::from(1u64)));
+    }
+
+    #[test]
+    fn eval_with_assignments() {
+        let x = var(0, None) * Scalar::from(2u64) + var(1, None) + Expression::constant(3u64);
+        assert_eq!(x.eval(), None);
+
+        let assignment = |v: r1cs::Variable| match v {
+            r1cs::Variable::Committed(0) => Some(Scalar::from(10u64)),
+            r1cs::Variable::Committed(1) => Some(Scalar::from(4u64)),
+            _ => None,
+        };
+        assert_eq!(
+            x.eval_with(assignment).map(|x| x.to_scalar()),
+            Some(Scalar::from(27u64))
+        );
+
+        // Unknown variable
+        let y = x + var(2, None);
+        assert_eq!(y.eval_with(assignment), None);
+
+        // Expression's own assignment takes precedence.
+        let z = var(0, Some(5));
+        assert_eq!(z.eval_with(assignment), Some(ScalarWitness::from(5u64)));
+    }
+
+    #[test]
+    fn sum_and_dot() {
+        let x = var(0, Some(2));
+        let y = var(1, Some(3));
+        let sum = Expression::sum(vec![
+            x.clone(),
+            y.clone(),
+            Expression::constant(1u64),
+            x.clone(),
+            Expression::constant(4u64),
+        ]);
+        match sum {
+            Expression::LinearCombination(terms, assignment) => {
+                assert_eq!(
+                    terms,
+                    vec![
+                        (r1cs::Variable::Committed(0), Scalar::from(2u64)),
+                        (r1cs::Variable::Committed(1), Scalar::one()),
+                        (r1cs::Variable::One(), Scalar::from(5u64)),
+                    ]
+                );
+                assert_eq!(assignment, Some(ScalarWitness::from(12u64)));
+            }
+            _ => panic!("Sum of linear combinations must produce a linear combination"),
+        }
+
+        let dot = Expression::dot(
+            &[x, y, Expression::constant(1u64)],
+            &[
+                Scalar::from(10u64),
+                Scalar::from(100u64),
+                Scalar::from(7u64),
+            ],
+        );
+        match dot {
+            Expression::LinearCombination(terms, assignment) => {
+                assert_eq!(terms.len(), 3);
+                assert_eq!(
+                    assignment.map(|x| x.to_scalar()),
+                    Some(Scalar::from(327u64))
+                );
+            }
+            _ => panic!("Dot product of linear combinations must produce a linear combination"),
+        }
+
+        match Expression::sum(vec![Expression::constant(1u64), Expression::constant(2u64)]) {
+            Expression::Constant(c) => assert_eq!(c, ScalarWitness::from(3u64)),
+            _ => panic!("Sum of constants must produce a constant"),
+        }
+    }
+
+    #[test]
+    fn compact_terms() {
+        let x = var(0, Some(2));
+        let y = var(1, Some(3));
+
+        // Accumulating the same variables does not grow the expression.
+        let mut acc = Expression::constant(0u64);
+        for _ in 0..10 {
+            acc = acc + x.clone() + y.clone() + Expression::constant(1u64);
+        }
+        match &acc {
+            Expression::LinearCombination(terms, assignment) => {
+                assert_eq!(
+                    terms,
+                    &vec![
+                        (r1cs::Variable::Committed(0), Scalar::from(10u64)),
+                        (r1cs::Variable::Committed(1), Scalar::from(10u64)),
+                        (r1cs::Variable::One(), Scalar::from(10u64)),
+                    ]
+                );
+                assert_eq!(*assignment, Some(ScalarWitness::from(60u64)));
+            }
+            _ => panic!("Sum of linear combinations must produce a linear combination"),
+        }
+
+        // Cancelled terms are removed.
+        match acc - x * Scalar::from(10u64) {
+            Expression::LinearCombination(terms, _) => assert_eq!(terms.len(), 2),
+            _ => panic!("LC - LC must produce a linear combination"),
+        }
+
+        // Explicit compaction of a manually built expression.
+        let raw = Expression::LinearCombination(
+            vec![
+                (r1cs::Variable::Committed(0), Scalar::one()),
+                (r1cs::Variable::Committed(1), Scalar::zero()),
+                (r1cs::Variable::Committed(0), Scalar::one()),
+            ],
+            None,
+        );
+        match raw.compact() {
+            Expression::LinearCombination(terms, _) => assert_eq!(
+                terms,
+                vec![(r1cs::Variable::Committed(0), Scalar::from(2u64))]
+            ),
+            _ => panic!("Compacting a linear combination must produce a linear combination"),
+        }
+    }
+
+    #[test]
+    fn sub_constants() {
+        let e = Expression::constant(10u64) - Expression::constant(3u64);
+        match e {
+            Expression::Constant(c) => assert_eq!(c, ScalarWitness::from(7u64)),
+            _ => panic!("Constant - Constant must produce a constant"),
+        }
+    }
+
+    #[test]
+    fn sub_linear_combinations() {
+        let a = Expression::LinearCombination(
+            vec![(r1cs::Variable::Committed(0), Scalar::one())],
+            Some(ScalarWitness::from(5u64)),
+        );
+        let b = Expression::LinearCombination(
+            vec![(r1cs::Variable::Committed(1), Scalar::one())],
+            Some(ScalarWitness::from(2u64)),
+        );
+        match &a - &b {
+            Expression::LinearCombination(terms, assignment) => {
+                assert_eq!(
+                    terms,
+                    vec![
+                        (r1cs::Variable::Committed(0), Scalar::one()),
+                        (r1cs::Variable::Committed(1), -Scalar::one()),
+                    ]
+                );
+                assert_eq!(assignment, Some(ScalarWitness::from(3u64)));
+            }
+            _ => panic!("LC - LC must produce a linear combination"),
+        }
+    }
+
+    #[test]
+    fn sub_constant_from_linear_combination() {
+        let a = Expression::LinearCombination(
+            vec![(r1cs::Variable::Committed(0), Scalar::one())],
+            None,
+        );
+        match a - Expression::constant(4u64) {
+            Expression::LinearCombination(terms, assignment) => {
+                assert_eq!(terms[1], (r1cs::Variable::One(), -Scalar::from(4u64)));
+                assert_eq!(assignment, None);
+            }
+            _ => panic!("LC - Constant must produce a linear combination"),
+        }
+    }
+
+    #[test]
+    fn mul_by_constant() {
+        let a = Expression::LinearCombination(
+            vec![(r1cs::Variable::Committed(0), Scalar::from(2u64))],
+            Some(ScalarWitness::from(3u64)),
+        );
+        match a * ScalarWitness::from(5u64) {
+            Expression::LinearCombination(terms, assignment) => {
+                assert_eq!(
+                    terms,
+                    vec![(r1cs::Variable::Committed(0), Scalar::from(10u64))]
+                );
+                assert_eq!(assignment, Some(ScalarWitness::from(15u64)));
+            }
+            _ => panic!("LC * Constant must produce a linear combination"),
+        }
+
+        match Expression::constant(6u64) * Scalar::from(7u64) {
+            Expression::Constant(c) => assert_eq!(c.to_scalar(), Scalar::from(42u64)),
+            _ => panic!("Constant * Constant must produce a constant"),
+        }
+    }
+
+    #[test]
+    fn commitments_to_points() {
+        let commitments = (0..100u64)
+            .map(|i| match i % 3 {
+                0 => Commitment::unblinded(i),
+                1 => Commitment::blinded(i),
+                _ => Commitment::Closed(Commitment::blinded(i).to_point()),
+            })
+            .collect::<Vec<_>>();
+        let points = Commitment::to_points(&commitments);
+        assert_eq!(
+            points,
+            commitments.iter().map(|c| c.to_point()).collect::<Vec<_>>()
+        );
+        assert!(Commitment::to_points(&[]).is_empty());
+    }
+}
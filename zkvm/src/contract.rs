@@ -1,10 +1,14 @@
 use merlin::Transcript;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 use crate::constraints::Commitment;
 use crate::encoding;
 use crate::encoding::SliceReader;
 use crate::errors::VMError;
+use crate::merkle::MerkleItem;
 use crate::predicate::Predicate;
+use crate::transcript::labels;
 use crate::types::{Data, Value};
 
 /// Prefix for the data type in the Output Structure
@@ -18,11 +22,12 @@ pub const VALUE_TYPE: u8 = 0x01;
 pub struct Anchor([u8; 32]);
 
 /// A unique identifier for a contract.
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
 pub struct ContractID([u8; 32]);
 
 /// A ZkVM contract that holds a _payload_ (a list of portable items) protected by a _predicate_.
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Contract {
     /// Anchor string which makes the contract unique.
     pub anchor: Anchor,
@@ -36,6 +41,7 @@ pub struct Contract {
 
 /// Representation of items that can be stored within outputs and contracts.
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum PortableItem {
     /// Plain data payload
     Data(Data),
@@ -54,9 +60,7 @@ pub struct Output {
 impl Output {
     /// Creates an Output with a given contract
     pub fn new(contract: Contract) -> Self {
-        let mut buf = Vec::with_capacity(contract.serialized_length());
-        contract.encode(&mut buf);
-        let id = ContractID::from_serialized_contract(&buf);
+        let id = contract.id();
         Self { id, contract }
     }
 
@@ -65,6 +69,11 @@ impl Output {
         self.id
     }
 
+    /// Returns the contract stored in the output
+    pub fn contract(&self) -> &Contract {
+        &self.contract
+    }
+
     /// Converts output to a contract and also returns its precomputed ID
     pub fn into_contract(self) -> (Contract, ContractID) {
         (self.contract, self.id)
@@ -85,17 +94,58 @@ impl Output {
         let (contract, id) = Contract::decode(output)?;
         Ok(Self { contract, id })
     }
+
+    /// Parses an output from a byte array produced by `encode`.
+    pub fn from_bytes(data: &[u8]) -> Result<Self, VMError> {
+        SliceReader::parse(data, |r| Self::decode(r))
+    }
 }
 
+// Output is serialized as its contract: the contract ID is recomputed when deserializing.
+#[cfg(feature = "serde")]
+impl Serialize for Output {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.contract.serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for Output {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Contract::deserialize(deserializer).map(Output::new)
+    }
+}
+
+#[cfg(feature = "serde")]
+serialize_bytes!(Anchor, "a 32-byte anchor", |a| a.0, |bytes| {
+    SliceReader::parse(bytes, |r| r.read_u8x32()).map(Anchor)
+});
+
+#[cfg(feature = "serde")]
+serialize_bytes!(ContractID, "a 32-byte contract ID", |id| id.0, |bytes| {
+    SliceReader::parse(bytes, |r| r.read_u8x32()).map(ContractID)
+});
+
 impl Anchor {
     /// Provides a view into the anchor’s bytes.
     pub fn as_bytes(&self) -> &[u8] {
         &self.0
     }
 
+    /// Wraps the anchor bytes, e.g. when decoding a transaction log.
+    pub(crate) fn from_raw_bytes(bytes: [u8; 32]) -> Self {
+        Anchor(bytes)
+    }
+
     /// Computes a nonce anchor from its components
     pub fn nonce(blockid: [u8; 32], predicate: &Predicate, maxtime: u64) -> Self {
-        let mut t = Transcript::new(b"ZkVM.nonce");
+        let mut t = Transcript::new(labels::NONCE);
         t.commit_bytes(b"blockid", &blockid);
         t.commit_bytes(b"predicate", predicate.to_point().as_bytes());
         t.commit_u64(b"maxtime", maxtime);
@@ -106,7 +156,7 @@ impl Anchor {
 
     /// Ratchet the anchor into a new anchor
     pub fn ratchet(mut self) -> Self {
-        let mut t = Transcript::new(b"ZkVM.ratchet-anchor");
+        let mut t = Transcript::new(labels::RATCHET_ANCHOR);
         t.commit_bytes(b"old", &self.0);
         t.challenge_bytes(b"new", &mut self.0);
         self
@@ -119,8 +169,14 @@ impl ContractID {
         &self.0
     }
 
+    /// Wraps the contract ID bytes, e.g. when decoding a transaction log
+    /// or a contract ID received from a client.
+    pub fn from_raw_bytes(bytes: [u8; 32]) -> Self {
+        ContractID(bytes)
+    }
+
     fn from_serialized_contract(bytes: &[u8]) -> Self {
-        let mut t = Transcript::new(b"ZkVM.contractid");
+        let mut t = Transcript::new(labels::CONTRACT_ID);
         t.commit_bytes(b"contract", bytes);
         let mut id = [0u8; 32];
         t.challenge_bytes(b"id", &mut id);
@@ -133,17 +189,47 @@ impl ContractID {
     }
 }
 
+impl MerkleItem for ContractID {
+    fn commit(&self, t: &mut Transcript) {
+        t.commit_bytes(b"contract", &self.0);
+    }
+}
+
 impl PortableItem {
+    /// Returns the type prefix of the item: `DATA_TYPE` or `VALUE_TYPE`
+    pub fn item_type(&self) -> u8 {
+        match self {
+            PortableItem::Data(_) => DATA_TYPE,
+            PortableItem::Value(_) => VALUE_TYPE,
+        }
+    }
+
+    /// Returns the data if the item is a data item
+    pub fn as_data(&self) -> Option<&Data> {
+        match self {
+            PortableItem::Data(d) => Some(d),
+            PortableItem::Value(_) => None,
+        }
+    }
+
+    /// Returns the value if the item is a value item
+    pub fn as_value(&self) -> Option<&Value> {
+        match self {
+            PortableItem::Data(_) => None,
+            PortableItem::Value(v) => Some(v),
+        }
+    }
+
     /// Precise length of a serialized payload item
-    fn serialized_length(&self) -> usize {
+    pub fn serialized_length(&self) -> usize {
         match self {
             PortableItem::Data(d) => 1 + 4 + d.serialized_length(),
             PortableItem::Value(_) => 1 + 64,
         }
     }
 
-    /// Serializes the contract to a byte array
-    fn encode(&self, buf: &mut Vec<u8>) {
+    /// Serializes the payload item to a byte array
+    pub fn encode(&self, buf: &mut Vec<u8>) {
         match self {
             // Data = 0x00 || LE32(len) || <bytes>
             PortableItem::Data(d) => {
@@ -178,7 +264,25 @@ impl PortableItem {
 }
 
 impl Contract {
-    fn serialized_length(&self) -> usize {
+    /// Computes the contract ID from the serialized contract
+    pub fn id(&self) -> ContractID {
+        let mut buf = Vec::with_capacity(self.serialized_length());
+        self.encode(&mut buf);
+        ContractID::from_serialized_contract(&buf)
+    }
+
+    /// Iterates over the values in the payload
+    pub fn values(&self) -> impl Iterator<Item = &Value> {
+        self.payload.iter().filter_map(PortableItem::as_value)
+    }
+
+    /// Iterates over the data items in the payload
+    pub fn data(&self) -> impl Iterator<Item = &Data> {
+        self.payload.iter().filter_map(PortableItem::as_data)
+    }
+
+    /// Precise length of a serialized contract
+    pub fn serialized_length(&self) -> usize {
         let mut size = 32 + 32 + 4;
         for item in self.payload.iter() {
             size += item.serialized_length();
@@ -186,7 +290,8 @@ impl Contract {
         size
     }
 
-    fn encode(&self, buf: &mut Vec<u8>) {
+    /// Serializes the contract to a byte array
+    pub fn encode(&self, buf: &mut Vec<u8>) {
         encoding::write_bytes(&self.anchor.0, buf);
         encoding::write_point(&self.predicate.to_point(), buf);
         encoding::write_u32(self.payload.len() as u32, buf);
@@ -228,3 +333,38 @@ impl Contract {
         Ok((contract, id))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn payload_introspection() {
+        let contract = Contract {
+            anchor: Anchor([0u8; 32]),
+            payload: vec![
+                PortableItem::Value(Value {
+                    qty: Commitment::unblinded(10u64),
+                    flv: Commitment::unblinded(0u64),
+                }),
+                PortableItem::Data(Data::Opaque(b"note".to_vec())),
+            ],
+            predicate: Predicate::Opaque(Default::default()),
+        };
+        let types = contract
+            .payload
+            .iter()
+            .map(PortableItem::item_type)
+            .collect::<Vec<_>>();
+        assert_eq!(types, vec![VALUE_TYPE, DATA_TYPE]);
+        assert_eq!(contract.values().count(), 1);
+        assert_eq!(contract.data().count(), 1);
+
+        // The ID computed from the contract matches the ID of the decoded output.
+        let mut buf = Vec::new();
+        contract.encode(&mut buf);
+        let output = SliceReader::parse(&buf, |r| Output::decode(r)).unwrap();
+        assert_eq!(contract.id(), output.id());
+        assert_eq!(output.contract().values().count(), 1);
+    }
+}
@@ -0,0 +1,113 @@
+//! Cost model that bounds the verification work of a transaction.
+//!
+//! Every executed instruction is charged for the multipliers and constraints it allocates
+//! in the constraint system and for the points it adds to the batch verification.
+//! The VM fails as soon as the total cost exceeds `maxcost` specified in the `TxHeader`,
+//! so a node can bound the verification cost of a transaction before verifying its proofs.
+
+use crate::circuit_size::CircuitSize;
+use crate::constraints::Constraint;
+use crate::ops::Instruction;
+
+/// Cost of decoding and dispatching any instruction.
+pub(crate) const INSTRUCTION_COST: u64 = 1;
+
+/// Cost of a linear constraint: scalar operations performed to flatten it.
+pub(crate) const CONSTRAINT_COST: u64 = 1;
+
+/// Cost of a multiplication gate: two generators in the proof verification
+/// and the scalar operations to weight its inputs and output.
+/// Two variables allocated with `alloc` share one gate.
+pub(crate) const MULTIPLIER_COST: u64 = 32;
+
+/// Cost of a point: decompression and one term in the multiscalar multiplication.
+pub(crate) const POINT_COST: u64 = 16;
+
+/// Computes the cost of a single instruction.
+///
+/// Commitments added to the constraint system are charged separately
+/// when they are used, since their number depends on the items on the stack.
+pub(crate) fn instruction(instr: &Instruction) -> u64 {
+    // The prover-only debug instruction is not part of the transaction.
+    if let Instruction::Inspect(_) = instr {
+        return 0;
+    }
+    let size = CircuitSize::instruction(instr);
+    // Points added to the batch verification of the point operations.
+    // Base points are shared by all operations and are not counted.
+    let points = match instr {
+        Instruction::Unblind | Instruction::Issue | Instruction::Call => 1,
+        Instruction::Select(n, _) => *n as u64 + 1,
+        // Signature's nonce commitment and the verification key.
+        Instruction::Delegate => 2,
+        // Verification key added to the aggregated transaction signature.
+        Instruction::Signtx => 1,
+        _ => 0,
+    };
+    circuit_cost(size) + INSTRUCTION_COST + points * POINT_COST
+}
+
+/// Computes the cost of a copy of the constraint made by `dup`:
+/// the same as the cost of the instructions creating each of its nodes.
+/// Otherwise repeated `dup` and `or` would build a constraint of exponential size
+/// at a linear cost, to be flattened by `verify`.
+pub(crate) fn constraint_copy(constraint: &Constraint) -> u64 {
+    circuit_cost(CircuitSize::constraint(constraint)) + nodes(constraint) * INSTRUCTION_COST
+}
+
+fn circuit_cost(size: CircuitSize) -> u64 {
+    size.multipliers as u64 * MULTIPLIER_COST
+        + size.allocations as u64 * MULTIPLIER_COST / 2
+        + size.constraints as u64 * CONSTRAINT_COST
+}
+
+/// Number of the nodes in the constraint tree.
+fn nodes(constraint: &Constraint) -> u64 {
+    1 + match constraint {
+        Constraint::Eq(_, _)
+        | Constraint::Range(_, _)
+        | Constraint::Lt(_, _)
+        | Constraint::Le(_, _) => 0,
+        Constraint::Not(c) => nodes(c),
+        Constraint::And(c1, c2) | Constraint::Or(c1, c2) | Constraint::Xor(c1, c2) => {
+            nodes(c1) + nodes(c2)
+        }
+        Constraint::IfThenElse(cond, c1, c2) => nodes(cond) + nodes(c1) + nodes(c2),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constraints::Expression;
+    use spacesuit::BitRange;
+
+    #[test]
+    fn instruction_costs() {
+        assert_eq!(instruction(&Instruction::Drop), INSTRUCTION_COST);
+        assert_eq!(instruction(&Instruction::Signtx), 1 + POINT_COST);
+        assert_eq!(
+            instruction(&Instruction::Alloc(None)),
+            1 + MULTIPLIER_COST / 2
+        );
+        assert_eq!(
+            instruction(&Instruction::Range(BitRange::new(8).unwrap())),
+            1 + 8 * MULTIPLIER_COST + 17 * CONSTRAINT_COST
+        );
+        assert_eq!(instruction(&Instruction::Select(3, 0)), 1 + 4 * POINT_COST);
+        assert!(instruction(&Instruction::Cloak(2, 2)) > instruction(&Instruction::Cloak(1, 1)));
+    }
+
+    #[test]
+    fn constraint_copy_costs() {
+        let eq = || Constraint::Eq(Expression::constant(0u64), Expression::constant(0u64));
+        assert_eq!(constraint_copy(&eq()), INSTRUCTION_COST);
+
+        // Same as the cost of `eq`, `eq`, `or` and `not` instructions.
+        let c = Constraint::Not(Box::new(Constraint::Or(Box::new(eq()), Box::new(eq()))));
+        assert_eq!(
+            constraint_copy(&c),
+            2 * INSTRUCTION_COST + instruction(&Instruction::Or) + instruction(&Instruction::Not)
+        );
+    }
+}
@@ -0,0 +1,120 @@
+//! Coverage of the programs executed by the prover's VM, for auditing the contracts.
+//!
+//! The coverage counts the executions of the instructions by their location
+//! (the program and the byte offset of the instruction in it) and by their mnemonic,
+//! and records the branches of the predicate trees taken with `select`, `call` and `delegate`.
+
+use curve25519_dalek::ristretto::CompressedRistretto;
+use std::collections::BTreeMap;
+
+use crate::ops::Instruction;
+use crate::program::Program;
+
+/// Location of an instruction: the index of its program in `Coverage::programs`
+/// and the byte offset of the instruction in the program's bytecode.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct InstructionLocation {
+    /// Index of the program in `Coverage::programs`: 0 for the transaction's program,
+    /// then the programs executed by `call` and `delegate` in the order of their first execution.
+    pub program: usize,
+
+    /// Offset of the instruction in the program's bytecode.
+    pub offset: usize,
+}
+
+/// Branch of a predicate tree taken by the VM.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Branch {
+    /// Predicate `k` of the disjunction of `n` predicates, selected with `select:n:k`.
+    Select(u8, u8),
+
+    /// Program committed to by the predicate and executed with `call`.
+    Call,
+
+    /// Program signed with the predicate's key and executed with `delegate`.
+    Delegate,
+}
+
+/// Branch of a predicate tree taken by the instruction at the location.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ExercisedBranch {
+    /// Location of the `select`, `call` or `delegate` instruction.
+    pub location: InstructionLocation,
+
+    /// Predicate of the contract before the instruction.
+    pub predicate: CompressedRistretto,
+
+    /// Branch taken by the instruction.
+    pub branch: Branch,
+}
+
+/// Coverage of the programs executed by the VM (see `Prover::run_with_coverage`).
+/// If the execution fails, the last executed instruction is the one that failed.
+#[derive(Clone, Debug, Default)]
+pub struct Coverage {
+    /// Bytecode of the executed programs. The identical programs executed
+    /// by several `call` or `delegate` instructions are listed once.
+    pub programs: Vec<Vec<u8>>,
+
+    /// Number of executions of the instructions by their location.
+    pub instructions: BTreeMap<InstructionLocation, usize>,
+
+    /// Number of executions of the instructions by their mnemonic (e.g. `cloak`).
+    pub opcodes: BTreeMap<&'static str, usize>,
+
+    /// Branches of the predicate trees, in the order of execution.
+    pub branches: Vec<ExercisedBranch>,
+}
+
+impl Coverage {
+    /// Returns the number of executions of the instruction at the location (zero if it was not executed).
+    pub fn count(&self, location: InstructionLocation) -> usize {
+        self.instructions.get(&location).cloned().unwrap_or(0)
+    }
+
+    /// Returns the locations of the instructions of the program that were not executed.
+    pub fn missed(&self, program: usize) -> Vec<InstructionLocation> {
+        let bytecode = match self.programs.get(program) {
+            Some(bytecode) => bytecode,
+            None => return Vec::new(),
+        };
+        let instructions = match Program::disassemble(bytecode) {
+            Ok(instructions) => instructions,
+            Err(_) => return Vec::new(),
+        };
+        let mut offset = 0;
+        let mut missed = Vec::new();
+        for instr in instructions.iter() {
+            let location = InstructionLocation { program, offset };
+            if self.count(location) == 0 {
+                missed.push(location);
+            }
+            offset += encoded_length(instr);
+        }
+        missed
+    }
+
+    /// Adds the program if it is not listed yet and returns its index.
+    pub(crate) fn add_program(&mut self, bytecode: Vec<u8>) -> usize {
+        match self.programs.iter().position(|p| *p == bytecode) {
+            Some(index) => index,
+            None => {
+                self.programs.push(bytecode);
+                self.programs.len() - 1
+            }
+        }
+    }
+
+    /// Counts the execution of the instruction at the location.
+    pub(crate) fn add_instruction(&mut self, location: InstructionLocation, instr: &Instruction) {
+        *self.instructions.entry(location).or_insert(0) += 1;
+        *self.opcodes.entry(instr.mnemonic()).or_insert(0) += 1;
+    }
+}
+
+/// Returns the length of the instruction in the bytecode (zero for the prover's `inspect`).
+pub(crate) fn encoded_length(instr: &Instruction) -> usize {
+    let mut buf = Vec::new();
+    instr.encode(&mut buf);
+    buf.len()
+}
@@ -1,9 +1,10 @@
 //! Encoding utils for ZkVM
 //! All methods err using VMError::FormatError for convenience.
 
-use byteorder::{ByteOrder, LittleEndian};
+use byteorder::{ByteOrder, LittleEndian, ReadBytesExt, WriteBytesExt};
 use curve25519_dalek::ristretto::CompressedRistretto;
 use curve25519_dalek::scalar::Scalar;
+use std::io;
 
 use crate::errors::VMError;
 
@@ -27,7 +28,7 @@ impl<'a> SliceReader<'a> {
         self.end - self.start
     }
 
-    pub fn slice<F, T>(&mut self, slice_fn: F) -> Result<(T, &[u8]), VMError>
+    pub fn slice<F, T>(&mut self, slice_fn: F) -> Result<(T, &'a [u8]), VMError>
     where
         F: FnOnce(&mut Self) -> Result<T, VMError>,
     {
@@ -56,8 +57,8 @@ impl<'a> SliceReader<'a> {
     }
 
     /// Returns a slice of the first `prefix_size` of bytes and advances
-    /// the internal offset.
-    pub fn read_bytes(&mut self, prefix_size: usize) -> Result<&[u8], VMError> {
+    /// the internal offset. The slice borrows the underlying buffer, not the reader.
+    pub fn read_bytes(&mut self, prefix_size: usize) -> Result<&'a [u8], VMError> {
         if prefix_size > self.len() {
             return Err(VMError::FormatError);
         }
@@ -104,8 +105,7 @@ impl<'a> SliceReader<'a> {
     }
 
     pub fn read_point(&mut self) -> Result<CompressedRistretto, VMError> {
-        let buf = self.read_u8x32()?;
-        Ok(CompressedRistretto(buf))
+        read_canonical_point(self.read_u8x32()?)
     }
 
     pub fn read_scalar(&mut self) -> Result<Scalar, VMError> {
@@ -150,3 +150,266 @@ pub(crate) fn write_bytes(x: &[u8], target: &mut Vec<u8>) {
 pub(crate) fn write_point(x: &CompressedRistretto, target: &mut Vec<u8>) {
     write_bytes(x.as_bytes(), target);
 }
+
+/// Writes a scalar
+pub(crate) fn write_scalar(x: &Scalar, target: &mut Vec<u8>) {
+    write_bytes(x.as_bytes(), target);
+}
+
+/// Streaming encoder that writes ZkVM-encoded data directly into an `io::Write`,
+/// e.g. a file or a socket, without buffering the entire payload.
+#[derive(Debug)]
+pub struct Writer<W: io::Write> {
+    inner: W,
+}
+
+impl<W: io::Write> Writer<W> {
+    /// Creates a writer around an underlying `io::Write`.
+    pub fn new(inner: W) -> Self {
+        Writer { inner }
+    }
+
+    /// Unwraps the underlying `io::Write`.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    /// Writes a single byte.
+    pub fn write_u8(&mut self, x: u8) -> io::Result<()> {
+        self.inner.write_u8(x)
+    }
+
+    /// Writes a LE32-encoded integer.
+    pub fn write_u32(&mut self, x: u32) -> io::Result<()> {
+        self.inner.write_u32::<LittleEndian>(x)
+    }
+
+    /// Writes a LE64-encoded integer.
+    pub fn write_u64(&mut self, x: u64) -> io::Result<()> {
+        self.inner.write_u64::<LittleEndian>(x)
+    }
+
+    /// Writes a usize as a LE32-encoded integer.
+    pub fn write_size(&mut self, x: usize) -> io::Result<()> {
+        self.write_u32(x as u32)
+    }
+
+    /// Writes raw bytes without a length prefix.
+    pub fn write_bytes(&mut self, x: &[u8]) -> io::Result<()> {
+        self.inner.write_all(x)
+    }
+
+    /// Writes a compressed point.
+    pub fn write_point(&mut self, x: &CompressedRistretto) -> io::Result<()> {
+        self.write_bytes(x.as_bytes())
+    }
+
+    /// Writes a scalar.
+    pub fn write_scalar(&mut self, x: &Scalar) -> io::Result<()> {
+        self.write_bytes(x.as_bytes())
+    }
+
+    /// Flushes the underlying `io::Write`.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Streaming decoder that reads ZkVM-encoded data from an `io::Read`.
+/// Unexpected end of the stream is reported as `VMError::FormatError`,
+/// consistently with `SliceReader`.
+#[derive(Debug)]
+pub struct Reader<R: io::Read> {
+    inner: R,
+}
+
+impl<R: io::Read> Reader<R> {
+    /// Creates a reader around an underlying `io::Read`.
+    pub fn new(inner: R) -> Self {
+        Reader { inner }
+    }
+
+    /// Unwraps the underlying `io::Read`.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Reads exactly `len` bytes.
+    /// The buffer grows as the data arrives, so a malformed length prefix
+    /// does not cause a large allocation up front.
+    pub fn read_bytes(&mut self, len: usize) -> Result<Vec<u8>, VMError> {
+        let mut buf = Vec::new();
+        (&mut self.inner)
+            .take(len as u64)
+            .read_to_end(&mut buf)
+            .map_err(io_error)?;
+        if buf.len() != len {
+            return Err(VMError::FormatError);
+        }
+        Ok(buf)
+    }
+
+    /// Reads all the remaining bytes until the end of the stream.
+    pub fn read_to_end(&mut self) -> Result<Vec<u8>, VMError> {
+        let mut buf = Vec::new();
+        self.inner.read_to_end(&mut buf).map_err(io_error)?;
+        Ok(buf)
+    }
+
+    /// Reads a single byte.
+    pub fn read_u8(&mut self) -> Result<u8, VMError> {
+        self.inner.read_u8().map_err(io_error)
+    }
+
+    /// Reads a LE32-encoded integer.
+    pub fn read_u32(&mut self) -> Result<u32, VMError> {
+        self.inner.read_u32::<LittleEndian>().map_err(io_error)
+    }
+
+    /// Reads a LE64-encoded integer.
+    pub fn read_u64(&mut self) -> Result<u64, VMError> {
+        self.inner.read_u64::<LittleEndian>().map_err(io_error)
+    }
+
+    /// Reads a LE32-encoded "size" type used in ZkVM.
+    pub fn read_size(&mut self) -> Result<usize, VMError> {
+        let n = self.read_u32()?;
+        Ok(n as usize)
+    }
+
+    /// Reads a 32-byte array.
+    pub fn read_u8x32(&mut self) -> Result<[u8; 32], VMError> {
+        let mut buf = [0u8; 32];
+        self.inner.read_exact(&mut buf).map_err(io_error)?;
+        Ok(buf)
+    }
+
+    /// Reads a 64-byte array.
+    pub fn read_u8x64(&mut self) -> Result<[u8; 64], VMError> {
+        let mut buf = [0u8; 64];
+        self.inner.read_exact(&mut buf).map_err(io_error)?;
+        Ok(buf)
+    }
+
+    /// Reads a canonically encoded compressed point.
+    pub fn read_point(&mut self) -> Result<CompressedRistretto, VMError> {
+        read_canonical_point(self.read_u8x32()?)
+    }
+
+    /// Reads a canonically encoded scalar.
+    pub fn read_scalar(&mut self) -> Result<Scalar, VMError> {
+        let buf = self.read_u8x32()?;
+        Scalar::from_canonical_bytes(buf).ok_or(VMError::FormatError)
+    }
+}
+
+/// Checks that the bytes are the canonical encoding of a Ristretto point:
+/// the field element is fully reduced and non-negative (its low bit is zero).
+/// Every point has exactly one such encoding. The point itself is not decompressed here,
+/// so the bytes may still fail to decode when the point is used.
+pub(crate) fn read_canonical_point(buf: [u8; 32]) -> Result<CompressedRistretto, VMError> {
+    if buf[0] & 1 == 1 || !is_reduced_field_element(&buf) {
+        return Err(VMError::FormatError);
+    }
+    Ok(CompressedRistretto(buf))
+}
+
+/// Checks that the little-endian integer is less than `p = 2^255 - 19`.
+fn is_reduced_field_element(buf: &[u8; 32]) -> bool {
+    if buf[31] & 0x80 != 0 {
+        return false;
+    }
+    // `p` is `0x7fff...ffed`: the integer is not reduced only if its higher bytes
+    // are all equal to the bytes of `p` and the lowest byte is at least 0xed.
+    !(buf[31] == 0x7f && buf[1..31].iter().all(|b| *b == 0xff) && buf[0] >= 0xed)
+}
+
+fn io_error(e: io::Error) -> VMError {
+    match e.kind() {
+        io::ErrorKind::UnexpectedEof => VMError::FormatError,
+        kind => VMError::IoError(kind),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use curve25519_dalek::constants::RISTRETTO_BASEPOINT_POINT;
+
+    #[test]
+    fn stream_roundtrip() {
+        let mut writer = Writer::new(Vec::new());
+        writer.write_u8(7).unwrap();
+        writer.write_u32(0xdeadbeef).unwrap();
+        writer.write_u64(u64::max_value()).unwrap();
+        writer.write_size(3).unwrap();
+        writer.write_bytes(b"abc").unwrap();
+        writer.write_scalar(&Scalar::from(42u64)).unwrap();
+        let bytes = writer.into_inner();
+
+        // Streaming encoding is identical to the buffered one.
+        let mut buf = Vec::new();
+        write_u8(7, &mut buf);
+        write_u32(0xdeadbeef, &mut buf);
+        write_u64(u64::max_value(), &mut buf);
+        write_size(3, &mut buf);
+        write_bytes(b"abc", &mut buf);
+        write_bytes(Scalar::from(42u64).as_bytes(), &mut buf);
+        assert_eq!(bytes, buf);
+
+        let mut reader = Reader::new(&bytes[..]);
+        assert_eq!(reader.read_u8(), Ok(7));
+        assert_eq!(reader.read_u32(), Ok(0xdeadbeef));
+        assert_eq!(reader.read_u64(), Ok(u64::max_value()));
+        let len = reader.read_size().unwrap();
+        assert_eq!(reader.read_bytes(len), Ok(b"abc".to_vec()));
+        assert_eq!(reader.read_scalar(), Ok(Scalar::from(42u64)));
+        assert_eq!(reader.read_to_end(), Ok(vec![]));
+    }
+
+    #[test]
+    fn canonical_points() {
+        let point = (Scalar::from(42u64) * RISTRETTO_BASEPOINT_POINT).compress();
+        let read = |buf: [u8; 32]| SliceReader::parse(&buf, |r| r.read_point());
+        assert_eq!(read(point.to_bytes()), Ok(point));
+        assert_eq!(read([0u8; 32]), Ok(CompressedRistretto([0u8; 32])));
+
+        // Negative field element.
+        let mut negative = point.to_bytes();
+        negative[0] |= 1;
+        assert_eq!(read(negative), Err(VMError::FormatError));
+
+        // The top bit is set.
+        let mut high_bit = point.to_bytes();
+        high_bit[31] |= 0x80;
+        assert_eq!(read(high_bit), Err(VMError::FormatError));
+
+        // `p` encodes zero, like the identity, but is not reduced.
+        let mut p = [0xffu8; 32];
+        p[0] = 0xed;
+        p[31] = 0x7f;
+        assert_eq!(read(p), Err(VMError::FormatError));
+        p[0] = 0xec;
+        assert_eq!(read(p), Ok(CompressedRistretto(p)));
+        assert_eq!(Reader::new(&p[..]).read_point(), Ok(CompressedRistretto(p)));
+        p[0] = 0xee;
+        assert_eq!(Reader::new(&p[..]).read_point(), Err(VMError::FormatError));
+    }
+
+    #[test]
+    fn stream_unexpected_eof() {
+        let bytes = [1u8, 2, 3];
+        assert_eq!(
+            Reader::new(&bytes[..]).read_u32(),
+            Err(VMError::FormatError)
+        );
+        assert_eq!(
+            Reader::new(&bytes[..]).read_bytes(4),
+            Err(VMError::FormatError)
+        );
+        assert_eq!(
+            Reader::new(&bytes[..]).read_u8x32(),
+            Err(VMError::FormatError)
+        );
+    }
+}
@@ -0,0 +1,292 @@
+//! Verifiable encryption of the commitment openings to an auditor key.
+//!
+//! The sender encrypts the value of a Pedersen commitment `V = v·B + f·B2`
+//! with exponential ElGamal to the auditor's key `A = a·B`:
+//!
+//! ```ascii
+//! C1 = k·B
+//! C2 = v·B + k·A
+//! ```
+//!
+//! and proves with a Schnorr-style proof of knowledge of `(v, f, k)`
+//! that the ciphertext encrypts the same `v` that is committed in `V`.
+//! The proof is checked with deferred point operations, so it can be verified
+//! in the same batch as the transaction with `Verifier::verify_tx_with_point_ops`.
+//!
+//! The auditor decrypts `v·B = C2 - a·C1` and recovers `v` with a bounded
+//! discrete logarithm search, so the plaintext is expected to be a quantity,
+//! not an arbitrary scalar.
+
+#![allow(non_snake_case)]
+
+use curve25519_dalek::ristretto::{CompressedRistretto, RistrettoPoint};
+use curve25519_dalek::scalar::Scalar;
+use curve25519_dalek::traits::Identity;
+use merlin::Transcript;
+use rand::{CryptoRng, RngCore};
+use std::collections::HashMap;
+
+use crate::constraints::Commitment;
+use crate::encoding::{self, SliceReader};
+use crate::errors::VMError;
+use crate::gens;
+use crate::point_ops::PointOp;
+use crate::transcript::TranscriptProtocol;
+
+/// Auditor's public key for the verifiable encryption.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct EncryptionKey(pub CompressedRistretto);
+
+/// ElGamal encryption of the value of a commitment with the proof
+/// that it encrypts the committed value.
+#[derive(Clone, Debug)]
+pub struct EncryptedOpening {
+    C1: CompressedRistretto,
+    C2: CompressedRistretto,
+    T_V: CompressedRistretto,
+    T_1: CompressedRistretto,
+    T_2: CompressedRistretto,
+    z_v: Scalar,
+    z_f: Scalar,
+    z_k: Scalar,
+}
+
+impl EncryptionKey {
+    /// Constructs the auditor's key from its private key.
+    pub fn from_secret(privkey: &Scalar) -> Self {
+        EncryptionKey(gens::mul_base(privkey).compress())
+    }
+}
+
+impl EncryptedOpening {
+    /// Encrypts the value of the open commitment to the auditor's key.
+    /// The transcript binds the proof to its context, e.g. the transaction ID.
+    #[cfg(feature = "std")]
+    pub fn encrypt(
+        transcript: &mut Transcript,
+        commitment: &Commitment,
+        key: &EncryptionKey,
+    ) -> Result<Self, VMError> {
+        Self::encrypt_with_rng(transcript, commitment, key, &mut rand::thread_rng())
+    }
+
+    /// Encrypts the value of the open commitment to the auditor's key using the provided RNG.
+    /// Fails with `VMError::WitnessMissing` if the commitment is not open,
+    /// and with `VMError::InvalidPoint` if the key is not a valid point.
+    pub fn encrypt_with_rng<R: RngCore + CryptoRng>(
+        transcript: &mut Transcript,
+        commitment: &Commitment,
+        key: &EncryptionKey,
+        rng: &mut R,
+    ) -> Result<Self, VMError> {
+        let (value, blinding) = commitment.witness().ok_or(VMError::WitnessMissing)?;
+        let v = value.to_scalar();
+        let A = key.0.decompress().ok_or(VMError::InvalidPoint)?;
+
+        let mut rng = transcript
+            .build_rng()
+            .commit_witness_bytes(b"v", v.as_bytes())
+            .commit_witness_bytes(b"f", blinding.as_bytes())
+            .finalize(rng);
+        let k = Scalar::random(&mut rng);
+        let r_v = Scalar::random(&mut rng);
+        let r_f = Scalar::random(&mut rng);
+        let r_k = Scalar::random(&mut rng);
+
+        let C1 = gens::mul_base(&k).compress();
+        let C2 = (gens::mul_base(&v) + k * A).compress();
+        let T_V = gens::commit(&r_v, &r_f).compress();
+        let T_1 = gens::mul_base(&r_k).compress();
+        let T_2 = (gens::mul_base(&r_v) + r_k * A).compress();
+
+        let c = Self::challenge(
+            transcript,
+            &commitment.to_point(),
+            key,
+            [&C1, &C2, &T_V, &T_1, &T_2],
+        );
+
+        Ok(EncryptedOpening {
+            C1,
+            C2,
+            T_V,
+            T_1,
+            T_2,
+            z_v: r_v + c * v,
+            z_f: r_f + c * blinding,
+            z_k: r_k + c * k,
+        })
+    }
+
+    /// Verifies that the ciphertext encrypts the value of the commitment to the auditor's key.
+    /// Returns the point operations to be checked together with the transaction.
+    pub fn verify(
+        &self,
+        transcript: &mut Transcript,
+        commitment: &Commitment,
+        key: &EncryptionKey,
+    ) -> Vec<PointOp> {
+        let V = commitment.to_point();
+        let c = Self::challenge(
+            transcript,
+            &V,
+            key,
+            [&self.C1, &self.C2, &self.T_V, &self.T_1, &self.T_2],
+        );
+        vec![
+            // `0 == z_v·B + z_f·B2 - T_V - c·V`
+            PointOp {
+                primary: Some(self.z_v),
+                secondary: Some(self.z_f),
+                arbitrary: vec![(-Scalar::one(), self.T_V), (-c, V)],
+            },
+            // `0 == z_k·B - T_1 - c·C1`
+            PointOp {
+                primary: Some(self.z_k),
+                secondary: None,
+                arbitrary: vec![(-Scalar::one(), self.T_1), (-c, self.C1)],
+            },
+            // `0 == z_v·B + z_k·A - T_2 - c·C2`
+            PointOp {
+                primary: Some(self.z_v),
+                secondary: None,
+                arbitrary: vec![(self.z_k, key.0), (-Scalar::one(), self.T_2), (-c, self.C2)],
+            },
+        ]
+    }
+
+    /// Decrypts the value with the auditor's private key.
+    /// Returns `None` if the value is greater than `max_value`.
+    /// The search takes `O(sqrt(max_value))` time and memory.
+    pub fn decrypt(&self, privkey: &Scalar, max_value: u64) -> Option<u64> {
+        let B = gens::pedersen_gens().B;
+        let C1 = self.C1.decompress()?;
+        let C2 = self.C2.decompress()?;
+        let target = C2 - privkey * C1;
+
+        // Baby-step giant-step: find `v = i*m + j` with `j < m`.
+        let m = (max_value as f64).sqrt() as u64 + 1;
+        let mut baby_steps = HashMap::new();
+        let mut P = RistrettoPoint::identity();
+        for j in 0..m {
+            baby_steps.insert(P.compress().to_bytes(), j);
+            P += B;
+        }
+        let giant_step = Scalar::from(m) * B;
+        let mut Q = target;
+        for i in 0..=max_value / m {
+            if let Some(j) = baby_steps.get(Q.compress().as_bytes()) {
+                let v = (i as u128) * (m as u128) + (*j as u128);
+                return if v <= max_value as u128 {
+                    Some(v as u64)
+                } else {
+                    None
+                };
+            }
+            Q -= giant_step;
+        }
+        None
+    }
+
+    /// Returns the number of bytes needed to serialize the encrypted opening.
+    pub fn serialized_length(&self) -> usize {
+        5 * 32 + 3 * 32
+    }
+
+    /// Encodes the encrypted opening as a byte array.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(self.serialized_length());
+        for p in [&self.C1, &self.C2, &self.T_V, &self.T_1, &self.T_2].iter() {
+            encoding::write_point(p, &mut buf);
+        }
+        for s in [&self.z_v, &self.z_f, &self.z_k].iter() {
+            encoding::write_scalar(s, &mut buf);
+        }
+        buf
+    }
+
+    /// Decodes the encrypted opening from a byte array.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, VMError> {
+        SliceReader::parse(bytes, |r| {
+            Ok(EncryptedOpening {
+                C1: r.read_point()?,
+                C2: r.read_point()?,
+                T_V: r.read_point()?,
+                T_1: r.read_point()?,
+                T_2: r.read_point()?,
+                z_v: r.read_scalar()?,
+                z_f: r.read_scalar()?,
+                z_k: r.read_scalar()?,
+            })
+        })
+    }
+
+    fn challenge(
+        transcript: &mut Transcript,
+        V: &CompressedRistretto,
+        key: &EncryptionKey,
+        points: [&CompressedRistretto; 5],
+    ) -> Scalar {
+        transcript.commit_bytes(b"dom-sep", b"ZkVM.encryption");
+        transcript.commit_point(b"V", V);
+        transcript.commit_point(b"A", &key.0);
+        let labels: [&'static [u8]; 5] = [b"C1", b"C2", b"T_V", b"T_1", b"T_2"];
+        for (label, p) in labels.iter().zip(points.iter()) {
+            transcript.commit_point(*label, *p);
+        }
+        transcript.challenge_scalar(b"c")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encrypt_and_decrypt() {
+        let privkey = Scalar::from(1234u64);
+        let key = EncryptionKey::from_secret(&privkey);
+        let commitment = Commitment::blinded(4242u64);
+
+        let opening =
+            EncryptedOpening::encrypt(&mut Transcript::new(b"encryption"), &commitment, &key)
+                .unwrap();
+        let ops = opening.verify(&mut Transcript::new(b"encryption"), &commitment, &key);
+        assert!(PointOp::verify_batch(&ops).is_ok());
+
+        let decoded = EncryptedOpening::from_bytes(&opening.to_bytes()).unwrap();
+        assert_eq!(decoded.to_bytes(), opening.to_bytes());
+        assert_eq!(decoded.decrypt(&privkey, 10_000), Some(4242));
+        assert_eq!(decoded.decrypt(&privkey, 4241), None);
+        assert_eq!(decoded.decrypt(&(privkey + Scalar::one()), 10_000), None);
+    }
+
+    #[test]
+    fn wrong_statement() {
+        let key = EncryptionKey::from_secret(&Scalar::from(1234u64));
+        let other_key = EncryptionKey::from_secret(&Scalar::from(4321u64));
+        let commitment = Commitment::blinded(10u64);
+        let opening =
+            EncryptedOpening::encrypt(&mut Transcript::new(b"encryption"), &commitment, &key)
+                .unwrap();
+
+        let verify = |commitment: &Commitment, key: &EncryptionKey, label: &'static [u8]| {
+            let ops = opening.verify(&mut Transcript::new(label), commitment, key);
+            PointOp::verify_batch(&ops)
+        };
+        assert!(verify(&commitment, &key, b"encryption").is_ok());
+        assert!(verify(&Commitment::blinded(10u64), &key, b"encryption").is_err());
+        assert!(verify(&commitment, &other_key, b"encryption").is_err());
+        assert!(verify(&commitment, &key, b"other context").is_err());
+
+        assert_eq!(
+            EncryptedOpening::encrypt(
+                &mut Transcript::new(b"encryption"),
+                &Commitment::Closed(commitment.to_point()),
+                &key
+            )
+            .unwrap_err(),
+            VMError::WitnessMissing
+        );
+    }
+}
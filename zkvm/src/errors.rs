@@ -1,7 +1,13 @@
 //! Errors related to proving and verifying proofs.
 use bulletproofs::r1cs::R1CSError;
+use std::fmt;
+use std::io;
 
 /// Represents an error in proof creation, verification, or parsing.
+///
+/// Errors of the VM instructions are wrapped in `VMError::InstructionFailed`
+/// with the context of the failing instruction; `VMError::root` returns the underlying error.
+/// `VMError` implements `failure::Fail`; use `Fail::compat` to convert it to `std::error::Error`.
 #[derive(Fail, Clone, Debug, Eq, PartialEq)]
 pub enum VMError {
     /// This error occurs when an individual point operation failed.
@@ -68,6 +74,10 @@ pub enum VMError {
     #[fail(display = "Item is not a scalar.")]
     TypeNotScalar,
 
+    /// This error occurs when an instruction requires a vector commitment data type.
+    #[fail(display = "Item is not a vector commitment.")]
+    TypeNotVectorCommitment,
+
     /// This errors occurs when an instruction expects a predicate disjunction type.
     #[fail(display = "Item is not a disjunction.")]
     TypeNotDisjunction,
@@ -135,19 +145,201 @@ pub enum VMError {
     #[fail(display = "Item misses witness data.")]
     WitnessMissing,
 
-    /// This error occurs when we supply a number not in the range [1,64]
-    #[fail(display = "Bitrange for rangeproof is not between 1 and 64")]
+    /// This error occurs when we supply a number of bits above 64,
+    /// or above 252 in the transactions of `WIDE_RANGE_VERSION` and later.
+    #[fail(display = "Bitrange for rangeproof is too wide")]
     InvalidBitrange,
 
     /// This error occurs when a Merkle proof of inclusion is invalid.
     #[fail(display = "Invalid Merkle proof.")]
     InvalidMerkleProof,
 
+    /// This error occurs when `carry` is executed outside of a program of a contract.
+    #[fail(display = "Program is not executed by a contract")]
+    PredicateMissing,
+
     /// This error occurs when the an index of a selected predicate is invalid.
     #[fail(display = "Predicate index out of bounds")]
     PredicateIndexInvalid,
 
+    /// This error occurs when a MuSig session receives messages out of order.
+    #[fail(display = "MuSig session is not expecting this message")]
+    MuSigSessionError,
+
+    /// This error occurs when a dealer's proof of knowledge of its secret
+    /// fails to verify during the threshold key generation.
+    #[fail(display = "Dealer #{} failed to prove knowledge of its secret", dealer)]
+    DKGProofError {
+        /// The index of the dealer whose proof failed to verify
+        dealer: u64,
+    },
+
+    /// This error occurs when a share received during the threshold key generation
+    /// does not match the dealer's commitment.
+    #[fail(display = "Share from dealer #{} failed to verify correctly", dealer)]
+    DKGShareError {
+        /// The index of the dealer whose share failed to verify
+        dealer: u64,
+    },
+
     /// This error occurs when a function is called with bad arguments.
     #[fail(display = "Bad arguments")]
     BadArguments,
+
+    /// This error occurs when checked arithmetic on integer witnesses overflows the 64-bit range.
+    #[fail(display = "Integer witness overflow")]
+    IntegerOverflow,
+
+    /// This error occurs when the prover's witness is outside of the range enforced by a range proof.
+    #[fail(display = "Witness is out of range of the range proof")]
+    WitnessOutOfRange,
+
+    /// This error occurs when a range constraint is negated, or is used in the condition
+    /// of an if-then-else or an operand of a `xor` constraint that cannot be rewritten without it.
+    #[fail(display = "Range constraint cannot be negated")]
+    NegatedRange,
+
+    /// This error occurs when the total fee paid by the transaction does not fit in 64 bits.
+    #[fail(display = "Transaction fee is too high")]
+    FeeTooHigh,
+
+    /// This error occurs when the assembly text contains an invalid token.
+    #[fail(display = "Invalid assembly at line {}: {}", line, token)]
+    AssemblyError {
+        /// Line number of the invalid token, starting from 1.
+        line: usize,
+        /// The invalid token.
+        token: String,
+    },
+
+    /// This error occurs when the cost of executing the transaction exceeds the limit in its header.
+    #[fail(display = "Transaction cost exceeds the limit")]
+    CostLimitExceeded,
+
+    /// This error occurs when the transaction's `mintime` is greater than its `maxtime`.
+    #[fail(display = "Transaction time bounds are empty")]
+    InvalidTimeBounds,
+
+    /// This error occurs when the serialized transaction exceeds the size limit of `Tx::precheck`.
+    #[fail(
+        display = "Transaction of {} bytes exceeds the limit of {} bytes",
+        size, limit
+    )]
+    TxTooLarge {
+        /// Size of the serialized transaction in bytes.
+        size: usize,
+        /// Maximum size of the serialized transaction in bytes.
+        limit: usize,
+    },
+
+    /// This error occurs when `call` or `delegate` exceeds the limit of the nested programs.
+    #[fail(display = "Nested programs exceed the depth limit")]
+    ProgramTooDeep,
+
+    /// This error occurs when the outputs in a signing request do not match its transaction log.
+    #[fail(display = "Signing request does not match the transaction log")]
+    InvalidSigningRequest,
+
+    /// This error occurs when the secret does not match the hash of an HTLC.
+    #[fail(display = "Secret is not a preimage of the SHA-256 hash")]
+    InvalidPreimage,
+
+    /// This error occurs when a signature is not a completion of the adaptor signature.
+    #[fail(display = "Signature does not complete the adaptor signature")]
+    InvalidAdaptorSignature,
+
+    /// This error occurs when the issuer's response to a blind signing request is invalid.
+    #[fail(display = "Invalid blind signature response")]
+    BlindSignatureError,
+
+    /// This error occurs when the Bulletproofs generators are too small for the constraint system of the transaction.
+    #[fail(
+        display = "Transaction requires up to {} Bulletproofs generators, but only {} are available",
+        required, available
+    )]
+    InsufficientGenerators {
+        /// Estimated capacity required by the transaction, padded to a power of two.
+        required: usize,
+        /// Capacity of the provided generators.
+        available: usize,
+    },
+
+    /// This error occurs when reading from a stream fails for a reason other than unexpected EOF.
+    #[fail(display = "I/O error: {:?}", _0)]
+    IoError(io::ErrorKind),
+
+    /// This error occurs when the static type check of a program finds an ill-typed instruction
+    /// or the items left on the stack at the end of the program (see `Program::typecheck`).
+    #[fail(display = "Type check failed at offset {}: {}", offset, error)]
+    TypeCheckFailed {
+        /// Byte offset of the failing instruction in the bytecode, or the length of the bytecode
+        /// if the stack is not clean at the end of the program.
+        offset: usize,
+        /// Name of the failing instruction.
+        instruction: Option<&'static str>,
+        /// The error reported by the check.
+        error: Box<VMError>,
+    },
+
+    /// This error occurs when an instruction fails during the VM execution.
+    #[fail(display = "{} ({})", error, context)]
+    InstructionFailed {
+        /// The error reported by the instruction.
+        error: Box<VMError>,
+        /// The failing instruction and the state of the VM before it was executed.
+        context: Box<ErrorContext>,
+    },
+}
+
+/// Describes the failing instruction and the state of the VM before it was executed.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ErrorContext {
+    /// Index of the instruction in the order of execution, including the instructions of the nested programs.
+    pub instruction_index: usize,
+
+    /// Number of the nested programs being executed (e.g. via `call` or `delegate`), 0 for the transaction's program.
+    pub run_depth: usize,
+
+    /// Name of the instruction (e.g. `output`), or `None` if the instruction could not be decoded.
+    pub instruction: Option<&'static str>,
+
+    /// Number of items on the stack.
+    pub stack_depth: usize,
+
+    /// Type of the item on the top of the stack, e.g. the actual type of the item
+    /// when the instruction expects a different one.
+    pub stack_top: Option<&'static str>,
+
+    /// The last entry of the transaction log, in the debug format.
+    pub last_entry: Option<String>,
+}
+
+impl VMError {
+    /// Returns the underlying error without the context of the failing instruction.
+    pub fn root(&self) -> &VMError {
+        match self {
+            VMError::InstructionFailed { error, .. } => error.root(),
+            e => e,
+        }
+    }
+}
+
+impl fmt::Display for ErrorContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "instruction #{} `{}` at depth {}, stack depth {}",
+            self.instruction_index,
+            self.instruction.unwrap_or("<invalid>"),
+            self.run_depth,
+            self.stack_depth
+        )?;
+        if let Some(stack_top) = self.stack_top {
+            write!(f, ", top item is {}", stack_top)?;
+        }
+        if let Some(entry) = &self.last_entry {
+            write!(f, ", last log entry {}", entry)?;
+        }
+        Ok(())
+    }
 }
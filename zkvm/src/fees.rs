@@ -0,0 +1,138 @@
+//! Fee rate of a transaction: the total fee paid with `fee` instructions
+//! relative to the size of the serialized transaction.
+
+use core::cmp::Ordering;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::errors::VMError;
+use crate::txlog::{Entry, TxLog};
+
+/// Fee rate expressed as a fraction of a fee quantity over a transaction size in bytes.
+/// Fee rates are compared exactly (without rounding), which makes them
+/// usable as a priority ordering of transactions.
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct FeeRate {
+    fee: u64,
+    size: u64,
+}
+
+impl FeeRate {
+    /// Creates a fee rate for the given fee paid by a transaction of a given size in bytes.
+    pub fn new(fee: u64, size: usize) -> Self {
+        FeeRate {
+            fee,
+            size: size as u64,
+        }
+    }
+
+    /// Returns the zero fee rate.
+    pub fn zero() -> Self {
+        FeeRate { fee: 0, size: 1 }
+    }
+
+    /// Returns the total fee.
+    pub fn fee(&self) -> u64 {
+        self.fee
+    }
+
+    /// Returns the size of the transaction in bytes.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Returns the fee paid per 1000 bytes, rounded down.
+    pub fn per_kb(&self) -> u64 {
+        if self.size == 0 {
+            return u64::max_value();
+        }
+        let rate = (self.fee as u128) * 1000 / (self.size as u128);
+        if rate > (u64::max_value() as u128) {
+            u64::max_value()
+        } else {
+            rate as u64
+        }
+    }
+
+    /// Combines the fee rates of two transactions into the fee rate of the pair,
+    /// e.g. for evaluating a package of a parent and a child transaction.
+    /// Returns `None` if the sum of the fees or sizes overflows.
+    pub fn combine(&self, other: &FeeRate) -> Option<FeeRate> {
+        Some(FeeRate {
+            fee: self.fee.checked_add(other.fee)?,
+            size: self.size.checked_add(other.size)?,
+        })
+    }
+
+    /// Sums up the quantities of all fee entries in the transaction log.
+    pub(crate) fn total_fee(txlog: &TxLog) -> Result<u64, VMError> {
+        txlog.iter().try_fold(0u64, |total, entry| match entry {
+            Entry::Fee(qty) => total.checked_add(*qty).ok_or(VMError::FeeTooHigh),
+            _ => Ok(total),
+        })
+    }
+}
+
+impl PartialEq for FeeRate {
+    fn eq(&self, other: &FeeRate) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for FeeRate {}
+
+impl PartialOrd for FeeRate {
+    fn partial_cmp(&self, other: &FeeRate) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for FeeRate {
+    fn cmp(&self, other: &FeeRate) -> Ordering {
+        // a/b ? c/d  <=>  a*d ? c*b  (sizes are non-negative, products fit in 128 bits)
+        let lhs = (self.fee as u128) * (other.size as u128);
+        let rhs = (other.fee as u128) * (self.size as u128);
+        lhs.cmp(&rhs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exact_ordering() {
+        assert_eq!(FeeRate::new(1, 100), FeeRate::new(10, 1000));
+        assert!(FeeRate::new(1, 100) < FeeRate::new(11, 1000));
+        assert!(FeeRate::new(u64::max_value(), 1000) > FeeRate::new(u64::max_value() - 1, 1000));
+        assert!(FeeRate::zero() < FeeRate::new(1, usize::max_value()));
+        assert_eq!(FeeRate::zero(), FeeRate::new(0, 500));
+    }
+
+    #[test]
+    fn per_kb() {
+        assert_eq!(FeeRate::new(5, 1000).per_kb(), 5);
+        assert_eq!(FeeRate::new(5, 3000).per_kb(), 1);
+        assert_eq!(FeeRate::new(u64::max_value(), 1).per_kb(), u64::max_value());
+    }
+
+    #[test]
+    fn combined_rate() {
+        let parent = FeeRate::new(0, 1000);
+        let child = FeeRate::new(300, 500);
+        let package = parent.combine(&child).unwrap();
+        assert_eq!(package.fee(), 300);
+        assert_eq!(package.size(), 1500);
+        assert!(package > parent);
+        assert!(package < child);
+    }
+
+    #[test]
+    fn total_fee_from_log() {
+        let log = vec![Entry::Fee(10), Entry::Data(vec![]), Entry::Fee(5)];
+        assert_eq!(FeeRate::total_fee(&log).unwrap(), 15);
+        let log = vec![Entry::Fee(u64::max_value()), Entry::Fee(1)];
+        assert!(FeeRate::total_fee(&log).is_err());
+    }
+}
@@ -0,0 +1,1170 @@
+//! Reusable circuits built on top of `Expression` and `Constraint`.
+//!
+//! Gadgets that compute new values (`is_zero`, `unpack_bits`, `lookup`) allocate
+//! low-level variables and immediately add the constraints that bind them to the inputs.
+//! Gadgets that only check a property (`boolean`) return a `Constraint` that can be
+//! composed with other constraints and enforced with `Constraint::verify`.
+//! Range checks (`range`, `after`, `before`) cannot be expressed as a `Constraint`
+//! and are added to the constraint system immediately.
+//! The `sha256` and `sha512` gadgets compute the hash of the committed bytes,
+//! and `sha256_preimage` and `sha512_preimage` return a `Constraint` that compares it
+//! with the expected hash (also available to programs as the `sha256` and `sha512` instructions).
+//! The `poseidon` gadget computes an algebraic hash that is much cheaper in the constraint system;
+//! `poseidon_merkle_root` computes the root of a Merkle tree from a leaf and its path,
+//! `merkle_membership` returns a `Constraint` that the leaf belongs to the tree with a given root,
+//! and `nullifier` derives the tag that marks a one-time claim as redeemed.
+
+use bulletproofs::r1cs;
+use bulletproofs::r1cs::{ConstraintSystem, R1CSError};
+use curve25519_dalek::scalar::Scalar;
+use spacesuit::BitRange;
+use subtle::{ConditionallySelectable, ConstantTimeEq};
+
+use crate::constraints::{Constraint, Expression};
+use crate::errors::VMError;
+use crate::poseidon::Poseidon;
+use crate::scalar_witness::ScalarWitness;
+use core::iter::FromIterator;
+
+/// Returns a constraint that evaluates to true if `x` is either 0 or 1.
+/// Allocates one multiplier for `x*(1-x)` unless `x` is a constant.
+pub fn boolean<CS: ConstraintSystem>(cs: &mut CS, x: Expression) -> Constraint {
+    let one_minus_x = Expression::constant(1u64) - &x;
+    Constraint::Eq(x.multiply(one_minus_x, cs), Expression::constant(0u64))
+}
+
+/// Returns an expression that equals 1 if `x` is zero and 0 otherwise.
+/// Allocates two multipliers and adds four constraints unless `x` is a constant.
+pub fn is_zero<CS: ConstraintSystem>(cs: &mut CS, x: Expression) -> Result<Expression, VMError> {
+    if let Expression::Constant(c) = &x {
+        let y = if c.to_scalar() == Scalar::zero() {
+            1u64
+        } else {
+            0u64
+        };
+        return Ok(Expression::constant(y));
+    }
+    let assignment = x.eval().map(|x| x.to_scalar());
+    let (y, y_assg) = is_zero_lc(cs, x.to_r1cs_lc(), assignment).map_err(VMError::R1CSError)?;
+    let y_assg = y_assg.map(|y| {
+        let y = u64::conditional_select(&0, &1, y.ct_eq(&Scalar::one()));
+        ScalarWitness::from(y)
+    });
+    Ok(Expression::LinearCombination(
+        vec![(y, Scalar::one())],
+        y_assg,
+    ))
+}
+
+/// Returns a variable `y` that equals 1 if `x` is zero and 0 otherwise,
+/// together with its assignment.
+pub(crate) fn is_zero_lc<CS: ConstraintSystem>(
+    cs: &mut CS,
+    x_lc: r1cs::LinearCombination,
+    x_assg: Option<Scalar>,
+) -> Result<(r1cs::Variable, Option<Scalar>), R1CSError> {
+    // Compute assignments for all the wires
+    let (xy_assg, xw_assg, y_assg) = match x_assg {
+        Some(x) => {
+            let is_zero = x.ct_eq(&Scalar::zero());
+            let y = Scalar::conditional_select(&Scalar::zero(), &Scalar::one(), is_zero.into());
+            let w = Scalar::conditional_select(&x, &Scalar::one(), is_zero.into());
+            let w = w.invert();
+            (Some((x, y)), Some((x, w)), Some(y))
+        }
+        None => (None, None, None),
+    };
+
+    // Allocate two multipliers.
+    let (l1, r1, o1) = cs.allocate_multiplier(xy_assg)?;
+    let (l2, _r2, o2) = cs.allocate_multiplier(xw_assg)?;
+
+    // Add 4 constraints.
+
+    // (1) `x == l1`
+    cs.constrain(l1 - x_lc);
+
+    // (2) `l1 == l2` (== x)
+    cs.constrain(l1 - l2);
+
+    // (3) `x*y == 0` which implies that y == 0 if x != 0.
+    cs.constrain(o1.into());
+
+    // (4) `x*w == 1 - y` which implies that y == 1 if x == 0.
+    cs.constrain(o2 - Scalar::one() + r1);
+
+    // Note: w (r2) is left unconstrained — it is a free variable.
+
+    Ok((r1, y_assg))
+}
+
+/// Returns `a` if `cond` is 1 and `b` if `cond` is 0.
+/// The condition is expected to be constrained to be either 0 or 1 (see `boolean`).
+/// Allocates one multiplier for `cond*(a-b)` unless either factor is a constant.
+pub fn select<CS: ConstraintSystem>(
+    cs: &mut CS,
+    cond: Expression,
+    a: Expression,
+    b: Expression,
+) -> Expression {
+    cond.multiply(a - &b, cs) + b
+}
+
+/// Decomposes `x` into `n` bits in little-endian order and constrains `x`
+/// to be equal to the packed bits, which also proves that `x` is in range [0, 2^n).
+/// Allocates `n` multipliers.
+pub fn unpack_bits<CS: ConstraintSystem>(
+    cs: &mut CS,
+    x: Expression,
+    n: BitRange,
+) -> Result<Vec<Expression>, VMError> {
+    // Values out of range are decomposed into their lower `n` bits,
+    // so the final constraint is not satisfied.
+    let bytes = x.eval().map(|x| x.to_scalar().to_bytes());
+
+    let n: usize = n.into();
+    let mut bits = Vec::with_capacity(n);
+    for i in 0..n {
+        let bit = bytes.map(|bytes| u64::from((bytes[i / 8] >> (i % 8)) & 1));
+        let (a, b, o) = cs
+            .allocate_multiplier(bit.map(|bit| ((1 - bit).into(), bit.into())))
+            .map_err(VMError::R1CSError)?;
+
+        // Enforce a * b = 0, so one of (a,b) is zero
+        cs.constrain(o.into());
+
+        // Enforce that a = 1 - b, so they both are 1 or 0.
+        cs.constrain(a + (b - 1u64));
+
+        bits.push(Expression::LinearCombination(
+            vec![(b, Scalar::one())],
+            bit.map(ScalarWitness::from),
+        ));
+    }
+
+    cs.constrain((x - pack_bits(&bits)).to_r1cs_lc());
+    Ok(bits)
+}
+
+/// Packs the bits in little-endian order into a number `Sum(b_i * 2^i)`.
+/// Does not allocate multipliers: the bits are expected to be constrained
+/// to be either 0 or 1 (see `boolean` and `unpack_bits`).
+pub fn pack_bits(bits: &[Expression]) -> Expression {
+    let mut result = Expression::constant(0u64);
+    let mut exp_2 = ScalarWitness::from(1u64);
+    for bit in bits.iter() {
+        result = result + bit.clone() * exp_2;
+        exp_2 = exp_2 + exp_2;
+    }
+    result
+}
+
+/// Returns the entry `table[index]`, constraining `index` to be in range [0, table.len()).
+/// Allocates two multipliers per table entry to compare it with the index,
+/// and one more multiplier per entry that is not a constant.
+pub fn lookup<CS: ConstraintSystem>(
+    cs: &mut CS,
+    index: Expression,
+    table: &[Expression],
+) -> Result<Expression, VMError> {
+    if table.is_empty() {
+        return Err(VMError::R1CSError(R1CSError::GadgetError {
+            description: "lookup table must not be empty".to_string(),
+        }));
+    }
+
+    let mut result = Expression::constant(0u64);
+    let mut selectors_sum = Expression::constant(0u64);
+    for (i, entry) in table.iter().enumerate() {
+        // selector is 1 for the entry at `index` and 0 for all others.
+        let selector = is_zero(cs, index.clone() - Expression::constant(i as u64))?;
+        selectors_sum = selectors_sum + selector.clone();
+        result = result + selector.multiply(entry.clone(), cs);
+    }
+
+    // Exactly one selector must be set, so the index is within the table.
+    cs.constrain((selectors_sum - Expression::constant(1u64)).to_r1cs_lc());
+
+    Ok(result)
+}
+
+/// Constrains `x` to be in range [0, 2^n) with a range proof.
+/// Allocates `n` multipliers, the same as the `range` instruction.
+/// Fails with `WitnessOutOfRange` if the assignment of `x` is known and out of range,
+/// instead of producing a proof that does not verify.
+pub fn range<CS: ConstraintSystem>(cs: &mut CS, x: Expression, n: BitRange) -> Result<(), VMError> {
+    let (lc, assignment) = match x {
+        Expression::Constant(x) => (r1cs::LinearCombination::from(x), Some(x)),
+        Expression::LinearCombination(terms, assignment) => {
+            (r1cs::LinearCombination::from_iter(terms), assignment)
+        }
+    };
+    if assignment.map_or(false, |x| !x.in_range(n)) {
+        return Err(VMError::WitnessOutOfRange);
+    }
+    spacesuit::range_proof(cs, lc, assignment.map(|x| x.to_scalar()), n)
+        .map_err(|_| VMError::R1CSInconsistency)
+}
+
+/// Constrains the transaction's `mintime` (see `TxHeader::mintime_expr`) to be at least `time`,
+/// so the transaction cannot be included in a block before `time`.
+/// Proves that `mintime - time` is in range [0, 2^64).
+pub fn after<CS: ConstraintSystem>(
+    cs: &mut CS,
+    mintime: Expression,
+    time: u64,
+) -> Result<(), VMError> {
+    range(cs, mintime - Expression::constant(time), BitRange::u64())
+}
+
+/// Constrains the transaction's `maxtime` (see `TxHeader::maxtime_expr`) to be at most `time`,
+/// so the transaction cannot be included in a block after `time`.
+/// Proves that `time - maxtime` is in range [0, 2^64).
+pub fn before<CS: ConstraintSystem>(
+    cs: &mut CS,
+    maxtime: Expression,
+    time: u64,
+) -> Result<(), VMError> {
+    range(cs, Expression::constant(time) - maxtime, BitRange::u64())
+}
+
+/// Computes the SHA-256 hash of the message given as a sequence of bytes
+/// and returns the 32 bytes of the hash. Each byte of the message is constrained
+/// to be in range [0, 256); the length of the message is public.
+/// Allocates 8 multipliers per byte that is not a constant
+/// and about 26,000 multipliers per 64-byte block of the padded message.
+pub fn sha256<CS: ConstraintSystem>(
+    cs: &mut CS,
+    message: &[Expression],
+) -> Result<Vec<Expression>, VMError> {
+    SHA256.hash(cs, message)
+}
+
+/// Computes the SHA-512 hash of the message given as a sequence of bytes
+/// and returns the 64 bytes of the hash, like `sha256`.
+/// Allocates 8 multipliers per byte that is not a constant
+/// and about 67,000 multipliers per 128-byte block of the padded message.
+pub fn sha512<CS: ConstraintSystem>(
+    cs: &mut CS,
+    message: &[Expression],
+) -> Result<Vec<Expression>, VMError> {
+    SHA512.hash(cs, message)
+}
+
+/// Returns a constraint that evaluates to true if `hash` is the SHA-256 hash
+/// of the `preimage` bytes (see `sha256`). The hash is compared in 128-bit chunks.
+pub fn sha256_preimage<CS: ConstraintSystem>(
+    cs: &mut CS,
+    preimage: &[Expression],
+    hash: &[u8; 32],
+) -> Result<Constraint, VMError> {
+    SHA256.preimage(cs, preimage, hash)
+}
+
+/// Returns a constraint that evaluates to true if `hash` is the SHA-512 hash
+/// of the `preimage` bytes (see `sha512`). The hash is compared in 128-bit chunks.
+pub fn sha512_preimage<CS: ConstraintSystem>(
+    cs: &mut CS,
+    preimage: &[Expression],
+    hash: &[u8; 64],
+) -> Result<Constraint, VMError> {
+    SHA512.preimage(cs, preimage, hash)
+}
+
+/// Computes the Poseidon hash of the expressions (see `Poseidon::hash`).
+/// Allocates 243 multipliers per pair of inputs, except for the S-boxes applied to constants.
+pub fn poseidon<CS: ConstraintSystem>(
+    cs: &mut CS,
+    poseidon: &Poseidon,
+    inputs: &[Expression],
+) -> Expression {
+    poseidon.hash_expr(cs, inputs)
+}
+
+/// Computes the nullifier of a one-time claim with the given `secret` in the `domain`
+/// (see `Poseidon::nullifier`). The result is published with the `nullify` instruction,
+/// which prevents the claim from being redeemed twice.
+/// Allocates 243 multipliers, except for the S-boxes applied to constants.
+pub fn nullifier<CS: ConstraintSystem>(
+    cs: &mut CS,
+    poseidon: &Poseidon,
+    domain: Expression,
+    secret: Expression,
+) -> Expression {
+    poseidon.hash_expr(cs, &[domain, secret])
+}
+
+/// Computes the root of a Poseidon Merkle tree from the `leaf` and its path
+/// (see `Poseidon::merkle_path`). Each step of the path is a pair of a bit,
+/// which is 1 if the neighbor is on the left, and the neighbor's hash,
+/// so the position of the leaf can remain secret.
+/// The bits are constrained to be either 0 or 1.
+/// Allocates 245 multipliers per step and 243 multipliers for the leaf.
+pub fn poseidon_merkle_root<CS: ConstraintSystem>(
+    cs: &mut CS,
+    poseidon: &Poseidon,
+    leaf: Expression,
+    path: &[(Expression, Expression)],
+) -> Result<Expression, VMError> {
+    let (root, bits) = merkle_path(cs, poseidon, leaf, path);
+    for bit in bits {
+        bit.verify(cs)?;
+    }
+    Ok(root)
+}
+
+/// Returns a constraint that evaluates to true if `leaf` belongs to the Poseidon Merkle tree
+/// with the given `root`, proven with the path as in `poseidon_merkle_root`.
+/// The constraint also checks that the directions of the path are bits,
+/// so membership can be combined with other constraints, e.g. to prove membership in one of several sets.
+/// Allocates the same multipliers as `poseidon_merkle_root`.
+pub fn merkle_membership<CS: ConstraintSystem>(
+    cs: &mut CS,
+    poseidon: &Poseidon,
+    leaf: Expression,
+    path: Vec<(Expression, Expression)>,
+    root: Expression,
+) -> Constraint {
+    let (computed, bits) = merkle_path(cs, poseidon, leaf, &path);
+    bits.into_iter()
+        .fold(Constraint::Eq(computed, root), |c, bit| {
+            Constraint::And(Box::new(c), Box::new(bit))
+        })
+}
+
+/// Computes the root of the Merkle tree from the leaf and its path,
+/// and returns it together with the constraints that the directions of the path are bits.
+fn merkle_path<CS: ConstraintSystem>(
+    cs: &mut CS,
+    poseidon: &Poseidon,
+    leaf: Expression,
+    path: &[(Expression, Expression)],
+) -> (Expression, Vec<Constraint>) {
+    let mut node = poseidon.hash_expr(cs, &[leaf]);
+    let mut bits = Vec::with_capacity(path.len());
+    for (is_left, neighbor) in path.iter() {
+        bits.push(boolean(cs, is_left.clone()));
+        // Swaps the node and the neighbor if the neighbor is on the left.
+        let swap = is_left.clone().multiply(neighbor.clone() - &node, cs);
+        let left = node.clone() + swap.clone();
+        let right = neighbor.clone() - &swap;
+        node = poseidon.hash_expr(cs, &[left, right]);
+    }
+    (node, bits)
+}
+
+/// Word of a SHA-2 function as a list of bits in little-endian order.
+type Word = Vec<Expression>;
+
+/// Parameters of a SHA-2 hash function.
+pub(crate) struct Sha2 {
+    /// Size of a word in bits.
+    pub(crate) word_bits: usize,
+    /// Initial hash value.
+    h: [u64; 8],
+    /// Round constants, one per round.
+    pub(crate) k: &'static [u64],
+    /// Rotations of `Σ0` and `Σ1`.
+    big_sigma: [[usize; 3]; 2],
+    /// Two rotations and a shift of `σ0` and `σ1`.
+    pub(crate) small_sigma: [[usize; 3]; 2],
+}
+
+pub(crate) const SHA256: Sha2 = Sha2 {
+    word_bits: 32,
+    h: [
+        0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+        0x5be0cd19,
+    ],
+    k: &[
+        0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4,
+        0xab1c5ed5, 0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe,
+        0x9bdc06a7, 0xc19bf174, 0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f,
+        0x4a7484aa, 0x5cb0a9dc, 0x76f988da, 0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7,
+        0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967, 0x27b70a85, 0x2e1b2138, 0x4d2c6dfc,
+        0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85, 0xa2bfe8a1, 0xa81a664b,
+        0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070, 0x19a4c116,
+        0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+        0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7,
+        0xc67178f2,
+    ],
+    big_sigma: [[2, 13, 22], [6, 11, 25]],
+    small_sigma: [[7, 18, 3], [17, 19, 10]],
+};
+
+pub(crate) const SHA512: Sha2 = Sha2 {
+    word_bits: 64,
+    h: [
+        0x6a09e667f3bcc908,
+        0xbb67ae8584caa73b,
+        0x3c6ef372fe94f82b,
+        0xa54ff53a5f1d36f1,
+        0x510e527fade682d1,
+        0x9b05688c2b3e6c1f,
+        0x1f83d9abfb41bd6b,
+        0x5be0cd19137e2179,
+    ],
+    k: &[
+        0x428a2f98d728ae22,
+        0x7137449123ef65cd,
+        0xb5c0fbcfec4d3b2f,
+        0xe9b5dba58189dbbc,
+        0x3956c25bf348b538,
+        0x59f111f1b605d019,
+        0x923f82a4af194f9b,
+        0xab1c5ed5da6d8118,
+        0xd807aa98a3030242,
+        0x12835b0145706fbe,
+        0x243185be4ee4b28c,
+        0x550c7dc3d5ffb4e2,
+        0x72be5d74f27b896f,
+        0x80deb1fe3b1696b1,
+        0x9bdc06a725c71235,
+        0xc19bf174cf692694,
+        0xe49b69c19ef14ad2,
+        0xefbe4786384f25e3,
+        0x0fc19dc68b8cd5b5,
+        0x240ca1cc77ac9c65,
+        0x2de92c6f592b0275,
+        0x4a7484aa6ea6e483,
+        0x5cb0a9dcbd41fbd4,
+        0x76f988da831153b5,
+        0x983e5152ee66dfab,
+        0xa831c66d2db43210,
+        0xb00327c898fb213f,
+        0xbf597fc7beef0ee4,
+        0xc6e00bf33da88fc2,
+        0xd5a79147930aa725,
+        0x06ca6351e003826f,
+        0x142929670a0e6e70,
+        0x27b70a8546d22ffc,
+        0x2e1b21385c26c926,
+        0x4d2c6dfc5ac42aed,
+        0x53380d139d95b3df,
+        0x650a73548baf63de,
+        0x766a0abb3c77b2a8,
+        0x81c2c92e47edaee6,
+        0x92722c851482353b,
+        0xa2bfe8a14cf10364,
+        0xa81a664bbc423001,
+        0xc24b8b70d0f89791,
+        0xc76c51a30654be30,
+        0xd192e819d6ef5218,
+        0xd69906245565a910,
+        0xf40e35855771202a,
+        0x106aa07032bbd1b8,
+        0x19a4c116b8d2d0c8,
+        0x1e376c085141ab53,
+        0x2748774cdf8eeb99,
+        0x34b0bcb5e19b48a8,
+        0x391c0cb3c5c95a63,
+        0x4ed8aa4ae3418acb,
+        0x5b9cca4f7763e373,
+        0x682e6ff3d6b2b8a3,
+        0x748f82ee5defb2fc,
+        0x78a5636f43172f60,
+        0x84c87814a1f0ab72,
+        0x8cc702081a6439ec,
+        0x90befffa23631e28,
+        0xa4506cebde82bde9,
+        0xbef9a3f7b2c67915,
+        0xc67178f2e372532b,
+        0xca273eceea26619c,
+        0xd186b8c721c0c207,
+        0xeada7dd6cde0eb1e,
+        0xf57d4f7fee6ed178,
+        0x06f067aa72176fba,
+        0x0a637dc5a2c898a6,
+        0x113f9804bef90dae,
+        0x1b710b35131c471b,
+        0x28db77f523047d84,
+        0x32caab7b40c72493,
+        0x3c9ebe0a15c9bebc,
+        0x431d67c49c100d4c,
+        0x4cc5d4becb3e42b6,
+        0x597f299cfc657e2a,
+        0x5fcb6fab3ad6faec,
+        0x6c44198c4a475817,
+    ],
+    big_sigma: [[28, 34, 39], [14, 18, 41]],
+    small_sigma: [[1, 8, 7], [19, 61, 6]],
+};
+
+impl Sha2 {
+    /// Size of the hash in bytes: 8 words.
+    pub(crate) fn hash_size(&self) -> usize {
+        self.word_bits
+    }
+
+    /// Size of a block in bytes: 16 words.
+    pub(crate) fn block_size(&self) -> usize {
+        2 * self.word_bits
+    }
+
+    /// Number of blocks of the padded message of the given length:
+    /// the message is followed by 0x80 and its length in bits in two words.
+    pub(crate) fn blocks(&self, message_len: usize) -> usize {
+        (message_len + 1 + self.word_bits / 4 + self.block_size() - 1) / self.block_size()
+    }
+
+    fn hash<CS: ConstraintSystem>(
+        &self,
+        cs: &mut CS,
+        message: &[Expression],
+    ) -> Result<Vec<Expression>, VMError> {
+        let word_bytes = self.word_bits / 8;
+
+        // Pad the message with 0x80, zeros and its length in bits to a multiple of the block size.
+        let mut bytes = message.to_vec();
+        bytes.push(Expression::constant(0x80u64));
+        while bytes.len() % self.block_size() != self.block_size() - 2 * word_bytes {
+            bytes.push(Expression::constant(0u64));
+        }
+        let bit_length = (message.len() as u64) * 8;
+        for _ in 8..2 * word_bytes {
+            bytes.push(Expression::constant(0u64));
+        }
+        bytes.extend(
+            bit_length
+                .to_be_bytes()
+                .iter()
+                .map(|b| Expression::constant(u64::from(*b))),
+        );
+
+        // Words are big-endian, while the bits of a word are in little-endian order.
+        let mut words = Vec::with_capacity(bytes.len() / word_bytes);
+        for chunk in bytes.chunks(word_bytes) {
+            let mut word = Vec::with_capacity(self.word_bits);
+            for byte in chunk.iter().rev() {
+                word.extend(to_bits(cs, byte.clone(), 8)?);
+            }
+            words.push(word);
+        }
+
+        let mut state = self
+            .h
+            .iter()
+            .map(|h| self.constant_word(*h))
+            .collect::<Vec<_>>();
+        for block in words.chunks(16) {
+            state = self.compress(cs, &state, block)?;
+        }
+
+        let mut hash = Vec::with_capacity(8 * word_bytes);
+        for word in state.iter() {
+            for i in (0..word_bytes).rev() {
+                hash.push(pack_bits(&word[8 * i..8 * i + 8]));
+            }
+        }
+        Ok(hash)
+    }
+
+    /// Returns a constraint that compares the hash of the preimage with the expected bytes
+    /// in 128-bit chunks. Fails if the expected hash has a wrong length.
+    pub(crate) fn preimage<CS: ConstraintSystem>(
+        &self,
+        cs: &mut CS,
+        preimage: &[Expression],
+        hash: &[u8],
+    ) -> Result<Constraint, VMError> {
+        if hash.len() != self.hash_size() {
+            return Err(VMError::FormatError);
+        }
+        let computed = self.hash(cs, preimage)?;
+
+        let mut weights = Vec::with_capacity(16);
+        let mut weight = Scalar::one();
+        for _ in 0..16 {
+            weights.push(weight);
+            weight = weight * Scalar::from(256u64);
+        }
+        let mut chunks = computed
+            .chunks(16)
+            .zip(hash.chunks(16))
+            .map(|(computed, expected)| {
+                let mut bytes = [0u8; 32];
+                bytes[..16].copy_from_slice(expected);
+                Constraint::Eq(
+                    Expression::dot(computed, &weights),
+                    Expression::constant(Scalar::from_bytes_mod_order(bytes)),
+                )
+            });
+        let first = chunks.next().expect("hash has at least one chunk");
+        Ok(chunks.fold(first, |c, chunk| {
+            Constraint::And(Box::new(c), Box::new(chunk))
+        }))
+    }
+
+    /// Applies the compression function to the state and a block of 16 words.
+    fn compress<CS: ConstraintSystem>(
+        &self,
+        cs: &mut CS,
+        state: &[Word],
+        block: &[Word],
+    ) -> Result<Vec<Word>, VMError> {
+        let [s0, s1] = self.small_sigma;
+        let [b0, b1] = self.big_sigma;
+
+        // Message schedule
+        let mut w = block.to_vec();
+        for t in 16..self.k.len() {
+            let sigma0 = xor3_words(
+                cs,
+                &rotr(&w[t - 15], s0[0]),
+                &rotr(&w[t - 15], s0[1]),
+                &shr(&w[t - 15], s0[2]),
+            );
+            let sigma1 = xor3_words(
+                cs,
+                &rotr(&w[t - 2], s1[0]),
+                &rotr(&w[t - 2], s1[1]),
+                &shr(&w[t - 2], s1[2]),
+            );
+            let next = add_words(cs, &[&sigma1, &w[t - 7], &sigma0, &w[t - 16]])?;
+            w.push(next);
+        }
+
+        // Working variables a, b, c, d, e, f, g, h
+        let mut v = state.to_vec();
+        for (t, k) in self.k.iter().enumerate() {
+            let sigma1 = xor3_words(
+                cs,
+                &rotr(&v[4], b1[0]),
+                &rotr(&v[4], b1[1]),
+                &rotr(&v[4], b1[2]),
+            );
+            let ch = (0..self.word_bits)
+                .map(|i| select(cs, v[4][i].clone(), v[5][i].clone(), v[6][i].clone()))
+                .collect::<Word>();
+            let sigma0 = xor3_words(
+                cs,
+                &rotr(&v[0], b0[0]),
+                &rotr(&v[0], b0[1]),
+                &rotr(&v[0], b0[2]),
+            );
+            let maj = (0..self.word_bits)
+                .map(|i| majority(cs, &v[0][i], &v[1][i], &v[2][i]))
+                .collect::<Word>();
+            let k = self.constant_word(*k);
+
+            // e = d + T1, a = T1 + T2, where T1 = h + Σ1 + ch + k + w, T2 = Σ0 + maj
+            let e = add_words(cs, &[&v[3], &v[7], &sigma1, &ch, &k, &w[t]])?;
+            let a = add_words(cs, &[&v[7], &sigma1, &ch, &k, &w[t], &sigma0, &maj])?;
+            v.pop();
+            v.insert(0, a);
+            v[4] = e;
+        }
+
+        state
+            .iter()
+            .zip(v.iter())
+            .map(|(h, x)| add_words(cs, &[h, x]))
+            .collect()
+    }
+
+    fn constant_word(&self, x: u64) -> Word {
+        (0..self.word_bits)
+            .map(|i| Expression::constant((x >> i) & 1))
+            .collect()
+    }
+}
+
+/// Decomposes `x` into `n` bits in little-endian order (see `unpack_bits`).
+/// Constants are decomposed without allocating multipliers.
+fn to_bits<CS: ConstraintSystem>(cs: &mut CS, x: Expression, n: usize) -> Result<Word, VMError> {
+    match x {
+        Expression::Constant(c) => {
+            let bytes = c.to_scalar().to_bytes();
+            let bit = |i: usize| (bytes[i / 8] >> (i % 8)) & 1;
+            if (n..256).any(|i| bit(i) != 0) {
+                return Err(VMError::BadArguments);
+            }
+            Ok((0..n)
+                .map(|i| Expression::constant(u64::from(bit(i))))
+                .collect())
+        }
+        x => unpack_bits(
+            cs,
+            x,
+            BitRange::new_wide(n).ok_or(VMError::InvalidBitrange)?,
+        ),
+    }
+}
+
+fn rotr(word: &Word, n: usize) -> Word {
+    (0..word.len())
+        .map(|i| word[(i + n) % word.len()].clone())
+        .collect()
+}
+
+fn shr(word: &Word, n: usize) -> Word {
+    (0..word.len())
+        .map(|i| match word.get(i + n) {
+            Some(bit) => bit.clone(),
+            None => Expression::constant(0u64),
+        })
+        .collect()
+}
+
+/// Returns `a ^ b` for bits: `a + b - 2ab`. Allocates one multiplier unless either bit is a constant.
+fn xor<CS: ConstraintSystem>(cs: &mut CS, a: &Expression, b: &Expression) -> Expression {
+    let ab = a.clone().multiply(b.clone(), cs);
+    a.clone() + b.clone() - ab * Scalar::from(2u64)
+}
+
+fn xor3_words<CS: ConstraintSystem>(cs: &mut CS, a: &Word, b: &Word, c: &Word) -> Word {
+    (0..a.len())
+        .map(|i| {
+            let ab = xor(cs, &a[i], &b[i]);
+            xor(cs, &ab, &c[i])
+        })
+        .collect()
+}
+
+/// Returns the majority of bits: `bc + a(b + c - 2bc)`. Allocates two multipliers.
+fn majority<CS: ConstraintSystem>(
+    cs: &mut CS,
+    a: &Expression,
+    b: &Expression,
+    c: &Expression,
+) -> Expression {
+    let bc = b.clone().multiply(c.clone(), cs);
+    let b_xor_c = b.clone() + c.clone() - bc.clone() * Scalar::from(2u64);
+    bc + a.clone().multiply(b_xor_c, cs)
+}
+
+/// Adds up the words modulo 2^n by decomposing their sum into bits
+/// and dropping the carry bits.
+fn add_words<CS: ConstraintSystem>(cs: &mut CS, words: &[&Word]) -> Result<Word, VMError> {
+    let n = words[0].len();
+    let sum = Expression::sum(words.iter().map(|word| pack_bits(word)));
+    let mut bits = to_bits(cs, sum, n + carry_bits(words.len()))?;
+    bits.truncate(n);
+    Ok(bits)
+}
+
+/// Number of bits needed for the carry of a sum of `k` words.
+pub(crate) fn carry_bits(k: usize) -> usize {
+    let mut bits = 0;
+    while (1 << bits) < k {
+        bits += 1;
+    }
+    bits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::poseidon::PoseidonNeighbor;
+    use bulletproofs::{BulletproofGens, PedersenGens};
+    use merlin::Transcript;
+    use sha2::{Digest, Sha256, Sha512};
+
+    /// Commits `values` in a prover and a verifier, builds the circuit
+    /// with `$body` where `$cs` is the constraint system and `$inputs` are the committed values,
+    /// and checks the resulting R1CS proof.
+    /// The capacity of the generators can be specified for large circuits.
+    macro_rules! prove_and_verify {
+        ($values:expr, |$cs:ident, $inputs:ident| $body:expr) => {
+            prove_and_verify!($values, 256, |$cs, $inputs| $body)
+        };
+        ($values:expr, $capacity:expr, |$cs:ident, $inputs:ident| $body:expr) => {{
+            let values: Vec<u64> = $values;
+            let pc_gens = PedersenGens::default();
+            let bp_gens = BulletproofGens::new($capacity, 1);
+
+            let proof_and_commitments = (|| -> Result<_, VMError> {
+                let mut transcript = Transcript::new(b"GadgetsTest");
+                let mut prover = r1cs::Prover::new(&bp_gens, &pc_gens, &mut transcript);
+                let mut commitments = Vec::new();
+                let mut $inputs = Vec::new();
+                for v in values.iter() {
+                    let (com, var) = prover.commit(Scalar::from(*v), Scalar::from(1u64));
+                    commitments.push(com);
+                    $inputs.push(Expression::LinearCombination(
+                        vec![(var, Scalar::one())],
+                        Some((*v).into()),
+                    ));
+                }
+                {
+                    let $cs = &mut prover;
+                    $body?;
+                }
+                Ok((prover.prove().map_err(VMError::R1CSError)?, commitments))
+            })();
+
+            proof_and_commitments.and_then(|(proof, commitments)| {
+                let mut transcript = Transcript::new(b"GadgetsTest");
+                let mut verifier = r1cs::Verifier::new(&bp_gens, &pc_gens, &mut transcript);
+                let $inputs = commitments
+                    .into_iter()
+                    .map(|com| {
+                        let var = verifier.commit(com);
+                        Expression::LinearCombination(vec![(var, Scalar::one())], None)
+                    })
+                    .collect::<Vec<_>>();
+                {
+                    let $cs = &mut verifier;
+                    $body?;
+                }
+                verifier.verify(&proof).map_err(VMError::R1CSError)
+            })
+        }};
+    }
+
+    fn expect<CS: ConstraintSystem>(
+        cs: &mut CS,
+        expr: Expression,
+        value: u64,
+    ) -> Result<(), VMError> {
+        Constraint::Eq(expr, Expression::constant(value)).verify(cs)
+    }
+
+    #[test]
+    fn boolean_gadget() {
+        for (x, ok) in [(0, true), (1, true), (2, false)].iter() {
+            let res = prove_and_verify!(vec![*x], |cs, inputs| {
+                boolean(cs, inputs[0].clone()).verify(cs)
+            });
+            assert_eq!(res.is_ok(), *ok);
+        }
+    }
+
+    #[test]
+    fn is_zero_gadget() {
+        for (x, expected) in [(0, 1), (1, 0), (100, 0)].iter() {
+            let res = prove_and_verify!(vec![*x], |cs, inputs| {
+                let y = is_zero(cs, inputs[0].clone())?;
+                expect(cs, y, *expected)
+            });
+            assert!(res.is_ok());
+
+            let wrong = prove_and_verify!(vec![*x], |cs, inputs| {
+                let y = is_zero(cs, inputs[0].clone())?;
+                expect(cs, y, 1 - *expected)
+            });
+            assert!(wrong.is_err());
+        }
+
+        // Constants are compared without allocating multipliers.
+        let mut transcript = Transcript::new(b"GadgetsTest");
+        let pc_gens = PedersenGens::default();
+        let bp_gens = BulletproofGens::new(256, 1);
+        let mut prover = r1cs::Prover::new(&bp_gens, &pc_gens, &mut transcript);
+        match is_zero(&mut prover, Expression::constant(0u64)).unwrap() {
+            Expression::Constant(y) => assert_eq!(y, ScalarWitness::from(1u64)),
+            _ => panic!("is_zero of a constant must be a constant"),
+        }
+    }
+
+    #[test]
+    fn select_gadget() {
+        let res = prove_and_verify!(vec![1, 10, 20], |cs, inputs| {
+            let r = select(cs, inputs[0].clone(), inputs[1].clone(), inputs[2].clone());
+            expect(cs, r, 10)
+        });
+        assert!(res.is_ok());
+
+        let res = prove_and_verify!(vec![0, 10, 20], |cs, inputs| {
+            let r = select(cs, inputs[0].clone(), inputs[1].clone(), inputs[2].clone());
+            expect(cs, r, 20)
+        });
+        assert!(res.is_ok());
+
+        let res = prove_and_verify!(vec![0, 10, 20], |cs, inputs| {
+            let r = select(cs, inputs[0].clone(), inputs[1].clone(), inputs[2].clone());
+            expect(cs, r, 10)
+        });
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn unpack_and_pack_bits() {
+        let res = prove_and_verify!(vec![0b1011], |cs, inputs| {
+            let bits = unpack_bits(cs, inputs[0].clone(), BitRange::new(4).unwrap())?;
+            expect(cs, bits[0].clone(), 1)?;
+            expect(cs, bits[1].clone(), 1)?;
+            expect(cs, bits[2].clone(), 0)?;
+            expect(cs, bits[3].clone(), 1)?;
+            expect(cs, pack_bits(&bits[1..]), 0b101)
+        });
+        assert!(res.is_ok());
+
+        // Value does not fit into 4 bits
+        let res = prove_and_verify!(vec![16], |cs, inputs| {
+            unpack_bits(cs, inputs[0].clone(), BitRange::new(4).unwrap()).map(|_| ())
+        });
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn lookup_gadget() {
+        let table = || {
+            vec![
+                Expression::constant(7u64),
+                Expression::constant(11u64),
+                Expression::constant(13u64),
+            ]
+        };
+        for (i, v) in [(0, 7), (1, 11), (2, 13)].iter() {
+            let res = prove_and_verify!(vec![*i], |cs, inputs| {
+                let r = lookup(cs, inputs[0].clone(), &table())?;
+                expect(cs, r, *v)
+            });
+            assert!(res.is_ok());
+        }
+
+        // Index out of bounds
+        let res = prove_and_verify!(vec![3], |cs, inputs| {
+            lookup(cs, inputs[0].clone(), &table()).map(|_| ())
+        });
+        assert!(res.is_err());
+
+        // Table of committed values
+        let res = prove_and_verify!(vec![1, 100, 200], |cs, inputs| {
+            let r = lookup(cs, inputs[0].clone(), &inputs[1..])?;
+            expect(cs, r, 200)
+        });
+        assert!(res.is_ok());
+    }
+
+    #[test]
+    fn range_gadget() {
+        let res = prove_and_verify!(vec![255], |cs, inputs| {
+            range(cs, inputs[0].clone(), BitRange::new(8).unwrap())
+        });
+        assert!(res.is_ok());
+
+        // The prover reports the witness out of range instead of making an invalid proof.
+        let res = prove_and_verify!(vec![256], |cs, inputs| {
+            range(cs, inputs[0].clone(), BitRange::new(8).unwrap())
+        });
+        assert_eq!(res, Err(VMError::WitnessOutOfRange));
+
+        let res = prove_and_verify!(vec![1], |cs, inputs| {
+            range(cs, -inputs[0].clone(), BitRange::u64())
+        });
+        assert_eq!(res, Err(VMError::WitnessOutOfRange));
+    }
+
+    #[test]
+    fn timelock_gadgets() {
+        let header = |mintime, maxtime| crate::vm::TxHeader {
+            version: 0,
+            mintime,
+            maxtime,
+            maxcost: u64::max_value(),
+        };
+        for (h, ok) in [
+            (header(10, 20), true),
+            (header(9, 20), false),
+            (header(10, 21), false),
+        ]
+        .iter()
+        {
+            let res = prove_and_verify!(vec![], |cs, _inputs| {
+                after(cs, h.mintime_expr(), 10)?;
+                before(cs, h.maxtime_expr(), 20)
+            });
+            assert_eq!(res.is_ok(), *ok);
+        }
+
+        // Committed times are compared the same way.
+        let res = prove_and_verify!(vec![15], |cs, inputs| {
+            after(cs, inputs[0].clone(), 10)?;
+            before(cs, inputs[0].clone(), 20)
+        });
+        assert!(res.is_ok());
+    }
+
+    /// Computes the hash of a constant message, which does not allocate multipliers.
+    fn constant_hash(sha2: &Sha2, message: &[u8]) -> Vec<u8> {
+        let mut transcript = Transcript::new(b"GadgetsTest");
+        let pc_gens = PedersenGens::default();
+        let bp_gens = BulletproofGens::new(256, 1);
+        let mut prover = r1cs::Prover::new(&bp_gens, &pc_gens, &mut transcript);
+        let message = message
+            .iter()
+            .map(|b| Expression::constant(u64::from(*b)))
+            .collect::<Vec<_>>();
+        sha2.hash(&mut prover, &message)
+            .unwrap()
+            .into_iter()
+            .map(|byte| match byte {
+                Expression::Constant(b) => b.to_scalar().to_bytes()[0],
+                _ => panic!("hash of a constant message must be a constant"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn sha256_gadget() {
+        assert_eq!(
+            constant_hash(&SHA256, b"abc"),
+            hex::decode("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad")
+                .unwrap()
+        );
+        assert_eq!(
+            constant_hash(
+                &SHA256,
+                b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"
+            ),
+            hex::decode("248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1")
+                .unwrap()
+        );
+        // Padding of the messages around the block boundaries.
+        for len in [0, 55, 56, 63, 64, 119, 120].iter() {
+            let message = vec![0xa5u8; *len];
+            assert_eq!(
+                constant_hash(&SHA256, &message),
+                Sha256::digest(&message).to_vec()
+            );
+        }
+    }
+
+    #[test]
+    fn sha512_gadget() {
+        assert_eq!(
+            constant_hash(&SHA512, b"abc"),
+            hex::decode(
+                "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a\
+                 2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f"
+            )
+            .unwrap()
+        );
+        for len in [0, 111, 112, 127, 128, 239, 240].iter() {
+            let message = vec![0xa5u8; *len];
+            assert_eq!(
+                constant_hash(&SHA512, &message),
+                Sha512::digest(&message).to_vec()
+            );
+        }
+    }
+
+    #[test]
+    fn sha256_preimage_gadget() {
+        let secret = b"ZkVM atomic swap";
+        let mut hash = [0u8; 32];
+        hash.copy_from_slice(&Sha256::digest(secret));
+
+        let values = secret.iter().map(|b| u64::from(*b)).collect();
+        let res = prove_and_verify!(values, 32768, |cs, inputs| {
+            sha256_preimage(cs, &inputs, &hash)?.verify(cs)
+        });
+        assert!(res.is_ok());
+
+        // Bytes of the preimage are range-checked.
+        let res = prove_and_verify!(vec![256], 32768, |cs, inputs| {
+            sha256(cs, &inputs).map(|_| ())
+        });
+        assert!(res.is_err());
+
+        let res = prove_and_verify!(vec![], |cs, _inputs| {
+            sha256_preimage(cs, &[Expression::constant(0u64)], &hash)?.verify(cs)
+        });
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn poseidon_merkle_gadget() {
+        let p = Poseidon::new();
+        let leaves = (0..5u64).map(Scalar::from).collect::<Vec<_>>();
+        let root = p.merkle_root(&leaves);
+        for index in 0..leaves.len() {
+            // Committed leaf and directions, followed by the neighbors as constants.
+            let path = p.merkle_path(&leaves, index).unwrap();
+            let mut values = vec![index as u64];
+            let mut neighbors = Vec::new();
+            for neighbor in path.iter() {
+                match neighbor {
+                    PoseidonNeighbor::Left(l) => {
+                        values.push(1);
+                        neighbors.push(Expression::constant(*l));
+                    }
+                    PoseidonNeighbor::Right(r) => {
+                        values.push(0);
+                        neighbors.push(Expression::constant(*r));
+                    }
+                }
+            }
+            let prove_root = |values: Vec<u64>, root: Scalar| {
+                prove_and_verify!(values, 2048, |cs, inputs| {
+                    let path = inputs[1..]
+                        .iter()
+                        .cloned()
+                        .zip(neighbors.iter().cloned())
+                        .collect::<Vec<_>>();
+                    let computed = poseidon_merkle_root(cs, &p, inputs[0].clone(), &path)?;
+                    Constraint::Eq(computed, Expression::constant(root)).verify(cs)
+                })
+            };
+            assert!(prove_root(values.clone(), root).is_ok());
+            assert!(prove_root(values.clone(), root + Scalar::one()).is_err());
+
+            // Directions must be bits.
+            values[1] = 2;
+            assert!(prove_root(values, root).is_err());
+        }
+
+        let res = prove_and_verify!(vec![1, 2], |cs, inputs| {
+            let hash = poseidon(cs, &p, &inputs);
+            let expected = p.hash(&[Scalar::from(1u64), Scalar::from(2u64)]);
+            Constraint::Eq(hash, Expression::constant(expected)).verify(cs)
+        });
+        assert!(res.is_ok());
+    }
+
+    #[test]
+    fn merkle_membership_gadget() {
+        let p = Poseidon::new();
+        let leaves = (10..17u64).map(Scalar::from).collect::<Vec<_>>();
+        let root = p.merkle_root(&leaves);
+        let other_root = p.merkle_root(&leaves[..4]);
+
+        // Leaf 13 is the last leaf of the left subtree of 4 leaves.
+        let path = p.merkle_path(&leaves, 3).unwrap();
+        let prove_membership = |values: Vec<u64>, roots: Vec<Scalar>| {
+            prove_and_verify!(values, 4096, |cs, inputs| {
+                let mut membership: Option<Constraint> = None;
+                for root in roots.iter() {
+                    let steps = inputs[1..]
+                        .iter()
+                        .zip(path.iter())
+                        .map(|(bit, neighbor)| match neighbor {
+                            PoseidonNeighbor::Left(h) | PoseidonNeighbor::Right(h) => {
+                                (bit.clone(), Expression::constant(*h))
+                            }
+                        })
+                        .collect::<Vec<_>>();
+                    let leaf = inputs[0].clone();
+                    let m = merkle_membership(cs, &p, leaf, steps, Expression::constant(*root));
+                    membership = Some(match membership {
+                        Some(c) => Constraint::Or(Box::new(c), Box::new(m)),
+                        None => m,
+                    });
+                }
+                membership.unwrap().verify(cs)
+            })
+        };
+
+        assert!(prove_membership(vec![13, 1, 1, 0], vec![root]).is_ok());
+        assert!(prove_membership(vec![14, 1, 1, 0], vec![root]).is_err());
+        // The leaf is not in the tree of the first 4 leaves with this path,
+        // but it is in one of the two trees.
+        assert!(prove_membership(vec![13, 1, 1, 0], vec![other_root]).is_err());
+        assert!(prove_membership(vec![13, 1, 1, 0], vec![other_root, root]).is_ok());
+        // Directions must be bits.
+        assert!(prove_membership(vec![13, 2, 1, 0], vec![root]).is_err());
+    }
+
+    #[test]
+    fn nullifier_gadget() {
+        let p = Poseidon::new();
+        let expected = p.nullifier(Scalar::from(7u64), Scalar::from(42u64));
+        let res = prove_and_verify!(vec![42], 512, |cs, inputs| {
+            let tag = nullifier(cs, &p, Expression::constant(7u64), inputs[0].clone());
+            Constraint::Eq(tag, Expression::constant(expected)).verify(cs)
+        });
+        assert!(res.is_ok());
+    }
+}
@@ -0,0 +1,97 @@
+//! Precomputed generators.
+//!
+//! Pedersen generators are initialized once per process, together with the tables
+//! for fixed-base multiplications by `B` and `B_blinding`, which are several times faster
+//! than the variable-base multiplications. Bulletproofs generators are expensive to create,
+//! so they are shared by the transactions via `GensCache` and grown on demand.
+
+use bulletproofs::{BulletproofGens, PedersenGens};
+use curve25519_dalek::constants::RISTRETTO_BASEPOINT_TABLE;
+use curve25519_dalek::ristretto::{RistrettoBasepointTable, RistrettoPoint};
+use curve25519_dalek::scalar::Scalar;
+
+lazy_static! {
+    static ref PC_GENS: PedersenGens = PedersenGens::default();
+    static ref B_BLINDING_TABLE: RistrettoBasepointTable =
+        RistrettoBasepointTable::create(&PC_GENS.B_blinding);
+}
+
+/// Returns the Pedersen generators used for the commitments.
+pub(crate) fn pedersen_gens() -> &'static PedersenGens {
+    &PC_GENS
+}
+
+/// Computes `x·B` using the precomputed table.
+pub(crate) fn mul_base(x: &Scalar) -> RistrettoPoint {
+    // `PedersenGens::B` is the Ristretto basepoint.
+    x * &RISTRETTO_BASEPOINT_TABLE
+}
+
+/// Computes `x·B_blinding` using the precomputed table.
+pub(crate) fn mul_blinding(x: &Scalar) -> RistrettoPoint {
+    x * &*B_BLINDING_TABLE
+}
+
+/// Computes the Pedersen commitment `value·B + blinding·B_blinding` using the precomputed tables.
+pub(crate) fn commit(value: &Scalar, blinding: &Scalar) -> RistrettoPoint {
+    mul_base(value) + mul_blinding(blinding)
+}
+
+/// Bulletproofs generators shared by the transactions,
+/// grown to the capacity of the largest constraint system seen so far.
+pub struct GensCache {
+    bp_gens: BulletproofGens,
+}
+
+impl GensCache {
+    /// Creates the generators of the given capacity.
+    pub fn new(capacity: usize) -> Self {
+        GensCache {
+            bp_gens: BulletproofGens::new(capacity, 1),
+        }
+    }
+
+    /// Returns the generators of at least the given capacity,
+    /// creating the missing generators if needed.
+    pub fn reserve(&mut self, capacity: usize) -> &BulletproofGens {
+        if self.bp_gens.gens_capacity < capacity {
+            self.bp_gens.increase_capacity(capacity);
+        }
+        &self.bp_gens
+    }
+
+    /// Returns the generators created so far.
+    pub fn bp_gens(&self) -> &BulletproofGens {
+        &self.bp_gens
+    }
+}
+
+impl Default for GensCache {
+    /// Creates the generators for the cloaked transactions with up to two outputs.
+    fn default() -> Self {
+        GensCache::new(256)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn precomputed_tables() {
+        let gens = PedersenGens::default();
+        let (v, f) = (Scalar::from(42u64), Scalar::from(7u64));
+        assert_eq!(mul_base(&v), v * gens.B);
+        assert_eq!(mul_blinding(&f), f * gens.B_blinding);
+        assert_eq!(commit(&v, &f), gens.commit(v, f));
+        assert_eq!(pedersen_gens().B_blinding, gens.B_blinding);
+    }
+
+    #[test]
+    fn gens_cache() {
+        let mut cache = GensCache::new(64);
+        assert_eq!(cache.reserve(32).gens_capacity, 64);
+        assert_eq!(cache.reserve(128).gens_capacity, 128);
+        assert_eq!(cache.bp_gens().gens_capacity, 128);
+    }
+}
@@ -0,0 +1,386 @@
+//! Hash time-locked contracts (HTLC) for atomic swaps.
+//!
+//! Implements the [atomic swap example](../docs/zkvm-spec.md#atomic-swap-example)
+//! from the specification. The value is locked with a disjunction of two programs:
+//! the _claim_ program pays it to the recipient if the preimage of the hash is revealed,
+//! and the _refund_ program pays it back to the sender after the timeout.
+//! The claim transaction pushes the bytes of the secret as public constants,
+//! and the claim program checks them with the `sha256` instruction.
+//!
+//! The other side of the swap is usually on another blockchain with its own conventions:
+//! `BitcoinHtlc` builds the [BIP-199](https://github.com/bitcoin/bips/blob/master/bip-0199.mediawiki)
+//! script, which uses the same SHA-256 hashlock, public key hashes and an absolute locktime
+//! in blocks or seconds instead of milliseconds.
+//!
+//! The party that picks the secret must use a longer timeout for its own HTLC,
+//! so the counterparty has enough time to claim it after learning the secret.
+
+use sha2::{Digest, Sha256};
+
+use crate::contract::Output;
+use crate::errors::VMError;
+use crate::ops::Instruction;
+use crate::predicate::Predicate;
+use crate::program::Program;
+use crate::types::Data;
+
+/// Size of the HTLC secret in bytes. The claim program checks a preimage of this size.
+pub const SECRET_SIZE: usize = 32;
+
+/// Returns the SHA-256 hash of the secret, which locks the HTLCs on both sides of the swap.
+pub fn hashlock(secret: &[u8]) -> [u8; 32] {
+    let mut hash = [0u8; 32];
+    hash.copy_from_slice(&Sha256::digest(secret));
+    hash
+}
+
+/// HTLC for ZkVM.
+#[derive(Clone, Debug)]
+pub struct Htlc {
+    /// SHA-256 hash of the secret.
+    pub hash: [u8; 32],
+    /// Predicate that receives the value when the secret is revealed.
+    pub recipient: Predicate,
+    /// Predicate that receives the value back after the timeout.
+    pub sender: Predicate,
+    /// Time after which the value can be refunded, in milliseconds since the Unix epoch.
+    pub timeout: u64,
+}
+
+/// HTLC script for Bitcoin and similar blockchains, as specified in BIP-199.
+#[derive(Clone, Debug)]
+pub struct BitcoinHtlc {
+    /// SHA-256 hash of the secret.
+    pub hash: [u8; 32],
+    /// HASH160 of the public key that receives the value when the secret is revealed.
+    pub recipient: [u8; 20],
+    /// HASH160 of the public key that receives the value back after the locktime.
+    pub sender: [u8; 20],
+    /// Locktime checked with `OP_CHECKLOCKTIMEVERIFY`: a block height if below 500,000,000,
+    /// or a Unix time in seconds otherwise.
+    pub locktime: u32,
+}
+
+impl Htlc {
+    /// Returns the program that pays the value to the recipient.
+    /// It expects the `SECRET_SIZE` bytes of the secret under the contract's value,
+    /// as constant expressions with the first byte deepest in the stack.
+    pub fn claim_program(&self) -> Program {
+        Program::build(|p| {
+            p.push(self.recipient.clone())
+                .output(1) // stack: secret bytes
+                .push(Data::Opaque(self.hash.to_vec()))
+                .sha256(SECRET_SIZE)
+                .verify()
+        })
+    }
+
+    /// Returns the program that pays the value back to the sender after the timeout.
+    pub fn refund_program(&self) -> Program {
+        Program::build(|p| p.after(self.timeout).push(self.sender.clone()).output(1))
+    }
+
+    /// Returns the predicate of the HTLC.
+    pub fn predicate(&self) -> Result<Predicate, VMError> {
+        Predicate::disjunction(vec![
+            Predicate::unblinded_program(self.claim_program()),
+            Predicate::unblinded_program(self.refund_program()),
+        ])
+    }
+
+    /// Adds instructions that lock the value on top of the stack in the HTLC.
+    pub fn lock(&self, p: &mut Program) -> Result<(), VMError> {
+        p.push(self.predicate()?).output(1);
+        Ok(())
+    }
+
+    /// Adds instructions that spend the HTLC with the secret, paying the value to the recipient.
+    /// Fails if the secret is not `SECRET_SIZE` bytes long or its hash does not match the HTLC.
+    pub fn claim(&self, p: &mut Program, contract: Output, secret: &[u8]) -> Result<(), VMError> {
+        if secret.len() != SECRET_SIZE || hashlock(secret) != self.hash {
+            return Err(VMError::InvalidPreimage);
+        }
+        push_secret(p, secret);
+        p.push(contract).input();
+        p.choose_predicate(self.predicate()?, |t| t.select(0)?.call())?;
+        Ok(())
+    }
+
+    /// Adds instructions that spend the HTLC after the timeout, paying the value back to the sender.
+    /// The transaction's `mintime` must not be earlier than the timeout.
+    pub fn refund(&self, p: &mut Program, contract: Output) -> Result<(), VMError> {
+        p.push(contract).input();
+        p.choose_predicate(self.predicate()?, |t| t.select(1)?.call())?;
+        Ok(())
+    }
+
+    /// Finds the secret in the program of a transaction that claimed the HTLC,
+    /// so it can be used to claim the other side of the swap.
+    pub fn find_secret(&self, program: &[u8]) -> Option<Vec<u8>> {
+        let instructions = Program::disassemble(program).ok()?;
+        // bytes pushed as constant expressions, in the order of the program
+        let bytes = instructions
+            .windows(2)
+            .filter_map(|pair| match pair {
+                [Instruction::Push(data), Instruction::Const] => {
+                    let scalar = data.clone().to_bytes();
+                    if scalar.len() == 32 && scalar[1..].iter().all(|b| *b == 0) {
+                        Some(scalar[0])
+                    } else {
+                        None
+                    }
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        bytes
+            .windows(SECRET_SIZE)
+            .find(|secret| hashlock(secret) == self.hash)
+            .map(|secret| secret.to_vec())
+    }
+}
+
+/// Pushes the bytes of the secret as constant expressions for the `sha256` instruction.
+fn push_secret(p: &mut Program, secret: &[u8]) {
+    for byte in secret.iter() {
+        p.push(u64::from(*byte)).r#const();
+    }
+}
+
+impl BitcoinHtlc {
+    /// Returns the script of the HTLC:
+    ///
+    /// ```text
+    /// OP_IF
+    ///     OP_SHA256 <hash> OP_EQUALVERIFY OP_DUP OP_HASH160 <recipient>
+    /// OP_ELSE
+    ///     <locktime> OP_CHECKLOCKTIMEVERIFY OP_DROP OP_DUP OP_HASH160 <sender>
+    /// OP_ENDIF
+    /// OP_EQUALVERIFY OP_CHECKSIG
+    /// ```
+    pub fn script(&self) -> Vec<u8> {
+        let mut script = Vec::new();
+        script.push(OP_IF);
+        script.push(OP_SHA256);
+        push_data(&mut script, &self.hash);
+        script.extend_from_slice(&[OP_EQUALVERIFY, OP_DUP, OP_HASH160]);
+        push_data(&mut script, &self.recipient);
+        script.push(OP_ELSE);
+        push_number(&mut script, self.locktime);
+        script.extend_from_slice(&[OP_CHECKLOCKTIMEVERIFY, OP_DROP, OP_DUP, OP_HASH160]);
+        push_data(&mut script, &self.sender);
+        script.push(OP_ENDIF);
+        script.extend_from_slice(&[OP_EQUALVERIFY, OP_CHECKSIG]);
+        script
+    }
+
+    /// Returns the witness that claims the HTLC with the secret,
+    /// given the recipient's signature and public key.
+    pub fn claim_witness(&self, signature: &[u8], pubkey: &[u8], secret: &[u8]) -> Vec<Vec<u8>> {
+        vec![
+            signature.to_vec(),
+            pubkey.to_vec(),
+            secret.to_vec(),
+            vec![1],
+            self.script(),
+        ]
+    }
+
+    /// Returns the witness that refunds the HTLC after the locktime,
+    /// given the sender's signature and public key.
+    pub fn refund_witness(&self, signature: &[u8], pubkey: &[u8]) -> Vec<Vec<u8>> {
+        vec![signature.to_vec(), pubkey.to_vec(), vec![], self.script()]
+    }
+
+    /// Finds the secret in the witness of a transaction that claimed the HTLC,
+    /// so it can be used to claim the other side of the swap.
+    pub fn find_secret(&self, witness: &[Vec<u8>]) -> Option<Vec<u8>> {
+        witness
+            .get(2)
+            .filter(|secret| hashlock(secret) == self.hash)
+            .cloned()
+    }
+}
+
+const OP_0: u8 = 0x00;
+const OP_1: u8 = 0x51;
+const OP_IF: u8 = 0x63;
+const OP_ELSE: u8 = 0x67;
+const OP_ENDIF: u8 = 0x68;
+const OP_DROP: u8 = 0x75;
+const OP_DUP: u8 = 0x76;
+const OP_EQUALVERIFY: u8 = 0x88;
+const OP_SHA256: u8 = 0xa8;
+const OP_HASH160: u8 = 0xa9;
+const OP_CHECKSIG: u8 = 0xac;
+const OP_CHECKLOCKTIMEVERIFY: u8 = 0xb1;
+
+/// Pushes data shorter than 76 bytes, prefixed with its length.
+fn push_data(script: &mut Vec<u8>, data: &[u8]) {
+    script.push(data.len() as u8);
+    script.extend_from_slice(data);
+}
+
+/// Pushes the number with the minimal encoding.
+fn push_number(script: &mut Vec<u8>, n: u32) {
+    match n {
+        0 => script.push(OP_0),
+        1..=16 => script.push(OP_1 + (n - 1) as u8),
+        _ => {
+            let mut bytes = n.to_le_bytes().to_vec();
+            while bytes.last() == Some(&0) {
+                bytes.pop();
+            }
+            // The most significant bit is the sign bit.
+            if bytes.last().map_or(false, |b| b & 0x80 != 0) {
+                bytes.push(0);
+            }
+            push_data(script, &bytes);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constraints::Commitment;
+    use crate::contract::{Anchor, Contract, PortableItem};
+    use crate::prover::Prover;
+    use crate::signature::{Signature, VerificationKey};
+    use crate::txlog::Entry;
+    use crate::types::Value;
+    use crate::verifier::Verifier;
+    use crate::vm::TxHeader;
+    use bulletproofs::BulletproofGens;
+    use curve25519_dalek::scalar::Scalar;
+
+    /// Proves and verifies the transaction, returning its program and first output.
+    fn build_and_verify(program: Program, mintime: u64) -> Result<(Vec<u8>, Output), VMError> {
+        let header = TxHeader {
+            version: 0,
+            mintime,
+            maxtime: u64::max_value(),
+            maxcost: u64::max_value(),
+        };
+        // The claim program computes SHA-256 in the constraint system.
+        let bp_gens = BulletproofGens::new(32768, 1);
+        let (tx, _, log) = Prover::build_tx(program, header, &bp_gens, |t, _| {
+            Signature::sign_aggregated(t, &[])
+        })?;
+        let tx_program = tx.program.clone();
+        Verifier::verify_tx(tx, &bp_gens)?;
+        let output = log
+            .into_iter()
+            .filter_map(|entry| match entry {
+                Entry::Output(output) => Some(output),
+                _ => None,
+            })
+            .next()
+            .expect("transaction must have outputs");
+        Ok((tx_program, output))
+    }
+
+    fn make_htlc(secret: &[u8]) -> (Htlc, Output) {
+        let key = |x: u64| Predicate::Key(VerificationKey::from_secret(&Scalar::from(x)));
+        let htlc = Htlc {
+            hash: hashlock(secret),
+            recipient: key(1),
+            sender: key(2),
+            timeout: 1000,
+        };
+        let contract = Output::new(Contract {
+            anchor: Anchor::from_raw_bytes([0u8; 32]),
+            payload: vec![PortableItem::Value(Value {
+                qty: Commitment::blinded(10u64),
+                flv: Commitment::blinded(0u64),
+            })],
+            predicate: htlc.predicate().unwrap(),
+        });
+        (htlc, contract)
+    }
+
+    #[test]
+    fn claim_and_refund() {
+        let secret = [7u8; SECRET_SIZE];
+        let secret = &secret[..];
+        let (htlc, contract) = make_htlc(secret);
+
+        let mut p = Program::new();
+        htlc.claim(&mut p, contract.clone(), secret).unwrap();
+        let (program, output) = build_and_verify(p, 0).unwrap();
+        assert_eq!(
+            output.contract().predicate.to_point(),
+            htlc.recipient.to_point()
+        );
+        // The counterparty learns the secret from the claim transaction.
+        assert_eq!(htlc.find_secret(&program), Some(secret.to_vec()));
+
+        let mut p = Program::new();
+        assert_eq!(
+            htlc.claim(&mut p, contract.clone(), &[8u8; SECRET_SIZE])
+                .unwrap_err(),
+            VMError::InvalidPreimage
+        );
+        assert_eq!(
+            htlc.claim(&mut p, contract.clone(), &secret[1..])
+                .unwrap_err(),
+            VMError::InvalidPreimage
+        );
+        // The constraint system checks the preimage even if the program is built by hand.
+        let mut p = Program::new();
+        push_secret(&mut p, &[8u8; SECRET_SIZE]);
+        p.push(contract.clone())
+            .input()
+            .choose_predicate(htlc.predicate().unwrap(), |t| t.select(0)?.call())
+            .unwrap();
+        assert!(build_and_verify(p, 0).is_err());
+
+        let refund = |mintime| {
+            let mut p = Program::new();
+            htlc.refund(&mut p, contract.clone()).unwrap();
+            build_and_verify(p, mintime)
+        };
+        assert!(refund(999).is_err());
+        let (_, output) = refund(1000).unwrap();
+        assert_eq!(
+            output.contract().predicate.to_point(),
+            htlc.sender.to_point()
+        );
+    }
+
+    #[test]
+    fn bitcoin_script() {
+        let secret = b"swap secret";
+        let htlc = BitcoinHtlc {
+            hash: hashlock(secret),
+            recipient: [1u8; 20],
+            sender: [2u8; 20],
+            locktime: 600_000,
+        };
+        let script = htlc.script();
+        let mut expected = vec![0x63, 0xa8, 0x20];
+        expected.extend_from_slice(&htlc.hash);
+        expected.extend_from_slice(&[0x88, 0x76, 0xa9, 0x14]);
+        expected.extend_from_slice(&[1u8; 20]);
+        // 600000 = 0x0927c0 in 3 bytes, little-endian.
+        expected.extend_from_slice(&[0x67, 0x03, 0xc0, 0x27, 0x09, 0xb1, 0x75, 0x76, 0xa9, 0x14]);
+        expected.extend_from_slice(&[2u8; 20]);
+        expected.extend_from_slice(&[0x68, 0x88, 0xac]);
+        assert_eq!(script, expected);
+
+        let witness = htlc.claim_witness(&[0xaa; 71], &[0xbb; 33], secret);
+        assert_eq!(htlc.find_secret(&witness), Some(secret.to_vec()));
+        let witness = htlc.refund_witness(&[0xaa; 71], &[0xbb; 33]);
+        assert_eq!(htlc.find_secret(&witness), None);
+
+        let number = |n| {
+            let mut script = Vec::new();
+            push_number(&mut script, n);
+            script
+        };
+        assert_eq!(number(0), vec![0x00]);
+        assert_eq!(number(16), vec![0x60]);
+        assert_eq!(number(128), vec![0x02, 0x80, 0x00]);
+        assert_eq!(number(500_000_000), vec![0x04, 0x00, 0x65, 0xcd, 0x1d]);
+    }
+}
@@ -0,0 +1,272 @@
+//! Declarative description of a transaction that compiles deterministically to its program.
+//!
+//! The parties of a cooperative transaction agree on a `TxIntent`: the inputs, issuances,
+//! outputs, retirements and the fee, and a shared secret seed. Each of them compiles the intent
+//! into the identical program, so they can sign the same unsigned transaction
+//! without exchanging the program itself.
+//!
+//! The compiled program does not depend on the order in which the items were added:
+//! the items are sorted canonically, and the blinding factors of the new commitments
+//! are derived from the seed and the sorted items with a transcript.
+//! All values are merged and split with a single `cloak`.
+
+use curve25519_dalek::scalar::Scalar;
+use merlin::Transcript;
+
+use crate::constraints::Commitment;
+use crate::contract::{Output, PortableItem};
+use crate::errors::VMError;
+use crate::predicate::Predicate;
+use crate::program::Program;
+use crate::transcript::TranscriptProtocol;
+use crate::types::{Data, Value};
+use crate::vm::TxHeader;
+
+/// Declarative description of a transaction.
+#[derive(Clone, Debug)]
+pub struct TxIntent {
+    header: TxHeader,
+    seed: [u8; 32],
+    nonce: Option<(Predicate, [u8; 32])>,
+    inputs: Vec<Output>,
+    issuances: Vec<Issuance>,
+    outputs: Vec<IntentOutput>,
+    retirements: Vec<(u64, Scalar)>,
+    fee: u64,
+}
+
+/// Issuance of the quantity of the flavor defined by the issuer's predicate and the metadata.
+#[derive(Clone, Debug)]
+pub struct Issuance {
+    /// Issued quantity.
+    pub qty: u64,
+    /// Predicate of the issuer, which signs the transaction.
+    pub issuer: Predicate,
+    /// Metadata of the flavor.
+    pub metadata: Vec<u8>,
+}
+
+/// Output of the transaction.
+#[derive(Clone, Debug)]
+pub struct IntentOutput {
+    /// Quantity of the value.
+    pub qty: u64,
+    /// Flavor of the value.
+    pub flv: Scalar,
+    /// Predicate of the output.
+    pub predicate: Predicate,
+    /// Time until which the value is locked (see `Program::lock_until`).
+    pub lock_until: Option<u64>,
+}
+
+impl TxIntent {
+    /// Creates an empty intent of a transaction with the header.
+    /// The seed is a secret shared by the parties: it determines the blinding factors
+    /// of the commitments, which the observers must not be able to guess.
+    pub fn new(header: TxHeader, seed: [u8; 32]) -> Self {
+        TxIntent {
+            header,
+            seed,
+            nonce: None,
+            inputs: Vec::new(),
+            issuances: Vec::new(),
+            outputs: Vec::new(),
+            retirements: Vec::new(),
+            fee: 0,
+        }
+    }
+
+    /// Adds a nonce signed by the predicate, which makes the transaction unique
+    /// when it has no inputs (e.g. issues a new asset).
+    pub fn nonce(mut self, predicate: Predicate, blockid: [u8; 32]) -> Self {
+        self.nonce = Some((predicate, blockid));
+        self
+    }
+
+    /// Adds an input spent with a signature of its predicate.
+    /// The output must contain only values with open commitments.
+    pub fn input(mut self, output: Output) -> Self {
+        self.inputs.push(output);
+        self
+    }
+
+    /// Adds an issuance of the quantity of the flavor defined by the issuer and the metadata
+    /// (see `Value::issue_flavor`).
+    pub fn issue(mut self, qty: u64, issuer: Predicate, metadata: Vec<u8>) -> Self {
+        self.issuances.push(Issuance {
+            qty,
+            issuer,
+            metadata,
+        });
+        self
+    }
+
+    /// Adds an output with the value locked by the predicate.
+    pub fn output(mut self, qty: u64, flv: Scalar, predicate: Predicate) -> Self {
+        self.outputs.push(IntentOutput {
+            qty,
+            flv,
+            predicate,
+            lock_until: None,
+        });
+        self
+    }
+
+    /// Adds an output with the value locked by the predicate until the time.
+    pub fn output_locked(mut self, qty: u64, flv: Scalar, predicate: Predicate, time: u64) -> Self {
+        self.outputs.push(IntentOutput {
+            qty,
+            flv,
+            predicate,
+            lock_until: Some(time),
+        });
+        self
+    }
+
+    /// Adds a retirement of the quantity of the flavor.
+    pub fn retire(mut self, qty: u64, flv: Scalar) -> Self {
+        self.retirements.push((qty, flv));
+        self
+    }
+
+    /// Sets the fee, paid in the asset with the zero flavor.
+    pub fn fee(mut self, fee: u64) -> Self {
+        self.fee = fee;
+        self
+    }
+
+    /// Returns the header of the transaction.
+    pub fn header(&self) -> TxHeader {
+        self.header
+    }
+
+    /// Compiles the intent into the program: signs the nonce, spends the inputs,
+    /// issues the values, pays the fee, merges and splits all the values with a `cloak`,
+    /// retires the retirements and creates the outputs.
+    /// The inputs are signed in the order of their contract IDs, followed by the issuers.
+    /// Fails with `VMError::BadArguments` if an input contains data or a closed commitment.
+    pub fn compile(&self) -> Result<Program, VMError> {
+        let mut inputs = self.inputs.clone();
+        inputs.sort_by(|a, b| a.id().as_bytes().cmp(b.id().as_bytes()));
+        let mut input_count = 0;
+        for input in inputs.iter() {
+            for item in input.contract().payload.iter() {
+                match item {
+                    PortableItem::Value(value) => {
+                        if value.assignment()?.is_none() {
+                            return Err(VMError::BadArguments);
+                        }
+                        input_count += 1;
+                    }
+                    PortableItem::Data(_) => return Err(VMError::BadArguments),
+                }
+            }
+        }
+
+        let mut issuances = self.issuances.clone();
+        issuances.sort_by_key(|i| (i.issuer.to_point().to_bytes(), i.metadata.clone(), i.qty));
+        let mut outputs = self.outputs.clone();
+        outputs.sort_by_key(|o| {
+            (
+                o.predicate.to_point().to_bytes(),
+                o.flv.to_bytes(),
+                o.qty,
+                o.lock_until,
+            )
+        });
+        let mut retirements = self.retirements.clone();
+        retirements.sort_by_key(|(qty, flv)| (flv.to_bytes(), *qty));
+
+        // Blinding factors are bound to the seed and all the items of the transaction.
+        let mut t = Transcript::new(b"ZkVM.txintent");
+        t.commit_bytes(b"seed", &self.seed);
+        t.commit_u64(b"version", self.header.version);
+        t.commit_u64(b"mintime", self.header.mintime);
+        t.commit_u64(b"maxtime", self.header.maxtime);
+        t.commit_u64(b"maxcost", self.header.maxcost);
+        if let Some((predicate, blockid)) = &self.nonce {
+            t.commit_point(b"nonce.predicate", &predicate.to_point());
+            t.commit_bytes(b"nonce.blockid", blockid);
+        }
+        for input in inputs.iter() {
+            t.commit_bytes(b"input", input.id().as_bytes());
+        }
+        for issuance in issuances.iter() {
+            t.commit_point(b"issue.predicate", &issuance.issuer.to_point());
+            t.commit_bytes(b"issue.metadata", &issuance.metadata);
+            t.commit_u64(b"issue.qty", issuance.qty);
+        }
+        t.commit_u64(b"fee", self.fee);
+        for output in outputs.iter() {
+            t.commit_point(b"output.predicate", &output.predicate.to_point());
+            t.commit_scalar(b"output.flv", &output.flv);
+            t.commit_u64(b"output.qty", output.qty);
+            t.commit_u64(b"output.lock", output.lock_until.unwrap_or(0));
+        }
+        for (qty, flv) in retirements.iter() {
+            t.commit_scalar(b"retire.flv", flv);
+            t.commit_u64(b"retire.qty", *qty);
+        }
+        let mut blinded = |qty: u64, flv: Scalar| Value {
+            qty: Commitment::blinded_with_factor(qty, t.challenge_scalar(b"qty_blinding")),
+            flv: Commitment::blinded_with_factor(flv, t.challenge_scalar(b"flv_blinding")),
+        };
+
+        let issued = issuances
+            .iter()
+            .map(|i| {
+                let flv = Value::issue_flavor(&i.issuer, Data::Opaque(i.metadata.clone()));
+                (blinded(i.qty, flv).qty, flv)
+            })
+            .collect::<Vec<_>>();
+        let new_values = outputs
+            .iter()
+            .map(|o| (o.qty, o.flv))
+            .chain(retirements.iter().cloned())
+            .map(|(qty, flv)| blinded(qty, flv))
+            .collect::<Vec<_>>();
+
+        let fee = self.fee;
+        let program = Program::build(|p| {
+            if let Some((predicate, blockid)) = &self.nonce {
+                p.push(predicate.clone())
+                    .push(Data::Opaque(blockid.to_vec()))
+                    .nonce()
+                    .sign_tx(); // stack: ø
+            }
+            for input in inputs.iter() {
+                p.push(input.clone()).input().sign_tx(); // stack: values
+            }
+            for (issuance, (qty, flv)) in issuances.iter().zip(issued.iter()) {
+                p.push(qty.clone())
+                    .var()
+                    .push(Commitment::unblinded(*flv))
+                    .var()
+                    .push(Data::Opaque(issuance.metadata.clone()))
+                    .push(issuance.issuer.clone())
+                    .issue()
+                    .sign_tx(); // stack: values
+            }
+            let mut m = input_count + issued.len();
+            if fee > 0 {
+                p.push(Data::Opaque(fee.to_le_bytes().to_vec())).fee(); // stack: values, fee
+                m += 1;
+            }
+            for value in new_values.iter() {
+                p.push(value.qty.clone()).push(value.flv.clone());
+            }
+            p.cloak(m, new_values.len()); // stack: outputs, retirements
+            for _ in retirements.iter() {
+                p.retire();
+            }
+            for output in outputs.iter().rev() {
+                match output.lock_until {
+                    Some(time) => p.lock_until(time, output.predicate.clone()),
+                    None => p.push(output.predicate.clone()).output(1),
+                };
+            }
+            p
+        });
+        Ok(program)
+    }
+}
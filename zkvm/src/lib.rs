@@ -3,40 +3,91 @@
 
 #[macro_use]
 extern crate failure;
+#[macro_use]
+extern crate lazy_static;
+#[cfg(feature = "serde")]
 extern crate serde;
 
+#[macro_use]
+mod serialization;
+
+mod assembler;
+mod circuit_size;
 mod constraints;
 mod contract;
+mod cost;
+mod coverage;
 mod encoding;
+mod encryption;
 mod errors;
+mod fees;
+mod gens;
+#[cfg(feature = "prover")]
+mod intent;
 mod merkle;
 mod ops;
 mod point_ops;
 mod predicate;
 mod program;
+mod proof_system;
+#[cfg(feature = "prover")]
 mod prover;
+#[cfg(feature = "prover")]
+mod pszt;
 mod scalar_witness;
+mod secret;
+mod signing_request;
 mod transcript;
 mod txlog;
+mod typecheck;
 mod types;
+mod vector_commitment;
 mod verifier;
 mod vm;
 
+#[cfg(feature = "prover")]
+pub mod channels;
+pub mod gadgets;
+#[cfg(feature = "prover")]
+pub mod htlc;
+pub mod poseidon;
+#[cfg(feature = "testutil")]
+pub mod testutil;
+
 // TODO: remove this when we move musig in another crate
 pub mod signature;
 
-pub use self::constraints::{Commitment, Constraint, Expression, Variable};
+pub use self::circuit_size::{CircuitSize, R1CSProofMetrics};
+pub use self::constraints::{Commitment, CommitmentWitness, Constraint, Expression, Variable};
 pub use self::contract::{Anchor, Contract, ContractID, Output, PortableItem};
-pub use self::errors::VMError;
-pub use self::merkle::{MerkleItem, MerkleNeighbor, MerkleTree};
-pub use self::ops::{Instruction, Opcode};
-pub use self::predicate::Predicate;
+pub use self::coverage::{Branch, Coverage, ExercisedBranch, InstructionLocation};
+pub use self::encoding::{Reader, Writer};
+pub use self::encryption::{EncryptedOpening, EncryptionKey};
+pub use self::errors::{ErrorContext, VMError};
+pub use self::fees::FeeRate;
+pub use self::gens::GensCache;
+#[cfg(feature = "prover")]
+pub use self::intent::{IntentOutput, Issuance, TxIntent};
+pub use self::merkle::{MerkleItem, MerkleNeighbor, MerkleProof, MerkleTree, TxTree};
+pub use self::ops::{Instruction, Opcode, MIN_NOP_OPCODE};
+pub use self::point_ops::PointOp;
+pub use self::predicate::{Predicate, PredicateBranch, PredicateProof};
 pub use self::program::Program;
+pub use self::proof_system::{Bulletproofs, ProofSystem};
+#[cfg(feature = "prover")]
 pub use self::prover::Prover;
+#[cfg(feature = "prover")]
+pub use self::pszt::{Pszt, PsztHint, PsztSigner};
 pub use self::scalar_witness::ScalarWitness;
 pub use self::signature::{Signature, VerificationKey};
-pub use self::transcript::TranscriptProtocol;
-pub use self::txlog::{Entry, TxID, TxLog, UTXO};
+pub use self::signing_request::{
+    CompactEntry, OutputOpening, SigningRequest, SigningSummary, ValueOpening,
+};
+pub use self::transcript::{labels, transcript_for_tx, TranscriptProtocol};
+pub use self::txlog::{Entry, TxID, TxLog, WTxID, UTXO};
 pub use self::types::{Data, Item, Value, WideValue};
-pub use self::verifier::Verifier;
-pub use self::vm::{Tx, TxHeader, VerifiedTx};
+pub use self::vector_commitment::{
+    vector_generator, VectorCommitment, VectorCommitmentWitness, VectorOpening,
+};
+pub use self::verifier::{DeferredTx, Verifier};
+pub use self::vm::{Inspection, Tx, TxHeader, TxRef, VMParams, VerifiedTx};
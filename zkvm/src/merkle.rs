@@ -1,7 +1,12 @@
 use merlin::Transcript;
 use subtle::ConstantTimeEq;
 
+use crate::encoding::{self, SliceReader};
 use crate::errors::VMError;
+use crate::txlog::TxID;
+
+/// Transcript label of the Merkle tree of transaction IDs in a block.
+const TX_ROOT_LABEL: &'static [u8] = b"ZkVM.txroot";
 
 /// MerkleItem defines an item in the Merkle tree.
 pub trait MerkleItem: Sized {
@@ -88,6 +93,11 @@ impl MerkleTree {
         }
     }
 
+    /// Returns the root hash of the Merkle tree.
+    pub fn root_hash(&self) -> &[u8; 32] {
+        self.root.hash()
+    }
+
     /// Builds and returns the root hash of a Merkle tree constructed from
     /// the supplied list.
     pub fn root<M: MerkleItem>(label: &'static [u8], list: &[M]) -> [u8; 32] {
@@ -148,6 +158,92 @@ impl MerkleTree {
     }
 }
 
+/// Merkle tree of transaction IDs that commits to the list of transactions in a block.
+pub struct TxTree {
+    root: [u8; 32],
+    tree: Option<MerkleTree>,
+}
+
+/// Compact proof of inclusion of a transaction ID in a block's transaction tree.
+#[derive(Clone, PartialEq, Debug)]
+pub struct MerkleProof {
+    /// Neighbors of the transaction ID, from the leaf up to the root.
+    pub path: Vec<MerkleNeighbor>,
+}
+
+impl TxTree {
+    /// Builds a Merkle tree of the given transaction IDs in the order of the transactions in a block.
+    pub fn build(txids: &[TxID]) -> Self {
+        TxTree {
+            root: MerkleTree::root(TX_ROOT_LABEL, txids),
+            tree: MerkleTree::build(TX_ROOT_LABEL, txids),
+        }
+    }
+
+    /// Returns the root hash of the block's transactions.
+    /// Block with no transactions has a root of an empty tree.
+    pub fn root(&self) -> [u8; 32] {
+        self.root
+    }
+
+    /// Creates a proof of inclusion for the transaction at the given index.
+    pub fn create_proof(&self, index: usize) -> Result<MerkleProof, VMError> {
+        match &self.tree {
+            Some(tree) => Ok(MerkleProof {
+                path: tree.create_path(index)?,
+            }),
+            None => Err(VMError::InvalidMerkleProof),
+        }
+    }
+}
+
+impl MerkleProof {
+    /// Verifies that the transaction ID is included in the block
+    /// with the given transaction root.
+    pub fn verify(&self, root: &[u8; 32], txid: &TxID) -> Result<(), VMError> {
+        MerkleTree::verify_path(TX_ROOT_LABEL, txid, self.path.clone(), root)
+    }
+
+    /// Serializes the proof into a byte array:
+    /// LE32 number of steps, followed by a side byte (0 for left, 1 for right)
+    /// and a 32-byte hash for each step.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(4 + 33 * self.path.len());
+        encoding::write_size(self.path.len(), &mut buf);
+        for neighbor in self.path.iter() {
+            match neighbor {
+                MerkleNeighbor::Left(h) => {
+                    encoding::write_u8(0, &mut buf);
+                    encoding::write_bytes(h, &mut buf);
+                }
+                MerkleNeighbor::Right(h) => {
+                    encoding::write_u8(1, &mut buf);
+                    encoding::write_bytes(h, &mut buf);
+                }
+            }
+        }
+        buf
+    }
+
+    /// Decodes the proof from a byte array.
+    pub fn from_bytes(data: &[u8]) -> Result<Self, VMError> {
+        SliceReader::parse(data, |r| {
+            let n = r.read_size()?;
+            let mut path = Vec::new();
+            for _ in 0..n {
+                let side = r.read_u8()?;
+                let hash = r.read_u8x32()?;
+                path.push(match side {
+                    0 => MerkleNeighbor::Left(hash),
+                    1 => MerkleNeighbor::Right(hash),
+                    _ => return Err(VMError::FormatError),
+                });
+            }
+            Ok(MerkleProof { path })
+        })
+    }
+}
+
 impl MerkleNode {
     fn subpath(&self, t: Transcript, index: usize, size: usize, result: &mut Vec<MerkleNeighbor>) {
         match self {
@@ -242,6 +338,24 @@ mod tests {
         }
     }
 
+    #[test]
+    fn tx_tree_proofs() {
+        let txids: Vec<TxID> = (0..7u8).map(|i| TxID([i; 32])).collect();
+        let tree = TxTree::build(&txids);
+        for (i, txid) in txids.iter().enumerate() {
+            let proof = tree.create_proof(i).unwrap();
+            let proof = MerkleProof::from_bytes(&proof.to_bytes()).unwrap();
+            assert!(proof.verify(&tree.root(), txid).is_ok());
+            assert!(proof.verify(&tree.root(), &TxID([0xff; 32])).is_err());
+        }
+        assert!(tree.create_proof(7).is_err());
+
+        // Roots of the empty blocks match, but there is nothing to prove.
+        let empty = TxTree::build(&[]);
+        assert_eq!(empty.root(), TxTree::build(&[]).root());
+        assert!(empty.create_proof(0).is_err());
+    }
+
     #[test]
     fn invalid_proofs() {
         let tests = [(10, 7, 8), (11, 3, 5), (12, 0, 2), (5, 3, 1), (25, 9, 8)];
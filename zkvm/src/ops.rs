@@ -6,11 +6,15 @@ use crate::encoding::SliceReader;
 use crate::errors::VMError;
 use crate::scalar_witness::ScalarWitness;
 use crate::types::Data;
+use core::fmt;
 use core::mem;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 use spacesuit::BitRange;
 
 /// A decoded instruction.
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[allow(missing_docs)]
 pub enum Instruction {
     Push(Data), // size of the string
@@ -27,7 +31,11 @@ pub enum Instruction {
     Add,
     Mul,
     Eq,
-    Range(BitRange), // bitwidth (0...64)
+    // bitwidth (0...64, or 0...252 in tx version `WIDE_RANGE_VERSION` and later)
+    Range(#[cfg_attr(feature = "serde", serde(with = "crate::serialization::bit_range"))] BitRange),
+    Sha256(usize),   // preimage length
+    Sha512(usize),   // preimage length
+    Poseidon(usize), // number of inputs
     And,
     Or,
     Not,
@@ -36,6 +44,7 @@ pub enum Instruction {
     Issue,
     Borrow,
     Retire,
+    Fee,
     Cloak(usize, usize), // M inputs, N outputs
     Import,
     Export,
@@ -44,11 +53,15 @@ pub enum Instruction {
     Contract(usize), // payload count
     Nonce,
     Log,
+    Nullify,
+    Carry(usize), // payload count
     Signtx,
     Call,
     Select(u8, u8),
     Delegate,
     Ext(u8),
+    // prover-only debug record with a label, not encoded in the bytecode
+    Inspect(String),
 }
 
 /// A bytecode representation of the instruction.
@@ -90,10 +103,23 @@ pub enum Opcode {
     Signtx = 0x1f,
     Call = 0x20,
     Select = 0x21,
-    Delegate = MAX_OPCODE,
+    Delegate = 0x22,
+    Fee = 0x23,
+    Sha256 = 0x24,
+    Sha512 = 0x25,
+    Poseidon = 0x26,
+    Nullify = 0x27,
+    Carry = MAX_OPCODE,
 }
 
-const MAX_OPCODE: u8 = 0x22;
+const MAX_OPCODE: u8 = 0x28;
+
+/// Unassigned opcodes from `MIN_NOP_OPCODE` to `0xff` are reserved no-ops:
+/// unlike the other extension opcodes, they are permitted in transactions of any version.
+/// A future version may redefine them as instructions that leave the stack intact
+/// and only add failure conditions, so the upgrade is a soft fork:
+/// the non-upgraded nodes keep treating them as no-ops.
+pub const MIN_NOP_OPCODE: u8 = 0xf0;
 
 impl Opcode {
     /// Converts the opcode to `u8`.
@@ -101,6 +127,11 @@ impl Opcode {
         unsafe { mem::transmute(self) }
     }
 
+    /// Returns true if the code is a reserved no-op (see `MIN_NOP_OPCODE`).
+    pub fn is_nop(code: u8) -> bool {
+        code >= MIN_NOP_OPCODE
+    }
+
     /// Instantiates the opcode from `u8`.
     /// Unassigned code is mapped to `None`.
     pub fn from_u8(code: u8) -> Option<Opcode> {
@@ -123,6 +154,11 @@ impl Instruction {
             Instruction::Cloak(_, _) => 1 + 4 + 4,
             Instruction::Output(_) => 1 + 4,
             Instruction::Contract(_) => 1 + 4,
+            Instruction::Carry(_) => 1 + 4,
+            Instruction::Sha256(_) => 1 + 4,
+            Instruction::Sha512(_) => 1 + 4,
+            Instruction::Poseidon(_) => 1 + 4,
+            Instruction::Inspect(_) => 0,
             _ => 1,
         }
     }
@@ -171,10 +207,24 @@ impl Instruction {
             Opcode::Mul => Ok(Instruction::Mul),
             Opcode::Eq => Ok(Instruction::Eq),
             Opcode::Range => {
+                // Ranges wider than 64 bits are allowed by the VM
+                // only in the transactions of `WIDE_RANGE_VERSION` and later.
                 let bit_width =
-                    BitRange::new(program.read_u8()? as usize).ok_or(VMError::FormatError)?;
+                    BitRange::new_wide(program.read_u8()? as usize).ok_or(VMError::FormatError)?;
                 Ok(Instruction::Range(bit_width))
             }
+            Opcode::Sha256 => {
+                let n = program.read_size()?;
+                Ok(Instruction::Sha256(n))
+            }
+            Opcode::Sha512 => {
+                let n = program.read_size()?;
+                Ok(Instruction::Sha512(n))
+            }
+            Opcode::Poseidon => {
+                let n = program.read_size()?;
+                Ok(Instruction::Poseidon(n))
+            }
             Opcode::And => Ok(Instruction::And),
             Opcode::Or => Ok(Instruction::Or),
             Opcode::Not => Ok(Instruction::Not),
@@ -201,6 +251,11 @@ impl Instruction {
             }
             Opcode::Nonce => Ok(Instruction::Nonce),
             Opcode::Log => Ok(Instruction::Log),
+            Opcode::Nullify => Ok(Instruction::Nullify),
+            Opcode::Carry => {
+                let k = program.read_size()?;
+                Ok(Instruction::Carry(k))
+            }
             Opcode::Signtx => Ok(Instruction::Signtx),
             Opcode::Call => Ok(Instruction::Call),
             Opcode::Select => {
@@ -209,6 +264,7 @@ impl Instruction {
                 Ok(Instruction::Select(n, k))
             }
             Opcode::Delegate => Ok(Instruction::Delegate),
+            Opcode::Fee => Ok(Instruction::Fee),
         }
     }
 
@@ -246,6 +302,18 @@ impl Instruction {
                 let bit_width: BitRange = *n;
                 program.push(bit_width.into());
             }
+            Instruction::Sha256(n) => {
+                write(Opcode::Sha256);
+                encoding::write_u32(*n as u32, program);
+            }
+            Instruction::Sha512(n) => {
+                write(Opcode::Sha512);
+                encoding::write_u32(*n as u32, program);
+            }
+            Instruction::Poseidon(n) => {
+                write(Opcode::Poseidon);
+                encoding::write_u32(*n as u32, program);
+            }
             Instruction::And => write(Opcode::And),
             Instruction::Or => write(Opcode::Or),
             Instruction::Not => write(Opcode::Not),
@@ -254,6 +322,7 @@ impl Instruction {
             Instruction::Issue => write(Opcode::Issue),
             Instruction::Borrow => write(Opcode::Borrow),
             Instruction::Retire => write(Opcode::Retire),
+            Instruction::Fee => write(Opcode::Fee),
             Instruction::Cloak(m, n) => {
                 write(Opcode::Cloak);
                 encoding::write_u32(*m as u32, program);
@@ -272,6 +341,11 @@ impl Instruction {
             }
             Instruction::Nonce => write(Opcode::Nonce),
             Instruction::Log => write(Opcode::Log),
+            Instruction::Nullify => write(Opcode::Nullify),
+            Instruction::Carry(k) => {
+                write(Opcode::Carry);
+                encoding::write_u32(*k as u32, program);
+            }
             Instruction::Signtx => write(Opcode::Signtx),
             Instruction::Call => write(Opcode::Call),
             Instruction::Select(n, k) => {
@@ -281,6 +355,94 @@ impl Instruction {
             }
             Instruction::Delegate => write(Opcode::Delegate),
             Instruction::Ext(x) => program.push(*x),
+            // The debug instruction is stripped from the bytecode.
+            Instruction::Inspect(_) => {}
         };
     }
 }
+
+impl Instruction {
+    /// Returns the name of the instruction without the immediate data, e.g. `push` or `output`.
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Instruction::Push(_) => "push",
+            Instruction::Drop => "drop",
+            Instruction::Dup(_) => "dup",
+            Instruction::Roll(_) => "roll",
+            Instruction::Const => "const",
+            Instruction::Var => "var",
+            Instruction::Alloc(_) => "alloc",
+            Instruction::Mintime => "mintime",
+            Instruction::Maxtime => "maxtime",
+            Instruction::Expr => "expr",
+            Instruction::Neg => "neg",
+            Instruction::Add => "add",
+            Instruction::Mul => "mul",
+            Instruction::Eq => "eq",
+            Instruction::Range(_) => "range",
+            Instruction::Sha256(_) => "sha256",
+            Instruction::Sha512(_) => "sha512",
+            Instruction::Poseidon(_) => "poseidon",
+            Instruction::And => "and",
+            Instruction::Or => "or",
+            Instruction::Not => "not",
+            Instruction::Verify => "verify",
+            Instruction::Unblind => "unblind",
+            Instruction::Issue => "issue",
+            Instruction::Borrow => "borrow",
+            Instruction::Retire => "retire",
+            Instruction::Fee => "fee",
+            Instruction::Cloak(_, _) => "cloak",
+            Instruction::Import => "import",
+            Instruction::Export => "export",
+            Instruction::Input => "input",
+            Instruction::Output(_) => "output",
+            Instruction::Contract(_) => "contract",
+            Instruction::Nonce => "nonce",
+            Instruction::Log => "log",
+            Instruction::Nullify => "nullify",
+            Instruction::Carry(_) => "carry",
+            Instruction::Signtx => "signtx",
+            Instruction::Call => "call",
+            Instruction::Select(_, _) => "select",
+            Instruction::Delegate => "delegate",
+            Instruction::Ext(_) => "ext",
+            Instruction::Inspect(_) => "inspect",
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    /// Formats the instruction in the notation of the ZkVM specification,
+    /// with the immediate data separated by colons: `push:3:0x010203`, `output:1`, `signtx`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Instruction::Push(data) => {
+                let mut buf = Vec::with_capacity(data.serialized_length());
+                data.encode(&mut buf);
+                write!(f, "push:{}:0x", buf.len())?;
+                for byte in buf.iter() {
+                    write!(f, "{:02x}", byte)?;
+                }
+                Ok(())
+            }
+            Instruction::Dup(k) => write!(f, "dup:{}", k),
+            Instruction::Roll(k) => write!(f, "roll:{}", k),
+            Instruction::Range(n) => {
+                let bit_width: u8 = (*n).into();
+                write!(f, "range:{}", bit_width)
+            }
+            Instruction::Sha256(n) => write!(f, "sha256:{}", n),
+            Instruction::Sha512(n) => write!(f, "sha512:{}", n),
+            Instruction::Poseidon(n) => write!(f, "poseidon:{}", n),
+            Instruction::Cloak(m, n) => write!(f, "cloak:{}:{}", m, n),
+            Instruction::Output(k) => write!(f, "output:{}", k),
+            Instruction::Contract(k) => write!(f, "contract:{}", k),
+            Instruction::Carry(k) => write!(f, "carry:{}", k),
+            Instruction::Select(n, k) => write!(f, "select:{}:{}", n, k),
+            Instruction::Ext(x) => write!(f, "ext:0x{:02x}", x),
+            Instruction::Inspect(label) => write!(f, "inspect:{}", label),
+            _ => f.write_str(self.mnemonic()),
+        }
+    }
+}
@@ -1,9 +1,15 @@
-use bulletproofs::PedersenGens;
 use curve25519_dalek::ristretto::{CompressedRistretto, RistrettoPoint};
 use curve25519_dalek::scalar::Scalar;
 use curve25519_dalek::traits::{Identity, IsIdentity, VartimeMultiscalarMul};
+#[cfg(not(feature = "std"))]
+use merlin::Transcript;
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
 
 use super::errors::VMError;
+use super::gens;
+#[cfg(not(feature = "std"))]
+use super::transcript::{labels, TranscriptProtocol};
 
 /// Deferred point operation.
 #[derive(Clone, Debug)]
@@ -23,7 +29,7 @@ pub struct PointOp {
 impl PointOp {
     /// Compute an individual point operation.
     pub fn compute(self) -> Result<RistrettoPoint, VMError> {
-        let gens = PedersenGens::default();
+        let gens = gens::pedersen_gens();
         let (mut weights, points): (Vec<_>, Vec<_>) = self.arbitrary.into_iter().unzip();
         let mut points: Vec<_> = points.into_iter().map(|p| p.decompress()).collect();
 
@@ -54,29 +60,22 @@ impl PointOp {
 
     /// Verifies a batch of point operations using one multi-scalar multiplication
     pub fn verify_batch(batch: &[PointOp]) -> Result<(), VMError> {
-        let gens = PedersenGens::default();
+        let gens = gens::pedersen_gens();
 
         // Get the total number of points in batch
         let dyn_length: usize = batch.iter().map(|p| p.arbitrary.len()).sum();
         let length = 2 + dyn_length; // include the (B, B_blinding) pair
 
-        let mut weights: Vec<Scalar> = Vec::with_capacity(length + 2);
-        let mut points: Vec<Option<RistrettoPoint>> = Vec::with_capacity(length);
+        let mut weights: Vec<Scalar> = Vec::with_capacity(length);
+        let mut compressed: Vec<CompressedRistretto> = Vec::with_capacity(dyn_length);
 
-        // Add base points
-        points.push(Some(gens.B));
-        points.push(Some(gens.B_blinding));
+        // Add weights for base points
         weights.push(Scalar::zero());
         weights.push(Scalar::zero());
 
-        let mut rng = rand::thread_rng();
-
         // Iterate over every point, adding both weights and points to
         // our arrays
-        for p in batch.iter() {
-            // Sample free variable e
-            let e = Scalar::random(&mut rng);
-
+        for (p, e) in batch.iter().zip(Self::batch_factors(batch)) {
             // Add weights for base points
             if let Some(w) = p.primary {
                 weights[0] = weights[0] + e * w;
@@ -87,24 +86,95 @@ impl PointOp {
 
             // Add weights and points for arbitrary points
             let arbitrary_scalars = p.arbitrary.iter().map(|p| p.0 * e);
-            let arbitrary_points = p.arbitrary.iter().map(|p| p.1.decompress());
+            let arbitrary_points = p.arbitrary.iter().map(|p| p.1);
             weights.extend(arbitrary_scalars);
-            points.extend(arbitrary_points);
+            compressed.extend(arbitrary_points);
         }
 
-        let check = RistrettoPoint::optional_multiscalar_mul(weights, points)
-            .ok_or(VMError::PointOperationFailed)?;
+        let mut points: Vec<Option<RistrettoPoint>> = Vec::with_capacity(length);
+        points.push(Some(gens.B));
+        points.push(Some(gens.B_blinding));
+        points.extend(Self::decompress(&compressed));
+
+        let check =
+            Self::multiscalar_mul(&weights, &points).ok_or(VMError::PointOperationFailed)?;
         if !check.is_identity() {
             return Err(VMError::PointOperationFailed);
         }
 
         Ok(())
     }
+
+    /// Decompresses the points of the batch.
+    #[cfg(not(feature = "parallel"))]
+    fn decompress(points: &[CompressedRistretto]) -> Vec<Option<RistrettoPoint>> {
+        points.iter().map(|p| p.decompress()).collect()
+    }
+
+    /// Decompresses the points of the batch on all the threads of the rayon pool.
+    #[cfg(feature = "parallel")]
+    fn decompress(points: &[CompressedRistretto]) -> Vec<Option<RistrettoPoint>> {
+        points.par_iter().map(|p| p.decompress()).collect()
+    }
+
+    /// Computes the multi-scalar multiplication of the batch.
+    #[cfg(not(feature = "parallel"))]
+    fn multiscalar_mul(
+        weights: &[Scalar],
+        points: &[Option<RistrettoPoint>],
+    ) -> Option<RistrettoPoint> {
+        RistrettoPoint::optional_multiscalar_mul(weights, points.iter().cloned())
+    }
+
+    /// Splits the multi-scalar multiplication of the batch into chunks
+    /// computed on all the threads of the rayon pool, and adds up the results.
+    #[cfg(feature = "parallel")]
+    fn multiscalar_mul(
+        weights: &[Scalar],
+        points: &[Option<RistrettoPoint>],
+    ) -> Option<RistrettoPoint> {
+        const CHUNK_SIZE: usize = 256;
+        weights
+            .par_chunks(CHUNK_SIZE)
+            .zip(points.par_chunks(CHUNK_SIZE))
+            .map(|(w, p)| RistrettoPoint::optional_multiscalar_mul(w, p.iter().cloned()))
+            .reduce(
+                || Some(RistrettoPoint::identity()),
+                |a, b| a.and_then(|a| b.map(|b| a + b)),
+            )
+    }
+
+    /// Samples a free variable `e` for each operation in the batch.
+    #[cfg(feature = "std")]
+    fn batch_factors(batch: &[PointOp]) -> Vec<Scalar> {
+        let mut rng = rand::thread_rng();
+        batch.iter().map(|_| Scalar::random(&mut rng)).collect()
+    }
+
+    /// Derives a free variable `e` for each operation in the batch
+    /// from a transcript of the entire batch, for the targets without OS randomness
+    /// (e.g. `wasm32-unknown-unknown`).
+    #[cfg(not(feature = "std"))]
+    fn batch_factors(batch: &[PointOp]) -> Vec<Scalar> {
+        let mut t = Transcript::new(labels::POINT_OPS);
+        t.commit_u64(b"n", batch.len() as u64);
+        for p in batch.iter() {
+            t.commit_scalar(b"primary", &p.primary.unwrap_or(Scalar::zero()));
+            t.commit_scalar(b"secondary", &p.secondary.unwrap_or(Scalar::zero()));
+            t.commit_u64(b"m", p.arbitrary.len() as u64);
+            for (w, x) in p.arbitrary.iter() {
+                t.commit_scalar(b"w", w);
+                t.commit_point(b"X", x);
+            }
+        }
+        batch.iter().map(|_| t.challenge_scalar(b"e")).collect()
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use bulletproofs::PedersenGens;
 
     #[test]
     fn empty() {
@@ -212,4 +282,21 @@ mod tests {
         };
         assert!(PointOp::verify_batch(&[op1, op2]).is_ok());
     }
+
+    #[test]
+    fn large_batch_verify() {
+        let gens = PedersenGens::default();
+        // More points than in one chunk of the parallel multi-scalar multiplication.
+        let mut ops = (1..600u64)
+            .map(|i| PointOp {
+                primary: Some(Scalar::from(i)),
+                secondary: None,
+                arbitrary: vec![(-Scalar::one(), (Scalar::from(i) * gens.B).compress())],
+            })
+            .collect::<Vec<_>>();
+        assert!(PointOp::verify_batch(&ops).is_ok());
+
+        ops[500].primary = Some(Scalar::zero());
+        assert!(PointOp::verify_batch(&ops).is_err());
+    }
 }
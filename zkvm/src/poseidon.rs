@@ -0,0 +1,286 @@
+//! Poseidon hash over the Ristretto scalar field.
+//!
+//! Unlike SHA-2, Poseidon operates on scalars natively: the permutation uses
+//! 243 multipliers, so hashing a node of a Merkle tree in the constraint system
+//! is about a hundred times cheaper than one block of SHA-256.
+//! The host-side `Poseidon::hash` and the `gadgets::poseidon` gadget evaluate
+//! the same permutation on scalars and on expressions, so they produce the same hash.
+//!
+//! Parameters: a state of 3 scalars (rate 2, capacity 1), S-box `x^5`,
+//! 8 full rounds and 57 partial rounds. The round constants are derived from a Merlin transcript
+//! and the MDS matrix is the Cauchy matrix `1/(x_i + y_j)` with `x_i = i` and `y_j = 3 + j`.
+//! The capacity element is initialized with the number of inputs,
+//! so the hashes of inputs of different lengths are independent.
+
+use bulletproofs::r1cs::ConstraintSystem;
+use curve25519_dalek::scalar::Scalar;
+use merlin::Transcript;
+
+use crate::constraints::Expression;
+use crate::errors::VMError;
+use crate::transcript::{labels, TranscriptProtocol};
+
+const WIDTH: usize = 3;
+const RATE: usize = 2;
+const FULL_ROUNDS: usize = 8;
+const PARTIAL_ROUNDS: usize = 57;
+
+/// Number of S-boxes in one permutation, each allocating three multipliers.
+pub(crate) const SBOXES: usize = FULL_ROUNDS * WIDTH + PARTIAL_ROUNDS;
+
+/// Parameters of the Poseidon permutation.
+#[derive(Clone)]
+pub struct Poseidon {
+    round_constants: Vec<[Scalar; WIDTH]>,
+    mds: [[Scalar; WIDTH]; WIDTH],
+}
+
+/// Step of a Merkle path in a tree hashed with Poseidon.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum PoseidonNeighbor {
+    /// Hash of the left subtree.
+    Left(Scalar),
+    /// Hash of the right subtree.
+    Right(Scalar),
+}
+
+impl Poseidon {
+    /// Derives the round constants and the MDS matrix.
+    pub fn new() -> Self {
+        let mut t = Transcript::new(labels::POSEIDON);
+        t.commit_u64(b"width", WIDTH as u64);
+        t.commit_u64(b"full_rounds", FULL_ROUNDS as u64);
+        t.commit_u64(b"partial_rounds", PARTIAL_ROUNDS as u64);
+        let round_constants = (0..FULL_ROUNDS + PARTIAL_ROUNDS)
+            .map(|_| {
+                [
+                    t.challenge_scalar(b"c"),
+                    t.challenge_scalar(b"c"),
+                    t.challenge_scalar(b"c"),
+                ]
+            })
+            .collect();
+
+        let mut mds = [[Scalar::zero(); WIDTH]; WIDTH];
+        for (i, row) in mds.iter_mut().enumerate() {
+            for (j, m) in row.iter_mut().enumerate() {
+                *m = Scalar::from((i + WIDTH + j) as u64).invert();
+            }
+        }
+        Poseidon {
+            round_constants,
+            mds,
+        }
+    }
+
+    /// Computes the hash of the scalars.
+    pub fn hash(&self, inputs: &[Scalar]) -> Scalar {
+        let mut state = [
+            Scalar::from(inputs.len() as u64),
+            Scalar::zero(),
+            Scalar::zero(),
+        ];
+        for block in 0..Self::permutations(inputs.len()) {
+            for (s, x) in state[1..].iter_mut().zip(inputs.iter().skip(block * RATE)) {
+                *s += x;
+            }
+            self.permute(&mut state);
+        }
+        state[1]
+    }
+
+    /// Computes the nullifier of a one-time claim: the hash of the `domain`
+    /// (e.g. the root of the claim tree) and the secret of the claim.
+    /// The claim itself is committed as `hash(&[secret])`, so the nullifier
+    /// does not reveal which claim is redeemed, and the same secret
+    /// has independent nullifiers in different domains.
+    pub fn nullifier(&self, domain: Scalar, secret: Scalar) -> Scalar {
+        self.hash(&[domain, secret])
+    }
+
+    /// Computes the root of the Merkle tree of the scalars.
+    /// Leaves are hashed individually, and the tree is split
+    /// at the largest power of two, like `MerkleTree`.
+    pub fn merkle_root(&self, leaves: &[Scalar]) -> Scalar {
+        match leaves.len() {
+            0 => self.hash(&[]),
+            1 => self.hash(leaves),
+            n => {
+                let k = n.next_power_of_two() / 2;
+                self.hash(&[
+                    self.merkle_root(&leaves[..k]),
+                    self.merkle_root(&leaves[k..]),
+                ])
+            }
+        }
+    }
+
+    /// Builds the Merkle path for the leaf at the given index, starting from the bottom of the tree.
+    pub fn merkle_path(
+        &self,
+        leaves: &[Scalar],
+        index: usize,
+    ) -> Result<Vec<PoseidonNeighbor>, VMError> {
+        if index >= leaves.len() {
+            return Err(VMError::InvalidMerkleProof);
+        }
+        let mut result = Vec::new();
+        self.subpath(leaves, index, &mut result);
+        Ok(result)
+    }
+
+    /// Computes the root of the Merkle tree from the leaf and its path.
+    pub fn merkle_path_root(&self, leaf: Scalar, path: &[PoseidonNeighbor]) -> Scalar {
+        path.iter()
+            .fold(self.hash(&[leaf]), |node, neighbor| match neighbor {
+                PoseidonNeighbor::Left(l) => self.hash(&[*l, node]),
+                PoseidonNeighbor::Right(r) => self.hash(&[node, *r]),
+            })
+    }
+
+    /// Computes the hash of the expressions in the constraint system.
+    /// Allocates `3*SBOXES` multipliers per permutation,
+    /// except for the S-boxes applied to constants.
+    pub(crate) fn hash_expr<CS: ConstraintSystem>(
+        &self,
+        cs: &mut CS,
+        inputs: &[Expression],
+    ) -> Expression {
+        let mut state = [
+            Expression::constant(inputs.len() as u64),
+            Expression::constant(0u64),
+            Expression::constant(0u64),
+        ];
+        for block in 0..Self::permutations(inputs.len()) {
+            for (s, x) in state[1..].iter_mut().zip(inputs.iter().skip(block * RATE)) {
+                *s = s.clone() + x.clone();
+            }
+            self.permute_expr(cs, &mut state);
+        }
+        state[1].clone()
+    }
+
+    /// Number of permutations needed to absorb `n` inputs.
+    pub(crate) fn permutations(n: usize) -> usize {
+        core::cmp::max(1, (n + RATE - 1) / RATE)
+    }
+
+    fn subpath(&self, leaves: &[Scalar], index: usize, result: &mut Vec<PoseidonNeighbor>) {
+        if leaves.len() <= 1 {
+            return;
+        }
+        let k = leaves.len().next_power_of_two() / 2;
+        if index < k {
+            self.subpath(&leaves[..k], index, result);
+            result.push(PoseidonNeighbor::Right(self.merkle_root(&leaves[k..])));
+        } else {
+            self.subpath(&leaves[k..], index - k, result);
+            result.push(PoseidonNeighbor::Left(self.merkle_root(&leaves[..k])));
+        }
+    }
+
+    fn permute(&self, state: &mut [Scalar; WIDTH]) {
+        for (round, constants) in self.round_constants.iter().enumerate() {
+            for (s, c) in state.iter_mut().zip(constants.iter()) {
+                *s += c;
+            }
+            for s in state.iter_mut().take(Self::sboxes(round)) {
+                let x2 = *s * *s;
+                *s = x2 * x2 * *s;
+            }
+            let old = *state;
+            for (s, row) in state.iter_mut().zip(self.mds.iter()) {
+                *s = old
+                    .iter()
+                    .zip(row.iter())
+                    .fold(Scalar::zero(), |sum, (x, m)| sum + x * m);
+            }
+        }
+    }
+
+    fn permute_expr<CS: ConstraintSystem>(&self, cs: &mut CS, state: &mut [Expression; WIDTH]) {
+        for (round, constants) in self.round_constants.iter().enumerate() {
+            for (s, c) in state.iter_mut().zip(constants.iter()) {
+                *s = s.clone() + Expression::constant(*c);
+            }
+            for s in state.iter_mut().take(Self::sboxes(round)) {
+                let x2 = s.clone().multiply(s.clone(), cs);
+                let x4 = x2.clone().multiply(x2, cs);
+                *s = x4.multiply(s.clone(), cs);
+            }
+            let old = state.clone();
+            for (s, row) in state.iter_mut().zip(self.mds.iter()) {
+                *s = Expression::dot(&old, row);
+            }
+        }
+    }
+
+    /// Full rounds apply the S-box to the whole state, partial rounds only to the first scalar.
+    fn sboxes(round: usize) -> usize {
+        if round < FULL_ROUNDS / 2 || round >= FULL_ROUNDS / 2 + PARTIAL_ROUNDS {
+            WIDTH
+        } else {
+            1
+        }
+    }
+}
+
+impl Default for Poseidon {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bulletproofs::{r1cs, BulletproofGens, PedersenGens};
+
+    #[test]
+    fn hash() {
+        let poseidon = Poseidon::new();
+        let a = Scalar::from(1u64);
+        let b = Scalar::from(2u64);
+        assert_eq!(poseidon.hash(&[a, b]), Poseidon::new().hash(&[a, b]));
+        assert_ne!(poseidon.hash(&[a, b]), poseidon.hash(&[b, a]));
+        // The length of the input is committed, so padding with zeros changes the hash.
+        assert_ne!(poseidon.hash(&[a]), poseidon.hash(&[a, Scalar::zero()]));
+        assert_ne!(poseidon.hash(&[]), poseidon.hash(&[Scalar::zero()]));
+        assert_ne!(poseidon.nullifier(a, b), poseidon.nullifier(b, b));
+        assert_ne!(poseidon.nullifier(a, b), poseidon.hash(&[b]));
+
+        // Hashing constant expressions does not allocate multipliers.
+        let bp_gens = BulletproofGens::new(1, 1);
+        let pc_gens = PedersenGens::default();
+        let mut transcript = Transcript::new(b"PoseidonTest");
+        let mut cs = r1cs::Verifier::new(&bp_gens, &pc_gens, &mut transcript);
+        for n in 0..6 {
+            let inputs = (0..n).map(|i| Scalar::from(i as u64)).collect::<Vec<_>>();
+            let exprs = inputs
+                .iter()
+                .map(|x| Expression::constant(*x))
+                .collect::<Vec<_>>();
+            let hash = poseidon.hash_expr(&mut cs, &exprs);
+            assert_eq!(
+                hash.eval().map(|h| h.to_scalar()),
+                Some(poseidon.hash(&inputs))
+            );
+        }
+    }
+
+    #[test]
+    fn merkle_path() {
+        let poseidon = Poseidon::new();
+        let leaves = (0..5u64).map(Scalar::from).collect::<Vec<_>>();
+        let root = poseidon.merkle_root(&leaves);
+        for (i, leaf) in leaves.iter().enumerate() {
+            let path = poseidon.merkle_path(&leaves, i).unwrap();
+            assert_eq!(poseidon.merkle_path_root(*leaf, &path), root);
+            assert_ne!(poseidon.merkle_path_root(leaf + Scalar::one(), &path), root);
+        }
+        assert_eq!(
+            poseidon.merkle_path(&leaves, 5),
+            Err(VMError::InvalidMerkleProof)
+        );
+    }
+}
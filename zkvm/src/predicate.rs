@@ -3,21 +3,27 @@
 //! Operations:
 //! - disjunction: P = L + f(L,R)*B
 //! - program_commitment: P = h(prog)*B2
-use bulletproofs::PedersenGens;
+//! - disclosure: path from the root to a leaf via `Predicate::proof_for`
 use curve25519_dalek::ristretto::CompressedRistretto;
 use curve25519_dalek::scalar::Scalar;
 use merlin::Transcript;
+use rand::{CryptoRng, RngCore};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 use std::borrow::Borrow;
 
 use crate::encoding;
 use crate::errors::VMError;
+use crate::gens;
 use crate::point_ops::PointOp;
 use crate::program::Program;
+use crate::signature::musig::Multikey;
 use crate::signature::VerificationKey;
-use crate::transcript::TranscriptProtocol;
+use crate::transcript::{labels, TranscriptProtocol};
 
 /// Represents a ZkVM predicate with its optional witness data.
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Predicate {
     /// Verifier's view on the predicate in a compressed form to defer decompression cost.
     Opaque(CompressedRistretto),
@@ -35,6 +41,7 @@ pub enum Predicate {
 }
 
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct PredicateDisjunction {
     preds: Vec<Predicate>,
     // We precompute the disjunction predicate when composing it via `Predicate::or()`,
@@ -42,6 +49,30 @@ pub struct PredicateDisjunction {
     precomputed_point: CompressedRistretto,
 }
 
+/// Disclosure of a single leaf of a predicate tree:
+/// the leaf predicate and the disjunctions on the path from the root to it.
+#[derive(Clone, Debug)]
+pub struct PredicateProof {
+    /// Disclosed leaf predicate.
+    pub leaf: Predicate,
+
+    /// Disjunctions from the root to the leaf, one per level of the tree.
+    pub path: Vec<PredicateBranch>,
+}
+
+/// One level of the path in a predicate tree.
+#[derive(Clone, Debug)]
+pub struct PredicateBranch {
+    /// Opaque predicates of all the branches of the disjunction.
+    pub branches: Vec<Predicate>,
+
+    /// Index of the branch leading to the disclosed leaf.
+    pub index: usize,
+
+    /// Adjustment factor `f` of the disjunction: `P = X[0] + f*B`.
+    pub adjustment: Scalar,
+}
+
 impl Predicate {
     /// Returns the number of bytes needed to serialize the Predicate.
     pub fn serialized_length(&self) -> usize {
@@ -58,7 +89,7 @@ impl Predicate {
                 let mut bytecode = Vec::new();
                 prog.encode(&mut bytecode);
                 let h = Predicate::commit_program(&bytecode, blinding);
-                (h * PedersenGens::default().B_blinding).compress()
+                gens::mul_blinding(&h).compress()
             }
         }
     }
@@ -97,8 +128,18 @@ impl Predicate {
     }
 
     /// Creates a program with a random blinding factor.
+    #[cfg(feature = "std")]
     pub fn blinded_program<T: Into<Program>>(x: T) -> Self {
-        let blinding: [u8; 16] = rand::random();
+        Predicate::blinded_program_with_rng(x, &mut rand::thread_rng())
+    }
+
+    /// Creates a program with a blinding factor sampled from the provided RNG.
+    pub fn blinded_program_with_rng<T: Into<Program>, R: RngCore + CryptoRng>(
+        x: T,
+        rng: &mut R,
+    ) -> Self {
+        let mut blinding = [0u8; 16];
+        rng.fill_bytes(&mut blinding);
         Predicate::Program(x.into(), blinding.to_vec())
     }
 
@@ -144,7 +185,7 @@ impl Predicate {
                 .to_point()
                 .decompress()
                 .ok_or(VMError::InvalidPoint)?;
-            l + f * PedersenGens::default().B
+            l + gens::mul_base(&f)
         };
         Ok(Predicate::Or(PredicateDisjunction {
             preds: preds,
@@ -152,13 +193,111 @@ impl Predicate {
         }))
     }
 
+    /// Creates a predicate that requires signatures of `t` out of the `keys`:
+    /// a disjunction of the aggregated keys of all `t`-element subsets of the keys
+    /// in lexicographic order, so spending reveals only the subset that signed.
+    /// For `t` equal to the number of keys it is the aggregated key itself.
+    /// Fails with `VMError::BadArguments` if `t` is zero or above the number of keys,
+    /// or if there are more subsets than `select` can choose from (255).
+    pub fn multisig(t: usize, keys: &[VerificationKey]) -> Result<Self, VMError> {
+        let n = keys.len();
+        if t == 0 || t > n {
+            return Err(VMError::BadArguments);
+        }
+        let mut leaves = Vec::new();
+        let mut indices: Vec<usize> = (0..t).collect();
+        loop {
+            let subset = indices.iter().map(|&i| keys[i]).collect();
+            leaves.push(Predicate::Key(Multikey::new(subset)?.aggregated_key()));
+
+            // Advance to the next subset: increment the rightmost index that can be incremented
+            // and put the following indices right after it.
+            let j = match (0..t).rev().find(|&j| indices[j] < n - t + j) {
+                Some(j) => j,
+                None => break,
+            };
+            if leaves.len() == u8::max_value() as usize {
+                return Err(VMError::BadArguments);
+            }
+            indices[j] += 1;
+            for k in j + 1..t {
+                indices[k] = indices[k - 1] + 1;
+            }
+        }
+        if leaves.len() == 1 {
+            return Ok(leaves.remove(0));
+        }
+        Predicate::disjunction(leaves)
+    }
+
+    /// Returns the leaves of the predicate tree in depth-first order.
+    /// Leaves are all the predicates that are not disjunctions,
+    /// including the opaque predicates whose structure is unknown.
+    pub fn leaves(&self) -> Vec<&Predicate> {
+        let mut leaves = Vec::new();
+        self.collect_leaves(&mut leaves);
+        leaves
+    }
+
+    /// Builds a disclosure of the leaf with the given index in `leaves()` order.
+    /// The resulting path contains the blinded siblings and the adjustment factor
+    /// for each disjunction on the way from the root to the leaf.
+    pub fn proof_for(&self, leaf_index: usize) -> Result<PredicateProof, VMError> {
+        let mut path = Vec::new();
+        let mut index = leaf_index;
+        let mut current = self;
+        while let Predicate::Or(d) = current {
+            let mut selected = None;
+            for (k, pred) in d.preds.iter().enumerate() {
+                let count = pred.leaves_count();
+                if index < count {
+                    selected = Some(k);
+                    break;
+                }
+                index -= count;
+            }
+            let k = selected.ok_or(VMError::PredicateIndexInvalid)?;
+            path.push(PredicateBranch {
+                branches: d.preds.iter().map(|p| p.as_opaque()).collect(),
+                index: k,
+                adjustment: Self::commit_disjunction(d.preds.iter().map(|p| p.to_point())),
+            });
+            current = &d.preds[k];
+        }
+        if index != 0 {
+            return Err(VMError::PredicateIndexInvalid);
+        }
+        Ok(PredicateProof {
+            leaf: current.clone(),
+            path,
+        })
+    }
+
+    fn collect_leaves<'a>(&'a self, leaves: &mut Vec<&'a Predicate>) {
+        match self {
+            Predicate::Or(d) => {
+                for pred in d.preds.iter() {
+                    pred.collect_leaves(leaves);
+                }
+            }
+            _ => leaves.push(self),
+        }
+    }
+
+    fn leaves_count(&self) -> usize {
+        match self {
+            Predicate::Or(d) => d.preds.iter().map(|p| p.leaves_count()).sum(),
+            _ => 1,
+        }
+    }
+
     fn commit_disjunction<I>(preds: I) -> Scalar
     where
         I: IntoIterator,
         I::Item: Borrow<CompressedRistretto>,
         I::IntoIter: ExactSizeIterator,
     {
-        let mut t = Transcript::new(b"ZkVM.predicate");
+        let mut t = Transcript::new(labels::PREDICATE);
         let iter = preds.into_iter();
         t.commit_u64(b"n", iter.len() as u64);
         for x in iter {
@@ -168,13 +307,45 @@ impl Predicate {
     }
 
     fn commit_program(prog: &[u8], blinding: &[u8]) -> Scalar {
-        let mut t = Transcript::new(b"ZkVM.predicate");
+        let mut t = Transcript::new(labels::PREDICATE);
         t.commit_bytes(b"blinding", blinding);
         t.commit_bytes(b"prog", &prog);
         t.challenge_scalar(b"h")
     }
 }
 
+impl PredicateProof {
+    /// Verifies that the disclosed leaf belongs to the tree with the given root predicate.
+    pub fn verify(&self, root: &Predicate) -> Result<(), VMError> {
+        let mut point = root.to_point();
+        let mut ops = Vec::with_capacity(self.path.len());
+        for branch in self.path.iter() {
+            let selected = branch
+                .branches
+                .get(branch.index)
+                .ok_or(VMError::PredicateIndexInvalid)?;
+            let f = Predicate::commit_disjunction(branch.branches.iter().map(|p| p.to_point()));
+            if f != branch.adjustment {
+                return Err(VMError::PointOperationFailed);
+            }
+            // P = X[0] + f*B
+            ops.push(PointOp {
+                primary: Some(f),
+                secondary: None,
+                arbitrary: vec![
+                    (Scalar::one(), branch.branches[0].to_point()),
+                    (-Scalar::one(), point),
+                ],
+            });
+            point = selected.to_point();
+        }
+        if point != self.leaf.to_point() {
+            return Err(VMError::PointOperationFailed);
+        }
+        PointOp::verify_batch(&ops)
+    }
+}
+
 impl Into<CompressedRistretto> for Predicate {
     fn into(self) -> CompressedRistretto {
         self.to_point()
@@ -209,6 +380,32 @@ mod tests {
         assert!(op.verify().is_err());
     }
 
+    #[test]
+    fn multisig() {
+        let keys: Vec<_> = (1..5u64)
+            .map(|i| VerificationKey::from_secret(&Scalar::from(i)))
+            .collect();
+        let aggregated = |subset: &[usize]| {
+            Multikey::new(subset.iter().map(|&i| keys[i]).collect())
+                .unwrap()
+                .aggregated_key()
+        };
+
+        let pred = Predicate::multisig(2, &keys).unwrap();
+        let leaves = pred.leaves();
+        assert_eq!(leaves.len(), 6);
+        assert_eq!(leaves[0].to_point(), aggregated(&[0, 1]).0);
+        assert_eq!(leaves[2].to_point(), aggregated(&[0, 3]).0);
+        assert_eq!(leaves[5].to_point(), aggregated(&[2, 3]).0);
+
+        let all = Predicate::multisig(4, &keys).unwrap();
+        assert_eq!(all.to_point(), aggregated(&[0, 1, 2, 3]).0);
+        assert_eq!(Predicate::multisig(1, &keys).unwrap().leaves().len(), 4);
+
+        assert!(Predicate::multisig(0, &keys).is_err());
+        assert!(Predicate::multisig(5, &keys).is_err());
+    }
+
     #[test]
     fn valid_disjunction1() {
         let gens = PedersenGens::default();
@@ -252,4 +449,59 @@ mod tests {
         let op = pred.prove_disjunction(&preds);
         assert!(op.verify().is_err());
     }
+
+    fn leaf_tree() -> (Predicate, Vec<Predicate>) {
+        let gens = PedersenGens::default();
+        let leaves = vec![
+            Predicate::Opaque(gens.B.compress()),
+            Predicate::unblinded_program(Program::build(|p| p.drop())),
+            Predicate::blinded_program(Program::build(|p| p.dup(1))),
+            Predicate::Opaque(gens.B_blinding.compress()),
+        ];
+        let inner = Predicate::disjunction(vec![leaves[1].clone(), leaves[2].clone()]).unwrap();
+        let tree =
+            Predicate::disjunction(vec![leaves[0].clone(), inner, leaves[3].clone()]).unwrap();
+        (tree, leaves)
+    }
+
+    #[test]
+    fn enumerate_leaves() {
+        let (tree, leaves) = leaf_tree();
+        let points: Vec<_> = tree.leaves().iter().map(|p| p.to_point()).collect();
+        let expected: Vec<_> = leaves.iter().map(|p| p.to_point()).collect();
+        assert_eq!(points, expected);
+    }
+
+    #[test]
+    fn valid_leaf_proofs() {
+        let (tree, leaves) = leaf_tree();
+        for (i, leaf) in leaves.iter().enumerate() {
+            let proof = tree.proof_for(i).unwrap();
+            assert_eq!(proof.leaf.to_point(), leaf.to_point());
+            assert!(proof.verify(&tree).is_ok());
+        }
+        let proof = tree.proof_for(2).unwrap();
+        assert_eq!(proof.path.len(), 2);
+        assert_eq!(proof.path[0].index, 1);
+        assert_eq!(proof.path[1].index, 1);
+        assert!(tree.proof_for(4).is_err());
+    }
+
+    #[test]
+    fn invalid_leaf_proofs() {
+        let (tree, _) = leaf_tree();
+        let (other_tree, _) = leaf_tree();
+
+        // Blinded program leaf makes the other tree different.
+        let proof = tree.proof_for(1).unwrap();
+        assert!(proof.verify(&other_tree).is_err());
+
+        let mut wrong_leaf = proof.clone();
+        wrong_leaf.leaf = Predicate::Opaque(PedersenGens::default().B.compress());
+        assert!(wrong_leaf.verify(&tree).is_err());
+
+        let mut wrong_adjustment = proof;
+        wrong_adjustment.path[0].adjustment += Scalar::one();
+        assert!(wrong_adjustment.verify(&tree).is_err());
+    }
 }
@@ -1,15 +1,27 @@
+use crate::assembler;
+use crate::circuit_size::CircuitSize;
+#[cfg(feature = "std")]
+use crate::constraints::Commitment;
 use crate::encoding::SliceReader;
 use crate::errors::VMError;
 use crate::ops::Instruction;
-use crate::predicate::Predicate;
+use crate::predicate::{Predicate, PredicateProof};
 use crate::scalar_witness::ScalarWitness;
+use crate::signature::VerificationKey;
+use crate::typecheck;
 use crate::types::Data;
 use core::borrow::Borrow;
+use core::fmt;
+#[cfg(feature = "std")]
+use curve25519_dalek::scalar::Scalar;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 use spacesuit::BitRange;
 
 /// A builder type for assembling a sequence of `Instruction`s with chained method calls.
 /// E.g. `let prog = Program::new().push(...).input().push(...).output(1).to_vec()`.
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Program(Vec<Instruction>);
 
 macro_rules! def_op {
@@ -42,6 +54,7 @@ impl Program {
     def_op!(and, And);
     def_op!(borrow, Borrow);
     def_op!(call, Call);
+    def_op!(carry, Carry, usize);
     def_op!(cloak, Cloak, usize, usize);
     def_op!(r#const, Const);
     def_op!(contract, Contract, usize);
@@ -51,6 +64,7 @@ impl Program {
     def_op!(eq, Eq);
     def_op!(export, Export);
     def_op!(expr, Expr);
+    def_op!(fee, Fee);
     def_op!(import, Import);
     def_op!(input, Input);
     def_op!(issue, Issue);
@@ -60,17 +74,29 @@ impl Program {
     def_op!(mul, Mul);
     def_op!(neg, Neg);
     def_op!(nonce, Nonce);
+    def_op!(nullify, Nullify);
     def_op!(or, Or);
     def_op!(output, Output, usize);
+    def_op!(poseidon, Poseidon, usize);
     def_op!(range, Range, BitRange);
     def_op!(retire, Retire);
     def_op!(roll, Roll, usize);
     def_op!(select, Select, u8, u8);
+    def_op!(sha256, Sha256, usize);
+    def_op!(sha512, Sha512, usize);
     def_op!(sign_tx, Signtx);
     def_op!(unblind, Unblind);
     def_op!(var, Var);
     def_op!(verify, Verify);
 
+    /// Records the label with the top item of the stack in the prover's debug log
+    /// (see `Prover::build_tx_inspect`). The instruction is not encoded in the bytecode,
+    /// so it does not affect the transaction.
+    pub fn inspect<S: Into<String>>(&mut self, label: S) -> &mut Program {
+        self.0.push(Instruction::Inspect(label.into()));
+        self
+    }
+
     /// Creates an empty `Program`.
     pub fn new() -> Self {
         Program(vec![])
@@ -87,6 +113,24 @@ impl Program {
         program
     }
 
+    /// Creates a program from a list of instructions.
+    pub fn from_instructions(instructions: Vec<Instruction>) -> Self {
+        Program(instructions)
+    }
+
+    /// Assembles a program from the textual format: whitespace-separated instructions
+    /// in the notation of the specification, e.g. `push:3:0x010203 input signtx`,
+    /// as produced by the `Display` implementation.
+    ///
+    /// The length of the pushed data may be omitted (`push:0x010203`), and a bare hex literal
+    /// is a shorthand for `push`. Text after `#` until the end of the line is a comment.
+    /// A label `name:` starts a block of instructions that is not executed in place,
+    /// but is pushed as a data string with `@name` (e.g. a program for `call`).
+    /// Fails with `VMError::AssemblyError` pointing to the first invalid token.
+    pub fn parse_assembly(src: &str) -> Result<Self, VMError> {
+        assembler::assemble(src)
+    }
+
     /// Creates a program from parsing the opaque data slice of encoded instructions.
     pub(crate) fn parse(data: &[u8]) -> Result<Self, VMError> {
         SliceReader::parse(data, |r| {
@@ -98,11 +142,36 @@ impl Program {
         })
     }
 
+    /// Decodes the bytecode into a list of instructions.
+    /// Fails with `VMError::FormatError` if the bytecode ends in the middle of an instruction.
+    pub fn disassemble(bytecode: &[u8]) -> Result<Vec<Instruction>, VMError> {
+        Self::parse(bytecode).map(|p| p.to_vec())
+    }
+
     /// Converts the program to a plain vector of instructions.
     pub fn to_vec(self) -> Vec<Instruction> {
         self.0
     }
 
+    /// Estimates the number of multipliers and constraints that the program
+    /// allocates in the constraint system. The estimate is an upper bound:
+    /// e.g. `mul` is counted as a multiplier even if one of the operands is a constant.
+    /// Nested programs executed via `call` and `delegate` are not included.
+    pub fn estimate_circuit_size(&self) -> CircuitSize {
+        self.0.iter().fold(CircuitSize::default(), |size, instr| {
+            size + CircuitSize::instruction(instr)
+        })
+    }
+
+    /// Checks the types of the stack items and the stack depth of the transaction's program
+    /// without executing it, to catch the errors before the expensive proving.
+    /// Fails with `VMError::TypeCheckFailed` pointing to the byte offset of the failing instruction.
+    /// The check stops at `call` and `delegate`, whose nested programs are not checked,
+    /// and at `signtx` of a contract whose payload is not known to the prover.
+    pub fn typecheck(&self) -> Result<(), VMError> {
+        typecheck::typecheck(&self.0)
+    }
+
     /// Returns the serialized length of the program.
     pub(crate) fn serialized_length(&self) -> usize {
         self.0.iter().map(|p| p.serialized_length()).sum()
@@ -115,12 +184,144 @@ impl Program {
         }
     }
 
+    /// Encodes the program into a bytecode.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(self.serialized_length());
+        self.encode(&mut buf);
+        buf
+    }
+
     /// Adds a `push` instruction with an immediate data type that can be converted into `Data`.
     pub fn push<T: Into<Data>>(&mut self, data: T) -> &mut Program {
         self.0.push(Instruction::Push(data.into()));
         self
     }
 
+    /// Adds instructions that require the transaction's `mintime` to be at least `time`,
+    /// so the transaction cannot be included in a block before `time`.
+    /// Leaves the stack unchanged.
+    pub fn after(&mut self, time: u64) -> &mut Program {
+        self.mintime() // stack: mintime
+            .push(time)
+            .r#const()
+            .neg() // stack: mintime, -time
+            .add()
+            .range(BitRange::u64()) // stack: mintime-time
+            .drop()
+    }
+
+    /// Adds instructions that require the transaction's `maxtime` to be at most `time`,
+    /// so the transaction cannot be included in a block after `time`.
+    /// Leaves the stack unchanged.
+    pub fn before(&mut self, time: u64) -> &mut Program {
+        self.push(time)
+            .r#const() // stack: time
+            .maxtime()
+            .neg() // stack: time, -maxtime
+            .add()
+            .range(BitRange::u64()) // stack: time-maxtime
+            .drop()
+    }
+
+    /// Adds instructions that push the creation time of a contract to be stored in its payload,
+    /// for a relative timelock (see `mature`). Requires the transaction's `maxtime`
+    /// to be at most `time`, so the contract cannot be created later than the recorded time.
+    /// The time is committed to by the contract ID, together with the rest of the payload.
+    pub fn timestamp(&mut self, time: u64) -> &mut Program {
+        self.before(time).push(time) // stack: time
+    }
+
+    /// Adds instructions for a contract's program that make the contract spendable
+    /// only `delay` after its creation. Pops the creation time recorded with `timestamp`
+    /// (which is at the top of the stack when the contract's payload ends with it),
+    /// and requires the transaction's `mintime` to be at least the creation time plus `delay`.
+    pub fn mature(&mut self, delay: u64) -> &mut Program {
+        self.r#const() // stack: time
+            .push(delay)
+            .r#const()
+            .add()
+            .neg() // stack: -(time+delay)
+            .mintime()
+            .add()
+            .range(BitRange::u64()) // stack: mintime-(time+delay)
+            .drop()
+    }
+
+    /// Adds the instructions produced by `body` for each iteration `i` in `0..n`.
+    /// Each iteration operates on the stack left by the previous one, e.g. to pay out
+    /// the `n` values on top of the stack, the body pushes the `i`-th predicate and adds `output:1`.
+    /// The loop is unrolled, so the bytecode contains `n` copies of the body
+    /// and the VM executes it as a straight-line program.
+    pub fn repeat<F>(&mut self, n: usize, mut body: F) -> &mut Program
+    where
+        F: FnMut(&mut Program, usize) -> &mut Program,
+    {
+        for i in 0..n {
+            body(self, i);
+        }
+        self
+    }
+
+    /// Adds instructions that lock the value on top of the stack in an output
+    /// spendable with a signature by the verification key `key`.
+    /// Stack: value → ø.
+    pub fn pay_to_key(&mut self, key: VerificationKey) -> &mut Program {
+        self.push(Predicate::Key(key)).output(1)
+    }
+
+    /// Adds instructions that split the value on top of the stack into values of flavor `flv`
+    /// with the quantities `qtys` (the last one on top of the stack).
+    /// The quantities must add up to the quantity of the split value.
+    /// Stack: value → values.
+    #[cfg(feature = "std")]
+    pub fn split_value(&mut self, flv: Scalar, qtys: &[u64]) -> &mut Program {
+        self.repeat(qtys.len(), |p, i| {
+            p.push(Commitment::blinded(qtys[i]))
+                .push(Commitment::blinded(flv))
+        })
+        .cloak(1, qtys.len())
+    }
+
+    /// Adds instructions that merge the `m` values on top of the stack
+    /// into a single value of flavor `flv` and quantity `qty`.
+    /// The merged values must have the same flavor and add up to `qty`.
+    /// Stack: values → value.
+    #[cfg(feature = "std")]
+    pub fn merge_values(&mut self, m: usize, flv: Scalar, qty: u64) -> &mut Program {
+        self.push(Commitment::blinded(qty))
+            .push(Commitment::blinded(flv))
+            .cloak(m, 1)
+    }
+
+    /// Adds instructions that lock the value on top of the stack in an output
+    /// that cannot be spent before `time`. The output's predicate is the program
+    /// `Program::timelock(time, pred)`, which the spender executes with `call`.
+    /// Stack: value → ø.
+    pub fn lock_until(&mut self, time: u64, pred: Predicate) -> &mut Program {
+        self.push(Predicate::unblinded_program(Program::timelock(time, pred)))
+            .output(1)
+    }
+
+    /// Creates a contract's program that requires the transaction's `mintime` to be
+    /// at least `time` and locks the value of the contract's payload in a new contract
+    /// with the predicate `pred`, which is left on the stack to be unlocked
+    /// (e.g. with `signtx` for a key predicate).
+    pub fn timelock(time: u64, pred: Predicate) -> Program {
+        Program::build(|p| p.after(time).push(pred).contract(1))
+    }
+
+    /// Adds instructions that lock the value on top of the stack in an output
+    /// spendable with signatures of `t` out of the `keys` (see `Predicate::multisig`).
+    /// Stack: value → ø.
+    pub fn multisig(
+        &mut self,
+        t: usize,
+        keys: &[VerificationKey],
+    ) -> Result<&mut Program, VMError> {
+        let pred = Predicate::multisig(t, keys)?;
+        Ok(self.push(pred).output(1))
+    }
+
     /// Takes predicate and closure to add choose operations for
     /// predicate tree traversal.
     pub fn choose_predicate<F, T>(
@@ -139,6 +340,19 @@ impl Program {
     }
 }
 
+impl fmt::Display for Program {
+    /// Formats the program as a space-separated list of instructions.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, instr) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" ")?;
+            }
+            write!(f, "{}", instr)?;
+        }
+        Ok(())
+    }
+}
+
 /// Adds data and instructions to traverse a predicate tree.
 pub struct PredicateTree<'a> {
     prog: &'a mut Program,
@@ -146,14 +360,16 @@ pub struct PredicateTree<'a> {
 }
 
 impl<'a> PredicateTree<'a> {
-    /// Kth Predicate branch
+    /// Kth Predicate branch.
+    /// Fails with `VMError::PredicateIndexInvalid` if `k` is out of range,
+    /// leaving the program unchanged.
     pub fn select(self, k: usize) -> Result<Self, VMError> {
         let preds = self.pred.to_disjunction()?;
         let n = preds.len();
-        let selected = preds[k].clone();
         if k >= n {
             return Err(VMError::PredicateIndexInvalid);
         }
+        let selected = preds[k].clone();
         let prog = self.prog;
         for pred in preds.iter() {
             prog.push(pred.as_opaque());
@@ -165,12 +381,112 @@ impl<'a> PredicateTree<'a> {
         })
     }
 
-    /// Pushes program to the stack and calls the contract protected
-    /// by the program predicate.
+    /// Returns the disclosure of the leaf with the given index
+    /// in the current subtree, in `Predicate::leaves()` order.
+    pub fn proof_for(&self, leaf_index: usize) -> Result<PredicateProof, VMError> {
+        self.pred.proof_for(leaf_index)
+    }
+
+    /// Selects the branches on the path to the leaf with the given index.
+    pub fn select_leaf(self, leaf_index: usize) -> Result<Self, VMError> {
+        let proof = self.pred.proof_for(leaf_index)?;
+        proof
+            .path
+            .iter()
+            .try_fold(self, |tree, branch| tree.select(branch.index))
+    }
+
+    /// Pushes the blinding factor and the program to the stack and calls the contract protected
+    /// by the program predicate, matching the operands of the `call` instruction.
     pub fn call(self) -> Result<(), VMError> {
         let (subprog, blinding) = self.pred.to_program()?;
-        self.prog.push(Data::Opaque(blinding)).call();
-        self.prog.push(subprog).call();
+        self.prog.push(Data::Opaque(blinding)).push(subprog).call();
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repeat_program() {
+        let program = Program::build(|p| {
+            p.input()
+                .repeat(3, |p, i| p.push(Data::Opaque(vec![i as u8])).output(1))
+                .sign_tx()
+        });
+        assert_eq!(
+            program.to_string(),
+            "input push:1:0x00 output:1 push:1:0x01 output:1 push:1:0x02 output:1 signtx"
+        );
+        assert_eq!(
+            Program::build(|p| p.repeat(0, |p, _| p.drop()))
+                .to_vec()
+                .len(),
+            0
+        );
+    }
+
+    #[test]
+    fn disassemble_program() {
+        let program = Program::build(|p| {
+            p.push(Data::Opaque(vec![1, 2, 3]))
+                .input()
+                .sign_tx()
+                .dup(2)
+                .range(BitRange::new(64).unwrap())
+                .cloak(2, 1)
+                .select(3, 1)
+                .output(1)
+        });
+        let mut bytecode = Vec::new();
+        program.encode(&mut bytecode);
+        bytecode.push(0xff);
+
+        let instructions = Program::disassemble(&bytecode).unwrap();
+        assert_eq!(instructions.len(), 9);
+        assert_eq!(
+            Program(instructions).to_string(),
+            "push:3:0x010203 input signtx dup:2 range:64 cloak:2:1 select:3:1 output:1 ext:0xff"
+        );
+
+        // Truncated immediate data is rejected.
+        assert_eq!(
+            Program::disassemble(&bytecode[..3]).err(),
+            Some(VMError::FormatError)
+        );
+    }
+
+    #[test]
+    fn predicate_tree_select_and_call() {
+        let key = Predicate::Key(VerificationKey::from_secret(&Scalar::from(1u64)));
+        let subprog = Program::build(|p| p.drop());
+        let tree = Predicate::disjunction(vec![key, Predicate::unblinded_program(subprog.clone())])
+            .unwrap();
+
+        // Index out of range is an error rather than a panic, and adds no instructions.
+        let mut program = Program::new();
+        assert_eq!(
+            program
+                .choose_predicate(tree.clone(), |t| t.select(2))
+                .err(),
+            Some(VMError::PredicateIndexInvalid)
+        );
+        assert!(program.0.is_empty());
+
+        // `call` pushes the blinding factor and the program, then calls the contract once.
+        let program = Program::build(|p| {
+            p.choose_predicate(tree.clone(), |t| t.select(1)?.call())
+                .unwrap()
+        });
+        let expected = Program::build(|p| {
+            p.choose_predicate(tree, |t| t.select(1))
+                .unwrap()
+                .push(Data::Opaque(Vec::new()))
+                .push(subprog)
+                .call()
+        });
+        assert_eq!(program.to_bytes(), expected.to_bytes());
+    }
+}
@@ -0,0 +1,146 @@
+//! Proof system of the constraint system of a transaction.
+//!
+//! The VM and the gadgets are written against `r1cs::ConstraintSystem`, so a proof system
+//! only provides the prover's and the verifier's constraint systems, the commitments
+//! to their high-level variables and the proof. The transactions of all versions defined so far
+//! are proven with `Bulletproofs`: the version in `TxHeader` does not select a backend
+//! until another proof system is defined by a new transaction version (see the spec).
+
+use bulletproofs::r1cs::{self, R1CSError, R1CSProof};
+use bulletproofs::BulletproofGens;
+use curve25519_dalek::ristretto::CompressedRistretto;
+use curve25519_dalek::scalar::Scalar;
+use merlin::Transcript;
+use rand::{CryptoRng, RngCore};
+
+use crate::gens;
+
+/// Proves and verifies the constraint system of a transaction.
+/// `'a` is the lifetime of the transcript, `'b` the lifetime of the generators.
+pub trait ProofSystem<'a, 'b> {
+    /// Generators of the proof.
+    type Gens;
+
+    /// Proof of the constraint system.
+    type Proof;
+
+    /// Prover's constraint system.
+    type Prover: r1cs::ConstraintSystem;
+
+    /// Verifier's constraint system.
+    type Verifier: r1cs::ConstraintSystem;
+
+    /// Creates the prover's constraint system.
+    fn prover(gens: &'b Self::Gens, transcript: &'a mut Transcript) -> Self::Prover;
+
+    /// Creates the verifier's constraint system.
+    fn verifier(gens: &'b Self::Gens, transcript: &'a mut Transcript) -> Self::Verifier;
+
+    /// Commits to the secret value with the blinding factor and returns the commitment
+    /// with the variable allocated for it in the prover's constraint system.
+    fn commit(
+        prover: &mut Self::Prover,
+        value: Scalar,
+        blinding: Scalar,
+    ) -> (CompressedRistretto, r1cs::Variable);
+
+    /// Allocates the variable for the commitment in the verifier's constraint system.
+    fn commit_point(verifier: &mut Self::Verifier, point: CompressedRistretto) -> r1cs::Variable;
+
+    /// Proves the constraint system, drawing the blinding factors of the proof
+    /// from the transcript, the witness and the RNG.
+    fn prove<R: RngCore + CryptoRng>(
+        prover: Self::Prover,
+        rng: &mut R,
+    ) -> Result<Self::Proof, R1CSError>;
+
+    /// Verifies the proof of the constraint system.
+    fn verify(verifier: Self::Verifier, proof: &Self::Proof) -> Result<(), R1CSError>;
+}
+
+/// R1CS proofs in the Bulletproofs format, the proof system of all transaction versions.
+pub struct Bulletproofs;
+
+impl<'a, 'b> ProofSystem<'a, 'b> for Bulletproofs {
+    type Gens = BulletproofGens;
+    type Proof = R1CSProof;
+    type Prover = r1cs::Prover<'a, 'b>;
+    type Verifier = r1cs::Verifier<'a, 'b>;
+
+    fn prover(bp_gens: &'b BulletproofGens, transcript: &'a mut Transcript) -> Self::Prover {
+        r1cs::Prover::new(bp_gens, gens::pedersen_gens(), transcript)
+    }
+
+    fn verifier(bp_gens: &'b BulletproofGens, transcript: &'a mut Transcript) -> Self::Verifier {
+        r1cs::Verifier::new(bp_gens, gens::pedersen_gens(), transcript)
+    }
+
+    fn commit(
+        prover: &mut Self::Prover,
+        value: Scalar,
+        blinding: Scalar,
+    ) -> (CompressedRistretto, r1cs::Variable) {
+        prover.commit(value, blinding)
+    }
+
+    fn commit_point(verifier: &mut Self::Verifier, point: CompressedRistretto) -> r1cs::Variable {
+        verifier.commit(point)
+    }
+
+    fn prove<R: RngCore + CryptoRng>(
+        prover: Self::Prover,
+        rng: &mut R,
+    ) -> Result<R1CSProof, R1CSError> {
+        prover.prove_with_rng(rng)
+    }
+
+    fn verify(verifier: Self::Verifier, proof: &R1CSProof) -> Result<(), R1CSError> {
+        verifier.verify(proof)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bulletproofs::r1cs::ConstraintSystem;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    // Proves the knowledge of the committed `x` such that `x*x = 9`.
+    fn prove<R: RngCore + CryptoRng>(x: u64, rng: &mut R) -> (CompressedRistretto, R1CSProof) {
+        let bp_gens = BulletproofGens::new(8, 1);
+        let mut transcript = Transcript::new(b"ZkVM.proof-system-test");
+        let mut prover = Bulletproofs::prover(&bp_gens, &mut transcript);
+        let (point, var) = Bulletproofs::commit(&mut prover, Scalar::from(x), Scalar::from(7u64));
+        let (_, _, square) = prover.multiply(var.into(), var.into());
+        prover.constrain(square - Scalar::from(9u64));
+        (point, Bulletproofs::prove(prover, rng).unwrap())
+    }
+
+    fn verify(point: CompressedRistretto, proof: &R1CSProof) -> Result<(), R1CSError> {
+        let bp_gens = BulletproofGens::new(8, 1);
+        let mut transcript = Transcript::new(b"ZkVM.proof-system-test");
+        let mut verifier = Bulletproofs::verifier(&bp_gens, &mut transcript);
+        let var = Bulletproofs::commit_point(&mut verifier, point);
+        let (_, _, square) = verifier.multiply(var.into(), var.into());
+        verifier.constrain(square - Scalar::from(9u64));
+        Bulletproofs::verify(verifier, proof)
+    }
+
+    #[test]
+    fn prove_and_verify() {
+        let (point, proof) = prove(3, &mut rand::thread_rng());
+        assert!(verify(point, &proof).is_ok());
+
+        let (point, proof) = prove(4, &mut rand::thread_rng());
+        assert!(verify(point, &proof).is_err());
+    }
+
+    #[test]
+    fn seeded_rng() {
+        let (_, proof1) = prove(3, &mut StdRng::seed_from_u64(1));
+        let (_, proof2) = prove(3, &mut StdRng::seed_from_u64(1));
+        let (_, proof3) = prove(3, &mut StdRng::seed_from_u64(2));
+        assert_eq!(proof1.to_bytes(), proof2.to_bytes());
+        assert_ne!(proof1.to_bytes(), proof3.to_bytes());
+    }
+}
@@ -1,30 +1,55 @@
 use bulletproofs::r1cs;
-use bulletproofs::{BulletproofGens, PedersenGens};
+use bulletproofs::BulletproofGens;
 use curve25519_dalek::ristretto::CompressedRistretto;
 use merlin::Transcript;
+use rand::{CryptoRng, RngCore};
 use std::collections::VecDeque;
 
+use crate::circuit_size::{CircuitSize, R1CSProofMetrics};
 use crate::constraints::Commitment;
+use crate::coverage::{self, Branch, Coverage, ExercisedBranch, InstructionLocation};
 use crate::errors::VMError;
+use crate::gens::GensCache;
 use crate::ops::Instruction;
 use crate::point_ops::PointOp;
 use crate::predicate::Predicate;
 use crate::program::Program;
+use crate::proof_system::{Bulletproofs, ProofSystem};
 use crate::signature::{Signature, VerificationKey};
+use crate::transcript::{labels, transcript_for_tx};
 use crate::txlog::{TxID, TxLog};
 use crate::types::Data;
-use crate::vm::{Delegate, Tx, TxHeader, VM};
+use crate::vm::{Delegate, Inspection, Tx, TxHeader, VMParams, VM};
+
 /// This is the entry point API for creating a transaction.
 /// Prover passes the list of instructions through the VM,
 /// creates an aggregated transaction signature (for `signtx` instruction),
 /// creates a R1CS proof and returns a complete `Tx` object that can be published.
 pub struct Prover<'a, 'b> {
     signtx_keys: Vec<VerificationKey>,
+    circuit_size: CircuitSize,
+    commitments: usize,
+    inspections: Vec<Inspection>,
+    coverage: Option<Coverage>,
+    location: Option<InstructionLocation>,
     cs: r1cs::Prover<'a, 'b>,
 }
 
 pub(crate) struct ProverRun {
     program: VecDeque<Instruction>,
+    // index of the program in the coverage report, assigned when its first instruction is executed
+    coverage_index: Option<usize>,
+    offset: usize,
+}
+
+impl ProverRun {
+    fn new(program: Program) -> Self {
+        ProverRun {
+            program: program.to_vec().into(),
+            coverage_index: None,
+            offset: 0,
+        }
+    }
 }
 
 impl<'a, 'b> Delegate<r1cs::Prover<'a, 'b>> for Prover<'a, 'b> {
@@ -35,7 +60,8 @@ impl<'a, 'b> Delegate<r1cs::Prover<'a, 'b>> for Prover<'a, 'b> {
         com: &Commitment,
     ) -> Result<(CompressedRistretto, r1cs::Variable), VMError> {
         let (v, v_blinding) = com.witness().ok_or(VMError::WitnessMissing)?;
-        Ok(self.cs.commit(v.into(), v_blinding))
+        self.commitments += 1;
+        Ok(Bulletproofs::commit(&mut self.cs, v.into(), v_blinding))
     }
 
     fn verify_point_op<F>(&mut self, _point_op_fn: F) -> Result<(), VMError>
@@ -51,17 +77,54 @@ impl<'a, 'b> Delegate<r1cs::Prover<'a, 'b>> for Prover<'a, 'b> {
         Ok(())
     }
 
+    fn inspect(&mut self, inspection: Inspection) {
+        self.inspections.push(inspection);
+    }
+
+    fn exercise_branch(&mut self, predicate: &Predicate, branch: Branch) {
+        if let (Some(coverage), Some(location)) = (&mut self.coverage, self.location) {
+            coverage.branches.push(ExercisedBranch {
+                location,
+                predicate: predicate.to_point(),
+                branch,
+            });
+        }
+    }
+
     fn next_instruction(
         &mut self,
         run: &mut Self::RunType,
     ) -> Result<Option<Instruction>, VMError> {
-        Ok(run.program.pop_front())
+        if let Some(coverage) = &mut self.coverage {
+            if run.coverage_index.is_none() {
+                let program = Program::from_instructions(run.program.iter().cloned().collect());
+                run.coverage_index = Some(coverage.add_program(program.to_bytes()));
+            }
+        }
+        let instr = run.program.pop_front();
+        if let Some(i) = &instr {
+            self.circuit_size += CircuitSize::instruction(i);
+            if let (Some(coverage), Some(index)) = (&mut self.coverage, run.coverage_index) {
+                let location = InstructionLocation {
+                    program: index,
+                    offset: run.offset,
+                };
+                match i {
+                    // The debug instruction is stripped from the bytecode and has no location.
+                    Instruction::Inspect(_) => {}
+                    _ => {
+                        coverage.add_instruction(location, i);
+                        self.location = Some(location);
+                    }
+                }
+                run.offset += coverage::encoded_length(i);
+            }
+        }
+        Ok(instr)
     }
 
     fn new_run(&self, data: Data) -> Result<Self::RunType, VMError> {
-        Ok(ProverRun {
-            program: data.to_program()?.to_vec().into(),
-        })
+        Ok(ProverRun::new(data.to_program()?))
     }
 
     fn cs(&mut self) -> &mut r1cs::Prover<'a, 'b> {
@@ -73,6 +136,10 @@ impl<'a, 'b> Prover<'a, 'b> {
     /// Builds a transaction with a given list of instructions and a `TxHeader`.
     /// Returns a transaction `Tx` along with its ID (`TxID`) and a transaction log (`TxLog`).
     /// Fails if the input program is malformed, or some witness data is missing.
+    ///
+    /// Range proofs of all the values and the other gadgets share the single R1CS proof,
+    /// whose multipliers are padded to the next power of two (see `CircuitSize::bp_gens_capacity`).
+    /// Fails with `VMError::InsufficientGenerators` if `bp_gens` are too small for the padded circuit.
     pub fn build_tx<'g, F>(
         program: Program,
         header: TxHeader,
@@ -81,11 +148,156 @@ impl<'a, 'b> Prover<'a, 'b> {
     ) -> Result<(Tx, TxID, TxLog), VMError>
     where
         F: FnOnce(&mut Transcript, &Vec<VerificationKey>) -> Signature,
+    {
+        Self::build_tx_with_params(program, header, &VMParams::default(), bp_gens, sign_tx_fn)
+    }
+
+    /// Builds a transaction like `build_tx` for a network with the VM parameters
+    /// other than the default ones. The transaction is valid only with the same parameters.
+    pub fn build_tx_with_params<'g, F>(
+        program: Program,
+        header: TxHeader,
+        params: &VMParams,
+        bp_gens: &'g BulletproofGens,
+        sign_tx_fn: F,
+    ) -> Result<(Tx, TxID, TxLog), VMError>
+    where
+        F: FnOnce(&mut Transcript, &Vec<VerificationKey>) -> Signature,
+    {
+        Self::build(
+            program,
+            header,
+            params,
+            bp_gens,
+            sign_tx_fn,
+            &mut rand::thread_rng(),
+            &mut Vec::new(),
+        )
+        .map(|(tx, txid, txlog, _)| (tx, txid, txlog))
+    }
+
+    /// Builds a transaction like `build_tx`, with the blinding factors of the R1CS proof
+    /// drawn from the given RNG instead of `rand::thread_rng`.
+    /// With the blinded commitments and the signature also created from a seeded RNG,
+    /// the same program produces the same transaction, e.g. for the test vectors.
+    pub fn build_tx_with_rng<'g, F, R>(
+        program: Program,
+        header: TxHeader,
+        bp_gens: &'g BulletproofGens,
+        rng: &mut R,
+        sign_tx_fn: F,
+    ) -> Result<(Tx, TxID, TxLog), VMError>
+    where
+        F: FnOnce(&mut Transcript, &Vec<VerificationKey>) -> Signature,
+        R: RngCore + CryptoRng,
+    {
+        Self::build(
+            program,
+            header,
+            &VMParams::default(),
+            bp_gens,
+            sign_tx_fn,
+            rng,
+            &mut Vec::new(),
+        )
+        .map(|(tx, txid, txlog, _)| (tx, txid, txlog))
+    }
+
+    /// Builds a transaction like `build_tx` and also returns the size of its proof
+    /// and the estimated cost of its verification.
+    pub fn build_tx_with_metrics<'g, F>(
+        program: Program,
+        header: TxHeader,
+        bp_gens: &'g BulletproofGens,
+        sign_tx_fn: F,
+    ) -> Result<(Tx, TxID, TxLog, R1CSProofMetrics), VMError>
+    where
+        F: FnOnce(&mut Transcript, &Vec<VerificationKey>) -> Signature,
+    {
+        Self::build(
+            program,
+            header,
+            &VMParams::default(),
+            bp_gens,
+            sign_tx_fn,
+            &mut rand::thread_rng(),
+            &mut Vec::new(),
+        )
+    }
+
+    /// Builds a transaction like `build_tx` and returns the records of the debug instructions
+    /// `inspect` executed by the VM (see `Program::inspect`), even if the transaction cannot be built.
+    pub fn build_tx_inspect<'g, F>(
+        program: Program,
+        header: TxHeader,
+        bp_gens: &'g BulletproofGens,
+        sign_tx_fn: F,
+    ) -> (Result<(Tx, TxID, TxLog), VMError>, Vec<Inspection>)
+    where
+        F: FnOnce(&mut Transcript, &Vec<VerificationKey>) -> Signature,
+    {
+        let mut inspections = Vec::new();
+        let result = Self::build(
+            program,
+            header,
+            &VMParams::default(),
+            bp_gens,
+            sign_tx_fn,
+            &mut rand::thread_rng(),
+            &mut inspections,
+        )
+        .map(|(tx, txid, txlog, _)| (tx, txid, txlog));
+        (result, inspections)
+    }
+
+    /// Executes the program in the prover's VM without signing and proving the transaction,
+    /// and reports which instructions and branches of the predicate trees were executed,
+    /// even if the execution fails (see `Coverage`).
+    pub fn run_with_coverage(
+        program: Program,
+        header: TxHeader,
+    ) -> (Result<(TxID, TxLog), VMError>, Coverage) {
+        // The constraint system is not proven, so it needs no generators for the multipliers.
+        let bp_gens = BulletproofGens::new(1, 1);
+        let mut r1cs_transcript = Transcript::new(labels::R1CS);
+        let cs = Bulletproofs::prover(&bp_gens, &mut r1cs_transcript);
+
+        let mut prover = Prover {
+            signtx_keys: Vec::new(),
+            circuit_size: CircuitSize::default(),
+            commitments: 0,
+            inspections: Vec::new(),
+            coverage: Some(Coverage::default()),
+            location: None,
+            cs,
+        };
+
+        let result = VM::new(
+            header,
+            &VMParams::default(),
+            ProverRun::new(program),
+            &mut prover,
+        )
+        .run();
+        (result, prover.coverage.take().unwrap_or_default())
+    }
+
+    fn build<'g, F, R>(
+        program: Program,
+        header: TxHeader,
+        params: &VMParams,
+        bp_gens: &'g BulletproofGens,
+        sign_tx_fn: F,
+        rng: &mut R,
+        inspections: &mut Vec<Inspection>,
+    ) -> Result<(Tx, TxID, TxLog, R1CSProofMetrics), VMError>
+    where
+        F: FnOnce(&mut Transcript, &Vec<VerificationKey>) -> Signature,
+        R: RngCore + CryptoRng,
     {
         // Prepare the constraint system
-        let mut r1cs_transcript = Transcript::new(b"ZkVM.r1cs");
-        let pc_gens = PedersenGens::default();
-        let cs = r1cs::Prover::new(bp_gens, &pc_gens, &mut r1cs_transcript);
+        let mut r1cs_transcript = Transcript::new(labels::R1CS);
+        let cs = Bulletproofs::prover(bp_gens, &mut r1cs_transcript);
 
         // Serialize the tx program
         let mut bytecode = Vec::new();
@@ -93,27 +305,33 @@ impl<'a, 'b> Prover<'a, 'b> {
 
         let mut prover = Prover {
             signtx_keys: Vec::new(),
+            circuit_size: CircuitSize::default(),
+            commitments: 0,
+            inspections: Vec::new(),
+            coverage: None,
+            location: None,
             cs,
         };
 
-        let vm = VM::new(
-            header,
-            ProverRun {
-                program: program.to_vec().into(),
-            },
-            &mut prover,
-        );
+        let vm = VM::new(header, params, ProverRun::new(program), &mut prover);
 
-        let (txid, txlog) = vm.run()?;
+        let result = vm.run();
+        inspections.append(&mut prover.inspections);
+        let (txid, txlog) = result?;
 
         // Sign txid
         // TBD: implement holistic Signer trait/interface for tx signing
-        let mut signtx_transcript = Transcript::new(b"ZkVM.signtx");
-        signtx_transcript.commit_bytes(b"txid", &txid.0);
+        let mut signtx_transcript = transcript_for_tx(&txid);
         let signature = sign_tx_fn(&mut signtx_transcript, &prover.signtx_keys);
 
         // Generate the R1CS proof
-        let proof = prover.cs.prove().map_err(|_| VMError::InvalidR1CSProof)?;
+        let circuit_size = prover.circuit_size;
+        let proof = Bulletproofs::prove(prover.cs, rng).map_err(|_| {
+            circuit_size
+                .insufficient_generators(bp_gens)
+                .unwrap_or(VMError::InvalidR1CSProof)
+        })?;
+        let metrics = R1CSProofMetrics::new(circuit_size, prover.commitments, &proof);
 
         Ok((
             Tx {
@@ -124,6 +342,35 @@ impl<'a, 'b> Prover<'a, 'b> {
             },
             txid,
             txlog,
+            metrics,
         ))
     }
+
+    /// Builds a transaction like `build_tx`, with the generators from the cache
+    /// grown to the capacity of its constraint system. The same cache can be used to verify it.
+    ///
+    /// The capacity is estimated from the program and padded to the next power of two.
+    /// If the nested programs (`call` and `delegate`) need more generators,
+    /// the transaction is built again with the capacity required by the executed instructions,
+    /// so the signing function may be invoked twice.
+    pub fn build_tx_padded<F>(
+        program: Program,
+        header: TxHeader,
+        gens_cache: &mut GensCache,
+        mut sign_tx_fn: F,
+    ) -> Result<(Tx, TxID, TxLog), VMError>
+    where
+        F: FnMut(&mut Transcript, &Vec<VerificationKey>) -> Signature,
+    {
+        let mut capacity = program.estimate_circuit_size().bp_gens_capacity();
+        loop {
+            let bp_gens = gens_cache.reserve(capacity);
+            match Self::build_tx(program.clone(), header, bp_gens, &mut sign_tx_fn) {
+                Err(VMError::InsufficientGenerators { required, .. }) if required > capacity => {
+                    capacity = required;
+                }
+                result => return result,
+            }
+        }
+    }
 }
@@ -0,0 +1,594 @@
+//! Partially-signed ZkVM transactions (PSZT), similar to Bitcoin's PSBT.
+//!
+//! The creator proves the transaction and collects the keys that must sign it with `signtx`.
+//! The container carries the proved transaction, its log and the openings of the output values,
+//! so the signers can check what they sign without the secrets of the other parties.
+//! The aggregated signature is produced in three rounds: nonce precommitments,
+//! nonce commitments and signature shares. Copies of the container updated by different
+//! signers (e.g. on air-gapped devices) are combined with `Pszt::merge`.
+
+use bulletproofs::BulletproofGens;
+use curve25519_dalek::ristretto::CompressedRistretto;
+use curve25519_dalek::scalar::Scalar;
+use merlin::Transcript;
+use rand::{CryptoRng, RngCore};
+
+use crate::constraints::Commitment;
+use crate::contract::PortableItem;
+use crate::encoding::{self, SliceReader};
+use crate::errors::VMError;
+use crate::program::Program;
+use crate::prover::Prover;
+use crate::signature::musig::{NonceCommitment, NoncePrecommitment};
+use crate::signature::{SecretNonce, Signature, VerificationKey};
+use crate::signing_request::{CompactEntry, OutputOpening, SigningRequest, ValueOpening};
+use crate::transcript::transcript_for_tx;
+use crate::txlog::{Entry, TxID, TxLog};
+use crate::vm::{Tx, TxHeader};
+
+/// Partially-signed transaction.
+#[derive(Clone)]
+pub struct Pszt {
+    // proved transaction with a placeholder signature
+    tx: Tx,
+    log: TxLog,
+    hints: Vec<PsztHint>,
+    signers: Vec<PsztSigner>,
+}
+
+/// Opening of a value commitment in the outputs of the transaction.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PsztHint {
+    /// The commitment to a quantity or a flavor.
+    pub commitment: CompressedRistretto,
+    /// Committed quantity or flavor.
+    pub value: Scalar,
+    /// Blinding factor of the commitment.
+    pub blinding: Scalar,
+}
+
+/// Signing progress of one of the `signtx` keys.
+#[derive(Copy, Clone, Debug)]
+pub struct PsztSigner {
+    /// Verification key that must sign the transaction.
+    pub key: VerificationKey,
+    /// Precommitment to the nonce, added in the first round.
+    pub precommitment: Option<NoncePrecommitment>,
+    /// Nonce commitment, added in the second round.
+    pub commitment: Option<NonceCommitment>,
+    /// Signature share, added in the third round.
+    pub share: Option<Scalar>,
+}
+
+impl Pszt {
+    /// Proves the transaction and creates the container without any signatures.
+    /// The openings of the values in the outputs are included as hints for the signers.
+    pub fn new(
+        program: Program,
+        header: TxHeader,
+        bp_gens: &BulletproofGens,
+    ) -> Result<Self, VMError> {
+        let mut keys = Vec::new();
+        let (tx, _, log) = Prover::build_tx(program, header, bp_gens, |_, vks| {
+            keys = vks.clone();
+            Signature::from_bytes([0u8; 64]).expect("Zero signature is well-formed")
+        })?;
+
+        let hints = log
+            .iter()
+            .filter_map(|entry| match entry {
+                Entry::Output(output) => Some(output.clone().into_contract().0.payload),
+                _ => None,
+            })
+            .flatten()
+            .filter_map(|item| match item {
+                PortableItem::Value(value) => Some(vec![value.qty, value.flv]),
+                PortableItem::Data(_) => None,
+            })
+            .flatten()
+            .filter_map(|commitment| {
+                commitment.witness().map(|(value, blinding)| PsztHint {
+                    commitment: commitment.to_point(),
+                    value: value.into(),
+                    blinding,
+                })
+            })
+            .collect();
+
+        let signers = keys
+            .into_iter()
+            .map(|key| PsztSigner {
+                key,
+                precommitment: None,
+                commitment: None,
+                share: None,
+            })
+            .collect();
+
+        Ok(Pszt {
+            tx,
+            log,
+            hints,
+            signers,
+        })
+    }
+
+    /// Returns the header of the transaction.
+    pub fn header(&self) -> &TxHeader {
+        &self.tx.header
+    }
+
+    /// Returns the ID of the transaction that is being signed.
+    pub fn txid(&self) -> TxID {
+        TxID::from_log(&self.log)
+    }
+
+    /// Returns the log of the transaction.
+    pub fn log(&self) -> &TxLog {
+        &self.log
+    }
+
+    /// Returns the openings of the value commitments in the outputs.
+    pub fn hints(&self) -> &[PsztHint] {
+        &self.hints
+    }
+
+    /// Returns the signing progress of the keys, in the order of the `signtx` instructions.
+    pub fn signers(&self) -> &[PsztSigner] {
+        &self.signers
+    }
+
+    /// Returns true if all the signature shares are added.
+    pub fn is_signed(&self) -> bool {
+        self.signers.iter().all(|s| s.share.is_some())
+    }
+
+    /// Creates a nonce for signing with the key at `index` and adds its precommitment.
+    /// The signer keeps the nonce until all the shares can be created with `sign`.
+    pub fn precommit<R: RngCore + CryptoRng>(
+        &mut self,
+        index: usize,
+        privkey: &Scalar,
+        rng: &mut R,
+    ) -> Result<SecretNonce, VMError> {
+        let transcript = self.transcript();
+        let signer = self.signers.get_mut(index).ok_or(VMError::BadArguments)?;
+        if signer.key != VerificationKey::from_secret(privkey) {
+            return Err(VMError::BadArguments);
+        }
+        if signer.precommitment.is_some() {
+            return Err(VMError::MuSigSessionError);
+        }
+        let nonce = SecretNonce::new(&transcript, privkey, rng);
+        signer.precommitment = Some(nonce.commitment().precommit());
+        Ok(nonce)
+    }
+
+    /// Adds the nonce commitment for the key at `index`.
+    /// Fails if the precommitments of all the keys are not added yet.
+    pub fn reveal_nonce(&mut self, index: usize, nonce: &SecretNonce) -> Result<(), VMError> {
+        if self.signers.iter().any(|s| s.precommitment.is_none()) {
+            return Err(VMError::MuSigSessionError);
+        }
+        let signer = self.signers.get_mut(index).ok_or(VMError::BadArguments)?;
+        let precommitment = nonce.commitment().precommit();
+        if signer.precommitment.map(|p| p.to_bytes()) != Some(precommitment.to_bytes()) {
+            return Err(VMError::BadArguments);
+        }
+        signer.commitment = Some(nonce.commitment());
+        Ok(())
+    }
+
+    /// Adds the signature share for the key at `index`.
+    /// Fails if the nonce commitments of all the keys are not added yet.
+    pub fn sign(
+        &mut self,
+        index: usize,
+        privkey: Scalar,
+        nonce: SecretNonce,
+    ) -> Result<(), VMError> {
+        let commitments = self.commitments()?;
+        match commitments.get(index) {
+            Some(c) if c.to_bytes() == nonce.commitment().to_bytes() => {}
+            _ => return Err(VMError::BadArguments),
+        }
+        let share = Signature::sign_share(
+            &mut self.transcript(),
+            &self.keys(),
+            &commitments,
+            index,
+            privkey,
+            nonce,
+        )?;
+        self.signers[index].share = Some(share);
+        Ok(())
+    }
+
+    /// Merges the signing progress from another copy of the container.
+    /// Fails if the copy is of a different transaction or has conflicting data.
+    pub fn merge(&mut self, other: &Pszt) -> Result<(), VMError> {
+        if self.txid() != other.txid() || self.keys() != other.keys() {
+            return Err(VMError::BadArguments);
+        }
+        for (signer, theirs) in self.signers.iter_mut().zip(other.signers.iter()) {
+            signer.precommitment =
+                merge_field(signer.precommitment, theirs.precommitment, |p| p.to_bytes())?;
+            signer.commitment =
+                merge_field(signer.commitment, theirs.commitment, |c| c.to_bytes())?;
+            signer.share = merge_field(signer.share, theirs.share, |s| s.to_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Verifies the signature shares and returns the signed transaction.
+    pub fn finalize(self) -> Result<Tx, VMError> {
+        let commitments = self.commitments()?;
+        let shares = self
+            .signers
+            .iter()
+            .map(|s| s.share)
+            .collect::<Option<Vec<_>>>()
+            .ok_or(VMError::MuSigSessionError)?;
+        let signature = Signature::aggregate_shares(
+            &mut self.transcript(),
+            &self.keys(),
+            &commitments,
+            &shares,
+        )?;
+        let mut tx = self.tx;
+        tx.signature = signature;
+        Ok(tx)
+    }
+
+    /// Creates a request for a constrained signer, which signs for all the keys at once.
+    /// Fails if the openings of some output values are not known.
+    pub fn signing_request(&self) -> Result<SigningRequest, VMError> {
+        let mut outputs = Vec::new();
+        for entry in self.log.iter() {
+            if let Entry::Output(output) = entry {
+                let (contract, _) = output.clone().into_contract();
+                let mut values = Vec::new();
+                for item in contract.payload.iter() {
+                    if let PortableItem::Value(value) = item {
+                        let (qty, qty_blinding) = self.opening(&value.qty)?;
+                        let (flv, flv_blinding) = self.opening(&value.flv)?;
+                        values.push(ValueOpening {
+                            qty: scalar_to_u64(&qty)?,
+                            qty_blinding,
+                            flv,
+                            flv_blinding,
+                        });
+                    }
+                }
+                outputs.push(OutputOpening {
+                    output: output.clone(),
+                    values,
+                });
+            }
+        }
+        Ok(SigningRequest {
+            log: self.log.iter().map(CompactEntry::from).collect(),
+            outputs,
+            keys: self.keys(),
+        })
+    }
+
+    /// Verifies the signature returned by the signer of the `signing_request`
+    /// and returns the signed transaction.
+    pub fn apply_signature(self, signature: Signature) -> Result<Tx, VMError> {
+        signature
+            .verify_aggregated(&mut self.transcript(), &self.keys())
+            .verify()?;
+        let mut tx = self.tx;
+        tx.signature = signature;
+        Ok(tx)
+    }
+
+    /// Serializes the container.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        let tx = self.tx.to_bytes();
+        encoding::write_size(tx.len(), &mut buf);
+        encoding::write_bytes(&tx, &mut buf);
+
+        encoding::write_size(self.log.len(), &mut buf);
+        for entry in self.log.iter() {
+            entry.encode(&mut buf);
+        }
+
+        encoding::write_size(self.hints.len(), &mut buf);
+        for hint in self.hints.iter() {
+            encoding::write_point(&hint.commitment, &mut buf);
+            encoding::write_bytes(hint.value.as_bytes(), &mut buf);
+            encoding::write_bytes(hint.blinding.as_bytes(), &mut buf);
+        }
+
+        encoding::write_size(self.signers.len(), &mut buf);
+        for signer in self.signers.iter() {
+            encoding::write_point(&signer.key.0, &mut buf);
+            write_option(signer.precommitment.map(|p| p.to_bytes()), &mut buf);
+            write_option(signer.commitment.map(|c| c.to_bytes()), &mut buf);
+            write_option(signer.share.map(|s| s.to_bytes()), &mut buf);
+        }
+        buf
+    }
+
+    /// Deserializes the container.
+    /// Fails if the hints do not open their commitments.
+    pub fn from_bytes(slice: &[u8]) -> Result<Self, VMError> {
+        SliceReader::parse(slice, |r| {
+            let tx_len = r.read_size()?;
+            let tx = Tx::from_bytes(r.read_bytes(tx_len)?)?;
+
+            let mut log = Vec::new();
+            for _ in 0..r.read_size()? {
+                log.push(Entry::decode(r)?);
+            }
+
+            let mut hints = Vec::new();
+            for _ in 0..r.read_size()? {
+                let hint = PsztHint {
+                    commitment: r.read_point()?,
+                    value: r.read_scalar()?,
+                    blinding: r.read_scalar()?,
+                };
+                if !Commitment::Closed(hint.commitment).verify_opening(hint.value, hint.blinding) {
+                    return Err(VMError::FormatError);
+                }
+                hints.push(hint);
+            }
+
+            let mut signers = Vec::new();
+            for _ in 0..r.read_size()? {
+                let key = VerificationKey(r.read_point()?);
+                let precommitment = read_option(r)?.map(NoncePrecommitment::from_bytes);
+                let commitment = match read_option(r)? {
+                    Some(bytes) => Some(NonceCommitment::from_bytes(bytes)?),
+                    None => None,
+                };
+                let share = match read_option(r)? {
+                    Some(bytes) => {
+                        Some(Scalar::from_canonical_bytes(bytes).ok_or(VMError::FormatError)?)
+                    }
+                    None => None,
+                };
+                signers.push(PsztSigner {
+                    key,
+                    precommitment,
+                    commitment,
+                    share,
+                });
+            }
+
+            Ok(Pszt {
+                tx,
+                log,
+                hints,
+                signers,
+            })
+        })
+    }
+
+    fn transcript(&self) -> Transcript {
+        transcript_for_tx(&self.txid())
+    }
+
+    /// Returns the value and the blinding factor of the output commitment from the hints.
+    fn opening(&self, commitment: &Commitment) -> Result<(Scalar, Scalar), VMError> {
+        let point = commitment.to_point();
+        self.hints
+            .iter()
+            .find(|h| h.commitment == point)
+            .map(|h| (h.value, h.blinding))
+            .ok_or(VMError::WitnessMissing)
+    }
+
+    fn keys(&self) -> Vec<VerificationKey> {
+        self.signers.iter().map(|s| s.key).collect()
+    }
+
+    /// Returns the nonce commitments of all the keys, checked against their precommitments.
+    fn commitments(&self) -> Result<Vec<NonceCommitment>, VMError> {
+        self.signers
+            .iter()
+            .map(|s| match (s.precommitment, s.commitment) {
+                (Some(p), Some(c)) => {
+                    if c.precommit().to_bytes() == p.to_bytes() {
+                        Ok(c)
+                    } else {
+                        Err(VMError::MuSigShareError {
+                            pubkey: s.key.0.to_bytes(),
+                        })
+                    }
+                }
+                _ => Err(VMError::MuSigSessionError),
+            })
+            .collect()
+    }
+}
+
+fn merge_field<T, B, F>(
+    ours: Option<T>,
+    theirs: Option<T>,
+    to_bytes: F,
+) -> Result<Option<T>, VMError>
+where
+    F: Fn(&T) -> B,
+    B: PartialEq,
+{
+    match (ours, theirs) {
+        (Some(a), Some(b)) => {
+            if to_bytes(&a) == to_bytes(&b) {
+                Ok(Some(a))
+            } else {
+                Err(VMError::BadArguments)
+            }
+        }
+        (a, b) => Ok(a.or(b)),
+    }
+}
+
+fn scalar_to_u64(scalar: &Scalar) -> Result<u64, VMError> {
+    let bytes = scalar.as_bytes();
+    if bytes[8..].iter().any(|b| *b != 0) {
+        return Err(VMError::FormatError);
+    }
+    let mut qty = [0u8; 8];
+    qty.copy_from_slice(&bytes[..8]);
+    Ok(u64::from_le_bytes(qty))
+}
+
+fn write_option(bytes: Option<[u8; 32]>, buf: &mut Vec<u8>) {
+    match bytes {
+        Some(bytes) => {
+            encoding::write_u8(1, buf);
+            encoding::write_bytes(&bytes, buf);
+        }
+        None => encoding::write_u8(0, buf),
+    }
+}
+
+fn read_option(r: &mut SliceReader) -> Result<Option<[u8; 32]>, VMError> {
+    match r.read_u8()? {
+        0 => Ok(None),
+        1 => Ok(Some(r.read_u8x32()?)),
+        _ => Err(VMError::FormatError),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::predicate::Predicate;
+    use crate::types::{Data, Value};
+    use crate::verifier::Verifier;
+
+    /// Issues a value signed by the issuer, anchored with a nonce signed by another party.
+    fn make_pszt(issuer_key: Scalar, nonce_key: Scalar) -> Pszt {
+        let issuer = Predicate::Key(VerificationKey::from_secret(&issuer_key));
+        let metadata = Data::Opaque(b"USD".to_vec());
+        let flavor = Value::issue_flavor(&issuer, metadata.clone());
+        let program = Program::build(|p| {
+            p.push(Predicate::Key(VerificationKey::from_secret(&nonce_key)))
+                .push(Data::Opaque([0xffu8; 32].to_vec()))
+                .nonce()
+                .sign_tx()
+                .push(Commitment::blinded(10u64))
+                .var()
+                .push(Commitment::unblinded(flavor))
+                .var()
+                .push(metadata)
+                .push(issuer)
+                .issue()
+                .sign_tx()
+                .push(Predicate::Opaque(Default::default()))
+                .output(1)
+        });
+        let header = TxHeader {
+            version: 0,
+            mintime: 0,
+            maxtime: 0,
+            maxcost: u64::max_value(),
+        };
+        Pszt::new(program, header, &BulletproofGens::new(256, 1)).unwrap()
+    }
+
+    #[test]
+    fn sign_by_two_parties() {
+        let mut rng = rand::thread_rng();
+        let (issuer_key, nonce_key) = (Scalar::from(1u64), Scalar::from(2u64));
+        let pszt = make_pszt(issuer_key, nonce_key);
+        assert_eq!(pszt.signers().len(), 2);
+        assert_eq!(
+            pszt.signers()[0].key,
+            VerificationKey::from_secret(&nonce_key)
+        );
+        // Quantity and flavor of the output are revealed to the signers.
+        assert_eq!(pszt.hints().len(), 2);
+        assert_eq!(pszt.hints()[0].value, Scalar::from(10u64));
+
+        // Each party works on its own copy, which is passed around serialized.
+        let mut alice = Pszt::from_bytes(&pszt.to_bytes()).unwrap();
+        let mut bob = Pszt::from_bytes(&pszt.to_bytes()).unwrap();
+        assert_eq!(alice.txid(), pszt.txid());
+
+        let alice_nonce = alice.precommit(0, &nonce_key, &mut rng).unwrap();
+        let bob_nonce = bob.precommit(1, &issuer_key, &mut rng).unwrap();
+        assert_eq!(
+            bob.precommit(0, &issuer_key, &mut rng).unwrap_err(),
+            VMError::BadArguments
+        );
+        // Nonces are revealed only after all the precommitments are known.
+        assert_eq!(
+            alice.reveal_nonce(0, &alice_nonce).unwrap_err(),
+            VMError::MuSigSessionError
+        );
+        alice.merge(&bob).unwrap();
+        bob.merge(&alice).unwrap();
+
+        alice.reveal_nonce(0, &alice_nonce).unwrap();
+        bob.reveal_nonce(1, &bob_nonce).unwrap();
+        alice.merge(&bob).unwrap();
+        bob.merge(&alice).unwrap();
+
+        alice.sign(0, nonce_key, alice_nonce).unwrap();
+        bob.sign(1, issuer_key, bob_nonce).unwrap();
+        assert!(!alice.is_signed());
+        let alice_bytes = alice.to_bytes();
+        alice.merge(&bob).unwrap();
+        assert!(alice.is_signed());
+
+        // Conflicting shares are rejected.
+        let mut conflicting = Pszt::from_bytes(&alice_bytes).unwrap();
+        conflicting.signers[1].share = Some(Scalar::one());
+        assert_eq!(
+            conflicting.merge(&alice).unwrap_err(),
+            VMError::BadArguments
+        );
+        // Copies of other transactions cannot be merged.
+        let mut other = make_pszt(issuer_key, nonce_key);
+        assert_eq!(other.merge(&alice).unwrap_err(), VMError::BadArguments);
+
+        let tx = alice.finalize().unwrap();
+        assert!(Verifier::verify_tx(tx, &BulletproofGens::new(256, 1)).is_ok());
+    }
+
+    #[test]
+    fn sign_on_device() {
+        let (issuer_key, nonce_key) = (Scalar::from(1u64), Scalar::from(2u64));
+        let pszt = make_pszt(issuer_key, nonce_key);
+
+        // The device receives only the serialized request.
+        let request =
+            SigningRequest::from_bytes(&pszt.signing_request().unwrap().to_bytes()).unwrap();
+        let summary = request.verify().unwrap();
+        assert_eq!(summary.txid, pszt.txid());
+        assert_eq!(summary.fee, 0);
+        assert_eq!(summary.outputs.len(), 1);
+        assert_eq!(summary.outputs[0].values[0].qty, 10);
+
+        let mut rng = rand::thread_rng();
+        assert_eq!(
+            request
+                .sign(&[issuer_key, nonce_key], &mut rng)
+                .unwrap_err(),
+            VMError::BadArguments
+        );
+        let signature = request.sign(&[nonce_key, issuer_key], &mut rng).unwrap();
+
+        // Tampered values are detected by the device.
+        let mut tampered = request.clone();
+        tampered.outputs[0].values[0].qty = 11;
+        assert_eq!(
+            tampered.verify().unwrap_err(),
+            VMError::InvalidSigningRequest
+        );
+
+        // A signature for a different transaction is rejected.
+        let other = make_pszt(issuer_key, nonce_key);
+        assert!(other.apply_signature(signature).is_err());
+
+        let tx = pszt.apply_signature(signature).unwrap();
+        assert!(Verifier::verify_tx(tx, &BulletproofGens::new(256, 1)).is_ok());
+    }
+}
@@ -1,7 +1,11 @@
 //! Arithmetic and conversion API for ScalarWitness.
 
 use curve25519_dalek::scalar::Scalar;
-use spacesuit::SignedInteger;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use spacesuit::{BitRange, SignedInteger};
+use subtle::{Choice, ConstantTimeEq};
+use zeroize::Zeroize;
 
 use crate::encoding;
 use crate::errors::VMError;
@@ -9,10 +13,18 @@ use std::ops::{Add, Mul, Neg, Sub};
 use std::u64;
 
 /// Represents a concrete kind of a number represented by a scalar.
-#[derive(Copy, Clone, Debug, PartialEq)]
+/// Witnesses are compared in constant time (see `ConstantTimeEq`).
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum ScalarWitness {
     /// `ScalarKind::Integer` represents a signed integer with 64-bit absolute value (think "i65")
-    Integer(SignedInteger),
+    Integer(
+        #[cfg_attr(
+            feature = "serde",
+            serde(with = "crate::serialization::signed_integer")
+        )]
+        SignedInteger,
+    ),
     /// `ScalarKind::Scalar` represents a scalar modulo group order.
     Scalar(Scalar),
 }
@@ -44,6 +56,50 @@ impl ScalarWitness {
         }
     }
 
+    /// Adds two witnesses. Fails with `IntegerOverflow` if both are integers
+    /// and the sum does not fit in the integer range, instead of falling back to a scalar.
+    pub fn checked_add(self, rhs: ScalarWitness) -> Result<ScalarWitness, VMError> {
+        match (self, rhs) {
+            (ScalarWitness::Integer(a), ScalarWitness::Integer(b)) => (a + b)
+                .map(ScalarWitness::Integer)
+                .ok_or(VMError::IntegerOverflow),
+            (a, b) => Ok(a + b),
+        }
+    }
+
+    /// Subtracts two witnesses. Fails with `IntegerOverflow` if both are integers
+    /// and the difference does not fit in the integer range.
+    pub fn checked_sub(self, rhs: ScalarWitness) -> Result<ScalarWitness, VMError> {
+        self.checked_add(-rhs)
+    }
+
+    /// Multiplies two witnesses. Fails with `IntegerOverflow` if both are integers
+    /// and the product does not fit in the integer range.
+    pub fn checked_mul(self, rhs: ScalarWitness) -> Result<ScalarWitness, VMError> {
+        match (self, rhs) {
+            (ScalarWitness::Integer(a), ScalarWitness::Integer(b)) => (a * b)
+                .map(ScalarWitness::Integer)
+                .ok_or(VMError::IntegerOverflow),
+            (a, b) => Ok(a * b),
+        }
+    }
+
+    /// Returns true if the witness is in range [0, 2^n).
+    /// Integers are checked directly, and scalars by their canonical encoding.
+    pub fn in_range(self, n: BitRange) -> bool {
+        let n: usize = n.into();
+        match self {
+            ScalarWitness::Integer(i) => match i.to_u64() {
+                Some(x) => n == 64 || x >> n == 0,
+                None => false,
+            },
+            ScalarWitness::Scalar(s) => {
+                let bytes = s.to_bytes();
+                (n..256).all(|i| bytes[i / 8] & (1 << (i % 8)) == 0)
+            }
+        }
+    }
+
     /// Converts `Option<ScalarWitness>` into optional integer if it is one.
     pub fn option_to_integer(assignment: Option<Self>) -> Result<Option<SignedInteger>, VMError> {
         match assignment {
@@ -54,6 +110,37 @@ impl ScalarWitness {
     }
 }
 
+impl ConstantTimeEq for ScalarWitness {
+    /// Compares the values of the witnesses in constant time.
+    /// Witnesses of different kinds are not equal: the kind is not secret.
+    fn ct_eq(&self, other: &Self) -> Choice {
+        let same_kind = match (self, other) {
+            (ScalarWitness::Integer(_), ScalarWitness::Integer(_)) => 1u8,
+            (ScalarWitness::Scalar(_), ScalarWitness::Scalar(_)) => 1u8,
+            _ => 0u8,
+        };
+        Choice::from(same_kind) & self.to_scalar().ct_eq(&other.to_scalar())
+    }
+}
+
+impl PartialEq for ScalarWitness {
+    fn eq(&self, other: &Self) -> bool {
+        self.ct_eq(other).into()
+    }
+}
+
+/// `ScalarWitness` is `Copy`, so zeroizing it clears only the copy in place.
+/// It is implemented for the witnesses stored in `CommitmentWitness`,
+/// which zeroizes its value when dropped.
+impl Zeroize for ScalarWitness {
+    fn zeroize(&mut self) {
+        match self {
+            ScalarWitness::Integer(i) => i.zeroize(),
+            ScalarWitness::Scalar(s) => s.zeroize(),
+        }
+    }
+}
+
 // Implementing arithmetic operatons for ScalarWitness
 
 impl Neg for ScalarWitness {
@@ -85,7 +172,7 @@ impl Sub for ScalarWitness {
     type Output = ScalarWitness;
 
     fn sub(self, rhs: ScalarWitness) -> ScalarWitness {
-        -self + rhs
+        self + (-rhs)
     }
 }
 
@@ -215,6 +302,19 @@ mod tests {
         );
     }
 
+    #[test]
+    fn sub() {
+        assert_eq!(
+            ScalarWitness::from(7u64) - ScalarWitness::from(5u64),
+            ScalarWitness::from(2u64)
+        );
+
+        assert_eq!(
+            ScalarWitness::from(5u64) - ScalarWitness::from(7u64),
+            -ScalarWitness::from(2u64)
+        );
+    }
+
     #[test]
     fn mul() {
         assert_eq!(
@@ -238,6 +338,67 @@ mod tests {
         );
     }
 
+    #[test]
+    fn checked_arithmetic() {
+        assert_eq!(
+            ScalarWitness::from(2u64).checked_add(ScalarWitness::from(5u64)),
+            Ok(ScalarWitness::from(7u64))
+        );
+        assert_eq!(
+            ScalarWitness::from(5u64).checked_sub(ScalarWitness::from(7u64)),
+            Ok(-ScalarWitness::from(2u64))
+        );
+        assert_eq!(
+            ScalarWitness::from(5u64).checked_mul(ScalarWitness::from(6u64)),
+            Ok(ScalarWitness::from(30u64))
+        );
+
+        assert_eq!(
+            ScalarWitness::from(u64::MAX).checked_add(ScalarWitness::from(1u64)),
+            Err(VMError::IntegerOverflow)
+        );
+        assert_eq!(
+            (-ScalarWitness::from(u64::MAX)).checked_sub(ScalarWitness::from(1u64)),
+            Err(VMError::IntegerOverflow)
+        );
+        assert_eq!(
+            ScalarWitness::from(u64::MAX).checked_mul(ScalarWitness::from(2u64)),
+            Err(VMError::IntegerOverflow)
+        );
+
+        // Scalars use the field arithmetic.
+        assert_eq!(
+            ScalarWitness::from(u64::MAX).checked_add(ScalarWitness::from(Scalar::one())),
+            Ok(ScalarWitness::from(Scalar::from(u64::MAX) + Scalar::one()))
+        );
+    }
+
+    #[test]
+    fn in_range() {
+        assert!(ScalarWitness::from(u64::MAX).in_range(BitRange::u64()));
+        assert!(ScalarWitness::from(255u64).in_range(BitRange::new(8).unwrap()));
+        assert!(!ScalarWitness::from(256u64).in_range(BitRange::new(8).unwrap()));
+        assert!(!(-ScalarWitness::from(1u64)).in_range(BitRange::u64()));
+        assert!(ScalarWitness::from(Scalar::from(255u64)).in_range(BitRange::new(8).unwrap()));
+        assert!(!ScalarWitness::from(-Scalar::one()).in_range(BitRange::u64()));
+    }
+
+    #[test]
+    fn constant_time_eq() {
+        assert_eq!(ScalarWitness::from(5u64), ScalarWitness::from(5u64));
+        assert_ne!(ScalarWitness::from(5u64), -ScalarWitness::from(5u64));
+        assert_ne!(
+            ScalarWitness::from(5u64),
+            ScalarWitness::from(Scalar::from(5u64))
+        );
+        assert_eq!(
+            ScalarWitness::from(5u64)
+                .ct_eq(&ScalarWitness::from(6u64))
+                .unwrap_u8(),
+            0
+        );
+    }
+
     #[test]
     fn overflow() {
         assert_eq!(
@@ -0,0 +1,63 @@
+//! Zeroization of the secrets: private keys, nonces and blinding factors.
+//!
+//! Scalars are `Copy`, so the copies made during the computations cannot be tracked.
+//! Instead, the types that keep the secrets between the calls (witnesses of the commitments,
+//! nonces and keys of the signing protocols) overwrite them with zeros when they are dropped,
+//! using the `Zeroize` implementations of the `zeroize` crate for the scalars and the byte arrays.
+
+use core::ops::Deref;
+use curve25519_dalek::scalar::Scalar;
+use zeroize::Zeroize;
+
+/// Secret scalar that is zeroized when dropped.
+/// Used in the states of the signing protocols, whose fields are moved from one round to the next.
+pub(crate) struct SecretScalar(Scalar);
+
+impl SecretScalar {
+    pub(crate) fn new(secret: Scalar) -> Self {
+        SecretScalar(secret)
+    }
+}
+
+impl Deref for SecretScalar {
+    type Target = Scalar;
+
+    fn deref(&self) -> &Scalar {
+        &self.0
+    }
+}
+
+impl Zeroize for SecretScalar {
+    fn zeroize(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl Drop for SecretScalar {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CommitmentWitness, ScalarWitness};
+
+    #[test]
+    fn zeroize_secrets() {
+        let mut secret = SecretScalar::new(Scalar::from(42u64));
+        assert_eq!(*secret, Scalar::from(42u64));
+        secret.zeroize();
+        assert_eq!(*secret, Scalar::zero());
+
+        let mut witness = CommitmentWitness::new(-ScalarWitness::from(7u64), Scalar::from(9u64));
+        witness.zeroize();
+        assert_eq!(witness.value(), ScalarWitness::from(0u64));
+        assert_eq!(witness.blinding(), Scalar::zero());
+
+        let mut witness = ScalarWitness::from(Scalar::from(5u64));
+        witness.zeroize();
+        assert_eq!(witness, ScalarWitness::from(Scalar::zero()));
+    }
+}
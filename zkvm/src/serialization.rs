@@ -0,0 +1,336 @@
+//! Helpers for the optional `serde` support.
+//!
+//! Types with a fixed wire encoding (identifiers, keys, signatures, transactions)
+//! are serialized as hex strings in human-readable formats (e.g. JSON)
+//! and as raw bytes in binary formats. `Tx` keeps the byte string representation
+//! it had before the `serde` feature was introduced (an array of numbers in JSON).
+//! Types that carry prover's secrets
+//! (commitments, predicates, programs) are serialized structurally,
+//! so that the witness data survives the round trip.
+
+/// Implements `Serialize` and `Deserialize` for a type via its byte encoding.
+///
+/// Usage: `serialize_bytes!(Type, "description", |x| x.to_bytes(), |bytes| Type::from_bytes(bytes));`
+/// where the decoding expression returns `Result<Type, VMError>`.
+macro_rules! serialize_bytes {
+    ($type:ty, $expecting:expr, |$this:ident| $to_bytes:expr, |$bytes:ident| $from_bytes:expr) => {
+        impl ::serde::Serialize for $type {
+            fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+            where
+                S: ::serde::Serializer,
+            {
+                let $this = self;
+                let bytes = $to_bytes;
+                $crate::serialization::serialize_bytes(&bytes[..], serializer)
+            }
+        }
+
+        impl<'de> ::serde::Deserialize<'de> for $type {
+            fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+            where
+                D: ::serde::Deserializer<'de>,
+            {
+                $crate::serialization::deserialize_bytes(deserializer, $expecting, |$bytes| {
+                    $from_bytes
+                })
+            }
+        }
+    };
+}
+
+#[cfg(feature = "serde")]
+pub(crate) use self::inner::*;
+
+#[cfg(feature = "serde")]
+mod inner {
+    use bulletproofs::r1cs;
+    use core::fmt;
+    use core::marker::PhantomData;
+    use curve25519_dalek::scalar::Scalar;
+    use serde::de::{self, Unexpected, Visitor};
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use crate::errors::VMError;
+
+    /// Serializes bytes as a lowercase hex string in human-readable formats
+    /// and as a plain byte string otherwise.
+    pub(crate) fn serialize_bytes<S: Serializer>(
+        bytes: &[u8],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&to_hex(bytes))
+        } else {
+            serializer.serialize_bytes(bytes)
+        }
+    }
+
+    struct BytesVisitor<T, F> {
+        expecting: &'static str,
+        from_bytes: F,
+        _phantom: PhantomData<T>,
+    }
+
+    impl<'de, T, F> Visitor<'de> for BytesVisitor<T, F>
+    where
+        F: FnOnce(&[u8]) -> Result<T, VMError>,
+    {
+        type Value = T;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str(self.expecting)
+        }
+
+        fn visit_str<E: de::Error>(self, v: &str) -> Result<T, E> {
+            let bytes = from_hex(v).ok_or_else(|| E::invalid_value(Unexpected::Str(v), &self))?;
+            (self.from_bytes)(&bytes).map_err(E::custom)
+        }
+
+        fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<T, E> {
+            (self.from_bytes)(v).map_err(E::custom)
+        }
+
+        fn visit_seq<A: de::SeqAccess<'de>>(self, mut seq: A) -> Result<T, A::Error> {
+            let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+            while let Some(b) = seq.next_element()? {
+                bytes.push(b);
+            }
+            (self.from_bytes)(&bytes).map_err(de::Error::custom)
+        }
+    }
+
+    /// Deserializes bytes produced by `serialize_bytes` and decodes them with `from_bytes`.
+    pub(crate) fn deserialize_bytes<'de, D, T, F>(
+        deserializer: D,
+        expecting: &'static str,
+        from_bytes: F,
+    ) -> Result<T, D::Error>
+    where
+        D: Deserializer<'de>,
+        F: FnOnce(&[u8]) -> Result<T, VMError>,
+    {
+        let visitor = BytesVisitor {
+            expecting,
+            from_bytes,
+            _phantom: PhantomData,
+        };
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_str(visitor)
+        } else {
+            deserializer.deserialize_bytes(visitor)
+        }
+    }
+
+    /// Deserializes bytes serialized with `Serializer::serialize_bytes` in any format
+    /// (an array of numbers in JSON) and decodes them with `from_bytes`.
+    pub(crate) fn deserialize_raw_bytes<'de, D, T, F>(
+        deserializer: D,
+        expecting: &'static str,
+        from_bytes: F,
+    ) -> Result<T, D::Error>
+    where
+        D: Deserializer<'de>,
+        F: FnOnce(&[u8]) -> Result<T, VMError>,
+    {
+        deserializer.deserialize_bytes(BytesVisitor {
+            expecting,
+            from_bytes,
+            _phantom: PhantomData,
+        })
+    }
+
+    fn to_hex(bytes: &[u8]) -> String {
+        const DIGITS: &[u8; 16] = b"0123456789abcdef";
+        let mut s = String::with_capacity(bytes.len() * 2);
+        for b in bytes {
+            s.push(DIGITS[(b >> 4) as usize] as char);
+            s.push(DIGITS[(b & 0x0f) as usize] as char);
+        }
+        s
+    }
+
+    fn from_hex(s: &str) -> Option<Vec<u8>> {
+        fn digit(c: u8) -> Option<u8> {
+            match c {
+                b'0'..=b'9' => Some(c - b'0'),
+                b'a'..=b'f' => Some(c - b'a' + 10),
+                b'A'..=b'F' => Some(c - b'A' + 10),
+                _ => None,
+            }
+        }
+        let s = s.as_bytes();
+        if s.len() % 2 != 0 {
+            return None;
+        }
+        s.chunks(2)
+            .map(|pair| Some(digit(pair[0])? << 4 | digit(pair[1])?))
+            .collect()
+    }
+
+    /// Serializes `SignedInteger` as a pair of a sign flag and a 64-bit absolute value.
+    pub(crate) mod signed_integer {
+        use super::*;
+        use spacesuit::SignedInteger;
+
+        pub fn serialize<S: Serializer>(
+            x: &SignedInteger,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            let pair = match x.to_u64() {
+                Some(abs) => (false, abs),
+                None => (true, (-*x).to_u64().unwrap_or(0)),
+            };
+            pair.serialize(serializer)
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<SignedInteger, D::Error> {
+            let (negative, abs): (bool, u64) = Deserialize::deserialize(deserializer)?;
+            let x = SignedInteger::from(abs);
+            Ok(if negative { -x } else { x })
+        }
+    }
+
+    /// Serializes `BitRange` as a number of bits.
+    pub(crate) mod bit_range {
+        use super::*;
+        use spacesuit::BitRange;
+
+        pub fn serialize<S: Serializer>(n: &BitRange, serializer: S) -> Result<S::Ok, S::Error> {
+            let n: u8 = (*n).into();
+            n.serialize(serializer)
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<BitRange, D::Error> {
+            let n: u8 = Deserialize::deserialize(deserializer)?;
+            BitRange::new_wide(n as usize).ok_or_else(|| {
+                de::Error::invalid_value(
+                    Unexpected::Unsigned(n as u64),
+                    &"a bit range within [0,252]",
+                )
+            })
+        }
+    }
+
+    /// Serializes the terms of a low-level linear combination.
+    /// Note: R1CS variables are indices into a particular constraint system,
+    /// so the deserialized terms are meaningful only within that constraint system.
+    pub(crate) mod r1cs_terms {
+        use super::*;
+
+        #[derive(Serialize, Deserialize)]
+        enum R1CSVariable {
+            Committed(usize),
+            MultiplierLeft(usize),
+            MultiplierRight(usize),
+            MultiplierOutput(usize),
+            One,
+        }
+
+        impl From<r1cs::Variable> for R1CSVariable {
+            fn from(v: r1cs::Variable) -> Self {
+                match v {
+                    r1cs::Variable::Committed(i) => R1CSVariable::Committed(i),
+                    r1cs::Variable::MultiplierLeft(i) => R1CSVariable::MultiplierLeft(i),
+                    r1cs::Variable::MultiplierRight(i) => R1CSVariable::MultiplierRight(i),
+                    r1cs::Variable::MultiplierOutput(i) => R1CSVariable::MultiplierOutput(i),
+                    r1cs::Variable::One() => R1CSVariable::One,
+                }
+            }
+        }
+
+        impl Into<r1cs::Variable> for R1CSVariable {
+            fn into(self) -> r1cs::Variable {
+                match self {
+                    R1CSVariable::Committed(i) => r1cs::Variable::Committed(i),
+                    R1CSVariable::MultiplierLeft(i) => r1cs::Variable::MultiplierLeft(i),
+                    R1CSVariable::MultiplierRight(i) => r1cs::Variable::MultiplierRight(i),
+                    R1CSVariable::MultiplierOutput(i) => r1cs::Variable::MultiplierOutput(i),
+                    R1CSVariable::One => r1cs::Variable::One(),
+                }
+            }
+        }
+
+        pub fn serialize<S: Serializer>(
+            terms: &Vec<(r1cs::Variable, Scalar)>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            let terms: Vec<(R1CSVariable, Scalar)> =
+                terms.iter().map(|(v, s)| ((*v).into(), *s)).collect();
+            terms.serialize(serializer)
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Vec<(r1cs::Variable, Scalar)>, D::Error> {
+            let terms: Vec<(R1CSVariable, Scalar)> = Deserialize::deserialize(deserializer)?;
+            Ok(terms.into_iter().map(|(v, s)| (v.into(), s)).collect())
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::{Anchor, Commitment, Predicate, ScalarWitness, TxID, VerificationKey};
+        use spacesuit::SignedInteger;
+
+        #[test]
+        fn hex_roundtrip() {
+            let bytes = vec![0x00, 0x01, 0x7f, 0x80, 0xab, 0xff];
+            assert_eq!(to_hex(&bytes), "00017f80abff");
+            assert_eq!(from_hex("00017f80abff"), Some(bytes.clone()));
+            assert_eq!(from_hex("00017F80ABFF"), Some(bytes));
+            assert_eq!(from_hex("abc"), None);
+            assert_eq!(from_hex("zz"), None);
+        }
+
+        #[test]
+        fn ids_as_hex_strings() {
+            let txid = TxID([0xab; 32]);
+            let json = serde_json::to_string(&txid).unwrap();
+            assert_eq!(json, format!("\"{}\"", "ab".repeat(32)));
+            assert_eq!(serde_json::from_str::<TxID>(&json).unwrap(), txid);
+
+            // Anchors must be exactly 32 bytes long.
+            assert!(serde_json::from_str::<Anchor>("\"abab\"").is_err());
+        }
+
+        #[test]
+        fn scalar_witness_roundtrip() {
+            let witnesses = vec![
+                ScalarWitness::Integer(SignedInteger::from(42u64)),
+                ScalarWitness::Integer(-SignedInteger::from(u64::max_value())),
+                ScalarWitness::Scalar(Scalar::from(7u64).invert()),
+            ];
+            for w in witnesses.into_iter() {
+                let json = serde_json::to_string(&w).unwrap();
+                assert_eq!(serde_json::from_str::<ScalarWitness>(&json).unwrap(), w);
+            }
+        }
+
+        #[test]
+        fn open_commitment_keeps_witness() {
+            let c = Commitment::blinded(100u64);
+            let json = serde_json::to_string(&c).unwrap();
+            let c2: Commitment = serde_json::from_str(&json).unwrap();
+            assert_eq!(c2.to_point(), c.to_point());
+            assert_eq!(c2.witness(), c.witness());
+        }
+
+        #[test]
+        fn predicate_roundtrip() {
+            let key = VerificationKey::from_secret(&Scalar::from(1u64));
+            let pred = Predicate::Key(key);
+            let json = serde_json::to_string(&pred).unwrap();
+            let pred2: Predicate = serde_json::from_str(&json).unwrap();
+            assert_eq!(pred2.to_point(), pred.to_point());
+            match pred2 {
+                Predicate::Key(k) => assert_eq!(k, key),
+                _ => panic!("Key predicate must be deserialized as a key"),
+            }
+        }
+    }
+}
@@ -0,0 +1,223 @@
+//! Adaptor signatures ("scriptless scripts").
+//!
+//! An adaptor signature is a Schnorr signature offset by a secret scalar `t`,
+//! which is locked to the _adaptor point_ `T = t·B`. Anyone can check
+//! that the adaptor signature is valid for `T` without knowing `t`.
+//! Whoever knows `t` can complete it into a valid signature,
+//! and publishing that signature reveals `t` to the holder of the adaptor signature.
+//!
+//! This allows atomic swaps and payment channels without hashlocks:
+//! the secret `t` plays the role of a hash preimage, but the resulting
+//! transactions are indistinguishable from the ones with ordinary signatures.
+
+#![allow(non_snake_case)]
+
+use curve25519_dalek::ristretto::CompressedRistretto;
+use curve25519_dalek::scalar::Scalar;
+use merlin::Transcript;
+use rand::{CryptoRng, RngCore};
+
+use super::{key_factors, Signature, VerificationKey};
+use crate::errors::VMError;
+use crate::gens;
+use crate::point_ops::PointOp;
+use crate::transcript::TranscriptProtocol;
+
+/// A Schnorr signature which becomes valid once the secret of its adaptor point is added.
+#[derive(Copy, Clone, Debug)]
+pub struct AdaptorSignature {
+    /// Nonce commitment of the completed signature: `R = r·B + T`.
+    R: CompressedRistretto,
+    /// Signature scalar without the adaptor secret: `s' = r + e·x`.
+    s: Scalar,
+}
+
+impl Signature {
+    /// Creates an adaptor signature for a set of private keys, locked to the adaptor point.
+    /// The message must be already committed to the transcript.
+    /// Fails with `VMError::InvalidPoint` if the adaptor point is not a valid point.
+    #[cfg(feature = "std")]
+    pub fn sign_adaptor(
+        transcript: &mut Transcript,
+        privkeys: &[Scalar],
+        adaptor: CompressedRistretto,
+    ) -> Result<AdaptorSignature, VMError> {
+        Signature::sign_adaptor_with_rng(transcript, privkeys, adaptor, &mut rand::thread_rng())
+    }
+
+    /// Creates an adaptor signature for a set of private keys using the provided RNG.
+    ///
+    /// Like in `Signature::sign_aggregated_with_rng`, the nonce is derived from the transcript
+    /// and the private keys, and also from the adaptor point: signing the same message
+    /// for different adaptor points must not reuse the nonce.
+    pub fn sign_adaptor_with_rng<R: RngCore + CryptoRng>(
+        transcript: &mut Transcript,
+        privkeys: &[Scalar],
+        adaptor: CompressedRistretto,
+        rng: &mut R,
+    ) -> Result<AdaptorSignature, VMError> {
+        let T = adaptor.decompress().ok_or(VMError::InvalidPoint)?;
+        let pubkeys = privkeys
+            .iter()
+            .map(|p| VerificationKey::from_secret(p))
+            .collect::<Vec<_>>();
+
+        let x = key_factors(transcript, &pubkeys);
+        let aggregated_privkey = privkeys
+            .iter()
+            .zip(x.iter())
+            .fold(Scalar::zero(), |sum, (p, x)| sum + p * x);
+
+        let mut rng = transcript
+            .build_rng()
+            .commit_witness_bytes(b"privkey", aggregated_privkey.as_bytes())
+            .commit_witness_bytes(b"adaptor", adaptor.as_bytes())
+            .finalize(rng);
+        let r = Scalar::random(&mut rng);
+
+        // The challenge commits to the nonce of the completed signature.
+        let R = (gens::mul_base(&r) + T).compress();
+        transcript.commit_point(b"R", &R);
+        let e = transcript.challenge_scalar(b"e");
+
+        Ok(AdaptorSignature {
+            R,
+            s: r + e * aggregated_privkey,
+        })
+    }
+}
+
+impl AdaptorSignature {
+    /// Verifies the adaptor signature for a collection of public keys and the adaptor point.
+    /// The message must be already committed to the transcript.
+    pub fn verify_adaptor(
+        &self,
+        transcript: &mut Transcript,
+        pubkeys: &[VerificationKey],
+        adaptor: CompressedRistretto,
+    ) -> PointOp {
+        let x = key_factors(transcript, pubkeys);
+        transcript.commit_point(b"R", &self.R);
+        let e = transcript.challenge_scalar(b"e");
+
+        // `s'*B == e*(x0*P0 + x1*P1 + ...) + R - T`
+        //      ->
+        // `0 == -s'*B + e*(x0*P0 + x1*P1 + ...) + R - T`
+        let mut pairs = x
+            .into_iter()
+            .zip(pubkeys.iter())
+            .map(|(x, p)| (x * e, p.0))
+            .collect::<Vec<_>>();
+        pairs.push((Scalar::one(), self.R));
+        pairs.push((-Scalar::one(), adaptor));
+
+        PointOp {
+            primary: Some(-self.s),
+            secondary: None,
+            arbitrary: pairs,
+        }
+    }
+
+    /// Completes the adaptor signature with the secret of the adaptor point.
+    /// The result is an ordinary signature, verified with `Signature::verify_aggregated`.
+    pub fn adapt(&self, secret: Scalar) -> Signature {
+        Signature {
+            R: self.R,
+            s: self.s + secret,
+        }
+    }
+
+    /// Extracts the secret of the adaptor point from the completed signature.
+    /// Fails with `VMError::InvalidAdaptorSignature` if the signature
+    /// was not completed from this adaptor signature.
+    pub fn extract_secret(
+        &self,
+        signature: &Signature,
+        adaptor: CompressedRistretto,
+    ) -> Result<Scalar, VMError> {
+        let secret = signature.s - self.s;
+        if signature.R != self.R || VerificationKey::from_secret(&secret).0 != adaptor {
+            return Err(VMError::InvalidAdaptorSignature);
+        }
+        Ok(secret)
+    }
+
+    /// Decodes an adaptor signature from 64-byte array.
+    pub fn from_bytes(sig: [u8; 64]) -> Result<Self, VMError> {
+        let sig = Signature::from_bytes(sig)?;
+        Ok(AdaptorSignature { R: sig.R, s: sig.s })
+    }
+
+    /// Encodes the adaptor signature as a 64-byte array.
+    pub fn to_bytes(&self) -> [u8; 64] {
+        Signature {
+            R: self.R,
+            s: self.s,
+        }
+        .to_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keypair() -> (Scalar, VerificationKey) {
+        let privkey = Scalar::random(&mut rand::thread_rng());
+        (privkey, VerificationKey::from_secret(&privkey))
+    }
+
+    #[test]
+    fn adapt_and_extract() {
+        let (x1, P1) = keypair();
+        let (x2, P2) = keypair();
+        let (t, T) = keypair();
+
+        let adaptor_sig =
+            Signature::sign_adaptor(&mut Transcript::new(b"adaptor_signature"), &[x1, x2], T.0)
+                .unwrap();
+        assert!(adaptor_sig
+            .verify_adaptor(&mut Transcript::new(b"adaptor_signature"), &[P1, P2], T.0)
+            .verify()
+            .is_ok());
+
+        // The adaptor signature is not a valid signature on its own.
+        let incomplete = adaptor_sig.adapt(Scalar::zero());
+        assert!(incomplete
+            .verify_aggregated(&mut Transcript::new(b"adaptor_signature"), &[P1, P2])
+            .verify()
+            .is_err());
+
+        let sig = adaptor_sig.adapt(t);
+        assert!(sig
+            .verify_aggregated(&mut Transcript::new(b"adaptor_signature"), &[P1, P2])
+            .verify()
+            .is_ok());
+        assert_eq!(adaptor_sig.extract_secret(&sig, T.0), Ok(t));
+        assert_eq!(
+            adaptor_sig.extract_secret(&incomplete, T.0),
+            Err(VMError::InvalidAdaptorSignature)
+        );
+
+        let decoded = AdaptorSignature::from_bytes(adaptor_sig.to_bytes()).unwrap();
+        assert_eq!(decoded.adapt(t).to_bytes()[..], sig.to_bytes()[..]);
+    }
+
+    #[test]
+    fn wrong_adaptor_point() {
+        let (x, P) = keypair();
+        let (_, T) = keypair();
+        let (_, wrong_T) = keypair();
+
+        let adaptor_sig =
+            Signature::sign_adaptor(&mut Transcript::new(b"adaptor_signature"), &[x], T.0).unwrap();
+        assert!(adaptor_sig
+            .verify_adaptor(&mut Transcript::new(b"adaptor_signature"), &[P], wrong_T.0)
+            .verify()
+            .is_err());
+        assert!(adaptor_sig
+            .verify_adaptor(&mut Transcript::new(b"other_message"), &[P], T.0)
+            .verify()
+            .is_err());
+    }
+}
@@ -0,0 +1,221 @@
+//! Blind Schnorr signatures.
+//!
+//! The issuer signs a message without learning the message or the resulting signature,
+//! as in ecash-style issuance where the issuer must not be able to link
+//! the redeemed tokens to the signing sessions:
+//!
+//! 1. The issuer creates a `BlindNonce` and sends its commitment `R'` to the requester.
+//! 2. The requester commits the message to the transcript, picks the blinding factors `α` and `β`,
+//!    and computes the nonce `R = R' + α·B + β·P` and the challenge `e` for `R`
+//!    (which includes the factor of the single key, as in `Signature::verify_single`).
+//!    The requester sends the blinded challenge `e' = e + β` to the issuer.
+//! 3. The issuer responds with `s' = r' + e'·x`.
+//! 4. The requester checks the response and unblinds it into the signature `(R, s' + α)`.
+//!
+//! The result is a regular `Signature` verified with `Signature::verify_single`.
+//! Note that the issuer must not sign concurrently with the same key: with enough parallel
+//! sessions, a requester can obtain one more signature than the number of sessions
+//! (the ROS attack), so each nonce must be consumed before the next one is issued.
+
+#![allow(non_snake_case)]
+
+use curve25519_dalek::constants::RISTRETTO_BASEPOINT_POINT;
+use curve25519_dalek::ristretto::{CompressedRistretto, RistrettoPoint};
+use curve25519_dalek::scalar::Scalar;
+use merlin::Transcript;
+use rand::{CryptoRng, RngCore};
+use zeroize::Zeroize;
+
+use super::counterparty::NonceCommitment;
+use super::{key_factors, Signature, VerificationKey};
+use crate::errors::VMError;
+use crate::transcript::TranscriptProtocol;
+
+/// Secret nonce of the issuer for one blind signature.
+/// The nonce is consumed by signing, so it cannot be reused for another request,
+/// and is zeroized when dropped.
+pub struct BlindNonce {
+    r: Scalar,
+    commitment: NonceCommitment,
+}
+
+/// Blinding factors of the requester, kept until the issuer responds to the blinded challenge.
+/// The blinding factor of the signature is zeroized when dropped.
+pub struct Unblinder {
+    alpha: Scalar,
+    challenge: Scalar,
+    pubkey: RistrettoPoint,
+    commitment: NonceCommitment,
+    R: CompressedRistretto,
+}
+
+impl BlindNonce {
+    /// Creates a nonce for a blind signature with the private key.
+    /// The RNG is used as an additional source of entropy.
+    pub fn new<R: RngCore + CryptoRng>(privkey: &Scalar, rng: &mut R) -> Self {
+        let mut rng = Transcript::new(b"ZkVM.blind-nonce")
+            .build_rng()
+            .commit_witness_bytes(b"privkey", privkey.as_bytes())
+            .finalize(rng);
+        let r = Scalar::random(&mut rng);
+        BlindNonce {
+            r,
+            commitment: NonceCommitment::new(r * RISTRETTO_BASEPOINT_POINT),
+        }
+    }
+
+    /// Returns the nonce commitment to send to the requester.
+    pub fn commitment(&self) -> NonceCommitment {
+        self.commitment
+    }
+
+    /// Signs the blinded challenge received from the requester.
+    pub fn sign(self, privkey: Scalar, blinded_challenge: Scalar) -> Scalar {
+        self.r + blinded_challenge * privkey
+    }
+}
+
+impl Unblinder {
+    /// Blinds the issuer's nonce commitment and returns the blinded challenge to send to the issuer.
+    /// The message must be already committed to the transcript.
+    /// Fails with `VMError::InvalidPoint` if the issuer's key is not a valid point.
+    pub fn new<R: RngCore + CryptoRng>(
+        transcript: &mut Transcript,
+        pubkey: VerificationKey,
+        commitment: NonceCommitment,
+        rng: &mut R,
+    ) -> Result<(Self, Scalar), VMError> {
+        let P = pubkey.0.decompress().ok_or(VMError::InvalidPoint)?;
+
+        let mut rng = transcript
+            .build_rng()
+            .commit_witness_bytes(b"R'", &commitment.to_bytes())
+            .finalize(rng);
+        let alpha = Scalar::random(&mut rng);
+        let beta = Scalar::random(&mut rng);
+        let R = (commitment.point() + alpha * RISTRETTO_BASEPOINT_POINT + beta * P).compress();
+
+        // Same protocol as `Signature::verify_single`.
+        let x = key_factors(transcript, &[pubkey])[0];
+        transcript.commit_point(b"R", &R);
+        let e = transcript.challenge_scalar(b"e");
+
+        let challenge = e * x + beta;
+        let unblinder = Unblinder {
+            alpha,
+            challenge,
+            pubkey: P,
+            commitment,
+            R,
+        };
+        Ok((unblinder, challenge))
+    }
+
+    /// Verifies the issuer's response and unblinds it into the signature.
+    /// Fails with `VMError::BlindSignatureError` if the response is invalid.
+    pub fn unblind(self, response: Scalar) -> Result<Signature, VMError> {
+        if response * RISTRETTO_BASEPOINT_POINT
+            != self.commitment.point() + self.challenge * self.pubkey
+        {
+            return Err(VMError::BlindSignatureError);
+        }
+        Ok(Signature {
+            R: self.R,
+            s: response + self.alpha,
+        })
+    }
+}
+
+impl Zeroize for BlindNonce {
+    fn zeroize(&mut self) {
+        self.r.zeroize();
+    }
+}
+
+impl Drop for BlindNonce {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
+impl Zeroize for Unblinder {
+    fn zeroize(&mut self) {
+        self.alpha.zeroize();
+    }
+}
+
+impl Drop for Unblinder {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::point_ops::PointOp;
+
+    fn issue(privkey: Scalar, message: &[u8]) -> Signature {
+        let mut rng = rand::thread_rng();
+        let pubkey = VerificationKey::from_secret(&privkey);
+
+        let nonce = BlindNonce::new(&privkey, &mut rng);
+        let mut transcript = Transcript::new(b"blind_signature");
+        transcript.commit_bytes(b"token", message);
+        let (unblinder, challenge) =
+            Unblinder::new(&mut transcript, pubkey, nonce.commitment(), &mut rng).unwrap();
+        let response = nonce.sign(privkey, challenge);
+        unblinder.unblind(response).unwrap()
+    }
+
+    #[test]
+    fn blind_signature() {
+        let privkey = Scalar::random(&mut rand::thread_rng());
+        let pubkey = VerificationKey::from_secret(&privkey);
+        let messages: Vec<&[u8]> = vec![&b"token 1"[..], &b"token 2"[..], &b"token 3"[..]];
+        let sigs = messages
+            .iter()
+            .map(|m| issue(privkey, m))
+            .collect::<Vec<_>>();
+
+        let transcripts = |messages: &[&[u8]]| {
+            messages
+                .iter()
+                .map(|m| {
+                    let mut t = Transcript::new(b"blind_signature");
+                    t.commit_bytes(b"token", m);
+                    t
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let ops = transcripts(&messages)
+            .iter_mut()
+            .zip(sigs.iter())
+            .map(|(t, sig)| sig.verify_single(t, pubkey))
+            .collect::<Vec<_>>();
+        assert!(PointOp::verify_batch(&ops).is_ok());
+        assert!(Signature::verify_batch(&mut transcripts(&messages), &[pubkey; 3], &sigs).is_ok());
+
+        // Signatures do not match the swapped messages.
+        let swapped: Vec<&[u8]> = vec![&b"token 2"[..], &b"token 1"[..], &b"token 3"[..]];
+        assert!(Signature::verify_batch(&mut transcripts(&swapped), &[pubkey; 3], &sigs).is_err());
+    }
+
+    #[test]
+    fn invalid_response() {
+        let mut rng = rand::thread_rng();
+        let privkey = Scalar::random(&mut rng);
+        let pubkey = VerificationKey::from_secret(&privkey);
+
+        let nonce = BlindNonce::new(&privkey, &mut rng);
+        let mut transcript = Transcript::new(b"blind_signature");
+        let (unblinder, challenge) =
+            Unblinder::new(&mut transcript, pubkey, nonce.commitment(), &mut rng).unwrap();
+        let response = nonce.sign(privkey, challenge + Scalar::one());
+        assert_eq!(
+            unblinder.unblind(response).unwrap_err(),
+            VMError::BlindSignatureError
+        );
+    }
+}
@@ -8,18 +8,46 @@ use curve25519_dalek::scalar::Scalar;
 use merlin::Transcript;
 use subtle::ConstantTimeEq;
 
-#[derive(Copy, Clone)]
+/// Hash of the party's nonce commitment, shared in the first round of the protocol.
+#[derive(Copy, Clone, Debug)]
 pub struct NoncePrecommitment([u8; 32]);
 
+/// Party's nonce commitment, revealed in the second round of the protocol.
 #[derive(Copy, Clone, Debug)]
 pub struct NonceCommitment(RistrettoPoint);
 
+impl NoncePrecommitment {
+    /// Converts the precommitment to bytes for sending to the counterparties.
+    pub fn to_bytes(&self) -> [u8; 32] {
+        self.0
+    }
+
+    /// Wraps the precommitment bytes received from a counterparty.
+    pub fn from_bytes(bytes: [u8; 32]) -> Self {
+        NoncePrecommitment(bytes)
+    }
+}
+
 impl NonceCommitment {
+    /// Converts the commitment to bytes for sending to the counterparties.
+    pub fn to_bytes(&self) -> [u8; 32] {
+        self.0.compress().to_bytes()
+    }
+
+    /// Decodes the commitment received from a counterparty.
+    pub fn from_bytes(bytes: [u8; 32]) -> Result<Self, VMError> {
+        CompressedRistretto(bytes)
+            .decompress()
+            .map(NonceCommitment)
+            .ok_or(VMError::InvalidPoint)
+    }
+
     pub(super) fn new(commitment: RistrettoPoint) -> Self {
         NonceCommitment(commitment)
     }
 
-    pub(super) fn precommit(&self) -> NoncePrecommitment {
+    /// Computes the precommitment to this nonce commitment.
+    pub fn precommit(&self) -> NoncePrecommitment {
         let mut h = Transcript::new(b"MuSig.nonce-precommit");
         h.commit_point(b"R", &self.0.compress());
         let mut precommitment = [0u8; 32];
@@ -27,11 +55,15 @@ impl NonceCommitment {
         NoncePrecommitment(precommitment)
     }
 
+    pub(super) fn point(&self) -> RistrettoPoint {
+        self.0
+    }
+
     pub(super) fn compress(&self) -> CompressedRistretto {
         self.0.compress()
     }
 
-    pub(super) fn sum(commitments: &Vec<Self>) -> RistrettoPoint {
+    pub(super) fn sum(commitments: &[Self]) -> RistrettoPoint {
         commitments.iter().map(|R_i| R_i.0).sum()
     }
 }
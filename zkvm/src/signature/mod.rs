@@ -2,20 +2,37 @@
 
 #![allow(non_snake_case)]
 
-use bulletproofs::PedersenGens;
 use curve25519_dalek::ristretto::{CompressedRistretto, RistrettoPoint};
 use curve25519_dalek::scalar::Scalar;
 use merlin::Transcript;
+use rand::{CryptoRng, RngCore};
 
+#[cfg(feature = "serde")]
+use crate::encoding::SliceReader;
 use crate::errors::VMError;
+use crate::gens;
 use crate::point_ops::PointOp;
 use crate::transcript::TranscriptProtocol;
 
+mod adaptor;
+mod blind;
 mod counterparty;
 mod multikey;
-mod musig;
+pub mod musig;
+#[cfg(feature = "prover")]
+mod partial;
+#[cfg(feature = "prover")]
+mod session;
+#[cfg(feature = "prover")]
 mod signer;
-
+#[cfg(feature = "prover")]
+pub mod threshold;
+
+pub use self::adaptor::AdaptorSignature;
+pub use self::blind::{BlindNonce, Unblinder};
+#[cfg(feature = "prover")]
+pub use self::partial::SecretNonce;
+#[cfg(feature = "prover")]
 pub use self::signer::Party;
 
 /// Verification key (aka "pubkey") is a wrapper type around a Ristretto point
@@ -43,29 +60,18 @@ impl Signature {
         transcript: &mut Transcript,
         pubkeys: &[VerificationKey],
     ) -> PointOp {
-        transcript.commit_u64(b"n", pubkeys.len() as u64);
-        for p in pubkeys.iter() {
-            transcript.commit_point(b"P", &p.0);
-        }
+        let factors = key_factors(transcript, pubkeys);
 
-        let mut pairs = pubkeys
-            .iter()
-            .map(|p| {
-                let x = transcript.challenge_scalar(b"x");
-                (x, p.0)
-            })
-            .collect::<Vec<_>>();
-
-        // Commit the signature's nonce commitment
+        // Commit the signature's nonce commitment and get the Fiat-Shamir challenge
         transcript.commit_point(b"R", &self.R);
-
-        // Get the Fiat-Shamir challenge
         let e = transcript.challenge_scalar(b"e");
 
         // Apply challenge to all the pubkeys
-        for i in 0..pubkeys.len() {
-            pairs[i] = (pairs[i].0 * e, pairs[i].1);
-        }
+        let mut pairs = factors
+            .into_iter()
+            .zip(pubkeys.iter())
+            .map(|(x, p)| (x * e, p.0))
+            .collect::<Vec<_>>();
 
         // Form the final linear combination:
         // `s*B == e*(P0 + x1*P1 + x2*P2 + ...) + R`
@@ -80,45 +86,77 @@ impl Signature {
         }
     }
 
+    /// Verifies a batch of signatures for single keys using one multi-scalar multiplication.
+    /// The messages must be already committed to the transcripts.
+    pub fn verify_batch(
+        transcripts: &mut [Transcript],
+        pubkeys: &[VerificationKey],
+        signatures: &[Signature],
+    ) -> Result<(), VMError> {
+        if transcripts.len() != pubkeys.len() || signatures.len() != pubkeys.len() {
+            return Err(VMError::BadArguments);
+        }
+        let ops = transcripts
+            .iter_mut()
+            .zip(pubkeys.iter().zip(signatures.iter()))
+            .map(|(t, (p, sig))| sig.verify_single(t, *p))
+            .collect::<Vec<_>>();
+        PointOp::verify_batch(&ops)
+    }
+
     /// Creates a signature for a single private key
+    #[cfg(feature = "std")]
     pub fn sign_single(transcript: &mut Transcript, privkey: Scalar) -> Self {
         Signature::sign_aggregated(transcript, &[privkey])
     }
 
+    /// Creates a signature for a single private key using the provided RNG.
+    pub fn sign_single_with_rng<R: RngCore + CryptoRng>(
+        transcript: &mut Transcript,
+        privkey: Scalar,
+        rng: &mut R,
+    ) -> Self {
+        Signature::sign_aggregated_with_rng(transcript, &[privkey], rng)
+    }
+
     /// Creates an aggregated signature for a set of private keys
+    #[cfg(feature = "std")]
     pub fn sign_aggregated(transcript: &mut Transcript, privkeys: &[Scalar]) -> Self {
+        Signature::sign_aggregated_with_rng(transcript, privkeys, &mut rand::thread_rng())
+    }
+
+    /// Creates an aggregated signature for a set of private keys using the provided RNG.
+    ///
+    /// The nonce is derived from the transcript and the private keys,
+    /// and the RNG only contributes additional entropy: a deterministic RNG
+    /// yields deterministic nonces (in the spirit of RFC6979).
+    pub fn sign_aggregated_with_rng<R: RngCore + CryptoRng>(
+        transcript: &mut Transcript,
+        privkeys: &[Scalar],
+        rng: &mut R,
+    ) -> Self {
         // Derive public keys from privkeys
-        let gens = PedersenGens::default();
         let pubkeys = privkeys
             .iter()
             .map(|p| VerificationKey::from_secret(p))
             .collect::<Vec<_>>();
 
-        // Commit pubkeys
-        let n = pubkeys.len();
-        transcript.commit_u64(b"n", n as u64);
-        for p in pubkeys.iter() {
-            transcript.commit_point(b"P", &p.0);
-        }
-
         // Generate aggregated private key
         let aggregated_privkey: Scalar = privkeys
             .iter()
-            .map(|p| {
-                let x = transcript.challenge_scalar(b"x");
-                p * x
-            })
+            .zip(key_factors(transcript, &pubkeys).iter())
+            .map(|(p, x)| p * x)
             .sum();
 
         // Generate secret nonce
         let mut rng = transcript
             .build_rng()
             .commit_witness_bytes(b"privkey", aggregated_privkey.as_bytes())
-            .finalize(&mut rand::thread_rng());
+            .finalize(rng);
         let r = Scalar::random(&mut rng);
 
         // Commit the nonce to the transcript
-        let R = (r * gens.B).compress();
+        let R = gens::mul_base(&r).compress();
         transcript.commit_point(b"R", &R);
 
         // Compute challenge scalar
@@ -129,20 +167,40 @@ impl Signature {
     }
 }
 
+/// Commits the public keys of an aggregated signature to the transcript
+/// and returns the factor `x_i` of each key: commits LE64 `n` as `n`, each key as `P`,
+/// then squeezes each `x_i` as `x`. The signature `(R, s)` for the keys is valid
+/// if `s·B == e·(x_0·P_0 + ... + x_{n-1}·P_{n-1}) + R`, where `e` is squeezed as `e`
+/// after committing `R` as `R`.
+pub(crate) fn key_factors(transcript: &mut Transcript, pubkeys: &[VerificationKey]) -> Vec<Scalar> {
+    transcript.commit_u64(b"n", pubkeys.len() as u64);
+    for p in pubkeys.iter() {
+        transcript.commit_point(b"P", &p.0);
+    }
+    pubkeys
+        .iter()
+        .map(|_| transcript.challenge_scalar(b"x"))
+        .collect()
+}
+
 // Serialization
 impl Signature {
     /// Decodes a signature from 64-byte array.
     pub fn from_bytes(sig: [u8; 64]) -> Result<Self, VMError> {
-        let mut Rbuf = [0u8; 32];
-        let mut sbuf = [0u8; 32];
-        Rbuf[..].copy_from_slice(&sig[..32]);
-        sbuf[..].copy_from_slice(&sig[32..]);
-        Ok(Signature {
-            R: CompressedRistretto(Rbuf),
-            s: Scalar::from_canonical_bytes(sbuf).ok_or(VMError::FormatError)?,
+        SliceReader::parse(&sig, |r| {
+            Ok(Signature {
+                R: r.read_point()?,
+                s: r.read_scalar()?,
+            })
         })
     }
 
+    /// Checks that the nonce commitment decodes to a Ristretto point.
+    /// The scalar is already checked to be canonical by `from_bytes`.
+    pub(crate) fn check_format(&self) -> Result<(), VMError> {
+        self.R.decompress().map(|_| ()).ok_or(VMError::InvalidPoint)
+    }
+
     /// Encodes the signature as a 64-byte array.
     pub fn to_bytes(&self) -> [u8; 64] {
         let mut buf = [0u8; 64];
@@ -152,6 +210,22 @@ impl Signature {
     }
 }
 
+#[cfg(feature = "serde")]
+serialize_bytes!(
+    Signature,
+    "a 64-byte signature",
+    |sig| sig.to_bytes(),
+    |bytes| SliceReader::parse(bytes, |r| r.read_u8x64()).and_then(Signature::from_bytes)
+);
+
+#[cfg(feature = "serde")]
+serialize_bytes!(
+    VerificationKey,
+    "a 32-byte verification key",
+    |key| key.0.to_bytes(),
+    |bytes| SliceReader::parse(bytes, |r| r.read_point()).map(VerificationKey)
+);
+
 impl VerificationKey {
     /// Constructs a VerificationKey from a private key.
     pub fn from_secret(privkey: &Scalar) -> Self {
@@ -160,8 +234,7 @@ impl VerificationKey {
 
     /// Constructs an uncompressed VerificationKey point from a private key.
     pub(crate) fn from_secret_uncompressed(privkey: &Scalar) -> RistrettoPoint {
-        let gens = PedersenGens::default();
-        (privkey * gens.B)
+        gens::mul_base(privkey)
     }
 }
 
@@ -250,4 +323,23 @@ mod tests {
             .verify()
             .is_err());
     }
+
+    #[test]
+    fn deterministic_signature() {
+        use rand::{rngs::StdRng, SeedableRng};
+
+        let privkey = Scalar::from(42u64);
+        let sign = || {
+            let mut transcript = Transcript::new(b"deterministic_signature");
+            let mut rng = StdRng::seed_from_u64(0);
+            Signature::sign_single_with_rng(&mut transcript, privkey, &mut rng)
+        };
+        let (sig1, sig2) = (sign(), sign());
+        assert_eq!(sig1.R, sig2.R);
+        assert_eq!(sig1.s, sig2.s);
+
+        let mut transcript = Transcript::new(b"deterministic_signature");
+        let pubkey = VerificationKey::from_secret(&privkey);
+        assert!(sig1.verify_single(&mut transcript, pubkey).verify().is_ok());
+    }
 }
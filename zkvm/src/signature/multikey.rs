@@ -5,6 +5,7 @@ use curve25519_dalek::ristretto::RistrettoPoint;
 use curve25519_dalek::scalar::Scalar;
 use merlin::Transcript;
 
+/// Aggregated verification key of multiple parties.
 #[derive(Clone)]
 pub struct Multikey {
     transcript: Option<Transcript>,
@@ -12,6 +13,8 @@ pub struct Multikey {
 }
 
 impl Multikey {
+    /// Aggregates the verification keys of the parties.
+    /// The order of the keys affects the aggregated key.
     pub fn new(pubkeys: Vec<VerificationKey>) -> Result<Self, VMError> {
         match pubkeys.len() {
             0 => return Err(VMError::BadArguments),
@@ -55,6 +58,7 @@ impl Multikey {
         a_i_transcript.challenge_scalar(b"a_i")
     }
 
+    /// Returns the factor `a_i` with which the party's key contributes to the aggregated key.
     pub fn factor_for_key(&self, X_i: &VerificationKey) -> Scalar {
         match &self.transcript {
             Some(t) => Multikey::compute_factor(&t, X_i),
@@ -62,6 +66,7 @@ impl Multikey {
         }
     }
 
+    /// Returns the aggregated verification key.
     pub fn aggregated_key(&self) -> VerificationKey {
         self.aggregated_key
     }
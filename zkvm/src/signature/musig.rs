@@ -1,4 +1,5 @@
-use super::counterparty::NonceCommitment;
+//! Multi-party Schnorr signatures with aggregated keys (MuSig).
+
 use super::VerificationKey;
 use crate::errors::VMError;
 use crate::transcript::TranscriptProtocol;
@@ -7,13 +8,24 @@ use curve25519_dalek::ristretto::CompressedRistretto;
 use curve25519_dalek::scalar::Scalar;
 use merlin::Transcript;
 
+pub use super::counterparty::{NonceCommitment, NoncePrecommitment};
+pub use super::multikey::Multikey;
+#[cfg(feature = "prover")]
+pub use super::session::Session;
+
+/// A MuSig signature of an aggregated key.
 #[derive(Debug, Clone)]
 pub struct Signature {
+    /// Sum of the signature shares of all the parties.
     pub s: Scalar,
+    /// Sum of the nonce commitments of all the parties.
     pub R: CompressedRistretto,
 }
 
 impl Signature {
+    /// Creates a signature for a single private key.
+    /// The message must be already committed to the transcript.
+    #[cfg(feature = "std")]
     pub fn sign_single(transcript: &mut Transcript, privkey: Scalar) -> Signature {
         let X = VerificationKey::from_secret(&privkey); // pubkey
 
@@ -38,6 +50,8 @@ impl Signature {
         Signature { s, R: R.compress() }
     }
 
+    /// Verifies the signature for a (possibly aggregated) verification key.
+    /// The message must be already committed to the transcript.
     pub fn verify(&self, transcript: &mut Transcript, X: VerificationKey) -> Result<(), VMError> {
         let G = RISTRETTO_BASEPOINT_POINT;
 
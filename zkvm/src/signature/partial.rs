@@ -0,0 +1,323 @@
+//! Multi-party creation of the aggregated signature and of signatures for aggregated keys.
+//!
+//! Each party signs for its own keys: the signature's nonce commitment is the sum
+//! of the parties' nonce commitments, and the signature is the sum of their shares.
+//! To prevent the parties from choosing their nonces adaptively, the nonce commitments
+//! are revealed only after all the precommitments are exchanged.
+
+#![allow(non_snake_case)]
+
+use curve25519_dalek::constants::RISTRETTO_BASEPOINT_POINT;
+use curve25519_dalek::ristretto::{CompressedRistretto, RistrettoPoint};
+use curve25519_dalek::scalar::Scalar;
+use curve25519_dalek::traits::VartimeMultiscalarMul;
+use merlin::Transcript;
+use rand::{CryptoRng, RngCore};
+use zeroize::Zeroize;
+
+use super::counterparty::NonceCommitment;
+use super::multikey::Multikey;
+use super::{key_factors, Signature, VerificationKey};
+use crate::errors::VMError;
+use crate::transcript::TranscriptProtocol;
+
+/// Secret nonce of a party, kept until the nonce commitments of all the parties are known.
+/// The nonce is consumed by signing, so it cannot be reused for another share,
+/// and is zeroized when dropped.
+pub struct SecretNonce {
+    r: Scalar,
+    commitment: NonceCommitment,
+}
+
+impl SecretNonce {
+    /// Creates a nonce for signing the transcript with the private key.
+    /// The message must be already committed to the transcript.
+    /// The RNG is used as an additional source of entropy.
+    pub fn new<R: RngCore + CryptoRng>(
+        transcript: &Transcript,
+        privkey: &Scalar,
+        rng: &mut R,
+    ) -> Self {
+        let mut rng = transcript
+            .build_rng()
+            .commit_witness_bytes(b"privkey", privkey.as_bytes())
+            .finalize(rng);
+        let r = Scalar::random(&mut rng);
+        SecretNonce {
+            r,
+            commitment: NonceCommitment::new(r * RISTRETTO_BASEPOINT_POINT),
+        }
+    }
+
+    /// Returns the nonce commitment to reveal to the other parties.
+    pub fn commitment(&self) -> NonceCommitment {
+        self.commitment
+    }
+}
+
+impl Zeroize for SecretNonce {
+    fn zeroize(&mut self) {
+        self.r.zeroize();
+    }
+}
+
+impl Drop for SecretNonce {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
+impl Signature {
+    /// Creates the share of the aggregated signature for the key at `index` among `pubkeys`,
+    /// given the nonce commitments for all the keys.
+    /// The message must be already committed to the transcript.
+    pub fn sign_share(
+        transcript: &mut Transcript,
+        pubkeys: &[VerificationKey],
+        commitments: &[NonceCommitment],
+        index: usize,
+        privkey: Scalar,
+        nonce: SecretNonce,
+    ) -> Result<Scalar, VMError> {
+        if index >= pubkeys.len() || VerificationKey::from_secret(&privkey) != pubkeys[index] {
+            return Err(VMError::BadArguments);
+        }
+        let R = nonce_sum(pubkeys, commitments)?;
+        let (x, e) = challenges(transcript, pubkeys, &R);
+        Ok(nonce.r + e * x[index] * privkey)
+    }
+
+    /// Verifies the shares of all the keys and combines them into the aggregated signature.
+    /// Fails with `VMError::MuSigShareError` if one of the shares is invalid.
+    pub fn aggregate_shares(
+        transcript: &mut Transcript,
+        pubkeys: &[VerificationKey],
+        commitments: &[NonceCommitment],
+        shares: &[Scalar],
+    ) -> Result<Signature, VMError> {
+        if shares.len() != pubkeys.len() {
+            return Err(VMError::BadArguments);
+        }
+        let R = nonce_sum(pubkeys, commitments)?;
+        let (x, e) = challenges(transcript, pubkeys, &R);
+        for i in 0..pubkeys.len() {
+            verify_share(&pubkeys[i], &commitments[i], e * x[i], &shares[i])?;
+        }
+        Ok(Signature {
+            R,
+            s: shares.iter().fold(Scalar::zero(), |s, s_i| s + s_i),
+        })
+    }
+
+    /// Creates the share of the signature for the aggregated key of `multikey`,
+    /// which is verified with `Signature::verify_single`, given the nonce commitments
+    /// of all the parties. The message must be already committed to the transcript.
+    pub fn sign_multikey_share(
+        transcript: &mut Transcript,
+        multikey: &Multikey,
+        commitments: &[NonceCommitment],
+        privkey: Scalar,
+        nonce: SecretNonce,
+    ) -> Scalar {
+        let key = multikey.aggregated_key();
+        let R = NonceCommitment::sum(commitments).compress();
+        let (x, e) = challenges(transcript, &[key], &R);
+        let a = multikey.factor_for_key(&VerificationKey::from_secret(&privkey));
+        nonce.r + e * x[0] * a * privkey
+    }
+
+    /// Verifies the shares of the parties of `multikey`, whose keys are `pubkeys`
+    /// in the order of aggregation, and combines them into the signature for the aggregated key.
+    /// Fails with `VMError::MuSigShareError` if one of the shares is invalid.
+    pub fn aggregate_multikey_shares(
+        transcript: &mut Transcript,
+        multikey: &Multikey,
+        pubkeys: &[VerificationKey],
+        commitments: &[NonceCommitment],
+        shares: &[Scalar],
+    ) -> Result<Signature, VMError> {
+        if shares.len() != pubkeys.len() {
+            return Err(VMError::BadArguments);
+        }
+        let R = nonce_sum(pubkeys, commitments)?;
+        let (x, e) = challenges(transcript, &[multikey.aggregated_key()], &R);
+        for i in 0..pubkeys.len() {
+            let a = multikey.factor_for_key(&pubkeys[i]);
+            verify_share(&pubkeys[i], &commitments[i], e * x[0] * a, &shares[i])?;
+        }
+        Ok(Signature {
+            R,
+            s: shares.iter().fold(Scalar::zero(), |s, s_i| s + s_i),
+        })
+    }
+}
+
+/// Returns the sum of the nonce commitments of all the keys.
+fn nonce_sum(
+    pubkeys: &[VerificationKey],
+    commitments: &[NonceCommitment],
+) -> Result<CompressedRistretto, VMError> {
+    if commitments.len() != pubkeys.len() {
+        return Err(VMError::BadArguments);
+    }
+    Ok(NonceCommitment::sum(commitments).compress())
+}
+
+/// Computes the factors of the keys and the signature challenge,
+/// in the same order as `Signature::verify_aggregated`.
+fn challenges(
+    transcript: &mut Transcript,
+    pubkeys: &[VerificationKey],
+    R: &CompressedRistretto,
+) -> (Vec<Scalar>, Scalar) {
+    let x = key_factors(transcript, pubkeys);
+    transcript.commit_point(b"R", R);
+    let e = transcript.challenge_scalar(b"e");
+    (x, e)
+}
+
+/// Checks the share of a key: `s_i*B == R_i + c_i*X_i`.
+fn verify_share(
+    pubkey: &VerificationKey,
+    commitment: &NonceCommitment,
+    c_i: Scalar,
+    share: &Scalar,
+) -> Result<(), VMError> {
+    let X_i = pubkey.0.decompress().ok_or(VMError::InvalidPoint)?;
+    let expected =
+        RistrettoPoint::vartime_multiscalar_mul(&[c_i, -share], &[X_i, RISTRETTO_BASEPOINT_POINT])
+            + commitment.point();
+    if expected != RistrettoPoint::default() {
+        return Err(VMError::MuSigShareError {
+            pubkey: pubkey.0.to_bytes(),
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shares_of_aggregated_signature() {
+        let privkeys = vec![Scalar::from(1u64), Scalar::from(2u64), Scalar::from(3u64)];
+        let pubkeys = privkeys
+            .iter()
+            .map(VerificationKey::from_secret)
+            .collect::<Vec<_>>();
+        let transcript = Transcript::new(b"partial test");
+        let mut rng = rand::thread_rng();
+
+        let nonces = privkeys
+            .iter()
+            .map(|x| SecretNonce::new(&transcript, x, &mut rng))
+            .collect::<Vec<_>>();
+        let commitments = nonces.iter().map(|n| n.commitment()).collect::<Vec<_>>();
+        let mut shares = privkeys
+            .iter()
+            .zip(nonces.into_iter())
+            .enumerate()
+            .map(|(i, (x, nonce))| {
+                Signature::sign_share(
+                    &mut transcript.clone(),
+                    &pubkeys,
+                    &commitments,
+                    i,
+                    *x,
+                    nonce,
+                )
+                .unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        let signature =
+            Signature::aggregate_shares(&mut transcript.clone(), &pubkeys, &commitments, &shares)
+                .unwrap();
+        assert!(signature
+            .verify_aggregated(&mut transcript.clone(), &pubkeys)
+            .verify()
+            .is_ok());
+
+        // The key must match its index.
+        let nonce = SecretNonce::new(&transcript, &privkeys[0], &mut rng);
+        assert_eq!(
+            Signature::sign_share(
+                &mut transcript.clone(),
+                &pubkeys,
+                &commitments,
+                1,
+                privkeys[0],
+                nonce
+            )
+            .unwrap_err(),
+            VMError::BadArguments
+        );
+
+        // An invalid share is attributed to its key.
+        shares[2] += Scalar::one();
+        assert_eq!(
+            Signature::aggregate_shares(&mut transcript.clone(), &pubkeys, &commitments, &shares)
+                .unwrap_err(),
+            VMError::MuSigShareError {
+                pubkey: pubkeys[2].0.to_bytes()
+            }
+        );
+    }
+
+    #[test]
+    fn shares_of_multikey_signature() {
+        let privkeys = vec![Scalar::from(1u64), Scalar::from(2u64)];
+        let pubkeys = privkeys
+            .iter()
+            .map(VerificationKey::from_secret)
+            .collect::<Vec<_>>();
+        let multikey = Multikey::new(pubkeys.clone()).unwrap();
+        let transcript = Transcript::new(b"partial test");
+        let mut rng = rand::thread_rng();
+
+        let nonces = privkeys
+            .iter()
+            .map(|x| SecretNonce::new(&transcript, x, &mut rng))
+            .collect::<Vec<_>>();
+        let commitments = nonces.iter().map(|n| n.commitment()).collect::<Vec<_>>();
+        let shares = privkeys
+            .iter()
+            .zip(nonces.into_iter())
+            .map(|(x, nonce)| {
+                Signature::sign_multikey_share(
+                    &mut transcript.clone(),
+                    &multikey,
+                    &commitments,
+                    *x,
+                    nonce,
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let signature = Signature::aggregate_multikey_shares(
+            &mut transcript.clone(),
+            &multikey,
+            &pubkeys,
+            &commitments,
+            &shares,
+        )
+        .unwrap();
+        assert!(signature
+            .verify_single(&mut transcript.clone(), multikey.aggregated_key())
+            .verify()
+            .is_ok());
+
+        // Shares are attributed to the keys in the order of aggregation.
+        let mut reversed = shares.clone();
+        reversed.reverse();
+        assert!(Signature::aggregate_multikey_shares(
+            &mut transcript.clone(),
+            &multikey,
+            &pubkeys,
+            &commitments,
+            &reversed,
+        )
+        .is_err());
+    }
+}
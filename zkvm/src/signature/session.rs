@@ -0,0 +1,219 @@
+use super::counterparty::{NonceCommitment, NoncePrecommitment};
+use super::multikey::Multikey;
+use super::musig::Signature;
+use super::signer::*;
+use super::VerificationKey;
+use crate::errors::VMError;
+use core::mem;
+use curve25519_dalek::scalar::Scalar;
+use merlin::Transcript;
+use rand::{CryptoRng, RngCore};
+
+/// MuSig signing session of a single party.
+///
+/// Unlike the `Party` states, the session can be stored between the network rounds
+/// as a single object, checks that the messages from all the counterparties are received,
+/// and reports the misbehaving counterparty by its public key.
+pub struct Session<'t> {
+    state: SessionState<'t>,
+    pubkeys: Vec<VerificationKey>,
+}
+
+enum SessionState<'t> {
+    AwaitingPrecommitments(PartyAwaitingPrecommitments<'t>),
+    AwaitingCommitments(PartyAwaitingCommitments<'t>),
+    AwaitingShares(PartyAwaitingShares),
+    // The session has completed or failed.
+    Finished,
+}
+
+impl<'t> Session<'t> {
+    /// Starts a signing session for a party with the secret key `x_i` among the signers
+    /// with the given public keys (including the party's own key).
+    /// The message must be already committed to the transcript.
+    /// Returns the session and the nonce precommitment to send to the counterparties.
+    pub fn new(
+        transcript: &'t mut Transcript,
+        x_i: Scalar,
+        pubkeys: Vec<VerificationKey>,
+    ) -> Result<(Self, NoncePrecommitment), VMError> {
+        Session::new_with_rng(transcript, x_i, pubkeys, &mut rand::thread_rng())
+    }
+
+    /// Starts a signing session using the provided RNG
+    /// as an additional source of entropy for the nonce.
+    pub fn new_with_rng<R: RngCore + CryptoRng>(
+        transcript: &'t mut Transcript,
+        x_i: Scalar,
+        pubkeys: Vec<VerificationKey>,
+        rng: &mut R,
+    ) -> Result<(Self, NoncePrecommitment), VMError> {
+        let multikey = Multikey::new(pubkeys.clone())?;
+        let (party, precommitment) =
+            Party::new_with_rng(transcript, x_i, multikey, pubkeys.clone(), rng);
+        Ok((
+            Session {
+                state: SessionState::AwaitingPrecommitments(party),
+                pubkeys,
+            },
+            precommitment,
+        ))
+    }
+
+    /// Receives the nonce precommitments of all the parties (in the order of their public keys)
+    /// and reveals the party's nonce commitment.
+    pub fn reveal_nonce(
+        &mut self,
+        precommitments: Vec<NoncePrecommitment>,
+    ) -> Result<NonceCommitment, VMError> {
+        self.check_count(precommitments.len())?;
+        match self.take_state() {
+            SessionState::AwaitingPrecommitments(party) => {
+                let (party, commitment) = party.receive_precommitments(precommitments);
+                self.state = SessionState::AwaitingCommitments(party);
+                Ok(commitment)
+            }
+            state => self.wrong_state(state),
+        }
+    }
+
+    /// Receives the nonce commitments of all the parties and creates the party's signature share.
+    /// Fails with `VMError::MuSigShareError` if a commitment does not match its precommitment.
+    pub fn sign(&mut self, commitments: Vec<NonceCommitment>) -> Result<Scalar, VMError> {
+        self.check_count(commitments.len())?;
+        match self.take_state() {
+            SessionState::AwaitingCommitments(party) => {
+                let (party, share) = party.receive_commitments(commitments)?;
+                self.state = SessionState::AwaitingShares(party);
+                Ok(share)
+            }
+            state => self.wrong_state(state),
+        }
+    }
+
+    /// Receives the signature shares of all the parties and aggregates them into a signature.
+    /// Fails with `VMError::MuSigShareError` if one of the shares is invalid.
+    pub fn aggregate(&mut self, shares: Vec<Scalar>) -> Result<Signature, VMError> {
+        self.check_count(shares.len())?;
+        match self.take_state() {
+            SessionState::AwaitingShares(party) => party.receive_shares(shares),
+            state => self.wrong_state(state),
+        }
+    }
+
+    /// Moves the state out of the session, leaving it finished.
+    /// The session remains finished if the transition fails.
+    fn take_state(&mut self) -> SessionState<'t> {
+        mem::replace(&mut self.state, SessionState::Finished)
+    }
+
+    fn wrong_state<T>(&mut self, state: SessionState<'t>) -> Result<T, VMError> {
+        self.state = state;
+        Err(VMError::MuSigSessionError)
+    }
+
+    fn check_count(&self, n: usize) -> Result<(), VMError> {
+        if n != self.pubkeys.len() {
+            return Err(VMError::BadArguments);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use curve25519_dalek::constants::RISTRETTO_BASEPOINT_POINT;
+
+    fn pubkeys(privkeys: &[Scalar]) -> Vec<VerificationKey> {
+        privkeys
+            .iter()
+            .map(|x| VerificationKey((x * RISTRETTO_BASEPOINT_POINT).compress()))
+            .collect()
+    }
+
+    #[test]
+    fn session_signature() {
+        let privkeys = vec![Scalar::from(1u64), Scalar::from(2u64), Scalar::from(3u64)];
+        let pubkeys = pubkeys(&privkeys);
+        let mut transcripts: Vec<_> = privkeys
+            .iter()
+            .map(|_| Transcript::new(b"session test"))
+            .collect();
+
+        let (mut sessions, precomms): (Vec<_>, Vec<_>) = privkeys
+            .iter()
+            .zip(transcripts.iter_mut())
+            .map(|(x, t)| Session::new(t, *x, pubkeys.clone()).unwrap())
+            .unzip();
+
+        // Messages from all the parties are required.
+        assert_eq!(
+            sessions[0].reveal_nonce(vec![precomms[0]]).unwrap_err(),
+            VMError::BadArguments
+        );
+
+        let comms: Vec<_> = sessions
+            .iter_mut()
+            .map(|s| s.reveal_nonce(precomms.clone()).unwrap())
+            .collect();
+
+        // Out-of-order calls are rejected without breaking the session.
+        assert_eq!(
+            sessions[0].reveal_nonce(precomms.clone()).unwrap_err(),
+            VMError::MuSigSessionError
+        );
+        let shares: Vec<_> = sessions
+            .iter_mut()
+            .map(|s| s.sign(comms.clone()).unwrap())
+            .collect();
+        let signature = sessions[0].aggregate(shares.clone()).unwrap();
+
+        let multikey = Multikey::new(pubkeys).unwrap();
+        assert!(signature
+            .verify(
+                &mut Transcript::new(b"session test"),
+                multikey.aggregated_key()
+            )
+            .is_ok());
+
+        // Session cannot be reused after completion.
+        assert_eq!(
+            sessions[0].aggregate(shares).unwrap_err(),
+            VMError::MuSigSessionError
+        );
+    }
+
+    #[test]
+    fn detects_misbehaving_party() {
+        let privkeys = vec![Scalar::from(1u64), Scalar::from(2u64)];
+        let pubkeys = pubkeys(&privkeys);
+        let mut transcripts: Vec<_> = privkeys
+            .iter()
+            .map(|_| Transcript::new(b"session test"))
+            .collect();
+
+        let (mut sessions, precomms): (Vec<_>, Vec<_>) = privkeys
+            .iter()
+            .zip(transcripts.iter_mut())
+            .map(|(x, t)| Session::new(t, *x, pubkeys.clone()).unwrap())
+            .unzip();
+        let comms: Vec<_> = sessions
+            .iter_mut()
+            .map(|s| s.reveal_nonce(precomms.clone()).unwrap())
+            .collect();
+        let mut shares: Vec<_> = sessions
+            .iter_mut()
+            .map(|s| s.sign(comms.clone()).unwrap())
+            .collect();
+
+        // Second party sends an inconsistent share.
+        shares[1] += Scalar::one();
+        assert_eq!(
+            sessions[0].aggregate(shares).unwrap_err(),
+            VMError::MuSigShareError {
+                pubkey: pubkeys[1].0.to_bytes()
+            }
+        );
+    }
+}
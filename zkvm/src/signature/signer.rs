@@ -3,22 +3,24 @@ use super::multikey::Multikey;
 use super::musig::Signature;
 use super::VerificationKey;
 use crate::errors::VMError;
+use crate::secret::SecretScalar;
 use crate::transcript::TranscriptProtocol;
 use curve25519_dalek::constants::RISTRETTO_BASEPOINT_POINT;
 use curve25519_dalek::ristretto::RistrettoPoint;
 use curve25519_dalek::scalar::Scalar;
 use merlin::Transcript;
-use rand;
+use rand::{self, CryptoRng, RngCore};
 
 /// Entry point to multi-party signing protocol.
+/// The private key and the nonce of the party are zeroized when its state is dropped.
 pub struct Party {}
 
 /// State of the party when awaiting nonce precommitments from other parties.
 pub struct PartyAwaitingPrecommitments<'t> {
     transcript: &'t mut Transcript,
     multikey: Multikey,
-    x_i: Scalar,
-    r_i: Scalar,
+    x_i: SecretScalar,
+    r_i: SecretScalar,
     R_i: NonceCommitment,
     counterparties: Vec<Counterparty>,
 }
@@ -27,8 +29,8 @@ pub struct PartyAwaitingPrecommitments<'t> {
 pub struct PartyAwaitingCommitments<'t> {
     transcript: &'t mut Transcript,
     multikey: Multikey,
-    x_i: Scalar,
-    r_i: Scalar,
+    x_i: SecretScalar,
+    r_i: SecretScalar,
     counterparties: Vec<CounterpartyPrecommitted>,
 }
 
@@ -49,11 +51,23 @@ impl Party {
         multikey: Multikey,
         // TBD: move this inside Multikey API to avoid such redundancy.
         pubkeys: Vec<VerificationKey>,
+    ) -> (PartyAwaitingPrecommitments<'t>, NoncePrecommitment) {
+        Party::new_with_rng(transcript, x_i, multikey, pubkeys, &mut rand::thread_rng())
+    }
+
+    /// Create new signing party for a given transcript using the provided RNG
+    /// as an additional source of entropy for the nonce.
+    pub fn new_with_rng<'t, R: RngCore + CryptoRng>(
+        transcript: &'t mut Transcript,
+        x_i: Scalar,
+        multikey: Multikey,
+        pubkeys: Vec<VerificationKey>,
+        rng: &mut R,
     ) -> (PartyAwaitingPrecommitments<'t>, NoncePrecommitment) {
         let mut rng = transcript
             .build_rng()
             .commit_witness_bytes(b"x_i", &x_i.to_bytes())
-            .finalize(&mut rand::thread_rng());
+            .finalize(rng);
 
         // Generate ephemeral keypair (r_i, R_i). r_i is a random nonce.
         let r_i = Scalar::random(&mut rng);
@@ -71,8 +85,8 @@ impl Party {
             PartyAwaitingPrecommitments {
                 transcript,
                 multikey,
-                x_i,
-                r_i,
+                x_i: SecretScalar::new(x_i),
+                r_i: SecretScalar::new(r_i),
                 R_i,
                 counterparties,
             },
@@ -135,11 +149,11 @@ impl<'t> PartyAwaitingCommitments<'t> {
         };
 
         // Make a_i = H(L, X_i)
-        let X_i = VerificationKey((self.x_i * RISTRETTO_BASEPOINT_POINT).compress());
+        let X_i = VerificationKey((*self.x_i * RISTRETTO_BASEPOINT_POINT).compress());
         let a_i = self.multikey.factor_for_key(&X_i);
 
         // Generate share: s_i = r_i + c * a_i * x_i
-        let s_i = self.r_i + c * a_i * self.x_i;
+        let s_i = *self.r_i + c * a_i * *self.x_i;
 
         // Store received nonce commitments in next state
         Ok((
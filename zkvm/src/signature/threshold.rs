@@ -0,0 +1,495 @@
+//! Threshold (t-of-n) Schnorr signatures.
+//!
+//! Each of `n` parties deals shares of its own random secret using Feldman's
+//! verifiable secret sharing, so no single dealer knows the group secret.
+//! Dealers prove knowledge of their secrets, so none of them can choose its commitment
+//! as a function of the others' ones to control the group key.
+//! The group key is the sum of the parties' secret commitments, and each party's
+//! secret share is the sum of the shares it received.
+//!
+//! Any `t` parties can then produce a regular `Signature` for the group key,
+//! which does not reveal which of the parties participated.
+
+use super::counterparty::{NonceCommitment, NoncePrecommitment};
+use super::{key_factors, Signature, VerificationKey};
+use crate::errors::VMError;
+use crate::transcript::TranscriptProtocol;
+use core::mem;
+use curve25519_dalek::constants::RISTRETTO_BASEPOINT_POINT;
+use curve25519_dalek::ristretto::{CompressedRistretto, RistrettoPoint};
+use curve25519_dalek::scalar::Scalar;
+use curve25519_dalek::traits::Identity;
+use merlin::Transcript;
+use rand::{CryptoRng, RngCore};
+use subtle::ConstantTimeEq;
+use zeroize::Zeroize;
+
+/// Commitments to the coefficients of a party's secret polynomial,
+/// published so the other parties can verify their shares,
+/// with the proof of knowledge of the secret.
+#[derive(Clone, Debug)]
+pub struct SharingCommitment {
+    coefficients: Vec<RistrettoPoint>,
+    // Schnorr signature with the secret a_0 proving its knowledge
+    proof: Signature,
+}
+
+/// Party's share of the group secret and the public data of the group.
+/// The secret share is zeroized when dropped.
+#[derive(Clone)]
+pub struct ThresholdKey {
+    index: u64,
+    threshold: usize,
+    secret_share: Scalar,
+    group_key: VerificationKey,
+    // verification shares X_j = x_j * G for j = 1..n
+    verification_shares: Vec<RistrettoPoint>,
+}
+
+/// Signing session of one of the participating parties.
+pub struct ThresholdSession<'t> {
+    transcript: &'t mut Transcript,
+    key: ThresholdKey,
+    signers: Vec<u64>,
+    r_i: Scalar,
+    R_i: NonceCommitment,
+    state: ThresholdState,
+}
+
+enum ThresholdState {
+    AwaitingPrecommitments,
+    AwaitingCommitments(Vec<NoncePrecommitment>),
+    AwaitingShares {
+        commitments: Vec<NonceCommitment>,
+        R: RistrettoPoint,
+        e: Scalar,
+    },
+    // The session has completed or failed.
+    Finished,
+}
+
+/// Creates shares of a random secret for `n` parties, `t` of which can sign together,
+/// on behalf of the dealer with a given index (starting with 1).
+/// Returns the commitment to publish and the shares for parties with indices `1..=n`
+/// (`shares[j-1]` must be sent privately to party `j`).
+pub fn deal_shares<R: RngCore + CryptoRng>(
+    dealer: u64,
+    t: usize,
+    n: usize,
+    rng: &mut R,
+) -> Result<(SharingCommitment, Vec<Scalar>), VMError> {
+    if t == 0 || t > n || dealer == 0 || dealer > n as u64 {
+        return Err(VMError::BadArguments);
+    }
+    let mut coefficients: Vec<Scalar> = (0..t).map(|_| Scalar::random(rng)).collect();
+    let points: Vec<RistrettoPoint> = coefficients
+        .iter()
+        .map(|a| a * RISTRETTO_BASEPOINT_POINT)
+        .collect();
+    let proof = Signature::sign_single_with_rng(
+        &mut SharingCommitment::proof_transcript(dealer, &points),
+        coefficients[0],
+        rng,
+    );
+    let commitment = SharingCommitment {
+        coefficients: points,
+        proof,
+    };
+    let shares = (1..=n as u64)
+        .map(|j| {
+            // Horner's scheme: f(j) = a_0 + j*(a_1 + j*(a_2 + ...))
+            let j = Scalar::from(j);
+            coefficients
+                .iter()
+                .rev()
+                .fold(Scalar::zero(), |acc, a| acc * j + a)
+        })
+        .collect();
+    // The coefficients (including the secret itself) are no longer needed.
+    for a in coefficients.iter_mut() {
+        a.zeroize();
+    }
+    Ok((commitment, shares))
+}
+
+impl SharingCommitment {
+    /// Returns the public share for the party with a given index: `f(index)*G`.
+    fn evaluate(&self, index: u64) -> RistrettoPoint {
+        let j = Scalar::from(index);
+        self.coefficients
+            .iter()
+            .rev()
+            .fold(RistrettoPoint::identity(), |acc, c| acc * j + c)
+    }
+
+    /// Checks the dealer's proof of knowledge of the secret committed with `a_0*G`.
+    /// The proof is bound to the dealer's index and all the coefficient commitments,
+    /// so it cannot be replayed by another dealer.
+    pub fn verify_proof(&self, dealer: u64) -> Result<(), VMError> {
+        let key = match self.coefficients.first() {
+            Some(a_0) => VerificationKey(a_0.compress()),
+            None => return Err(VMError::BadArguments),
+        };
+        self.proof
+            .verify_single(
+                &mut SharingCommitment::proof_transcript(dealer, &self.coefficients),
+                key,
+            )
+            .verify()
+            .map_err(|_| VMError::DKGProofError { dealer })
+    }
+
+    /// Checks that the share sent by the dealer to the party with a given index
+    /// matches the commitment.
+    pub fn verify_share(&self, dealer: u64, index: u64, share: &Scalar) -> Result<(), VMError> {
+        if share * RISTRETTO_BASEPOINT_POINT == self.evaluate(index) {
+            Ok(())
+        } else {
+            Err(VMError::DKGShareError { dealer })
+        }
+    }
+
+    /// Transcript of the proof of knowledge: commits LE64 `dealer` as `dealer`,
+    /// LE64 number of coefficients as `t` and each coefficient commitment as `A`.
+    fn proof_transcript(dealer: u64, coefficients: &[RistrettoPoint]) -> Transcript {
+        let mut transcript = Transcript::new(b"ZkVM.threshold-dkg");
+        transcript.commit_u64(b"dealer", dealer);
+        transcript.commit_u64(b"t", coefficients.len() as u64);
+        for a in coefficients.iter() {
+            transcript.commit_point(b"A", &a.compress());
+        }
+        transcript
+    }
+}
+
+impl ThresholdKey {
+    /// Combines the shares received by the party with a given index (starting with 1)
+    /// from all `n` parties into the party's key. `commitments[i]` and `shares[i]`
+    /// must be received from the party with index `i+1`.
+    /// Fails with `VMError::DKGProofError` naming the dealer whose proof of knowledge
+    /// of its secret is invalid, and with `VMError::DKGShareError` naming the dealer
+    /// whose share does not match its commitment.
+    pub fn combine(
+        index: u64,
+        threshold: usize,
+        commitments: &[SharingCommitment],
+        shares: &[Scalar],
+    ) -> Result<Self, VMError> {
+        let n = commitments.len();
+        if n != shares.len() || index == 0 || index > n as u64 {
+            return Err(VMError::BadArguments);
+        }
+        for (i, (c, share)) in commitments.iter().zip(shares.iter()).enumerate() {
+            if c.coefficients.len() != threshold {
+                return Err(VMError::BadArguments);
+            }
+            let dealer = i as u64 + 1;
+            c.verify_proof(dealer)?;
+            c.verify_share(dealer, index, share)?;
+        }
+        let group_key: RistrettoPoint = commitments.iter().map(|c| c.coefficients[0]).sum();
+        let verification_shares = (1..=n as u64)
+            .map(|j| commitments.iter().map(|c| c.evaluate(j)).sum())
+            .collect();
+        Ok(ThresholdKey {
+            index,
+            threshold,
+            secret_share: shares.iter().sum(),
+            group_key: VerificationKey(group_key.compress()),
+            verification_shares,
+        })
+    }
+
+    /// Returns the group verification key.
+    pub fn group_key(&self) -> VerificationKey {
+        self.group_key
+    }
+
+    /// Returns the party's index.
+    pub fn index(&self) -> u64 {
+        self.index
+    }
+
+    /// Returns the verification key of the party's secret share.
+    pub fn verification_share(&self, index: u64) -> Option<VerificationKey> {
+        self.verification_shares
+            .get((index as usize).wrapping_sub(1))
+            .map(|p| VerificationKey(p.compress()))
+    }
+}
+
+impl Zeroize for ThresholdKey {
+    fn zeroize(&mut self) {
+        self.secret_share.zeroize();
+    }
+}
+
+impl Drop for ThresholdKey {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
+impl<'t> ThresholdSession<'t> {
+    /// Starts a signing session for a party among the `signers` (party indices,
+    /// at least `threshold` of them). The message must be already committed to the transcript.
+    /// Returns the session and the nonce precommitment to send to the other signers.
+    pub fn new<R: RngCore + CryptoRng>(
+        transcript: &'t mut Transcript,
+        key: ThresholdKey,
+        signers: Vec<u64>,
+        rng: &mut R,
+    ) -> Result<(Self, NoncePrecommitment), VMError> {
+        let n = key.verification_shares.len() as u64;
+        let mut sorted = signers.clone();
+        sorted.sort();
+        sorted.dedup();
+        if sorted.len() != signers.len()
+            || signers.len() < key.threshold
+            || signers.iter().any(|j| *j == 0 || *j > n)
+            || !signers.contains(&key.index)
+        {
+            return Err(VMError::BadArguments);
+        }
+
+        let mut rng = transcript
+            .build_rng()
+            .commit_witness_bytes(b"x_i", key.secret_share.as_bytes())
+            .finalize(rng);
+        let r_i = Scalar::random(&mut rng);
+        let R_i = NonceCommitment::new(r_i * RISTRETTO_BASEPOINT_POINT);
+        let precommitment = R_i.precommit();
+        Ok((
+            ThresholdSession {
+                transcript,
+                key,
+                signers,
+                r_i,
+                R_i,
+                state: ThresholdState::AwaitingPrecommitments,
+            },
+            precommitment,
+        ))
+    }
+
+    /// Receives the nonce precommitments of the signers (in the order of `signers`)
+    /// and reveals the party's nonce commitment.
+    pub fn reveal_nonce(
+        &mut self,
+        precommitments: Vec<NoncePrecommitment>,
+    ) -> Result<NonceCommitment, VMError> {
+        self.check_count(precommitments.len())?;
+        match mem::replace(&mut self.state, ThresholdState::Finished) {
+            ThresholdState::AwaitingPrecommitments => {
+                self.state = ThresholdState::AwaitingCommitments(precommitments);
+                Ok(self.R_i)
+            }
+            state => self.wrong_state(state),
+        }
+    }
+
+    /// Receives the nonce commitments of the signers and creates the party's signature share.
+    /// Fails with `VMError::MuSigShareError` if a commitment does not match its precommitment.
+    pub fn sign(&mut self, commitments: Vec<NonceCommitment>) -> Result<Scalar, VMError> {
+        self.check_count(commitments.len())?;
+        let precommitments = match mem::replace(&mut self.state, ThresholdState::Finished) {
+            ThresholdState::AwaitingCommitments(p) => p,
+            state => return self.wrong_state(state),
+        };
+        for (i, (commitment, precommitment)) in
+            commitments.iter().zip(precommitments.iter()).enumerate()
+        {
+            if commitment
+                .precommit()
+                .to_bytes()
+                .ct_eq(&precommitment.to_bytes())
+                .unwrap_u8()
+                == 0
+            {
+                return Err(self.misbehavior(i));
+            }
+        }
+
+        // Challenge for a single-key signature, see `Signature::verify_single`.
+        let R = NonceCommitment::sum(&commitments);
+        let x = key_factors(self.transcript, &[self.key.group_key])[0];
+        self.transcript.commit_point(b"R", &R.compress());
+        let e = self.transcript.challenge_scalar(b"e") * x;
+
+        let lambda = self.lagrange_coefficient(self.key.index);
+        let share = self.r_i + e * lambda * self.key.secret_share;
+        self.state = ThresholdState::AwaitingShares { commitments, R, e };
+        Ok(share)
+    }
+
+    /// Receives the signature shares of the signers and aggregates them into a signature
+    /// for the group key.
+    /// Fails with `VMError::MuSigShareError` if one of the shares is invalid.
+    pub fn aggregate(&mut self, shares: Vec<Scalar>) -> Result<Signature, VMError> {
+        self.check_count(shares.len())?;
+        let (commitments, R, e) = match mem::replace(&mut self.state, ThresholdState::Finished) {
+            ThresholdState::AwaitingShares { commitments, R, e } => (commitments, R, e),
+            state => return self.wrong_state(state),
+        };
+        for (i, (share, commitment)) in shares.iter().zip(commitments.iter()).enumerate() {
+            let j = self.signers[i];
+            let X_j = self.key.verification_shares[(j - 1) as usize];
+            let lambda = self.lagrange_coefficient(j);
+            // Check s_j*G == R_j + e*λ_j*X_j
+            if share * RISTRETTO_BASEPOINT_POINT != commitment.point() + e * lambda * X_j {
+                return Err(self.misbehavior(i));
+            }
+        }
+        Ok(Signature {
+            R: R.compress(),
+            s: shares.iter().sum(),
+        })
+    }
+
+    /// Lagrange coefficient for interpolating the secret at zero from the signers' shares.
+    fn lagrange_coefficient(&self, j: u64) -> Scalar {
+        let x_j = Scalar::from(j);
+        let (num, den) = self.signers.iter().filter(|m| **m != j).fold(
+            (Scalar::one(), Scalar::one()),
+            |(num, den), m| {
+                let x_m = Scalar::from(*m);
+                (num * x_m, den * (x_m - x_j))
+            },
+        );
+        num * den.invert()
+    }
+
+    fn misbehavior(&self, i: usize) -> VMError {
+        let j = self.signers[i];
+        let X_j: CompressedRistretto = self.key.verification_shares[(j - 1) as usize].compress();
+        VMError::MuSigShareError {
+            pubkey: X_j.to_bytes(),
+        }
+    }
+
+    fn wrong_state<T>(&mut self, state: ThresholdState) -> Result<T, VMError> {
+        self.state = state;
+        Err(VMError::MuSigSessionError)
+    }
+
+    fn check_count(&self, n: usize) -> Result<(), VMError> {
+        if n != self.signers.len() {
+            return Err(VMError::BadArguments);
+        }
+        Ok(())
+    }
+}
+
+impl<'t> Drop for ThresholdSession<'t> {
+    /// Zeroizes the nonce (the key zeroizes its secret share).
+    fn drop(&mut self) {
+        self.r_i.zeroize();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    /// Runs the key generation for `n` parties with threshold `t`.
+    fn keygen(t: usize, n: usize, rng: &mut StdRng) -> Vec<ThresholdKey> {
+        let (commitments, shares): (Vec<_>, Vec<_>) = (1..=n as u64)
+            .map(|dealer| deal_shares(dealer, t, n, rng).unwrap())
+            .unzip();
+        (1..=n)
+            .map(|j| {
+                let received: Vec<Scalar> = shares.iter().map(|s| s[j - 1]).collect();
+                ThresholdKey::combine(j as u64, t, &commitments, &received).unwrap()
+            })
+            .collect()
+    }
+
+    fn sign(keys: &[ThresholdKey], signers: Vec<u64>, rng: &mut StdRng) -> Signature {
+        let mut transcripts: Vec<_> = signers
+            .iter()
+            .map(|_| Transcript::new(b"threshold test"))
+            .collect();
+        let (mut sessions, precomms): (Vec<_>, Vec<_>) = signers
+            .iter()
+            .zip(transcripts.iter_mut())
+            .map(|(j, t)| {
+                let key = keys[(*j - 1) as usize].clone();
+                ThresholdSession::new(t, key, signers.clone(), rng).unwrap()
+            })
+            .unzip();
+        let comms: Vec<_> = sessions
+            .iter_mut()
+            .map(|s| s.reveal_nonce(precomms.clone()).unwrap())
+            .collect();
+        let shares: Vec<_> = sessions
+            .iter_mut()
+            .map(|s| s.sign(comms.clone()).unwrap())
+            .collect();
+        sessions[0].aggregate(shares).unwrap()
+    }
+
+    #[test]
+    fn two_of_three() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let keys = keygen(2, 3, &mut rng);
+        let group_key = keys[0].group_key();
+        assert!(keys.iter().all(|k| k.group_key() == group_key));
+
+        for signers in vec![vec![1, 2], vec![3, 1], vec![1, 2, 3]] {
+            let sig = sign(&keys, signers, &mut rng);
+            assert!(sig
+                .verify_single(&mut Transcript::new(b"threshold test"), group_key)
+                .verify()
+                .is_ok());
+        }
+    }
+
+    #[test]
+    fn rejects_insufficient_signers() {
+        let mut rng = StdRng::seed_from_u64(2);
+        let keys = keygen(2, 3, &mut rng);
+        let mut t = Transcript::new(b"threshold test");
+        assert!(ThresholdSession::new(&mut t, keys[0].clone(), vec![1], &mut rng).is_err());
+        assert!(ThresholdSession::new(&mut t, keys[0].clone(), vec![2, 3], &mut rng).is_err());
+        assert!(ThresholdSession::new(&mut t, keys[0].clone(), vec![1, 1], &mut rng).is_err());
+    }
+
+    #[test]
+    fn detects_invalid_shares() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let (c1, s1) = deal_shares(1, 2, 2, &mut rng).unwrap();
+        let (c2, mut s2) = deal_shares(2, 2, 2, &mut rng).unwrap();
+        s2[0] += Scalar::one();
+        let commitments = vec![c1, c2];
+        assert_eq!(
+            ThresholdKey::combine(1, 2, &commitments, &[s1[0], s2[0]]).err(),
+            Some(VMError::DKGShareError { dealer: 2 })
+        );
+        assert!(ThresholdKey::combine(2, 2, &commitments, &[s1[1], s2[1]]).is_ok());
+    }
+
+    #[test]
+    fn detects_rogue_dealers() {
+        let mut rng = StdRng::seed_from_u64(4);
+        let (c1, s1) = deal_shares(1, 2, 2, &mut rng).unwrap();
+        let (c2, s2) = deal_shares(2, 2, 2, &mut rng).unwrap();
+
+        // A dealer cannot cancel the other's secret without knowing its own one.
+        let mut rogue = c2.clone();
+        rogue.coefficients[0] -= c1.coefficients[0];
+        let commitments = vec![c1.clone(), rogue];
+        assert_eq!(
+            ThresholdKey::combine(1, 2, &commitments, &[s1[0], s2[0]]).err(),
+            Some(VMError::DKGProofError { dealer: 2 })
+        );
+
+        // Proofs are bound to the dealer's index.
+        let commitments = vec![c2, c1];
+        assert_eq!(
+            ThresholdKey::combine(1, 2, &commitments, &[s2[0], s1[0]]).err(),
+            Some(VMError::DKGProofError { dealer: 1 })
+        );
+    }
+}
@@ -0,0 +1,256 @@
+//! Detached signing requests for constrained signers, such as hardware wallets.
+//!
+//! The request carries only what the device needs to show and sign the transaction:
+//! the log with the outputs replaced by their IDs, which is enough to recompute
+//! the transaction ID, the outputs with the openings of their values, and the `signtx` keys.
+//! The device does not run the VM: it checks the outputs against the log,
+//! shows the summary to the user and signs the transaction ID it computed itself.
+
+use curve25519_dalek::scalar::Scalar;
+use merlin::Transcript;
+use rand::{CryptoRng, RngCore};
+
+use crate::constraints::Commitment;
+use crate::contract::{ContractID, Output};
+use crate::encoding::{self, SliceReader};
+use crate::errors::VMError;
+use crate::merkle::{MerkleItem, MerkleTree};
+use crate::scalar_witness::ScalarWitness;
+use crate::signature::{Signature, VerificationKey};
+use crate::transcript::{labels, transcript_for_tx, TranscriptProtocol};
+use crate::txlog::{Entry, TxID};
+use crate::vm::TxHeader;
+
+/// Entry of the transaction log with the output replaced by its ID.
+#[derive(Clone, Debug)]
+pub enum CompactEntry {
+    /// Any entry other than an output.
+    Entry(Entry),
+    /// ID of the output.
+    Output(ContractID),
+}
+
+/// Cleartext quantity and flavor of a value with the blinding factors of their commitments.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ValueOpening {
+    /// Quantity of the value.
+    pub qty: u64,
+    /// Blinding factor of the quantity commitment.
+    pub qty_blinding: Scalar,
+    /// Flavor of the value.
+    pub flv: Scalar,
+    /// Blinding factor of the flavor commitment.
+    pub flv_blinding: Scalar,
+}
+
+/// Output with the openings of the values in its payload.
+#[derive(Clone, Debug)]
+pub struct OutputOpening {
+    /// The output.
+    pub output: Output,
+    /// Openings of the values, in the order of the payload.
+    pub values: Vec<ValueOpening>,
+}
+
+/// Request to sign a transaction, transported to the signing device.
+#[derive(Clone, Debug)]
+pub struct SigningRequest {
+    /// Log of the transaction with the outputs replaced by their IDs.
+    pub log: Vec<CompactEntry>,
+    /// All outputs of the transaction with the openings of their values, in the order of the log.
+    pub outputs: Vec<OutputOpening>,
+    /// Keys that must sign the transaction, in the order of the `signtx` instructions.
+    pub keys: Vec<VerificationKey>,
+}
+
+/// Summary of the transaction checked by the signing device, to be shown to the user.
+#[derive(Clone, Debug)]
+pub struct SigningSummary {
+    /// ID of the transaction computed from the log.
+    pub txid: TxID,
+    /// Header of the transaction.
+    pub header: TxHeader,
+    /// IDs of the spent contracts.
+    pub inputs: Vec<ContractID>,
+    /// Outputs with the cleartext values.
+    pub outputs: Vec<OutputOpening>,
+    /// Total fee.
+    pub fee: u64,
+}
+
+impl From<&Entry> for CompactEntry {
+    fn from(entry: &Entry) -> Self {
+        match entry {
+            Entry::Output(output) => CompactEntry::Output(output.id()),
+            entry => CompactEntry::Entry(entry.clone()),
+        }
+    }
+}
+
+impl MerkleItem for CompactEntry {
+    fn commit(&self, t: &mut Transcript) {
+        match self {
+            CompactEntry::Entry(entry) => entry.commit(t),
+            CompactEntry::Output(id) => t.commit_bytes(b"output", id.as_bytes()),
+        }
+    }
+}
+
+impl SigningRequest {
+    /// Computes the ID of the transaction from the log.
+    pub fn txid(&self) -> TxID {
+        TxID(MerkleTree::root(labels::TXID, &self.log))
+    }
+
+    /// Checks the outputs against the log and returns the summary of the transaction.
+    /// Fails if any output is missing or does not open to the given values.
+    pub fn verify(&self) -> Result<SigningSummary, VMError> {
+        let mut header = None;
+        let mut inputs = Vec::new();
+        let mut output_ids = Vec::new();
+        let mut fee = 0u64;
+        for entry in self.log.iter() {
+            match entry {
+                CompactEntry::Entry(Entry::Header(h)) => header = Some(*h),
+                CompactEntry::Entry(Entry::Input(id)) => inputs.push(*id),
+                CompactEntry::Entry(Entry::Fee(qty)) => {
+                    fee = fee.checked_add(*qty).ok_or(VMError::FeeTooHigh)?
+                }
+                CompactEntry::Entry(Entry::Output(output)) => output_ids.push(output.id()),
+                CompactEntry::Output(id) => output_ids.push(*id),
+                _ => {}
+            }
+        }
+        let header = header.ok_or(VMError::InvalidSigningRequest)?;
+
+        if output_ids.len() != self.outputs.len() {
+            return Err(VMError::InvalidSigningRequest);
+        }
+        let mut openings = Vec::new();
+        for (id, opening) in output_ids.iter().zip(self.outputs.iter()) {
+            if opening.output.id() != *id {
+                return Err(VMError::InvalidSigningRequest);
+            }
+            let values = opening.output.contract().values().collect::<Vec<_>>();
+            if values.len() != opening.values.len() {
+                return Err(VMError::InvalidSigningRequest);
+            }
+            for (value, v) in values.into_iter().zip(opening.values.iter()) {
+                openings.push((
+                    value.qty.clone(),
+                    ScalarWitness::from(v.qty),
+                    v.qty_blinding,
+                ));
+                openings.push((
+                    value.flv.clone(),
+                    ScalarWitness::from(v.flv),
+                    v.flv_blinding,
+                ));
+            }
+        }
+        if !Commitment::verify_openings(&openings) {
+            return Err(VMError::InvalidSigningRequest);
+        }
+
+        Ok(SigningSummary {
+            txid: self.txid(),
+            header,
+            inputs,
+            outputs: self.outputs.clone(),
+            fee,
+        })
+    }
+
+    /// Signs the transaction with the secret keys for all the `signtx` keys, in their order.
+    /// The transaction ID is computed from the log, so the device signs only what it has checked.
+    pub fn sign<R: RngCore + CryptoRng>(
+        &self,
+        privkeys: &[Scalar],
+        rng: &mut R,
+    ) -> Result<Signature, VMError> {
+        if privkeys.len() != self.keys.len()
+            || privkeys
+                .iter()
+                .zip(self.keys.iter())
+                .any(|(privkey, key)| VerificationKey::from_secret(privkey) != *key)
+        {
+            return Err(VMError::BadArguments);
+        }
+        let mut t = transcript_for_tx(&self.txid());
+        Ok(Signature::sign_aggregated_with_rng(&mut t, privkeys, rng))
+    }
+
+    /// Serializes the request.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        encoding::write_size(self.log.len(), &mut buf);
+        for entry in self.log.iter() {
+            match entry {
+                CompactEntry::Entry(entry) => {
+                    encoding::write_u8(0, &mut buf);
+                    entry.encode(&mut buf);
+                }
+                CompactEntry::Output(id) => {
+                    encoding::write_u8(1, &mut buf);
+                    encoding::write_bytes(id.as_bytes(), &mut buf);
+                }
+            }
+        }
+
+        encoding::write_size(self.outputs.len(), &mut buf);
+        for opening in self.outputs.iter() {
+            encoding::write_size(opening.output.serialized_length(), &mut buf);
+            opening.output.encode(&mut buf);
+            encoding::write_size(opening.values.len(), &mut buf);
+            for v in opening.values.iter() {
+                encoding::write_u64(v.qty, &mut buf);
+                encoding::write_bytes(v.qty_blinding.as_bytes(), &mut buf);
+                encoding::write_bytes(v.flv.as_bytes(), &mut buf);
+                encoding::write_bytes(v.flv_blinding.as_bytes(), &mut buf);
+            }
+        }
+
+        encoding::write_size(self.keys.len(), &mut buf);
+        for key in self.keys.iter() {
+            encoding::write_point(&key.0, &mut buf);
+        }
+        buf
+    }
+
+    /// Deserializes the request.
+    pub fn from_bytes(slice: &[u8]) -> Result<Self, VMError> {
+        SliceReader::parse(slice, |r| {
+            let mut log = Vec::new();
+            for _ in 0..r.read_size()? {
+                let entry = match r.read_u8()? {
+                    0 => CompactEntry::Entry(Entry::decode(r)?),
+                    1 => CompactEntry::Output(ContractID::from_raw_bytes(r.read_u8x32()?)),
+                    _ => return Err(VMError::FormatError),
+                };
+                log.push(entry);
+            }
+
+            let mut outputs = Vec::new();
+            for _ in 0..r.read_size()? {
+                let len = r.read_size()?;
+                let output = SliceReader::parse(r.read_bytes(len)?, |r| Output::decode(r))?;
+                let mut values = Vec::new();
+                for _ in 0..r.read_size()? {
+                    values.push(ValueOpening {
+                        qty: r.read_u64()?,
+                        qty_blinding: r.read_scalar()?,
+                        flv: r.read_scalar()?,
+                        flv_blinding: r.read_scalar()?,
+                    });
+                }
+                outputs.push(OutputOpening { output, values });
+            }
+
+            let mut keys = Vec::new();
+            for _ in 0..r.read_size()? {
+                keys.push(VerificationKey(r.read_point()?));
+            }
+            Ok(SigningRequest { log, outputs, keys })
+        })
+    }
+}
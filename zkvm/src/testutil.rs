@@ -0,0 +1,299 @@
+//! Test vectors and property-based testing strategies (enabled by the `testutil` feature).
+//!
+//! The vectors are canonical encodings of commitments, signatures, programs and transactions
+//! built from fixed secrets, so the implementations of ZkVM in other languages can check
+//! their encoders against this one. The vectors that are known in advance are also provided
+//! as hex constants.
+//!
+//! R1CS proofs are randomized by the prover, so the transaction vectors cover
+//! the header, the program, the ID and the signature of the transaction, but not the proof.
+//!
+//! The [proptest](https://docs.rs/proptest) strategies generate random scalars, data,
+//! commitments, values, predicates and programs for the property-based tests.
+
+use bulletproofs::BulletproofGens;
+use curve25519_dalek::scalar::Scalar;
+use merlin::Transcript;
+use proptest::collection;
+use proptest::prelude::*;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+use crate::constraints::Commitment;
+use crate::contract::{Anchor, Contract, Output, PortableItem};
+use crate::encoding;
+use crate::predicate::Predicate;
+use crate::program::Program;
+use crate::prover::Prover;
+use crate::signature::{Signature, VerificationKey};
+use crate::types::{Data, Value};
+use crate::vm::TxHeader;
+
+/// Encodings of the unblinded commitments `Commitment::unblinded(qty)` to the quantities 0 to 3:
+/// the identity and the multiples of the Ristretto basepoint.
+pub const UNBLINDED_COMMITMENTS: [(u64, &str); 4] = [
+    (
+        0,
+        "0000000000000000000000000000000000000000000000000000000000000000",
+    ),
+    (
+        1,
+        "e2f2ae0a6abc4e71a884a961c500515f58e30b6aa582dd8db6a65945e08d2d76",
+    ),
+    (
+        2,
+        "6a493210f7499cd17fecb510ae0cea23a110e8d5b901f8acadd3095c73a3b919",
+    ),
+    (
+        3,
+        "94741f5d5d52755ece4f23f044ee27d5d1ea1e2bd196b462166b16152a9d0259",
+    ),
+];
+
+/// ID of the transaction returned by `issue_tx`.
+pub const ISSUE_TXID: &str = "9428ec448cc03c1984a2e0317ed6d1b2ac5e268e872157ca3b0c1989b25f8851";
+
+/// Label of the transcript in the signature vectors.
+pub const SIGNATURE_LABEL: &[u8] = b"ZkVM.testvector";
+
+/// Test vector: named inputs and the expected output.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TestVector {
+    /// Name of the vector, e.g. `commitment/blinded/100`.
+    pub name: String,
+    /// Named inputs of the vector.
+    pub inputs: Vec<(&'static str, Vec<u8>)>,
+    /// Expected output for the inputs.
+    pub output: Vec<u8>,
+}
+
+impl TestVector {
+    /// Creates a test vector.
+    pub fn new<S: Into<String>>(
+        name: S,
+        inputs: Vec<(&'static str, Vec<u8>)>,
+        output: Vec<u8>,
+    ) -> Self {
+        TestVector {
+            name: name.into(),
+            inputs,
+            output,
+        }
+    }
+
+    /// Formats the vector as lines of `name`, `input: hex` and `output: hex`.
+    pub fn to_hex(&self) -> String {
+        let mut s = format!("{}\n", self.name);
+        for (label, input) in self.inputs.iter() {
+            s.push_str(&format!("{}: {}\n", label, hex(input)));
+        }
+        s.push_str(&format!("output: {}\n", hex(&self.output)));
+        s
+    }
+}
+
+/// Returns all the test vectors.
+pub fn vectors() -> Vec<TestVector> {
+    let mut vectors = commitments();
+    vectors.extend(signatures());
+    vectors.extend(programs());
+    vectors.extend(transactions());
+    vectors
+}
+
+/// Pedersen commitments to the quantity with the blinding factor: `qty·B + blinding·B_blinding`.
+pub fn commitments() -> Vec<TestVector> {
+    [(0u64, 0u64), (1, 0), (100, 1), (u64::max_value(), 7)]
+        .iter()
+        .map(|&(qty, blinding)| {
+            let blinding = Scalar::from(blinding);
+            TestVector::new(
+                format!("commitment/{}", qty),
+                vec![
+                    ("qty", qty.to_le_bytes().to_vec()),
+                    ("blinding", blinding.to_bytes().to_vec()),
+                ],
+                Commitment::blinded_with_factor(qty, blinding)
+                    .to_point()
+                    .to_bytes()
+                    .to_vec(),
+            )
+        })
+        .collect()
+}
+
+/// Signatures by the keys `Scalar::from(i)` of the messages committed as `message`
+/// to the transcript labeled `SIGNATURE_LABEL`. The nonces are derived with `StdRng`
+/// seeded with zero, so the signatures are deterministic.
+pub fn signatures() -> Vec<TestVector> {
+    [(1u64, &b""[..]), (2, &b"hello"[..]), (3, &[0xffu8; 64][..])]
+        .iter()
+        .map(|&(i, msg)| {
+            let privkey = Scalar::from(i);
+            let mut t = Transcript::new(SIGNATURE_LABEL);
+            t.commit_bytes(b"message", msg);
+            let sig =
+                Signature::sign_single_with_rng(&mut t, privkey, &mut StdRng::seed_from_u64(0));
+            TestVector::new(
+                format!("signature/{}", i),
+                vec![
+                    ("privkey", privkey.to_bytes().to_vec()),
+                    (
+                        "pubkey",
+                        VerificationKey::from_secret(&privkey).0.to_bytes().to_vec(),
+                    ),
+                    ("message", msg.to_vec()),
+                ],
+                sig.to_bytes().to_vec(),
+            )
+        })
+        .collect()
+}
+
+/// Bytecode of the programs in the assembly notation.
+pub fn programs() -> Vec<TestVector> {
+    [
+        "",
+        "push:0x00",
+        "push:0x0102 drop",
+        "push:0xff dup:0 drop drop",
+    ]
+    .iter()
+    .enumerate()
+    .map(|(i, src)| {
+        let program = Program::parse_assembly(src).expect("vectors are valid assembly");
+        TestVector::new(
+            format!("program/{}", i),
+            vec![("assembly", src.as_bytes().to_vec())],
+            program.to_bytes(),
+        )
+    })
+    .collect()
+}
+
+/// Returns the transaction that issues 1 unit of the flavor issued by the key `Scalar::from(100)`
+/// with the empty metadata, along with its header and the signing keys.
+/// The nonce is signed with the key `Scalar::from(0)` and the output is locked
+/// by the key `Scalar::from(1)`.
+pub fn issue_tx() -> (Program, TxHeader, Vec<Scalar>) {
+    let keys = vec![Scalar::from(0u64), Scalar::from(1u64), Scalar::from(100u64)];
+    let pred = |i: usize| Predicate::Key(VerificationKey::from_secret(&keys[i]));
+    let flv = Value::issue_flavor(&pred(2), Data::default());
+    let program = Program::build(|p| {
+        p.push(pred(0))
+            .push(Data::Opaque([0xffu8; 32].to_vec()))
+            .nonce()
+            .sign_tx()
+            .push(Commitment::blinded_with_factor(1u64, Scalar::from(1u64)))
+            .var()
+            .push(Commitment::unblinded(flv))
+            .var()
+            .push(Data::default())
+            .push(pred(2))
+            .issue()
+            .sign_tx()
+            .push(pred(1))
+            .output(1)
+    });
+    let header = TxHeader {
+        version: 0,
+        mintime: 0,
+        maxtime: 0,
+        maxcost: u64::max_value(),
+    };
+    (program, header, keys)
+}
+
+/// Transaction IDs and signatures of the transactions (see `issue_tx`).
+/// The header is encoded as LE64 version, mintime, maxtime and maxcost.
+pub fn transactions() -> Vec<TestVector> {
+    let (program, header, keys) = issue_tx();
+    let bp_gens = BulletproofGens::new(256, 1);
+    let (tx, txid, _) = Prover::build_tx(program, header, &bp_gens, |t, vkeys| {
+        let privkeys = vkeys
+            .iter()
+            .filter_map(|vk| {
+                keys.iter()
+                    .find(|k| VerificationKey::from_secret(k) == **vk)
+                    .cloned()
+            })
+            .collect::<Vec<_>>();
+        Signature::sign_aggregated_with_rng(t, &privkeys, &mut StdRng::seed_from_u64(0))
+    })
+    .expect("vector transaction is valid");
+
+    let mut header_bytes = Vec::with_capacity(32);
+    encoding::write_u64(header.version, &mut header_bytes);
+    encoding::write_u64(header.mintime, &mut header_bytes);
+    encoding::write_u64(header.maxtime, &mut header_bytes);
+    encoding::write_u64(header.maxcost, &mut header_bytes);
+    let inputs = vec![("header", header_bytes), ("program", tx.program.clone())];
+
+    vec![
+        TestVector::new("tx/issue/id", inputs.clone(), txid.0.to_vec()),
+        TestVector::new(
+            "tx/issue/signature",
+            inputs,
+            tx.signature.to_bytes().to_vec(),
+        ),
+    ]
+}
+
+/// Generates arbitrary scalars.
+pub fn arb_scalar() -> impl Strategy<Value = Scalar> {
+    any::<[u8; 32]>().prop_map(Scalar::from_bytes_mod_order)
+}
+
+/// Generates opaque data items of up to 64 bytes.
+pub fn arb_data() -> impl Strategy<Value = Data> {
+    collection::vec(any::<u8>(), 0..64).prop_map(Data::Opaque)
+}
+
+/// Generates open commitments to arbitrary quantities.
+pub fn arb_commitment() -> impl Strategy<Value = Commitment> {
+    (any::<u64>(), arb_scalar())
+        .prop_map(|(qty, blinding)| Commitment::blinded_with_factor(qty, blinding))
+}
+
+/// Generates values with arbitrary quantities and flavors.
+pub fn arb_value() -> impl Strategy<Value = Value> {
+    (any::<u64>(), arb_scalar(), arb_scalar(), arb_scalar()).prop_map(|(qty, flv, q, f)| Value {
+        qty: Commitment::blinded_with_factor(qty, q),
+        flv: Commitment::blinded_with_factor(flv, f),
+    })
+}
+
+/// Generates key predicates with arbitrary secret keys.
+pub fn arb_predicate() -> impl Strategy<Value = Predicate> {
+    arb_scalar().prop_map(|s| Predicate::Key(VerificationKey::from_secret(&s)))
+}
+
+/// Generates programs that push up to 16 data items, commitments, predicates and outputs,
+/// and drop them, leaving the stack clean.
+pub fn arb_program() -> impl Strategy<Value = Program> {
+    let item = prop_oneof![
+        arb_data(),
+        arb_commitment().prop_map(Data::from),
+        arb_predicate().prop_map(Data::from),
+        (arb_value(), arb_predicate()).prop_map(|(value, predicate)| {
+            Data::from(Output::new(Contract {
+                anchor: Anchor::from_raw_bytes([0u8; 32]),
+                payload: vec![PortableItem::Value(value)],
+                predicate,
+            }))
+        }),
+    ];
+    collection::vec(item, 0..16).prop_map(|items| {
+        Program::build(|p| {
+            let n = items.len();
+            for item in items {
+                p.push(item);
+            }
+            p.repeat(n, |p, _| p.drop())
+        })
+    })
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
@@ -1,9 +1,65 @@
-//! Defines a `TranscriptProtocol` trait for using a Merlin transcript.
+//! Defines a `TranscriptProtocol` trait for using a Merlin transcript,
+//! the domain separation labels of the transcripts used by ZkVM
+//! and the transcript of the transaction signature.
 
 use curve25519_dalek::ristretto::CompressedRistretto;
 use curve25519_dalek::scalar::Scalar;
 use merlin::Transcript;
 
+use crate::txlog::TxID;
+
+/// Domain separation labels of the Merlin transcripts used by ZkVM.
+///
+/// The labels are part of the consensus rules of the transaction version `labels::VERSION`:
+/// changing any of them changes the IDs, the signatures or the proofs of the transactions,
+/// and requires a new version of the transactions.
+pub mod labels {
+    /// Transaction version for which the labels are defined (see `CURRENT_VERSION`).
+    pub const VERSION: u64 = 1;
+
+    /// Label of the Merkle tree of the transaction log, whose root is the transaction ID.
+    pub const TXID: &[u8] = b"ZkVM.txid";
+
+    /// Label of the transcript of the witness transaction ID.
+    pub const WTXID: &[u8] = b"ZkVM.wtxid";
+
+    /// Label of the transcript of the UTXO ID.
+    pub const UTXO: &[u8] = b"ZkVM.utxo";
+
+    /// Label of the transcript of the aggregated transaction signature (see `transcript_for_tx`).
+    pub const SIGNTX: &[u8] = b"ZkVM.signtx";
+
+    /// Label of the transcript of the program signature checked by `delegate`.
+    pub const DELEGATE: &[u8] = b"ZkVM.delegate";
+
+    /// Label of the transcript of the R1CS proof.
+    pub const R1CS: &[u8] = b"ZkVM.r1cs";
+
+    /// Label of the transcript of the predicate disjunctions and program commitments.
+    pub const PREDICATE: &[u8] = b"ZkVM.predicate";
+
+    /// Label of the transcript of the issued flavors.
+    pub const ISSUE: &[u8] = b"ZkVM.issue";
+
+    /// Label of the transcript of the nonce anchors.
+    pub const NONCE: &[u8] = b"ZkVM.nonce";
+
+    /// Label of the transcript of the anchors ratcheted by the contracts.
+    pub const RATCHET_ANCHOR: &[u8] = b"ZkVM.ratchet-anchor";
+
+    /// Label of the transcript of the contract IDs.
+    pub const CONTRACT_ID: &[u8] = b"ZkVM.contractid";
+
+    /// Label of the transcript of the batched point operations.
+    pub const POINT_OPS: &[u8] = b"ZkVM.point_ops";
+
+    /// Label of the transcript of the Poseidon round constants.
+    pub const POSEIDON: &[u8] = b"ZkVM.poseidon";
+
+    /// Label of the transcript of the generators of the vector commitments.
+    pub const VECTOR_GENERATORS: &[u8] = b"ZkVM.vector-generators";
+}
+
 /// Extension trait to the Merlin transcript API that allows committing scalars and points and
 /// generating challenges as scalars.
 pub trait TranscriptProtocol {
@@ -31,3 +87,16 @@ impl TranscriptProtocol for Transcript {
         Scalar::from_bytes_mod_order_wide(&buf)
     }
 }
+
+/// Creates the transcript of the aggregated signature of the transaction with the ID,
+/// so external signers (e.g. HSMs) can recompute the challenges of the signature
+/// (see `Signature::challenges`).
+///
+/// The transcript is labeled `labels::SIGNTX` and commits the 32-byte ID as `txid`.
+/// The header of the transaction is not committed separately: the ID commits to it
+/// as the first entry of the transaction log.
+pub fn transcript_for_tx(txid: &TxID) -> Transcript {
+    let mut t = Transcript::new(labels::SIGNTX);
+    t.commit_bytes(b"txid", &txid.0);
+    t
+}
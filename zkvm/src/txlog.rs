@@ -1,9 +1,15 @@
+use bulletproofs::r1cs::R1CSProof;
 use curve25519_dalek::ristretto::CompressedRistretto;
 use merlin::Transcript;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 
 use crate::contract::{Anchor, ContractID, Output};
+use crate::encoding::{self, SliceReader};
+use crate::errors::VMError;
 use crate::merkle::{MerkleItem, MerkleTree};
-use crate::transcript::TranscriptProtocol;
+use crate::signature::Signature;
+use crate::transcript::{labels, TranscriptProtocol};
 use crate::vm::TxHeader;
 
 /// Transaction log. `TxLog` is a type alias for `Vec<Entry>`.
@@ -11,31 +17,57 @@ pub type TxLog = Vec<Entry>;
 
 /// Entry in a transaction log
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[allow(missing_docs)]
 pub enum Entry {
     Header(TxHeader),
     Issue(CompressedRistretto, CompressedRistretto),
     Retire(CompressedRistretto, CompressedRistretto),
+    Fee(u64),
     Input(ContractID),
     Output(Output),
     Nonce([u8; 32], u64, Anchor),
     Data(Vec<u8>),
+    Nullifier([u8; 32]),
     Import, // TBD: parameters
     Export, // TBD: parameters
 }
 
 /// Transaction ID is a unique 32-byte identifier of a transaction
-#[derive(Copy, Clone, PartialEq, Debug)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
 pub struct TxID(pub [u8; 32]);
 
+/// Witness transaction ID commits to the transaction ID together with the program,
+/// the signature and the R1CS proof, which the `TxID` does not cover.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct WTxID(pub [u8; 32]);
+
 /// UTXO is a unique 32-byte identifier of a transaction output
 #[derive(Copy, Clone, PartialEq, Debug)]
 pub struct UTXO(pub [u8; 32]);
 
+#[cfg(feature = "serde")]
+serialize_bytes!(TxID, "a 32-byte transaction ID", |id| id.0, |bytes| {
+    SliceReader::parse(bytes, |r| r.read_u8x32()).map(TxID)
+});
+
+#[cfg(feature = "serde")]
+serialize_bytes!(
+    WTxID,
+    "a 32-byte witness transaction ID",
+    |id| id.0,
+    |bytes| { SliceReader::parse(bytes, |r| r.read_u8x32()).map(WTxID) }
+);
+
+#[cfg(feature = "serde")]
+serialize_bytes!(UTXO, "a 32-byte UTXO ID", |id| id.0, |bytes| {
+    SliceReader::parse(bytes, |r| r.read_u8x32()).map(UTXO)
+});
+
 impl UTXO {
     /// Computes UTXO identifier from an output and transaction id.
     pub fn from_output(output: &[u8], txid: &TxID) -> Self {
-        let mut t = Transcript::new(b"ZkVM.utxo");
+        let mut t = Transcript::new(labels::UTXO);
         t.commit_bytes(b"txid", &txid.0);
         t.commit_bytes(b"output", &output);
         let mut utxo = UTXO([0u8; 32]);
@@ -47,7 +79,135 @@ impl UTXO {
 impl TxID {
     /// Computes TxID from a tx log
     pub fn from_log(list: &[Entry]) -> Self {
-        TxID(MerkleTree::root(b"ZkVM.txid", list))
+        TxID(MerkleTree::root(labels::TXID, list))
+    }
+}
+
+impl WTxID {
+    /// Computes WTxID from the transaction ID and the witness data of the transaction.
+    pub fn from_witness(
+        txid: &TxID,
+        program: &[u8],
+        signature: &Signature,
+        proof: &R1CSProof,
+    ) -> Self {
+        Self::from_transcript(Self::witness_transcript(program, signature, proof), txid)
+    }
+
+    /// Commits the witness data, so the verifier can drop the program
+    /// before the transaction ID is known.
+    pub(crate) fn witness_transcript(
+        program: &[u8],
+        signature: &Signature,
+        proof: &R1CSProof,
+    ) -> Transcript {
+        let mut t = Transcript::new(labels::WTXID);
+        t.commit_bytes(b"program", program);
+        t.commit_bytes(b"signature", &signature.to_bytes());
+        t.commit_bytes(b"proof", &proof.to_bytes());
+        t
+    }
+
+    pub(crate) fn from_transcript(mut t: Transcript, txid: &TxID) -> Self {
+        t.commit_bytes(b"txid", &txid.0);
+        let mut wtxid = WTxID([0u8; 32]);
+        t.challenge_bytes(b"id", &mut wtxid.0);
+        wtxid
+    }
+}
+
+impl MerkleItem for TxID {
+    fn commit(&self, t: &mut Transcript) {
+        t.commit_bytes(b"txid", &self.0);
+    }
+}
+
+impl Entry {
+    /// Encodes the entry, e.g. for passing the log to the signers.
+    pub(crate) fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            Entry::Header(h) => {
+                encoding::write_u8(0, buf);
+                encoding::write_u64(h.version, buf);
+                encoding::write_u64(h.mintime, buf);
+                encoding::write_u64(h.maxtime, buf);
+                encoding::write_u64(h.maxcost, buf);
+            }
+            Entry::Issue(q, f) => {
+                encoding::write_u8(1, buf);
+                encoding::write_point(q, buf);
+                encoding::write_point(f, buf);
+            }
+            Entry::Retire(q, f) => {
+                encoding::write_u8(2, buf);
+                encoding::write_point(q, buf);
+                encoding::write_point(f, buf);
+            }
+            Entry::Fee(qty) => {
+                encoding::write_u8(3, buf);
+                encoding::write_u64(*qty, buf);
+            }
+            Entry::Input(id) => {
+                encoding::write_u8(4, buf);
+                encoding::write_bytes(id.as_bytes(), buf);
+            }
+            Entry::Output(output) => {
+                encoding::write_u8(5, buf);
+                encoding::write_size(output.serialized_length(), buf);
+                output.encode(buf);
+            }
+            Entry::Nonce(blockid, maxtime, anchor) => {
+                encoding::write_u8(6, buf);
+                encoding::write_bytes(blockid, buf);
+                encoding::write_u64(*maxtime, buf);
+                encoding::write_bytes(anchor.as_bytes(), buf);
+            }
+            Entry::Data(data) => {
+                encoding::write_u8(7, buf);
+                encoding::write_size(data.len(), buf);
+                encoding::write_bytes(data, buf);
+            }
+            Entry::Import => encoding::write_u8(8, buf),
+            Entry::Export => encoding::write_u8(9, buf),
+            Entry::Nullifier(tag) => {
+                encoding::write_u8(10, buf);
+                encoding::write_bytes(tag, buf);
+            }
+        }
+    }
+
+    /// Decodes the entry encoded with `Entry::encode`.
+    pub(crate) fn decode(r: &mut SliceReader) -> Result<Entry, VMError> {
+        match r.read_u8()? {
+            0 => Ok(Entry::Header(TxHeader {
+                version: r.read_u64()?,
+                mintime: r.read_u64()?,
+                maxtime: r.read_u64()?,
+                maxcost: r.read_u64()?,
+            })),
+            1 => Ok(Entry::Issue(r.read_point()?, r.read_point()?)),
+            2 => Ok(Entry::Retire(r.read_point()?, r.read_point()?)),
+            3 => Ok(Entry::Fee(r.read_u64()?)),
+            4 => Ok(Entry::Input(ContractID::from_raw_bytes(r.read_u8x32()?))),
+            5 => {
+                let len = r.read_size()?;
+                let output = SliceReader::parse(r.read_bytes(len)?, |r| Output::decode(r))?;
+                Ok(Entry::Output(output))
+            }
+            6 => Ok(Entry::Nonce(
+                r.read_u8x32()?,
+                r.read_u64()?,
+                Anchor::from_raw_bytes(r.read_u8x32()?),
+            )),
+            7 => {
+                let len = r.read_size()?;
+                Ok(Entry::Data(r.read_bytes(len)?.to_vec()))
+            }
+            8 => Ok(Entry::Import),
+            9 => Ok(Entry::Export),
+            10 => Ok(Entry::Nullifier(r.read_u8x32()?)),
+            _ => Err(VMError::FormatError),
+        }
     }
 }
 
@@ -58,6 +218,7 @@ impl MerkleItem for Entry {
                 t.commit_u64(b"tx.version", h.version);
                 t.commit_u64(b"tx.mintime", h.mintime);
                 t.commit_u64(b"tx.maxtime", h.maxtime);
+                t.commit_u64(b"tx.maxcost", h.maxcost);
             }
             Entry::Issue(q, f) => {
                 t.commit_point(b"issue.q", q);
@@ -67,6 +228,9 @@ impl MerkleItem for Entry {
                 t.commit_point(b"retire.q", q);
                 t.commit_point(b"retire.f", f);
             }
+            Entry::Fee(qty) => {
+                t.commit_u64(b"fee", *qty);
+            }
             Entry::Input(id) => {
                 t.commit_bytes(b"input", id.as_bytes());
             }
@@ -81,6 +245,9 @@ impl MerkleItem for Entry {
             Entry::Data(data) => {
                 t.commit_bytes(b"data", data);
             }
+            Entry::Nullifier(tag) => {
+                t.commit_bytes(b"nullifier", tag);
+            }
             Entry::Import => {
                 // TBD: commit parameters
                 unimplemented!()
@@ -102,6 +269,7 @@ mod tests {
             Entry::Header(TxHeader {
                 mintime: 0,
                 maxtime: 0,
+                maxcost: 0,
                 version: 0,
             }),
             Entry::Issue(
@@ -118,23 +286,23 @@ mod tests {
     fn valid_txid_proof() {
         let (entry, txid, proof) = {
             let entries = txlog_helper();
-            let root = MerkleTree::build(b"ZkVM.txid", &entries).unwrap();
+            let root = MerkleTree::build(labels::TXID, &entries).unwrap();
             let index = 3;
             let proof = root.create_path(index).unwrap();
             (entries[index].clone(), TxID::from_log(&entries), proof)
         };
-        MerkleTree::verify_path(b"ZkVM.txid", &entry, proof, &txid.0).unwrap();
+        MerkleTree::verify_path(labels::TXID, &entry, proof, &txid.0).unwrap();
     }
 
     #[test]
     fn invalid_txid_proof() {
         let (entry, txid, proof) = {
             let entries = txlog_helper();
-            let root = MerkleTree::build(b"ZkVM.txid", &entries).unwrap();
+            let root = MerkleTree::build(labels::TXID, &entries).unwrap();
             let index = 3;
             let proof = root.create_path(index).unwrap();
             (entries[index + 1].clone(), TxID::from_log(&entries), proof)
         };
-        assert!(MerkleTree::verify_path(b"ZkVM.txid", &entry, proof, &txid.0).is_err());
+        assert!(MerkleTree::verify_path(labels::TXID, &entry, proof, &txid.0).is_err());
     }
 }
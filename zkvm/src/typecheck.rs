@@ -0,0 +1,418 @@
+//! Static type checking of the transaction's program before proving.
+//!
+//! The checker executes the instructions symbolically, tracking the types of the stack items
+//! (data, contract, value, wide value, variable, expression and constraint) and the stack depth.
+//! The payloads of the contracts are known when the contracts are created by the program
+//! or claimed from the outputs pushed by the prover. The check stops at the first instruction
+//! whose effect on the stack cannot be known statically: `call` and `delegate`
+//! (which execute another program), and `signtx` of a contract with an unknown payload.
+
+use crate::contract::PortableItem;
+use crate::coverage::encoded_length;
+use crate::errors::VMError;
+use crate::ops::Instruction;
+use crate::types::Data;
+
+/// Type of a stack item, with the types of the payload of the contract
+/// (or of the output data that claims the contract) when it is known.
+#[derive(Clone, Debug)]
+enum Type {
+    Data(Option<Vec<Type>>),
+    Contract(Option<Vec<Type>>),
+    Value,
+    WideValue,
+    Variable,
+    Expression,
+    Constraint,
+}
+
+/// Result of the symbolic execution of an instruction.
+enum Effect {
+    Continue,
+    Stop,
+}
+
+/// Checks the types of the instructions of the transaction's program, starting with an empty stack.
+/// Fails with `VMError::TypeCheckFailed` pointing to the byte offset of the failing instruction,
+/// or to the end of the program if the stack is not clean.
+pub(crate) fn typecheck(instructions: &[Instruction]) -> Result<(), VMError> {
+    let mut checker = Checker { stack: Vec::new() };
+    let mut offset = 0;
+    for instr in instructions.iter() {
+        let effect = checker
+            .execute(instr)
+            .map_err(|error| VMError::TypeCheckFailed {
+                offset,
+                instruction: Some(instr.mnemonic()),
+                error: Box::new(error),
+            })?;
+        if let Effect::Stop = effect {
+            return Ok(());
+        }
+        offset += encoded_length(instr);
+    }
+    if !checker.stack.is_empty() {
+        return Err(VMError::TypeCheckFailed {
+            offset,
+            instruction: None,
+            error: Box::new(VMError::StackNotClean),
+        });
+    }
+    Ok(())
+}
+
+struct Checker {
+    stack: Vec<Type>,
+}
+
+impl Checker {
+    fn execute(&mut self, instr: &Instruction) -> Result<Effect, VMError> {
+        match instr {
+            Instruction::Push(data) => self.stack.push(data_type(data)),
+            Instruction::Drop => {
+                self.pop_copyable()?;
+            }
+            Instruction::Dup(i) => {
+                if *i >= self.stack.len() {
+                    return Err(VMError::StackUnderflow);
+                }
+                let item = self.stack[self.stack.len() - i - 1].clone();
+                self.stack.push(copyable(item)?);
+            }
+            Instruction::Roll(i) => {
+                if *i >= self.stack.len() {
+                    return Err(VMError::StackUnderflow);
+                }
+                let item = self.stack.remove(self.stack.len() - i - 1);
+                self.stack.push(item);
+            }
+            Instruction::Const => {
+                self.pop_data()?;
+                self.stack.push(Type::Expression);
+            }
+            Instruction::Var => {
+                self.pop_data()?;
+                self.stack.push(Type::Variable);
+            }
+            Instruction::Alloc(_) | Instruction::Mintime | Instruction::Maxtime => {
+                self.stack.push(Type::Expression)
+            }
+            Instruction::Expr => {
+                self.pop_variable()?;
+                self.stack.push(Type::Expression);
+            }
+            Instruction::Neg | Instruction::Range(_) => {
+                self.pop_expression()?;
+                self.stack.push(Type::Expression);
+            }
+            Instruction::Add | Instruction::Mul => {
+                self.pop_expression()?;
+                self.pop_expression()?;
+                self.stack.push(Type::Expression);
+            }
+            Instruction::Eq => {
+                self.pop_expression()?;
+                self.pop_expression()?;
+                self.stack.push(Type::Constraint);
+            }
+            Instruction::Sha256(n) | Instruction::Sha512(n) => {
+                self.pop_data()?;
+                for _ in 0..*n {
+                    self.pop_expression()?;
+                }
+                self.stack.push(Type::Constraint);
+            }
+            Instruction::Poseidon(n) => {
+                for _ in 0..*n {
+                    self.pop_expression()?;
+                }
+                self.stack.push(Type::Expression);
+            }
+            Instruction::And | Instruction::Or => {
+                self.pop_constraint()?;
+                self.pop_constraint()?;
+                self.stack.push(Type::Constraint);
+            }
+            Instruction::Not => {
+                self.pop_constraint()?;
+                self.stack.push(Type::Constraint);
+            }
+            Instruction::Verify => {
+                self.pop_constraint()?;
+            }
+            Instruction::Unblind => {
+                self.pop_data()?;
+                self.pop_data()?;
+                self.stack.push(Type::Data(None));
+            }
+            Instruction::Issue => {
+                self.pop_data()?;
+                self.pop_data()?;
+                self.pop_variable()?;
+                self.pop_variable()?;
+                self.stack.push(Type::Contract(Some(vec![Type::Value])));
+            }
+            Instruction::Borrow => {
+                self.pop_variable()?;
+                self.pop_variable()?;
+                self.stack.push(Type::WideValue);
+                self.stack.push(Type::Value);
+            }
+            Instruction::Retire => match self.pop()? {
+                Type::Value => {}
+                _ => return Err(VMError::TypeNotValue),
+            },
+            Instruction::Fee => {
+                self.pop_data()?;
+                self.stack.push(Type::WideValue);
+            }
+            Instruction::Cloak(m, n) => {
+                if m + 2 * n > self.stack.len() {
+                    return Err(VMError::StackUnderflow);
+                }
+                for _ in 0..*n {
+                    self.pop_data()?;
+                    self.pop_data()?;
+                }
+                for _ in 0..*m {
+                    match self.pop()? {
+                        Type::Value | Type::WideValue => {}
+                        _ => return Err(VMError::TypeNotWideValue),
+                    }
+                }
+                for _ in 0..*n {
+                    self.stack.push(Type::Value);
+                }
+            }
+            Instruction::Input => {
+                let payload = self.pop_data()?;
+                self.stack.push(Type::Contract(payload));
+            }
+            Instruction::Output(k) => {
+                self.pop_data()?;
+                self.pop_portable(*k)?;
+            }
+            Instruction::Contract(k) => {
+                self.pop_data()?;
+                let payload = self.pop_portable(*k)?;
+                self.stack.push(Type::Contract(Some(payload)));
+            }
+            Instruction::Carry(k) => {
+                self.pop_portable(*k)?;
+            }
+            Instruction::Nonce => {
+                self.pop_data()?;
+                self.pop_data()?;
+                self.stack.push(Type::Contract(Some(Vec::new())));
+            }
+            Instruction::Log => {
+                self.pop_data()?;
+            }
+            Instruction::Nullify => {
+                self.pop_data()?;
+                self.pop_expression()?;
+            }
+            Instruction::Signtx => match self.pop_contract()? {
+                Some(payload) => self.stack.extend(payload),
+                None => return Ok(Effect::Stop),
+            },
+            Instruction::Select(n, k) => {
+                if k >= n {
+                    return Err(VMError::PredicateIndexInvalid);
+                }
+                for _ in 0..*n {
+                    self.pop_data()?;
+                }
+                let payload = self.pop_contract()?;
+                self.stack.push(Type::Contract(payload));
+            }
+            Instruction::Call | Instruction::Delegate => {
+                self.pop_data()?;
+                self.pop_data()?;
+                self.pop_contract()?;
+                return Ok(Effect::Stop);
+            }
+            Instruction::Import | Instruction::Export => return Ok(Effect::Stop),
+            Instruction::Ext(_) | Instruction::Inspect(_) => {}
+        }
+        Ok(Effect::Continue)
+    }
+
+    fn pop(&mut self) -> Result<Type, VMError> {
+        self.stack.pop().ok_or(VMError::StackUnderflow)
+    }
+
+    fn pop_copyable(&mut self) -> Result<Type, VMError> {
+        let item = self.pop()?;
+        copyable(item)
+    }
+
+    fn pop_data(&mut self) -> Result<Option<Vec<Type>>, VMError> {
+        match self.pop()? {
+            Type::Data(payload) => Ok(payload),
+            _ => Err(VMError::TypeNotData),
+        }
+    }
+
+    fn pop_contract(&mut self) -> Result<Option<Vec<Type>>, VMError> {
+        match self.pop()? {
+            Type::Contract(payload) => Ok(payload),
+            _ => Err(VMError::TypeNotContract),
+        }
+    }
+
+    fn pop_variable(&mut self) -> Result<(), VMError> {
+        match self.pop()? {
+            Type::Variable => Ok(()),
+            _ => Err(VMError::TypeNotVariable),
+        }
+    }
+
+    fn pop_expression(&mut self) -> Result<(), VMError> {
+        match self.pop()? {
+            Type::Expression => Ok(()),
+            _ => Err(VMError::TypeNotExpression),
+        }
+    }
+
+    fn pop_constraint(&mut self) -> Result<(), VMError> {
+        match self.pop()? {
+            Type::Constraint => Ok(()),
+            _ => Err(VMError::TypeNotConstraint),
+        }
+    }
+
+    /// Pops `k` portable items (data or values), returning them in the order of the stack.
+    fn pop_portable(&mut self, k: usize) -> Result<Vec<Type>, VMError> {
+        if k > self.stack.len() {
+            return Err(VMError::StackUnderflow);
+        }
+        let payload = self.stack.drain(self.stack.len() - k..).collect::<Vec<_>>();
+        for item in payload.iter() {
+            match item {
+                Type::Data(_) | Type::Value => {}
+                _ => return Err(VMError::TypeNotPortable),
+            }
+        }
+        Ok(payload)
+    }
+}
+
+fn copyable(item: Type) -> Result<Type, VMError> {
+    match item {
+        Type::Data(_) | Type::Variable | Type::Expression | Type::Constraint => Ok(item),
+        _ => Err(VMError::TypeNotCopyable),
+    }
+}
+
+/// Returns the type of the data, with the payload of the contract claimed by the output witness.
+fn data_type(data: &Data) -> Type {
+    match data {
+        Data::Output(output) => Type::Data(Some(
+            output
+                .contract()
+                .payload
+                .iter()
+                .map(|item| match item {
+                    PortableItem::Data(data) => data_type(data),
+                    PortableItem::Value(_) => Type::Value,
+                })
+                .collect(),
+        )),
+        _ => Type::Data(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constraints::Commitment;
+    use crate::program::Program;
+    use curve25519_dalek::scalar::Scalar;
+
+    fn failure(program: &Program) -> (usize, Option<&'static str>, VMError) {
+        match program.typecheck() {
+            Err(VMError::TypeCheckFailed {
+                offset,
+                instruction,
+                error,
+            }) => (offset, instruction, *error),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn well_typed_programs() {
+        let program = Program::build(|p| {
+            p.push(Commitment::blinded_with_factor(1u64, Scalar::one()))
+                .var()
+                .expr()
+                .push(Scalar::one())
+                .r#const()
+                .eq()
+                .verify()
+                .push(Data::Opaque(vec![1, 2, 3]))
+                .dup(0)
+                .drop()
+                .drop()
+        });
+        assert_eq!(program.typecheck(), Ok(()));
+        assert_eq!(Program::new().typecheck(), Ok(()));
+    }
+
+    #[test]
+    fn type_errors() {
+        // `expr` expects a variable, but the data is on top of the stack.
+        let program = Program::build(|p| p.push(Data::Opaque(vec![0])).expr());
+        assert_eq!(
+            failure(&program),
+            (1 + 4 + 1, Some("expr"), VMError::TypeNotVariable)
+        );
+
+        // Variables cannot be outputs.
+        let program = Program::build(|p| {
+            p.push(Commitment::blinded_with_factor(1u64, Scalar::one()))
+                .var()
+                .push(Data::Opaque(vec![]))
+                .output(1)
+        });
+        assert_eq!(failure(&program).2, VMError::TypeNotPortable);
+
+        // Values are not copyable.
+        let program = Program::build(|p| {
+            p.push(Commitment::blinded_with_factor(1u64, Scalar::one()))
+                .var()
+                .push(Commitment::blinded_with_factor(1u64, Scalar::one()))
+                .var()
+                .borrow()
+                .dup(0)
+        });
+        assert_eq!(failure(&program).2, VMError::TypeNotCopyable);
+
+        // Stack underflow and the items left on the stack.
+        assert_eq!(
+            failure(&Program::build(|p| p.cloak(1, 1))),
+            (0, Some("cloak"), VMError::StackUnderflow)
+        );
+        let program = Program::build(|p| p.push(Data::Opaque(vec![])));
+        assert_eq!(failure(&program), (1 + 4, None, VMError::StackNotClean));
+    }
+
+    #[test]
+    fn unknown_effects() {
+        // The payload of a contract claimed from opaque output data is unknown,
+        // so the check stops at `signtx`.
+        let program = Program::build(|p| p.push(Data::Opaque(vec![])).input().sign_tx().drop());
+        assert_eq!(program.typecheck(), Ok(()));
+
+        // The payload of the contract created by the program is known.
+        let program = Program::build(|p| {
+            p.push(Data::Opaque(vec![]))
+                .push(Data::Opaque(vec![]))
+                .nonce()
+                .sign_tx()
+                .drop()
+        });
+        assert_eq!(failure(&program).2, VMError::StackUnderflow);
+    }
+}
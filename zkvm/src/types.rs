@@ -3,6 +3,8 @@
 use bulletproofs::r1cs;
 use curve25519_dalek::scalar::Scalar;
 use merlin::Transcript;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 use spacesuit::SignedInteger;
 
 use crate::constraints::{Commitment, Constraint, Expression, Variable};
@@ -12,7 +14,8 @@ use crate::errors::VMError;
 use crate::predicate::Predicate;
 use crate::program::Program;
 use crate::scalar_witness::ScalarWitness;
-use crate::transcript::TranscriptProtocol;
+use crate::transcript::{labels, TranscriptProtocol};
+use crate::vector_commitment::VectorCommitment;
 
 /// An item on a VM stack.
 #[derive(Debug)]
@@ -41,6 +44,7 @@ pub enum Item {
 
 /// A data item.
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Data {
     /// Opaque data item.
     Opaque(Vec<u8>),
@@ -59,12 +63,16 @@ pub enum Data {
 
     /// An input object (claimed UTXO).
     Output(Box<Output>),
+
+    /// A vector Pedersen commitment.
+    VectorCommitment(Box<VectorCommitment>),
 }
 
 /// Represents a value of an issued asset in the VM.
 /// Note: values do not necessarily have open commitments. Some can be reblinded,
 /// others can be passed-through to an output without going through `cloak` and the constraint system.
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Value {
     /// Commitment to value's quantity
     pub qty: Commitment,
@@ -81,6 +89,19 @@ pub struct WideValue {
 }
 
 impl Item {
+    /// Returns the name of the item's type, e.g. for reporting a type mismatch.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Item::Data(_) => "data",
+            Item::Contract(_) => "contract",
+            Item::Value(_) => "value",
+            Item::WideValue(_) => "wide value",
+            Item::Variable(_) => "variable",
+            Item::Expression(_) => "expression",
+            Item::Constraint(_) => "constraint",
+        }
+    }
+
     /// Downcasts item to `Data` type.
     pub fn to_data(self) -> Result<Data, VMError> {
         match self {
@@ -157,6 +178,7 @@ impl Data {
             Data::Commitment(commitment) => commitment.serialized_length(),
             Data::Scalar(scalar) => scalar.serialized_length(),
             Data::Output(output) => output.serialized_length(),
+            Data::VectorCommitment(commitment) => commitment.serialized_length(),
         }
     }
 
@@ -228,6 +250,15 @@ impl Data {
         }
     }
 
+    /// Downcast the data item to a `VectorCommitment` type.
+    pub fn to_vector_commitment(self) -> Result<VectorCommitment, VMError> {
+        match self {
+            Data::Opaque(data) => SliceReader::parse(&data, |r| VectorCommitment::decode(r)),
+            Data::VectorCommitment(c) => Ok(*c),
+            _ => Err(VMError::TypeNotVectorCommitment),
+        }
+    }
+
     /// Encodes the data item to an opaque bytestring.
     pub fn encode(&self, buf: &mut Vec<u8>) {
         match self {
@@ -237,6 +268,7 @@ impl Data {
             Data::Commitment(commitment) => commitment.encode(buf),
             Data::Scalar(scalar) => scalar.encode(buf),
             Data::Output(output) => output.encode(buf),
+            Data::VectorCommitment(commitment) => commitment.encode(buf),
         };
     }
 }
@@ -250,7 +282,7 @@ impl Default for Data {
 impl Value {
     /// Computes a flavor as defined by the `issue` instruction from a predicate.
     pub fn issue_flavor(predicate: &Predicate, metadata: Data) -> Scalar {
-        let mut t = Transcript::new(b"ZkVM.issue");
+        let mut t = Transcript::new(labels::ISSUE);
         t.commit_bytes(b"predicate", predicate.to_point().as_bytes());
         t.commit_bytes(b"metadata", &metadata.to_bytes());
         t.challenge_scalar(b"flavor")
@@ -302,6 +334,12 @@ impl From<Output> for Data {
     }
 }
 
+impl From<VectorCommitment> for Data {
+    fn from(x: VectorCommitment) -> Self {
+        Data::VectorCommitment(Box::new(x))
+    }
+}
+
 // Upcasting all types to Item
 
 impl From<Data> for Item {
@@ -0,0 +1,391 @@
+//! Vector Pedersen commitments to tuples of scalars.
+//!
+//! A vector commitment hides the scalars `v_0, ..., v_{n-1}` and a blinding factor `f`
+//! in a single point:
+//!
+//! ```ascii
+//! V = v_0·G_0 + ... + v_{n-1}·G_{n-1} + f·B2
+//! ```
+//!
+//! The generator `G_i` of each position is derived from a transcript, so its discrete log
+//! with respect to the other generators is unknown. A contract stores the commitment
+//! as a data item in its payload (see `Data::VectorCommitment`), which keeps structured
+//! state compact: 36 bytes regardless of the number of scalars.
+//!
+//! The committer reveals a single position with a `VectorOpening`: a Schnorr-style proof
+//! of knowledge of the other scalars and the blinding factor of `V - v_i·G_i`.
+//! The proof is checked with deferred point operations, like the signatures.
+
+#![allow(non_snake_case)]
+
+use curve25519_dalek::ristretto::{CompressedRistretto, RistrettoPoint};
+use curve25519_dalek::scalar::Scalar;
+#[cfg(feature = "parallel")]
+use curve25519_dalek::traits::Identity;
+use curve25519_dalek::traits::MultiscalarMul;
+use merlin::Transcript;
+use rand::{CryptoRng, RngCore};
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroize;
+
+use crate::encoding::{self, SliceReader};
+use crate::errors::VMError;
+use crate::gens;
+use crate::point_ops::PointOp;
+use crate::transcript::{labels, TranscriptProtocol};
+
+/// Pedersen commitment to a vector of scalars: an _open_ or _closed_ one.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum VectorCommitment {
+    /// Hides a vector of the given length in the Ristretto point.
+    Closed(usize, CompressedRistretto),
+
+    /// Contains the secret vector and its blinding factor for the use in the prover.
+    Open(Box<VectorCommitmentWitness>),
+}
+
+/// Prover's representation of the vector commitment secret: the scalars and the blinding factor.
+/// The secret is zeroized when dropped.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct VectorCommitmentWitness {
+    values: Vec<Scalar>,
+    blinding: Scalar,
+}
+
+/// Proof that the vector commitment contains the value at the index,
+/// revealing nothing about the other values.
+#[derive(Clone, Debug)]
+pub struct VectorOpening {
+    R: CompressedRistretto,
+    z_f: Scalar,
+    z_v: Vec<Scalar>,
+}
+
+/// Returns the generator `G_i` of the position `i` of the vector commitments.
+pub fn vector_generator(index: usize) -> RistrettoPoint {
+    let mut t = Transcript::new(labels::VECTOR_GENERATORS);
+    t.commit_u64(b"index", index as u64);
+    let mut buf = [0u8; 64];
+    t.challenge_bytes(b"G", &mut buf);
+    RistrettoPoint::from_uniform_bytes(&buf)
+}
+
+/// Computes `sum{v_j·G_j}` for the pairs of positions `j` and scalars `v_j`.
+#[cfg(not(feature = "parallel"))]
+fn generators_mul(terms: &[(usize, Scalar)]) -> RistrettoPoint {
+    RistrettoPoint::multiscalar_mul(
+        terms.iter().map(|(_, v)| v),
+        terms.iter().map(|(j, _)| vector_generator(*j)),
+    )
+}
+
+/// Computes `sum{v_j·G_j}` for the pairs of positions `j` and scalars `v_j`,
+/// deriving the generators and multiplying them in chunks on all the threads of the rayon pool.
+#[cfg(feature = "parallel")]
+fn generators_mul(terms: &[(usize, Scalar)]) -> RistrettoPoint {
+    const CHUNK_SIZE: usize = 64;
+    terms
+        .par_chunks(CHUNK_SIZE)
+        .map(|chunk| {
+            RistrettoPoint::multiscalar_mul(
+                chunk.iter().map(|(_, v)| v),
+                chunk.iter().map(|(j, _)| vector_generator(*j)),
+            )
+        })
+        .reduce(RistrettoPoint::identity, |a, b| a + b)
+}
+
+impl VectorCommitment {
+    /// Creates an open commitment to the values with the blinding factor.
+    pub fn blinded_with_factor(values: Vec<Scalar>, blinding: Scalar) -> Self {
+        VectorCommitment::Open(Box::new(VectorCommitmentWitness { values, blinding }))
+    }
+
+    /// Creates an open commitment to the values with a random blinding factor.
+    #[cfg(feature = "std")]
+    pub fn blinded(values: Vec<Scalar>) -> Self {
+        Self::blinded_with_rng(values, &mut rand::thread_rng())
+    }
+
+    /// Creates an open commitment to the values with a blinding factor from the provided RNG.
+    pub fn blinded_with_rng<R: RngCore + CryptoRng>(values: Vec<Scalar>, rng: &mut R) -> Self {
+        Self::blinded_with_factor(values, Scalar::random(rng))
+    }
+
+    /// Returns the number of the committed scalars.
+    pub fn len(&self) -> usize {
+        match self {
+            VectorCommitment::Closed(len, _) => *len,
+            VectorCommitment::Open(w) => w.values.len(),
+        }
+    }
+
+    /// Returns true if the commitment is to the empty vector.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the commitment point.
+    /// With the `parallel` feature, the point of an open commitment is computed on all the threads.
+    pub fn to_point(&self) -> CompressedRistretto {
+        match self {
+            VectorCommitment::Closed(_, point) => *point,
+            VectorCommitment::Open(w) => {
+                let terms = w.values.iter().cloned().enumerate().collect::<Vec<_>>();
+                (generators_mul(&terms) + gens::mul_blinding(&w.blinding)).compress()
+            }
+        }
+    }
+
+    /// Returns the secret values and the blinding factor, if the commitment is open.
+    pub fn witness(&self) -> Option<(&[Scalar], Scalar)> {
+        match self {
+            VectorCommitment::Closed(..) => None,
+            VectorCommitment::Open(w) => Some((&w.values, w.blinding)),
+        }
+    }
+
+    /// Returns the number of bytes needed to serialize the commitment.
+    pub fn serialized_length(&self) -> usize {
+        4 + 32
+    }
+
+    /// Encodes the commitment as LE32 length followed by the point.
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        encoding::write_size(self.len(), buf);
+        encoding::write_point(&self.to_point(), buf);
+    }
+
+    /// Decodes a closed commitment.
+    pub(crate) fn decode<'a>(reader: &mut SliceReader<'a>) -> Result<Self, VMError> {
+        let len = reader.read_size()?;
+        let point = reader.read_point()?;
+        Ok(VectorCommitment::Closed(len, point))
+    }
+
+    /// Creates a proof that the commitment contains the value at the index, and returns the value.
+    /// The transcript binds the proof to its context, e.g. the contract ID.
+    #[cfg(feature = "std")]
+    pub fn open(
+        &self,
+        transcript: &mut Transcript,
+        index: usize,
+    ) -> Result<(Scalar, VectorOpening), VMError> {
+        self.open_with_rng(transcript, index, &mut rand::thread_rng())
+    }
+
+    /// Creates a proof that the commitment contains the value at the index using the provided RNG.
+    /// Fails with `VMError::WitnessMissing` if the commitment is not open,
+    /// and with `VMError::BadArguments` if the index is out of range.
+    pub fn open_with_rng<R: RngCore + CryptoRng>(
+        &self,
+        transcript: &mut Transcript,
+        index: usize,
+        rng: &mut R,
+    ) -> Result<(Scalar, VectorOpening), VMError> {
+        let (values, blinding) = self.witness().ok_or(VMError::WitnessMissing)?;
+        let value = *values.get(index).ok_or(VMError::BadArguments)?;
+
+        let mut rng = values
+            .iter()
+            .fold(transcript.build_rng(), |b, v| {
+                b.commit_witness_bytes(b"v", v.as_bytes())
+            })
+            .commit_witness_bytes(b"f", blinding.as_bytes())
+            .finalize(rng);
+        let r_f = Scalar::random(&mut rng);
+        let r_v = (0..values.len())
+            .filter(|&j| j != index)
+            .map(|j| (j, Scalar::random(&mut rng)))
+            .collect::<Vec<_>>();
+
+        let R = (generators_mul(&r_v) + gens::mul_blinding(&r_f)).compress();
+
+        let c = VectorOpening::challenge(
+            transcript,
+            values.len(),
+            &self.to_point(),
+            index,
+            &value,
+            &R,
+        );
+
+        let opening = VectorOpening {
+            R,
+            z_f: r_f + c * blinding,
+            z_v: r_v.iter().map(|(j, r)| r + c * values[*j]).collect(),
+        };
+        Ok((value, opening))
+    }
+}
+
+impl VectorOpening {
+    /// Verifies that the commitment contains the value at the index.
+    /// Returns the point operation to be checked together with the transaction,
+    /// or fails with `VMError::BadArguments` if the index is out of range
+    /// or the proof does not match the length of the commitment.
+    pub fn verify(
+        &self,
+        transcript: &mut Transcript,
+        commitment: &VectorCommitment,
+        index: usize,
+        value: &Scalar,
+    ) -> Result<PointOp, VMError> {
+        let n = commitment.len();
+        if index >= n || self.z_v.len() + 1 != n {
+            return Err(VMError::BadArguments);
+        }
+        let V = commitment.to_point();
+        let c = Self::challenge(transcript, n, &V, index, value, &self.R);
+
+        // `0 == sum{z_j·G_j} + z_f·B2 - R - c·(V - v_i·G_i)`
+        let mut arbitrary = (0..n)
+            .filter(|&j| j != index)
+            .zip(self.z_v.iter())
+            .map(|(j, z)| (*z, vector_generator(j).compress()))
+            .collect::<Vec<_>>();
+        arbitrary.push((-Scalar::one(), self.R));
+        arbitrary.push((-c, V));
+        arbitrary.push((c * value, vector_generator(index).compress()));
+        Ok(PointOp {
+            primary: None,
+            secondary: Some(self.z_f),
+            arbitrary,
+        })
+    }
+
+    /// Returns the number of bytes needed to serialize the opening.
+    pub fn serialized_length(&self) -> usize {
+        32 + 32 + 4 + 32 * self.z_v.len()
+    }
+
+    /// Encodes the opening as a byte array.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(self.serialized_length());
+        encoding::write_point(&self.R, &mut buf);
+        encoding::write_scalar(&self.z_f, &mut buf);
+        encoding::write_size(self.z_v.len(), &mut buf);
+        for z in self.z_v.iter() {
+            encoding::write_scalar(z, &mut buf);
+        }
+        buf
+    }
+
+    /// Decodes the opening from a byte array.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, VMError> {
+        SliceReader::parse(bytes, |r| {
+            let R = r.read_point()?;
+            let z_f = r.read_scalar()?;
+            let n = r.read_size()?;
+            let mut z_v = Vec::with_capacity(n.min(r.len() / 32));
+            for _ in 0..n {
+                z_v.push(r.read_scalar()?);
+            }
+            Ok(VectorOpening { R, z_f, z_v })
+        })
+    }
+
+    fn challenge(
+        transcript: &mut Transcript,
+        n: usize,
+        V: &CompressedRistretto,
+        index: usize,
+        value: &Scalar,
+        R: &CompressedRistretto,
+    ) -> Scalar {
+        transcript.commit_bytes(b"dom-sep", b"ZkVM.vector-opening");
+        transcript.commit_u64(b"n", n as u64);
+        transcript.commit_point(b"V", V);
+        transcript.commit_u64(b"index", index as u64);
+        transcript.commit_scalar(b"v", value);
+        transcript.commit_point(b"R", R);
+        transcript.challenge_scalar(b"c")
+    }
+}
+
+impl Zeroize for VectorCommitmentWitness {
+    fn zeroize(&mut self) {
+        for v in self.values.iter_mut() {
+            v.zeroize();
+        }
+        self.blinding.zeroize();
+    }
+}
+
+impl Drop for VectorCommitmentWitness {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scalars(values: &[u64]) -> Vec<Scalar> {
+        values.iter().map(|v| Scalar::from(*v)).collect()
+    }
+
+    #[test]
+    fn open_and_verify() {
+        let commitment = VectorCommitment::blinded(scalars(&[10, 20, 30]));
+        let closed = VectorCommitment::Closed(3, commitment.to_point());
+
+        for index in 0..3 {
+            let (value, opening) = commitment
+                .open(&mut Transcript::new(b"vector"), index)
+                .unwrap();
+            assert_eq!(value, Scalar::from(10u64 * (index as u64 + 1)));
+            let opening = VectorOpening::from_bytes(&opening.to_bytes()).unwrap();
+            let op = opening
+                .verify(&mut Transcript::new(b"vector"), &closed, index, &value)
+                .unwrap();
+            assert!(op.verify().is_ok());
+        }
+    }
+
+    #[test]
+    fn wrong_statement() {
+        let commitment = VectorCommitment::blinded(scalars(&[1, 2]));
+        let (value, opening) = commitment.open(&mut Transcript::new(b"vector"), 1).unwrap();
+
+        let verify = |index: usize, value: u64, label: &'static [u8]| {
+            opening
+                .verify(
+                    &mut Transcript::new(label),
+                    &commitment,
+                    index,
+                    &Scalar::from(value),
+                )
+                .and_then(|op| op.verify())
+        };
+        assert_eq!(value, Scalar::from(2u64));
+        assert!(verify(1, 2, b"vector").is_ok());
+        assert!(verify(1, 3, b"vector").is_err());
+        assert!(verify(0, 2, b"vector").is_err());
+        assert!(verify(1, 2, b"other context").is_err());
+        assert_eq!(verify(2, 2, b"vector").unwrap_err(), VMError::BadArguments);
+
+        let closed = VectorCommitment::Closed(2, commitment.to_point());
+        assert_eq!(
+            closed.open(&mut Transcript::new(b"vector"), 0).unwrap_err(),
+            VMError::WitnessMissing
+        );
+    }
+
+    #[test]
+    fn encoding() {
+        let commitment = VectorCommitment::blinded_with_factor(scalars(&[1, 2, 3]), Scalar::one());
+        let mut buf = Vec::new();
+        commitment.encode(&mut buf);
+        assert_eq!(buf.len(), commitment.serialized_length());
+        let decoded = SliceReader::parse(&buf, |r| VectorCommitment::decode(r)).unwrap();
+        assert_eq!(decoded.len(), 3);
+        assert_eq!(decoded.to_point(), commitment.to_point());
+    }
+}
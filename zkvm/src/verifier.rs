@@ -1,17 +1,26 @@
 use bulletproofs::r1cs;
-use bulletproofs::{BulletproofGens, PedersenGens};
+use bulletproofs::r1cs::R1CSProof;
+use bulletproofs::BulletproofGens;
 use curve25519_dalek::ristretto::CompressedRistretto;
 use merlin::Transcript;
+use std::borrow::Cow;
 
+use crate::circuit_size::{CircuitSize, R1CSProofMetrics};
 use crate::constraints::Commitment;
 use crate::encoding::*;
 use crate::errors::VMError;
+use crate::fees::FeeRate;
 use crate::ops::Instruction;
 use crate::point_ops::PointOp;
 use crate::predicate::Predicate;
-use crate::signature::VerificationKey;
+use crate::proof_system::{Bulletproofs, ProofSystem};
+use crate::signature::{Signature, VerificationKey};
+use crate::transcript::{labels, transcript_for_tx};
+use crate::txlog::{TxID, WTxID};
 use crate::types::Data;
-use crate::vm::{Delegate, Tx, VerifiedTx, VM};
+use crate::vm::{
+    serialized_tx_size, Delegate, Inspection, Tx, TxHeader, TxRef, VMParams, VerifiedTx, VM,
+};
 
 /// This is the entry point API for verifying a transaction.
 /// Verifier passes the `Tx` object through the VM,
@@ -21,23 +30,37 @@ use crate::vm::{Delegate, Tx, VerifiedTx, VM};
 pub struct Verifier<'a, 'b> {
     signtx_keys: Vec<VerificationKey>,
     deferred_operations: Vec<PointOp>,
+    circuit_size: CircuitSize,
+    commitments: usize,
     cs: r1cs::Verifier<'a, 'b>,
 }
 
-pub struct VerifierRun {
-    program: Vec<u8>,
+/// Transaction verified except for its deferred point operations
+/// (the transaction signature and the other Schnorr-style proofs).
+/// It becomes a `VerifiedTx` only when its point operations are verified with `verify`,
+/// or together with the point operations of other transactions with `verify_batch`.
+pub struct DeferredTx {
+    tx: VerifiedTx,
+    point_ops: Vec<PointOp>,
+}
+
+/// The program of the transaction is borrowed from the decoded `TxRef`,
+/// while the programs called by `call`, `delegate` and `contract` are owned by their runs.
+pub struct VerifierRun<'a> {
+    program: Cow<'a, [u8]>,
     offset: usize,
 }
 
 impl<'a, 'b> Delegate<r1cs::Verifier<'a, 'b>> for Verifier<'a, 'b> {
-    type RunType = VerifierRun;
+    type RunType = VerifierRun<'a>;
 
     fn commit_variable(
         &mut self,
         com: &Commitment,
     ) -> Result<(CompressedRistretto, r1cs::Variable), VMError> {
         let point = com.to_point();
-        let var = self.cs.commit(point);
+        let var = Bulletproofs::commit_point(&mut self.cs, point);
+        self.commitments += 1;
         Ok((point, var))
     }
 
@@ -53,6 +76,10 @@ impl<'a, 'b> Delegate<r1cs::Verifier<'a, 'b>> for Verifier<'a, 'b> {
         Ok(self.signtx_keys.push(VerificationKey(pred.to_point())))
     }
 
+    fn inspect(&mut self, _inspection: Inspection) {
+        // The debug instruction is not encoded, so the verifier never executes it.
+    }
+
     fn next_instruction(
         &mut self,
         run: &mut Self::RunType,
@@ -64,11 +91,12 @@ impl<'a, 'b> Delegate<r1cs::Verifier<'a, 'b>> for Verifier<'a, 'b> {
             Ok((Instruction::parse(r)?, r.skip_trailing_bytes()))
         })?;
         run.offset = run.program.len() - remainder;
+        self.circuit_size += CircuitSize::instruction(&instr);
         Ok(Some(instr))
     }
 
     fn new_run(&self, prog: Data) -> Result<Self::RunType, VMError> {
-        Ok(VerifierRun::new(prog.to_bytes()))
+        Ok(VerifierRun::new(Cow::Owned(prog.to_bytes())))
     }
 
     fn cs(&mut self) -> &mut r1cs::Verifier<'a, 'b> {
@@ -80,47 +108,233 @@ impl<'a, 'b> Verifier<'a, 'b> {
     /// Verifies the `Tx` object by executing the VM and returns the `VerifiedTx`.
     /// Returns an error if the program is malformed or any of the proofs are not valid.
     pub fn verify_tx<'g>(tx: Tx, bp_gens: &'g BulletproofGens) -> Result<VerifiedTx, VMError> {
-        let mut r1cs_transcript = Transcript::new(b"ZkVM.r1cs");
-        let pc_gens = PedersenGens::default();
-        let cs = r1cs::Verifier::new(bp_gens, &pc_gens, &mut r1cs_transcript);
+        Self::verify_tx_with_point_ops(tx, bp_gens, Vec::new())
+    }
+
+    /// Verifies the `Tx` object together with the additional point operations,
+    /// such as the proofs of `EncryptedOpening`, in the same batch
+    /// with the transaction signature and the other deferred operations.
+    pub fn verify_tx_with_point_ops<'g>(
+        tx: Tx,
+        bp_gens: &'g BulletproofGens,
+        point_ops: Vec<PointOp>,
+    ) -> Result<VerifiedTx, VMError> {
+        let (vtx, mut deferred) = Self::verify(
+            tx.header,
+            Cow::Owned(tx.program),
+            &tx.signature,
+            &tx.proof,
+            &VMParams::default(),
+            bp_gens,
+            |_| false,
+        )?;
+        deferred.extend(point_ops);
+        PointOp::verify_batch(&deferred)?;
+        Ok(vtx)
+    }
+
+    /// Verifies the `Tx` object except for the deferred point operations
+    /// (the transaction signature and the other Schnorr-style proofs),
+    /// and returns the `DeferredTx` whose point operations are verified by the caller.
+    /// The point operations of many transactions can be verified with `DeferredTx::verify_batch`
+    /// in a single multi-scalar multiplication.
+    pub fn verify_tx_deferred<'g>(
+        tx: Tx,
+        bp_gens: &'g BulletproofGens,
+    ) -> Result<DeferredTx, VMError> {
+        Self::verify_deferred(
+            tx.header,
+            Cow::Owned(tx.program),
+            &tx.signature,
+            &tx.proof,
+            &VMParams::default(),
+            bp_gens,
+            |_| false,
+        )
+    }
+
+    /// Verifies the `Tx` object like `verify_tx_deferred`, but skips the R1CS proof
+    /// and the point operations if `is_verified` returns true for the witness transaction ID,
+    /// e.g. if the transaction was verified when it entered the mempool.
+    /// The VM is still executed to compute the transaction ID and the log.
+    /// The witness ID commits to the program, the signature and the proof,
+    /// so a cached transaction cannot be altered without changing its witness ID.
+    pub fn verify_tx_cached<'g, F>(
+        tx: Tx,
+        bp_gens: &'g BulletproofGens,
+        is_verified: F,
+    ) -> Result<DeferredTx, VMError>
+    where
+        F: FnOnce(&WTxID) -> bool,
+    {
+        Self::verify_tx_with_params(tx, &VMParams::default(), bp_gens, is_verified)
+    }
+
+    /// Verifies the `Tx` object like `verify_tx_cached` for a network with the VM parameters
+    /// other than the default ones.
+    pub fn verify_tx_with_params<'g, F>(
+        tx: Tx,
+        params: &VMParams,
+        bp_gens: &'g BulletproofGens,
+        is_verified: F,
+    ) -> Result<DeferredTx, VMError>
+    where
+        F: FnOnce(&WTxID) -> bool,
+    {
+        Self::verify_deferred(
+            tx.header,
+            Cow::Owned(tx.program),
+            &tx.signature,
+            &tx.proof,
+            params,
+            bp_gens,
+            is_verified,
+        )
+    }
+
+    /// Verifies the `TxRef` object decoded with `TxRef::from_bytes`,
+    /// executing the program in place without copying it out of the serialized transaction.
+    pub fn verify_tx_ref<'g>(
+        tx: &TxRef,
+        bp_gens: &'g BulletproofGens,
+    ) -> Result<VerifiedTx, VMError> {
+        let (vtx, point_ops) = Self::verify(
+            tx.header,
+            Cow::Borrowed(tx.program),
+            &tx.signature,
+            &tx.proof,
+            &VMParams::default(),
+            bp_gens,
+            |_| false,
+        )?;
+        PointOp::verify_batch(&point_ops)?;
+        Ok(vtx)
+    }
+
+    fn verify_deferred<'g, F>(
+        header: TxHeader,
+        program: Cow<[u8]>,
+        signature: &Signature,
+        proof: &R1CSProof,
+        params: &VMParams,
+        bp_gens: &'g BulletproofGens,
+        is_verified: F,
+    ) -> Result<DeferredTx, VMError>
+    where
+        F: FnOnce(&WTxID) -> bool,
+    {
+        let (tx, point_ops) = Self::verify(
+            header,
+            program,
+            signature,
+            proof,
+            params,
+            bp_gens,
+            is_verified,
+        )?;
+        Ok(DeferredTx { tx, point_ops })
+    }
+
+    fn verify<'g, F>(
+        header: TxHeader,
+        program: Cow<[u8]>,
+        signature: &Signature,
+        proof: &R1CSProof,
+        params: &VMParams,
+        bp_gens: &'g BulletproofGens,
+        is_verified: F,
+    ) -> Result<(VerifiedTx, Vec<PointOp>), VMError>
+    where
+        F: FnOnce(&WTxID) -> bool,
+    {
+        let mut r1cs_transcript = Transcript::new(labels::R1CS);
+        let cs = Bulletproofs::verifier(bp_gens, &mut r1cs_transcript);
 
         let mut verifier = Verifier {
             signtx_keys: Vec::new(),
             deferred_operations: Vec::new(),
+            circuit_size: CircuitSize::default(),
+            commitments: 0,
             cs: cs,
         };
 
-        let vm = VM::new(tx.header, VerifierRun::new(tx.program), &mut verifier);
+        let wtxid_transcript = WTxID::witness_transcript(&program, signature, proof);
+        let tx_size = serialized_tx_size(&program, proof);
+        let vm = VM::new(header, params, VerifierRun::new(program), &mut verifier);
 
         let (txid, txlog) = vm.run()?;
+        let wtxid = WTxID::from_transcript(wtxid_transcript, &txid);
+        let circuit_size = verifier.circuit_size;
+
+        if is_verified(&wtxid) {
+            verifier.deferred_operations.clear();
+        } else {
+            // Verify the signatures over txid
+            let mut signtx_transcript = transcript_for_tx(&txid);
 
-        // Verify the signatures over txid
-        let mut signtx_transcript = Transcript::new(b"ZkVM.signtx");
-        signtx_transcript.commit_bytes(b"txid", &txid.0);
-
-        let signtx_point_op = tx
-            .signature
-            .verify_aggregated(&mut signtx_transcript, &verifier.signtx_keys);
-        verifier.deferred_operations.push(signtx_point_op);
-        // Verify all deferred crypto operations.
-        PointOp::verify_batch(&verifier.deferred_operations[..])?;
-
-        // Verify the R1CS proof
-        verifier
-            .cs
-            .verify(&tx.proof)
-            .map_err(|_| VMError::InvalidR1CSProof)?;
-
-        Ok(VerifiedTx {
-            header: tx.header,
+            let signtx_point_op =
+                signature.verify_aggregated(&mut signtx_transcript, &verifier.signtx_keys);
+            verifier.deferred_operations.push(signtx_point_op);
+
+            // Verify the R1CS proof
+            Bulletproofs::verify(verifier.cs, proof).map_err(|_| {
+                circuit_size
+                    .insufficient_generators(bp_gens)
+                    .unwrap_or(VMError::InvalidR1CSProof)
+            })?;
+        }
+
+        let feerate = FeeRate::new(FeeRate::total_fee(&txlog)?, tx_size);
+        let vtx = VerifiedTx {
+            header,
             id: txid,
+            wtxid,
             log: txlog,
-        })
+            feerate,
+            proof_metrics: R1CSProofMetrics::new(circuit_size, verifier.commitments, proof),
+        };
+        // The deferred crypto operations are verified by the caller.
+        Ok((vtx, verifier.deferred_operations))
+    }
+}
+
+impl DeferredTx {
+    /// Returns the ID of the transaction.
+    pub fn id(&self) -> TxID {
+        self.tx.id
+    }
+
+    /// Returns the witness ID of the transaction.
+    pub fn wtxid(&self) -> WTxID {
+        self.tx.wtxid
+    }
+
+    /// Returns the deferred point operations. They are empty if the transaction
+    /// was found in the cache of the verified transactions.
+    pub fn point_ops(&self) -> &[PointOp] {
+        &self.point_ops
+    }
+
+    /// Verifies the deferred point operations and returns the verified transaction.
+    pub fn verify(self) -> Result<VerifiedTx, VMError> {
+        PointOp::verify_batch(&self.point_ops)?;
+        Ok(self.tx)
+    }
+
+    /// Verifies the deferred point operations of all the transactions
+    /// in a single multi-scalar multiplication and returns the verified transactions.
+    pub fn verify_batch(txs: Vec<DeferredTx>) -> Result<Vec<VerifiedTx>, VMError> {
+        let point_ops = txs
+            .iter()
+            .flat_map(|tx| tx.point_ops.iter().cloned())
+            .collect::<Vec<_>>();
+        PointOp::verify_batch(&point_ops)?;
+        Ok(txs.into_iter().map(|tx| tx.tx).collect())
     }
 }
 
-impl VerifierRun {
-    fn new(program: Vec<u8>) -> Self {
+impl<'a> VerifierRun<'a> {
+    fn new(program: Cow<'a, [u8]>) -> Self {
         VerifierRun { program, offset: 0 }
     }
 }
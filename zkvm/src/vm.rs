@@ -3,31 +3,68 @@ use bulletproofs::r1cs::R1CSProof;
 use curve25519_dalek::ristretto::CompressedRistretto;
 use curve25519_dalek::scalar::Scalar;
 use merlin::Transcript;
-use serde::de::Visitor;
-use serde::{self, Deserialize, Deserializer, Serialize, Serializer};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 use spacesuit;
-use spacesuit::BitRange;
-use std::iter::FromIterator;
+use spacesuit::{BitRange, SignedInteger};
+use std::io;
 use std::mem;
 
+use crate::circuit_size::R1CSProofMetrics;
 use crate::constraints::{Commitment, Constraint, Expression, Variable};
 use crate::contract::{Anchor, Contract, Output, PortableItem};
+use crate::cost;
+use crate::coverage::Branch;
 use crate::encoding;
-use crate::encoding::SliceReader;
-use crate::errors::VMError;
-use crate::ops::Instruction;
+use crate::encoding::{Reader, SliceReader, Writer};
+use crate::errors::{ErrorContext, VMError};
+use crate::fees::FeeRate;
+use crate::gadgets;
+use crate::ops::{Instruction, Opcode};
 use crate::point_ops::PointOp;
+use crate::poseidon::Poseidon;
 use crate::predicate::Predicate;
 use crate::scalar_witness::ScalarWitness;
 use crate::signature::*;
-use crate::txlog::{Entry, TxID, TxLog};
+use crate::transcript::labels;
+use crate::txlog::{Entry, TxID, TxLog, WTxID};
 use crate::types::*;
 
 /// Current tx version determines which extension opcodes are treated as noops (see VM.extension flag).
 pub const CURRENT_VERSION: u64 = 1;
 
+/// Tx version from which the `range` instruction accepts widths up to 252 bits instead of 64 bits.
+pub const WIDE_RANGE_VERSION: u64 = 2;
+
+/// Size of the encoded transaction header: four LE64 integers.
+const TX_HEADER_SIZE: usize = 8 * 4;
+
+/// Parameters of the VM configured by the network (see `Verifier::verify_tx_with_params`).
+/// Transactions are valid only with the parameters they were built with
+/// (see `Prover::build_tx_with_params`).
+#[derive(Clone, Debug, PartialEq)]
+pub struct VMParams {
+    /// Maximum number of the nested programs executed with `call` and `delegate`
+    /// at any point of the execution. The transaction's program is not counted.
+    pub max_program_depth: usize,
+
+    /// Flavor of the values created by the `fee` instruction.
+    pub fee_flavor: Scalar,
+}
+
+impl Default for VMParams {
+    /// Parameters of the main network: unlimited nesting and the zero fee flavor.
+    fn default() -> Self {
+        VMParams {
+            max_program_depth: usize::max_value(),
+            fee_flavor: Scalar::zero(),
+        }
+    }
+}
+
 /// Header metadata for the transaction
 #[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct TxHeader {
     /// Version of the transaction
     pub version: u64,
@@ -37,17 +74,27 @@ pub struct TxHeader {
 
     /// Timestamp after which tx is invalid (in milliseconds since the Unix epoch)
     pub maxtime: u64,
+
+    /// Maximum cost of verifying the transaction, in the units of the ZkVM cost model
+    pub maxcost: u64,
 }
 
 impl TxHeader {
-    fn serialized_size(&self) -> usize {
-        8 * 3
+    /// Returns the `mintime` as a constant expression, as pushed by the `mintime` instruction.
+    pub fn mintime_expr(&self) -> Expression {
+        Expression::constant(self.mintime)
+    }
+
+    /// Returns the `maxtime` as a constant expression, as pushed by the `maxtime` instruction.
+    pub fn maxtime_expr(&self) -> Expression {
+        Expression::constant(self.maxtime)
     }
 
     fn encode(&self, buf: &mut Vec<u8>) {
         encoding::write_u64(self.version, buf);
         encoding::write_u64(self.mintime, buf);
         encoding::write_u64(self.maxtime, buf);
+        encoding::write_u64(self.maxcost, buf);
     }
 
     fn decode<'a>(reader: &mut SliceReader<'a>) -> Result<Self, VMError> {
@@ -55,11 +102,13 @@ impl TxHeader {
             version: reader.read_u64()?,
             mintime: reader.read_u64()?,
             maxtime: reader.read_u64()?,
+            maxcost: reader.read_u64()?,
         })
     }
 }
 
 /// Instance of a transaction that contains all necessary data to validate it.
+#[derive(Clone)]
 pub struct Tx {
     /// Header metadata
     pub header: TxHeader,
@@ -74,6 +123,23 @@ pub struct Tx {
     pub proof: R1CSProof,
 }
 
+/// Transaction decoded without copying its program out of the serialized buffer.
+/// Verified in place with `Verifier::verify_tx_ref`.
+#[derive(Clone)]
+pub struct TxRef<'a> {
+    /// Header metadata
+    pub header: TxHeader,
+
+    /// Program representing the transaction, borrowed from the encoded transaction
+    pub program: &'a [u8],
+
+    /// Aggregated signature of the txid
+    pub signature: Signature,
+
+    /// Constraint system proof for all the constraints
+    pub proof: R1CSProof,
+}
+
 impl Tx {
     fn encode(&self, buf: &mut Vec<u8>) {
         self.header.encode(buf);
@@ -84,13 +150,40 @@ impl Tx {
     }
 
     fn decode<'a>(r: &mut SliceReader<'a>) -> Result<Tx, VMError> {
-        let header = TxHeader::decode(r)?;
-        let prog_len = r.read_size()?;
-        let program = r.read_bytes(prog_len)?.to_vec();
+        TxRef::decode(r).map(|tx| tx.to_owned())
+    }
 
+    /// Serializes the tx into a byte array.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(self.serialized_size());
+        self.encode(&mut buf);
+        buf
+    }
+
+    /// Writes the tx directly into a stream, e.g. a file or a socket.
+    /// Produces the same encoding as `to_bytes`.
+    pub fn write_to<W: io::Write>(&self, w: W) -> io::Result<()> {
+        let mut w = Writer::new(w);
+        let mut header = Vec::with_capacity(TX_HEADER_SIZE);
+        self.header.encode(&mut header);
+        w.write_bytes(&header)?;
+        w.write_size(self.program.len())?;
+        w.write_bytes(&self.program)?;
+        w.write_bytes(&self.signature.to_bytes())?;
+        w.write_bytes(&self.proof.to_bytes())
+    }
+
+    /// Reads the tx encoded with `to_bytes` or `write_to` from a stream.
+    /// The proof occupies the rest of the encoding, so the stream is consumed until the end:
+    /// to keep several transactions in one stream, prefix each of them with its length
+    /// (see `Writer::write_size`).
+    pub fn read_from<R: io::Read>(r: R) -> Result<Tx, VMError> {
+        let mut r = Reader::new(r);
+        let header = SliceReader::parse(&r.read_bytes(TX_HEADER_SIZE)?, TxHeader::decode)?;
+        let prog_len = r.read_size()?;
+        let program = r.read_bytes(prog_len)?;
         let signature = Signature::from_bytes(r.read_u8x64()?)?;
-        let proof =
-            R1CSProof::from_bytes(r.read_bytes(r.len())?).map_err(|_| VMError::FormatError)?;
+        let proof = R1CSProof::from_bytes(&r.read_to_end()?).map_err(|_| VMError::FormatError)?;
         Ok(Tx {
             header,
             program,
@@ -99,21 +192,9 @@ impl Tx {
         })
     }
 
-    /// Serializes the tx into a byte array.
-    pub fn to_bytes(&self) -> Vec<u8> {
-        let mut buf = Vec::with_capacity(self.serialized_size());
-        self.encode(&mut buf);
-        buf
-    }
-
     /// Returns the size in bytes required to serialize the `Tx`.
     pub fn serialized_size(&self) -> usize {
-        // header is 8 bytes * 3 fields = 24 bytes
-        // program length is 4 bytes
-        // program is self.program.len() bytes
-        // signature is 64 bytes
-        // proof is 14*32 + the ipp bytes
-        self.header.serialized_size() + 4 + self.program.len() + 64 + self.proof.serialized_size()
+        serialized_tx_size(&self.program, &self.proof)
     }
 
     /// Deserializes the tx from a byte slice.
@@ -122,52 +203,151 @@ impl Tx {
     pub fn from_bytes(slice: &[u8]) -> Result<Tx, VMError> {
         SliceReader::parse(slice, |r| Self::decode(r))
     }
+
+    /// Performs a cheap structural check of the transaction decoded with `from_bytes`,
+    /// so a relay can drop malformed transactions before verifying them:
+    /// checks that `mintime` does not exceed `maxtime`, that the serialized transaction
+    /// is at most `max_tx_size` bytes (the consensus limit of the network),
+    /// that the program consists of well-formed instructions
+    /// (with no extension opcodes unless the version allows them),
+    /// and that the signature's nonce commitment is a valid point.
+    ///
+    /// Neither the program is executed nor the signature and the R1CS proof are verified,
+    /// so a transaction that passes the check may still be invalid.
+    /// Programs executed by `call` and `delegate` are not checked.
+    pub fn precheck(&self, max_tx_size: usize) -> Result<(), VMError> {
+        if self.header.mintime > self.header.maxtime {
+            return Err(VMError::InvalidTimeBounds);
+        }
+        let size = self.serialized_size();
+        if size > max_tx_size {
+            return Err(VMError::TxTooLarge {
+                size,
+                limit: max_tx_size,
+            });
+        }
+        let extension = self.header.version > CURRENT_VERSION;
+        SliceReader::parse(&self.program, |r| {
+            while r.len() > 0 {
+                if let Instruction::Ext(opcode) = Instruction::parse(r)? {
+                    if !Opcode::is_nop(opcode) && !extension {
+                        return Err(VMError::ExtensionsNotAllowed);
+                    }
+                }
+            }
+            Ok(())
+        })?;
+        self.signature.check_format()
+    }
+}
+
+impl<'a> TxRef<'a> {
+    fn decode(r: &mut SliceReader<'a>) -> Result<Self, VMError> {
+        let header = TxHeader::decode(r)?;
+        let prog_len = r.read_size()?;
+        let program = r.read_bytes(prog_len)?;
+
+        let signature = Signature::from_bytes(r.read_u8x64()?)?;
+        let proof =
+            R1CSProof::from_bytes(r.read_bytes(r.len())?).map_err(|_| VMError::FormatError)?;
+        Ok(TxRef {
+            header,
+            program,
+            signature,
+            proof,
+        })
+    }
+
+    /// Deserializes the tx from a byte slice, borrowing the program from it.
+    ///
+    /// Returns an error if the byte slice cannot be parsed into a `Tx`.
+    pub fn from_bytes(slice: &'a [u8]) -> Result<Self, VMError> {
+        SliceReader::parse(slice, |r| Self::decode(r))
+    }
+
+    /// Returns the size in bytes required to serialize the tx.
+    pub fn serialized_size(&self) -> usize {
+        serialized_tx_size(self.program, &self.proof)
+    }
+
+    /// Copies the program into an owned `Tx`.
+    pub fn to_tx(&self) -> Tx {
+        Tx {
+            header: self.header,
+            program: self.program.to_vec(),
+            signature: self.signature,
+            proof: self.proof.clone(),
+        }
+    }
+}
+
+/// Returns the size of the serialized transaction with the program and the proof.
+pub(crate) fn serialized_tx_size(program: &[u8], proof: &R1CSProof) -> usize {
+    // header is 8 bytes * 4 fields = 32 bytes
+    // program length is 4 bytes
+    // program is program.len() bytes
+    // signature is 64 bytes
+    // proof is 14*32 + the ipp bytes
+    TX_HEADER_SIZE + 4 + program.len() + 64 + proof.serialized_size()
 }
 
+// Transactions are serialized as byte strings in all formats, as before the `serde` feature
+// was introduced, so that the existing JSON encodings of transactions remain valid.
+#[cfg(feature = "serde")]
 impl Serialize for Tx {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
-        S: Serializer,
+        S: serde::Serializer,
     {
         serializer.serialize_bytes(&self.to_bytes()[..])
     }
 }
+
+#[cfg(feature = "serde")]
 impl<'de> Deserialize<'de> for Tx {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
-        D: Deserializer<'de>,
+        D: serde::Deserializer<'de>,
     {
-        struct TxVisitor;
-
-        impl<'de> Visitor<'de> for TxVisitor {
-            type Value = Tx;
-
-            fn expecting(&self, formatter: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {
-                formatter.write_str("a valid Tx")
-            }
-
-            fn visit_bytes<E>(self, v: &[u8]) -> Result<Tx, E>
-            where
-                E: serde::de::Error,
-            {
-                Tx::from_bytes(v).map_err(serde::de::Error::custom)
-            }
-        }
-
-        deserializer.deserialize_bytes(TxVisitor)
+        crate::serialization::deserialize_raw_bytes(deserializer, "a valid Tx", Tx::from_bytes)
     }
 }
 
 /// Represents a verified transaction: a txid and a list of state updates.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct VerifiedTx {
     /// Transaction header
     pub header: TxHeader,
 
-    /// Transaction ID
+    /// Transaction ID: commits to the header and the log, but not to the witness data,
+    /// so it cannot be changed without changing the effects of the transaction.
     pub id: TxID,
 
+    /// Witness transaction ID: also commits to the program, the signature and the proof.
+    pub wtxid: WTxID,
+
     /// Transaction log: a list of changes to the blockchain state (UTXOs to delete/insert, etc.)
     pub log: TxLog,
+
+    /// Fee rate: total fee paid with the `fee` instructions per byte of the transaction.
+    pub feerate: FeeRate,
+
+    /// Size of the proof and the estimated cost of its verification.
+    pub proof_metrics: R1CSProofMetrics,
+}
+
+/// A record of the prover's debug instruction `inspect`: the label
+/// and the state of the stack when the instruction was executed.
+#[derive(Clone, Debug)]
+pub struct Inspection {
+    /// Label of the instruction.
+    pub label: String,
+
+    /// Number of items on the stack.
+    pub stack_depth: usize,
+
+    /// The item on the top of the stack, in the debug format.
+    pub item: Option<String>,
 }
 
 pub(crate) struct VM<'d, CS, D>
@@ -182,16 +362,37 @@ where
     // we allow treating unassigned opcodes as no-ops.
     extension: bool,
 
+    // is true when tx version allows range proofs wider than 64 bits.
+    wide_range: bool,
+
     // updated by nonce/input/issue/contract/output instructions
     last_anchor: Option<Anchor>,
 
+    // total quantity paid with fee instructions
+    total_fee: u64,
+
+    // cost of the executed instructions and the limit set by the tx header
+    cost: u64,
+    maxcost: u64,
+
+    // number of the executed instructions, including the nested programs
+    instruction_index: usize,
+
+    // limit of the nested programs and the flavor of the fees set by the network
+    max_program_depth: usize,
+    fee_flavor: Scalar,
+
     // stack of all items in the VM
     stack: Vec<Item>,
 
     delegate: &'d mut D,
 
     current_run: D::RunType,
-    run_stack: Vec<D::RunType>,
+    run_stack: Vec<(D::RunType, Option<Predicate>)>,
+
+    // predicate of the contract whose program is executed by `call` or `delegate`,
+    // `None` for the transaction's program
+    current_predicate: Option<Predicate>,
     txlog: TxLog,
 }
 
@@ -213,6 +414,13 @@ pub(crate) trait Delegate<CS: r1cs::ConstraintSystem> {
     /// sign a transaction
     fn process_tx_signature(&mut self, pred: Predicate) -> Result<(), VMError>;
 
+    /// Records the state of the stack for the prover's debug instruction `inspect`.
+    fn inspect(&mut self, inspection: Inspection);
+
+    /// Records the branch of the predicate tree taken by `select`, `call` or `delegate`
+    /// for the prover's coverage report.
+    fn exercise_branch(&mut self, _predicate: &Predicate, _branch: Branch) {}
+
     /// Returns the delegate's underlying constraint system
     fn cs(&mut self) -> &mut CS;
 
@@ -232,16 +440,24 @@ where
     D: Delegate<CS>,
 {
     /// Instantiates a new VM instance.
-    pub fn new(header: TxHeader, run: D::RunType, delegate: &'d mut D) -> Self {
+    pub fn new(header: TxHeader, params: &VMParams, run: D::RunType, delegate: &'d mut D) -> Self {
         VM {
             mintime: header.mintime,
             maxtime: header.maxtime,
             extension: header.version > CURRENT_VERSION,
+            wide_range: header.version >= WIDE_RANGE_VERSION,
             last_anchor: None,
+            total_fee: 0,
+            cost: 0,
+            maxcost: header.maxcost,
+            instruction_index: 0,
+            max_program_depth: params.max_program_depth,
+            fee_flavor: params.fee_flavor,
             delegate,
             stack: Vec::new(),
             current_run: run,
             run_stack: Vec::new(),
+            current_predicate: None,
             txlog: vec![Entry::Header(header)],
         }
     }
@@ -269,9 +485,10 @@ where
 
     fn finish_run(&mut self) -> bool {
         // Do we have more programs to run?
-        if let Some(run) = self.run_stack.pop() {
+        if let Some((run, predicate)) = self.run_stack.pop() {
             // Continue with the previously remembered program
             self.current_run = run;
+            self.current_predicate = predicate;
             return true;
         }
         // Finish the execution
@@ -280,52 +497,101 @@ where
 
     /// Returns a flag indicating whether to continue the execution
     fn step(&mut self) -> Result<bool, VMError> {
-        if let Some(instr) = self.delegate.next_instruction(&mut self.current_run)? {
-            // Attempt to read the next instruction and advance the program state
-            match instr {
-                // the data is just a slice, so the clone would copy the slice struct,
-                // not the actual buffer of bytes.
-                Instruction::Push(data) => self.pushdata(data)?,
-                Instruction::Drop => self.drop()?,
-                Instruction::Dup(i) => self.dup(i)?,
-                Instruction::Roll(i) => self.roll(i)?,
-                Instruction::Const => self.r#const()?,
-                Instruction::Var => self.var()?,
-                Instruction::Alloc(sw) => self.alloc(sw)?,
-                Instruction::Mintime => self.mintime()?,
-                Instruction::Maxtime => self.maxtime()?,
-                Instruction::Expr => self.expr()?,
-                Instruction::Neg => self.neg()?,
-                Instruction::Add => self.add()?,
-                Instruction::Mul => self.mul()?,
-                Instruction::Eq => self.eq()?,
-                Instruction::Range(i) => self.range(i)?,
-                Instruction::And => self.and()?,
-                Instruction::Or => self.or()?,
-                Instruction::Not => self.not()?,
-                Instruction::Verify => self.verify()?,
-                Instruction::Unblind => self.unblind()?,
-                Instruction::Issue => self.issue()?,
-                Instruction::Borrow => self.borrow()?,
-                Instruction::Retire => self.retire()?,
-                Instruction::Cloak(m, n) => self.cloak(m, n)?,
-                Instruction::Import => unimplemented!(),
-                Instruction::Export => unimplemented!(),
-                Instruction::Input => self.input()?,
-                Instruction::Output(k) => self.output(k)?,
-                Instruction::Contract(k) => self.contract(k)?,
-                Instruction::Nonce => self.nonce()?,
-                Instruction::Log => self.log()?,
-                Instruction::Signtx => self.signtx()?,
-                Instruction::Call => self.call()?,
-                Instruction::Select(n, k) => self.select(n, k)?,
-                Instruction::Delegate => self.delegate()?,
-                Instruction::Ext(opcode) => self.ext(opcode)?,
-            }
-            return Ok(true);
-        } else {
+        let instr = match self.delegate.next_instruction(&mut self.current_run) {
+            Ok(Some(instr)) => instr,
             // Reached the end of the current program
-            return Ok(self.finish_run());
+            Ok(None) => return Ok(self.finish_run()),
+            Err(error) => {
+                let (stack_depth, stack_top) = self.stack_state();
+                return Err(self.instruction_failed(error, None, stack_depth, stack_top));
+            }
+        };
+
+        let mnemonic = instr.mnemonic();
+        let (stack_depth, stack_top) = self.stack_state();
+        if let Err(error) = self.execute(instr) {
+            return Err(self.instruction_failed(error, Some(mnemonic), stack_depth, stack_top));
+        }
+        self.instruction_index += 1;
+        Ok(true)
+    }
+
+    fn execute(&mut self, instr: Instruction) -> Result<(), VMError> {
+        self.charge(cost::instruction(&instr))?;
+
+        // Attempt to read the next instruction and advance the program state
+        match instr {
+            // the data is just a slice, so the clone would copy the slice struct,
+            // not the actual buffer of bytes.
+            Instruction::Push(data) => self.pushdata(data)?,
+            Instruction::Drop => self.drop()?,
+            Instruction::Dup(i) => self.dup(i)?,
+            Instruction::Roll(i) => self.roll(i)?,
+            Instruction::Const => self.r#const()?,
+            Instruction::Var => self.var()?,
+            Instruction::Alloc(sw) => self.alloc(sw)?,
+            Instruction::Mintime => self.mintime()?,
+            Instruction::Maxtime => self.maxtime()?,
+            Instruction::Expr => self.expr()?,
+            Instruction::Neg => self.neg()?,
+            Instruction::Add => self.add()?,
+            Instruction::Mul => self.mul()?,
+            Instruction::Eq => self.eq()?,
+            Instruction::Range(i) => self.range(i)?,
+            Instruction::Sha256(n) => self.sha2(&gadgets::SHA256, n)?,
+            Instruction::Sha512(n) => self.sha2(&gadgets::SHA512, n)?,
+            Instruction::Poseidon(n) => self.poseidon(n)?,
+            Instruction::And => self.and()?,
+            Instruction::Or => self.or()?,
+            Instruction::Not => self.not()?,
+            Instruction::Verify => self.verify()?,
+            Instruction::Unblind => self.unblind()?,
+            Instruction::Issue => self.issue()?,
+            Instruction::Borrow => self.borrow()?,
+            Instruction::Retire => self.retire()?,
+            Instruction::Fee => self.fee()?,
+            Instruction::Cloak(m, n) => self.cloak(m, n)?,
+            Instruction::Import => unimplemented!(),
+            Instruction::Export => unimplemented!(),
+            Instruction::Input => self.input()?,
+            Instruction::Output(k) => self.output(k)?,
+            Instruction::Contract(k) => self.contract(k)?,
+            Instruction::Nonce => self.nonce()?,
+            Instruction::Log => self.log()?,
+            Instruction::Nullify => self.nullify()?,
+            Instruction::Carry(k) => self.carry(k)?,
+            Instruction::Signtx => self.signtx()?,
+            Instruction::Call => self.call()?,
+            Instruction::Select(n, k) => self.select(n, k)?,
+            Instruction::Delegate => self.delegate()?,
+            Instruction::Ext(opcode) => self.ext(opcode)?,
+            Instruction::Inspect(label) => self.inspect(label),
+        }
+        Ok(())
+    }
+
+    fn stack_state(&self) -> (usize, Option<&'static str>) {
+        (self.stack.len(), self.stack.last().map(Item::type_name))
+    }
+
+    /// Wraps the error of an instruction with the context of the VM execution.
+    fn instruction_failed(
+        &self,
+        error: VMError,
+        instruction: Option<&'static str>,
+        stack_depth: usize,
+        stack_top: Option<&'static str>,
+    ) -> VMError {
+        VMError::InstructionFailed {
+            error: Box::new(error),
+            context: Box::new(ErrorContext {
+                instruction_index: self.instruction_index,
+                run_depth: self.run_stack.len(),
+                instruction,
+                stack_depth,
+                stack_top,
+                last_entry: self.txlog.last().map(|entry| format!("{:?}", entry)),
+            }),
         }
     }
 
@@ -356,6 +622,9 @@ where
             Item::Constraint(x) => Item::Constraint(x.clone()),
             _ => return Err(VMError::TypeNotCopyable),
         };
+        if let Item::Constraint(c) = &item {
+            self.charge(cost::constraint_copy(c))?;
+        }
         self.push_item(item);
         Ok(())
     }
@@ -407,12 +676,39 @@ where
     }
 
     fn range(&mut self, i: BitRange) -> Result<(), VMError> {
+        if BitRange::new(i.into()).is_none() && !self.wide_range {
+            return Err(VMError::InvalidBitrange);
+        }
         let expr = self.pop_item()?.to_expression()?;
         self.add_range_proof(i, expr.clone())?;
         self.push_item(expr);
         Ok(())
     }
 
+    /// _x[0] ... x[n-1] hash_ **sha256:_n_** → _constraint_
+    fn sha2(&mut self, sha2: &gadgets::Sha2, n: usize) -> Result<(), VMError> {
+        let hash = self.pop_item()?.to_data()?.to_bytes();
+        let mut preimage = Vec::new();
+        for _ in 0..n {
+            preimage.push(self.pop_item()?.to_expression()?);
+        }
+        preimage.reverse();
+        let constraint = sha2.preimage(self.delegate.cs(), &preimage, &hash)?;
+        self.push_item(constraint);
+        Ok(())
+    }
+
+    fn poseidon(&mut self, n: usize) -> Result<(), VMError> {
+        let mut inputs = Vec::new();
+        for _ in 0..n {
+            inputs.push(self.pop_item()?.to_expression()?);
+        }
+        inputs.reverse();
+        let hash = gadgets::poseidon(self.delegate.cs(), &Poseidon::new(), &inputs);
+        self.push_item(hash);
+        Ok(())
+    }
+
     fn and(&mut self) -> Result<(), VMError> {
         let c2 = self.pop_item()?.to_constraint()?;
         let c1 = self.pop_item()?.to_constraint()?;
@@ -475,12 +771,7 @@ where
     }
 
     fn alloc(&mut self, sw: Option<ScalarWitness>) -> Result<(), VMError> {
-        let var = self
-            .delegate
-            .cs()
-            .allocate(sw.map(|s| s.to_scalar()))
-            .map_err(|e| VMError::R1CSError(e))?;
-        let expr = Expression::LinearCombination(vec![(var, Scalar::one())], sw);
+        let expr = Expression::allocate(self.delegate.cs(), sw)?;
         self.push_item(expr);
         Ok(())
     }
@@ -517,6 +808,15 @@ where
         Ok(())
     }
 
+    /// _expr tag_ **nullify** → ø
+    fn nullify(&mut self) -> Result<(), VMError> {
+        let tag = self.pop_item()?.to_data()?.to_scalar()?.to_scalar();
+        let expr = self.pop_item()?.to_expression()?;
+        Constraint::Eq(expr, Expression::constant(tag)).verify(self.delegate.cs())?;
+        self.txlog.push(Entry::Nullifier(tag.to_bytes()));
+        Ok(())
+    }
+
     /// _qty flv data pred_ **issue** → _contract_
     fn issue(&mut self) -> Result<(), VMError> {
         let predicate = self.pop_item()?.to_data()?.to_predicate()?;
@@ -524,8 +824,8 @@ where
         let flv = self.pop_item()?.to_variable()?;
         let qty = self.pop_item()?.to_variable()?;
 
-        let (flv_point, _) = self.delegate.commit_variable(&flv.commitment)?;
-        let (qty_point, _) = self.delegate.commit_variable(&qty.commitment)?;
+        let (flv_point, _) = self.commit_variable(&flv.commitment)?;
+        let (qty_point, _) = self.commit_variable(&qty.commitment)?;
 
         self.delegate.verify_point_op(|| {
             let flv_scalar = Value::issue_flavor(&predicate, metadata);
@@ -543,7 +843,7 @@ where
         };
 
         let qty_expr = self.variable_to_expression(qty)?;
-        self.add_range_proof(BitRange::max(), qty_expr)?;
+        self.add_range_proof(BitRange::u64(), qty_expr)?;
 
         self.txlog.push(Entry::Issue(qty_point, flv_point));
 
@@ -558,16 +858,19 @@ where
         let flv = self.pop_item()?.to_variable()?;
         let qty = self.pop_item()?.to_variable()?;
 
-        let (_, flv_var) = self.delegate.commit_variable(&flv.commitment)?;
-        let (_, qty_var) = self.delegate.commit_variable(&qty.commitment)?;
+        let (_, flv_var) = self.commit_variable(&flv.commitment)?;
+        let (_, qty_var) = self.commit_variable(&qty.commitment)?;
         let flv_assignment = flv.commitment.assignment().map(|sw| sw.to_scalar());
         let qty_assignment = ScalarWitness::option_to_integer(qty.commitment.assignment())?;
+        if qty_assignment.map_or(false, |q| q.to_u64().is_none()) {
+            return Err(VMError::WitnessOutOfRange);
+        }
 
         spacesuit::range_proof(
             self.delegate.cs(),
             qty_var.into(),
-            qty_assignment,
-            BitRange::max(),
+            qty_assignment.map(|q| q.to_scalar()),
+            BitRange::u64(),
         )
         .map_err(|_| VMError::R1CSInconsistency)?;
 
@@ -575,7 +878,7 @@ where
             .delegate
             .cs()
             .allocate(qty_assignment.map(|q| -q.to_scalar()))
-            .map_err(|e| VMError::R1CSError(e))?;
+            .map_err(VMError::R1CSError)?;
         self.delegate.cs().constrain(qty_var + neg_qty_var);
         let value = Value {
             qty: qty.commitment.clone(),
@@ -601,6 +904,33 @@ where
         Ok(())
     }
 
+    /// _qty_ **fee** → _widevalue_
+    fn fee(&mut self) -> Result<(), VMError> {
+        let qty = self.pop_item()?.to_data()?.to_bytes();
+        let qty = SliceReader::parse(&qty, |r| r.read_u64())?;
+        self.total_fee = self.total_fee.checked_add(qty).ok_or(VMError::FeeTooHigh)?;
+
+        // Quantity and flavor are public, so both variables are constrained
+        // to constants: -qty and the flavor of the fee asset.
+        let neg_qty = -SignedInteger::from(qty);
+        let fee_flavor = self.fee_flavor;
+        let (qty_var, flv_var, _) = self
+            .delegate
+            .cs()
+            .allocate_multiplier(Some((neg_qty.into(), fee_flavor)))
+            .map_err(VMError::R1CSError)?;
+        self.delegate.cs().constrain(qty_var + Scalar::from(qty));
+        self.delegate.cs().constrain(flv_var - fee_flavor);
+
+        self.push_item(WideValue {
+            r1cs_qty: qty_var,
+            r1cs_flv: flv_var,
+            witness: Some((neg_qty, fee_flavor)),
+        });
+        self.txlog.push(Entry::Fee(qty));
+        Ok(())
+    }
+
     /// _input_ **input** → _contract_
     fn input(&mut self) -> Result<(), VMError> {
         let output = self.pop_item()?.to_data()?.to_output()?;
@@ -618,6 +948,17 @@ where
         Ok(())
     }
 
+    fn carry(&mut self, k: usize) -> Result<(), VMError> {
+        // _items..._ **carry:_k_** → ø
+        // Creates an output with the predicate of the contract that executes the current program.
+        let predicate = self
+            .current_predicate
+            .clone()
+            .ok_or(VMError::PredicateMissing)?;
+        self.push_item(Data::Predicate(Box::new(predicate)));
+        self.output(k)
+    }
+
     fn contract(&mut self, k: usize) -> Result<(), VMError> {
         let output = self.pop_output(k)?;
         self.push_item(output.into_contract().0);
@@ -723,6 +1064,7 @@ where
             predicate
                 .prove_program_predicate(&prog.clone().to_bytes(), &blinding.clone().to_bytes())
         })?;
+        self.delegate.exercise_branch(&predicate, Branch::Call);
 
         // Place contract payload on the stack
         for item in contract.payload.into_iter() {
@@ -730,7 +1072,7 @@ where
         }
 
         // Replace current program with new program
-        self.continue_with_program(prog)?;
+        self.continue_with_program(prog, predicate)?;
         Ok(())
     }
 
@@ -747,6 +1089,8 @@ where
         let p = &contract.predicate;
         self.delegate
             .verify_point_op(|| p.prove_disjunction(&preds))?;
+        self.delegate
+            .exercise_branch(&contract.predicate, Branch::Select(n, k));
 
         contract.predicate = preds.remove(k as usize);
         self.push_item(contract);
@@ -771,20 +1115,32 @@ where
         let verification_key = contract.predicate.to_key()?;
 
         // Verify signature using Verification key, over the message `program`
-        let mut t = Transcript::new(b"ZkVM.delegate");
+        let mut t = Transcript::new(labels::DELEGATE);
         t.commit_bytes(b"prog", &prog.clone().to_bytes());
         self.delegate
             .verify_point_op(|| signature.verify_single(&mut t, verification_key))?;
+        self.delegate
+            .exercise_branch(&contract.predicate, Branch::Delegate);
 
         // Replace current program with new program
-        self.continue_with_program(prog)?;
+        self.continue_with_program(prog, contract.predicate)?;
         Ok(())
     }
 
-    fn ext(&mut self, _: u8) -> Result<(), VMError> {
-        if self.extension {
-            // if extensions are allowed by tx version,
-            // unknown opcodes are treated as no-ops.
+    fn inspect(&mut self, label: String) {
+        let inspection = Inspection {
+            label,
+            stack_depth: self.stack.len(),
+            item: self.stack.last().map(|item| format!("{:?}", item)),
+        };
+        self.delegate.inspect(inspection);
+    }
+
+    fn ext(&mut self, opcode: u8) -> Result<(), VMError> {
+        if Opcode::is_nop(opcode) || self.extension {
+            // Reserved no-ops are permitted in all tx versions,
+            // and if extensions are allowed by tx version,
+            // other unknown opcodes are treated as no-ops as well.
             Ok(())
         } else {
             Err(VMError::ExtensionsNotAllowed)
@@ -814,9 +1170,10 @@ where
         value: &Value,
     ) -> Result<spacesuit::AllocatedValue, VMError> {
         Ok(spacesuit::AllocatedValue {
-            q: self.delegate.commit_variable(&value.qty)?.1,
-            f: self.delegate.commit_variable(&value.flv)?.1,
+            q: self.commit_variable(&value.qty)?.1,
+            f: self.commit_variable(&value.flv)?.1,
             assignment: value.assignment()?.map(|(q, f)| spacesuit::Value { q, f }),
+            bits: BitRange::u64(),
         })
     }
 
@@ -828,14 +1185,15 @@ where
                 None => None,
                 Some(w) => Some(spacesuit::Value { q: w.0, f: w.1 }),
             },
+            bits: BitRange::u64(),
         }
     }
 
     fn item_to_wide_value(&mut self, item: Item) -> Result<WideValue, VMError> {
         match item {
             Item::Value(value) => Ok(WideValue {
-                r1cs_qty: self.delegate.commit_variable(&value.qty)?.1,
-                r1cs_flv: self.delegate.commit_variable(&value.flv)?.1,
+                r1cs_qty: self.commit_variable(&value.qty)?.1,
+                r1cs_flv: self.commit_variable(&value.flv)?.1,
                 witness: value.assignment()?,
             }),
             Item::WideValue(w) => Ok(w),
@@ -844,7 +1202,7 @@ where
     }
 
     fn variable_to_expression(&mut self, var: Variable) -> Result<Expression, VMError> {
-        let (_, r1cs_var) = self.delegate.commit_variable(&var.commitment)?;
+        let (_, r1cs_var) = self.commit_variable(&var.commitment)?;
 
         Ok(Expression::LinearCombination(
             vec![(r1cs_var, Scalar::one())],
@@ -852,27 +1210,38 @@ where
         ))
     }
 
-    fn continue_with_program(&mut self, prog: Data) -> Result<(), VMError> {
+    /// Adds the commitment to the constraint system, charging for the point.
+    fn commit_variable(
+        &mut self,
+        com: &Commitment,
+    ) -> Result<(CompressedRistretto, r1cs::Variable), VMError> {
+        self.charge(cost::POINT_COST)?;
+        self.delegate.commit_variable(com)
+    }
+
+    /// Adds to the cost of the transaction and fails if it exceeds `maxcost`.
+    fn charge(&mut self, cost: u64) -> Result<(), VMError> {
+        self.cost = self
+            .cost
+            .checked_add(cost)
+            .filter(|total| *total <= self.maxcost)
+            .ok_or(VMError::CostLimitExceeded)?;
+        Ok(())
+    }
+
+    fn continue_with_program(&mut self, prog: Data, predicate: Predicate) -> Result<(), VMError> {
+        if self.run_stack.len() >= self.max_program_depth {
+            return Err(VMError::ProgramTooDeep);
+        }
         let new_run = self.delegate.new_run(prog)?;
         let paused_run = mem::replace(&mut self.current_run, new_run);
-        self.run_stack.push(paused_run);
+        let paused_predicate = mem::replace(&mut self.current_predicate, Some(predicate));
+        self.run_stack.push((paused_run, paused_predicate));
         Ok(())
     }
 
     fn add_range_proof(&mut self, bitrange: BitRange, expr: Expression) -> Result<(), VMError> {
-        let (lc, assignment) = match expr {
-            Expression::Constant(x) => (r1cs::LinearCombination::from(x), Some(x)),
-            Expression::LinearCombination(terms, assignment) => {
-                (r1cs::LinearCombination::from_iter(terms), assignment)
-            }
-        };
-        spacesuit::range_proof(
-            self.delegate.cs(),
-            lc,
-            ScalarWitness::option_to_integer(assignment)?,
-            bitrange,
-        )
-        .map_err(|_| VMError::R1CSInconsistency)
+        gadgets::range(self.delegate.cs(), expr, bitrange)
     }
 
     /// Creates and anchors the contract
@@ -0,0 +1,85 @@
+use curve25519_dalek::ristretto::CompressedRistretto;
+use curve25519_dalek::scalar::Scalar;
+use merlin::Transcript;
+use proptest::prelude::*;
+
+use zkvm::testutil::{self, TestVector};
+use zkvm::{Commitment, Program, Signature, VerificationKey};
+
+fn input<'a>(vector: &'a TestVector, label: &str) -> &'a [u8] {
+    &vector
+        .inputs
+        .iter()
+        .find(|(l, _)| *l == label)
+        .expect("input is present")
+        .1
+}
+
+#[test]
+fn vectors_are_deterministic() {
+    let vectors = testutil::vectors();
+    assert_eq!(vectors, testutil::vectors());
+    for vector in vectors.iter() {
+        assert!(vector.to_hex().starts_with(&vector.name));
+    }
+}
+
+#[test]
+fn constant_vectors() {
+    for (qty, point) in testutil::UNBLINDED_COMMITMENTS.iter() {
+        assert_eq!(
+            hex::encode(Commitment::unblinded(*qty).to_point().as_bytes()),
+            *point
+        );
+    }
+    let txid = testutil::transactions()
+        .into_iter()
+        .find(|v| v.name == "tx/issue/id")
+        .unwrap();
+    assert_eq!(hex::encode(&txid.output), testutil::ISSUE_TXID);
+}
+
+#[test]
+fn signature_vectors() {
+    for vector in testutil::signatures() {
+        let mut sig = [0u8; 64];
+        sig.copy_from_slice(&vector.output);
+        let sig = Signature::from_bytes(sig).unwrap();
+        let pubkey = VerificationKey(CompressedRistretto::from_slice(input(&vector, "pubkey")));
+
+        let mut t = Transcript::new(testutil::SIGNATURE_LABEL);
+        t.commit_bytes(b"message", input(&vector, "message"));
+        assert!(sig.verify_single(&mut t, pubkey).verify().is_ok());
+    }
+}
+
+#[test]
+fn commitment_vectors() {
+    for vector in testutil::commitments() {
+        let mut qty = [0u8; 8];
+        qty.copy_from_slice(input(&vector, "qty"));
+        let mut blinding = [0u8; 32];
+        blinding.copy_from_slice(input(&vector, "blinding"));
+        let commitment = Commitment::blinded_with_factor(
+            u64::from_le_bytes(qty),
+            Scalar::from_canonical_bytes(blinding).unwrap(),
+        );
+        assert_eq!(commitment.to_point().as_bytes(), &vector.output[..]);
+    }
+}
+
+proptest! {
+    #[test]
+    fn program_encoding_roundtrip(program in testutil::arb_program()) {
+        let bytes = program.to_bytes();
+        let decoded = Program::from_instructions(Program::disassemble(&bytes).unwrap());
+        prop_assert_eq!(decoded.to_bytes(), bytes);
+    }
+
+    #[test]
+    fn commitment_openings(qty in any::<u64>(), blinding in testutil::arb_scalar()) {
+        let commitment = Commitment::blinded_with_factor(qty, blinding);
+        prop_assert!(commitment.verify_opening(qty, blinding));
+        prop_assert!(!commitment.verify_opening(qty.wrapping_add(1), blinding));
+    }
+}
@@ -2,10 +2,17 @@ use bulletproofs::{BulletproofGens, PedersenGens};
 use curve25519_dalek::constants::RISTRETTO_BASEPOINT_COMPRESSED;
 use curve25519_dalek::scalar::Scalar;
 use hex;
+use merlin::Transcript;
+use sha2::{Digest, Sha256};
+use spacesuit::BitRange;
 
+use zkvm::poseidon::Poseidon;
+use zkvm::signature::SecretNonce;
 use zkvm::{
-    Anchor, Commitment, Contract, Data, Output, PortableItem, Predicate, Program, Prover,
-    Signature, TxHeader, TxID, VMError, Value, Verifier,
+    transcript_for_tx, Anchor, Branch, Commitment, Contract, Data, EncryptedOpening, EncryptionKey,
+    Entry, FeeRate, GensCache, Instruction, InstructionLocation, Output, PortableItem, Predicate,
+    Program, Prover, Signature, Tx, TxHeader, TxID, TxIntent, TxRef, VMError, VMParams, Value,
+    VectorCommitment, VerificationKey, Verifier, MIN_NOP_OPCODE,
 };
 
 // TODO(vniu): move builder convenience functions into separate crate,
@@ -131,39 +138,82 @@ fn make_output(qty: u64, flv: Scalar, pred: Predicate) -> Contract {
     }
 }
 
+fn build_tx(program: Program, header: TxHeader, keys: &Vec<Scalar>) -> Result<Tx, VMError> {
+    build_tx_with_gens(program, header, keys, &BulletproofGens::new(256, 1))
+}
+
+fn build_tx_with_gens(
+    program: Program,
+    header: TxHeader,
+    keys: &Vec<Scalar>,
+    bp_gens: &BulletproofGens,
+) -> Result<Tx, VMError> {
+    let (tx, _, _) = Prover::build_tx(program, header, bp_gens, |t, verification_keys| {
+        Signature::sign_aggregated(t, &signtx_keys(keys, verification_keys))
+    })?;
+    Ok(tx)
+}
+
+/// Selects the private keys for the `signtx` verification keys, in their order.
+fn signtx_keys(keys: &Vec<Scalar>, verification_keys: &[VerificationKey]) -> Vec<Scalar> {
+    let gens = PedersenGens::default();
+    verification_keys
+        .iter()
+        .filter_map(|vk| {
+            for k in keys {
+                if (k * gens.B).compress() == vk.0 {
+                    return Some(*k);
+                }
+            }
+            None
+        })
+        .collect()
+}
+
 fn build_and_verify(program: Program, keys: &Vec<Scalar>) -> Result<TxID, VMError> {
-    let (tx, _, _) = {
-        // Build tx
-        let bp_gens = BulletproofGens::new(256, 1);
-        let header = TxHeader {
-            version: 0u64,
-            mintime: 0u64,
-            maxtime: 0u64,
-        };
-        let gens = PedersenGens::default();
-        Prover::build_tx(program, header, &bp_gens, |t, verification_keys| {
-            let signtx_keys: Vec<Scalar> = verification_keys
-                .iter()
-                .filter_map(|vk| {
-                    for k in keys {
-                        if (k * gens.B).compress() == vk.0 {
-                            return Some(*k);
-                        }
-                    }
-                    None
-                })
-                .collect();
-            Signature::sign_aggregated(t, &signtx_keys)
-        })?
+    let header = TxHeader {
+        version: 0u64,
+        mintime: 0u64,
+        maxtime: 0u64,
+        maxcost: u64::max_value(),
     };
+    let tx = build_tx(program, header, keys)?;
+
+    // Pass the tx through a stream encoding
+    let mut stream = Vec::new();
+    tx.write_to(&mut stream).unwrap();
+    assert_eq!(stream, tx.to_bytes());
+    let tx = Tx::read_from(&stream[..])?;
 
     // Verify tx
     let bp_gens = BulletproofGens::new(256, 1);
 
+    // Verify the tx borrowed from its encoding
+    let vtx_ref = Verifier::verify_tx_ref(&TxRef::from_bytes(&stream)?, &bp_gens)?;
+
     let vtx = Verifier::verify_tx(tx, &bp_gens)?;
+    assert_eq!(vtx_ref.id, vtx.id);
+    assert_eq!(vtx_ref.wtxid, vtx.wtxid);
     Ok(vtx.id)
 }
 
+fn build_and_verify_fee(program: Program, keys: &Vec<Scalar>) -> Result<FeeRate, VMError> {
+    let bp_gens = BulletproofGens::new(256, 1);
+    let header = TxHeader {
+        version: 0u64,
+        mintime: 0u64,
+        maxtime: 0u64,
+        maxcost: u64::max_value(),
+    };
+    let (tx, _, _) = Prover::build_tx(program, header, &bp_gens, |t, _| {
+        Signature::sign_aggregated(t, keys)
+    })?;
+    let tx_size = tx.to_bytes().len();
+    let vtx = Verifier::verify_tx(tx, &bp_gens)?;
+    assert_eq!(vtx.feerate.size(), tx_size as u64);
+    Ok(vtx.feerate)
+}
+
 fn issue_contract(
     qty: u64,
     flv: Scalar,
@@ -197,7 +247,7 @@ fn issue() {
         Ok(txid) => {
             // Check txid
             assert_eq!(
-                "316f835973819a8cf6219010faf712bd17a1fe6fa2cc6350e4d96483b2065d82",
+                "9428ec448cc03c1984a2e0317ed6d1b2ac5e268e872157ca3b0c1989b25f8851",
                 hex::encode(txid.0)
             );
         }
@@ -216,6 +266,81 @@ fn issue() {
     }
 }
 
+#[cfg(feature = "serde")]
+#[test]
+fn tx_serde_bytes() {
+    let (predicates, mut scalars) = generate_predicates(2);
+    let (issuance_scalar, issuance_pred, flavor) = make_flavor();
+    scalars.push(issuance_scalar);
+    let program = issue_contract(
+        1u64,
+        flavor,
+        issuance_pred,
+        predicates[0].clone(),
+        predicates[1].clone(),
+    );
+    let header = TxHeader {
+        version: 0u64,
+        mintime: 0u64,
+        maxtime: 0u64,
+        maxcost: u64::max_value(),
+    };
+    let tx = build_tx(program, header, &scalars).unwrap();
+
+    // Transaction is a JSON array of the bytes of its encoding, as before the `serde` feature.
+    let json = serde_json::to_string(&tx).unwrap();
+    let bytes: Vec<u8> = serde_json::from_str(&json).unwrap();
+    assert_eq!(bytes, tx.to_bytes());
+    let tx2: Tx = serde_json::from_str(&json).unwrap();
+    assert_eq!(tx2.to_bytes(), tx.to_bytes());
+}
+
+#[test]
+fn inspect() {
+    let (predicates, mut scalars) = generate_predicates(2);
+    let (issuance_scalar, issuance_pred, flavor) = make_flavor();
+    scalars.push(issuance_scalar);
+
+    let program = Program::build(|p| {
+        p.inspect("start")
+            .issue_helper(1u64, flavor, issuance_pred, predicates[0].clone())
+            .inspect("issued")
+            .output_helper(predicates[1].clone())
+            .inspect("end")
+    });
+    let header = TxHeader {
+        version: 0u64,
+        mintime: 0u64,
+        maxtime: 0u64,
+        maxcost: u64::max_value(),
+    };
+    let bp_gens = BulletproofGens::new(256, 1);
+    let (result, inspections) =
+        Prover::build_tx_inspect(program.clone(), header, &bp_gens, |t, verification_keys| {
+            Signature::sign_aggregated(t, &signtx_keys(&scalars, verification_keys))
+        });
+    let (tx, _, _) = result.unwrap();
+
+    let labels = inspections.iter().map(|i| &i.label[..]).collect::<Vec<_>>();
+    assert_eq!(labels, vec!["start", "issued", "end"]);
+    let depths = inspections
+        .iter()
+        .map(|i| i.stack_depth)
+        .collect::<Vec<_>>();
+    assert_eq!(depths, vec![0, 1, 0]);
+    assert!(inspections[1].item.as_ref().unwrap().starts_with("Value"));
+    assert!(inspections[2].item.is_none());
+
+    // The debug instructions are stripped from the transaction.
+    assert_eq!(program.to_string().matches("inspect").count(), 3);
+    assert!(
+        !Program::from_instructions(Program::disassemble(&tx.program).unwrap())
+            .to_string()
+            .contains("inspect")
+    );
+    assert!(Verifier::verify_tx(tx, &bp_gens).is_ok());
+}
+
 fn spend_1_1_contract(
     input: u64,
     output: u64,
@@ -262,6 +387,75 @@ fn spend_1_1() {
     }
 }
 
+#[test]
+fn proof_metrics() {
+    let (predicates, scalars) = generate_predicates(2);
+    let program = spend_1_1_contract(
+        10u64,
+        10u64,
+        Scalar::from(1u64),
+        predicates[0].clone(),
+        predicates[1].clone(),
+    );
+    let size = program.estimate_circuit_size();
+    let header = TxHeader {
+        version: 0u64,
+        mintime: 0u64,
+        maxtime: 0u64,
+        maxcost: u64::max_value(),
+    };
+    let bp_gens = BulletproofGens::new(256, 1);
+    let (tx, _, _, metrics) =
+        Prover::build_tx_with_metrics(program, header, &bp_gens, |t, verification_keys| {
+            Signature::sign_aggregated(t, &signtx_keys(&scalars, verification_keys))
+        })
+        .unwrap();
+
+    assert_eq!(metrics.multipliers, size.gates());
+    assert_eq!(metrics.constraints, size.constraints);
+    assert_eq!(metrics.proof_size, tx.proof.serialized_size());
+    assert!(metrics.commitments > 0);
+    // Two scalars per padded multiplier and per round of the inner-product proof.
+    let rounds = (metrics.proof_size - 16 * 32) / 64;
+    assert_eq!(
+        metrics.verification_scalar_ops,
+        2 * (1 << rounds) + 2 * rounds + metrics.commitments + 13
+    );
+
+    // Verifier computes the same metrics.
+    let vtx = Verifier::verify_tx(tx, &bp_gens).unwrap();
+    assert_eq!(vtx.proof_metrics, metrics);
+}
+
+#[test]
+fn witness_malleability() {
+    let (predicates, scalars) = generate_predicates(2);
+    let program = spend_1_1_contract(
+        10u64,
+        10u64,
+        Scalar::from(1u64),
+        predicates[0].clone(),
+        predicates[1].clone(),
+    );
+    let header = TxHeader {
+        version: 0u64,
+        mintime: 0u64,
+        maxtime: 0u64,
+        maxcost: u64::max_value(),
+    };
+    let bp_gens = BulletproofGens::new(256, 1);
+
+    // Proving the same program again produces a different proof with the same effects.
+    let tx1 = build_tx(program.clone(), header, &scalars).unwrap();
+    let tx2 = build_tx(program, header, &scalars).unwrap();
+    assert_ne!(tx1.proof.to_bytes(), tx2.proof.to_bytes());
+
+    let vtx1 = Verifier::verify_tx(tx1, &bp_gens).unwrap();
+    let vtx2 = Verifier::verify_tx(tx2, &bp_gens).unwrap();
+    assert_eq!(vtx1.id, vtx2.id);
+    assert_ne!(vtx1.wtxid, vtx2.wtxid);
+}
+
 fn spend_1_2_contract(
     input: u64,
     output_1: u64,
@@ -582,3 +776,1050 @@ fn predicate_disjunction_program_path() {
         panic!("Unlocking input with incorrect secret scalar should have failed but didn't");
     }
 }
+
+#[test]
+fn tx_intent() {
+    let (predicates, mut scalars) = generate_predicates(3);
+    let (issuance_scalar, issuance_pred, flavor) = make_flavor();
+    scalars.push(issuance_scalar);
+    let header = TxHeader {
+        version: 0u64,
+        mintime: 0u64,
+        maxtime: 0u64,
+        maxcost: u64::max_value(),
+    };
+
+    // Issue 10 units, pay 3 and 6 of them (locked) and retire the rest.
+    let intent = |seed: [u8; 32]| {
+        TxIntent::new(header, seed)
+            .nonce(predicates[0].clone(), [0xffu8; 32])
+            .issue(10, issuance_pred.clone(), Vec::new())
+            .output(3, flavor, predicates[1].clone())
+            .output_locked(6, flavor, predicates[2].clone(), 0)
+            .retire(1, flavor)
+    };
+    let program = intent([7u8; 32]).compile().unwrap();
+
+    // The program does not depend on the order of the items.
+    let reordered = TxIntent::new(header, [7u8; 32])
+        .retire(1, flavor)
+        .output_locked(6, flavor, predicates[2].clone(), 0)
+        .output(3, flavor, predicates[1].clone())
+        .issue(10, issuance_pred.clone(), Vec::new())
+        .nonce(predicates[0].clone(), [0xffu8; 32]);
+    assert_eq!(program.to_bytes(), reordered.compile().unwrap().to_bytes());
+
+    // The seed determines the blinding factors.
+    assert_ne!(
+        program.to_bytes(),
+        intent([8u8; 32]).compile().unwrap().to_bytes()
+    );
+
+    assert_eq!(program.typecheck(), Ok(()));
+    build_and_verify(program, &scalars).unwrap();
+}
+
+#[test]
+fn coverage() {
+    let (key_pred, _) = generate_predicate();
+    let (output_pred, _) = generate_predicate();
+    let (qty, flavor) = (10u64, Scalar::from(1u64));
+    let secret_scalar = Scalar::from(101u64);
+    let spend_prog = spend_with_secret_scalar(qty, flavor, output_pred, secret_scalar);
+    let program_pred = Predicate::unblinded_program(spend_prog.clone());
+    let disjunction = Predicate::disjunction(vec![key_pred.clone(), program_pred.clone()]).unwrap();
+
+    let prog = Program::build(|p| {
+        p.push(secret_scalar)
+            .push(Output::new(make_output(qty, flavor, disjunction.clone())))
+            .input()
+            .push(key_pred)
+            .push(program_pred.clone())
+            .select(2, 1)
+            .push(Data::Opaque(Vec::new()))
+            .push(spend_prog.clone())
+            .call()
+    });
+    let header = TxHeader {
+        version: 0u64,
+        mintime: 0u64,
+        maxtime: 0u64,
+        maxcost: u64::max_value(),
+    };
+    let (result, coverage) = Prover::run_with_coverage(prog.clone(), header);
+    assert!(result.is_ok());
+
+    // The transaction's program and the program of the contract
+    assert_eq!(
+        coverage.programs,
+        vec![prog.to_bytes(), spend_prog.to_bytes()]
+    );
+    assert!(coverage.missed(0).is_empty());
+    assert!(coverage.missed(1).is_empty());
+    assert_eq!(coverage.opcodes["select"], 1);
+    assert_eq!(coverage.opcodes["cloak"], 1);
+
+    // Branch 1 of the disjunction, then the program of the branch
+    let call = InstructionLocation {
+        program: 0,
+        offset: prog.to_bytes().len() - 1,
+    };
+    assert_eq!(coverage.count(call), 1);
+    assert_eq!(coverage.branches.len(), 2);
+    assert_eq!(coverage.branches[0].branch, Branch::Select(2, 1));
+    assert_eq!(coverage.branches[0].predicate, disjunction.to_point());
+    assert_eq!(coverage.branches[1].branch, Branch::Call);
+    assert_eq!(coverage.branches[1].location, call);
+    assert_eq!(coverage.branches[1].predicate, program_pred.to_point());
+
+    // The failing instruction is the last one executed.
+    let failing = Program::build(|p| p.push(Data::Opaque(Vec::new())).input().drop());
+    let (result, coverage) = Prover::run_with_coverage(failing, header);
+    assert!(result.is_err());
+    assert_eq!(coverage.opcodes.get("drop"), None);
+    assert_eq!(coverage.opcodes["input"], 1);
+    assert_eq!(
+        coverage.missed(0),
+        vec![InstructionLocation {
+            program: 0,
+            offset: 1 + 4 + 0 + 1,
+        }]
+    );
+}
+
+#[test]
+fn predicate_tree_leaf_path() {
+    let (key_pred, key_scalar) = generate_predicate();
+    let (other_key_pred, _) = generate_predicate();
+    let (output_pred, _) = generate_predicate();
+
+    // Quantity, flavor
+    let (qty, flavor) = (10u64, Scalar::from(1u64));
+
+    // Program leaf nested in the second branch of the tree
+    let secret_scalar = Scalar::from(101u64);
+    let spend_prog = spend_with_secret_scalar(qty, flavor, output_pred.clone(), secret_scalar);
+    let program_pred = Predicate::blinded_program(spend_prog);
+    let inner = Predicate::disjunction(vec![other_key_pred, program_pred.clone()]).unwrap();
+    let tree = Predicate::disjunction(vec![key_pred, inner]).unwrap();
+    let prev_output = make_output(qty, flavor, tree.clone());
+
+    let proof = tree.proof_for(2).unwrap();
+    assert!(proof.verify(&tree).is_ok());
+    assert_eq!(proof.leaf.to_point(), program_pred.to_point());
+
+    let prog = Program::build(|p| {
+        p.push(secret_scalar)
+            .push(Output::new(prev_output))
+            .input()
+            .choose_predicate(tree, |t| t.select_leaf(2)?.call())
+            .unwrap()
+    });
+    build_and_verify(prog, &vec![key_scalar]).unwrap();
+}
+
+#[test]
+fn carry_contract_state() {
+    let (key_pred, key_scalar) = generate_predicate();
+    let (qty, flavor) = (10u64, Scalar::from(1u64));
+
+    // Counter contract: the payload holds a value and a committed counter,
+    // and the next state must lock the value under the same program with the counter incremented.
+    let counter_prog = Program::build(|p| {
+        // stack: next value counter
+        p.var() // next value counter-var
+            .push(Scalar::one())
+            .r#const() // next value counter-var 1
+            .add() // next value counter+1
+            .roll(2) // value counter+1 next
+            .dup(0) // value counter+1 next next
+            .var() // value counter+1 next next-var
+            .roll(2) // value next next-var counter+1
+            .eq()
+            .verify() // value next
+            .carry(2)
+    });
+    let counter_pred = Predicate::unblinded_program(counter_prog.clone());
+
+    let mut prev_output = make_output(qty, flavor, counter_pred.clone());
+    prev_output
+        .payload
+        .push(PortableItem::Data(Commitment::blinded(5u64).into()));
+
+    let call_with_next_counter = |next: u64| {
+        Program::build(|p| {
+            p.push(Commitment::blinded(next))
+                .push(Output::new(prev_output.clone()))
+                .input()
+                .push(Data::Opaque(Vec::new()))
+                .push(counter_prog.clone())
+                .call()
+        })
+    };
+    let header = TxHeader {
+        version: 0u64,
+        mintime: 0u64,
+        maxtime: 0u64,
+        maxcost: u64::max_value(),
+    };
+    let bp_gens = BulletproofGens::new(256, 1);
+    let build_and_verify_with = |program: Program| {
+        build_tx_with_gens(program, header, &vec![key_scalar], &bp_gens)
+            .and_then(|tx| Verifier::verify_tx(tx, &bp_gens))
+    };
+
+    // The successor output is locked by the same program and carries the new state.
+    let vtx = build_and_verify_with(call_with_next_counter(6)).unwrap();
+    assert!(vtx.log.iter().any(|entry| match entry {
+        Entry::Output(output) => {
+            output.contract().predicate.to_point() == counter_pred.to_point()
+                && output.contract().payload.len() == 2
+        }
+        _ => false,
+    }));
+
+    // The state transition is enforced by the contract's program.
+    assert!(build_and_verify_with(call_with_next_counter(7)).is_err());
+
+    // The transaction program has no contract predicate to carry.
+    let top_level = Program::build(|p| {
+        p.push(Output::new(make_output(qty, flavor, key_pred.clone())))
+            .input()
+            .carry(1)
+    });
+    assert_eq!(
+        build_and_verify_with(top_level)
+            .map(|_| ())
+            .map_err(|e| e.root().clone()),
+        Err(VMError::PredicateMissing)
+    );
+}
+
+fn spend_with_fee(input: u64, output: u64, fee: u64, input_pred: Predicate) -> Program {
+    let (output_pred, _) = generate_predicate();
+    let fee_flavor = Scalar::zero();
+    Program::build(|p| {
+        p.input_helper(input, fee_flavor, input_pred) // stack: input-value
+            .push(Data::Opaque(fee.to_le_bytes().to_vec()))
+            .fee() // stack: input-value, fee-widevalue
+            .cloak_helper(2, vec![(output, fee_flavor)])
+            .output_helper(output_pred)
+    })
+}
+
+#[test]
+fn pay_fee() {
+    let (input_pred, input_scalar) = generate_predicate();
+
+    let feerate = build_and_verify_fee(
+        spend_with_fee(10, 7, 3, input_pred.clone()),
+        &vec![input_scalar],
+    )
+    .unwrap();
+    assert_eq!(feerate.fee(), 3);
+    assert!(feerate > FeeRate::zero());
+
+    // Fee is subtracted from the inputs, so outputs cannot claim it.
+    assert!(
+        build_and_verify_fee(spend_with_fee(10, 10, 3, input_pred), &vec![input_scalar]).is_err()
+    );
+
+    // Total fee cannot overflow 64 bits: the VM fails as the verifier does.
+    let overflow = Program::build(|p| {
+        p.push(Data::Opaque(u64::max_value().to_le_bytes().to_vec()))
+            .fee()
+            .push(Data::Opaque(1u64.to_le_bytes().to_vec()))
+            .fee()
+    });
+    assert_eq!(
+        build_and_verify_fee(overflow, &vec![]),
+        Err(VMError::FeeTooHigh)
+    );
+}
+
+#[test]
+fn vm_params() {
+    let bp_gens = BulletproofGens::new(256, 1);
+    let header = TxHeader {
+        version: 0u64,
+        mintime: 0u64,
+        maxtime: 0u64,
+        maxcost: u64::max_value(),
+    };
+    let params = VMParams {
+        max_program_depth: 0,
+        fee_flavor: Scalar::from(5u64),
+    };
+
+    // Fee is paid in the flavor of the network.
+    let (input_pred, input_scalar) = generate_predicate();
+    let (output_pred, _) = generate_predicate();
+    let program = Program::build(|p| {
+        p.input_helper(10, params.fee_flavor, input_pred)
+            .push(Data::Opaque(3u64.to_le_bytes().to_vec()))
+            .fee()
+            .cloak_helper(2, vec![(7, params.fee_flavor)])
+            .output_helper(output_pred.clone())
+    });
+    let (tx, _, _) = Prover::build_tx_with_params(program, header, &params, &bp_gens, |t, _| {
+        Signature::sign_aggregated(t, &vec![input_scalar])
+    })
+    .unwrap();
+    let vtx = Verifier::verify_tx_with_params(tx.clone(), &params, &bp_gens, |_| false)
+        .unwrap()
+        .verify()
+        .unwrap();
+    assert_eq!(vtx.feerate.fee(), 3);
+    assert!(Verifier::verify_tx(tx, &bp_gens).is_err());
+
+    // Programs executed with `call` exceed the depth limit.
+    let (key_pred, key_scalar) = generate_predicate();
+    let (qty, flavor) = (10u64, Scalar::from(1u64));
+    let secret_scalar = Scalar::from(101u64);
+    let spend_prog = spend_with_secret_scalar(qty, flavor, output_pred, secret_scalar);
+    let program_pred = Predicate::unblinded_program(spend_prog.clone());
+    let disjunction = Predicate::disjunction(vec![key_pred.clone(), program_pred.clone()]).unwrap();
+    let prev_output = make_output(qty, flavor, disjunction);
+    let program = Program::build(|p| {
+        p.push(secret_scalar)
+            .push(Output::new(prev_output))
+            .input()
+            .push(key_pred)
+            .push(program_pred)
+            .select(2, 1)
+            .push(Data::Opaque(Vec::new()))
+            .push(spend_prog)
+            .call()
+    });
+    assert_eq!(
+        Prover::build_tx_with_params(program, header, &params, &bp_gens, |t, _| {
+            Signature::sign_aggregated(t, &vec![key_scalar])
+        })
+        .map(|_| ())
+        .map_err(|e| e.root().clone()),
+        Err(VMError::ProgramTooDeep)
+    );
+}
+
+#[test]
+fn cost_limit() {
+    let (predicates, mut scalars) = generate_predicates(2);
+    let (issuance_scalar, issuance_pred, flavor) = make_flavor();
+    scalars.push(issuance_scalar);
+    let program = issue_contract(
+        1u64,
+        flavor,
+        issuance_pred,
+        predicates[0].clone(), // nonce predicate
+        predicates[1].clone(), // output predicate
+    );
+    let header = |maxcost| TxHeader {
+        version: 0u64,
+        mintime: 0u64,
+        maxtime: 0u64,
+        maxcost,
+    };
+
+    // The range proof for the issued quantity alone exceeds the limit.
+    assert_eq!(
+        build_tx(program.clone(), header(1000), &scalars)
+            .map_err(|e| e.root().clone())
+            .map(|_| ()),
+        Err(VMError::CostLimitExceeded)
+    );
+
+    // The verifier enforces the limit before checking the proofs.
+    let mut tx = build_tx(program, header(100_000), &scalars).unwrap();
+    tx.header.maxcost = 1000;
+    let bp_gens = BulletproofGens::new(256, 1);
+    assert_eq!(
+        Verifier::verify_tx(tx, &bp_gens)
+            .map_err(|e| e.root().clone())
+            .map(|_| ()),
+        Err(VMError::CostLimitExceeded)
+    );
+
+    // Copies of a constraint are charged by their size, so `dup` and `or` repeated
+    // 40 times exceed the limit long before building a constraint with 2^40 disjunctions.
+    let program = Program::build(|p| {
+        p.push(Scalar::zero())
+            .r#const()
+            .push(Scalar::zero())
+            .r#const()
+            .eq();
+        for _ in 0..40 {
+            p.dup(0).or();
+        }
+        p.verify()
+    });
+    assert_eq!(
+        build_tx(program, header(10_000_000), &scalars)
+            .map_err(|e| e.root().clone())
+            .map(|_| ()),
+        Err(VMError::CostLimitExceeded)
+    );
+}
+
+#[test]
+fn extension_opcodes() {
+    let (predicates, mut scalars) = generate_predicates(2);
+    let (issuance_scalar, issuance_pred, flavor) = make_flavor();
+    scalars.push(issuance_scalar);
+    let bp_gens = BulletproofGens::new(256, 1);
+
+    let build_and_verify_ext = |opcode: u8, version: u64| {
+        let mut instructions = issue_contract(
+            1u64,
+            flavor,
+            issuance_pred.clone(),
+            predicates[0].clone(), // nonce predicate
+            predicates[1].clone(), // output predicate
+        )
+        .to_vec();
+        instructions.push(Instruction::Ext(opcode));
+        let header = TxHeader {
+            version,
+            mintime: 0u64,
+            maxtime: 0u64,
+            maxcost: u64::max_value(),
+        };
+        build_tx_with_gens(
+            Program::from_instructions(instructions),
+            header,
+            &scalars,
+            &bp_gens,
+        )
+        .and_then(|tx| Verifier::verify_tx(tx, &bp_gens))
+        .map(|_| ())
+        .map_err(|e| e.root().clone())
+    };
+
+    // Reserved no-ops are permitted in all versions.
+    assert_eq!(build_and_verify_ext(MIN_NOP_OPCODE, 1), Ok(()));
+    assert_eq!(build_and_verify_ext(0xff, 0), Ok(()));
+
+    // Other unassigned opcodes require the extension flag set by a higher tx version.
+    assert_eq!(
+        build_and_verify_ext(0x80, 1),
+        Err(VMError::ExtensionsNotAllowed)
+    );
+    assert_eq!(build_and_verify_ext(0x80, 2), Ok(()));
+}
+
+#[test]
+fn wide_ranges() {
+    let (predicates, mut scalars) = generate_predicates(2);
+    let (issuance_scalar, issuance_pred, flavor) = make_flavor();
+    scalars.push(issuance_scalar);
+    let bp_gens = BulletproofGens::new(512, 1);
+
+    let build_and_verify_range = |bits: usize, version: u64| {
+        let mut program = issue_contract(
+            1u64,
+            flavor,
+            issuance_pred.clone(),
+            predicates[0].clone(), // nonce predicate
+            predicates[1].clone(), // output predicate
+        );
+        program
+            .push(Commitment::blinded(5u64))
+            .expr()
+            .range(BitRange::new_wide(bits).unwrap())
+            .drop();
+        let header = TxHeader {
+            version,
+            mintime: 0u64,
+            maxtime: 0u64,
+            maxcost: u64::max_value(),
+        };
+        build_tx_with_gens(program, header, &scalars, &bp_gens)
+            .and_then(|tx| Verifier::verify_tx(tx, &bp_gens))
+            .map(|_| ())
+            .map_err(|e| e.root().clone())
+    };
+
+    // Ranges up to 64 bits are permitted in all versions.
+    assert_eq!(build_and_verify_range(64, 1), Ok(()));
+
+    // Wider ranges require the tx version that enables them.
+    assert_eq!(
+        build_and_verify_range(128, 1),
+        Err(VMError::InvalidBitrange)
+    );
+    assert_eq!(build_and_verify_range(128, 2), Ok(()));
+}
+
+#[test]
+fn timelocks() {
+    let (predicates, mut scalars) = generate_predicates(2);
+    let (issuance_scalar, issuance_pred, flavor) = make_flavor();
+    scalars.push(issuance_scalar);
+    let program = Program::build(|p| {
+        p.after(10)
+            .before(20)
+            .issue_helper(1u64, flavor, issuance_pred, predicates[0].clone())
+            .output_helper(predicates[1].clone())
+    });
+    let build_and_verify_at = |mintime, maxtime| {
+        let header = TxHeader {
+            version: 0u64,
+            mintime,
+            maxtime,
+            maxcost: u64::max_value(),
+        };
+        let bp_gens = BulletproofGens::new(256, 1);
+        build_tx(program.clone(), header, &scalars)
+            .and_then(|tx| Verifier::verify_tx(tx, &bp_gens))
+            .map(|_| ())
+    };
+
+    assert!(build_and_verify_at(10, 20).is_ok());
+    assert!(build_and_verify_at(15, 15).is_ok());
+    // Transaction may be included before the timelock expires.
+    assert!(build_and_verify_at(9, 20).is_err());
+    // Transaction may be included after the deadline.
+    assert!(build_and_verify_at(10, 21).is_err());
+}
+
+#[test]
+fn relative_timelock() {
+    let (predicates, mut scalars) = generate_predicates(2);
+    let (issuance_scalar, issuance_pred, flavor) = make_flavor();
+    scalars.push(issuance_scalar);
+    let header = |mintime, maxtime| TxHeader {
+        version: 0u64,
+        mintime,
+        maxtime,
+        maxcost: u64::max_value(),
+    };
+    let build_and_verify_with = |program: &Program, header: TxHeader| {
+        let bp_gens = BulletproofGens::new(256, 1);
+        build_tx(program.clone(), header, &scalars)
+            .and_then(|tx| Verifier::verify_tx(tx, &bp_gens))
+            .map(|_| ())
+    };
+
+    // Contract can be spent 500ms after its creation.
+    let (output_pred, _) = generate_predicate();
+    let spend_prog = Program::build(|p| p.mature(500).output_helper(output_pred.clone()));
+    let locked_pred = Predicate::unblinded_program(spend_prog);
+
+    // Creation time is recorded in the payload after the value.
+    let create = Program::build(|p| {
+        p.issue_helper(1u64, flavor, issuance_pred, predicates[0].clone())
+            .timestamp(1000)
+            .push(locked_pred.clone())
+            .output(2)
+    });
+    assert!(build_and_verify_with(&create, header(0, 1000)).is_ok());
+    // Contract may be created after the recorded time.
+    assert!(build_and_verify_with(&create, header(0, 1001)).is_err());
+
+    let mut locked = make_output(1u64, flavor, locked_pred.clone());
+    locked.payload.push(PortableItem::Data(Data::from(1000u64)));
+    let spend = Program::build(|p| {
+        p.push(Output::new(locked))
+            .input()
+            .choose_predicate(locked_pred, |t| t.call())
+            .unwrap()
+    });
+    assert!(build_and_verify_with(&spend, header(1500, 2000)).is_ok());
+    // Contract may be spent earlier than 500ms after its creation.
+    assert!(build_and_verify_with(&spend, header(1499, 2000)).is_err());
+}
+
+#[test]
+fn program_combinators() {
+    let (predicates, mut scalars) = generate_predicates(4);
+    let (issuance_scalar, issuance_pred, flavor) = make_flavor();
+    scalars.push(issuance_scalar);
+    let keys: Vec<VerificationKey> = predicates
+        .iter()
+        .map(|p| p.clone().to_key().unwrap())
+        .collect();
+    let header = |mintime| TxHeader {
+        version: 0u64,
+        mintime,
+        maxtime: u64::max_value(),
+        maxcost: u64::max_value(),
+    };
+    let build_and_verify_with = |program: &Program, header: TxHeader| {
+        let bp_gens = BulletproofGens::new(256, 1);
+        build_tx(program.clone(), header, &scalars).and_then(|tx| Verifier::verify_tx(tx, &bp_gens))
+    };
+
+    // Issue 10 units and pay out 3 to a key, 2 to a 2-of-3 multisig and 5 after a timelock.
+    let program = Program::build(|p| {
+        p.issue_helper(10u64, flavor, issuance_pred, predicates[0].clone())
+            .split_value(flavor, &[5, 2, 3])
+            .pay_to_key(keys[1])
+            .multisig(2, &keys[1..])
+            .unwrap()
+            .lock_until(1000, predicates[1].clone())
+    });
+    let vtx = build_and_verify_with(&program, header(0)).unwrap();
+    let multisig_pred = Predicate::multisig(2, &keys[1..]).unwrap();
+    let timelock_pred =
+        Predicate::unblinded_program(Program::timelock(1000, predicates[1].clone()));
+    let output_preds: Vec<_> = vtx
+        .log
+        .iter()
+        .filter_map(|entry| match entry {
+            Entry::Output(output) => Some(output.contract().predicate.to_point()),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(
+        output_preds,
+        vec![
+            predicates[1].to_point(),
+            multisig_pred.to_point(),
+            timelock_pred.to_point()
+        ]
+    );
+
+    // The timelocked value is unlocked by the key after the time and merged with another value.
+    let timelock_prog = Program::timelock(1000, predicates[1].clone());
+    let spend = Program::build(|p| {
+        p.push(Output::new(make_output(5, flavor, timelock_pred.clone())))
+            .input()
+            .push(Data::Opaque(Vec::new()))
+            .push(timelock_prog.clone())
+            .call()
+            .sign_tx()
+            .input_helper(4, flavor, predicates[2].clone())
+            .merge_values(2, flavor, 9)
+            .pay_to_key(keys[3])
+    });
+    assert!(build_and_verify_with(&spend, header(1000)).is_ok());
+    assert!(build_and_verify_with(&spend, header(999)).is_err());
+}
+
+#[test]
+fn hash_preimage() {
+    let (predicates, mut scalars) = generate_predicates(2);
+    let (issuance_scalar, issuance_pred, flavor) = make_flavor();
+    scalars.push(issuance_scalar);
+    let secret = b"zkvm secret";
+    let hash = Sha256::digest(secret).to_vec();
+
+    let program_with_hash = |hash: Vec<u8>| {
+        Program::build(|p| {
+            // The secret stays hidden in the committed bytes.
+            for byte in secret.iter() {
+                p.push(Commitment::blinded(*byte as u64)).var().expr();
+            }
+            p.push(Data::Opaque(hash))
+                .sha256(secret.len())
+                .verify()
+                .issue_helper(1u64, flavor, issuance_pred.clone(), predicates[0].clone())
+                .output_helper(predicates[1].clone())
+        })
+    };
+    let header = TxHeader {
+        version: 0u64,
+        mintime: 0u64,
+        maxtime: 0u64,
+        maxcost: u64::max_value(),
+    };
+    let bp_gens = BulletproofGens::new(32768, 1);
+    let build_and_verify_with = |program: Program| {
+        build_tx_with_gens(program, header, &scalars, &bp_gens)
+            .and_then(|tx| Verifier::verify_tx(tx, &bp_gens))
+            .map(|_| ())
+    };
+
+    assert!(build_and_verify_with(program_with_hash(hash.clone())).is_ok());
+    assert_eq!(
+        build_and_verify_with(program_with_hash(hash[..31].to_vec()))
+            .map_err(|e| e.root().clone())
+            .err(),
+        Some(VMError::FormatError)
+    );
+}
+
+#[test]
+fn instruction_error_context() {
+    let program = Program::build(|p| p.push(Data::Opaque(vec![1, 2, 3])).input());
+    let err = build_and_verify(program, &vec![]).unwrap_err();
+    assert_eq!(err.root(), &VMError::FormatError);
+    match &err {
+        VMError::InstructionFailed { error, context } => {
+            assert_eq!(**error, VMError::FormatError);
+            assert_eq!(context.instruction_index, 1);
+            assert_eq!(context.run_depth, 0);
+            assert_eq!(context.instruction, Some("input"));
+            assert_eq!(context.stack_depth, 1);
+            assert_eq!(context.stack_top, Some("data"));
+            assert!(context.last_entry.as_ref().unwrap().starts_with("Header"));
+        }
+        _ => panic!("error without context: {:?}", err),
+    }
+    assert!(err
+        .to_string()
+        .contains("instruction #1 `input` at depth 0, stack depth 1, top item is data"));
+}
+
+#[test]
+fn nullifier() {
+    let (predicates, mut scalars) = generate_predicates(2);
+    let (issuance_scalar, issuance_pred, flavor) = make_flavor();
+    scalars.push(issuance_scalar);
+    let poseidon = Poseidon::new();
+    let domain = Scalar::from(7u64);
+    let secret = Scalar::from(42u64);
+    let tag = poseidon.nullifier(domain, secret);
+
+    let program_with_tag = |tag: Scalar| {
+        Program::build(|p| {
+            p.push(domain)
+                .r#const()
+                .push(Commitment::blinded(secret))
+                .var()
+                .expr()
+                .poseidon(2)
+                .push(tag)
+                .nullify()
+                .issue_helper(1u64, flavor, issuance_pred.clone(), predicates[0].clone())
+                .output_helper(predicates[1].clone())
+        })
+    };
+    let header = TxHeader {
+        version: 0u64,
+        mintime: 0u64,
+        maxtime: 0u64,
+        maxcost: u64::max_value(),
+    };
+    let bp_gens = BulletproofGens::new(1024, 1);
+    let build_and_verify_with = |program: Program| {
+        build_tx_with_gens(program, header, &scalars, &bp_gens)
+            .and_then(|tx| Verifier::verify_tx(tx, &bp_gens))
+    };
+
+    // The tag is published in the log without revealing the secret.
+    let vtx = build_and_verify_with(program_with_tag(tag)).unwrap();
+    assert!(vtx.log.iter().any(|entry| match entry {
+        Entry::Nullifier(t) => t == tag.as_bytes(),
+        _ => false,
+    }));
+
+    let wrong_tag = poseidon.nullifier(domain + Scalar::one(), secret);
+    assert!(build_and_verify_with(program_with_tag(wrong_tag)).is_err());
+}
+
+#[test]
+fn encrypted_opening() {
+    let (predicates, mut scalars) = generate_predicates(2);
+    let (issuance_scalar, issuance_pred, flavor) = make_flavor();
+    scalars.push(issuance_scalar);
+    let header = TxHeader {
+        version: 0u64,
+        mintime: 0u64,
+        maxtime: 0u64,
+        maxcost: u64::max_value(),
+    };
+    let bp_gens = BulletproofGens::new(256, 1);
+    let auditor_privkey = Scalar::from(100u64);
+    let auditor_key = EncryptionKey::from_secret(&auditor_privkey);
+
+    // `issue_helper` commits to the quantity with the blinding factor 1.
+    let program = issue_contract(
+        7u64,
+        flavor,
+        issuance_pred,
+        predicates[0].clone(),
+        predicates[1].clone(),
+    );
+    let qty = Commitment::blinded_with_factor(7u64, Scalar::one());
+    let opening =
+        EncryptedOpening::encrypt(&mut Transcript::new(b"audit"), &qty, &auditor_key).unwrap();
+    assert_eq!(opening.decrypt(&auditor_privkey, 1 << 20), Some(7));
+
+    let verify_with = |commitment: &Commitment| {
+        let ops = opening.verify(&mut Transcript::new(b"audit"), commitment, &auditor_key);
+        build_tx_with_gens(program.clone(), header, &scalars, &bp_gens)
+            .and_then(|tx| Verifier::verify_tx_with_point_ops(tx, &bp_gens, ops))
+    };
+    assert!(verify_with(&qty).is_ok());
+    assert!(verify_with(&Commitment::blinded_with_factor(8u64, Scalar::one())).is_err());
+}
+
+#[test]
+fn padded_generators() {
+    let (predicates, scalars) = generate_predicates(4);
+    let flavor = Scalar::from(1u64);
+    let program = spend_2_2_contract(
+        6u64,
+        4u64,
+        9u64,
+        1u64,
+        flavor,
+        predicates[0].clone(),
+        predicates[1].clone(),
+        predicates[2].clone(),
+        predicates[3].clone(),
+    );
+    let header = TxHeader {
+        version: 0u64,
+        mintime: 0u64,
+        maxtime: 0u64,
+        maxcost: u64::max_value(),
+    };
+
+    // Range proofs of both outputs share one proof padded to 256 multipliers.
+    assert_eq!(
+        build_tx_with_gens(
+            program.clone(),
+            header,
+            &scalars,
+            &BulletproofGens::new(128, 1)
+        )
+        .unwrap_err(),
+        VMError::InsufficientGenerators {
+            required: 256,
+            available: 128,
+        }
+    );
+
+    let mut gens_cache = GensCache::new(64);
+    let (tx, txid, _) =
+        Prover::build_tx_padded(program, header, &mut gens_cache, |t, verification_keys| {
+            Signature::sign_aggregated(t, &signtx_keys(&scalars, verification_keys))
+        })
+        .unwrap();
+    assert_eq!(gens_cache.bp_gens().gens_capacity, 256);
+    assert_eq!(
+        Verifier::verify_tx(tx, gens_cache.bp_gens()).unwrap().id,
+        txid
+    );
+}
+
+#[test]
+fn external_signer() {
+    let (predicates, mut scalars) = generate_predicates(2);
+    let (issuance_scalar, issuance_pred, flavor) = make_flavor();
+    scalars.push(issuance_scalar);
+    let program = issue_contract(
+        1u64,
+        flavor,
+        issuance_pred,
+        predicates[0].clone(),
+        predicates[1].clone(),
+    );
+    let header = TxHeader {
+        version: 0u64,
+        mintime: 0u64,
+        maxtime: 0u64,
+        maxcost: u64::max_value(),
+    };
+    let mut vkeys = Vec::new();
+    let (tx, txid, _) = Prover::build_tx(
+        program,
+        header,
+        &BulletproofGens::new(256, 1),
+        |t, verification_keys| {
+            vkeys = verification_keys.clone();
+            Signature::sign_aggregated(t, &signtx_keys(&scalars, verification_keys))
+        },
+    )
+    .unwrap();
+
+    // The transaction's signature is made over the exported transcript.
+    assert!(tx
+        .signature
+        .verify_aggregated(&mut transcript_for_tx(&txid), &vkeys)
+        .verify()
+        .is_ok());
+
+    // External signers sign the exported transcript with their shares of the signature.
+    let privkeys = signtx_keys(&scalars, &vkeys);
+    let nonces = privkeys
+        .iter()
+        .map(|p| SecretNonce::new(&transcript_for_tx(&txid), p, &mut rand::thread_rng()))
+        .collect::<Vec<_>>();
+    let commitments = nonces.iter().map(|n| n.commitment()).collect::<Vec<_>>();
+    let shares = privkeys
+        .iter()
+        .zip(nonces.into_iter())
+        .enumerate()
+        .map(|(i, (p, nonce))| {
+            Signature::sign_share(
+                &mut transcript_for_tx(&txid),
+                &vkeys,
+                &commitments,
+                i,
+                *p,
+                nonce,
+            )
+            .unwrap()
+        })
+        .collect::<Vec<_>>();
+    let signature =
+        Signature::aggregate_shares(&mut transcript_for_tx(&txid), &vkeys, &commitments, &shares)
+            .unwrap();
+    assert!(signature
+        .verify_aggregated(&mut transcript_for_tx(&txid), &vkeys)
+        .verify()
+        .is_ok());
+    assert!(Verifier::verify_tx(Tx { signature, ..tx }, &BulletproofGens::new(256, 1)).is_ok());
+}
+
+#[test]
+fn precheck() {
+    let (predicates, mut scalars) = generate_predicates(2);
+    let (issuance_scalar, issuance_pred, flavor) = make_flavor();
+    scalars.push(issuance_scalar);
+    let program = issue_contract(
+        1u64,
+        flavor,
+        issuance_pred,
+        predicates[0].clone(),
+        predicates[1].clone(),
+    );
+    let header = TxHeader {
+        version: 0u64,
+        mintime: 0u64,
+        maxtime: 0u64,
+        maxcost: u64::max_value(),
+    };
+    let tx = build_tx_with_gens(program, header, &scalars, &BulletproofGens::new(256, 1)).unwrap();
+    let size = tx.serialized_size();
+    assert!(tx.precheck(size).is_ok());
+
+    match tx.precheck(size - 1) {
+        Err(VMError::TxTooLarge { size: s, limit }) => {
+            assert_eq!(s, size);
+            assert_eq!(limit, size - 1);
+        }
+        _ => panic!("transaction size limit is not checked"),
+    }
+
+    let mut bad_header = tx.clone();
+    bad_header.header.mintime = 1;
+    assert_eq!(
+        bad_header.precheck(size).unwrap_err(),
+        VMError::InvalidTimeBounds
+    );
+
+    // Push of 5 bytes is truncated.
+    let truncated = Tx {
+        program: vec![0x00, 5, 0, 0, 0, 1],
+        ..tx.clone()
+    };
+    assert_eq!(truncated.precheck(size).unwrap_err(), VMError::FormatError);
+
+    // Extension opcodes are allowed only in the future versions.
+    let mut extension = Tx {
+        program: vec![0xaa],
+        ..tx.clone()
+    };
+    assert_eq!(
+        extension.precheck(size).unwrap_err(),
+        VMError::ExtensionsNotAllowed
+    );
+    extension.header.version = 2;
+    assert!(extension.precheck(size).is_ok());
+}
+
+#[test]
+fn cached_verification() {
+    let (predicates, mut scalars) = generate_predicates(2);
+    let (issuance_scalar, issuance_pred, flavor) = make_flavor();
+    scalars.push(issuance_scalar);
+    let program = issue_contract(
+        1u64,
+        flavor,
+        issuance_pred,
+        predicates[0].clone(),
+        predicates[1].clone(),
+    );
+    let header = TxHeader {
+        version: 0u64,
+        mintime: 0u64,
+        maxtime: 0u64,
+        maxcost: u64::max_value(),
+    };
+    let bp_gens = BulletproofGens::new(256, 1);
+    let tx = build_tx_with_gens(program, header, &scalars, &bp_gens).unwrap();
+
+    // Uncached transaction is fully verified.
+    let deferred = Verifier::verify_tx_cached(tx.clone(), &bp_gens, |_| false).unwrap();
+    assert!(!deferred.point_ops().is_empty());
+    let vtx = deferred.verify().unwrap();
+
+    // Cached transaction skips the proof and the point operations.
+    let wtxid = vtx.wtxid;
+    let cached = Verifier::verify_tx_cached(tx.clone(), &bp_gens, |w| *w == wtxid).unwrap();
+    assert!(cached.point_ops().is_empty());
+    let cached = cached.verify().unwrap();
+    assert_eq!(cached.id, vtx.id);
+    assert_eq!(cached.wtxid, vtx.wtxid);
+
+    // Altered signature changes the witness ID, so the transaction is verified again and fails.
+    let mut bytes = tx.signature.to_bytes();
+    bytes[32] ^= 1;
+    let signature = Signature::from_bytes(bytes).unwrap();
+    let altered =
+        Verifier::verify_tx_cached(Tx { signature, ..tx }, &bp_gens, |w| *w == wtxid).unwrap();
+    assert_ne!(altered.wtxid(), wtxid);
+    assert!(altered.verify().is_err());
+}
+
+#[test]
+fn vector_commitment_payload() {
+    let (predicates, mut scalars) = generate_predicates(2);
+    let (issuance_scalar, issuance_pred, flavor) = make_flavor();
+    scalars.push(issuance_scalar);
+    let state = VectorCommitment::blinded(vec![Scalar::from(7u64), Scalar::from(11u64)]);
+    let program = Program::build(|p| {
+        p.push(state.clone()) // stack: state
+            .issue_helper(1u64, flavor, issuance_pred, predicates[0].clone()) // stack: state, value
+            .push(predicates[1].clone())
+            .output(2) // stack: empty
+    });
+    let bp_gens = BulletproofGens::new(256, 1);
+    let header = TxHeader {
+        version: 0u64,
+        mintime: 0u64,
+        maxtime: 0u64,
+        maxcost: u64::max_value(),
+    };
+    let vtx = build_tx_with_gens(program, header, &scalars, &bp_gens)
+        .and_then(|tx| Verifier::verify_tx(tx, &bp_gens))
+        .unwrap();
+
+    // The verifier's log contains the closed commitment, whose position is opened
+    // in the context of the contract.
+    let output = vtx
+        .log
+        .iter()
+        .filter_map(|entry| match entry {
+            Entry::Output(output) => Some(output.clone()),
+            _ => None,
+        })
+        .next()
+        .unwrap();
+    let closed = output
+        .contract()
+        .data()
+        .next()
+        .unwrap()
+        .clone()
+        .to_vector_commitment()
+        .unwrap();
+    assert_eq!(closed.len(), 2);
+    assert_eq!(closed.to_point(), state.to_point());
+
+    let context = |t: &mut Transcript| t.commit_bytes(b"contract", output.id().as_bytes());
+    let mut t = Transcript::new(b"ZkVM.test.state");
+    context(&mut t);
+    let (value, opening) = state.open(&mut t, 1).unwrap();
+    let mut t = Transcript::new(b"ZkVM.test.state");
+    context(&mut t);
+    assert_eq!(value, Scalar::from(11u64));
+    assert!(opening
+        .verify(&mut t, &closed, 1, &value)
+        .unwrap()
+        .verify()
+        .is_ok());
+}